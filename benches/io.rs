@@ -0,0 +1,221 @@
+//! I/O benchmarks: `cargo bench --bench io`
+//!
+//! Hashes a large file and archives a generated layer-like tree with each buffer size, to pick
+//! [`DEFAULT_IO_BUFFER_SIZE`](layer_tool::tuning::DEFAULT_IO_BUFFER_SIZE) and to evaluate
+//! changes to the I/O paths. Directory hashing and layer archiving also run on a single-threaded
+//! and a multi-threaded pool over the same tree, to measure what the parallel paths gain, and
+//! gzip compression compares flate2 with the parallel encoder at several thread counts.
+//! Directory checksum schemes are compared on a tree of many small files, where the sequential
+//! V1 scheme and the parallel entry-record schemes differ most.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::path::Path;
+use tempfile::TempDir;
+
+use layer_tool::cancel::CancellationToken;
+use layer_tool::tuning::IoTuning;
+use layer_tool::types::{ChecksumAlgorithm, ChecksumFormat, ChecksumScheme, LayerArchiveOptions, CURRENT_CHECKSUM_SCHEME};
+use layer_tool::utils::{
+    calculate_directory_checksum, calculate_file_checksum, calculate_file_digest, compress_file, compress_file_parallel,
+    create_tar_archive, create_tar_archive_filtered,
+};
+
+const BUFFER_SIZES: [u64; 5] = [8 * 1024, 64 * 1024, 256 * 1024, 1024 * 1024, 4 * 1024 * 1024];
+
+/// Size of the single file hashed
+const LARGE_FILE_SIZE: usize = 256 * 1024 * 1024;
+
+/// Size of the file compressed
+const COMPRESSION_INPUT_SIZE: usize = 64 * 1024 * 1024;
+
+/// Thread counts of the parallel gzip encoder
+const COMPRESSION_THREADS: [usize; 4] = [1, 2, 4, 8];
+
+/// Files in the generated tree, from 1 KiB up to 4 MiB
+const TREE_FILES: usize = 2000;
+
+/// Files in the small-file tree, each from 512 bytes up to 8 KiB
+const SMALL_FILES: usize = 20_000;
+
+/// Deterministic, poorly compressible bytes
+fn fill(buffer: &mut [u8], mut seed: u64) {
+    for chunk in buffer.chunks_mut(8) {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        chunk.copy_from_slice(&seed.to_le_bytes()[..chunk.len()]);
+    }
+}
+
+/// Deterministic bytes from a 16-letter alphabet, which gzip shrinks to about half like typical layer contents
+fn fill_compressible(buffer: &mut [u8], seed: u64) {
+    fill(buffer, seed);
+    for byte in buffer.iter_mut() {
+        *byte = b'a' + *byte % 16;
+    }
+}
+
+fn write_file(path: &Path, size: usize, seed: u64) {
+    let mut contents = vec![0; size];
+    fill(&mut contents, seed);
+    std::fs::write(path, contents).expect("write fixture file");
+}
+
+/// A tree shaped like a container layer: many small files and a few large ones, in nested directories
+fn generate_tree(root: &Path) -> u64 {
+    let mut total = 0;
+    for index in 0..TREE_FILES {
+        let dir = root.join(format!("dir{:02}/sub{:02}", index % 40, index % 7));
+        std::fs::create_dir_all(&dir).expect("create fixture directory");
+        let size = match index % 100 {
+            0 => 4 * 1024 * 1024,
+            1..=9 => 256 * 1024,
+            _ => 1024 + index * 13,
+        };
+        write_file(&dir.join(format!("file{}.bin", index)), size, index as u64 + 1);
+        total += size as u64;
+    }
+    total
+}
+
+/// A tree of many small files spread over a few hundred directories, like a package-heavy layer
+fn generate_small_file_tree(root: &Path) -> u64 {
+    let mut total = 0;
+    for index in 0..SMALL_FILES {
+        let dir = root.join(format!("pkg{:03}/lib{:02}", index % 250, index % 11));
+        std::fs::create_dir_all(&dir).expect("create fixture directory");
+        let size = 512 + (index * 37) % (8 * 1024 - 512);
+        write_file(&dir.join(format!("module{}.py", index)), size, index as u64 + 1);
+        total += size as u64;
+    }
+    total
+}
+
+fn file_hashing(c: &mut Criterion) {
+    let fixtures = TempDir::new().expect("create fixture directory");
+    let path = fixtures.path().join("large.bin");
+    write_file(&path, LARGE_FILE_SIZE, 42);
+
+    let mut group = c.benchmark_group("file_hashing");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(LARGE_FILE_SIZE as u64));
+    for buffer_size in BUFFER_SIZES {
+        IoTuning::new(buffer_size).expect("valid buffer size").apply();
+        group.bench_with_input(BenchmarkId::new("sha256", buffer_size), &path, |b, path| {
+            b.iter(|| calculate_file_checksum(path).expect("hash file"));
+        });
+        group.bench_with_input(BenchmarkId::new("blake3", buffer_size), &path, |b, path| {
+            b.iter(|| calculate_file_digest(path, ChecksumAlgorithm::Blake3).expect("hash file"));
+        });
+    }
+    group.finish();
+}
+
+fn tar_creation(c: &mut Criterion) {
+    let fixtures = TempDir::new().expect("create fixture directory");
+    let tree = fixtures.path().join("layer");
+    let total = generate_tree(&tree);
+    let output = fixtures.path().join("layer.tar");
+
+    let mut group = c.benchmark_group("tar_creation");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(total));
+    for buffer_size in BUFFER_SIZES {
+        IoTuning::new(buffer_size).expect("valid buffer size").apply();
+        group.bench_function(BenchmarkId::from_parameter(buffer_size), |b| {
+            b.iter(|| create_tar_archive(&tree, &output).expect("create archive"));
+        });
+    }
+    group.finish();
+}
+
+/// Pools to compare: one thread, and one per available core
+fn thread_pools() -> Vec<(usize, rayon::ThreadPool)> {
+    let cores = std::thread::available_parallelism().map_or(4, |cores| cores.get());
+    [1, cores.max(2)]
+        .into_iter()
+        .map(|threads| (threads, rayon::ThreadPoolBuilder::new().num_threads(threads).build().expect("build thread pool")))
+        .collect()
+}
+
+fn parallel_hashing(c: &mut Criterion) {
+    let fixtures = TempDir::new().expect("create fixture directory");
+    let tree = fixtures.path().join("layer");
+    let total = generate_tree(&tree);
+    let format = ChecksumFormat::new(CURRENT_CHECKSUM_SCHEME, ChecksumAlgorithm::Sha256);
+
+    let mut group = c.benchmark_group("directory_checksum_threads");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(total));
+    for (threads, pool) in thread_pools() {
+        group.bench_function(BenchmarkId::from_parameter(threads), |b| {
+            b.iter(|| pool.install(|| calculate_directory_checksum(&tree, format).expect("hash directory")));
+        });
+    }
+    group.finish();
+}
+
+fn parallel_archiving(c: &mut Criterion) {
+    let fixtures = TempDir::new().expect("create fixture directory");
+    let tree = fixtures.path().join("layer");
+    let total = generate_tree(&tree);
+    let output = fixtures.path().join("layer.tar");
+    let cancel = CancellationToken::new();
+
+    // A filter makes the archiver hash every file in a parallel pass before writing the archive
+    let mut group = c.benchmark_group("layer_archiving_threads");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(total));
+    for (threads, pool) in thread_pools() {
+        group.bench_function(BenchmarkId::from_parameter(threads), |b| {
+            b.iter(|| pool.install(|| {
+                create_tar_archive_filtered(&tree, &output, Some(&|_, _| true), None, LayerArchiveOptions::default(), &cancel)
+                    .expect("create archive")
+            }));
+        });
+    }
+    group.finish();
+}
+
+fn gzip_compression(c: &mut Criterion) {
+    let fixtures = TempDir::new().expect("create fixture directory");
+    let input = fixtures.path().join("layer.tar");
+    let mut contents = vec![0; COMPRESSION_INPUT_SIZE];
+    fill_compressible(&mut contents, 7);
+    std::fs::write(&input, contents).expect("write fixture file");
+    let output = fixtures.path().join("layer.tar.gz");
+    let cancel = CancellationToken::new();
+
+    let mut group = c.benchmark_group("gzip_compression");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(COMPRESSION_INPUT_SIZE as u64));
+    group.bench_function("flate2", |b| {
+        b.iter(|| compress_file(&input, &output).expect("compress file"));
+    });
+    for threads in COMPRESSION_THREADS {
+        group.bench_with_input(BenchmarkId::new("parallel", threads), &threads, |b, &threads| {
+            b.iter(|| compress_file_parallel(&input, &output, threads, &cancel).expect("compress file"));
+        });
+    }
+    group.finish();
+}
+
+fn checksum_schemes(c: &mut Criterion) {
+    let fixtures = TempDir::new().expect("create fixture directory");
+    let tree = fixtures.path().join("layer");
+    let total = generate_small_file_tree(&tree);
+
+    let mut group = c.benchmark_group("checksum_schemes");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(total));
+    for scheme in [ChecksumScheme::V1, ChecksumScheme::V2, ChecksumScheme::V3, ChecksumScheme::V4] {
+        let format = ChecksumFormat::new(scheme, ChecksumAlgorithm::Sha256);
+        group.bench_function(BenchmarkId::from_parameter(scheme), |b| {
+            b.iter(|| calculate_directory_checksum(&tree, format).expect("hash directory"));
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, file_hashing, tar_creation, parallel_hashing, parallel_archiving, gzip_compression, checksum_schemes);
+criterion_main!(benches);
@@ -0,0 +1,431 @@
+use anyhow::Result;
+use filetime::FileTime;
+use std::io::{Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use tar::{Builder, EntryType, Header};
+
+use crate::types::{IdOffset, Owner, VerifiedAttributes};
+
+/// PAX record prefix carrying an extended attribute, as written by GNU tar and star
+pub const PAX_XATTR_PREFIX: &str = "SCHILY.xattr.";
+
+/// Extended attributes overlayfs keeps for itself; they describe the source host's lower layers
+/// and would confuse overlayfs if carried over to another container
+const OVERLAY_XATTR_PREFIX: &str = "trusted.overlay.";
+
+/// Which attributes an [`AttributeRestorer`] restores; all of them by default
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PreservedAttributes {
+    pub ownership: bool,
+    /// The full mode, including setuid/setgid/sticky
+    pub permissions: bool,
+    pub mtime: bool,
+    pub xattrs: bool,
+}
+
+impl Default for PreservedAttributes {
+    fn default() -> Self {
+        Self { ownership: true, permissions: true, mtime: true, xattrs: true }
+    }
+}
+
+/// Restores the ownership, permission bits (including setuid/setgid/sticky), modification times,
+/// and xattrs recorded in tar headers onto extracted entries. Directory modes and times are applied
+/// in [`AttributeRestorer::finish`] so extracting their children does not disturb them.
+///
+/// Failures are collected per path. When running as root they make `finish` fail; otherwise
+/// (where chown and trusted xattrs are expected to fail) they are returned for a single warning.
+#[derive(Debug, Default)]
+pub struct AttributeRestorer {
+    preserved: PreservedAttributes,
+    id_offset: IdOffset,
+    owner: Option<Owner>,
+    directories: Vec<(PathBuf, u32, FileTime)>,
+    failures: Vec<String>,
+}
+
+impl AttributeRestorer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Shift the owner ids recorded in the archive by `id_offset`
+    pub fn with_id_offset(mut self, id_offset: IdOffset) -> Self {
+        self.id_offset = id_offset;
+        self
+    }
+
+    /// Give every entry `owner` instead of the recorded one; a missing gid keeps the recorded group
+    pub fn with_owner(mut self, owner: Option<Owner>) -> Self {
+        self.owner = owner;
+        self
+    }
+
+    /// Restore only the attributes `preserved` selects
+    pub fn with_preserved(mut self, preserved: PreservedAttributes) -> Self {
+        self.preserved = preserved;
+        self
+    }
+
+    /// The attributes that verifying restored entries against their manifest can compare: those
+    /// restored as recorded, when running as root where nothing should have been refused
+    pub fn verified_attributes(&self) -> VerifiedAttributes {
+        let exact = running_as_root();
+        VerifiedAttributes {
+            mode: exact && self.preserved.permissions,
+            owner_shift: (exact && self.preserved.ownership && self.owner.is_none()).then(|| self.id_offset.inverse()),
+        }
+    }
+
+    /// Apply the attributes of `entry`, already extracted to `destination`
+    pub fn restore<R: Read>(&mut self, entry: &mut tar::Entry<R>, destination: &Path) -> Result<()> {
+        let header = entry.header();
+        let entry_type = header.entry_type();
+        if entry_type == EntryType::Link {
+            // Hard links share the attributes of the entry they point to
+            return Ok(());
+        }
+        let uid = header.uid()?;
+        let gid = header.gid()?;
+        let mode = header.mode()? & 0o7777;
+        let mtime = entry_mtime(entry)?;
+        let xattrs = if self.preserved.xattrs { entry_xattrs(entry)? } else { Vec::new() };
+
+        // Ownership first: chown clears setuid/setgid bits and file capabilities
+        let owner = match self.owner {
+            Some(Owner { uid, gid: Some(gid) }) => Some((uid, gid)),
+            Some(Owner { uid, gid: None }) => self.id_offset.apply(0, gid).map(|(_, gid)| (uid, gid)),
+            None => self.id_offset.apply(uid, gid),
+        };
+        match owner {
+            _ if !self.preserved.ownership => {}
+            Some((uid, gid)) => {
+                if let Err(error) = std::os::unix::fs::lchown(destination, Some(uid), Some(gid)) {
+                    self.fail(destination, &format!("ownership {}:{}", uid, gid), &error);
+                }
+            }
+            None => self.failures.push(format!(
+                "{}: ownership {}:{} is out of range once shifted by {}",
+                destination.display(), uid, gid, self.id_offset
+            )),
+        }
+
+        for (name, value) in &xattrs {
+            if let Err(error) = xattr::set(destination, name, value) {
+                self.fail(destination, &format!("xattr {}", name), &error);
+            }
+        }
+
+        if entry_type.is_symlink() {
+            self.set_mtime_nofollow(destination, mtime);
+        } else if entry_type.is_dir() {
+            self.directories.push((destination.to_path_buf(), mode, mtime));
+        } else {
+            self.set_mode_and_mtime(destination, mode, mtime);
+        }
+
+        Ok(())
+    }
+
+    /// Copy the ownership, xattrs, permission bits, and modification time of `source`, described by
+    /// `metadata`, onto `destination`, a copy of it made on this host. Unlike archived entries, the
+    /// overlayfs xattrs are kept, since they still describe this host's layers.
+    pub fn copy_from(&mut self, source: &Path, metadata: &std::fs::Metadata, destination: &Path) {
+        if self.preserved.ownership
+            && let Err(error) = std::os::unix::fs::lchown(destination, Some(metadata.uid()), Some(metadata.gid()))
+        {
+            self.fail(destination, &format!("ownership {}:{}", metadata.uid(), metadata.gid()), &error);
+        }
+
+        let mtime = FileTime::from_last_modification_time(metadata);
+        if metadata.file_type().is_symlink() {
+            self.set_mtime_nofollow(destination, mtime);
+            return;
+        }
+
+        if self.preserved.xattrs
+            && let Ok(names) = xattr::list(source)
+        {
+            for name in names {
+                let Ok(Some(value)) = xattr::get(source, &name) else {
+                    continue;
+                };
+                if let Err(error) = xattr::set(destination, &name, &value) {
+                    self.fail(destination, &format!("xattr {}", name.to_string_lossy()), &error);
+                }
+            }
+        }
+
+        let mode = metadata.mode() & 0o7777;
+        if metadata.is_dir() {
+            self.directories.push((destination.to_path_buf(), mode, mtime));
+        } else {
+            self.set_mode_and_mtime(destination, mode, mtime);
+        }
+    }
+
+    fn set_mtime_nofollow(&mut self, path: &Path, mtime: FileTime) {
+        if !self.preserved.mtime {
+            return;
+        }
+        if let Err(error) = filetime::set_symlink_file_times(path, mtime, mtime) {
+            self.fail(path, "modification time", &error);
+        }
+    }
+
+    /// Set an xattr the layer format implies rather than records, e.g. overlayfs' opaque marker
+    pub fn set_xattr(&mut self, path: &Path, name: &str, value: &[u8]) {
+        if let Err(error) = xattr::set(path, name, value) {
+            self.fail(path, &format!("xattr {}", name), &error);
+        }
+    }
+
+    /// Apply deferred directory attributes and return the paths whose attributes could not be
+    /// restored. Fails instead when running as root, where nothing should have been refused.
+    pub fn finish(mut self) -> Result<Vec<String>> {
+        // Deepest first, so setting a directory's mtime is not undone by its children
+        let directories = std::mem::take(&mut self.directories);
+        for (path, mode, mtime) in directories.into_iter().rev() {
+            self.set_mode_and_mtime(&path, mode, mtime);
+        }
+
+        if !self.failures.is_empty() && running_as_root() {
+            return Err(anyhow::anyhow!(
+                "Failed to restore attributes of {} path(s): {}",
+                self.failures.len(),
+                self.failures.join("; ")
+            ));
+        }
+        Ok(self.failures)
+    }
+
+    fn set_mode_and_mtime(&mut self, path: &Path, mode: u32, mtime: FileTime) {
+        if self.preserved.permissions
+            && let Err(error) = std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+        {
+            self.fail(path, &format!("mode {:o}", mode), &error);
+        }
+        // Never symlinks here; not following them also keeps device nodes from being opened
+        self.set_mtime_nofollow(path, mtime);
+    }
+
+    fn fail(&mut self, path: &Path, what: &str, error: &std::io::Error) {
+        self.failures.push(format!("{}: {} ({})", path.display(), what, error));
+    }
+}
+
+/// Whether the process runs with root privileges
+pub fn running_as_root() -> bool {
+    // SAFETY: geteuid has no preconditions and cannot fail
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Extended attributes recorded for an entry in PAX `SCHILY.xattr.*` records
+pub fn entry_xattrs<R: Read>(entry: &mut tar::Entry<R>) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut xattrs = Vec::new();
+    if let Some(extensions) = entry.pax_extensions()? {
+        for extension in extensions {
+            let extension = extension?;
+            if let Ok(key) = extension.key()
+                && let Some(name) = key.strip_prefix(PAX_XATTR_PREFIX)
+                && !name.starts_with(OVERLAY_XATTR_PREFIX)
+            {
+                xattrs.push((name.to_string(), extension.value_bytes().to_vec()));
+            }
+        }
+    }
+    Ok(xattrs)
+}
+
+/// Owner ids of an entry, from PAX records where the ustar fields cannot hold them
+pub fn entry_owner<R: Read>(entry: &mut tar::Entry<R>) -> Result<(u64, u64)> {
+    let (mut uid, mut gid) = (entry.header().uid()?, entry.header().gid()?);
+    if let Some(extensions) = entry.pax_extensions()? {
+        for extension in extensions {
+            let extension = extension?;
+            let id = || extension.value().ok().and_then(|value| value.parse().ok());
+            match extension.key() {
+                Ok("uid") => uid = id().unwrap_or(uid),
+                Ok("gid") => gid = id().unwrap_or(gid),
+                _ => {}
+            }
+        }
+    }
+    Ok((uid, gid))
+}
+
+/// Extended attributes of `path` (following symlinks) worth carrying in an export. Filesystems
+/// without xattr support simply have none.
+pub fn read_xattrs(path: &Path) -> Vec<(String, Vec<u8>)> {
+    let Ok(names) = xattr::list_deref(path) else {
+        return Vec::new();
+    };
+    names
+        .filter_map(|name| name.into_string().ok())
+        .filter(|name| !name.starts_with(OVERLAY_XATTR_PREFIX))
+        .filter_map(|name| {
+            let value = xattr::get_deref(path, &name).ok().flatten()?;
+            Some((name, value))
+        })
+        .collect()
+}
+
+/// Owner of `metadata` shifted by `id_offset`, as recorded in archives
+pub fn archived_owner(metadata: &std::fs::Metadata, id_offset: IdOffset) -> std::io::Result<(u32, u32)> {
+    id_offset.apply(metadata.uid() as u64, metadata.gid() as u64)
+        .ok_or_else(|| std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("owner {}:{} is outside the remapped id range ({})", metadata.uid(), metadata.gid(), id_offset),
+        ))
+}
+
+/// Record the owner of `metadata` in `header`, shifted by `id_offset`
+pub fn set_header_owner(header: &mut Header, metadata: &std::fs::Metadata, id_offset: IdOffset) -> std::io::Result<()> {
+    let (uid, gid) = archived_owner(metadata, id_offset)?;
+    header.set_uid(uid as u64);
+    header.set_gid(gid as u64);
+    Ok(())
+}
+
+/// Largest size the octal ustar size field holds (8 GiB - 1); larger sizes go in a PAX record
+const USTAR_MAX_SIZE: u64 = 0o77777777777;
+
+#[cfg(test)]
+thread_local! {
+    /// [`USTAR_MAX_SIZE`] as applied on this thread; tests lower it to send small files through the
+    /// PAX size path instead of archiving and hashing more than 8 GiB
+    pub(crate) static USTAR_SIZE_LIMIT: std::cell::Cell<u64> = const { std::cell::Cell::new(USTAR_MAX_SIZE) };
+}
+
+/// Largest size [`append_pax_entry`] writes in the ustar size field
+fn ustar_size_limit() -> u64 {
+    #[cfg(test)]
+    return USTAR_SIZE_LIMIT.get();
+    #[cfg(not(test))]
+    USTAR_MAX_SIZE
+}
+
+/// Largest uid or gid the octal ustar id fields hold; larger ids go in PAX records
+const USTAR_MAX_ID: u64 = 0o7777777;
+
+/// Append an entry under a POSIX ustar `header`, preceded when needed by a single PAX extended
+/// header carrying its xattrs and whatever the ustar fields cannot hold: a path or link target
+/// too long for them, a size of 8 GiB or more, an id above 2097151, and the sub-second part
+/// (`mtime_nsec`) of the modification time. No GNU extensions are written.
+pub fn append_pax_entry<W: Write, R: Read>(
+    builder: &mut Builder<W>,
+    header: &mut Header,
+    path: &Path,
+    link_target: Option<&Path>,
+    mtime_nsec: u32,
+    xattrs: &[(String, Vec<u8>)],
+    data: R,
+) -> std::io::Result<()> {
+    let mut records = PaxRecords::default();
+    if header.set_path(path).is_err() {
+        records.push("path", path.as_os_str().as_bytes());
+        if let Some(ustar) = header.as_ustar_mut() {
+            ustar.prefix = [0; 155];
+        }
+        copy_truncated(&mut header.as_old_mut().name, path);
+    }
+    if let Some(link_target) = link_target
+        && header.set_link_name(link_target).is_err()
+    {
+        records.push("linkpath", link_target.as_os_str().as_bytes());
+        copy_truncated(&mut header.as_old_mut().linkname, link_target);
+    }
+
+    let size = header.size()?;
+    if size > ustar_size_limit() {
+        records.push("size", size.to_string().as_bytes());
+        header.set_size(0);
+    }
+    let (uid, gid) = (header.uid()?, header.gid()?);
+    if uid > USTAR_MAX_ID {
+        records.push("uid", uid.to_string().as_bytes());
+        header.set_uid(0);
+    }
+    if gid > USTAR_MAX_ID {
+        records.push("gid", gid.to_string().as_bytes());
+        header.set_gid(0);
+    }
+    if mtime_nsec != 0 {
+        records.push("mtime", format!("{}.{:09}", header.mtime()?, mtime_nsec).as_bytes());
+    }
+    for (name, value) in xattrs {
+        records.push(&format!("{}{}", PAX_XATTR_PREFIX, name), value);
+    }
+
+    if !records.0.is_empty() {
+        let mut pax_header = Header::new_ustar();
+        pax_header.set_path("././@PaxHeader")?;
+        pax_header.set_entry_type(EntryType::XHeader);
+        pax_header.set_mode(0o644);
+        pax_header.set_size(records.0.len() as u64);
+        pax_header.set_cksum();
+        builder.append(&pax_header, records.0.as_slice())?;
+    }
+    header.set_cksum();
+    builder.append(header, data)
+}
+
+/// Body of a PAX extended header
+#[derive(Debug, Default)]
+struct PaxRecords(Vec<u8>);
+
+impl PaxRecords {
+    fn push(&mut self, key: &str, value: &[u8]) {
+        // Each record is "<length> <key>=<value>\n", where the length counts its own digits
+        let body = key.len() + value.len() + 3;
+        let mut length = body + 1;
+        while length != body + length.to_string().len() {
+            length = body + length.to_string().len();
+        }
+        self.0.extend_from_slice(format!("{} {}=", length, key).as_bytes());
+        self.0.extend_from_slice(value);
+        self.0.push(b'\n');
+    }
+}
+
+/// Fill a ustar name field with as much of `path` as fits; readers take the full path from PAX
+fn copy_truncated(field: &mut [u8], path: &Path) {
+    let bytes = path.as_os_str().as_bytes();
+    let length = bytes.len().min(field.len());
+    field.fill(0);
+    field[..length].copy_from_slice(&bytes[..length]);
+}
+
+/// Modification time of an archive entry, with the sub-second precision of a PAX `mtime` record
+/// when it has one
+pub fn entry_mtime<R: Read>(entry: &mut tar::Entry<R>) -> Result<FileTime> {
+    let seconds = entry.header().mtime()? as i64;
+    if let Some(extensions) = entry.pax_extensions()? {
+        for extension in extensions {
+            let extension = extension?;
+            if extension.key_bytes() == b"mtime"
+                && let Some(mtime) = extension.value().ok().and_then(parse_pax_time)
+            {
+                return Ok(mtime);
+            }
+        }
+    }
+    Ok(FileTime::from_unix_time(seconds, 0))
+}
+
+/// Parse a PAX time such as `1700000000.123456789`; fractions beyond nanoseconds are dropped
+fn parse_pax_time(value: &str) -> Option<FileTime> {
+    let (seconds, fraction) = value.split_once('.').unwrap_or((value, ""));
+    if !fraction.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    let whole = seconds.parse::<i64>().ok()?;
+    let nanoseconds = format!("{:0<9}", &fraction[..fraction.len().min(9)]).parse::<u32>().ok()?;
+    // The fraction of a negative time counts towards the epoch
+    if seconds.starts_with('-') && nanoseconds > 0 {
+        return Some(FileTime::from_unix_time(whole - 1, 1_000_000_000 - nanoseconds));
+    }
+    Some(FileTime::from_unix_time(whole, nanoseconds))
+}
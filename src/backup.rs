@@ -0,0 +1,506 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use tar::{Archive, Builder, EntryType, Header};
+use walkdir::WalkDir;
+
+use crate::attributes::{append_pax_entry, read_xattrs, AttributeRestorer};
+use crate::cancel::CancellationToken;
+use crate::output::{byte_progress_bar, finish_progress, print_warning, print_warnings_section};
+use crate::tuning::IoTuning;
+use crate::types::{BackupRecord, Checksum, ChecksumAlgorithm, ChecksumFormat, CURRENT_CHECKSUM_SCHEME};
+use crate::utils::{
+    calculate_directory_checksum, calculate_file_checksum, copy_directory, make_device_node, move_directory, resolve_within_root,
+    unpack_entry_within
+};
+use crate::workspace::Workspace;
+
+/// File name suffix of tarball backups written to a backup directory
+pub const BACKUP_TARBALL_SUFFIX: &str = ".tar.gz";
+
+/// Extension of rename-style backups next to the upper layer, followed by `-<timestamp>`.
+/// Backups taken before timestamps were added are named `<upper>.backup`.
+const BACKUP_EXTENSION: &str = "backup";
+
+/// UTC timestamp in backup names; sorts chronologically as a string
+const BACKUP_TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// Backups kept per container by default (import --keep-backups)
+pub const DEFAULT_KEEP_BACKUPS: usize = 3;
+
+/// A backup of a container's upper layer, taken by import before the layer is replaced
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LayerBackup {
+    /// The original layer directory, moved or copied aside next to the upper layer
+    Directory(PathBuf),
+    /// A gzipped tarball of the layer in a backup directory, with a JSON sidecar
+    Tarball(PathBuf),
+}
+
+impl LayerBackup {
+    pub fn path(&self) -> &Path {
+        match self {
+            LayerBackup::Directory(path) | LayerBackup::Tarball(path) => path,
+        }
+    }
+
+    /// Timestamp part of the backup's name; empty for an untimestamped `<upper>.backup`
+    pub fn timestamp(&self) -> String {
+        let name = self.path().file_name().unwrap_or_default().to_string_lossy();
+        match self {
+            LayerBackup::Directory(_) => name.split_once(&format!(".{}-", BACKUP_EXTENSION))
+                .map(|(_, timestamp)| timestamp.to_string())
+                .unwrap_or_default(),
+            LayerBackup::Tarball(_) => name.strip_suffix(BACKUP_TARBALL_SUFFIX)
+                .and_then(|stem| stem.split_once('_'))
+                .map(|(_, timestamp)| timestamp.to_string())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// File name identifying the backup, as accepted by `restore --backup`
+    pub fn name(&self) -> String {
+        self.path().file_name().unwrap_or_default().to_string_lossy().into_owned()
+    }
+
+    /// When the backup was taken, from its name; None for an untimestamped `<upper>.backup`
+    pub fn taken_at(&self) -> Option<DateTime<Utc>> {
+        NaiveDateTime::parse_from_str(self.timestamp().get(..16)?, BACKUP_TIMESTAMP_FORMAT)
+            .ok()
+            .map(|taken_at| taken_at.and_utc())
+    }
+
+    /// Sidecar record of the backup: `<name>.json` next to a directory, `<stem>.json` next to a tarball
+    fn sidecar_path(&self) -> PathBuf {
+        let name = self.name();
+        let stem = match self {
+            LayerBackup::Directory(_) => &name,
+            LayerBackup::Tarball(_) => name.strip_suffix(BACKUP_TARBALL_SUFFIX).unwrap_or(&name),
+        };
+        self.path().with_file_name(format!("{}.json", stem))
+    }
+
+    /// Sidecar record of the backup; None for backups taken before records were written
+    pub fn record(&self) -> Result<Option<BackupRecord>> {
+        let sidecar_path = self.sidecar_path();
+        if !sidecar_path.exists() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(&sidecar_path)
+            .with_context(|| format!("Failed to read backup record: {:?}", sidecar_path))?;
+        let record = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse backup record: {:?}", sidecar_path))?;
+        Ok(Some(record))
+    }
+
+    /// Delete the backup and its sidecar
+    pub fn remove(&self) -> Result<()> {
+        match self {
+            LayerBackup::Directory(path) => std::fs::remove_dir_all(path),
+            LayerBackup::Tarball(path) => std::fs::remove_file(path),
+        }
+        .with_context(|| format!("Failed to remove backup {:?}", self.path()))?;
+        self.remove_record()
+    }
+
+    fn remove_record(&self) -> Result<()> {
+        let sidecar_path = self.sidecar_path();
+        if sidecar_path.exists() {
+            std::fs::remove_file(&sidecar_path)
+                .with_context(|| format!("Failed to remove backup record {:?}", sidecar_path))?;
+        }
+        Ok(())
+    }
+
+    /// Check the backup against its record without restoring it: a tarball's archive checksum
+    /// (a mismatch is an error) and then the layer it extracts to, or a directory's contents
+    pub fn verify(&self) -> Result<BackupCheck> {
+        self.verify_archive()?;
+        let Some((expected, scheme)) = self.record()?
+            .and_then(|record| record.layer_checksum.map(|checksum| (checksum, record.checksum_scheme)))
+        else {
+            return Ok(BackupCheck::Unrecorded);
+        };
+
+        let actual = match self {
+            LayerBackup::Directory(path) => calculate_directory_checksum(path, ChecksumFormat::new(scheme, expected.algorithm))?,
+            LayerBackup::Tarball(path) => {
+                let workspace = Workspace::new().context("Failed to create temporary directory")?;
+                let layer_path = workspace.path().join("layer");
+                extract_backup_archive(path, &layer_path)
+                    .with_context(|| format!("Failed to extract backup {:?}", path))?;
+                calculate_directory_checksum(&layer_path, ChecksumFormat::new(scheme, expected.algorithm))?
+            }
+        };
+        Ok(BackupCheck::compare(expected, actual))
+    }
+
+    /// Fail if a tarball no longer matches the archive checksum in its record; returns the
+    /// verified checksum, or None when none was recorded
+    pub fn verify_archive(&self) -> Result<Option<String>> {
+        let Some(expected) = self.record()?.and_then(|record| record.archive_checksum) else {
+            return Ok(None);
+        };
+        let calculated = calculate_file_checksum(self.path())?;
+        if calculated != expected {
+            return Err(anyhow::anyhow!(
+                "Backup archive checksum mismatch: expected {}, got {}",
+                expected, calculated
+            ));
+        }
+        Ok(Some(calculated))
+    }
+
+    /// Restore the backup like [`LayerBackup::restore`] and compare the restored layer with the
+    /// checksum recorded when the backup was taken (or, for a directory backup without one, with
+    /// the backup's contents). Check a tarball with [`LayerBackup::verify_archive`] first.
+    pub fn restore_verified(&self, target_upper_path: &Path) -> Result<BackupCheck> {
+        let record = self.record()?;
+        let scheme = record.as_ref().map_or(CURRENT_CHECKSUM_SCHEME, |record| record.checksum_scheme);
+        let expected = match (record.and_then(|record| record.layer_checksum), self) {
+            (Some(layer_checksum), _) => Some(layer_checksum),
+            (None, LayerBackup::Directory(path)) => Some(calculate_directory_checksum(path, ChecksumFormat::new(scheme, ChecksumAlgorithm::default()))
+                .context("Failed to calculate backup checksum")?),
+            (None, LayerBackup::Tarball(_)) => None,
+        };
+
+        self.restore(target_upper_path)?;
+
+        let Some(expected) = expected else {
+            return Ok(BackupCheck::Unrecorded);
+        };
+        let restored = calculate_directory_checksum(target_upper_path, ChecksumFormat::new(scheme, expected.algorithm))
+            .context("Failed to calculate restored layer checksum")?;
+        Ok(BackupCheck::compare(expected, restored))
+    }
+
+    /// Put the backed-up layer back at `target_upper_path`, replacing whatever is there
+    pub fn restore(&self, target_upper_path: &Path) -> Result<()> {
+        if target_upper_path.exists() {
+            std::fs::remove_dir_all(target_upper_path)
+                .context("Failed to remove the partially imported layer")?;
+        }
+        match self {
+            LayerBackup::Directory(path) => {
+                move_layer(path, target_upper_path)
+                    .with_context(|| format!("Failed to move backup {:?} back into place", path))?;
+                // The backup is gone once moved back, and so is what its record describes
+                self.remove_record()
+            }
+            LayerBackup::Tarball(path) => extract_backup_archive(path, target_upper_path)
+                .with_context(|| format!("Failed to extract backup {:?}", path)),
+        }
+    }
+}
+
+/// Result of comparing a layer with the checksum recorded when its backup was taken
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BackupCheck {
+    Verified(Checksum),
+    Mismatch { expected: Checksum, actual: Checksum },
+    /// No layer checksum was recorded, as for backups taken by older versions
+    Unrecorded,
+}
+
+impl BackupCheck {
+    fn compare(expected: Checksum, actual: Checksum) -> Self {
+        if expected == actual {
+            BackupCheck::Verified(actual)
+        } else {
+            BackupCheck::Mismatch { expected, actual }
+        }
+    }
+}
+
+/// Move `upper_path` aside as `<upper>.backup-<timestamp>`, recording its checksum in a
+/// `<upper>.backup-<timestamp>.json` sidecar
+pub fn move_layer_aside(upper_path: &Path, container_id: &str, source: &str) -> Result<LayerBackup> {
+    directory_backup(upper_path, container_id, source, |backup_path| {
+        move_layer(upper_path, backup_path).context("Failed to backup existing layer")
+    })
+}
+
+/// Move a layer directory, copying it with a progress bar when `destination` is on another filesystem
+fn move_layer(path: &Path, destination: &Path) -> Result<()> {
+    let progress = byte_progress_bar("Moving layer");
+    let failures = move_directory(path, destination, &progress);
+    finish_progress(&progress);
+    let failures = failures?;
+    if !failures.is_empty() {
+        print_warning(&format!(
+            "Could not copy ownership, permissions, or xattrs of {} path(s) while moving {:?} across filesystems (not running as root)",
+            failures.len(), path
+        ));
+        print_warnings_section(&failures);
+    }
+    Ok(())
+}
+
+/// Copy `upper_path` to `<upper>.backup-<timestamp>` with a sidecar like [`move_layer_aside`],
+/// leaving the layer in place for an import that modifies it
+pub fn copy_layer_aside(upper_path: &Path, container_id: &str, source: &str) -> Result<LayerBackup> {
+    directory_backup(upper_path, container_id, source, |backup_path| {
+        copy_directory(upper_path, backup_path).context("Failed to backup existing layer")
+    })
+}
+
+fn directory_backup(upper_path: &Path, container_id: &str, source: &str, place: impl FnOnce(&Path) -> Result<()>) -> Result<LayerBackup> {
+    let created = Utc::now();
+    let layer_checksum = calculate_directory_checksum(upper_path, ChecksumFormat::new(CURRENT_CHECKSUM_SCHEME, ChecksumAlgorithm::default()))
+        .context("Failed to calculate existing layer checksum")?;
+    let backup_path = unused_path(|suffix| upper_path.with_extension(format!(
+        "{}-{}{}", BACKUP_EXTENSION, created.format(BACKUP_TIMESTAMP_FORMAT), suffix
+    )));
+    place(&backup_path)?;
+
+    let backup = LayerBackup::Directory(backup_path);
+    write_record(&backup, &BackupRecord {
+        container_id: container_id.to_string(),
+        original_path: upper_path.display().to_string(),
+        archive_checksum: None,
+        layer_checksum: Some(layer_checksum),
+        checksum_scheme: CURRENT_CHECKSUM_SCHEME,
+        export_file: source.to_string(),
+        created,
+    })?;
+    Ok(backup)
+}
+
+fn write_record(backup: &LayerBackup, record: &BackupRecord) -> Result<()> {
+    let sidecar_path = backup.sidecar_path();
+    let sidecar = serde_json::to_string_pretty(record)
+        .context("Failed to serialize backup record")?;
+    std::fs::write(&sidecar_path, sidecar)
+        .with_context(|| format!("Failed to write backup record: {:?}", sidecar_path))
+}
+
+/// Archive `upper_path` into `<short-id>_<timestamp>.tar.gz` in `backup_dir`, next to a
+/// `<short-id>_<timestamp>.json` sidecar. Unlike export archives, symlinks, whiteouts (device
+/// nodes) and ownership are kept as they are, so restoring reproduces the layer exactly.
+pub fn create_tarball_backup(
+    upper_path: &Path,
+    backup_dir: &Path,
+    container_id: &str,
+    export_file: &str,
+    cancel: &CancellationToken,
+) -> Result<LayerBackup> {
+    std::fs::create_dir_all(backup_dir)
+        .with_context(|| format!("Failed to create backup directory: {:?}", backup_dir))?;
+
+    let created = Utc::now();
+    let tarball_path = unused_path(|suffix| backup_dir.join(format!(
+        "{}_{}{}{}",
+        short_id(container_id), created.format(BACKUP_TIMESTAMP_FORMAT), suffix, BACKUP_TARBALL_SUFFIX
+    )));
+
+    let layer_checksum = calculate_directory_checksum(upper_path, ChecksumFormat::new(CURRENT_CHECKSUM_SCHEME, ChecksumAlgorithm::default()))
+        .context("Failed to calculate existing layer checksum")?;
+    if let Err(error) = write_backup_archive(upper_path, &tarball_path, cancel) {
+        let _ = std::fs::remove_file(&tarball_path);
+        return Err(error);
+    }
+
+    let backup = LayerBackup::Tarball(tarball_path);
+    write_record(&backup, &BackupRecord {
+        container_id: container_id.to_string(),
+        original_path: upper_path.display().to_string(),
+        archive_checksum: Some(calculate_file_checksum(backup.path())?),
+        layer_checksum: Some(layer_checksum),
+        checksum_scheme: CURRENT_CHECKSUM_SCHEME,
+        export_file: export_file.to_string(),
+        created,
+    })?;
+    Ok(backup)
+}
+
+/// Rename-style backups of `upper_path` and, with `backup_dir`, the container's tarball backups
+/// there, oldest first
+pub fn list_backups(upper_path: &Path, backup_dir: Option<&Path>, container_id: &str) -> Result<Vec<LayerBackup>> {
+    let mut backups = Vec::new();
+
+    if let (Some(parent), Some(upper_name)) = (upper_path.parent(), upper_path.file_name()) {
+        let legacy_name = format!("{}.{}", upper_name.to_string_lossy(), BACKUP_EXTENSION);
+        let prefix = format!("{}-", legacy_name);
+        for entry in std::fs::read_dir(parent).with_context(|| format!("Failed to read directory: {:?}", parent))? {
+            let entry = entry.context("Failed to read directory entry")?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if (name == legacy_name || name.starts_with(&prefix)) && entry.path().is_dir() {
+                backups.push(LayerBackup::Directory(entry.path()));
+            }
+        }
+    }
+
+    if let Some(backup_dir) = backup_dir
+        && backup_dir.is_dir()
+    {
+        let prefix = format!("{}_", short_id(container_id));
+        for entry in std::fs::read_dir(backup_dir).with_context(|| format!("Failed to read directory: {:?}", backup_dir))? {
+            let entry = entry.context("Failed to read directory entry")?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with(&prefix) && name.ends_with(BACKUP_TARBALL_SUFFIX) {
+                backups.push(LayerBackup::Tarball(entry.path()));
+            }
+        }
+    }
+
+    backups.sort_by_key(|backup| backup.timestamp());
+    Ok(backups)
+}
+
+/// A backup taken by a successful import and the older backups it replaced
+#[derive(Debug, Clone)]
+pub struct BackupRotation {
+    pub created: LayerBackup,
+    pub pruned: Vec<LayerBackup>,
+}
+
+/// Keep the newest `keep` backups stored the same way as `created` (next to the layer, or in
+/// `backup_dir`) and delete the rest. A backup that cannot be deleted is kept with a warning.
+pub fn rotate_backups(
+    created: LayerBackup,
+    upper_path: &Path,
+    backup_dir: Option<&Path>,
+    container_id: &str,
+    keep: usize,
+) -> Result<BackupRotation> {
+    let same_kind = |backup: &LayerBackup| std::mem::discriminant(backup) == std::mem::discriminant(&created);
+    let backups: Vec<LayerBackup> = list_backups(upper_path, backup_dir, container_id)?
+        .into_iter()
+        .filter(same_kind)
+        .collect();
+
+    let excess = backups.len().saturating_sub(keep);
+    let mut pruned = Vec::new();
+    for backup in backups.into_iter().take(excess).filter(|backup| *backup != created) {
+        match backup.remove() {
+            Ok(()) => pruned.push(backup),
+            Err(error) => print_warning(&format!("Failed to prune old backup: {:#}", error)),
+        }
+    }
+    Ok(BackupRotation { created, pruned })
+}
+
+/// Short container id used in tarball backup names
+pub(crate) fn short_id(container_id: &str) -> String {
+    container_id.chars().take(12).collect()
+}
+
+/// First of `name("")`, `name("-1")`, `name("-2")`, ... that does not exist yet, so two backups
+/// taken within the same second do not collide
+fn unused_path(name: impl Fn(&str) -> PathBuf) -> PathBuf {
+    let mut path = name("");
+    let mut counter = 1;
+    while path.exists() {
+        path = name(&format!("-{}", counter));
+        counter += 1;
+    }
+    path
+}
+
+/// Extract a tarball backup into `target_upper_path`. Runs after a cancelled import too, so it
+/// does not observe the import's cancellation token.
+fn extract_backup_archive(tarball_path: &Path, target_upper_path: &Path) -> Result<()> {
+    std::fs::create_dir_all(target_upper_path)
+        .with_context(|| format!("Failed to create directory: {:?}", target_upper_path))?;
+    let file = File::open(tarball_path)
+        .with_context(|| format!("Failed to open backup: {:?}", tarball_path))?;
+    let mut archive = Archive::new(MultiGzDecoder::new(IoTuning::current().reader(file)));
+    let mut attributes = AttributeRestorer::new();
+    for entry in archive.entries().context("Failed to read backup entries")? {
+        let mut entry = entry.context("Failed to read backup entry")?;
+        let destination = match entry.header().entry_type() {
+            EntryType::Char | EntryType::Block | EntryType::Fifo => make_special_file(&entry, target_upper_path)?,
+            _ => unpack_entry_within(&mut entry, target_upper_path)?,
+        };
+        attributes.restore(&mut entry, &destination)?;
+    }
+    attributes.finish()?;
+    Ok(())
+}
+
+/// Recreate a device node or fifo entry, which the tar crate would unpack as an empty file
+fn make_special_file<R: Read>(entry: &tar::Entry<R>, root: &Path) -> Result<PathBuf> {
+    let header = entry.header();
+    let entry_path = entry.path().context("Failed to read tar entry path")?;
+    let destination = resolve_within_root(root, &entry_path)?;
+    let file_type = match header.entry_type() {
+        EntryType::Char => libc::S_IFCHR,
+        EntryType::Block => libc::S_IFBLK,
+        _ => libc::S_IFIFO,
+    };
+    let device = libc::makedev(
+        header.device_major()?.unwrap_or(0),
+        header.device_minor()?.unwrap_or(0),
+    );
+    make_device_node(&destination, file_type, device)?;
+    Ok(destination)
+}
+
+/// Write the gzipped tarball of a layer directory, in path order
+fn write_backup_archive(upper_path: &Path, tarball_path: &Path, cancel: &CancellationToken) -> Result<()> {
+    let output_file = File::create(tarball_path)
+        .with_context(|| format!("Failed to create backup file: {:?}", tarball_path))?;
+    let mut builder = Builder::new(GzEncoder::new(IoTuning::current().writer(output_file), Compression::default()));
+    builder.follow_symlinks(false);
+
+    let mut entries = Vec::new();
+    for entry in WalkDir::new(upper_path).sort_by_file_name() {
+        let entry = entry.context("Failed to walk the layer directory")?;
+        if entry.path() != upper_path {
+            entries.push(entry.into_path());
+        }
+    }
+    for path in entries {
+        cancel.check()?;
+        let relative_path = path.strip_prefix(upper_path)
+            .context("Failed to create relative path")?;
+        // read_xattrs follows symlinks, whose own xattrs are not worth keeping
+        let xattrs = if path.is_symlink() { Vec::new() } else { read_xattrs(&path) };
+        append_backup_entry(&mut builder, &path, relative_path, &xattrs)
+            .with_context(|| format!("Failed to add {:?} to backup", path))?;
+    }
+
+    builder.into_inner()
+        .and_then(|encoder| encoder.finish())
+        .and_then(|mut writer| writer.flush())
+        .context("Failed to finish backup archive")?;
+
+    Ok(())
+}
+
+/// Append one layer entry without following symlinks, under a ustar header with PAX records
+/// for whatever it cannot hold. Device nodes (overlayfs whiteouts) keep their device numbers.
+fn append_backup_entry<W: Write>(builder: &mut Builder<W>, path: &Path, name: &Path, xattrs: &[(String, Vec<u8>)]) -> std::io::Result<()> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    let file_type = metadata.file_type();
+    let mut header = Header::new_ustar();
+    header.set_metadata(&metadata);
+    let mtime_nsec = metadata.mtime_nsec() as u32;
+
+    if file_type.is_file() {
+        // A file that changed size since its metadata was read would not match its header
+        let file = File::open(path)?.take(metadata.len()).chain(std::io::repeat(0)).take(metadata.len());
+        return append_pax_entry(builder, &mut header, name, None, mtime_nsec, xattrs, file);
+    }
+    if file_type.is_symlink() {
+        let target = std::fs::read_link(path)?;
+        return append_pax_entry(builder, &mut header, name, Some(&target), mtime_nsec, xattrs, std::io::empty());
+    }
+    if file_type.is_char_device() || file_type.is_block_device() {
+        let device = metadata.rdev();
+        header.set_device_major(libc::major(device))?;
+        header.set_device_minor(libc::minor(device))?;
+    } else if !file_type.is_dir() && !file_type.is_fifo() {
+        return Err(std::io::Error::other(format!("{} has a type that cannot be archived", path.display())));
+    }
+    header.set_size(0);
+    append_pax_entry(builder, &mut header, name, None, mtime_nsec, xattrs, std::io::empty())
+}
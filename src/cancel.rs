@@ -0,0 +1,73 @@
+use std::fmt;
+use std::io::Read;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Process exit code for an operation cancelled with Ctrl-C (128 + SIGINT)
+pub const CANCELLED_EXIT_CODE: i32 = 130;
+
+/// Shared flag used to cancel a running export, import, or check.
+/// Clones observe the same flag, so a handle kept by the caller can cancel a command from
+/// another thread; long-running loops poll it and unwind with a [`Cancelled`] error.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request cancellation of every operation holding this token
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Fail with [`Cancelled`] once cancellation has been requested
+    pub fn check(&self) -> anyhow::Result<()> {
+        if self.is_cancelled() {
+            return Err(Cancelled.into());
+        }
+        Ok(())
+    }
+}
+
+/// Error returned by operations stopped through a [`CancellationToken`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Operation cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Reader adapter that fails with [`Cancelled`] once the token is cancelled,
+/// so copies, compression, and extraction stop at the next read
+pub struct CancellableReader<'a, R> {
+    inner: R,
+    cancel: &'a CancellationToken,
+}
+
+impl<'a, R: Read> CancellableReader<'a, R> {
+    pub fn new(inner: R, cancel: &'a CancellationToken) -> Self {
+        Self { inner, cancel }
+    }
+}
+
+impl<R: Read> Read for CancellableReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.cancel.is_cancelled() {
+            // Not ErrorKind::Interrupted: std::io::copy would retry that forever
+            return Err(std::io::Error::other(Cancelled));
+        }
+        self.inner.read(buf)
+    }
+}
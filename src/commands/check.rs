@@ -1,207 +1,446 @@
 use anyhow::{Context, Result};
-use std::fs::File;
+use std::cell::OnceCell;
 use std::path::Path;
-use tar::Archive;
-use tempfile::TempDir;
+use std::sync::Arc;
+use std::time::Instant;
 
-use crate::docker::DockerClient;
+use crate::attributes::AttributeRestorer;
+use crate::cancel::CancellationToken;
+use crate::crypto::decrypt_if_needed;
+use crate::commands::import::{image_mismatch, is_running_state};
+use crate::excludes::matches_component;
+use crate::docker::{major_minor_version, same_architecture, DockerClient, IncompatibleContainer};
+use crate::diagnostics::{Diagnostic, DiagnosticCode, Severity};
 use crate::output::*;
-use crate::types::{CheckOptions, ExportData};
+use crate::signing::{verify_export_signature, SignatureStatus};
+use crate::types::{
+    ArchiveFormat, CheckOptions, CheckReport, CheckStatus, CheckVerdict, CheckedExport, CommandMetrics, DockerInfo, EnvironmentInfo, ExportDataV2, LayerKind, Manifest, OutputFormat,
+    SizedEntry, CHECK_REPORT_VERSION
+};
+use crate::http::{self, is_http_url};
+use crate::stream::{read_export_header, LayerFindings, LayerSizes, LayerWhiteouts};
 use crate::utils::{
-    decompress_file, is_gzip_file,
-    calculate_file_checksum, format_file_size, get_file_size
+    verify_directory_against_manifest, describe_layer_stats, filesystem_free_space, format_file_size, get_file_size, export_format, manifest_disk_usage,
+    reject_partial_file, assemble_chunks, estimate_uncompressed_size, find_chunk_index, is_writable, spool_stdin, verify_file_sha256,
+    zstd_seek_table, DiskUsage, EstimateSource, UncompressedEstimate, STDIN_PATH
 };
+use crate::verify::verify_export_file;
+use crate::workspace::Workspace;
+
+/// Kernels up to this many minor releases apart (within one major version) count as close enough
+const KERNEL_MINOR_TOLERANCE: u64 = 4;
+
+/// Free space beyond what the import needs, as a percentage of it, below which check --container warns
+const FREE_SPACE_HEADROOM_PERCENT: u64 = 20;
+
+/// Paths the deletions section lists; the JSON report has all of them
+const DELETION_LIST_LIMIT: usize = 20;
 
 pub struct CheckCommand {
     docker_client: DockerClient,
+    cancel: CancellationToken,
+    /// Where this command's output goes; the installed printer's sink unless set by `with_output`
+    output: Arc<dyn OutputSink>,
+    /// The current Docker environment, queried once for all the files checked
+    docker_info: OnceCell<Result<DockerInfo, String>>,
 }
 
 impl CheckCommand {
     pub fn new() -> Self {
         Self {
             docker_client: DockerClient::new(),
+            cancel: CancellationToken::new(),
+            output: current_sink(),
+            docker_info: OnceCell::new(),
         }
     }
 
+    /// Use `cancel` to stop the check
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Send this command's output to `output` instead, e.g. a [`CaptureSink`]
+    pub fn with_output(mut self, output: Arc<dyn OutputSink>) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Handle that cancels this command's running operations
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
     /// Check export file integrity and compatibility
     pub fn execute(&self, input_path: &str, options: CheckOptions) -> Result<()> {
-        print_progress(&format!("Checking export file: {}", input_path));
+        let _output = enter_output(&self.output);
+        // stdout carries the JSON report, so all human-readable output moves to stderr
+        if options.output == OutputFormat::Json {
+            set_output_to_stderr(true);
+        }
+        let (report, result) = self.check_file(input_path, &options);
 
-        let input_file_path = Path::new(input_path);
-        if !input_file_path.exists() {
-            return Err(anyhow::anyhow!("Input file not found: {}", input_path));
+        if options.output == OutputFormat::Json {
+            let report_json = serde_json::to_string_pretty(&report)
+                .context("Failed to serialize report")?;
+            print_result(&report_json);
         }
+        result
+    }
 
-        let file_size = get_file_size(input_file_path)?;
-        print_labeled_value("File size", &format_file_size(file_size));
+    /// Check several export files, and the files matching `options.glob` in directory inputs,
+    /// one after the other. Docker is queried once for all of them; the run ends with a summary
+    /// table and fails if any file failed. With `options.fail_fast` the files after the first
+    /// failure are not checked.
+    pub fn execute_many(&self, input_paths: &[String], options: CheckOptions) -> Result<()> {
+        let _output = enter_output(&self.output);
+        if options.output == OutputFormat::Json {
+            set_output_to_stderr(true);
+        }
+        let files = expand_check_inputs(input_paths, options.glob.as_deref())?;
+
+        let mut reports = Vec::new();
+        let mut failed = Vec::new();
+        let mut skipped = Vec::new();
+        for (index, input_path) in files.iter().enumerate() {
+            self.cancel.check()?;
+            if options.fail_fast && !failed.is_empty() {
+                let error = anyhow::anyhow!("Not checked after an earlier failure (--fail-fast)");
+                skipped.push(format!("{}: {}", input_path, error));
+                let mut report = new_check_report(input_path, &options);
+                finish_check_report(&mut report, &Err(error));
+                reports.push(report);
+                continue;
+            }
+            print_section_header(&format!("[{}/{}] {}", index + 1, files.len(), input_path));
+            let (report, result) = self.check_file(input_path, &options);
+            if let Err(error) = &result {
+                print_error(&format!("Check of {} failed: {:#}", input_path, error));
+                failed.push(format!("{}: {:#}", input_path, error));
+            }
+            reports.push(report);
+        }
+
+        if options.output == OutputFormat::Json {
+            let report_json = serde_json::to_string_pretty(&reports)
+                .context("Failed to serialize report")?;
+            print_result(&report_json);
+        }
+
+        print_section_header("Check Summary");
+        let rows: Vec<Vec<String>> = reports.iter()
+            .map(|report| vec![
+                report.input_path.clone(),
+                report.file_size.map(format_file_size).unwrap_or_else(|| "-".to_string()),
+                report.export.as_ref().map(|export| export.container_name.clone()).unwrap_or_else(|| "-".to_string()),
+                report.verdict.to_string(),
+                report.warnings.len().to_string(),
+            ])
+            .collect();
+        print_table(&["File", "Size", "Container", "Verdict", "Warnings"], &rows);
+        print_warnings_section(&skipped);
+        print_errors_section(&failed);
+
+        if !failed.is_empty() {
+            return Err(anyhow::anyhow!("{} of {} export file(s) failed the check", failed.len(), files.len()));
+        }
+        print_success(&format!("\n✅ All {} export files passed the check.", files.len()));
+        Ok(())
+    }
+
+    /// Check one export file, returning its report along with the result
+    fn check_file(&self, input_path: &str, options: &CheckOptions) -> (CheckReport, Result<()>) {
+        take_warnings();
+        take_diagnostics();
+        take_check_results();
+
+        let mut report = new_check_report(input_path, options);
+        let result = self.check(input_path, options, &mut report);
+        finish_check_report(&mut report, &result);
+        (report, result)
+    }
+
+    /// Run the checks, recording what was found in `report`
+    fn check(&self, input_path: &str, options: &CheckOptions, report: &mut CheckReport) -> Result<()> {
+        print_progress(&format!("Checking export file: {}", input_path));
 
         // Create temporary directory for extraction
-        let temp_dir = TempDir::new()
-            .context("Failed to create temporary directory")?;
+        let temp_dir = match &options.tmpdir {
+            Some(tmpdir) => Workspace::new_in(tmpdir)
+                .with_context(|| format!("Failed to create temporary directory in {}", tmpdir))?,
+            None => Workspace::new()
+                .context("Failed to create temporary directory")?,
+        };
         let temp_path = temp_dir.path();
 
-        // Handle decompression if needed
-        let is_compressed = is_gzip_file(input_file_path)?;
-        let export_tar_path = if is_compressed {
-            print_check_result("File compression", "✓ Compressed (gzip)", true);
-            let decompressed_path = temp_path.join("export.tar");
-            decompress_file(input_file_path, &decompressed_path)
-                .context("Failed to decompress input file")?;
-            decompressed_path
+        // Exports piped into stdin or downloaded are spooled to the workspace so they can be read more than once
+        let spooled_path = temp_path.join("input");
+        let input_file_path = if is_http_url(input_path) {
+            http::ensure_available()?;
+            if options.verify_signature {
+                return Err(anyhow::anyhow!(
+                    "--verify-signature is not supported for HTTP(S) inputs; download the export and its .sig first"
+                ));
+            }
+            print_progress(&format!("Downloading export from {}...", input_path));
+            let bytes = http::download_to_file(input_path, &options.headers, &spooled_path, &self.cancel)?;
+            print_labeled_value("Downloaded", &format_file_size(bytes));
+            spooled_path.as_path()
+        } else if input_path == STDIN_PATH {
+            if options.verify_signature {
+                return Err(anyhow::anyhow!(
+                    "--verify-signature is not supported when reading from stdin; check the export file and its .sig instead"
+                ));
+            }
+            print_progress("Reading export from stdin...");
+            spool_stdin(&spooled_path, &self.cancel)?;
+            spooled_path.as_path()
+        } else {
+            Path::new(input_path)
+        };
+        if !input_file_path.exists() {
+            return Err(anyhow::anyhow!("Input file not found: {}", input_path));
+        }
+        reject_partial_file(input_file_path)?;
+
+        // The bytes as received must be the ones that were published before anything reads them
+        if let Some(expected) = &options.sha256 {
+            print_progress("Verifying input checksum (--sha256)...");
+            let checksum = verify_file_sha256(input_file_path, expected)?;
+            print_checksum("Input checksum verified", &checksum);
+        }
+
+        // Verify the detached signature over the file as distributed
+        let signature_status = if options.verify_signature {
+            verify_export_signature(input_file_path, options.pubkey.as_deref())
+                .context("Signature verification failed")?
         } else {
-            print_check_result("File compression", "✓ Uncompressed", true);
-            input_file_path.to_path_buf()
+            SignatureStatus::NotChecked
+        };
+
+        // Reassemble split exports from their chunks before anything else reads the file
+        let assembled_path = temp_path.join("assembled");
+        let input_file_path = match find_chunk_index(input_file_path) {
+            Some(index_path) => {
+                print_progress(&format!("Reassembling split export from {}...", index_path.display()));
+                let index = assemble_chunks(&index_path, &assembled_path)
+                    .context("Failed to reassemble split export")?;
+                print_labeled_value("Chunks verified", &index.chunk_count.to_string());
+                assembled_path.as_path()
+            }
+            None => input_file_path,
         };
 
-        // Extract and validate archive structure
-        print_progress("Checking archive structure...");
-        let extract_dir = temp_path.join("extracted");
-        std::fs::create_dir_all(&extract_dir)
-            .context("Failed to create extraction directory")?;
+        // Decrypt age-encrypted exports with the supplied identity
+        let decrypted_path = temp_path.join("decrypted");
+        let input_file_path = decrypt_if_needed(input_file_path, &decrypted_path, options.identity.as_deref().map(Path::new))?;
 
-        self.extract_and_validate_structure(&export_tar_path, &extract_dir)
-            .context("Failed to validate archive structure")?;
+        let file_size = get_file_size(input_file_path)?;
+        print_labeled_value("File size", &format_file_size(file_size));
+        report.file_size = Some(file_size);
+        let estimate = estimate_uncompressed_size(input_file_path, file_size)?;
+        print_labeled_value("Estimated uncompressed size", &describe_estimate(&estimate));
+        report.estimated_uncompressed_size = Some(estimate.bytes);
+        let seek_table = zstd_seek_table(input_file_path)?;
+        if let Some(table) = &seek_table {
+            print_labeled_value("Seek table", &format!("{} zstd frames", table.frames().len()));
+        }
+
+        // Deep verification unpacks the whole layer, so make sure it fits before starting
+        if options.deep {
+            self.check_workspace_space(input_file_path, temp_path)?;
+        }
+
+        // Validate structure, metadata, and layer checksum
+        let started = Instant::now();
+        let verified = verify_export_file(input_file_path, temp_path, options.deep, options.top, &self.cancel)?;
+        let export_data = verified.export_data;
+        report.compressed = Some(verified.format.is_compressed());
+        report.seekable = (verified.format == ArchiveFormat::Zstd).then_some(seek_table.is_some());
+        report.export = Some(checked_export(&export_data));
+        print_layer_capacity(file_size, verified.format, &verified.sizes, report);
+        if let Some(manifest) = &verified.manifest {
+            check_uncompressed_estimate(&estimate, manifest, report);
+        }
+        report.largest_entries = verified.sizes.largest_entries();
+        report.directory_sizes = verified.sizes.directory_sizes();
+        check_whiteouts(&verified.whiteouts);
+        report.whiteouts = verified.whiteouts.removed.clone();
+        report.opaque_directories = verified.whiteouts.opaque.clone();
+        if options.scan {
+            check_findings(&verified.findings);
+            report.findings = verified.findings.findings.clone();
+        }
 
-        // Read and validate metadata
-        print_progress("Validating metadata...");
-        let metadata_path = extract_dir.join("metadata.json");
-        let export_data = self.read_and_validate_metadata(&metadata_path)
-            .context("Failed to validate metadata")?;
+        // Verify every layer entry against the manifest
+        if options.deep && let Some(layer_dir) = &verified.layer_dir {
+            print_progress("Performing deep verification of layer contents...");
+            self.deep_verify_layer(layer_dir, verified.manifest.as_ref(), export_data.incremental.is_some(), report)
+                .context("Deep verification failed")?;
+            let elapsed = started.elapsed().as_secs_f64();
+            let layer_size = verified.layer_size;
+            print_labeled_value("Verification throughput", &format!(
+                "{} in {:.1}s ({:.1} MB/s)",
+                format_file_size(layer_size), elapsed, layer_size as f64 / 1024.0 / 1024.0 / elapsed.max(f64::EPSILON)
+            ));
+        }
 
-        // Validate layer archive
-        print_progress("Validating layer archive...");
-        let layer_tar_path = extract_dir.join("layer.tar");
-        self.validate_layer_archive(&layer_tar_path, &export_data)
-            .context("Failed to validate layer archive")?;
+        // Enforce provenance policies
+        if !options.required_annotations.is_empty() {
+            self.check_required_annotations(&export_data, &options.required_annotations, report)
+                .context("Required annotation check failed")?;
+        }
 
         // Perform compatibility checks
         print_progress("Performing compatibility checks...");
-        self.perform_compatibility_checks(&export_data, &options)
+        self.perform_compatibility_checks(&export_data, options, report)
             .context("Compatibility checks failed")?;
 
-        // Display check results
-        self.display_check_results(&export_data, is_compressed, &options)?;
+        if let Some(container_id) = &options.container {
+            print_progress(&format!("Checking target container {}...", container_id));
+            self.check_target_container(&export_data, container_id, verified.sizes.disk_usage, report)?;
+        }
+
+        // The summary below repeats the checks already recorded
+        report.checks = take_check_results();
+        enforce_warning_policy(options, report)?;
+        self.display_check_results(&export_data, verified.format, verified.layer_checksum_verified, signature_status, options)?;
+        print_layer_sizes(&report.largest_entries, &report.directory_sizes, options.top);
+        print_deletions(&verified.whiteouts);
+        take_check_results();
 
         print_success("\n✅ All checks passed! Export file is valid and complete.");
 
         Ok(())
     }
 
-    /// Extract archive and validate basic structure
-    fn extract_and_validate_structure(&self, archive_path: &Path, output_dir: &Path) -> Result<()> {
-        let archive_file = File::open(archive_path)
-            .context("Failed to open export archive")?;
-        let mut archive = Archive::new(archive_file);
-
-        // Extract archive
-        archive.unpack(output_dir)
-            .context("Failed to extract export archive")?;
+    /// Verify that every required annotation (`key` or `key=value`) is present in the export
+    fn check_required_annotations(&self, export_data: &ExportDataV2, required: &[String], report: &mut CheckReport) -> Result<()> {
+        let mut errors = Vec::new();
 
-        // Check required files exist
-        let metadata_path = output_dir.join("metadata.json");
-        let layer_tar_path = output_dir.join("layer.tar");
+        for requirement in required {
+            let (key, expected_value) = match requirement.split_once('=') {
+                Some((key, value)) => (key, Some(value)),
+                None => (requirement.as_str(), None),
+            };
 
-        if !metadata_path.exists() {
-            return Err(anyhow::anyhow!("Missing metadata.json in export archive"));
+            match (export_data.annotations.get(key), expected_value) {
+                (None, _) => errors.push(format!("Missing required annotation '{}'", key)),
+                (Some(actual), Some(expected)) if actual != expected => errors.push(format!(
+                    "Annotation '{}' is '{}', expected '{}'",
+                    key, actual, expected
+                )),
+                _ => print_check_result(&format!("Annotation {}", key), "✓ Present", true),
+            }
         }
 
-        if !layer_tar_path.exists() {
-            return Err(anyhow::anyhow!("Missing layer.tar in export archive"));
+        print_errors_section(&errors);
+        report.errors.extend(errors.iter().cloned());
+        if !errors.is_empty() {
+            return Err(anyhow::anyhow!("{} required annotation(s) missing or mismatched", errors.len()));
         }
 
-        print_check_result("Archive structure", "✓ Valid", true);
         Ok(())
     }
 
-    /// Read and validate metadata file
-    fn read_and_validate_metadata(&self, metadata_path: &Path) -> Result<ExportData> {
-        let metadata_content = std::fs::read_to_string(metadata_path)
-            .context("Failed to read metadata file")?;
-
-        let export_data: ExportData = serde_json::from_str(&metadata_content)
-            .context("Failed to parse metadata JSON")?;
-
-        // Validate required fields
-        if export_data.version.is_empty() {
-            return Err(anyhow::anyhow!("Missing or empty version in metadata"));
-        }
-
-        if export_data.container_metadata.id.is_empty() {
-            return Err(anyhow::anyhow!("Missing or empty container ID in metadata"));
-        }
-
-        if export_data.container_metadata.image_sha256.is_empty() {
-            return Err(anyhow::anyhow!("Missing or empty image SHA256 in metadata"));
+    /// Fail unless the filesystem holding `workspace` has room for the decompressed export, its
+    /// layer archive, and the extracted layer
+    fn check_workspace_space(&self, export_path: &Path, workspace: &Path) -> Result<()> {
+        let header = read_export_header(export_path, &self.cancel)?;
+        let layer_size = header.layer_size
+            .ok_or_else(|| anyhow::anyhow!("Layer archive not found in export"))?;
+        let manifest = header.manifest.as_deref()
+            .map(serde_json::from_slice::<Manifest>)
+            .transpose()
+            .context("Failed to parse layer manifest")?;
+        let mut needed = match &manifest {
+            Some(manifest) => manifest_disk_usage(manifest),
+            None => DiskUsage { bytes: layer_size, inodes: 0 },
+        };
+        needed.bytes += layer_size;
+        if export_format(export_path)?.is_compressed() {
+            needed.bytes += get_file_size(export_path)?.max(layer_size);
         }
 
-        if export_data.layer_checksum.is_empty() {
-            return Err(anyhow::anyhow!("Missing or empty layer checksum in metadata"));
+        let free = filesystem_free_space(workspace)?;
+        if needed.bytes > free.bytes || needed.inodes > free.inodes {
+            return Err(anyhow::anyhow!(
+                "Not enough free space in {:?} for deep verification: needs {} and {} inodes, {} and {} inodes available. \
+                Free up space or point --tmpdir at a larger filesystem",
+                workspace, format_file_size(needed.bytes), needed.inodes, format_file_size(free.bytes), free.inodes
+            ));
         }
-
-        print_check_result("Metadata", "✓ Valid", true);
-        print_metadata_item("Version", &export_data.version);
-        print_container_info("Container", &export_data.container_metadata.name, &export_data.container_metadata.id);
-        print_metadata_item("Image", &export_data.container_metadata.image);
-
-        Ok(export_data)
+        print_check_result("Workspace space", &format!("✓ {} free for {} needed", format_file_size(free.bytes), format_file_size(needed.bytes)), true);
+        Ok(())
     }
 
-    /// Validate layer archive integrity
-    fn validate_layer_archive(&self, layer_tar_path: &Path, export_data: &ExportData) -> Result<()> {
-        // Check if layer tar file can be opened
-        let layer_file = File::open(layer_tar_path)
-            .context("Failed to open layer archive")?;
-        let mut layer_archive = Archive::new(layer_file);
-
-        // Try to list entries to validate tar structure
-        let entries = layer_archive.entries()
-            .context("Failed to read layer archive entries")?;
+    /// Verify every entry of the extracted layer against the manifest
+    fn deep_verify_layer(&self, layer_dir: &Path, manifest: Option<&Manifest>, incremental: bool, report: &mut CheckReport) -> Result<()> {
+        let manifest = match manifest {
+            Some(manifest) => manifest,
+            None => {
+                print_warning("Export has no per-file manifest; skipping deep verification");
+                return Ok(());
+            }
+        };
 
-        let mut entry_count = 0;
-        for entry in entries {
-            let _entry = entry.context("Failed to read layer archive entry")?;
-            entry_count += 1;
+        // Incremental exports only carry changed entries, so unchanged manifest entries are expected to be absent
+        // The layer was extracted with every recorded attribute, which only root restores exactly
+        let verified = AttributeRestorer::new().verified_attributes();
+        let mismatches = verify_directory_against_manifest(layer_dir, manifest, incremental, verified)?;
+        if !mismatches.is_empty() {
+            print_errors_section(&mismatches);
+            report.errors.extend(mismatches.iter().cloned());
+            return Err(anyhow::anyhow!(
+                "{} path(s) do not match the manifest",
+                mismatches.len()
+            ));
         }
 
-        print_check_result("Layer archive", &format!("✓ Readable ({} entries)", entry_count), true);
-
-        // Verify layer checksum
-        let calculated_checksum = calculate_file_checksum(layer_tar_path)
-            .context("Failed to calculate layer archive checksum")?;
-
-        // Note: This compares the tar file checksum, not the directory checksum
-        // In a real implementation, you might want to extract and verify the directory checksum
-        print_checksum("Layer archive checksum calculated", &calculated_checksum);
-        print_metadata_item("Expected layer checksum", &export_data.layer_checksum);
-
+        print_check_result("Deep verification", &format!("✓ All {} entries match the manifest", manifest.entries.len()), true);
         Ok(())
     }
 
+    /// The current Docker environment, queried on first use
+    fn current_docker_info(&self) -> Result<&DockerInfo> {
+        self.docker_info
+            .get_or_init(|| with_spinner(DOCKER_QUERY_MESSAGE, || self.docker_client.get_docker_info()).map_err(|e| e.to_string()))
+            .as_ref()
+            .map_err(|e| anyhow::anyhow!("{}", e))
+    }
+
     /// Perform compatibility checks with current Docker environment
-    fn perform_compatibility_checks(&self, export_data: &ExportData, options: &CheckOptions) -> Result<()> {
+    fn perform_compatibility_checks(&self, export_data: &ExportDataV2, options: &CheckOptions, report: &mut CheckReport) -> Result<()> {
         // Get current Docker info for comparison
-        let current_docker_info = match self.docker_client.get_docker_info() {
+        let current_docker_info = match self.current_docker_info() {
             Ok(info) => info,
             Err(e) => {
-                print_warning(&format!("Could not get current Docker info: {}", e));
+                print_diagnostic(Diagnostic::new(
+                    DiagnosticCode::DockerInfoUnavailable,
+                    format!("Could not get current Docker info: {}", e),
+                ).with_context("error", e));
                 print_warning("Skipping Docker environment compatibility checks");
                 return Ok(());
             }
         };
 
-        let mut warnings = Vec::new();
-        let mut errors = Vec::new();
+        let current = EnvironmentInfo::from(current_docker_info);
+        let mut diagnostics = Vec::new();
 
         // Check storage driver compatibility
         if !options.skip_storage {
-            if export_data.docker_info.driver != current_docker_info.driver {
-                warnings.push(format!(
+            if export_data.environment.driver != current.driver {
+                diagnostics.push(Diagnostic::new(DiagnosticCode::StorageDriverMismatch, format!(
                     "Storage driver mismatch: export uses '{}', current system uses '{}'",
-                    export_data.docker_info.driver,
-                    current_docker_info.driver
-                ));
+                    export_data.environment.driver,
+                    current.driver
+                ))
+                .with_context("export", &export_data.environment.driver)
+                .with_context("current", &current.driver));
+                print_check_result("Storage driver", &format!("⚠ Mismatch: {}", export_data.environment.driver), false);
             } else {
-                print_check_result("Storage driver", &format!("✓ Compatible: {}", current_docker_info.driver), true);
+                print_check_result("Storage driver", &format!("✓ Compatible: {}", current.driver), true);
             }
         } else {
             print_check_result("Storage driver check", "⏭ Skipped", false);
@@ -209,29 +448,48 @@ impl CheckCommand {
 
         // Check OS compatibility
         if !options.skip_os {
-            if export_data.docker_info.operating_system != current_docker_info.operating_system {
-                warnings.push(format!(
+            if export_data.environment.os != current.os {
+                diagnostics.push(Diagnostic::new(DiagnosticCode::OsMismatch, format!(
                     "Operating system mismatch: export from '{}', current system is '{}'",
-                    export_data.docker_info.operating_system,
-                    current_docker_info.operating_system
-                ));
+                    export_data.environment.os,
+                    current.os
+                ))
+                .with_context("export", &export_data.environment.os)
+                .with_context("current", &current.os));
+                print_check_result("Operating system", &format!("⚠ Mismatch: {}", export_data.environment.os), false);
             } else {
-                print_check_result("Operating system", &format!("✓ Compatible: {}", current_docker_info.operating_system), true);
+                print_check_result("Operating system", &format!("✓ Compatible: {}", current.os), true);
             }
         } else {
             print_check_result("OS check", "⏭ Skipped", false);
         }
 
+        // Overlay behavior has differed between Docker releases, kernels, and cgroup drivers
+        if !options.skip_version {
+            check_docker_version(&export_data.environment, &current, &mut diagnostics);
+            check_kernel_version(&export_data.environment, &current, &mut diagnostics);
+        } else {
+            print_check_result("Version checks", "⏭ Skipped", false);
+        }
+        if !options.skip_cgroup {
+            check_cgroup_driver(&export_data.environment, &current, &mut diagnostics);
+        } else {
+            print_check_result("Cgroup driver check", "⏭ Skipped", false);
+        }
+
         // Check architecture compatibility
         if !options.skip_arch {
-            if export_data.docker_info.architecture != current_docker_info.architecture {
-                errors.push(format!(
+            if !same_architecture(&export_data.environment.architecture, &current.architecture) {
+                diagnostics.push(Diagnostic::new(DiagnosticCode::ArchitectureMismatch, format!(
                     "Architecture mismatch: export from '{}', current system is '{}'",
-                    export_data.docker_info.architecture,
-                    current_docker_info.architecture
-                ));
+                    export_data.environment.architecture,
+                    current.architecture
+                ))
+                .with_context("export", &export_data.environment.architecture)
+                .with_context("current", &current.architecture));
+                print_check_result("Architecture", &format!("✗ Incompatible: {}", export_data.environment.architecture), false);
             } else {
-                print_check_result("Architecture", &format!("✓ Compatible: {}", current_docker_info.architecture), true);
+                print_check_result("Architecture", &format!("✓ Compatible: {}", current.architecture), true);
             }
         } else {
             print_check_result("Architecture check", "⏭ Skipped", false);
@@ -247,8 +505,11 @@ impl CheckCommand {
         }
 
         // Display warnings and errors
-        print_warnings_section(&warnings);
-        print_errors_section(&errors);
+        print_diagnostics_sections(&diagnostics);
+        let (errors, warnings): (Vec<_>, Vec<_>) = diagnostics.iter()
+            .partition(|diagnostic| diagnostic.severity == Severity::Error);
+        report.warnings.extend(warnings.iter().map(ToString::to_string));
+        report.errors.extend(errors.iter().map(ToString::to_string));
 
         // Fail if any errors
         if !errors.is_empty() {
@@ -258,12 +519,118 @@ impl CheckCommand {
         Ok(())
     }
 
+    /// Check that the export applies cleanly to `container_id`: the image it was taken from, a
+    /// stopped container, a writable upper layer, and room for the uncompressed `layer` there
+    fn check_target_container(&self, export_data: &ExportDataV2, container_id: &str, layer: DiskUsage, report: &mut CheckReport) -> Result<()> {
+        let target = with_spinner(DOCKER_QUERY_MESSAGE, || self.docker_client.get_container_metadata(container_id))
+            .context("Failed to get target container metadata")?;
+        let mut problems = Vec::new();
+
+        match image_mismatch(export_data, &target) {
+            None => print_check_result("Target image digest", &format!("✓ Matches {}", target.image_sha256), true),
+            Some(mismatch) => {
+                print_check_result("Target image digest", &format!("✗ Mismatch: {}", target.image_sha256), false);
+                problems.push(mismatch);
+            }
+        }
+        if target.image == export_data.container_metadata.image {
+            print_check_result("Target image name", &format!("✓ Matches {}", target.image), true);
+        } else {
+            print_check_result("Target image name", &format!(
+                "⚠ {} (export taken from {})", target.image, export_data.container_metadata.image
+            ), false);
+        }
+
+        if is_running_state(&target.state) {
+            print_check_result("Target state", &format!("⚠ {}; stop it before importing or pass --force", target.state), false);
+        } else {
+            print_check_result("Target state", &format!("✓ {}", target.state), true);
+        }
+
+        let upper_path = self.docker_client.get_upper_layer_path(container_id)
+            .context("Failed to get target container layer path")?;
+        if !upper_path.exists() {
+            print_check_result("Target layer directory", &format!("✗ Not found: {}", upper_path.display()), false);
+            problems.push(format!("Layer directory of container {} not found: {:?}", container_id, upper_path));
+        } else if !is_writable(&upper_path)? {
+            print_check_result("Target layer directory", &format!("✗ Not writable: {}", upper_path.display()), false);
+            problems.push(format!("Layer directory of container {} is not writable: {:?}", container_id, upper_path));
+        } else {
+            print_check_result("Target layer directory", &format!("✓ Writable: {}", upper_path.display()), true);
+
+            let free = filesystem_free_space(&upper_path)?;
+            report.target_free_space = Some(free.bytes);
+            if layer.bytes > free.bytes || layer.inodes > free.inodes {
+                print_check_result("Target free space", &format!(
+                    "✗ {} free for a {} layer", format_file_size(free.bytes), format_file_size(layer.bytes)
+                ), false);
+                problems.push(format!(
+                    "Not enough free space for the layer in {:?}: needs {} and {} inodes, {} and {} inodes available",
+                    upper_path, format_file_size(layer.bytes), layer.inodes, format_file_size(free.bytes), free.inodes
+                ));
+            } else if free.bytes - layer.bytes < layer.bytes * FREE_SPACE_HEADROOM_PERCENT / 100 {
+                print_check_result("Target free space", &format!(
+                    "⚠ {} free for a {} layer, under {}% headroom", format_file_size(free.bytes), format_file_size(layer.bytes), FREE_SPACE_HEADROOM_PERCENT
+                ), false);
+                print_warning(&format!(
+                    "Importing leaves only {} free in {:?}; the layer needs {}",
+                    format_file_size(free.bytes - layer.bytes), upper_path, format_file_size(layer.bytes)
+                ));
+            } else {
+                print_check_result("Target free space", &format!(
+                    "✓ {} free for a {} layer", format_file_size(free.bytes), format_file_size(layer.bytes)
+                ), true);
+            }
+        }
+
+        print_errors_section(&problems);
+        report.errors.extend(problems.iter().cloned());
+        if !problems.is_empty() {
+            return Err(IncompatibleContainer {
+                container: container_id.to_string(),
+                problems,
+            }.into());
+        }
+        Ok(())
+    }
+
     /// Display comprehensive check results
-    fn display_check_results(&self, export_data: &ExportData, is_compressed: bool, options: &CheckOptions) -> Result<()> {
+    fn display_check_results(
+        &self,
+        export_data: &ExportDataV2,
+        format: ArchiveFormat,
+        layer_recomputed: bool,
+        signature_status: SignatureStatus,
+        options: &CheckOptions,
+    ) -> Result<()> {
         print_section_header("Check Results");
-        print_labeled_value("Export file format", if is_compressed { "Compressed (gzip)" } else { "Uncompressed" });
-        print_labeled_value("Export version", &export_data.version);
+        print_labeled_value("Export file format", &match format.is_compressed() {
+            true => format!("Compressed ({})", format),
+            false => "Uncompressed".to_string(),
+        });
+        print_labeled_value("Export version", &export_data.version.to_string());
+        print_labeled_value("Layer kind", &export_data.layer_kind.to_string());
         print_labeled_value("Export created", &export_data.created.format("%Y-%m-%d %H:%M:%S UTC").to_string());
+        if !export_data.mounts.is_empty() {
+            print_labeled_value("Mounts included", &export_data.mounts.len().to_string());
+        }
+        print_annotations(&export_data.annotations);
+        if !export_data.redacted_keys.is_empty() {
+            print_warning(&format!(
+                "Metadata values were redacted at export time: {}",
+                export_data.redacted_keys.join(", ")
+            ));
+        }
+        if !export_data.transient_entries.is_empty() {
+            print_warning(&format!(
+                "{} path(s) vanished or changed while the layer was archived; the export is not an exact snapshot",
+                export_data.transient_entries.len()
+            ));
+            print_transient_entries(&export_data.transient_entries);
+        }
+        if export_data.layer_kind == LayerKind::Full {
+            print_warning("This export contains a full root filesystem; importing it requires --as-upper");
+        }
 
         print_info("\nContainer information:");
         print_metadata_item("ID", &export_data.container_metadata.id);
@@ -274,29 +641,554 @@ impl CheckCommand {
         print_metadata_item("State", &export_data.container_metadata.state);
 
         print_info("\nDocker environment (at export time):");
-        print_metadata_item("Storage driver", &export_data.docker_info.driver);
-        print_metadata_item("Operating system", &export_data.docker_info.operating_system);
-        print_metadata_item("Architecture", &export_data.docker_info.architecture);
-        print_metadata_item("Docker version", &export_data.docker_info.server_version);
+        print_metadata_item("Storage driver", &export_data.environment.driver);
+        print_metadata_item("Operating system", &export_data.environment.os);
+        print_metadata_item("Architecture", &export_data.environment.architecture);
+        print_metadata_item("Docker version", &export_data.environment.server_version);
+        print_metadata_item("Kernel version", &export_data.environment.kernel);
+        print_metadata_item("Cgroup driver", &export_data.environment.cgroup_driver);
 
         print_info("\nLayer information:");
-        print_metadata_item("Checksum", &export_data.layer_checksum);
+        print_metadata_item("Checksum", &export_data.layer_checksum.to_string());
+        print_metadata_item("Checksum algorithm", &export_data.layer_checksum.algorithm.to_string());
+        print_metadata_item("Checksum scheme", &export_data.checksum_scheme.to_string());
+        match &export_data.incremental {
+            Some(incremental) => {
+                print_metadata_item("Export type", "Incremental");
+                print_metadata_item("Parent layer checksum", &incremental.parent_layer_checksum.to_string());
+                print_metadata_item("Parent export checksum", &incremental.parent_export_checksum);
+                print_metadata_item("Deleted paths", &incremental.deleted_paths.len().to_string());
+            }
+            None => print_metadata_item("Export type", "Full"),
+        }
 
         print_info("\nChecks performed:");
         print_check_result("Archive structure", "✓", true);
         print_check_result("Metadata validation", "✓", true);
         print_check_result("Layer archive integrity", "✓", true);
+        match export_data.layer_archive_checksum {
+            Some(_) => print_check_result("Layer archive checksum", "✓", true),
+            None => print_check_result("Layer archive checksum", "⏭ Not recorded", false),
+        }
+        match (export_data.incremental.is_some(), layer_recomputed) {
+            (true, _) => print_check_result("Layer checksum", "⏭ Incremental", false),
+            (false, true) => print_check_result("Layer checksum", "✓", true),
+            (false, false) => print_check_result("Layer checksum", "⏭ Covered by the archive checksum (--deep recomputes it)", false),
+        }
+        if !export_data.mounts.is_empty() {
+            print_check_result("Mount checksums", "✓", true);
+        }
+        match signature_status {
+            SignatureStatus::Valid => print_check_result("Signature", "✓ Valid", true),
+            SignatureStatus::NotChecked => print_check_result("Signature", "⏭ Not checked", false),
+        }
+        print_check_result("Deep verification", if options.deep { "✓" } else { "⏭ Skipped" }, options.deep);
+        print_check_result("Security scan", if options.scan { "✓" } else { "⏭ Skipped" }, options.scan);
         print_check_result("Storage driver compatibility", if options.skip_storage { "⏭ Skipped" } else { "✓" }, !options.skip_storage);
         print_check_result("OS compatibility", if options.skip_os { "⏭ Skipped" } else { "✓" }, !options.skip_os);
+        print_check_result("Version compatibility", if options.skip_version { "⏭ Skipped" } else { "✓" }, !options.skip_version);
+        print_check_result("Cgroup driver compatibility", if options.skip_cgroup { "⏭ Skipped" } else { "✓" }, !options.skip_cgroup);
         print_check_result("Architecture compatibility", if options.skip_arch { "⏭ Skipped" } else { "✓" }, !options.skip_arch);
         print_check_result("Image verification", if options.skip_image { "⏭ Skipped" } else { "✓" }, !options.skip_image);
+        if let Some(container_id) = &options.container {
+            print_check_result("Target container", &format!("✓ {}", container_id), true);
+        }
 
         Ok(())
     }
 }
 
+/// Fail the check on warnings the options promote to errors: every warning with `--strict`,
+/// otherwise the warnings of the checks named by `--warn-as-error`. The promoted checks are
+/// recorded as failed.
+fn enforce_warning_policy(options: &CheckOptions, report: &mut CheckReport) -> Result<()> {
+    for id in &options.warn_as_error {
+        if !report.checks.iter().any(|check| &check.id == id) {
+            let mut known: Vec<&str> = report.checks.iter().map(|check| check.id.as_str()).collect();
+            known.dedup();
+            print_warning(&format!("--warn-as-error {}: no such check ran (checks: {})", id, known.join(", ")));
+        }
+    }
+
+    let mut promoted = Vec::new();
+    for check in report.checks.iter_mut() {
+        if check.status == CheckStatus::Warn && (options.strict || options.warn_as_error.contains(&check.id)) {
+            check.status = CheckStatus::Fail;
+            promoted.push(format!("{} ({}): {}", check.name, check.id, check.detail));
+        }
+    }
+    if options.strict {
+        promoted.extend(printed_warnings());
+        promoted.extend(report.warnings.iter().cloned());
+    }
+    if promoted.is_empty() {
+        return Ok(());
+    }
+
+    print_errors_section(&promoted);
+    report.errors.extend(promoted.iter().cloned());
+    let flag = if options.strict { "--strict" } else { "--warn-as-error" };
+    Err(anyhow::anyhow!("Check FAILED: warnings treated as errors ({})", flag))
+}
+
+/// Print how much the layer holds and how much disk importing it takes, and record it in `report`
+fn print_layer_capacity(file_size: u64, format: ArchiveFormat, sizes: &LayerSizes, report: &mut CheckReport) {
+    print_labeled_value("Layer size", &format!(
+        "{} uncompressed in {} entries", format_file_size(sizes.content_bytes), sizes.disk_usage.inodes
+    ));
+    report.layer_bytes = Some(sizes.content_bytes);
+    report.layer_entries = Some(sizes.disk_usage.inodes);
+    let stats = sizes.stats();
+    print_labeled_value("Layer contents", &describe_layer_stats(&stats));
+    report.layer_stats = Some(stats);
+    if format.is_compressed() && file_size > 0 {
+        let ratio = sizes.content_bytes as f64 / file_size as f64;
+        print_labeled_value("Compression ratio", &format!("{:.1}:1", ratio));
+        report.compression_ratio = Some(ratio);
+    }
+    print_labeled_value("Disk needed for import", &format!(
+        "{} and {} inodes", format_file_size(sizes.disk_usage.bytes), sizes.disk_usage.inodes
+    ));
+    report.import_disk_usage = Some(sizes.disk_usage.bytes);
+}
+
+/// The estimated uncompressed size and where it comes from
+fn describe_estimate(estimate: &UncompressedEstimate) -> String {
+    match (estimate.source, estimate.wraps) {
+        (EstimateSource::FileSize, _) => format!("{} (file size)", format_file_size(estimate.bytes)),
+        (EstimateSource::SeekTable, _) => format!("{} (zstd seek table)", format_file_size(estimate.bytes)),
+        (EstimateSource::ZstdFrameHeader, _) => format!("{} (zstd frame header)", format_file_size(estimate.bytes)),
+        (EstimateSource::GzipTrailer, false) => format!("{} (gzip trailer)", format_file_size(estimate.bytes)),
+        (EstimateSource::GzipTrailer, true) => format!("{} or more in steps of 4 GB (gzip trailer)", format_file_size(estimate.bytes)),
+    }
+}
+
+/// Compare the estimated uncompressed size with the layer the manifest records. The export's
+/// tar holds the layer's content plus a header per entry, so an estimate far below the content
+/// or far above it with all that overhead means the gzip trailer or the manifest is corrupt.
+/// Where the trailer allows several sizes, the one matching the manifest is recorded.
+fn check_uncompressed_estimate(estimate: &UncompressedEstimate, manifest: &Manifest, report: &mut CheckReport) {
+    let content: u64 = manifest.entries.values().map(|entry| entry.size).sum();
+    let entries = manifest.entries.len() as u64;
+    // Hardlinks carry no content in the archive, and long paths take extra headers
+    let lowest = content / 2;
+    let highest = 2 * (content + 2048 * entries) + 16 * 1024 * 1024;
+
+    let bytes = estimate.at_least(lowest);
+    let recorded = format!("{} in {} entries", format_file_size(content), entries);
+    if bytes < lowest || bytes > highest {
+        print_check_result("Size estimate", &format!(
+            "⚠ {} does not fit the manifest's {}", format_file_size(bytes), recorded
+        ), false);
+        print_warning(&format!(
+            "The estimated uncompressed size ({}) conflicts with the layer the manifest records ({}); the file may be corrupt",
+            format_file_size(bytes), recorded
+        ));
+        return;
+    }
+    print_check_result("Size estimate", &format!("✓ {} fits the manifest's {}", format_file_size(bytes), recorded), true);
+    report.estimated_uncompressed_size = Some(bytes);
+}
+
+/// List the largest files of the layer and the bytes under its top-level directories
+fn print_layer_sizes(largest_entries: &[SizedEntry], directory_sizes: &[SizedEntry], top: usize) {
+    if !largest_entries.is_empty() {
+        print_section_header(&format!("Largest Entries (top {})", top));
+        let rows: Vec<Vec<String>> = largest_entries.iter()
+            .map(|entry| vec![format!("/{}", entry.path), format_file_size(entry.size)])
+            .collect();
+        print_table(&["Path", "Size"], &rows);
+    }
+    if !directory_sizes.is_empty() {
+        print_info("\nSize by top-level directory:");
+        let rows: Vec<Vec<String>> = directory_sizes.iter()
+            .map(|directory| vec![directory.path.clone(), format_file_size(directory.size)])
+            .collect();
+        print_table(&["Directory", "Size"], &rows);
+    }
+}
+
+/// Report the whiteouts found in the layer, warning when there are whiteout or opaque marker files
+/// but no whiteout devices: an exporter that drops overlayfs whiteouts loses deletions that way
+fn check_whiteouts(whiteouts: &LayerWhiteouts) {
+    if whiteouts.is_empty() {
+        print_check_result("Whiteouts", "✓ None; importing deletes nothing", true);
+        return;
+    }
+    if whiteouts.devices == 0 {
+        print_check_result("Whiteouts", "⚠ Marker files without whiteout devices", false);
+        print_warning(&format!(
+            "The layer has {} whiteout or opaque marker file(s) but no whiteout devices; it was likely exported \
+            without whiteout support, so files deleted in the container may have been lost at export time and \
+            will reappear from the image",
+            whiteouts.removed.len() + whiteouts.opaque.len()
+        ));
+    } else {
+        print_check_result("Whiteouts", &format!(
+            "✓ {} path(s) removed, {} opaque dir(s)", whiteouts.removed.len(), whiteouts.opaque.len()
+        ), true);
+    }
+}
+
+/// Warn about every suspicious layer entry the scan found; entries outside the layer already
+/// failed the check
+fn check_findings(findings: &LayerFindings) {
+    if findings.findings.is_empty() {
+        print_check_result("Security scan", "✓ No suspicious entries", true);
+        return;
+    }
+    print_check_result("Security scan", &format!("⚠ {} suspicious entr(ies)", findings.findings.len()), false);
+    for finding in &findings.findings {
+        print_warning(&format!("{}: /{} ({})", finding.kind, finding.path, finding.detail));
+    }
+}
+
+/// List what importing the export deletes, capped at [`DELETION_LIST_LIMIT`] paths
+fn print_deletions(whiteouts: &LayerWhiteouts) {
+    if whiteouts.is_empty() {
+        return;
+    }
+    print_section_header("Deletions on Import");
+    let paths = whiteouts.removed.iter()
+        .map(|path| format!("/{}", path))
+        .chain(whiteouts.opaque.iter().map(|directory| format!("/{}/* (opaque directory)", directory)));
+    let total = whiteouts.removed.len() + whiteouts.opaque.len();
+    for path in paths.take(DELETION_LIST_LIMIT) {
+        print_list_item(&path);
+    }
+    if total > DELETION_LIST_LIMIT {
+        print_list_item(&format!("... and {} more (see --output json)", total - DELETION_LIST_LIMIT));
+    }
+    print_labeled_value("Total", &format!("{} removed path(s), {} opaque dir(s)", whiteouts.removed.len(), whiteouts.opaque.len()));
+}
+
+/// Warn when the export was taken on a Docker release with a newer major version than the current daemon's
+fn check_docker_version(export: &EnvironmentInfo, current: &EnvironmentInfo, diagnostics: &mut Vec<Diagnostic>) {
+    let (Some(export_version), Some(current_version)) = (major_minor_version(&export.server_version), major_minor_version(&current.server_version)) else {
+        print_check_result("Docker version", "⏭ Not comparable", false);
+        return;
+    };
+    if export_version.0 > current_version.0 {
+        diagnostics.push(Diagnostic::new(DiagnosticCode::DockerVersionMismatch, format!(
+            "Docker version mismatch: export taken on Docker {}, current daemon runs the older {}",
+            export.server_version, current.server_version
+        ))
+        .with_context("export", &export.server_version)
+        .with_context("current", &current.server_version));
+        print_check_result("Docker version", &format!("⚠ Newer at export time: {}", export.server_version), false);
+    } else {
+        print_check_result("Docker version", &format!("✓ Compatible: {}", current.server_version), true);
+    }
+}
+
+/// Warn when the export was taken on a kernel of another major version, or many minor releases apart
+fn check_kernel_version(export: &EnvironmentInfo, current: &EnvironmentInfo, diagnostics: &mut Vec<Diagnostic>) {
+    let (Some(export_version), Some(current_version)) = (major_minor_version(&export.kernel), major_minor_version(&current.kernel)) else {
+        print_check_result("Kernel version", "⏭ Not comparable", false);
+        return;
+    };
+    if export_version.0 != current_version.0 || export_version.1.abs_diff(current_version.1) > KERNEL_MINOR_TOLERANCE {
+        diagnostics.push(Diagnostic::new(DiagnosticCode::KernelVersionMismatch, format!(
+            "Kernel version mismatch: export taken on kernel {}, current system runs {}",
+            export.kernel, current.kernel
+        ))
+        .with_context("export", &export.kernel)
+        .with_context("current", &current.kernel));
+        print_check_result("Kernel version", &format!("⚠ Mismatch: {}", export.kernel), false);
+    } else {
+        print_check_result("Kernel version", &format!("✓ Compatible: {}", current.kernel), true);
+    }
+}
+
+/// Warn when the export was taken under another cgroup driver (cgroupfs or systemd)
+fn check_cgroup_driver(export: &EnvironmentInfo, current: &EnvironmentInfo, diagnostics: &mut Vec<Diagnostic>) {
+    if export.cgroup_driver.is_empty() || current.cgroup_driver.is_empty() {
+        print_check_result("Cgroup driver", "⏭ Not recorded", false);
+    } else if export.cgroup_driver != current.cgroup_driver {
+        diagnostics.push(Diagnostic::new(DiagnosticCode::CgroupDriverMismatch, format!(
+            "Cgroup driver mismatch: export uses '{}', current system uses '{}'",
+            export.cgroup_driver, current.cgroup_driver
+        ))
+        .with_context("export", &export.cgroup_driver)
+        .with_context("current", &current.cgroup_driver));
+        print_check_result("Cgroup driver", &format!("⚠ Mismatch: {}", export.cgroup_driver), false);
+    } else {
+        print_check_result("Cgroup driver", &format!("✓ Compatible: {}", current.cgroup_driver), true);
+    }
+}
+
+/// What the report records about an export's metadata
+fn checked_export(export_data: &ExportDataV2) -> CheckedExport {
+    let metadata = &export_data.container_metadata;
+    CheckedExport {
+        version: export_data.version,
+        created: export_data.created,
+        layer_kind: export_data.layer_kind,
+        container_id: metadata.id.clone(),
+        container_name: metadata.name.clone(),
+        image: metadata.image.clone(),
+        image_sha256: metadata.image_sha256.clone(),
+        layer_checksum: export_data.layer_checksum.clone(),
+        checksum_scheme: export_data.checksum_scheme,
+        layer_archive_checksum: export_data.layer_archive_checksum.clone(),
+        incremental: export_data.incremental.is_some(),
+        storage_driver: export_data.environment.driver.clone(),
+        operating_system: export_data.environment.os.clone(),
+        architecture: export_data.environment.architecture.clone(),
+        docker_version: export_data.environment.server_version.clone(),
+        kernel_version: export_data.environment.kernel.clone(),
+        cgroup_driver: export_data.environment.cgroup_driver.clone(),
+        annotations: export_data.annotations.clone(),
+        mounts: export_data.mounts.len(),
+    }
+}
+
+/// An empty report for checking `input_path`, failed until the check completes
+fn new_check_report(input_path: &str, options: &CheckOptions) -> CheckReport {
+    CheckReport {
+        report_version: CHECK_REPORT_VERSION,
+        verdict: CheckVerdict::Failed,
+        input_path: input_path.to_string(),
+        target_container: options.container.clone(),
+        file_size: None,
+        compressed: None,
+        seekable: None,
+        estimated_uncompressed_size: None,
+        layer_bytes: None,
+        layer_entries: None,
+        layer_stats: None,
+        compression_ratio: None,
+        import_disk_usage: None,
+        target_free_space: None,
+        export: None,
+        checks: Vec::new(),
+        largest_entries: Vec::new(),
+        directory_sizes: Vec::new(),
+        whiteouts: Vec::new(),
+        opaque_directories: Vec::new(),
+        findings: Vec::new(),
+        warnings: Vec::new(),
+        errors: Vec::new(),
+        diagnostics: Vec::new(),
+        metrics: CommandMetrics::default(),
+    }
+}
+
+/// The files to check: the paths given, with each directory replaced by its files matching
+/// `glob`, in name order. A file given twice is checked once.
+fn expand_check_inputs(input_paths: &[String], glob: Option<&str>) -> Result<Vec<String>> {
+    let mut files: Vec<String> = Vec::new();
+    for input_path in input_paths {
+        let path = Path::new(input_path);
+        if !path.is_dir() {
+            if !files.contains(input_path) {
+                files.push(input_path.clone());
+            }
+            continue;
+        }
+        let Some(glob) = glob else {
+            return Err(anyhow::anyhow!(
+                "{} is a directory; pass --glob to select the exports in it, e.g. --glob '*.tar.gz'", input_path
+            ));
+        };
+        let mut matched = Vec::new();
+        for entry in std::fs::read_dir(path).with_context(|| format!("Failed to read directory {}", input_path))? {
+            let entry = entry.with_context(|| format!("Failed to read directory {}", input_path))?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if entry.path().is_file() && matches_component(glob, &name) {
+                matched.push(entry.path().to_string_lossy().into_owned());
+            }
+        }
+        if matched.is_empty() {
+            print_warning(&format!("No files in {} match {}", input_path, glob));
+        }
+        matched.sort();
+        for file in matched {
+            if !files.contains(&file) {
+                files.push(file);
+            }
+        }
+    }
+    if files.is_empty() {
+        return Err(anyhow::anyhow!("No export files to check"));
+    }
+    Ok(files)
+}
+
+/// Record the warnings printed, the checks of a failed run, and the verdict
+fn finish_check_report(report: &mut CheckReport, result: &Result<()>) {
+    // The compatibility warnings were collected by the check itself, not print_warning
+    let mut warnings = take_warnings();
+    warnings.append(&mut report.warnings);
+    report.warnings = warnings;
+    report.diagnostics = take_diagnostics();
+    report.metrics = metrics::snapshot();
+    match result {
+        Ok(()) => {
+            let warned = report.checks.iter().any(|check| check.status == CheckStatus::Warn);
+            report.verdict = if warned || !report.warnings.is_empty() {
+                CheckVerdict::PassedWithWarnings
+            } else {
+                CheckVerdict::Passed
+            };
+        }
+        Err(error) => {
+            report.checks.extend(take_check_results());
+            report.errors.extend(error.chain().map(ToString::to_string));
+            report.verdict = CheckVerdict::Failed;
+        }
+    }
+}
+
 impl Default for CheckCommand {
     fn default() -> Self {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::events::EventKind;
+    use crate::output::{CaptureSink, Level};
+    use crate::testing::{corrupt, write_export};
+    use crate::verify::IntegrityFailure;
+    use std::path::PathBuf;
+    use std::sync::Mutex;
+    use tempfile::TempDir;
+    use walkdir::WalkDir;
+
+    #[test]
+    fn check_reports_to_its_output_sink() {
+        let layer = TempDir::new().unwrap();
+        let workspace = TempDir::new().unwrap();
+        std::fs::create_dir(layer.path().join("etc")).unwrap();
+        std::fs::write(layer.path().join("etc/hostname"), b"web\n").unwrap();
+        let (export_path, export_data) = write_export(layer.path(), workspace.path());
+
+        let sink = Arc::new(CaptureSink::new());
+        let options = CheckOptions { deep: true, ..CheckOptions::default() };
+        CheckCommand::new().with_output(sink.clone())
+            .execute(export_path.to_str().unwrap(), options)
+            .unwrap();
+
+        let results = sink.check_results();
+        let result = |id: &str| results.iter().find(|result| result.id == id)
+            .unwrap_or_else(|| panic!("no {} result in {:?}", id, results));
+        assert_eq!(result("layer-checksum").status, CheckStatus::Pass);
+        assert_eq!(result("layer-checksum").detail, format!("Matches {}", export_data.layer_checksum));
+        assert_eq!(result("manifest").detail, "Valid (2 entries)");
+        assert_eq!(result("deep-verification").detail, "All 2 entries match the manifest");
+        assert!(results.iter().all(|result| result.status != CheckStatus::Fail));
+
+        // Deep verification extracts and hashes the layer, each phase started and completed once
+        let events = sink.progress_events();
+        let phases: Vec<(&str, &str)> = events.iter()
+            .filter_map(|event| match event {
+                EventKind::PhaseStarted { phase } => Some(("started", phase.as_str())),
+                EventKind::PhaseCompleted { phase, .. } => Some(("completed", phase.as_str())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(phases, [("started", "Extracting"), ("completed", "Extracting"), ("started", "Hashing"), ("completed", "Hashing")]);
+        assert!(matches!(
+            events.last(),
+            Some(EventKind::PhaseCompleted { phase, bytes_done: 4, .. }) if phase == "Hashing"
+        ));
+
+        // The human-readable lines are captured too, ending with the verdict
+        assert!(sink.lines_at(Level::Error).is_empty());
+        assert!(sink.lines().last().unwrap().1.contains("All checks passed"));
+    }
+
+    #[test]
+    fn check_fails_on_a_bit_flipped_layer() {
+        let layer = TempDir::new().unwrap();
+        let workspace = TempDir::new().unwrap();
+        std::fs::create_dir(layer.path().join("etc")).unwrap();
+        std::fs::write(layer.path().join("etc/hostname"), b"web-frontend\n").unwrap();
+        let (export_path, _) = write_export(layer.path(), workspace.path());
+        // One bit of the file's contents inside layer.tar; every tar header stays valid
+        corrupt(&export_path, b"web-frontend", b"web-frontene");
+
+        let sink = Arc::new(CaptureSink::new());
+        let error = CheckCommand::new().with_output(sink.clone())
+            .execute(export_path.to_str().unwrap(), CheckOptions::default())
+            .unwrap_err();
+        assert!(error.chain().any(|cause| cause.is::<IntegrityFailure>()), "{:#}", error);
+        assert!(format!("{:#}", error).contains("Layer integrity check FAILED"), "{:#}", error);
+        assert!(!sink.lines().iter().any(|(_, line)| line.contains("All checks passed")));
+    }
+
+    /// Sink that looks into the check's scratch directory whenever the check prints, recording
+    /// the most bytes it held and every file name seen there
+    struct ScratchWatcher {
+        tmpdir: PathBuf,
+        peak_bytes: Mutex<u64>,
+        names: Mutex<Vec<String>>,
+    }
+
+    impl ScratchWatcher {
+        fn observe(&self) {
+            let mut bytes = 0;
+            let mut names = self.names.lock().unwrap();
+            for entry in WalkDir::new(&self.tmpdir).into_iter().filter_map(|entry| entry.ok()) {
+                if entry.file_type().is_file() {
+                    bytes += entry.metadata().map_or(0, |metadata| metadata.len());
+                }
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+            let mut peak_bytes = self.peak_bytes.lock().unwrap();
+            *peak_bytes = (*peak_bytes).max(bytes);
+        }
+    }
+
+    impl OutputSink for ScratchWatcher {
+        fn emit(&self, _level: Level, _message: &str) {
+            self.observe();
+        }
+
+        fn progress(&self, _event: &EventKind) {
+            self.observe();
+        }
+    }
+
+    #[test]
+    fn default_check_streams_without_extracting() {
+        let layer = TempDir::new().unwrap();
+        let workspace = TempDir::new().unwrap();
+        std::fs::create_dir_all(layer.path().join("var/lib")).unwrap();
+        std::fs::write(layer.path().join("var/lib/data.bin"), vec![0x5a; 4 << 20]).unwrap();
+        let (export_path, _) = write_export(layer.path(), workspace.path());
+
+        let check_with_watcher = |deep: bool| {
+            let tmpdir = TempDir::new().unwrap();
+            let watcher = Arc::new(ScratchWatcher {
+                tmpdir: tmpdir.path().to_path_buf(),
+                peak_bytes: Mutex::new(0),
+                names: Mutex::new(Vec::new()),
+            });
+            let options = CheckOptions { deep, tmpdir: Some(tmpdir.path().to_str().unwrap().to_string()), ..CheckOptions::default() };
+            CheckCommand::new().with_output(watcher.clone())
+                .execute(export_path.to_str().unwrap(), options)
+                .unwrap();
+            let peak_bytes = *watcher.peak_bytes.lock().unwrap();
+            let names = watcher.names.lock().unwrap().clone();
+            (peak_bytes, names)
+        };
+
+        let (peak_bytes, names) = check_with_watcher(false);
+        assert!(peak_bytes < 1 << 20, "default check held {} bytes of scratch", peak_bytes);
+        for name in ["extracted", "layer", "layer.tar", "data.bin"] {
+            assert!(!names.iter().any(|seen| seen == name), "default check wrote {} to its scratch directory", name);
+        }
+
+        // Only --deep extracts the layer, which the watcher must be able to see
+        let (peak_bytes, names) = check_with_watcher(true);
+        assert!(peak_bytes >= 4 << 20);
+        assert!(names.iter().any(|seen| seen == "data.bin"));
+    }
+}
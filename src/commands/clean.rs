@@ -0,0 +1,41 @@
+use anyhow::Result;
+
+use crate::output::*;
+use crate::workspace::{find_leftovers, remove_leftovers};
+
+pub struct CleanCommand;
+
+impl CleanCommand {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Remove the temporary workspaces left behind by layer-tool processes that are no longer running
+    pub fn execute(&self) -> Result<()> {
+        let leftovers = find_leftovers()?;
+        if leftovers.is_empty() {
+            print_info("No leftover workspaces found");
+            return Ok(());
+        }
+
+        let mut removed = 0;
+        for leftover in &leftovers {
+            print_progress(&format!("Removing leftovers of process {}...", leftover.pid));
+            for path in &leftover.workspaces {
+                print_list_item(&path.display().to_string());
+            }
+            remove_leftovers(leftover)?;
+            removed += leftover.workspaces.len();
+        }
+
+        print_success("Cleanup completed successfully!");
+        print_labeled_value("Workspaces removed", &removed.to_string());
+        Ok(())
+    }
+}
+
+impl Default for CleanCommand {
+    fn default() -> Self {
+        Self::new()
+    }
+}
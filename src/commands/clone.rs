@@ -0,0 +1,294 @@
+use anyhow::{Context, Result};
+use chrono::Utc;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::backup::rotate_backups;
+use crate::cancel::CancellationToken;
+use crate::commands::export::ExportCommand;
+use crate::commands::import::{attribute_restorer, manifest_mismatch_error, warn_unrestored_attributes, ImportCommand, ImportTarget};
+use crate::docker::DockerClient;
+use crate::lock::ContainerLock;
+use crate::output::*;
+use crate::timing::PhaseTimer;
+use crate::types::{Checksum, ChecksumAlgorithm, CloneOptions, EnvironmentInfo, ExportCompression, ExportDataV2, ExportOptions, ExtractOptions, ImportOptions, LayerArchiveOptions, LayerKind, Manifest, TransientEntry, CURRENT_CHECKSUM_SCHEME, CURRENT_FORMAT_VERSION};
+use crate::utils::{
+    directory_disk_usage, extract_tar_stream, verify_layer_against_manifest, write_tar_archive_filtered, HoldbackWriter
+};
+use crate::workspace::Workspace;
+
+pub struct CloneCommand {
+    docker_client: DockerClient,
+    cancel: CancellationToken,
+}
+
+impl CloneCommand {
+    pub fn new() -> Self {
+        Self {
+            docker_client: DockerClient::new(),
+            cancel: CancellationToken::new(),
+        }
+    }
+
+    /// Use `cancel` to stop the clone; cancelling restores the target layer that was moved aside as backup
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Copy the upper layer of `source_id` onto `target_id`, streaming it from one layer directory
+    /// into the other without an export file, or into a remote import with `options.to_host`
+    pub fn execute(&self, source_id: &str, target_id: &str, options: &CloneOptions) -> Result<()> {
+        if let Some(destination) = &options.to_host {
+            return self.clone_to_host(source_id, target_id, destination, options);
+        }
+
+        let import = ImportCommand::new().with_cancellation(self.cancel.clone());
+        let import_options = import_options(options);
+        let mut timer = PhaseTimer::new();
+        timer.start("validation");
+        print_progress(&format!("Cloning the layer of container {} into {}", source_id, target_id));
+
+        let source_full_id = self.docker_client.resolve_container_id(source_id)?;
+        let target_full_id = self.docker_client.resolve_container_id(target_id)?;
+        if source_full_id == target_full_id {
+            return Err(anyhow::anyhow!("Source and target are the same container: {}", source_id));
+        }
+
+        // Take both locks in a fixed order so two clones between the same containers cannot deadlock
+        let mut locked = [(&source_full_id, "export"), (&target_full_id, "import")];
+        locked.sort();
+        let _locks = locked.iter()
+            .map(|(full_id, operation)| ContainerLock::acquire(full_id, operation, options.wait.map(Duration::from_secs), &self.cancel))
+            .collect::<Result<Vec<_>>>()?;
+
+        print_progress("Validating source container state...");
+        self.docker_client.validate_container_for_layer_operations(source_id)
+            .context("Source container validation failed")?;
+        let container_metadata = self.docker_client.get_container_metadata(source_id)
+            .context("Failed to get source container metadata")?;
+        let docker_info = self.docker_client.get_docker_info()
+            .context("Failed to get Docker info")?;
+        let source_upper_path = self.docker_client.get_upper_layer_path(source_id)
+            .context("Failed to get source container layer path")?;
+        if !source_upper_path.exists() {
+            return Err(anyhow::anyhow!("Container layer directory not found: {:?}", source_upper_path));
+        }
+
+        print_progress("Validating target container state...");
+        self.docker_client.validate_container_for_layer_operations(target_id)
+            .context("Target container validation failed")?;
+        let target_state = import.check_target_running(target_id, &import_options)?;
+
+        // The source layer as an export of it would describe it, for the usual import checks
+        let source = ExportDataV2 {
+            version: CURRENT_FORMAT_VERSION,
+            created: Utc::now(),
+            container_metadata,
+            environment: EnvironmentInfo::from(&docker_info),
+            layer_checksum: Checksum::new(ChecksumAlgorithm::default(), String::new()),
+            checksum_scheme: CURRENT_CHECKSUM_SCHEME,
+            layer_archive_checksum: None,
+            compressed: false,
+            layer_kind: LayerKind::Upper,
+            manifest: None,
+            incremental: None,
+            annotations: Default::default(),
+            redacted_keys: Vec::new(),
+            transient_entries: Vec::new(),
+            mounts: Vec::new(),
+            id_offset: None,
+        };
+        import.check_target_compatibility(&source, target_id, &import_options)?;
+
+        print_progress("Locating target container layer directory...");
+        let target_upper_path = self.docker_client.get_upper_layer_path(target_id)
+            .context("Failed to get target container layer path")?;
+        let target = ImportTarget::Container(target_id);
+
+        // Running out of space halfway through would leave the container without its layer
+        let layer = directory_disk_usage(&source_upper_path)
+            .context("Failed to measure source layer")?;
+        if options.no_space_check {
+            print_warning("Skipping free space check (--no-space-check)");
+        } else {
+            print_progress("Checking free space on the target filesystem...");
+            let shortfalls = import.space_shortfalls(layer, target, &target_upper_path, false, &import_options)?;
+            if !shortfalls.is_empty() {
+                print_errors_section(&shortfalls);
+                return Err(anyhow::anyhow!(
+                    "Not enough free space for the clone; the target was not modified. Free up space or pass --no-space-check"
+                ));
+            }
+            print_check_result("Free space", "✓ Sufficient for the layer and backup", true);
+        }
+
+        self.cancel.check()?;
+        timer.start("backup");
+        let backup_source = format!("container {}", source.container_metadata.name);
+        let layer_backup = import.back_up_layer(target, &target_upper_path, false, &target_full_id, &backup_source, &import_options)?;
+
+        // Stream and verify the layer; a failure from here on rolls the target layer back
+        timer.start("clone");
+        timer.add_bytes(layer.bytes);
+        let cloned = self.stream_layer(&source_upper_path, &target_upper_path, layer.bytes, &import_options)
+            .and_then(|(checksum, manifest, transient_entries)| {
+                timer.start("checksum");
+                print_progress("Verifying layer against the source manifest...");
+                let progress = byte_progress_bar("Verifying");
+                progress.set_length(layer.bytes);
+                let mismatches = verify_layer_against_manifest(
+                    &target_upper_path, &manifest, &|_| true, attribute_restorer(&import_options).verified_attributes(), &progress
+                )
+                    .context("Failed to verify cloned layer")?;
+                finish_progress(&progress);
+                if !mismatches.is_empty() {
+                    print_errors_section(&mismatches);
+                    return Err(manifest_mismatch_error("path(s)", &mismatches));
+                }
+                Ok((checksum, manifest, transient_entries))
+            });
+        let (checksum, manifest, transient_entries) = match cloned {
+            Ok(cloned) => cloned,
+            Err(error) => return Err(import.roll_back(error, target, &target_upper_path, layer_backup.as_ref(), &import_options).0),
+        };
+
+        if !transient_entries.is_empty() {
+            print_warning(&format!(
+                "{} path(s) vanished or changed while cloning; the clone is not an exact point-in-time snapshot",
+                transient_entries.len()
+            ));
+        }
+
+        // Older rollback points are only discarded once the clone has succeeded
+        let backup_dir = options.backup_dir.as_deref().map(Path::new);
+        let backup_rotation = match layer_backup {
+            Some(layer_backup) => Some(rotate_backups(layer_backup, &target_upper_path, backup_dir, &target_full_id, options.keep_backups)?),
+            None => None,
+        };
+
+        print_success("Clone completed successfully!");
+        print_section_header("Clone Summary");
+        print_container_info("Source container", &source.container_metadata.name, &source.container_metadata.id);
+        print_labeled_value("Target container", target_id);
+        print_labeled_value("Target state", &target_state);
+        print_labeled_value("Image", &source.container_metadata.image);
+        print_labeled_value("Entries", &manifest.entries.len().to_string());
+        print_checksum("Layer checksum verified", &checksum.to_string());
+        if let Some(rotation) = &backup_rotation {
+            print_labeled_value("Backup", &rotation.created.path().display().to_string());
+            if !rotation.pruned.is_empty() {
+                print_labeled_value("Pruned backups", &format!("{} (--keep-backups {})", rotation.pruned.len(), options.keep_backups));
+                for backup in &rotation.pruned {
+                    print_list_item(&backup.path().display().to_string());
+                }
+            }
+        }
+        if !transient_entries.is_empty() {
+            print_labeled_value("Vanished or changed", &transient_entries.len().to_string());
+        }
+        for entry in &transient_entries {
+            print_list_item(&format!("{} ({})", entry.path, entry.issue));
+        }
+
+        let total_secs = timer.elapsed_secs();
+        print_phase_timings(&timer.finish(), total_secs);
+        Ok(())
+    }
+
+    /// Archive the source layer into a pipe on one thread while this one extracts it into the
+    /// target layer. Returns the source layer's checksum and manifest, computed as it was read,
+    /// and the paths that vanished or changed meanwhile.
+    fn stream_layer(
+        &self,
+        source_upper_path: &Path,
+        target_upper_path: &Path,
+        layer_bytes: u64,
+        options: &ImportOptions,
+    ) -> Result<(Checksum, Manifest, Vec<TransientEntry>)> {
+        print_progress("Streaming layer data from the source into the target container...");
+        let (reader, writer) = std::io::pipe()
+            .context("Failed to create pipe")?;
+        let mut attributes = attribute_restorer(options);
+        let progress = byte_progress_bar("Cloning");
+        progress.set_length(layer_bytes);
+
+        let (archived, extracted) = std::thread::scope(|scope| {
+            let archiver = scope.spawn(move || {
+                let mut sink = HoldbackWriter::new(writer);
+                let archived = write_tar_archive_filtered(
+                    source_upper_path, &mut sink, None, None, LayerArchiveOptions::default(), &self.cancel
+                )
+                    .context("Failed to archive source layer")?;
+                sink.finish()
+                    .context("Failed to send layer archive")?;
+                Ok(archived)
+            });
+
+            // The rest of the stream is drained so the archiver can finish writing its trailer;
+            // on failure the reader is dropped instead, which stops the archiver
+            let mut reader = progress.wrap_read(reader);
+            let extracted = extract_tar_stream(&mut reader, target_upper_path, &ExtractOptions::default(), &mut attributes, &self.cancel)
+                .and_then(|_| std::io::copy(&mut reader, &mut std::io::sink()).context("Failed to read layer archive"));
+            drop(reader);
+            let archived = archiver.join()
+                .unwrap_or_else(|_| Err(anyhow::anyhow!("Layer archiver thread panicked")));
+            (archived, extracted)
+        });
+        finish_progress(&progress);
+
+        // A failed archiver usually also breaks extraction; its error is the cause
+        let archived = archived?;
+        extracted.context("Failed to extract layer data to target container")?;
+        warn_unrestored_attributes(&attributes.finish()?);
+        Ok(archived)
+    }
+
+    /// Export the source into the workspace and stream it into `layer-tool import -` on `destination`
+    fn clone_to_host(&self, source_id: &str, target_id: &str, destination: &str, options: &CloneOptions) -> Result<()> {
+        let workspace = Workspace::new()
+            .context("Failed to create temporary directory")?;
+        let export_path = workspace.path().join("clone.tar.gz");
+        let export_path = export_path.to_string_lossy();
+
+        let export_options = ExportOptions {
+            compression: ExportCompression::Gzip,
+            wait: options.wait,
+            ..Default::default()
+        };
+        ExportCommand::new()
+            .with_cancellation(self.cancel.clone())
+            .execute(source_id, &export_path, export_options)?;
+
+        let import_options = ImportOptions {
+            ssh: Some(destination.to_string()),
+            ..import_options(options)
+        };
+        ImportCommand::new()
+            .with_cancellation(self.cancel.clone())
+            .execute(&export_path, Some(target_id), import_options)
+    }
+}
+
+impl Default for CloneCommand {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The import options the target side of a clone runs with
+fn import_options(options: &CloneOptions) -> ImportOptions {
+    ImportOptions {
+        backup: options.backup,
+        backup_dir: options.backup_dir.clone(),
+        keep_backups: options.keep_backups,
+        force: options.force,
+        allow_image_mismatch: options.allow_image_mismatch,
+        skip_arch: options.skip_arch,
+        no_space_check: options.no_space_check,
+        wait: options.wait,
+        // Naming both containers on the command line is the confirmation
+        yes: true,
+        ..Default::default()
+    }
+}
@@ -0,0 +1,353 @@
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use crate::cancel::CancellationToken;
+use crate::docker::DockerClient;
+use crate::output::*;
+use crate::stream::{layer_stream_manifest, read_export_header, with_layer_stream_progress};
+use crate::types::{
+    ChangeKind, ChecksumAlgorithm, DiffOptions, DiffReport, DiffSource, DiffSourceKind, EntryType, LayerKind, Manifest, ManifestEntry,
+    MetadataChange, OutputFormat, PathChange, DIFF_REPORT_VERSION
+};
+use crate::utils::{directory_manifest, format_file_size, parse_manifest, reject_partial_file};
+use crate::verify::parse_export_metadata;
+
+/// Exit code of `diff` when the two sides differ
+pub const DIFFERENCES_EXIT_CODE: i32 = 6;
+
+/// Two layers, or their metadata, that are not the same
+#[derive(Debug)]
+pub struct Differences {
+    pub message: String,
+}
+
+impl std::fmt::Display for Differences {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for Differences {}
+
+/// One side of a diff: the per-file view of its layer and the metadata compared
+struct DiffSide {
+    source: DiffSource,
+    manifest: Manifest,
+    /// Algorithm of the manifest's file digests
+    checksum_algorithm: ChecksumAlgorithm,
+    labels: BTreeMap<String, String>,
+    /// Annotations, which only exports carry
+    annotations: Option<BTreeMap<String, String>>,
+    /// Labels whose values were redacted at export time and cannot be compared
+    redacted_keys: Vec<String>,
+}
+
+pub struct DiffCommand {
+    docker_client: DockerClient,
+    cancel: CancellationToken,
+}
+
+impl DiffCommand {
+    pub fn new() -> Self {
+        Self {
+            docker_client: DockerClient::new(),
+            cancel: CancellationToken::new(),
+        }
+    }
+
+    /// Use `cancel` to stop the diff
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Handle that cancels this command's running operations
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
+    /// Compare two layers, each an export file or a container, path by path, along with their
+    /// image and labels. Fails with [`Differences`] unless they are identical.
+    pub fn execute(&self, a: &str, b: &str, options: DiffOptions) -> Result<()> {
+        // stdout carries the report or the path list, so all human-readable output moves to stderr
+        if options.output == OutputFormat::Json || options.name_only {
+            set_output_to_stderr(true);
+        }
+
+        // A container is read as the kind of layer the export on the other side holds, hashed
+        // with the same algorithm
+        let export_a = self.read_export(a)?;
+        let export_b = self.read_export(b)?;
+        if let (Some(side_a), Some(side_b)) = (&export_a, &export_b)
+            && side_a.checksum_algorithm != side_b.checksum_algorithm
+        {
+            return Err(anyhow::anyhow!(
+                "{} and {} hash files with different checksum algorithms ({} and {}); their contents cannot be compared",
+                a, b, side_a.checksum_algorithm, side_b.checksum_algorithm
+            ));
+        }
+        let layer_kind_of = |export: &Option<DiffSide>| export.as_ref().map_or(LayerKind::Upper, |side| side.source.layer_kind);
+        let algorithm_of = |export: &Option<DiffSide>| export.as_ref().map_or_else(ChecksumAlgorithm::default, |side| side.checksum_algorithm);
+        let side_a = match export_a {
+            Some(side) => side,
+            None => self.read_container(a, layer_kind_of(&export_b), algorithm_of(&export_b))?,
+        };
+        let side_b = match export_b {
+            Some(side) => side,
+            None => self.read_container(b, side_a.source.layer_kind, side_a.checksum_algorithm)?,
+        };
+
+        let changes = compare_manifests(&side_a.manifest, &side_b.manifest);
+        let metadata_changes = compare_metadata(&side_a, &side_b);
+        let count = |kind: ChangeKind| changes.iter().filter(|change| change.change == kind).count();
+        let report = DiffReport {
+            report_version: DIFF_REPORT_VERSION,
+            identical: changes.is_empty() && metadata_changes.is_empty(),
+            added: count(ChangeKind::Added),
+            removed: count(ChangeKind::Removed),
+            modified: count(ChangeKind::Modified),
+            size_delta: changes.iter().map(|change| change.size_delta).sum(),
+            a: side_a.source,
+            b: side_b.source,
+            changes,
+            metadata_changes,
+            metrics: metrics::snapshot(),
+        };
+
+        if options.name_only {
+            for change in &report.changes {
+                print_result(&change.path);
+            }
+        }
+        display_diff(&report, options.name_only);
+        if options.output == OutputFormat::Json {
+            let report_json = serde_json::to_string_pretty(&report)
+                .context("Failed to serialize report")?;
+            print_result(&report_json);
+        }
+
+        if report.identical {
+            return Ok(());
+        }
+        Err(Differences {
+            message: format!(
+                "{} and {} differ: {} added, {} removed, {} modified, {} metadata field(s)",
+                a, b, report.added, report.removed, report.modified, report.metadata_changes.len()
+            ),
+        }.into())
+    }
+
+    /// Read an export file's metadata and per-file view: its manifest, or for exports without one,
+    /// its layer archive hashed entry by entry. None if `path` is not a file, i.e. names a container.
+    fn read_export(&self, path: &str) -> Result<Option<DiffSide>> {
+        let export_path = Path::new(path);
+        if !export_path.is_file() {
+            return Ok(None);
+        }
+        reject_partial_file(export_path)?;
+        print_progress(&format!("Reading export {}...", path));
+
+        let header = read_export_header(export_path, &self.cancel)?;
+        let metadata_content = header.metadata
+            .ok_or_else(|| anyhow::anyhow!("Missing metadata.json in export archive: {}", path))?;
+        let export_data = parse_export_metadata(&metadata_content)
+            .context("Failed to parse metadata JSON")?;
+        let manifest = parse_manifest(header.manifest.as_deref(), export_data.manifest_checksum())
+            .context("Failed to validate layer manifest")?;
+        let manifest = match manifest {
+            Some(manifest) => manifest,
+            None => {
+                if export_data.incremental.is_some() {
+                    print_warning(&format!(
+                        "{} is an incremental export without a manifest; only the paths it carries are compared", path
+                    ));
+                }
+                let progress = byte_progress_bar("Hashing");
+                let manifest = with_layer_stream_progress(export_path, &self.cancel, &progress, |layer| {
+                    layer_stream_manifest(layer, export_data.layer_checksum.algorithm, &self.cancel)
+                })
+                    .context("Failed to read layer archive")?;
+                finish_progress(&progress);
+                manifest
+            }
+        };
+
+        let metadata = export_data.container_metadata;
+        Ok(Some(DiffSide {
+            source: DiffSource {
+                source: path.to_string(),
+                kind: DiffSourceKind::Export,
+                image: metadata.image,
+                image_sha256: metadata.image_sha256,
+                layer_kind: export_data.layer_kind,
+                entries: manifest.entries.len(),
+            },
+            manifest,
+            checksum_algorithm: export_data.layer_checksum.algorithm,
+            labels: metadata.labels,
+            annotations: Some(export_data.annotations),
+            redacted_keys: export_data.redacted_keys,
+        }))
+    }
+
+    /// Read a container's metadata and walk its live layer directory, hashing every file with `algorithm`
+    fn read_container(&self, container_id: &str, layer_kind: LayerKind, algorithm: ChecksumAlgorithm) -> Result<DiffSide> {
+        print_progress(&format!("Reading container {}...", container_id));
+        let metadata = self.docker_client.get_container_metadata(container_id)
+            .with_context(|| format!("{} is neither an export file nor a container", container_id))?;
+        let layer_dir = match layer_kind {
+            LayerKind::Upper => self.docker_client.get_upper_layer_path(container_id)?,
+            LayerKind::Full => self.docker_client.get_merged_dir_path(container_id)?,
+        };
+        let manifest = directory_manifest(&layer_dir, algorithm, &self.cancel)
+            .with_context(|| format!("Failed to read layer directory {:?}", layer_dir))?;
+
+        Ok(DiffSide {
+            source: DiffSource {
+                source: container_id.to_string(),
+                kind: DiffSourceKind::Container,
+                image: metadata.image,
+                image_sha256: metadata.image_sha256,
+                layer_kind,
+                entries: manifest.entries.len(),
+            },
+            manifest,
+            checksum_algorithm: algorithm,
+            labels: metadata.labels,
+            annotations: None,
+            redacted_keys: Vec::new(),
+        })
+    }
+}
+
+/// Every path added, removed or modified from `a` to `b`, in path order
+fn compare_manifests(a: &Manifest, b: &Manifest) -> Vec<PathChange> {
+    let size_of = |entry: Option<&ManifestEntry>| entry.map(|entry| entry.size);
+    let paths: BTreeSet<&String> = a.entries.keys().chain(b.entries.keys()).collect();
+
+    let mut changes = Vec::new();
+    for path in paths {
+        let (before, after) = (a.entries.get(path), b.entries.get(path));
+        let (change, detail) = match (before, after) {
+            (None, Some(_)) => (ChangeKind::Added, None),
+            (Some(_), None) => (ChangeKind::Removed, None),
+            (Some(before), Some(after)) => match modification(before, after) {
+                Some(detail) => (ChangeKind::Modified, Some(detail)),
+                None => continue,
+            },
+            (None, None) => continue,
+        };
+        let (size_before, size_after) = (size_of(before), size_of(after));
+        changes.push(PathChange {
+            path: path.clone(),
+            change,
+            size_before,
+            size_after,
+            size_delta: size_after.unwrap_or(0) as i64 - size_before.unwrap_or(0) as i64,
+            detail,
+        });
+    }
+    changes
+}
+
+/// What differs between two entries for the same path, if anything
+fn modification(before: &ManifestEntry, after: &ManifestEntry) -> Option<String> {
+    let type_name = |entry: &ManifestEntry| match entry.entry_type {
+        EntryType::File => "file",
+        EntryType::Directory => "directory",
+        EntryType::Symlink => "symlink",
+    };
+    let mut details = Vec::new();
+    if before.entry_type != after.entry_type {
+        details.push(format!("{} -> {}", type_name(before), type_name(after)));
+    } else if before.size != after.size || before.digest != after.digest {
+        details.push("content".to_string());
+    } else if before.target != after.target {
+        details.push(format!(
+            "target {} -> {}", before.target.as_deref().unwrap_or_default(), after.target.as_deref().unwrap_or_default()
+        ));
+    }
+    if before.mode != after.mode {
+        details.push(format!("mode {:04o} -> {:04o}", before.mode, after.mode));
+    }
+    (!details.is_empty()).then(|| details.join(", "))
+}
+
+/// Image, image digest, labels and (between two exports) annotations that differ
+fn compare_metadata(a: &DiffSide, b: &DiffSide) -> Vec<MetadataChange> {
+    let mut changes = Vec::new();
+    let mut compare = |field: String, before: Option<&String>, after: Option<&String>| {
+        if before != after {
+            changes.push(MetadataChange { field, before: before.cloned(), after: after.cloned() });
+        }
+    };
+    compare("image".to_string(), Some(&a.source.image), Some(&b.source.image));
+    compare("image_sha256".to_string(), Some(&a.source.image_sha256), Some(&b.source.image_sha256));
+
+    let redacted = |key: &String| a.redacted_keys.contains(key) || b.redacted_keys.contains(key);
+    let label_keys: BTreeSet<&String> = a.labels.keys().chain(b.labels.keys()).collect();
+    for key in label_keys.into_iter().filter(|key| !redacted(key)) {
+        compare(format!("label {}", key), a.labels.get(key), b.labels.get(key));
+    }
+    if let (Some(annotations_a), Some(annotations_b)) = (&a.annotations, &b.annotations) {
+        let keys: BTreeSet<&String> = annotations_a.keys().chain(annotations_b.keys()).collect();
+        for key in keys {
+            compare(format!("annotation {}", key), annotations_a.get(key), annotations_b.get(key));
+        }
+    }
+    changes
+}
+
+/// Print the differences: metadata first, then the paths with their size deltas, then a summary.
+/// With `name_only` the paths were already printed on stdout and only the summary is shown.
+fn display_diff(report: &DiffReport, name_only: bool) {
+    if !report.metadata_changes.is_empty() && !name_only {
+        print_section_header("Metadata Differences");
+        for change in &report.metadata_changes {
+            print_labeled_value(&change.field, &format!(
+                "{} -> {}",
+                change.before.as_deref().unwrap_or("(none)"),
+                change.after.as_deref().unwrap_or("(none)")
+            ));
+        }
+    }
+    if !report.changes.is_empty() && !name_only {
+        print_section_header("Layer Differences");
+        let rows: Vec<Vec<String>> = report.changes.iter()
+            .map(|change| vec![
+                change.change.to_string(),
+                format!("/{}", change.path),
+                change.size_after.or(change.size_before).map(format_file_size).unwrap_or_default(),
+                format_size_delta(change.size_delta),
+                change.detail.clone().unwrap_or_default(),
+            ])
+            .collect();
+        print_table(&["", "Path", "Size", "Delta", "Changed"], &rows);
+    }
+
+    print_section_header("Diff Summary");
+    print_labeled_value("A", &format!("{} ({} entries)", report.a.source, report.a.entries));
+    print_labeled_value("B", &format!("{} ({} entries)", report.b.source, report.b.entries));
+    if report.identical {
+        print_success("\n✅ Identical");
+        return;
+    }
+    print_labeled_value("Paths", &format!(
+        "{} added, {} removed, {} modified ({})",
+        report.added, report.removed, report.modified, format_size_delta(report.size_delta)
+    ));
+    print_labeled_value("Metadata fields", &report.metadata_changes.len().to_string());
+}
+
+/// A size change with its sign, e.g. `+1.50 KB`
+fn format_size_delta(delta: i64) -> String {
+    let sign = if delta < 0 { "-" } else { "+" };
+    format!("{}{}", sign, format_file_size(delta.unsigned_abs()))
+}
+
+impl Default for DiffCommand {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,50 @@
+use anyhow::Result;
+
+use crate::diagnostics::{find_code, CodeInfo, CODES};
+use crate::output::*;
+
+pub struct ExplainCommand;
+
+impl ExplainCommand {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Describe the diagnostic `code` (e.g. `W001`) and how to address it, or list every code
+    pub fn execute(&self, code: Option<&str>) -> Result<()> {
+        let Some(code) = code else {
+            // W codes are warnings and E codes errors, so the severity goes without saying
+            let mut codes = KeyValues::new();
+            for info in CODES {
+                codes.value(info.id, info.title);
+            }
+            codes.print();
+            return Ok(());
+        };
+        let info = find_code(code).ok_or_else(|| anyhow::anyhow!(
+            "Unknown diagnostic code '{}'; run `layer-tool explain` to list them", code
+        ))?;
+        explain(info);
+        Ok(())
+    }
+}
+
+fn explain(info: &CodeInfo) {
+    print_section_header(&format!("{}_{}", info.id, info.name));
+    print_labeled_value("Severity", &info.severity.to_string());
+    print_labeled_value("Title", info.title);
+    print_plain("");
+    print_plain(info.description);
+    if !info.hints.is_empty() {
+        print_info("\nHow to address it:");
+        for hint in info.hints {
+            print_list_item(hint);
+        }
+    }
+}
+
+impl Default for ExplainCommand {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,144 +1,974 @@
 use anyhow::{Context, Result};
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
 use std::fs::File;
-use std::path::Path;
-use tar::Builder;
-use tempfile::TempDir;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tar::{Archive, Builder, EntryType, Header};
 
-use crate::docker::DockerClient;
+use crate::attributes::{append_pax_entry, entry_mtime, entry_xattrs};
+use crate::cancel::CancellationToken;
+use crate::docker::{userns_remap_offset, DockerClient};
+use crate::crypto::{encrypt_to, parse_recipients};
+use crate::excludes::{ExcludeSet, EXCLUDE_PRESETS_ANNOTATION};
+use crate::hooks::{run_hook, PostHook};
+use crate::lock::ContainerLock;
+use crate::diagnostics::{Diagnostic, DiagnosticCode};
 use crate::output::*;
-use crate::types::ExportData;
-use crate::utils::{compress_file, create_tar_archive, format_file_size, get_file_size};
+use crate::s3::{self, is_s3_url, S3Location};
+use crate::ssh::{pipe_to_remote, shell_quote, SshTarget};
+use crate::verify::{parse_export_metadata, verify_export_file, MOUNTS_DIR};
+use crate::timing::PhaseTimer;
+use crate::types::{
+    Checksum, ChecksumAlgorithm, ChecksumScheme, CompressionReport, ContainerMetadata, DockerInfo, EntryType as ManifestEntryType, ExportCompression, ExportData, ExportFormatVersion, ExportMetadata,
+    ExportOptions, ExportReport, IdOffset, IncrementalInfo, LayerArchiveOptions, LayerKind, LayerStats, Manifest, ManifestEntry, MountExport, MountInfo, TransientEntry,
+    CURRENT_CHECKSUM_SCHEME, DEFAULT_TOP_ENTRIES, EXPORT_REPORT_VERSION, MANIFEST_FILE
+};
+use crate::utils::{
+    calculate_file_checksum, calculate_file_digest, create_layer_archive, create_tar_archive_filtered, describe_layer_stats,
+    chunk_index_path, ensure_directory_exists, format_file_size, get_file_size, read_export_member, split_file, stream_file_to_writer, write_json_report, CountingWriter, LayerScanner, PartialFile, PARTIAL_SUFFIX
+};
+use crate::workspace::Workspace;
+
+/// Output path that selects streaming the export to stdout
+const STDOUT_PATH: &str = "-";
+
+/// Layer archives above this size trigger a size warning
+const LARGE_LAYER_WARNING_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// Suffix given to an export that failed post-export verification
+const CORRUPT_SUFFIX: &str = ".corrupt";
+
+/// Key patterns redacted from container metadata when no --redact-env is given
+const DEFAULT_REDACT_PATTERNS: &[&str] = &["PASSWORD", "TOKEN", "SECRET", "KEY"];
+
+/// Replacement value for redacted metadata entries
+const REDACTED_VALUE: &str = "<redacted>";
 
 pub struct ExportCommand {
     docker_client: DockerClient,
+    cancel: CancellationToken,
+    /// Where this command's output goes; the installed printer's sink unless set by `with_output`
+    output: Arc<dyn OutputSink>,
 }
 
 impl ExportCommand {
     pub fn new() -> Self {
         Self {
             docker_client: DockerClient::new(),
+            cancel: CancellationToken::new(),
+            output: current_sink(),
         }
     }
 
+    /// Use `cancel` to stop the export; cancelling removes partial output before returning
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Send this command's output to `output` instead, e.g. a [`CaptureSink`]
+    pub fn with_output(mut self, output: Arc<dyn OutputSink>) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Handle that cancels this command's running operations
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
     /// Export container layer and metadata to a file
-    pub fn execute(&self, container_id: &str, output_path: &str, compress: bool) -> Result<()> {
+    pub fn execute(&self, container_id: &str, output_path: &str, options: ExportOptions) -> Result<()> {
+        let _output = enter_output(&self.output);
+        let report = self.export_container(container_id, output_path, &options)?;
+        if let (Some(report_path), Some(report)) = (&options.report, report) {
+            write_json_report(report_path, &report)?;
+            print_labeled_value("Report", report_path);
+        }
+        Ok(())
+    }
+
+    /// Export one container, returning its report (none for a dry run)
+    fn export_container(&self, container_id: &str, output_path: &str, options: &ExportOptions) -> Result<Option<ExportReport>> {
+        // Collect this export's warnings for the report from here on
+        take_warnings();
+        take_diagnostics();
+        let mut timer = PhaseTimer::new();
+        let compression = options.compression;
+        let layer_kind = if options.full_rootfs { LayerKind::Full } else { LayerKind::Upper };
+        let to_stdout = output_path == STDOUT_PATH;
+        if to_stdout {
+            if options.split_size.is_some() {
+                return Err(anyhow::anyhow!("--split-size cannot be used when streaming the export to stdout"));
+            }
+            if std::io::stdout().is_terminal() {
+                return Err(anyhow::anyhow!(
+                    "Refusing to write export data to a terminal. Redirect or pipe stdout when using '-' as the output path"
+                ));
+            }
+            // stdout carries the archive, so all human-readable output moves to stderr
+            set_output_to_stderr(true);
+        }
+        let ssh_target = options.ssh.as_deref().map(SshTarget::parse).transpose()?;
+        if ssh_target.is_some() && options.split_size.is_some() {
+            return Err(anyhow::anyhow!("--split-size cannot be used with an --ssh destination"));
+        }
+        let encryption_recipients = parse_recipients(&options.encrypt_recipients)?;
+        if options.verify && !encryption_recipients.is_empty() {
+            return Err(anyhow::anyhow!("--verify cannot be combined with --encrypt-recipient (the export cannot be decrypted here)"));
+        }
+        let excludes = ExcludeSet::new(&options.excludes, &options.exclude_presets)?;
+        let to_s3 = is_s3_url(output_path);
+        if options.verify && (to_stdout || to_s3 || ssh_target.is_some()) {
+            return Err(anyhow::anyhow!("--verify requires a local output file"));
+        }
+        if to_s3 {
+            s3::ensure_available()?;
+            S3Location::parse(output_path)?;
+            if options.split_size.is_some() {
+                return Err(anyhow::anyhow!("--split-size cannot be used with an s3:// destination"));
+            }
+        }
+
         print_progress(&format!("Starting export of container: {}", container_id));
+        let mut exclude_presets = Vec::new();
+        for preset in &options.exclude_presets {
+            if exclude_presets.contains(preset) {
+                continue;
+            }
+            exclude_presets.push(*preset);
+            print_info(&format!("Applying exclude preset '{}': {}", preset, preset.patterns().join(", ")));
+        }
+
+        // Refuse to clobber an existing export before doing any expensive work
+        if !to_stdout && !to_s3 && ssh_target.is_none() && !options.force && !Path::new(output_path).is_dir() {
+            let candidate = self.resolve_output_path(output_path, "", container_id, options);
+            if candidate.exists() || (options.split_size.is_some() && chunk_index_path(&candidate).exists()) {
+                return Err(anyhow::anyhow!(
+                    "Output file already exists: {}. Use --force to overwrite it",
+                    candidate.display()
+                ));
+            }
+        }
+
+        // Keep a concurrent import or export of this container from touching the layer
+        timer.start("validation");
+        let _lock = if options.dry_run {
+            None
+        } else {
+            let full_id = self.docker_client.resolve_container_id(container_id)?;
+            Some(ContainerLock::acquire(&full_id, "export", options.wait.map(Duration::from_secs), &self.cancel)?)
+        };
 
         // Validate container exists and is ready for layer operations
         print_progress("Validating container state...");
-        self.docker_client.validate_container_for_layer_operations(container_id)
+        with_spinner(DOCKER_QUERY_MESSAGE, || self.docker_client.validate_container_for_layer_operations(container_id))
             .context("Container validation failed")?;
 
         // Get container metadata
+        timer.start("metadata");
         print_progress("Gathering container metadata...");
-        let container_metadata = self.docker_client.get_container_metadata(container_id)
+        let mut container_metadata = with_spinner(DOCKER_QUERY_MESSAGE, || self.docker_client.get_container_metadata(container_id))
             .context("Failed to get container metadata")?;
 
+        // Scrub secret-looking values before they can reach the export file
+        let redacted_keys = redact_metadata(&mut container_metadata, &options.redact_patterns);
+        if !redacted_keys.is_empty() {
+            print_info(&format!("Redacted {} metadata value(s): {}", redacted_keys.len(), redacted_keys.join(", ")));
+        }
+
         // Get Docker info
         print_progress("Gathering Docker daemon information...");
-        let docker_info = self.docker_client.get_docker_info()
+        let docker_info = with_spinner(DOCKER_QUERY_MESSAGE, || self.docker_client.get_docker_info())
             .context("Failed to get Docker info")?;
 
+        // A userns-remapped daemon stores container ids shifted into its subordinate id range
+        let (archive_id_offset, recorded_id_offset) = match userns_remap_offset(&docker_info)? {
+            Some(remap_offset) if options.unshift => {
+                print_info(&format!("Unshifting layer ownership from the daemon's userns-remap range ({})", remap_offset));
+                (remap_offset.inverse(), None)
+            }
+            Some(remap_offset) => {
+                print_info(&format!(
+                    "Docker runs with userns-remap; layer ownership stays shifted ({}). Pass --unshift to export unshifted ids",
+                    remap_offset
+                ));
+                (IdOffset::default(), Some(remap_offset))
+            }
+            None => {
+                if options.unshift {
+                    print_diagnostic(Diagnostic::new(
+                        DiagnosticCode::UnshiftWithoutUsernsRemap,
+                        "Docker does not run with userns-remap; --unshift has no effect",
+                    ));
+                }
+                (IdOffset::default(), None)
+            }
+        };
+
         // Get container layer path
-        print_progress("Locating container layer directory...");
-        let upper_layer_path = self.docker_client.get_upper_layer_path(container_id)
-            .context("Failed to get container layer path")?;
+        let layer_path = match layer_kind {
+            LayerKind::Upper => {
+                print_progress("Locating container layer directory...");
+                self.docker_client.get_upper_layer_path(container_id)
+                    .context("Failed to get container layer path")?
+            }
+            LayerKind::Full => {
+                print_progress("Locating container merged root filesystem...");
+                print_diagnostic(Diagnostic::new(
+                    DiagnosticCode::FullRootfsExport,
+                    "Full rootfs export archives the complete filesystem of the container and may be very large",
+                ));
+                self.docker_client.get_merged_dir_path(container_id)
+                    .context("Failed to get container merged directory")?
+            }
+        };
 
-        if !upper_layer_path.exists() {
+        if !layer_path.exists() {
             return Err(anyhow::anyhow!(
-                "Container upper layer directory not found: {:?}",
-                upper_layer_path
+                "Container layer directory not found: {:?}",
+                layer_path
             ));
         }
 
+        if options.dry_run {
+            let destination = if let Some(target) = &ssh_target {
+                format!("{}:{}", target.destination, target.path)
+            } else if to_stdout {
+                "<stdout>".to_string()
+            } else if to_s3 {
+                output_path.to_string()
+            } else {
+                self.resolve_output_path(output_path, &container_metadata.name, container_id, options)
+                    .display()
+                    .to_string()
+            };
+            print_info(&format!("Dry run: would export {} to {}", layer_path.display(), destination));
+            for pattern in &options.excludes {
+                print_list_item(&format!("exclude {}", pattern));
+            }
+            let stats = self.scan_layer(&layer_path, &excludes, options)?;
+            for entry in &stats.largest_entries {
+                print_list_item(&format!("/{} ({})", entry.path, format_file_size(entry.size)));
+            }
+            if options.include_mounts {
+                for mount in &container_metadata.mounts {
+                    print_list_item(&format!("mount {} -> {}", mount.source, mount.destination));
+                }
+            }
+            return Ok(None);
+        }
+
+        // Load the parent export before touching the layer so a bad --since path fails fast
+        let parent = match &options.since {
+            Some(since_path) => {
+                print_progress(&format!("Loading previous export: {}", since_path));
+                Some(self.load_parent_export(since_path, layer_kind)?)
+            }
+            None => None,
+        };
+
+        // Hooks let services quiesce around the snapshot; the post-hook runs however we exit from here on
+        let hook_output = ssh_target.as_ref()
+            .map(|target| format!("{}:{}", target.destination, target.path))
+            .unwrap_or_else(|| output_path.to_string());
+        let hook_env = vec![
+            ("LAYER_TOOL_CONTAINER_ID".to_string(), container_metadata.id.clone()),
+            ("LAYER_TOOL_OUTPUT".to_string(), hook_output),
+        ];
+        let hook_timeout = options.hook_timeout.map(Duration::from_secs);
+        let post_hook = PostHook::new(options.post_hook.clone(), hook_env.clone(), hook_timeout);
+        if let Some(pre_hook) = &options.pre_hook {
+            timer.start("pre-hook");
+            let env: Vec<(&str, &str)> = hook_env.iter()
+                .map(|(key, value)| (key.as_str(), value.as_str()))
+                .collect();
+            run_hook("pre-hook", pre_hook, &env, hook_timeout)
+                .context("Pre-export hook failed; export aborted")?;
+        }
+
+        // Size the worker pool used for hashing and compression
+        let threads = options.threads.unwrap_or_else(default_thread_count).max(1);
+        let encoding = OutputEncoding {
+            compression,
+            threads,
+            recipients: encryption_recipients,
+            cancel: self.cancel.clone(),
+        };
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .context("Failed to create worker thread pool")?;
+
         // Create temporary directory for export files
-        let temp_dir = TempDir::new()
+        let temp_dir = Workspace::new()
             .context("Failed to create temporary directory")?;
         let temp_path = temp_dir.path();
 
+        // Walk the layer once; the archive is built from the entries found
+        self.cancel.check()?;
+        timer.start("scanning");
+        let layer_stats = self.scan_layer(&layer_path, &excludes, options)?;
+
         // Create tar archive of the upper layer first
+        self.cancel.check()?;
+        timer.start("archiving");
         print_progress("Creating layer archive...");
         let layer_tar_path = temp_path.join("layer.tar");
-        let layer_checksum = create_tar_archive(&upper_layer_path, &layer_tar_path)
-            .context("Failed to create layer archive")?;
+        let archive_options = LayerArchiveOptions {
+            ignore_transient_errors: options.ignore_transient_errors,
+            id_offset: archive_id_offset,
+            checksum_algorithm: options.checksum_algorithm,
+        };
+        let (layer_checksum, manifest, transient_entries, incremental) = match &parent {
+            Some(parent) => {
+                // Only archive entries that are new or differ from the parent's manifest
+                if let Some(parent_algorithm) = parent.checksum_algorithm
+                    && parent_algorithm != options.checksum_algorithm
+                {
+                    print_diagnostic(Diagnostic::new(DiagnosticCode::ChecksumAlgorithmChanged, format!(
+                        "Previous export hashes files with {}, not {}; every file is archived again",
+                        parent_algorithm, options.checksum_algorithm
+                    ))
+                    .with_context("previous", parent_algorithm)
+                    .with_context("current", options.checksum_algorithm));
+                }
+                let unchanged = |path: &str, entry: &ManifestEntry| parent.manifest.entries.get(path)
+                    .is_some_and(|recorded| entry.matches_recorded(recorded));
+                let (layer_checksum, manifest, transient_entries) = pool
+                    .install(|| create_layer_archive(
+                        &layer_path,
+                        &layer_stats,
+                        &layer_tar_path,
+                        Some(&|path, entry| !unchanged(path, entry)),
+                        archive_options,
+                        &self.cancel,
+                    ))
+                    .context("Failed to create incremental layer archive")?;
+
+                let changed = manifest.entries.iter()
+                    .filter(|(path, entry)| !unchanged(path, entry))
+                    .count();
+                // Excluded paths are absent from the new manifest but were not deleted
+                let deleted_paths: Vec<String> = parent.manifest.entries.keys()
+                    .filter(|path| !manifest.entries.contains_key(*path) && !excludes.is_excluded(path))
+                    .cloned()
+                    .collect();
+                print_labeled_value("Changed or added entries", &changed.to_string());
+                print_labeled_value("Deleted entries", &deleted_paths.len().to_string());
+
+                let incremental = IncrementalInfo {
+                    parent_layer_checksum: parent.layer_checksum.clone(),
+                    parent_checksum_scheme: parent.checksum_scheme,
+                    parent_export_checksum: parent.export_checksum.clone(),
+                    deleted_paths,
+                };
+                (layer_checksum, manifest, transient_entries, Some(incremental))
+            }
+            None => {
+                let (layer_checksum, manifest, transient_entries) = pool
+                    .install(|| create_layer_archive(
+                        &layer_path,
+                        &layer_stats,
+                        &layer_tar_path,
+                        None,
+                        archive_options,
+                        &self.cancel,
+                    ))
+                    .context("Failed to create layer archive")?;
+                (layer_checksum, manifest, transient_entries, None)
+            }
+        };
+
+        print_checksum("Layer archive created with checksum", &layer_checksum.to_string());
+        timer.add_bytes(get_file_size(&layer_tar_path)?);
+
+        // Archive volumes and bind mounts next to the layer when requested
+        let mut transient_entries = transient_entries;
+        let (mounts, mount_tar_paths) = if options.include_mounts {
+            print_progress("Archiving container mounts...");
+            let (mounts, mount_tar_paths, mount_transient_entries) = self.archive_mounts(
+                &container_metadata.mounts,
+                temp_path,
+                LayerArchiveOptions { id_offset: IdOffset::default(), ..archive_options },
+                &pool,
+            )?;
+            transient_entries.extend(mount_transient_entries);
+            (mounts, mount_tar_paths)
+        } else {
+            (Vec::new(), Vec::new())
+        };
+
+        if !transient_entries.is_empty() {
+            print_diagnostic(Diagnostic::new(DiagnosticCode::TransientEntries, format!(
+                "{} path(s) vanished or changed while archiving; this export is not an exact point-in-time snapshot",
+                transient_entries.len()
+            )).with_context("paths", transient_entries.len()));
+        }
 
-        print_checksum("Layer archive created with checksum", &layer_checksum);
+        // Write the per-file manifest next to the metadata
+        timer.start("packaging");
+        let manifest_path = temp_path.join(MANIFEST_FILE);
+        let manifest_json = serde_json::to_string_pretty(&manifest)
+            .context("Failed to serialize layer manifest")?;
+        std::fs::write(&manifest_path, manifest_json)
+            .context("Failed to write manifest file")?;
+        let manifest_checksum = calculate_file_checksum(&manifest_path)
+            .context("Failed to calculate manifest checksum")?;
+        print_labeled_value("Manifest entries", &manifest.entries.len().to_string());
+
+        let layer_archive_checksum = calculate_file_digest(&layer_tar_path, options.checksum_algorithm)
+            .context("Failed to calculate layer archive checksum")?;
+        let layer_tar_size = get_file_size(&layer_tar_path)?;
+        if layer_tar_size > LARGE_LAYER_WARNING_BYTES {
+            print_diagnostic(Diagnostic::new(DiagnosticCode::LargeLayer, format!("Layer archive is large: {}", format_file_size(layer_tar_size)))
+                .with_context("bytes", layer_tar_size));
+        }
+
+        // Pin every timestamp and volatile daemon counter so identical layers give identical output
+        let (created, docker_info) = if options.reproducible {
+            let timestamp = self.reproducible_timestamp(&container_metadata)?;
+            print_info(&format!("Reproducible export using timestamp {}", timestamp.to_rfc3339()));
+            (timestamp, normalize_docker_info(docker_info, timestamp))
+        } else {
+            (Utc::now(), docker_info)
+        };
+
+        // Record the presets so the export documents what was deliberately left out
+        let mut annotations = options.annotations.clone();
+        if !exclude_presets.is_empty() {
+            let names: Vec<&str> = exclude_presets.iter().map(|preset| preset.name()).collect();
+            annotations.insert(EXCLUDE_PRESETS_ANNOTATION.to_string(), names.join(","));
+        }
 
         // Create export data structure with the calculated checksum
         let export_data = ExportData {
-            version: "1.0".to_string(),
-            created: Utc::now(),
+            version: options.format_version.format_version(),
+            created,
             container_metadata,
             docker_info,
             layer_checksum: layer_checksum.clone(),
-            compressed: compress,
+            checksum_scheme: CURRENT_CHECKSUM_SCHEME,
+            layer_archive_checksum: Some(layer_archive_checksum),
+            compressed: compression.is_compressed(),
+            layer_kind,
+            manifest_checksum: Some(manifest_checksum),
+            incremental,
+            annotations,
+            redacted_keys,
+            transient_entries,
+            mounts,
+            id_offset: recorded_id_offset,
+        };
+
+        // Format 2 keeps only the daemon properties check and import compare
+        let metadata = match options.format_version {
+            ExportFormatVersion::V1 => ExportMetadata::V1(export_data),
+            ExportFormatVersion::V2 => ExportMetadata::V2(export_data.into()),
         };
 
         // Write metadata to temporary file
         let metadata_path = temp_path.join("metadata.json");
-        let metadata_json = serde_json::to_string_pretty(&export_data)
+        let metadata_json = serde_json::to_string_pretty(&metadata)
             .context("Failed to serialize export metadata")?;
         std::fs::write(&metadata_path, metadata_json)
             .context("Failed to write metadata file")?;
+        let export_data = metadata.normalize();
 
         // Create final export archive
         print_progress("Creating export archive...");
         let export_tar_path = temp_path.join("export.tar");
-        self.create_export_archive(&metadata_path, &layer_tar_path, &export_tar_path)
+        let mount_archives: Vec<(usize, &Path)> = export_data.mounts.iter()
+            .zip(&mount_tar_paths)
+            .map(|(mount, tar_path)| (mount.index, tar_path.as_path()))
+            .collect();
+        self.create_export_archive(&metadata_path, &manifest_path, &layer_tar_path, &mount_archives, &export_tar_path, created.timestamp().max(0) as u64)
             .context("Failed to create export archive")?;
 
         // Handle compression and final output
-        let final_output_path = Path::new(output_path);
-        if compress {
-            print_progress("Compressing export archive...");
-            let compressed_path = if output_path.ends_with(".gz") {
-                final_output_path.to_path_buf()
+        self.cancel.check()?;
+        let export_tar_size = get_file_size(&export_tar_path)?;
+        timer.start(if encoding.encrypted() { "encryption" } else if compression.is_compressed() { "compression" } else { "copy" });
+        timer.add_bytes(export_tar_size);
+        let mut summary = KeyValues::new();
+        let (final_output, output_bytes) = if to_stdout {
+            if compression.is_compressed() {
+                print_progress("Streaming compressed export archive to stdout...");
             } else {
-                final_output_path.with_extension("tar.gz")
-            };
+                print_progress("Streaming export archive to stdout...");
+            }
+            let bytes_written = encoding.write(&export_tar_path, std::io::stdout())
+                .context("Failed to stream export archive to stdout")?;
 
-            compress_file(&export_tar_path, &compressed_path)
-                .context("Failed to compress export archive")?;
-
-            let file_size = get_file_size(&compressed_path)?;
             print_success("Export completed successfully!");
-            print_file_info("Output file", &format!("{:?}", compressed_path), &format_file_size(file_size));
+            summary.file("Output file", "<stdout>", &format_file_size(bytes_written));
+            (STDOUT_PATH.to_string(), bytes_written)
+        } else if let Some(target) = &ssh_target {
+            let bytes_written = self.stream_to_ssh(&export_tar_path, target, &encoding, options.force, &mut summary)?;
+            (format!("{}:{}", target.destination, target.path), bytes_written)
+        } else if to_s3 {
+            let bytes_written = self.upload_to_s3(&export_tar_path, output_path, &encoding, &mut summary)?;
+            (output_path.to_string(), bytes_written)
         } else {
-            std::fs::copy(&export_tar_path, final_output_path)
-                .context("Failed to copy export archive to final location")?;
+            let final_output_path = self.resolve_output_path(output_path, &export_data.container_metadata.name, container_id, options);
+            let existing = final_output_path.exists()
+                || (options.split_size.is_some() && chunk_index_path(&final_output_path).exists());
+            if existing && !options.force {
+                return Err(anyhow::anyhow!(
+                    "Output file already exists: {}. Use --force to overwrite it",
+                    final_output_path.display()
+                ));
+            }
 
-            let file_size = get_file_size(final_output_path)?;
-            print_success("Export completed successfully!");
-            print_file_info("Output file", &format!("{:?}", final_output_path), &format_file_size(file_size));
+            // Write under a .partial name and only rename into place once the data is on disk
+            let partial_output = PartialFile::new(&final_output_path);
+            if encoding.encrypted() {
+                print_progress("Encrypting export archive...");
+                let output_file = File::create(partial_output.path())
+                    .with_context(|| format!("Failed to create output file: {:?}", partial_output.path()))?;
+                encoding.write(&export_tar_path, output_file)
+                    .context("Failed to encrypt export archive")?;
+            } else if compression.is_compressed() {
+                print_progress(&format!("Compressing export archive ({})...", compression));
+                let output_file = File::create(partial_output.path())
+                    .with_context(|| format!("Failed to create output file: {:?}", partial_output.path()))?;
+                stream_file_to_writer(&export_tar_path, output_file, compression, threads, &self.cancel)
+                    .context("Failed to compress export archive")?;
+            } else {
+                let output_file = File::create(partial_output.path())
+                    .with_context(|| format!("Failed to create output file: {:?}", partial_output.path()))?;
+                stream_file_to_writer(&export_tar_path, output_file, ExportCompression::None, threads, &self.cancel)
+                    .context("Failed to copy export archive to final location")?;
+            }
+            let final_output_path = partial_output.commit()?;
+
+            // Re-read the written file through the same pipeline check uses
+            let file_size = get_file_size(&final_output_path)?;
+            if options.verify {
+                timer.start("verification");
+                timer.add_bytes(file_size);
+                self.verify_output(&final_output_path)?;
+            }
+
+            if let Some(split_size) = options.split_size {
+                timer.start("splitting");
+                timer.add_bytes(file_size);
+                print_progress(&format!("Splitting export into {} chunks...", format_file_size(split_size)));
+                let index = split_file(&final_output_path, split_size)
+                    .context("Failed to split export archive")?;
+                std::fs::remove_file(&final_output_path)
+                    .with_context(|| format!("Failed to remove unsplit export: {:?}", final_output_path))?;
+
+                print_success("Export completed successfully!");
+                summary.file("Chunk index", &chunk_index_path(&final_output_path).display().to_string(), &format_file_size(file_size));
+                for chunk in &index.chunks {
+                    summary.item(&format!("{} ({})", chunk.name, format_file_size(chunk.size)));
+                }
+                (chunk_index_path(&final_output_path).display().to_string(), file_size)
+            } else {
+                print_success("Export completed successfully!");
+                summary.file("Output file", &final_output_path.display().to_string(), &format_file_size(file_size));
+                (final_output_path.display().to_string(), file_size)
+            }
+        };
+        timer.stop();
+
+        summary.container("Container", &export_data.container_metadata.name, container_id);
+        summary.value("Image", &export_data.container_metadata.image);
+        summary.value("Layer kind", &layer_kind.to_string());
+        summary.checksum("Layer checksum", &layer_checksum.to_string());
+        if !export_data.mounts.is_empty() {
+            summary.value("Mounts included", &export_data.mounts.len().to_string());
+            for mount in &export_data.mounts {
+                summary.item(&format!("{} -> {} ({} entries)", mount.source, mount.destination, mount.entries));
+            }
+        }
+        if options.verify {
+            summary.value("Verification", "verified");
+        }
+        summary.print();
+        print_annotations(&export_data.annotations);
+        print_transient_entries(&export_data.transient_entries);
+
+        if options.post_hook.is_some() {
+            timer.start("post-hook");
         }
+        post_hook.finish(true)
+            .context("Post-export hook failed")?;
 
-        print_container_info("Container", &export_data.container_metadata.name, container_id);
-        print_labeled_value("Image", &export_data.container_metadata.image);
-        print_checksum("Layer checksum", &layer_checksum);
+        let duration_secs = timer.elapsed_secs();
+        let phases = timer.finish();
+        print_phase_timings(&phases, duration_secs);
+        if final_output != STDOUT_PATH {
+            print_quiet_result(&final_output);
+        }
+
+        let report = ExportReport {
+            report_version: EXPORT_REPORT_VERSION,
+            container_id: export_data.container_metadata.id.clone(),
+            container_name: export_data.container_metadata.name.clone(),
+            image: export_data.container_metadata.image.clone(),
+            image_digest: export_data.container_metadata.image_sha256.clone(),
+            layer_kind,
+            layer_checksum,
+            layer_stats,
+            file_count: manifest.entries.values().filter(|entry| entry.entry_type == ManifestEntryType::File).count(),
+            bytes_archived: layer_tar_size,
+            compression: CompressionReport {
+                algorithm: compression.to_string(),
+                seekable: compression == ExportCompression::Zstd { seekable: true },
+                uncompressed_bytes: export_tar_size,
+                compressed_bytes: output_bytes,
+                ratio: if output_bytes > 0 { export_tar_size as f64 / output_bytes as f64 } else { 0.0 },
+            },
+            encrypted: encoding.encrypted(),
+            phases,
+            duration_secs,
+            warnings: take_warnings(),
+            diagnostics: take_diagnostics(),
+            output_path: final_output,
+            output_bytes,
+            metrics: metrics::snapshot(),
+        };
+
+        Ok(Some(report))
+    }
+
+    /// Walk the layer at `layer_path`, leaving out what `excludes` matches, and print what it holds
+    fn scan_layer(&self, layer_path: &Path, excludes: &ExcludeSet, options: &ExportOptions) -> Result<LayerStats> {
+        print_progress("Scanning layer...");
+        let stats = LayerScanner::new()
+            .exclude(Some(excludes))
+            .ignore_transient_errors(options.ignore_transient_errors)
+            .top(DEFAULT_TOP_ENTRIES)
+            .scan(layer_path, &self.cancel)
+            .context("Failed to scan container layer")?;
+        print_labeled_value("Layer contents", &describe_layer_stats(&stats));
+        Ok(stats)
+    }
+
+    /// Archive every mount that is a local directory into the workspace. Mounts whose source is
+    /// missing or is not a directory (sockets, single files) are skipped with a warning.
+    fn archive_mounts(
+        &self,
+        mounts: &[MountInfo],
+        temp_path: &Path,
+        archive_options: LayerArchiveOptions,
+        pool: &rayon::ThreadPool,
+    ) -> Result<(Vec<MountExport>, Vec<PathBuf>, Vec<TransientEntry>)> {
+        let mut exports = Vec::new();
+        let mut tar_paths = Vec::new();
+        let mut transient_entries = Vec::new();
+
+        for (index, mount) in mounts.iter().enumerate() {
+            self.cancel.check()?;
+            match std::fs::metadata(&mount.source) {
+                Ok(metadata) if metadata.is_dir() => {}
+                Ok(_) => {
+                    print_diagnostic(Diagnostic::new(
+                        DiagnosticCode::MountSkipped,
+                        format!("Skipping mount {} -> {}: source is not a directory", mount.source, mount.destination),
+                    ).with_context("source", &mount.source).with_context("destination", &mount.destination));
+                    continue;
+                }
+                Err(e) => {
+                    print_diagnostic(Diagnostic::new(
+                        DiagnosticCode::MountSkipped,
+                        format!("Skipping mount {} -> {}: {}", mount.source, mount.destination, e),
+                    ).with_context("source", &mount.source).with_context("destination", &mount.destination).with_context("error", e));
+                    continue;
+                }
+            }
+
+            let tar_path = temp_path.join(format!("mount-{}.tar", index));
+            let (checksum, manifest, mount_transient_entries) = pool
+                .install(|| create_tar_archive_filtered(
+                    Path::new(&mount.source),
+                    &tar_path,
+                    None,
+                    None,
+                    archive_options,
+                    &self.cancel,
+                ))
+                .with_context(|| format!("Failed to archive mount {}", mount.source))?;
+
+            print_check_result(&format!("Mount {}", mount.destination), &format!("✓ {} entries", manifest.entries.len()), true);
+            transient_entries.extend(mount_transient_entries.into_iter().map(|entry| TransientEntry {
+                path: format!("{}/{}/{}", MOUNTS_DIR, index, entry.path),
+                issue: entry.issue,
+            }));
+            exports.push(MountExport {
+                index,
+                source: mount.source.clone(),
+                destination: mount.destination.clone(),
+                checksum,
+                entries: manifest.entries.len(),
+            });
+            tar_paths.push(tar_path);
+        }
+
+        Ok((exports, tar_paths, transient_entries))
+    }
+
+    /// Validate the written export, moving it aside to `<name>.corrupt` if it fails
+    fn verify_output(&self, output_path: &Path) -> Result<()> {
+        print_progress("Verifying written export...");
+        let workspace = Workspace::new()
+            .context("Failed to create temporary directory")?;
+
+        if let Err(error) = verify_export_file(output_path, workspace.path(), true, 0, &self.cancel) {
+            // An interrupted verification says nothing about the file, so leave it in place
+            if self.cancel.is_cancelled() {
+                return Err(error);
+            }
+            let mut corrupt_name = output_path.as_os_str().to_owned();
+            corrupt_name.push(CORRUPT_SUFFIX);
+            let corrupt_path = PathBuf::from(corrupt_name);
+            std::fs::rename(output_path, &corrupt_path)
+                .with_context(|| format!("Failed to move corrupt export aside to {:?}", corrupt_path))?;
+            return Err(error.context(format!(
+                "Export verification failed; the output was moved to {}",
+                corrupt_path.display()
+            )));
+        }
 
         Ok(())
     }
 
-    /// Create the final export archive containing metadata and layer data
+    /// Stream the export archive to a remote host through `ssh`, writing it atomically on the far side
+    fn stream_to_ssh(&self, export_tar_path: &Path, target: &SshTarget, encoding: &OutputEncoding, force: bool, summary: &mut KeyValues) -> Result<u64> {
+        let remote_path = shell_quote(&target.path);
+        let remote_partial = shell_quote(&format!("{}{}", target.path, PARTIAL_SUFFIX));
+        let mut remote_command = format!("cat > {partial} && mv {partial} {path}", partial = remote_partial, path = remote_path);
+        if !force {
+            remote_command = format!(
+                "if [ -e {path} ]; then echo \"Output file already exists: \"{path}\". Use --force to overwrite it\" >&2; exit 1; fi; {command}",
+                path = remote_path,
+                command = remote_command
+            );
+        }
+
+        print_progress(&format!("Streaming export archive to {}:{}...", target.destination, target.path));
+        let started = Instant::now();
+        let bytes_written = pipe_to_remote(&target.destination, &remote_command, |pipe| {
+            encoding.write(export_tar_path, pipe)
+        })
+        .context("Failed to stream export archive over ssh")?;
+        let elapsed = started.elapsed().as_secs_f64().max(0.001);
+
+        print_success("Export completed successfully!");
+        summary.file("Output file", &format!("{}:{}", target.destination, target.path), &format_file_size(bytes_written));
+        summary.value("Transfer rate", &format!("{}/s", format_file_size((bytes_written as f64 / elapsed) as u64)));
+        Ok(bytes_written)
+    }
+
+    /// Stream the export archive into a multipart upload, aborting the upload on failure
+    #[cfg(feature = "s3")]
+    fn upload_to_s3(&self, export_tar_path: &Path, url: &str, encoding: &OutputEncoding, summary: &mut KeyValues) -> Result<u64> {
+        print_progress(&format!("Uploading export archive to {}...", url));
+        let mut writer = s3::S3Writer::create(url)?;
+        let bytes_written = encoding.write(export_tar_path, &mut writer)
+            .context("Failed to upload export archive")?;
+        let upload = writer.finish()?;
+
+        print_success("Export completed successfully!");
+        summary.file("Output object", &upload.url, &format_file_size(bytes_written));
+        summary.value("ETag", &upload.etag);
+        Ok(bytes_written)
+    }
+
+    #[cfg(not(feature = "s3"))]
+    fn upload_to_s3(&self, _export_tar_path: &Path, _url: &str, _encoding: &OutputEncoding, _summary: &mut KeyValues) -> Result<u64> {
+        s3::ensure_available()?;
+        Ok(0)
+    }
+
+    /// Export every container matching the label filters into an output directory
+    pub fn execute_by_label(&self, labels: &[String], output_dir: &str, options: ExportOptions) -> Result<()> {
+        let _output = enter_output(&self.output);
+        print_progress(&format!("Selecting containers with label(s): {}", labels.join(", ")));
+        let containers = self.docker_client.list_containers(labels)
+            .context("Failed to list containers")?;
+
+        if containers.is_empty() {
+            print_warning("No containers matched the label selection");
+            return Ok(());
+        }
+
+        if !options.dry_run {
+            ensure_directory_exists(output_dir)?;
+        }
+
+        let mut exported = Vec::new();
+        let mut skipped = Vec::new();
+        let mut failed = Vec::new();
+        let mut reports = Vec::new();
+
+        for container in &containers {
+            self.cancel.check()?;
+            let display_name = format!("{} ({})", container.name, &container.id[..container.id.len().min(12)]);
+            let state = container.state.to_lowercase();
+            if state == "dead" || state == "removing" {
+                skipped.push(format!("{}: container is {}", display_name, container.state));
+                continue;
+            }
+
+            print_section_header(&format!("Exporting {}", display_name));
+            match self.export_container(&container.id, output_dir, &options) {
+                Ok(report) => {
+                    reports.extend(report);
+                    exported.push(display_name);
+                }
+                Err(e) if self.cancel.is_cancelled() => return Err(e),
+                Err(e) => {
+                    print_error(&format!("Export of {} failed: {:#}", display_name, e));
+                    failed.push(format!("{}: {:#}", display_name, e));
+                }
+            }
+        }
+
+        print_section_header("Batch Export Summary");
+        print_labeled_value("Matched containers", &containers.len().to_string());
+        for container in &containers {
+            print_list_item(&format!("{} ({})", container.name, container.state));
+        }
+        let exported_label = if options.dry_run { "Would export" } else { "Exported" };
+        print_labeled_value(exported_label, &exported.len().to_string());
+        for name in &exported {
+            print_list_item(name);
+        }
+        print_warnings_section(&skipped);
+        print_errors_section(&failed);
+
+        // One array covering every container that was exported, written even if some failed
+        if let Some(report_path) = &options.report
+            && !options.dry_run
+        {
+            write_json_report(report_path, &reports)?;
+            print_labeled_value("Report", report_path);
+        }
+
+        if !failed.is_empty() {
+            return Err(anyhow::anyhow!("{} of {} container export(s) failed", failed.len(), containers.len()));
+        }
+
+        Ok(())
+    }
+
+    /// Resolve the file the export is written to.
+    /// The user-supplied path is used verbatim; a file name is only generated when the path is a
+    /// directory, and the compression's extension (`.gz`, `.zst`) is only appended when `--auto-extension` is set.
+    pub fn resolve_output_path(&self, output_path: &str, container_name: &str, container_id: &str, options: &ExportOptions) -> PathBuf {
+        let requested_path = Path::new(output_path);
+
+        if requested_path.is_dir() {
+            let base_name = if container_name.is_empty() { container_id } else { container_name };
+            let file_name = match options.compression.extension() {
+                Some(extension) => format!("{}.tar.{}", base_name, extension),
+                None => format!("{}.tar", base_name),
+            };
+            return requested_path.join(file_name);
+        }
+
+        if let Some(extension) = options.compression.extension()
+            && options.auto_extension
+            && !output_path.ends_with(&format!(".{}", extension))
+        {
+            return PathBuf::from(format!("{}.{}", output_path, extension));
+        }
+
+        requested_path.to_path_buf()
+    }
+
+    /// Load the manifest and checksums of the export an incremental export is based on
+    fn load_parent_export(&self, since_path: &str, layer_kind: LayerKind) -> Result<ParentExport> {
+        let metadata = read_export_member(since_path, "metadata.json")?
+            .ok_or_else(|| anyhow::anyhow!("Previous export {} contains no metadata.json", since_path))?;
+        let parent_data = parse_export_metadata(&metadata)
+            .context("Failed to parse previous export metadata")?;
+
+        if parent_data.layer_kind != layer_kind {
+            return Err(anyhow::anyhow!(
+                "Previous export is a {} export, but this export is {}",
+                parent_data.layer_kind,
+                layer_kind
+            ));
+        }
+
+        let manifest_content = read_export_member(since_path, "manifest.json")?
+            .ok_or_else(|| anyhow::anyhow!(
+                "Previous export {} has no per-file manifest and cannot be used as an incremental base",
+                since_path
+            ))?;
+        if let Some(expected) = parent_data.manifest_checksum() {
+            let actual = format!("{:x}", Sha256::digest(&manifest_content));
+            if actual != expected {
+                return Err(anyhow::anyhow!("Previous export manifest checksum mismatch: expected {}, got {}", expected, actual));
+            }
+        }
+        let manifest: Manifest = serde_json::from_slice(&manifest_content)
+            .context("Failed to parse previous export manifest")?;
+        let checksum_algorithm = manifest.checksum_algorithm()
+            .map_err(|error| anyhow::anyhow!("Invalid previous export manifest: {}", error))?;
+
+        Ok(ParentExport {
+            layer_checksum: parent_data.layer_checksum,
+            checksum_scheme: parent_data.checksum_scheme,
+            checksum_algorithm,
+            export_checksum: calculate_file_checksum(since_path)?,
+            manifest,
+        })
+    }
+
+    /// Timestamp used for reproducible exports: SOURCE_DATE_EPOCH if set, otherwise the container creation time
+    fn reproducible_timestamp(&self, container_metadata: &ContainerMetadata) -> Result<DateTime<Utc>> {
+        match std::env::var("SOURCE_DATE_EPOCH") {
+            Ok(value) => {
+                let seconds: i64 = value.trim().parse()
+                    .with_context(|| format!("Invalid SOURCE_DATE_EPOCH value: {}", value))?;
+                DateTime::from_timestamp(seconds, 0)
+                    .ok_or_else(|| anyhow::anyhow!("SOURCE_DATE_EPOCH out of range: {}", value))
+            }
+            Err(_) => Ok(container_metadata.created),
+        }
+    }
+
+    /// Create the final export archive containing metadata, manifest and layer data.
+    /// Entries get normalized headers (fixed mtime, root ownership, 0644) so the archive only depends on content.
     fn create_export_archive(
         &self,
         metadata_path: &Path,
+        manifest_path: &Path,
         layer_tar_path: &Path,
+        mount_archives: &[(usize, &Path)],
         output_path: &Path,
+        mtime: u64,
     ) -> Result<()> {
         let output_file = File::create(output_path)
             .context("Failed to create export archive file")?;
         let mut builder = Builder::new(output_file);
 
         // Add metadata file
-        builder.append_path_with_name(metadata_path, "metadata.json")
+        append_synthetic_file(&mut builder, metadata_path, "metadata.json", mtime)
             .context("Failed to add metadata to export archive")?;
 
+        // Add manifest file
+        append_synthetic_file(&mut builder, manifest_path, "manifest.json", mtime)
+            .context("Failed to add manifest to export archive")?;
+
         // Add layer tar file
-        builder.append_path_with_name(layer_tar_path, "layer.tar")
+        append_synthetic_file(&mut builder, layer_tar_path, "layer.tar", mtime)
             .context("Failed to add layer archive to export archive")?;
 
+        // Add included mounts under mounts/<index>/
+        for (index, mount_tar_path) in mount_archives {
+            append_mount_archive(&mut builder, *index, mount_tar_path, mtime)
+                .with_context(|| format!("Failed to add mount {} to export archive", index))?;
+        }
+
         builder.finish()
             .context("Failed to finish export archive")?;
 
@@ -146,8 +976,248 @@ impl ExportCommand {
     }
 }
 
+/// Previous export an incremental export is taken relative to
+struct ParentExport {
+    layer_checksum: Checksum,
+    checksum_scheme: ChecksumScheme,
+    /// Algorithm of the manifest's file digests; None if it lists no files
+    checksum_algorithm: Option<ChecksumAlgorithm>,
+    export_checksum: String,
+    manifest: Manifest,
+}
+
+/// How the final export archive is encoded on its way to its destination
+struct OutputEncoding {
+    compression: ExportCompression,
+    threads: usize,
+    recipients: Vec<age::x25519::Recipient>,
+    cancel: CancellationToken,
+}
+
+impl OutputEncoding {
+    fn encrypted(&self) -> bool {
+        !self.recipients.is_empty()
+    }
+
+    /// Stream the export archive into a writer, returning the number of bytes written to it
+    fn write<W: Write + Send>(&self, export_tar_path: &Path, writer: W) -> Result<u64> {
+        if !self.encrypted() {
+            return stream_file_to_writer(export_tar_path, writer, self.compression, self.threads, &self.cancel);
+        }
+
+        let mut counter = CountingWriter::new(writer);
+        encrypt_to(&mut counter, &self.recipients, |encrypted| {
+            stream_file_to_writer(export_tar_path, encrypted, self.compression, self.threads, &self.cancel).map(|_| ())
+        })?;
+        counter.flush()
+            .context("Failed to flush encrypted output")?;
+        Ok(counter.bytes_written())
+    }
+}
+
+/// Copy the entries of a mount archive into the export archive under `mounts/<index>/`
+fn append_mount_archive(builder: &mut Builder<File>, index: usize, mount_tar_path: &Path, mtime: u64) -> Result<()> {
+    let mount_root = Path::new(MOUNTS_DIR).join(index.to_string());
+
+    // The mount directory itself is always present, even for an empty mount
+    let mut header = Header::new_ustar();
+    header.set_size(0);
+    header.set_mode(0o755);
+    header.set_mtime(mtime);
+    header.set_entry_type(EntryType::Directory);
+    append_pax_entry(builder, &mut header, &mount_root, None, 0, &[], std::io::empty())?;
+
+    // Entries are re-written with their PAX records: long paths, large sizes, times and xattrs
+    let mut archive = Archive::new(File::open(mount_tar_path)?);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = mount_root.join(entry.path()?);
+        let link_target = entry.link_name()?.map(|target| target.into_owned());
+        let mtime = entry_mtime(&mut entry)?;
+        let xattrs = entry_xattrs(&mut entry)?;
+        let mut header = entry.header().clone();
+        header.set_size(entry.size());
+        header.set_mtime(mtime.unix_seconds().max(0) as u64);
+        append_pax_entry(builder, &mut header, &path, link_target.as_deref(), mtime.nanoseconds(), &xattrs, &mut entry)?;
+    }
+    Ok(())
+}
+
+/// Append a file generated by the export itself with a normalized header
+fn append_synthetic_file(builder: &mut Builder<File>, path: &Path, name: &str, mtime: u64) -> Result<()> {
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open {:?}", path))?;
+    let mut header = Header::new_ustar();
+    header.set_size(file.metadata()?.len());
+    header.set_mode(0o644);
+    header.set_mtime(mtime);
+    header.set_uid(0);
+    header.set_gid(0);
+    header.set_username("")?;
+    header.set_groupname("")?;
+    header.set_entry_type(EntryType::Regular);
+    append_pax_entry(builder, &mut header, Path::new(name), None, 0, &[], file)?;
+    Ok(())
+}
+
+/// Default worker count: one per available CPU
+fn default_thread_count() -> usize {
+    std::thread::available_parallelism()
+        .map(|count| count.get())
+        .unwrap_or(1)
+}
+
+/// Replace label values whose keys match a redaction pattern, returning the redacted keys
+fn redact_metadata(container_metadata: &mut ContainerMetadata, patterns: &[String]) -> Vec<String> {
+    let patterns: Vec<String> = if patterns.is_empty() {
+        DEFAULT_REDACT_PATTERNS.iter().map(|pattern| pattern.to_string()).collect()
+    } else {
+        patterns.iter().map(|pattern| pattern.to_uppercase()).collect()
+    };
+
+    let mut redacted_keys = Vec::new();
+    for (key, value) in container_metadata.labels.iter_mut() {
+        let upper_key = key.to_uppercase();
+        if patterns.iter().any(|pattern| upper_key.contains(pattern.as_str())) {
+            *value = REDACTED_VALUE.to_string();
+            redacted_keys.push(key.clone());
+        }
+    }
+
+    redacted_keys
+}
+
+/// Replace volatile daemon counters and clocks so they don't leak into reproducible metadata
+fn normalize_docker_info(mut docker_info: DockerInfo, timestamp: DateTime<Utc>) -> DockerInfo {
+    docker_info.containers = 0;
+    docker_info.containers_running = 0;
+    docker_info.containers_paused = 0;
+    docker_info.containers_stopped = 0;
+    docker_info.images = 0;
+    docker_info.nfd = 0;
+    docker_info.n_goroutines = 0;
+    docker_info.n_events_listener = 0;
+    docker_info.system_time = timestamp;
+    docker_info
+}
+
 impl Default for ExportCommand {
     fn default() -> Self {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::fixture_export_data;
+    use filetime::FileTime;
+    use flate2::read::MultiGzDecoder;
+    use std::io::Read;
+    use tempfile::TempDir;
+
+    fn options(compression: ExportCompression, auto_extension: bool) -> ExportOptions {
+        ExportOptions { compression, auto_extension, ..Default::default() }
+    }
+
+    #[test]
+    fn output_path_is_used_verbatim_when_compressing() {
+        let command = ExportCommand::new();
+        for compression in [ExportCompression::Gzip, ExportCompression::Zstd { seekable: false }] {
+            let path = command.resolve_output_path("backup.bin", "web", "0123abcd", &options(compression, false));
+            assert_eq!(path, PathBuf::from("backup.bin"));
+        }
+
+        let path = command.resolve_output_path("backup.bin", "web", "0123abcd", &options(ExportCompression::Gzip, true));
+        assert_eq!(path, PathBuf::from("backup.bin.gz"));
+        let path = command.resolve_output_path("backup.tar.gz", "web", "0123abcd", &options(ExportCompression::Gzip, true));
+        assert_eq!(path, PathBuf::from("backup.tar.gz"));
+
+        let directory = TempDir::new().unwrap();
+        let output = directory.path().to_str().unwrap();
+        let path = command.resolve_output_path(output, "web", "0123abcd", &options(ExportCompression::Gzip, false));
+        assert_eq!(path, directory.path().join("web.tar.gz"));
+        let path = command.resolve_output_path(output, "", "0123abcd", &options(ExportCompression::None, false));
+        assert_eq!(path, directory.path().join("0123abcd.tar"));
+    }
+
+    #[test]
+    fn compressed_export_creates_no_sibling_files() {
+        let source = TempDir::new().unwrap();
+        let export_tar_path = source.path().join("export.tar");
+        std::fs::write(&export_tar_path, vec![7u8; 64 * 1024]).unwrap();
+
+        let output = TempDir::new().unwrap();
+        let requested = output.path().join("backup.bin");
+        let command = ExportCommand::new();
+        let final_output_path = command.resolve_output_path(
+            requested.to_str().unwrap(), "web", "0123abcd", &options(ExportCompression::Gzip, false)
+        );
+
+        let partial_output = PartialFile::new(&final_output_path);
+        let output_file = File::create(partial_output.path()).unwrap();
+        stream_file_to_writer(&export_tar_path, output_file, ExportCompression::Gzip, 2, &command.cancel).unwrap();
+        let written = partial_output.commit().unwrap();
+        assert_eq!(written, requested);
+
+        let names: Vec<_> = std::fs::read_dir(output.path()).unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, vec![std::ffi::OsString::from("backup.bin")]);
+
+        let mut decompressed = Vec::new();
+        MultiGzDecoder::new(File::open(&requested).unwrap()).read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, vec![7u8; 64 * 1024]);
+    }
+
+    /// Package `layer` the way a reproducible export does, in a fresh workspace whose files are
+    /// stamped `workspace_mtime`, and return the SHA256 of the gzip-compressed output
+    fn reproducible_export_checksum(layer: &Path, workspace_mtime: i64) -> String {
+        let workspace = TempDir::new().unwrap();
+        let command = ExportCommand::new();
+        let layer_tar_path = workspace.path().join("layer.tar");
+        let (layer_checksum, manifest, _) = create_tar_archive_filtered(
+            layer, &layer_tar_path, None, None, LayerArchiveOptions::default(), &command.cancel
+        ).unwrap();
+        let manifest_path = workspace.path().join(MANIFEST_FILE);
+        std::fs::write(&manifest_path, serde_json::to_string_pretty(&manifest).unwrap()).unwrap();
+
+        let mut export_data = fixture_export_data();
+        let timestamp = command.reproducible_timestamp(&export_data.container_metadata).unwrap();
+        export_data.created = timestamp;
+        export_data.docker_info = normalize_docker_info(export_data.docker_info, timestamp);
+        export_data.layer_checksum = layer_checksum;
+        export_data.manifest_checksum = Some(calculate_file_checksum(&manifest_path).unwrap());
+        let metadata_path = workspace.path().join("metadata.json");
+        std::fs::write(&metadata_path, serde_json::to_string_pretty(&ExportMetadata::V1(export_data)).unwrap()).unwrap();
+
+        for path in [&layer_tar_path, &manifest_path, &metadata_path] {
+            filetime::set_file_mtime(path, FileTime::from_unix_time(workspace_mtime, 0)).unwrap();
+        }
+
+        let export_tar_path = workspace.path().join("export.tar");
+        command.create_export_archive(
+            &metadata_path, &manifest_path, &layer_tar_path, &[], &export_tar_path, timestamp.timestamp() as u64
+        ).unwrap();
+        let output_path = workspace.path().join("export.tar.gz");
+        stream_file_to_writer(&export_tar_path, File::create(&output_path).unwrap(), ExportCompression::Gzip, 2, &command.cancel).unwrap();
+        // The gzip header's MTIME field must not record when the export ran
+        assert_eq!(std::fs::read(&output_path).unwrap()[4..8], [0u8; 4]);
+        calculate_file_checksum(&output_path).unwrap()
+    }
+
+    #[test]
+    fn reproducible_exports_are_bit_identical() {
+        let layer = TempDir::new().unwrap();
+        std::fs::create_dir_all(layer.path().join("etc/nginx")).unwrap();
+        std::fs::write(layer.path().join("etc/nginx/nginx.conf"), b"worker_processes 4;\n").unwrap();
+        std::fs::write(layer.path().join("etc/hostname"), b"web\n").unwrap();
+
+        let first = reproducible_export_checksum(layer.path(), 1_000_000_000);
+        let second = reproducible_export_checksum(layer.path(), 1_700_000_000);
+        assert_eq!(first, second);
+
+        std::fs::write(layer.path().join("etc/hostname"), b"api\n").unwrap();
+        assert_ne!(reproducible_export_checksum(layer.path(), 1_000_000_000), first);
+    }
+}
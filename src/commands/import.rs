@@ -1,199 +1,2562 @@
 use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
 use std::fs::File;
-use std::path::Path;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use rayon::prelude::*;
 use tar::Archive;
-use tempfile::TempDir;
+use walkdir::WalkDir;
 
-use crate::docker::DockerClient;
+use crate::attributes::AttributeRestorer;
+use crate::backup::{
+    copy_layer_aside, create_tarball_backup, move_layer_aside, rotate_backups, BackupCheck, BackupRotation, LayerBackup,
+    DEFAULT_KEEP_BACKUPS
+};
+use crate::cancel::{CancellableReader, CancellationToken};
+use crate::crypto::decrypt_if_needed;
+use crate::diagnostics::{Diagnostic, DiagnosticCode, Severity};
+use crate::excludes::{ExcludeSet, PathSelection};
+use crate::docker::{normalize_image_digest, same_architecture, userns_remap_offset, ArchitectureMismatch, DockerClient};
+use crate::journal::{ImportJournal, JournalHeader, JOURNAL_VERSION};
+use crate::lock::ContainerLock;
 use crate::output::*;
-use crate::types::ExportData;
+use crate::hooks::run_command;
+use crate::http::{self, is_http_url};
+use crate::s3::{self, is_s3_url};
+use crate::signing::verify_export_signature;
+use crate::verify::{
+    mount_dir, parse_export_metadata, precheck_export_stream, verify_export_file, verify_mount_checksums, VerifyFailure, VERIFY_ERRORS_EXIT_CODE, VERIFY_WARNINGS_EXIT_CODE
+};
+use crate::ssh::{pipe_to_remote, shell_quote};
+use crate::stream::{
+    extract_export_mounts, layer_stream_disk_usage, read_export_header, with_layer_stream, with_layer_stream_progress
+};
+use crate::timing::PhaseTimer;
+use crate::types::{
+    AppliedWhiteouts, Checksum, ChecksumFormat, CollisionReport, CommandMetrics, ContainerMetadata, ExportDataV2, IdOffset, ImportMode, ImportOptions, ImportReport, ImportStatus, IncrementalInfo, LayerKind, Manifest, MergeStats,
+    OutputFormat, PostCheckReport, TargetLayerState, WhiteoutMode, IMPORT_REPORT_VERSION
+};
 use crate::utils::{
-    decompress_file, extract_tar_archive, is_gzip_file,
-    calculate_directory_checksum, format_file_size, get_file_size
+    copy_directory, remove_layer_paths, extract_tar_stream_verified, parse_manifest,
+    extract_layer_stream, merge_tar_stream, preview_merge_stream, ExtractionState, verify_layer_against_manifest, verify_paths_against_manifest,
+    calculate_directory_checksum, calculate_directory_checksum_with_progress, calculate_file_checksum_with_progress, directory_disk_usage, filesystem_free_space,
+    manifest_disk_usage, spool_stdin, decompress_file, export_format, verify_file_sha256, DiskUsage, STDIN_PATH, format_file_size, get_file_size, reject_partial_file, assemble_chunks, find_chunk_index,
+    sync_filesystem, write_json_report
 };
+use crate::workspace::Workspace;
+
+/// Entries of each collision list a merge prints before modifying the layer
+const COLLISION_PREVIEW_LIMIT: usize = 10;
+
+/// An export fetched and decrypted into a workspace once, ready to be applied to one or more targets
+struct PreparedExport {
+    /// The input as given on the command line, recorded with backups
+    input_path: String,
+    /// Holds downloads, decrypted copies and precheck scratch files until the import is done
+    workspace: Workspace,
+    /// The export the layer is streamed from: the input itself or a file in the workspace
+    export_path: PathBuf,
+    export_data: ExportDataV2,
+    manifest: Option<Manifest>,
+    layer_size: u64,
+    /// Set once the export passed the precheck, so targets do not repeat it
+    prechecked: bool,
+}
+
+/// What applying the layer did to the target, for the summary and report
+struct AppliedLayer {
+    /// The verified layer checksum; None when exclusions or a merge make a whole-layer checksum meaningless
+    checksum: Option<Checksum>,
+    /// Layer-relative paths that were left out
+    excluded: Vec<String>,
+    /// Existing paths removed, by deleted-path lists and whiteouts
+    deleted: usize,
+    /// Whiteouts and opaque markers applied
+    whiteouts: usize,
+    merge_stats: Option<MergeStats>,
+}
+
+/// Where an import writes the layer
+#[derive(Clone, Copy)]
+pub(crate) enum ImportTarget<'a> {
+    /// A container's upper layer, located through the Docker daemon
+    Container(&'a str),
+    /// A plain directory given with --target-path; Docker is not used
+    Directory(&'a Path),
+}
 
 pub struct ImportCommand {
     docker_client: DockerClient,
+    cancel: CancellationToken,
+    /// Where this command's output goes; the installed printer's sink unless set by `with_output`
+    output: Arc<dyn OutputSink>,
 }
 
 impl ImportCommand {
     pub fn new() -> Self {
         Self {
             docker_client: DockerClient::new(),
+            cancel: CancellationToken::new(),
+            output: current_sink(),
         }
     }
 
-    /// Import layer data from export file to container
-    pub fn execute(&self, input_path: &str, container_id: &str, backup: bool) -> Result<()> {
-        print_progress(&format!("Starting import to container: {}", container_id));
+    /// Use `cancel` to stop the import; cancelling restores a layer that was moved aside as backup
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
 
-        let input_file_path = Path::new(input_path);
-        if !input_file_path.exists() {
-            return Err(anyhow::anyhow!("Input file not found: {}", input_path));
+    /// Send this command's output to `output` instead, e.g. a [`CaptureSink`]
+    pub fn with_output(mut self, output: Arc<dyn OutputSink>) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Handle that cancels this command's running operations
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
+    /// Import layer data from export file to a container, or to the directory given in
+    /// `options.target_path` (in which case `container_id` is not needed and Docker is not used)
+    pub fn execute(&self, input_path: &str, container_id: Option<&str>, mut options: ImportOptions) -> Result<()> {
+        let _output = enter_output(&self.output);
+        // stdout carries the JSON report, so all human-readable output moves to stderr
+        if options.output == OutputFormat::Json {
+            set_output_to_stderr(true);
+        }
+        // Restoring selected paths must never replace the rest of the layer
+        if !options.only.is_empty() && options.mode != ImportMode::Merge {
+            print_info("--only implies --mode merge");
+            options.mode = ImportMode::Merge;
         }
 
-        // Validate target container exists and is ready for layer operations
-        print_progress("Validating target container state...");
-        self.docker_client.validate_container_for_layer_operations(container_id)
-            .context("Target container validation failed")?;
+        if options.resume && options.mode == ImportMode::Merge {
+            return Err(anyhow::anyhow!("--resume only continues --mode replace imports; merges are not journaled"));
+        }
+        check_max_overwrites(&options)?;
 
-        let file_size = get_file_size(input_file_path)?;
-        print_file_info("Input file", input_path, &format_file_size(file_size));
+        // Remote and verify-only imports write no import report, so automation must not wait for one
+        if wants_report(&options) {
+            let unreported_flags = [(options.ssh.is_some(), "--ssh"), (options.verify_only, "--verify-only")];
+            if let Some((_, flag)) = unreported_flags.iter().find(|(set, _)| *set) {
+                return Err(anyhow::anyhow!("--report and --output json cannot be used with {}, which writes no import report", flag));
+            }
+        }
 
-        // Create temporary directory for extraction
-        let temp_dir = TempDir::new()
-            .context("Failed to create temporary directory")?;
-        let temp_path = temp_dir.path();
+        // Stdin carries the export, so confirmations need a terminal of their own
+        if input_path == STDIN_PATH && options.restore_mounts && !options.yes && !can_prompt() {
+            return Err(anyhow::anyhow!(
+                "--restore-mounts asks before restoring each mount, but stdin carries the export and no terminal is available; pass --yes"
+            ));
+        }
 
-        // Handle decompression if needed
-        let export_tar_path = if is_gzip_file(input_file_path)? {
-            print_progress("Decompressing input file...");
-            let decompressed_path = temp_path.join("export.tar");
-            decompress_file(input_file_path, &decompressed_path)
-                .context("Failed to decompress input file")?;
-            decompressed_path
-        } else {
-            input_file_path.to_path_buf()
+        if let Some(target_path) = options.target_path.clone() {
+            return self.import(input_path, ImportTarget::Directory(Path::new(&target_path)), &options);
+        }
+        let container_id = container_id
+            .ok_or_else(|| anyhow::anyhow!("A target container is required unless --target-path is given"))?;
+        if !options.verify_only {
+            check_can_confirm(&options)?;
+        }
+
+        if let Some(destination) = &options.ssh {
+            return self.execute_remote(input_path, container_id, destination, &options);
+        }
+
+        if options.verify_only {
+            return self.verify_only(input_path, container_id, &options).map_err(|error| {
+                if error.is::<VerifyFailure>() || self.cancel.is_cancelled() {
+                    error
+                } else {
+                    error.context(VerifyFailure {
+                        exit_code: VERIFY_ERRORS_EXIT_CODE,
+                        message: "Verification failed".to_string(),
+                    })
+                }
+            });
+        }
+
+        self.import(input_path, ImportTarget::Container(container_id), &options)
+    }
+
+    /// Import every container matching the label filters, as [`execute_many`](Self::execute_many) does
+    pub fn execute_by_label(&self, input_path: &str, labels: &[String], options: ImportOptions) -> Result<()> {
+        let _output = enter_output(&self.output);
+        print_progress(&format!("Selecting containers with label(s): {}", labels.join(", ")));
+        let containers = self.docker_client.list_containers(labels)
+            .context("Failed to list containers")?;
+
+        let mut container_names = Vec::new();
+        for container in &containers {
+            let state = container.state.to_lowercase();
+            if state == "dead" || state == "removing" {
+                print_warning(&format!("Skipping {}: container is {}", container.name, container.state));
+            } else {
+                container_names.push(container.name.clone());
+            }
+        }
+
+        if container_names.is_empty() {
+            print_warning("No containers matched the label selection");
+            return Ok(());
+        }
+        self.execute_many(input_path, &container_names, options)
+    }
+
+    /// Import one export into several containers. The export is fetched, decompressed and
+    /// prechecked once; each container then gets its own lock, backup and rollback. Failed
+    /// containers are reported at the end, and with `options.fail_fast` the rest are skipped.
+    pub fn execute_many(&self, input_path: &str, container_ids: &[String], mut options: ImportOptions) -> Result<()> {
+        let _output = enter_output(&self.output);
+        if options.output == OutputFormat::Json {
+            set_output_to_stderr(true);
+        }
+        // Restoring selected paths must never replace the rest of the layer
+        if !options.only.is_empty() && options.mode != ImportMode::Merge {
+            print_info("--only implies --mode merge");
+            options.mode = ImportMode::Merge;
+        }
+
+        check_max_overwrites(&options)?;
+
+        // These either name a single target or, like mounts, do not depend on the target at all
+        let single_target_flags = [
+            (options.target_path.is_some(), "--target-path"),
+            (options.ssh.is_some(), "--ssh"),
+            (options.verify_only, "--verify-only"),
+            (options.restore_mounts, "--restore-mounts"),
+            (options.resume, "--resume"),
+        ];
+        if let Some((_, flag)) = single_target_flags.iter().find(|(set, _)| *set) {
+            return Err(anyhow::anyhow!("{} cannot be used when importing into several containers", flag));
+        }
+
+        let mut targets: Vec<&str> = Vec::new();
+        for container_id in container_ids {
+            if !targets.contains(&container_id.as_str()) {
+                targets.push(container_id);
+            }
+        }
+        if let [container_id] = targets[..] {
+            return self.execute(input_path, Some(container_id), options);
+        }
+        check_can_confirm(&options)?;
+
+        import_excludes(&options)?;
+        import_only(&options)?;
+        let mut timer = PhaseTimer::new();
+        print_progress(&format!("Starting import to {} containers: {}", targets.len(), targets.join(", ")));
+        take_warnings();
+        take_diagnostics();
+        let prepared = self.prepare_batch(input_path, &targets, &mut options, &mut timer);
+        let prepared = match prepared {
+            Ok(prepared) => prepared,
+            Err(error) => {
+                // No container was touched, but each still gets its report
+                if wants_report(&options) {
+                    let result = Err(error);
+                    let reports: Vec<ImportReport> = targets.iter()
+                        .map(|container_id| {
+                            let mut report = new_import_report(input_path, ImportTarget::Container(container_id), &options);
+                            finish_import_report(&mut report, &result, PhaseTimer::new());
+                            report
+                        })
+                        .collect();
+                    write_import_report(&reports, &options)?;
+                    return result;
+                }
+                return Err(error);
+            }
         };
 
-        // Extract export archive
-        print_progress("Extracting export archive...");
-        let extract_dir = temp_path.join("extracted");
-        std::fs::create_dir_all(&extract_dir)
-            .context("Failed to create extraction directory")?;
+        let total_secs = timer.elapsed_secs();
+        print_phase_timings(&timer.finish(), total_secs);
 
-        self.extract_export_archive(&export_tar_path, &extract_dir)
-            .context("Failed to extract export archive")?;
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(options.jobs.max(1))
+            .build()
+            .context("Failed to create worker thread pool")?;
+        let stopped = AtomicBool::new(false);
+        let results: Vec<Option<(Result<()>, ImportReport)>> = pool.install(|| {
+            targets.par_iter()
+                .map(|container_id| {
+                    let _output = enter_output(&self.output);
+                    if stopped.load(Ordering::SeqCst) || self.cancel.is_cancelled() {
+                        return None;
+                    }
+                    print_section_header(&format!("Importing into {}", container_id));
+                    let mut timer = PhaseTimer::new();
+                    timer.start("validation");
+                    let target = ImportTarget::Container(container_id);
+                    let mut report = new_import_report(input_path, target, &options);
+                    describe_source(&mut report, &prepared);
+                    let result = self.lock_target(target, &options).and_then(|(full_id, _lock)| {
+                        report.target_container_id = full_id.clone();
+                        self.import_prepared(&prepared, target, full_id.as_deref(), &options, &mut timer, &mut report)
+                    });
+                    finish_import_report(&mut report, &result, timer);
+                    match &result {
+                        Ok(()) => print_phase_timings(&report.phases, report.duration_secs),
+                        Err(error) => {
+                            print_error(&format!("Import into {} failed: {:#}", container_id, error));
+                            if options.fail_fast {
+                                stopped.store(true, Ordering::SeqCst);
+                            }
+                        }
+                    }
+                    Some((result, report))
+                })
+                .collect()
+        });
+        self.cancel.check()?;
 
-        // Read and validate metadata
-        print_progress("Reading export metadata...");
-        let metadata_path = extract_dir.join("metadata.json");
-        if !metadata_path.exists() {
-            return Err(anyhow::anyhow!("Export metadata not found in archive"));
+        let mut imported = Vec::new();
+        let mut skipped = Vec::new();
+        let mut failed = Vec::new();
+        let mut reports = Vec::new();
+        for (container_id, result) in targets.iter().zip(results) {
+            match result {
+                Some((result, report)) => {
+                    match result {
+                        Ok(()) => imported.push(container_id),
+                        Err(error) => failed.push(format!("{}: {:#}", container_id, error)),
+                    }
+                    reports.push(report);
+                }
+                None => {
+                    let error = anyhow::anyhow!("Not attempted after an earlier failure (--fail-fast)");
+                    skipped.push(format!("{}: {}", container_id, error));
+                    let mut report = new_import_report(input_path, ImportTarget::Container(container_id), &options);
+                    describe_source(&mut report, &prepared);
+                    finish_import_report(&mut report, &Err(error), PhaseTimer::new());
+                    reports.push(report);
+                }
+            }
+        }
+        if wants_report(&options) {
+            write_import_report(&reports, &options)?;
+        }
+
+        print_section_header("Batch Import Summary");
+        print_labeled_value("Target containers", &targets.len().to_string());
+        print_labeled_value("Imported", &imported.len().to_string());
+        for container_id in &imported {
+            print_list_item(container_id);
+        }
+        print_warnings_section(&skipped);
+        print_errors_section(&failed);
+
+        if !failed.is_empty() {
+            return Err(anyhow::anyhow!("{} of {} container import(s) failed", failed.len(), targets.len()));
+        }
+        Ok(())
+    }
+
+    /// Fetch, decompress and precheck the export once for a batch import, and confirm the batch
+    fn prepare_batch(&self, input_path: &str, targets: &[&str], options: &mut ImportOptions, timer: &mut PhaseTimer) -> Result<PreparedExport> {
+        let mut prepared = self.prepare_export(input_path, options, timer)?;
+
+        // Every target streams the layer again, so pay for decompression only once
+        if export_format(&prepared.export_path)?.is_compressed() {
+            timer.start("decompress");
+            print_progress("Decompressing export once for all containers...");
+            let decompressed_path = prepared.workspace.path().join("decompressed");
+            decompress_file(&prepared.export_path, &decompressed_path, &self.cancel)
+                .context("Failed to decompress export")?;
+            prepared.export_path = decompressed_path;
+        }
+        self.precheck(&prepared, options, timer)?;
+        prepared.prechecked = true;
+
+        // One confirmation covers the whole batch; with --max-overwrites each merge decides for itself
+        if !options.yes && !confirms_by_overwrites(options) {
+            print_section_header("Confirm Import");
+            print_labeled_value("Target containers", &targets.len().to_string());
+            for container_id in targets {
+                print_list_item(container_id);
+            }
+            print_labeled_value("Backup", &backup_plan(prepared.export_data.incremental.is_some(), options));
+            let count = targets.len().to_string();
+            if !confirm_typed(&format!("Type the number of containers ({}) to replace their layers:", count), &count) {
+                return Err(anyhow::anyhow!("Import cancelled; no container was modified"));
+            }
+            options.yes = true;
+        }
+
+        Ok(prepared)
+    }
+
+    /// Run the import pipeline against a container's upper layer or a plain directory
+    fn import(&self, input_path: &str, target: ImportTarget, options: &ImportOptions) -> Result<()> {
+        // Reject malformed --exclude and --only patterns before anything is read
+        import_excludes(options)?;
+        import_only(options)?;
+        let mut timer = PhaseTimer::new();
+        timer.start("validation");
+
+        take_warnings();
+        take_diagnostics();
+        let mut report = new_import_report(input_path, target, options);
+
+        // Keep a concurrent import or export of this container from touching the layer
+        let result = self.lock_target(target, options).and_then(|(full_container_id, _lock)| {
+            report.target_container_id = full_container_id.clone();
+            let prepared = self.prepare_export(input_path, options, &mut timer)?;
+            describe_source(&mut report, &prepared);
+            self.import_prepared(&prepared, target, full_container_id.as_deref(), options, &mut timer, &mut report)
+        });
+        finish_import_report(&mut report, &result, timer);
+        if result.is_ok() {
+            print_phase_timings(&report.phases, report.duration_secs);
+        }
+        if wants_report(options) {
+            write_import_report(&report, options)?;
+        }
+        result
+    }
+
+    /// Announce the import and, for a container, resolve its full ID and take its lock
+    fn lock_target(&self, target: ImportTarget, options: &ImportOptions) -> Result<(Option<String>, Option<ContainerLock>)> {
+        match target {
+            ImportTarget::Container(container_id) => {
+                print_progress(&format!("Starting import to container: {}", container_id));
+                let full_id = self.docker_client.resolve_container_id(container_id)?;
+                let lock = ContainerLock::acquire(&full_id, "import", options.wait.map(Duration::from_secs), &self.cancel)?;
+                Ok((Some(full_id), Some(lock)))
+            }
+            ImportTarget::Directory(target_dir) => {
+                print_progress(&format!("Starting import into directory: {}", target_dir.display()));
+                check_target_directory(target_dir, options.force)?;
+                Ok((None, None))
+            }
         }
+    }
 
-        let metadata_content = std::fs::read_to_string(&metadata_path)
-            .context("Failed to read metadata file")?;
-        let export_data: ExportData = serde_json::from_str(&metadata_content)
+    /// Fetch, reassemble and decrypt the export into a new workspace and read its metadata and
+    /// manifest; nothing here depends on the target
+    fn prepare_export(&self, input_path: &str, options: &ImportOptions, timer: &mut PhaseTimer) -> Result<PreparedExport> {
+        // Create temporary directory for extraction
+        let workspace = Workspace::new()
+            .context("Failed to create temporary directory")?;
+        let export_path = self.prepare_input(input_path, workspace.path(), options)?;
+
+        let file_size = get_file_size(&export_path)?;
+        print_file_info("Input file", input_path, &format_file_size(file_size));
+
+        // Read metadata and manifest without unpacking the export; the layer is streamed later
+        timer.start("metadata");
+        timer.add_bytes(file_size);
+        print_progress("Reading export metadata...");
+        let header = read_export_header(&export_path, &self.cancel)
+            .context("Failed to read export archive")?;
+        let metadata_content = header.metadata
+            .ok_or_else(|| anyhow::anyhow!("Export metadata not found in archive"))?;
+        let export_data = parse_export_metadata(&metadata_content)
             .context("Failed to parse export metadata")?;
 
+        // A full rootfs export would shadow every lower layer when applied as an upper dir
+        if export_data.layer_kind == LayerKind::Full && !options.only.is_empty() {
+            print_info("Importing selected paths of a full rootfs export (--only)");
+        } else if export_data.layer_kind == LayerKind::Full {
+            if !options.as_upper {
+                return Err(anyhow::anyhow!(
+                    "Export contains a full root filesystem, not a read-write layer. \
+                    Pass --as-upper to apply it onto the container's upper layer anyway"
+                ));
+            }
+            print_warning("Applying a full rootfs export as the container's upper layer (--as-upper specified)");
+        }
+
+        // Incremental exports already apply on top of the existing layer
+        if options.mode == ImportMode::Merge && export_data.incremental.is_some() {
+            return Err(anyhow::anyhow!(
+                "--mode merge and --only cannot be used with an incremental export; incremental exports are always applied on top of the existing layer"
+            ));
+        }
+
         // Validate layer archive exists
-        let layer_tar_path = extract_dir.join("layer.tar");
-        if !layer_tar_path.exists() {
-            return Err(anyhow::anyhow!("Layer archive not found in export"));
+        let layer_size = header.layer_size
+            .ok_or_else(|| anyhow::anyhow!("Layer archive not found in export"))?;
+
+        let manifest = parse_manifest(header.manifest.as_deref(), export_data.manifest_checksum())
+            .context("Failed to load layer manifest")?;
+
+        Ok(PreparedExport {
+            input_path: input_path.to_string(),
+            workspace,
+            export_path,
+            export_data,
+            manifest,
+            layer_size,
+            prechecked: false,
+        })
+    }
+
+    /// Prove the export is intact inside the workspace before a target layer is touched
+    fn precheck(&self, prepared: &PreparedExport, options: &ImportOptions, timer: &mut PhaseTimer) -> Result<()> {
+        if options.skip_precheck {
+            print_warning("Skipping precheck (--skip-precheck); a corrupt export is only detected after the target layer was replaced");
+            return Ok(());
+        }
+        timer.start("precheck");
+        timer.add_bytes(prepared.layer_size);
+        print_progress("Prechecking export before modifying the container...");
+        precheck_export_stream(&prepared.export_path, prepared.workspace.path(), &prepared.export_data, prepared.manifest.as_ref(), &self.cancel)
+            .context("Precheck failed; the target container was not modified")
+    }
+
+    /// Apply a prepared export to one target: check it, back up its layer, extract and verify
+    /// the layer, and roll back on failure
+    fn import_prepared(
+        &self,
+        prepared: &PreparedExport,
+        target: ImportTarget,
+        full_container_id: Option<&str>,
+        options: &ImportOptions,
+        timer: &mut PhaseTimer,
+        report: &mut ImportReport,
+    ) -> Result<()> {
+        let input_path = prepared.input_path.as_str();
+        let input_file_path = prepared.export_path.as_path();
+        let temp_path = prepared.workspace.path();
+        let export_data = &prepared.export_data;
+        let manifest = prepared.manifest.as_ref();
+        let excludes = import_excludes(options)?;
+
+        // Validate target container exists and is ready for layer operations
+        let target_state = match target {
+            ImportTarget::Container(container_id) => {
+                print_progress("Validating target container state...");
+                with_spinner(DOCKER_QUERY_MESSAGE, || self.docker_client.validate_container_for_layer_operations(container_id))
+                    .context("Target container validation failed")?;
+                Some(self.check_target_running(container_id, options)?)
+            }
+            ImportTarget::Directory(_) => None,
+        };
+
+        match target {
+            ImportTarget::Container(container_id) => self.check_target_compatibility(export_data, container_id, options)?,
+            ImportTarget::Directory(_) => print_info("Skipping architecture and image checks (--target-path)"),
         }
 
+        // From here on the offsets are the resolved shift applied to imported owners
+        let id_offset = self.import_id_offset(export_data, target, options)?;
+        let options = &ImportOptions {
+            uid_offset: Some(id_offset.uid),
+            gid_offset: Some(id_offset.gid),
+            auto_remap: false,
+            ..options.clone()
+        };
+
         // Get target container's upper layer path
-        print_progress("Locating target container layer directory...");
-        let target_upper_path = self.docker_client.get_upper_layer_path(container_id)
-            .context("Failed to get target container layer path")?;
+        let target_upper_path = match target {
+            ImportTarget::Container(container_id) => {
+                print_progress("Locating target container layer directory...");
+                self.docker_client.get_upper_layer_path(container_id)
+                    .context("Failed to get target container layer path")?
+            }
+            ImportTarget::Directory(target_dir) => target_dir.to_path_buf(),
+        };
 
-        // Backup existing upper layer if it exists and is not empty (when backup is enabled)
-        if backup && target_upper_path.exists() {
-            let entries = std::fs::read_dir(&target_upper_path)
-                .context("Failed to read target upper layer directory")?;
+        // Replacing a container's layer keeps a journal next to its backups, so an interrupted
+        // import can be continued with --resume
+        let backup_dir = options.backup_dir.as_deref().map(Path::new);
+        let journal_path = match target {
+            ImportTarget::Container(_) if options.mode == ImportMode::Replace => Some(
+                ImportJournal::path_for(&target_upper_path, backup_dir, full_container_id.unwrap_or_default())
+            ),
+            _ => None,
+        };
+        let input_checksum = match &journal_path {
+            Some(journal_path) => {
+                if options.resume && !journal_path.exists() {
+                    return Err(anyhow::anyhow!(
+                        "No import journal found at {:?}, so there is nothing to resume (pass the same --backup-dir as the interrupted import). \
+                        Run a fresh import without --resume",
+                        journal_path
+                    ));
+                }
+                Some(self.input_checksum(input_file_path)?)
+            }
+            None => None,
+        };
+        let resumed = match (options.resume, &journal_path, &input_checksum) {
+            (true, Some(journal_path), Some(input_checksum)) => {
+                let journal = self.resume_journal(journal_path, input_checksum, &target_upper_path)?;
+                report.target_layer = TargetLayerState::PartiallyImported;
+                Some(journal)
+            }
+            _ => None,
+        };
 
-            if entries.count() > 0 {
-                let backup_path = target_upper_path.with_extension("backup");
-                print_warning(&format!("Backing up existing layer to: {:?}", backup_path));
+        // Incremental exports only apply on top of the exact layer they were taken against; a
+        // resumed import has changed the layer already, and its journal vouches for the parent
+        if let Some(incremental) = &export_data.incremental && resumed.is_none() {
+            timer.start("parent checksum");
+            print_progress("Verifying target layer matches the incremental export's parent...");
+            let current_checksum = current_parent_checksum(&target_upper_path, incremental, id_offset)?;
 
-                if backup_path.exists() {
-                    std::fs::remove_dir_all(&backup_path)
-                        .context("Failed to remove existing backup")?;
+            if current_checksum.as_ref() != Some(&incremental.parent_layer_checksum) {
+                if !options.force_incremental {
+                    return Err(anyhow::anyhow!(
+                        "Target layer does not match the parent of this incremental export: expected {}, got {}. \
+                        Apply the parent export first or pass --force-incremental",
+                        incremental.parent_layer_checksum,
+                        current_checksum.map_or_else(|| "<missing layer>".to_string(), |checksum| checksum.to_string())
+                    ));
                 }
+                print_diagnostic(Diagnostic::new(
+                    DiagnosticCode::IncrementalParentForced,
+                    "Target layer does not match the incremental export's parent (--force-incremental specified)",
+                ).with_context("expected_parent", &incremental.parent_layer_checksum));
+            } else {
+                print_check_result("Incremental parent", "✓ Target layer matches", true);
+            }
+        }
+
+        // A batch import prechecks the export once, before the first target
+        if !prepared.prechecked {
+            self.precheck(prepared, options, timer)?;
+        }
 
-                std::fs::rename(&target_upper_path, &backup_path)
-                    .context("Failed to backup existing layer")?;
+        // Running out of space halfway through would leave the container without its layer
+        if options.no_space_check {
+            print_warning("Skipping free space check (--no-space-check)");
+        } else if resumed.is_some() {
+            print_info("Skipping free space check; the interrupted import already extracted part of the layer (--resume)");
+        } else {
+            print_progress("Checking free space on the target filesystem...");
+            let layer = self.layer_disk_usage(input_file_path, manifest)?;
+            let shortfalls = self.space_shortfalls(layer, target, &target_upper_path, export_data.incremental.is_some(), options)?;
+            if !shortfalls.is_empty() {
+                print_errors_section(&shortfalls);
+                return Err(anyhow::anyhow!(
+                    "Not enough free space for the import; the target was not modified. Free up space or pass --no-space-check"
+                ));
             }
-        } else if !backup && target_upper_path.exists() {
-            // Remove existing layer without backup when backup is disabled
-            print_warning("Removing existing layer without backup (--no-backup specified)");
-            std::fs::remove_dir_all(&target_upper_path)
-                .context("Failed to remove existing layer")?;
+            print_check_result("Free space", "✓ Sufficient for the layer and backup", true);
         }
 
-        // Create target directory
-        std::fs::create_dir_all(&target_upper_path)
-            .context("Failed to create target upper layer directory")?;
+        // A merge keeps the rest of the layer, so show which existing files it changes first
+        let collisions = match options.mode {
+            ImportMode::Merge => {
+                timer.start("collisions");
+                let collisions = self.merge_collisions(input_file_path, &target_upper_path, options)?;
+                print_collision_report(&collisions);
+                if wants_report(options) {
+                    report.collisions = Some(collisions.clone());
+                }
+                Some(collisions)
+            }
+            ImportMode::Replace => None,
+        };
 
-        // Extract layer data to target location
-        print_progress("Extracting layer data to container...");
-        extract_tar_archive(&layer_tar_path, &target_upper_path)
-            .context("Failed to extract layer data to target container")?;
+        // The import being resumed was confirmed when it started
+        if let ImportTarget::Container(container_id) = target && resumed.is_none() && needs_confirmation(collisions.as_ref(), options)? {
+            timer.start("confirmation");
+            self.confirm_import(
+                prepared, container_id, full_container_id.unwrap_or_default(), &target_upper_path, collisions.as_ref(), options
+            )?;
+        }
 
-        // Verify checksum
-        print_progress("Verifying layer integrity...");
-        let calculated_checksum = calculate_directory_checksum(&target_upper_path)
-            .context("Failed to calculate imported layer checksum")?;
+        self.cancel.check()?;
+        let (layer_backup, mut journal) = match resumed {
+            Some(journal) => (self.resumed_backup(&journal), Some(journal)),
+            None => {
+                timer.start("backup");
+                // A backup that fails halfway may already have moved or removed the layer
+                report.target_layer = TargetLayerState::Unknown;
+                let layer_backup = self.back_up_layer(
+                    target, &target_upper_path, export_data.incremental.is_some(), full_container_id.unwrap_or_default(), input_path, options
+                )?;
+                let journal = match (&journal_path, input_checksum) {
+                    (Some(journal_path), Some(input_checksum)) => Some(ImportJournal::create(journal_path, JournalHeader {
+                        journal_version: JOURNAL_VERSION,
+                        started: Utc::now(),
+                        input_path: input_path.to_string(),
+                        input_checksum,
+                        container_id: full_container_id.unwrap_or_default().to_string(),
+                        target_upper_path: target_upper_path.clone(),
+                        backup: layer_backup.clone(),
+                    })?),
+                    _ => None,
+                };
+                (layer_backup, journal)
+            }
+        };
+        report.backup_path = layer_backup.as_ref().map(|backup| backup.path().display().to_string());
 
-        if calculated_checksum != export_data.layer_checksum {
-            return Err(anyhow::anyhow!(
-                "Layer checksum verification failed: expected {}, got {}",
-                export_data.layer_checksum,
-                calculated_checksum
+        // Extract layer data and restore mounts; a failure from here on rolls the layer back
+        let applied = (|| {
+            let applied = match options.mode {
+                ImportMode::Replace => self.apply_layer(prepared, &target_upper_path, journal.as_mut(), options, timer)?,
+                ImportMode::Merge => self.merge_layer(manifest, input_file_path, &target_upper_path, options, timer)
+                    .map(|stats| AppliedLayer {
+                        checksum: None,
+                        excluded: stats.excluded.clone(),
+                        deleted: stats.deleted.len(),
+                        whiteouts: stats.whiteouts,
+                        merge_stats: Some(stats),
+                    })?,
+            };
+
+            // Put included volumes and bind mounts back where they came from
+            if options.restore_mounts {
+                timer.start("mounts");
+                if let Some(journal) = journal.as_mut() {
+                    journal.set_phase("mounts")?;
+                }
+                self.restore_mounts(export_data, input_file_path, temp_path, options.yes)?;
+            }
+
+            // The container has to work with its new layer, or the import is rolled back
+            if let (ImportTarget::Container(container_id), Some(post_check)) = (target, &options.post_check) {
+                timer.start("post-check");
+                if let Some(journal) = journal.as_mut() {
+                    journal.set_phase("post-check")?;
+                }
+                let outcome = self.run_post_check(container_id, post_check, target_state.as_deref(), options)?;
+                let failure = (!outcome.passed).then(|| post_check_error(&outcome, options.post_check_timeout));
+                report.post_check = Some(outcome);
+                if let Some(failure) = failure {
+                    return Err(failure);
+                }
+            }
+            anyhow::Ok(applied)
+        })();
+        let applied = match applied {
+            Ok(applied) => applied,
+            Err(error) => {
+                let (error, state) = self.roll_back(error, target, &target_upper_path, layer_backup.as_ref(), options);
+                report.target_layer = state;
+                if let Some(journal) = journal {
+                    finish_journal(journal, state);
+                }
+                return Err(error);
+            }
+        };
+        report.target_layer = TargetLayerState::Imported;
+        report.verified_checksum = applied.checksum.clone();
+        report.excluded_paths = applied.excluded.len();
+        report.files_deleted = applied.deleted;
+        report.whiteouts_applied = applied.whiteouts;
+        if wants_report(options) {
+            report.files_written = match &applied.merge_stats {
+                Some(stats) => stats.imported.len(),
+                None => self.layer_entry_count(input_file_path)?.saturating_sub(applied.excluded.len() + applied.whiteouts),
+            };
+        }
+
+        if !options.restore_mounts && !export_data.mounts.is_empty() {
+            print_info(&format!(
+                "Export includes {} mount(s); pass --restore-mounts to restore them",
+                export_data.mounts.len()
             ));
         }
 
+        // Older rollback points are only discarded once the import has succeeded
+        let backup_rotation = match layer_backup {
+            Some(layer_backup) => Some(rotate_backups(
+                layer_backup,
+                &target_upper_path,
+                backup_dir,
+                full_container_id.unwrap_or_default(),
+                options.keep_backups,
+            )?),
+            None => None,
+        };
+        if let Some(rotation) = &backup_rotation {
+            report.backup_path = Some(rotation.created.path().display().to_string());
+            report.pruned_backups = rotation.pruned.iter().map(|backup| backup.path().display().to_string()).collect();
+        }
+        // Report success only once the new layer is on disk
+        sync_filesystem(&target_upper_path)?;
+        if let Some(journal) = journal {
+            journal.remove()?;
+        }
+
         print_success("Import completed successfully!");
         print_container_info("Source container", &export_data.container_metadata.name, &export_data.container_metadata.id);
-        print_labeled_value("Target container", container_id);
+        match target {
+            ImportTarget::Container(container_id) => print_labeled_value("Target container", container_id),
+            ImportTarget::Directory(target_dir) => print_labeled_value("Target directory", &target_dir.display().to_string()),
+        }
         print_labeled_value("Image", &export_data.container_metadata.image);
-        print_checksum("Layer checksum verified", &calculated_checksum);
+        if let Some(verified_checksum) = &applied.checksum {
+            print_checksum("Layer checksum verified", &verified_checksum.to_string());
+        }
 
         // Display import summary
-        self.display_import_summary(&export_data)?;
+        Self::display_import_summary(export_data, target_state.as_deref(), &applied, excludes.as_ref(), options, backup_rotation.as_ref())?;
 
         Ok(())
     }
 
-    /// Extract the export archive (metadata + layer tar)
-    fn extract_export_archive(&self, archive_path: &Path, output_dir: &Path) -> Result<()> {
-        let archive_file = File::open(archive_path)
-            .context("Failed to open export archive")?;
-        let mut archive = Archive::new(archive_file);
+    /// Show what the import is about to replace and require the container's short ID to go on
+    fn confirm_import(
+        &self,
+        prepared: &PreparedExport,
+        container_id: &str,
+        full_container_id: &str,
+        target_upper_path: &Path,
+        collisions: Option<&CollisionReport>,
+        options: &ImportOptions,
+    ) -> Result<()> {
+        let export_data = &prepared.export_data;
+        let incremental = export_data.incremental.is_some();
+        let target_metadata = with_spinner(DOCKER_QUERY_MESSAGE, || self.docker_client.get_container_metadata(container_id))
+            .context("Failed to get target container metadata")?;
+        let short_id = &full_container_id[..full_container_id.len().min(12)];
+        let replaced = match collisions {
+            Some(collisions) => collisions.changed_count(),
+            None => self.replaced_entries(&prepared.export_path, target_upper_path, options.mode == ImportMode::Replace && !incremental)?,
+        };
 
-        archive.unpack(output_dir)
-            .context("Failed to extract export archive")?;
+        print_section_header("Confirm Import");
+        print_container_info("Target container", &target_metadata.name, short_id);
+        let image = match image_mismatch(export_data, &target_metadata) {
+            None => "Matches the export".to_string(),
+            Some(_) => format!("Differs from the export ({})", target_metadata.image),
+        };
+        print_labeled_value("Image", &image);
+        print_labeled_value("Backup", &backup_plan(incremental, options));
+        print_labeled_value("Existing entries replaced", &replaced.to_string());
 
+        if !confirm_typed(&format!("Type the container's short ID ({}) to replace its layer:", short_id), short_id) {
+            return Err(anyhow::anyhow!("Import cancelled; the target container was not modified"));
+        }
         Ok(())
     }
 
-    /// Display summary of imported data
-    fn display_import_summary(&self, export_data: &ExportData) -> Result<()> {
-        print_section_header("Import Summary");
-        print_labeled_value("Export version", &export_data.version);
-        print_labeled_value("Export created", &export_data.created.format("%Y-%m-%d %H:%M:%S UTC").to_string());
-        print_info("Source container:");
-        print_metadata_item("ID", &export_data.container_metadata.id);
-        print_metadata_item("Name", &export_data.container_metadata.name);
-        print_metadata_item("Image", &export_data.container_metadata.image);
-        print_metadata_item("Image SHA256", &export_data.container_metadata.image_sha256);
-        print_metadata_item("Created", &export_data.container_metadata.created.format("%Y-%m-%d %H:%M:%S UTC").to_string());
-        print_metadata_item("State", &export_data.container_metadata.state);
+    /// Entries of the target layer the import overwrites: all of them when the layer is replaced,
+    /// otherwise those the layer archive writes again
+    fn replaced_entries(&self, export_path: &Path, target_upper_path: &Path, replaces_layer: bool) -> Result<usize> {
+        if !target_upper_path.exists() {
+            return Ok(0);
+        }
+        if replaces_layer {
+            return Ok(WalkDir::new(target_upper_path).min_depth(1).into_iter().count());
+        }
+        with_layer_stream(export_path, &self.cancel, |layer| {
+            let mut archive = Archive::new(layer);
+            let mut replaced = 0;
+            for entry in archive.entries().context("Failed to read layer archive entries")? {
+                let entry = entry.context("Failed to read layer archive entry")?;
+                let entry_path = entry.path().context("Failed to read tar entry path")?;
+                if target_upper_path.join(&entry_path).symlink_metadata().is_ok() {
+                    replaced += 1;
+                }
+            }
+            Ok(replaced)
+        })
+            .context("Failed to read layer archive")
+    }
 
-        if !export_data.container_metadata.labels.is_empty() {
-            print_metadata_item("Labels", "");
-            for (key, value) in &export_data.container_metadata.labels {
-                print_nested_metadata_item(key, value);
+    /// What merging the export's layer archive into `target_upper_path` overwrites, deletes and adds
+    fn merge_collisions(&self, export_path: &Path, target_upper_path: &Path, options: &ImportOptions) -> Result<CollisionReport> {
+        print_progress("Comparing the export with the existing layer...");
+        let excludes = import_excludes(options)?;
+        let only = import_only(options)?;
+        with_layer_stream(export_path, &self.cancel, |layer| {
+            preview_merge_stream(layer, target_upper_path, excludes.as_ref(), only.as_ref(), &self.cancel)
+        })
+            .context("Failed to compare layer archive with the target layer")
+    }
+
+    /// Entries in the export's layer archive
+    fn layer_entry_count(&self, export_path: &Path) -> Result<usize> {
+        with_layer_stream(export_path, &self.cancel, |layer| {
+            let mut archive = Archive::new(layer);
+            let mut count = 0;
+            for entry in archive.entries().context("Failed to read layer archive entries")? {
+                entry.context("Failed to read layer archive entry")?;
+                count += 1;
             }
+            Ok(count)
+        })
+            .context("Failed to read layer archive")
+    }
+
+    /// Run `command` inside the container through `docker exec`, (re)starting the container first so
+    /// it runs on the new layer. A container that was stopped is stopped again afterwards.
+    fn run_post_check(
+        &self,
+        container_id: &str,
+        command: &str,
+        target_state: Option<&str>,
+        options: &ImportOptions,
+    ) -> Result<PostCheckReport> {
+        let was_running = target_state.is_some_and(is_running_state);
+        if was_running {
+            print_progress(&format!("Restarting container {} for the post-check...", container_id));
+            self.docker_client.restart_container(container_id)?;
+        } else {
+            print_progress(&format!("Starting container {} for the post-check...", container_id));
+            self.docker_client.start_container(container_id)?;
         }
 
-        if !export_data.container_metadata.mounts.is_empty() {
-            print_metadata_item("Mounts", &format!("{} mount(s)", export_data.container_metadata.mounts.len()));
+        print_plain(&format!("Running post-check: {}", command));
+        let started = Instant::now();
+        let timeout = Duration::from_secs(options.post_check_timeout);
+        let run = run_command("post-check", self.docker_client.exec_command(container_id, command), Some(timeout));
+        let duration_secs = started.elapsed().as_secs_f64();
+
+        if !was_running {
+            print_progress(&format!("Stopping container {} again...", container_id));
+            // Rolling back underneath a container that kept running would be worse than failing here
+            self.docker_client.stop_container(container_id)?;
         }
+        let run = run?;
 
-        print_info("Docker environment:");
-        print_metadata_item("Storage driver", &export_data.docker_info.driver);
-        print_metadata_item("Operating system", &export_data.docker_info.operating_system);
-        print_metadata_item("Architecture", &export_data.docker_info.architecture);
-        print_metadata_item("Docker version", &export_data.docker_info.server_version);
+        let outcome = PostCheckReport {
+            command: command.to_string(),
+            passed: run.status.is_some_and(|status| status.success()),
+            exit_code: run.status.and_then(|status| status.code()),
+            timed_out: run.status.is_none(),
+            duration_secs,
+            output: run.output,
+        };
+        let result = match (outcome.timed_out, outcome.exit_code) {
+            (true, _) => format!("✗ Timed out after {}s", options.post_check_timeout),
+            (false, Some(code)) if outcome.passed => format!("✓ Passed (exit code {})", code),
+            (false, Some(code)) => format!("✗ Failed with exit code {}", code),
+            (false, None) => "✗ Terminated by a signal".to_string(),
+        };
+        print_check_result("Post-check", &result, outcome.passed);
+        Ok(outcome)
+    }
+
+    /// SHA256 of the export being imported, which identifies it to the import journal
+    fn input_checksum(&self, export_path: &Path) -> Result<String> {
+        print_progress("Hashing the export for the import journal...");
+        let progress = byte_progress_bar("Hashing");
+        progress.set_length(get_file_size(export_path)?);
+        let checksum = calculate_file_checksum_with_progress(export_path, &progress)
+            .context("Failed to hash the export")?;
+        finish_progress(&progress);
+        Ok(checksum)
+    }
+
+    /// Open the journal of the interrupted import that --resume continues, check it was started for
+    /// this export and layer, and check the entries it extracted are still in place
+    fn resume_journal(&self, journal_path: &Path, input_checksum: &str, target_upper_path: &Path) -> Result<ImportJournal> {
+        let mut journal = ImportJournal::open(journal_path)?;
+        let header = journal.header();
+        if header.input_checksum != input_checksum {
+            return Err(anyhow::anyhow!(
+                "Import journal {:?} belongs to a different export ({}, SHA256 {}). Run a fresh import without --resume",
+                journal_path, header.input_path, header.input_checksum
+            ));
+        }
+        if header.target_upper_path != target_upper_path {
+            return Err(anyhow::anyhow!(
+                "Import journal {:?} belongs to another layer ({:?}). Run a fresh import without --resume",
+                journal_path, header.target_upper_path
+            ));
+        }
+
+        print_section_header("Resuming Import");
+        print_labeled_value("Journal", &journal_path.display().to_string());
+        print_labeled_value("Started", &header.started.format("%Y-%m-%d %H:%M:%S UTC").to_string());
+        let phase = match journal.phase() {
+            "" => "before extraction",
+            phase => phase,
+        };
+        print_labeled_value("Interrupted during", phase);
+        print_progress("Checking the entries extracted before the interruption...");
+        let changed = journal.verify_completed(target_upper_path)?;
+        if !changed.is_empty() {
+            print_warnings_section(&changed);
+            print_warning(&format!("{} previously extracted path(s) changed since the interruption; they are extracted again", changed.len()));
+        }
+        print_labeled_value("Already extracted", &format!("{} entries", journal.completed_count()));
+        Ok(journal)
+    }
+
+    /// The backup the interrupted import took, if it is still there to roll back to
+    fn resumed_backup(&self, journal: &ImportJournal) -> Option<LayerBackup> {
+        let backup = journal.header().backup.clone()?;
+        if !backup.path().exists() {
+            print_warning(&format!("The backup taken by the interrupted import is gone ({:?}); no rollback is possible", backup.path()));
+            return None;
+        }
+        print_labeled_value("Backup", &backup.path().display().to_string());
+        Some(backup)
+    }
+
+    /// Take the backup of the target's existing layer that a failed import rolls back to. A
+    /// replaced layer is moved aside (or archived and removed); an incremental import keeps the
+    /// layer in place and backs up a copy. Returns None when nothing was backed up.
+    pub(crate) fn back_up_layer(
+        &self,
+        target: ImportTarget,
+        target_upper_path: &Path,
+        incremental: bool,
+        container_id: &str,
+        source: &str,
+        options: &ImportOptions,
+    ) -> Result<Option<LayerBackup>> {
+        let backup = options.backup;
+        let backup_dir = options.backup_dir.as_deref().map(Path::new);
+        if options.mode == ImportMode::Merge {
+            print_warning("Merging into the existing layer (--mode merge); it is not backed up or removed");
+        } else if let ImportTarget::Directory(target_dir) = target {
+            // Only empty directories get here without --force; nothing is backed up or removed
+            if !is_empty_directory(target_dir)? {
+                print_warning("Extracting into a non-empty directory (--force specified); existing files are kept");
+            }
+        } else if incremental {
+            // Incremental imports modify the existing layer in place, so the backup is a copy
+            if backup && target_upper_path.exists() {
+                print_progress("Backing up existing layer...");
+                let created = match backup_dir {
+                    Some(backup_dir) => create_tarball_backup(target_upper_path, backup_dir, container_id, source, &self.cancel),
+                    None => copy_layer_aside(target_upper_path, container_id, source),
+                }
+                .context("Failed to backup existing layer")?;
+                print_warning(&format!("Backed up existing layer to: {:?}", created.path()));
+                return Ok(Some(created));
+            }
+        } else if backup && target_upper_path.exists() {
+            // Backup existing upper layer if it exists and is not empty (when backup is enabled)
+            let entries = std::fs::read_dir(target_upper_path)
+                .context("Failed to read target upper layer directory")?;
+
+            if entries.count() > 0 {
+                print_progress("Backing up existing layer...");
+                let created = match backup_dir {
+                    Some(backup_dir) => {
+                        // Another filesystem cannot take a rename, so archive the layer and then remove it
+                        let created = create_tarball_backup(target_upper_path, backup_dir, container_id, source, &self.cancel)
+                            .context("Failed to backup existing layer")?;
+                        std::fs::remove_dir_all(target_upper_path)
+                            .context("Failed to remove existing layer")?;
+                        created
+                    }
+                    None => move_layer_aside(target_upper_path, container_id, source)?,
+                };
+                print_warning(&format!("Backed up existing layer to: {:?}", created.path()));
+                return Ok(Some(created));
+            }
+        } else if !backup && target_upper_path.exists() {
+            // Remove existing layer without backup when backup is disabled
+            print_warning("Removing existing layer without backup (--no-backup specified)");
+            std::fs::remove_dir_all(target_upper_path)
+                .context("Failed to remove existing layer")?;
+        }
+        Ok(None)
+    }
+
+    /// Space the extracted layer takes: from the manifest, or else the layer archive's headers
+    fn layer_disk_usage(&self, export_path: &Path, manifest: Option<&Manifest>) -> Result<DiskUsage> {
+        match manifest {
+            Some(manifest) => Ok(manifest_disk_usage(manifest)),
+            None => with_layer_stream(export_path, &self.cancel, |layer| layer_stream_disk_usage(layer, &self.cancel))
+                .context("Failed to measure layer archive"),
+        }
+    }
+
+    /// Describe each filesystem without room for the import: the extracted `layer`, plus whatever
+    /// backup of the existing layer the import will take, against free bytes and inodes
+    pub(crate) fn space_shortfalls(
+        &self,
+        layer: DiskUsage,
+        target: ImportTarget,
+        target_upper_path: &Path,
+        incremental: bool,
+        options: &ImportOptions,
+    ) -> Result<Vec<String>> {
+        // What the backup step writes and frees, mirroring the backup step of the import
+        let existing = match target {
+            ImportTarget::Container(_) if options.mode == ImportMode::Replace => directory_disk_usage(target_upper_path)
+                .context("Failed to measure existing layer")?,
+            _ => DiskUsage::default(),
+        };
+        let mut target_needed = layer;
+        let mut target_freed = DiskUsage::default();
+        let mut backup_needed = None;
+        match (options.backup, options.backup_dir.as_deref()) {
+            (true, Some(backup_dir)) => {
+                // The tarball is at most as large as the layer; a replaced layer is removed once archived
+                backup_needed = Some((Path::new(backup_dir), existing));
+                if !incremental {
+                    target_freed = existing;
+                }
+            }
+            (true, None) if incremental => target_needed = target_needed + existing,
+            (true, None) => {}
+            (false, _) if !incremental => target_freed = existing,
+            (false, _) => {}
+        }
+        if let Some((backup_dir, needed)) = backup_needed
+            && same_filesystem(backup_dir, target_upper_path)
+        {
+            target_needed = target_needed + needed;
+            backup_needed = None;
+        }
+
+        let mut shortfalls = Vec::new();
+        let target_free = filesystem_free_space(target_upper_path)? + target_freed;
+        shortfalls.extend(space_shortfall(target_upper_path, target_needed, target_free));
+        if let Some((backup_dir, needed)) = backup_needed {
+            shortfalls.extend(space_shortfall(backup_dir, needed, filesystem_free_space(backup_dir)?));
+        }
+        Ok(shortfalls)
+    }
+
+    /// Refuse to rewrite the layer of a running container unless `options.force`; returns its state
+    pub(crate) fn check_target_running(&self, container_id: &str, options: &ImportOptions) -> Result<String> {
+        let state = with_spinner(DOCKER_QUERY_MESSAGE, || self.docker_client.get_container_metadata(container_id))
+            .context("Failed to get target container metadata")?
+            .state;
+        if is_running_state(&state) {
+            if !options.force {
+                return Err(anyhow::anyhow!(
+                    "Container {} is running. Rewriting its layer underneath it leaves running processes on \
+                    deleted files and overlayfs serving stale entries; stop the container first or pass --force",
+                    container_id
+                ));
+            }
+            print_diagnostic(Diagnostic::new(DiagnosticCode::TargetRunningForced, format!(
+                "Importing into running container {} (--force specified). Restart the container afterwards; \
+                until then its processes may keep seeing the old files",
+                container_id
+            )).with_context("container", container_id));
+        }
+        Ok(state)
+    }
+
+    /// Refuse targets the layer cannot work on: another architecture (unless --skip-arch) or another
+    /// image (unless --allow-image-mismatch)
+    pub(crate) fn check_target_compatibility(&self, export_data: &ExportDataV2, container_id: &str, options: &ImportOptions) -> Result<()> {
+        // Binaries in the layer only run on the architecture they were built for
+        let docker_info = with_spinner(DOCKER_QUERY_MESSAGE, || self.docker_client.get_docker_info())
+            .context("Failed to get Docker info")?;
+        if !same_architecture(&export_data.environment.architecture, &docker_info.architecture) {
+            if !options.skip_arch {
+                return Err(ArchitectureMismatch {
+                    export_architecture: export_data.environment.architecture.clone(),
+                    host_architecture: docker_info.architecture,
+                }.into());
+            }
+            print_diagnostic(Diagnostic::new(DiagnosticCode::ArchitectureMismatchSkipped, format!(
+                "Importing a '{}' export onto a '{}' daemon (--skip-arch specified)",
+                export_data.environment.architecture, docker_info.architecture
+            ))
+            .with_context("export", &export_data.environment.architecture)
+            .with_context("target", &docker_info.architecture));
+        } else {
+            print_check_result("Architecture", &format!("✓ Compatible: {}", docker_info.architecture), true);
+        }
+
+        // A layer only makes sense on top of the image it was taken from
+        let target_metadata = with_spinner(DOCKER_QUERY_MESSAGE, || self.docker_client.get_container_metadata(container_id))
+            .context("Failed to get target container metadata")?;
+        if let Some(mismatch) = image_mismatch(export_data, &target_metadata) {
+            if !options.allow_image_mismatch {
+                return Err(anyhow::anyhow!(
+                    "{}. Importing a layer onto a different image usually breaks the container; pass --allow-image-mismatch to import anyway",
+                    mismatch
+                ));
+            }
+            print_diagnostic(Diagnostic::new(DiagnosticCode::ImageMismatchAllowed, format!("{} (--allow-image-mismatch specified)", mismatch))
+                .with_context("export", &export_data.container_metadata.image_sha256)
+                .with_context("target", &target_metadata.image_sha256));
+        } else {
+            print_check_result("Image digest", &format!("✓ Matches {}", target_metadata.image_sha256), true);
+        }
 
         Ok(())
     }
-}
 
-impl Default for ImportCommand {
-    fn default() -> Self {
-        Self::new()
+    /// Shift to apply to the owners recorded in the export: the explicit --uid-offset/--gid-offset,
+    /// or with --auto-remap the difference between the target daemon's userns-remap range and the
+    /// one recorded at export time
+    fn import_id_offset(&self, export_data: &ExportDataV2, target: ImportTarget, options: &ImportOptions) -> Result<IdOffset> {
+        let export_offset = export_data.id_offset.unwrap_or_default();
+        if options.uid_offset.is_some() || options.gid_offset.is_some() {
+            let offset = IdOffset {
+                uid: options.uid_offset.unwrap_or(0),
+                gid: options.gid_offset.unwrap_or(0),
+            };
+            print_info(&format!("Shifting imported ownership by {}", offset));
+            return Ok(offset);
+        }
+        if options.chown.is_some() {
+            // Recorded owners are replaced, so the export's id mapping does not matter
+            return Ok(IdOffset::default());
+        }
+
+        let ImportTarget::Container(_) = target else {
+            if !export_offset.is_zero() {
+                print_diagnostic(Diagnostic::new(DiagnosticCode::UsernsRemapMismatch, format!(
+                    "Export was taken on a userns-remap daemon; its owners are shifted by {}. Pass --uid-offset/--gid-offset to undo it",
+                    export_offset
+                )).with_context("export_offset", export_offset));
+            }
+            return Ok(IdOffset::default());
+        };
+
+        let docker_info = with_spinner(DOCKER_QUERY_MESSAGE, || self.docker_client.get_docker_info())
+            .context("Failed to get Docker info")?;
+        let target_offset = userns_remap_offset(&docker_info)?.unwrap_or_default();
+        let offset = target_offset.relative_to(export_offset);
+        if offset.is_zero() {
+            if options.auto_remap {
+                print_check_result("User namespace", "✓ Export and target use the same id mapping", true);
+            }
+            return Ok(offset);
+        }
+
+        if options.auto_remap {
+            print_check_result("User namespace", &format!("✓ Remapping imported ownership by {}", offset), true);
+            Ok(offset)
+        } else {
+            print_diagnostic(Diagnostic::new(DiagnosticCode::UsernsRemapMismatch, format!(
+                "Export and target daemon use different userns-remap id ranges (shift {}); pass --auto-remap to remap imported ownership",
+                offset
+            ))
+            .with_context("export_offset", export_offset)
+            .with_context("target_offset", target_offset));
+            Ok(IdOffset::default())
+        }
+    }
+
+    /// Validate the export against the target container without modifying it: input checks, the
+    /// layer checksum recomputed in the workspace, and compatibility with the target's daemon,
+    /// image, and (for incremental exports) current layer
+    fn verify_only(&self, input_path: &str, container_id: &str, options: &ImportOptions) -> Result<()> {
+        print_progress(&format!("Verifying export for import into container: {}", container_id));
+
+        let temp_dir = Workspace::new()
+            .context("Failed to create temporary directory")?;
+        let temp_path = temp_dir.path();
+        let input_file_path = self.prepare_input(input_path, temp_path, options)?;
+
+        print_progress("Validating target container state...");
+        with_spinner(DOCKER_QUERY_MESSAGE, || self.docker_client.validate_container_for_layer_operations(container_id))
+            .context("Target container validation failed")?;
+
+        let workspace = temp_path.join("verify");
+        std::fs::create_dir_all(&workspace)
+            .context("Failed to create verification directory")?;
+        let verified = verify_export_file(&input_file_path, &workspace, true, 0, &self.cancel)?;
+        let export_data = &verified.export_data;
+        print_check_result("Layer checksum", "✓ Verified", true);
+
+        let mut diagnostics = Vec::new();
+
+        if options.mode == ImportMode::Merge && export_data.incremental.is_some() {
+            diagnostics.push(Diagnostic::new(
+                DiagnosticCode::IncrementalMerge,
+                "--mode merge and --only cannot be used with an incremental export",
+            ));
+        }
+        if export_data.layer_kind == LayerKind::Full && !options.as_upper && options.only.is_empty() {
+            diagnostics.push(Diagnostic::new(
+                DiagnosticCode::FullRootfsNeedsAsUpper,
+                "Export contains a full root filesystem; importing it requires --as-upper",
+            ));
+        }
+
+        // Compare against the daemon and image the target container actually runs on
+        print_progress("Comparing export with target container...");
+        let target_metadata = with_spinner(DOCKER_QUERY_MESSAGE, || self.docker_client.get_container_metadata(container_id))
+            .context("Failed to get target container metadata")?;
+        let docker_info = with_spinner(DOCKER_QUERY_MESSAGE, || self.docker_client.get_docker_info())
+            .context("Failed to get Docker info")?;
+
+        if is_running_state(&target_metadata.state) {
+            diagnostics.push(if options.force {
+                Diagnostic::new(
+                    DiagnosticCode::TargetRunningForced,
+                    "Target container is running; restart it after the import (--force specified)",
+                )
+            } else {
+                Diagnostic::new(DiagnosticCode::TargetRunning, "Target container is running; stop it first or pass --force")
+            }.with_context("container", container_id).with_context("state", &target_metadata.state));
+        }
+
+        if options.skip_arch {
+            print_check_result("Architecture check", "⏭ Skipped", false);
+        } else if !same_architecture(&export_data.environment.architecture, &docker_info.architecture) {
+            diagnostics.push(Diagnostic::new(DiagnosticCode::ArchitectureMismatch, format!(
+                "Architecture mismatch: export from '{}', target runs on '{}'",
+                export_data.environment.architecture,
+                docker_info.architecture
+            ))
+            .with_context("export", &export_data.environment.architecture)
+            .with_context("target", &docker_info.architecture));
+        } else {
+            print_check_result("Architecture", &format!("✓ Compatible: {}", docker_info.architecture), true);
+        }
+
+        if export_data.environment.driver != docker_info.driver {
+            diagnostics.push(Diagnostic::new(DiagnosticCode::StorageDriverMismatch, format!(
+                "Storage driver mismatch: export uses '{}', target uses '{}'",
+                export_data.environment.driver,
+                docker_info.driver
+            ))
+            .with_context("export", &export_data.environment.driver)
+            .with_context("target", &docker_info.driver));
+        } else {
+            print_check_result("Storage driver", &format!("✓ Compatible: {}", docker_info.driver), true);
+        }
+
+        if let Some(mismatch) = image_mismatch(export_data, &target_metadata) {
+            diagnostics.push(if options.allow_image_mismatch {
+                Diagnostic::new(DiagnosticCode::ImageMismatchAllowed, format!("{} (--allow-image-mismatch specified)", mismatch))
+            } else {
+                Diagnostic::new(DiagnosticCode::ImageMismatch, mismatch)
+            }
+            .with_context("export", &export_data.container_metadata.image_sha256)
+            .with_context("target", &target_metadata.image_sha256));
+        } else {
+            print_check_result("Image digest", &format!("✓ Matches {}", target_metadata.image_sha256), true);
+        }
+
+        if let Some(incremental) = &export_data.incremental {
+            let target_upper_path = self.docker_client.get_upper_layer_path(container_id)
+                .context("Failed to get target container layer path")?;
+            let id_offset = self.import_id_offset(export_data, ImportTarget::Container(container_id), options)?;
+            let current_checksum = current_parent_checksum(&target_upper_path, incremental, id_offset)?;
+            if current_checksum.as_ref() == Some(&incremental.parent_layer_checksum) {
+                print_check_result("Incremental parent", "✓ Target layer matches", true);
+            } else {
+                diagnostics.push(if options.force_incremental {
+                    Diagnostic::new(
+                        DiagnosticCode::IncrementalParentForced,
+                        "Target layer does not match the incremental export's parent (--force-incremental specified)",
+                    )
+                } else {
+                    Diagnostic::new(DiagnosticCode::IncrementalParentMismatch, "Target layer does not match the parent of this incremental export")
+                }.with_context("expected_parent", &incremental.parent_layer_checksum));
+            }
+        }
+
+        if options.no_space_check {
+            print_check_result("Free space check", "⏭ Skipped", false);
+        } else {
+            let target_upper_path = self.docker_client.get_upper_layer_path(container_id)
+                .context("Failed to get target container layer path")?;
+            let layer = self.layer_disk_usage(&input_file_path, verified.manifest.as_ref())?;
+            let shortfalls = self.space_shortfalls(
+                layer, ImportTarget::Container(container_id), &target_upper_path, export_data.incremental.is_some(), options
+            )?;
+            if shortfalls.is_empty() {
+                print_check_result("Free space", "✓ Sufficient for the layer and backup", true);
+            }
+            diagnostics.extend(shortfalls.into_iter().map(|shortfall| {
+                Diagnostic::new(DiagnosticCode::InsufficientSpace, format!("Not enough free space: {}", shortfall))
+            }));
+        }
+
+        print_diagnostics_sections(&diagnostics);
+        let count = |severity: Severity| diagnostics.iter().filter(|diagnostic| diagnostic.severity == severity).count();
+        let (errors, warnings) = (count(Severity::Error), count(Severity::Warning));
+
+        if errors > 0 {
+            return Err(VerifyFailure {
+                exit_code: VERIFY_ERRORS_EXIT_CODE,
+                message: format!("Verification failed with {} error(s); the container was not modified", errors),
+            }
+            .into());
+        }
+        if warnings > 0 {
+            return Err(VerifyFailure {
+                exit_code: VERIFY_WARNINGS_EXIT_CODE,
+                message: format!("Verification passed with {} warning(s); the container was not modified", warnings),
+            }
+            .into());
+        }
+
+        print_success("Verification passed; the container was not modified");
+        Ok(())
+    }
+
+    /// Resolve the export to read inside the workspace: download S3 objects, check the signature,
+    /// reassemble split exports, and decrypt encrypted ones
+    fn prepare_input(&self, input_path: &str, temp_path: &Path, options: &ImportOptions) -> Result<PathBuf> {
+        // Objects in S3 are downloaded into the workspace and imported from there
+        let downloaded_path = temp_path.join("downloaded");
+        let input_file_path = if is_s3_url(input_path) {
+            s3::ensure_available()?;
+            if options.verify_signature {
+                return Err(anyhow::anyhow!(
+                    "--verify-signature is not supported for S3 inputs; download the export and its .sig first"
+                ));
+            }
+            self.download_from_s3(input_path, &downloaded_path)?;
+            downloaded_path
+        } else if is_http_url(input_path) {
+            http::ensure_available()?;
+            if options.verify_signature {
+                return Err(anyhow::anyhow!(
+                    "--verify-signature is not supported for HTTP(S) inputs; download the export and its .sig first"
+                ));
+            }
+            print_progress(&format!("Downloading export from {}...", input_path));
+            let bytes = http::download_to_file(input_path, &options.headers, &downloaded_path, &self.cancel)?;
+            print_labeled_value("Downloaded", &format_file_size(bytes));
+            downloaded_path
+        } else if input_path == STDIN_PATH {
+            if options.verify_signature {
+                return Err(anyhow::anyhow!(
+                    "--verify-signature is not supported when reading from stdin; import the export file and its .sig instead"
+                ));
+            }
+            print_progress("Reading export from stdin...");
+            let spooled_path = temp_path.join("stdin");
+            let bytes = spool_stdin(&spooled_path, &self.cancel)?;
+            print_labeled_value("Received", &format_file_size(bytes));
+            spooled_path
+        } else {
+            let input_file_path = Path::new(input_path);
+            if !input_file_path.exists() {
+                return Err(anyhow::anyhow!("Input file not found: {}", input_path));
+            }
+            reject_partial_file(input_file_path)?;
+            if options.verify_signature {
+                verify_export_signature(input_file_path, options.pubkey.as_deref())
+                    .context("Signature verification failed")?;
+            }
+            input_file_path.to_path_buf()
+        };
+
+        // The bytes as received must be the ones that were published before anything reads them
+        if let Some(expected) = &options.sha256 {
+            print_progress("Verifying input checksum (--sha256)...");
+            let checksum = verify_file_sha256(&input_file_path, expected)?;
+            print_checksum("Input checksum verified", &checksum);
+        }
+
+        // Reassemble split exports from their chunks before anything else reads the file
+        let assembled_path = temp_path.join("assembled");
+        let input_file_path = match find_chunk_index(&input_file_path) {
+            Some(index_path) => {
+                print_progress(&format!("Reassembling split export from {}...", index_path.display()));
+                let index = assemble_chunks(&index_path, &assembled_path)
+                    .context("Failed to reassemble split export")?;
+                print_labeled_value("Chunks verified", &index.chunk_count.to_string());
+                assembled_path
+            }
+            None => input_file_path,
+        };
+
+        // Decrypt age-encrypted exports with the supplied identity
+        let decrypted_path = temp_path.join("decrypted");
+        let input_file_path = decrypt_if_needed(&input_file_path, &decrypted_path, options.identity.as_deref().map(Path::new))?;
+        Ok(input_file_path.to_path_buf())
+    }
+
+    /// Extract the layer into the target upper directory, applying its whiteouts, and verify the result
+    fn apply_layer(
+        &self,
+        prepared: &PreparedExport,
+        target_upper_path: &Path,
+        mut journal: Option<&mut ImportJournal>,
+        options: &ImportOptions,
+        timer: &mut PhaseTimer,
+    ) -> Result<AppliedLayer> {
+        let export_data = &prepared.export_data;
+        let manifest = prepared.manifest.as_ref();
+        let export_path = prepared.export_path.as_path();
+        let excludes = import_excludes(options)?;
+        let excludes = excludes.as_ref();
+
+        timer.start("layer extraction");
+        timer.add_bytes(get_file_size(export_path)?);
+        if let Some(journal) = journal.as_mut() {
+            journal.set_phase("layer extraction")?;
+        }
+
+        // Create target directory
+        std::fs::create_dir_all(target_upper_path)
+            .context("Failed to create target upper layer directory")?;
+
+        // Extract layer data to target location
+        if let Some(incremental) = &export_data.incremental {
+            // Apply changed entries on top of the existing layer, then remove deleted paths
+            print_progress("Applying incremental layer data to container...");
+            let (excluded, whiteouts) = self.extract_layer(export_path, target_upper_path, journal, options)?;
+            let deleted_paths: Vec<String> = incremental.deleted_paths.iter()
+                .filter(|path| !excludes.is_some_and(|excludes| excludes.is_excluded(path)))
+                .cloned()
+                .collect();
+            let removed = remove_layer_paths(target_upper_path, &deleted_paths)
+                .context("Failed to remove deleted paths from target layer")?;
+            print_labeled_value("Deleted paths removed", &removed.to_string());
+            let applied = |checksum| AppliedLayer {
+                checksum,
+                excluded: excluded.clone(),
+                deleted: removed + whiteouts.deleted.len(),
+                whiteouts: whiteouts.count,
+                merge_stats: None,
+            };
+
+            // The manifest describes the whole resulting layer, unchanged entries included
+            if let Some(manifest) = manifest {
+                timer.start("checksum");
+                print_progress("Verifying layer against the manifest...");
+                let progress = byte_progress_bar("Verifying");
+                progress.set_length(manifest.entries.values().map(|entry| entry.size).sum());
+                let mismatches = verify_layer_against_manifest(target_upper_path, manifest, &|path| {
+                    !excludes.is_some_and(|excludes| excludes.is_excluded(path))
+                }, attribute_restorer(options).verified_attributes(), &progress)
+                    .context("Failed to verify imported layer")?;
+                finish_progress(&progress);
+
+                if !mismatches.is_empty() {
+                    if !options.force_incremental {
+                        print_errors_section(&mismatches);
+                        return Err(manifest_mismatch_error("path(s)", &mismatches));
+                    }
+                    print_warning(&format!(
+                        "Resulting layer differs from the exported layer in {} path(s), as expected when forcing an incremental import onto a different base",
+                        mismatches.len()
+                    ));
+                    return Ok(applied(None));
+                }
+                if !excluded.is_empty() {
+                    print_check_result("Layer", "✓ Verified against the manifest (excluded paths skipped)", true);
+                    return Ok(applied(None));
+                }
+                return Ok(applied(Some(export_data.layer_checksum.clone())));
+            }
+
+            if !excluded.is_empty() {
+                print_warning(&format!(
+                    "Layer checksum not verified: {} path(s) were excluded (--exclude) and the export has no per-file manifest",
+                    excluded.len()
+                ));
+                return Ok(applied(None));
+            }
+
+            timer.start("checksum");
+            print_progress("Verifying layer integrity...");
+            let progress = byte_progress_bar("Verifying");
+            let calculated_checksum = calculate_directory_checksum_with_progress(
+                target_upper_path, ChecksumFormat::new(export_data.checksum_scheme, export_data.layer_checksum.algorithm), &progress
+            )
+                .context("Failed to calculate imported layer checksum")?;
+            finish_progress(&progress);
+
+            if calculated_checksum != export_data.layer_checksum {
+                if !options.force_incremental {
+                    return Err(layer_checksum_error(&export_data.layer_checksum, &calculated_checksum));
+                }
+                print_warning("Resulting layer differs from the exported layer, as expected when forcing an incremental import onto a different base");
+            }
+            Ok(applied(Some(calculated_checksum)))
+        } else if let Some(manifest) = manifest {
+            // Verify each entry against the manifest while it is written
+            print_progress("Extracting and verifying layer data...");
+            let mut attributes = attribute_restorer(options);
+            let mut state = ExtractionState::new(whiteout_mode(options), journal);
+            let progress = byte_progress_bar("Extracting");
+            let mismatches = with_layer_stream_progress(export_path, &self.cancel, &progress, |layer| {
+                extract_tar_stream_verified(layer, target_upper_path, manifest, excludes, &mut state, &mut attributes, &self.cancel)
+            })
+                .context("Failed to extract layer data to target container")?;
+            finish_progress(&progress);
+            warn_unrestored_attributes(&attributes.finish()?);
+            let whiteouts = state.finish();
+
+            if !mismatches.is_empty() {
+                print_errors_section(&mismatches);
+                return Err(manifest_mismatch_error("path(s)", &mismatches));
+            }
+
+            let excluded: Vec<String> = manifest.entries.keys()
+                .filter(|path| excludes.is_some_and(|excludes| excludes.is_excluded(path)))
+                .cloned()
+                .collect();
+            let checksum = if excluded.is_empty() {
+                Some(export_data.layer_checksum.clone())
+            } else {
+                print_check_result("Imported paths", "✓ Verified against the manifest (excluded paths skipped)", true);
+                None
+            };
+            Ok(AppliedLayer {
+                checksum,
+                excluded,
+                deleted: whiteouts.deleted.len(),
+                whiteouts: whiteouts.count,
+                merge_stats: None,
+            })
+        } else {
+            print_progress("Extracting layer data to container...");
+            let (excluded, whiteouts) = self.extract_layer(export_path, target_upper_path, journal, options)?;
+            let applied = |checksum| AppliedLayer {
+                checksum,
+                excluded: excluded.clone(),
+                deleted: whiteouts.deleted.len(),
+                whiteouts: whiteouts.count,
+                merge_stats: None,
+            };
+
+            if !excluded.is_empty() {
+                print_warning(&format!(
+                    "Layer checksum not verified: {} path(s) were excluded (--exclude) and the export has no per-file manifest",
+                    excluded.len()
+                ));
+                return Ok(applied(None));
+            }
+
+            // Verify checksum
+            timer.start("checksum");
+            print_progress("Verifying layer integrity...");
+            let progress = byte_progress_bar("Verifying");
+            let calculated_checksum = calculate_directory_checksum_with_progress(
+                target_upper_path, ChecksumFormat::new(export_data.checksum_scheme, export_data.layer_checksum.algorithm), &progress
+            )
+                .context("Failed to calculate imported layer checksum")?;
+            finish_progress(&progress);
+
+            if calculated_checksum != export_data.layer_checksum {
+                return Err(layer_checksum_error(&export_data.layer_checksum, &calculated_checksum));
+            }
+            Ok(applied(Some(calculated_checksum)))
+        }
+    }
+
+    /// Stream the export's layer archive into the target, leaving out excluded entries, applying
+    /// whiteouts, and recording progress in `journal`; returns the excluded paths and the whiteouts applied
+    fn extract_layer(
+        &self,
+        export_path: &Path,
+        target_upper_path: &Path,
+        journal: Option<&mut ImportJournal>,
+        options: &ImportOptions,
+    ) -> Result<(Vec<String>, AppliedWhiteouts)> {
+        let excludes = import_excludes(options)?;
+        let mut attributes = attribute_restorer(options);
+        let mut state = ExtractionState::new(whiteout_mode(options), journal);
+        let progress = byte_progress_bar("Extracting");
+        let excluded = with_layer_stream_progress(export_path, &self.cancel, &progress, |layer| {
+            extract_layer_stream(layer, target_upper_path, excludes.as_ref(), &mut state, &mut attributes, &self.cancel)
+        })
+        .context("Failed to extract layer data to target container")?;
+        finish_progress(&progress);
+        warn_unrestored_attributes(&attributes.finish()?);
+        Ok((excluded, state.finish()))
+    }
+
+    /// Extract the layer over the existing upper directory and verify the imported paths against the
+    /// manifest; a whole-directory checksum cannot match once pre-existing files are kept.
+    /// With `only`, entries outside the selected paths are left alone.
+    fn merge_layer(
+        &self,
+        manifest: Option<&Manifest>,
+        export_path: &Path,
+        target_upper_path: &Path,
+        options: &ImportOptions,
+        timer: &mut PhaseTimer,
+    ) -> Result<MergeStats> {
+        let excludes = import_excludes(options)?;
+        let excludes = excludes.as_ref();
+        let only = import_only(options)?;
+        let only = only.as_ref();
+
+        timer.start("layer extraction");
+        timer.add_bytes(get_file_size(export_path)?);
+
+        std::fs::create_dir_all(target_upper_path)
+            .context("Failed to create target upper layer directory")?;
+
+        print_progress("Merging layer data into container...");
+        let mut attributes = attribute_restorer(options);
+        let verified = attributes.verified_attributes();
+        let progress = byte_progress_bar("Merging");
+        let stats = with_layer_stream_progress(export_path, &self.cancel, &progress, |layer| {
+            merge_tar_stream(layer, target_upper_path, excludes, only, whiteout_mode(options), &mut attributes, &self.cancel)
+        })
+            .context("Failed to merge layer data into target container")?;
+        finish_progress(&progress);
+        warn_unrestored_attributes(&attributes.finish()?);
+
+        if let Some(only) = only {
+            let matched = [stats.imported.as_slice(), &stats.deleted, &stats.excluded].concat();
+            for prefix in only.unmatched(&matched) {
+                print_warning(&format!("--only {} matched nothing in the export", prefix));
+            }
+        }
+
+        timer.start("checksum");
+        match manifest {
+            Some(manifest) => {
+                print_progress("Verifying imported paths against the manifest...");
+                let mismatches = verify_paths_against_manifest(target_upper_path, manifest, &stats.imported, &|path| {
+                    only.is_none_or(|only| only.is_selected(path)) && !excludes.is_some_and(|excludes| excludes.is_excluded(path))
+                }, verified)
+                    .context("Failed to verify merged layer")?;
+                if !mismatches.is_empty() {
+                    print_errors_section(&mismatches);
+                    return Err(manifest_mismatch_error("imported path(s)", &mismatches));
+                }
+                print_check_result("Imported paths", &format!("✓ {} verified against the manifest", stats.imported.len()), true);
+            }
+            None => print_warning("Export has no per-file manifest; merged files were not verified"),
+        }
+
+        Ok(stats)
+    }
+
+    /// Extract the included mounts into `workspace` and copy each back to its source path after
+    /// verifying its checksum, asking for confirmation per mount unless `yes` is set
+    fn restore_mounts(&self, export_data: &ExportDataV2, export_path: &Path, workspace: &Path, yes: bool) -> Result<()> {
+        if export_data.mounts.is_empty() {
+            print_warning("Export contains no mounts (it was not created with --include-mounts)");
+            return Ok(());
+        }
+
+        print_progress("Extracting included mounts...");
+        let extract_dir = workspace.join("extracted");
+        extract_export_mounts(export_path, &extract_dir, &self.cancel)
+            .context("Failed to extract mounts from export archive")?;
+        let extract_dir = extract_dir.as_path();
+
+        print_progress("Verifying included mounts...");
+        verify_mount_checksums(extract_dir, export_data)
+            .context("Mount verification failed")?;
+
+        for mount in &export_data.mounts {
+            self.cancel.check()?;
+            let question = format!("Restore mount {} to {}? Existing files there will be overwritten.", mount.destination, mount.source);
+            if !yes && !confirm(&question) {
+                print_warning(&format!("Skipped restoring mount {} (confirm interactively or pass --yes)", mount.destination));
+                continue;
+            }
+
+            print_progress(&format!("Restoring mount {} to {}...", mount.destination, mount.source));
+            let source_path = Path::new(&mount.source);
+            std::fs::create_dir_all(source_path)
+                .with_context(|| format!("Failed to create mount source directory: {:?}", source_path))?;
+            copy_directory(mount_dir(extract_dir, mount.index).as_path(), source_path)
+                .with_context(|| format!("Failed to restore mount {}", mount.destination))?;
+            print_check_result(&format!("Mount {}", mount.destination), &format!("✓ Restored to {}", mount.source), true);
+        }
+
+        Ok(())
+    }
+
+    /// Undo a failed import by putting the backed-up layer back and verifying it. The returned
+    /// error keeps `error` as its cause and says whether the previous layer was restored.
+    pub(crate) fn roll_back(
+        &self,
+        error: anyhow::Error,
+        target: ImportTarget,
+        target_upper_path: &Path,
+        layer_backup: Option<&LayerBackup>,
+        options: &ImportOptions,
+    ) -> (anyhow::Error, TargetLayerState) {
+        let Some(layer_backup) = layer_backup else {
+            let error = match target {
+                ImportTarget::Container(_) if options.mode == ImportMode::Merge => error.context(
+                    "Import failed; --mode merge modifies the layer in place without a backup, so no rollback was possible and the layer may be partially merged"
+                ),
+                ImportTarget::Container(_) if !options.backup => {
+                    print_error("NO ROLLBACK POSSIBLE: the existing layer was not backed up (--no-backup)");
+                    error.context(
+                        "Import failed and no rollback was possible (--no-backup); the container's layer is left partially imported"
+                    )
+                }
+                _ => error,
+            };
+            return (error, TargetLayerState::PartiallyImported);
+        };
+
+        print_warning(&format!("Import failed; restoring the previous layer from {:?}", layer_backup.path()));
+        let restored = layer_backup.verify_archive()
+            .and_then(|_| layer_backup.restore_verified(target_upper_path));
+        match restored {
+            Ok(check) => {
+                report_backup_check("Rollback", &check);
+                (error.context("Import failed, previous layer restored"), TargetLayerState::RolledBack)
+            }
+            Err(rollback_error) => (error.context(format!(
+                "Import failed and restoring the previous layer from {:?} also failed: {:#}",
+                layer_backup.path(), rollback_error
+            )), TargetLayerState::Unknown),
+        }
+    }
+
+    /// Stream a local export file to `layer-tool import -` running on a remote host over ssh
+    fn execute_remote(&self, input_path: &str, container_id: &str, destination: &str, options: &ImportOptions) -> Result<()> {
+        // The journal of an interrupted import lives on the remote host, next to an input read from stdin
+        if options.resume {
+            return Err(anyhow::anyhow!("--resume cannot be used with --ssh; run `layer-tool import --resume` on the remote host"));
+        }
+        if input_path == STDIN_PATH {
+            return Err(anyhow::anyhow!("--ssh streams a local export file; it cannot forward stdin"));
+        }
+        let input_file_path = Path::new(input_path);
+        if !input_file_path.exists() {
+            return Err(anyhow::anyhow!("Input file not found: {}", input_path));
+        }
+        reject_partial_file(input_file_path)?;
+
+        if let Some(expected) = &options.sha256 {
+            print_progress("Verifying input checksum (--sha256)...");
+            let checksum = verify_file_sha256(input_file_path, expected)?;
+            print_checksum("Input checksum verified", &checksum);
+        }
+
+        // The signature covers the local file, so it is checked before anything is sent
+        if options.verify_signature {
+            verify_export_signature(input_file_path, options.pubkey.as_deref())
+                .context("Signature verification failed")?;
+        }
+
+        let mut remote_command = format!("layer-tool import - {}", shell_quote(container_id));
+        if !options.backup {
+            remote_command.push_str(" --no-backup");
+        }
+        if options.as_upper {
+            remote_command.push_str(" --as-upper");
+        }
+        if options.force_incremental {
+            remote_command.push_str(" --force-incremental");
+        }
+        if options.force {
+            remote_command.push_str(" --force");
+        }
+        if options.restore_mounts {
+            remote_command.push_str(" --restore-mounts");
+        }
+        if let Some(post_check) = &options.post_check {
+            remote_command.push_str(&format!(
+                " --post-check {} --post-check-timeout {}", shell_quote(post_check), options.post_check_timeout
+            ));
+        }
+        // The remote side reads the export from stdin and cannot ask, so confirm here; a merge
+        // with --max-overwrites is left to the remote side, which refuses one beyond the limit
+        if let Some(max_overwrites) = options.max_overwrites {
+            remote_command.push_str(&format!(" --max-overwrites {}", max_overwrites));
+        }
+        if !options.yes && !options.verify_only && !confirms_by_overwrites(options) {
+            print_section_header("Confirm Import");
+            print_labeled_value("Target container", &format!("{} on {}", container_id, destination));
+            print_labeled_value("Backup", &backup_plan(false, options));
+            if !confirm_typed(&format!("Type the container ({}) to replace its layer:", container_id), container_id) {
+                return Err(anyhow::anyhow!("Import cancelled; the target container was not modified"));
+            }
+        }
+        if !confirms_by_overwrites(options) || options.yes {
+            remote_command.push_str(" --yes");
+        }
+        if let Some(wait) = options.wait {
+            remote_command.push_str(&format!(" --wait {}", wait));
+        }
+        if options.verify_only {
+            remote_command.push_str(" --verify-only");
+        }
+        if options.skip_precheck {
+            remote_command.push_str(" --skip-precheck");
+        }
+        if options.no_space_check {
+            remote_command.push_str(" --no-space-check");
+        }
+        if options.allow_image_mismatch {
+            remote_command.push_str(" --allow-image-mismatch");
+        }
+        if options.skip_arch {
+            remote_command.push_str(" --skip-arch");
+        }
+        for pattern in &options.excludes {
+            remote_command.push_str(&format!(" --exclude {}", shell_quote(pattern)));
+        }
+        for path in &options.only {
+            remote_command.push_str(&format!(" --only {}", shell_quote(path)));
+        }
+        if options.mode != ImportMode::Replace {
+            remote_command.push_str(&format!(" --mode {}", options.mode));
+        }
+        if let Some(uid_offset) = options.uid_offset {
+            remote_command.push_str(&format!(" --uid-offset {}", uid_offset));
+        }
+        if let Some(gid_offset) = options.gid_offset {
+            remote_command.push_str(&format!(" --gid-offset {}", gid_offset));
+        }
+        if options.auto_remap {
+            remote_command.push_str(" --auto-remap");
+        }
+        if let Some(owner) = options.chown {
+            remote_command.push_str(&format!(" --chown {}", owner));
+        }
+        if let Some(backup_dir) = &options.backup_dir {
+            remote_command.push_str(&format!(" --backup-dir {}", shell_quote(backup_dir)));
+        }
+        if options.keep_backups != DEFAULT_KEEP_BACKUPS {
+            remote_command.push_str(&format!(" --keep-backups {}", options.keep_backups));
+        }
+        match verbosity() {
+            Verbosity::Quiet => remote_command.push_str(" --quiet"),
+            Verbosity::Normal => {}
+            Verbosity::Verbose => remote_command.push_str(" -v"),
+            Verbosity::Trace => remote_command.push_str(" -vv"),
+        }
+        if symbols() == SymbolStyle::Ascii {
+            remote_command.push_str(" --ascii");
+        }
+        if full_values() {
+            remote_command.push_str(" --full");
+        }
+
+        // The remote side has no identity, so encrypted exports are decrypted here and sent over the ssh channel
+        let workspace = Workspace::new()
+            .context("Failed to create temporary directory")?;
+        let decrypted_path = workspace.path().join("decrypted");
+        let input_file_path = decrypt_if_needed(input_file_path, &decrypted_path, options.identity.as_deref().map(Path::new))?;
+
+        let file_size = get_file_size(input_file_path)?;
+        print_file_info("Input file", input_path, &format_file_size(file_size));
+        print_progress(&format!("Streaming export to {} for import into container {}...", destination, container_id));
+
+        let started = Instant::now();
+        let bytes_sent = pipe_to_remote(destination, &remote_command, |pipe| {
+            let input_file = File::open(input_file_path)
+                .with_context(|| format!("Failed to open input file: {:?}", input_file_path))?;
+            std::io::copy(&mut CancellableReader::new(input_file, &self.cancel), pipe)
+                .context("Failed to send export file")
+        })
+        .context("Remote import failed")?;
+        let elapsed = started.elapsed().as_secs_f64().max(0.001);
+
+        print_success("Remote import completed successfully!");
+        print_labeled_value("Remote host", destination);
+        print_labeled_value("Target container", container_id);
+        print_labeled_value("Transfer rate", &format!("{}/s", format_file_size((bytes_sent as f64 / elapsed) as u64)));
+        Ok(())
+    }
+
+    /// Download an export object from S3 into the temporary workspace
+    #[cfg(feature = "s3")]
+    fn download_from_s3(&self, url: &str, output_path: &Path) -> Result<()> {
+        print_progress(&format!("Downloading export from {}...", url));
+        let bytes = s3::download_to_file(url, output_path)?;
+        print_labeled_value("Downloaded", &format_file_size(bytes));
+        Ok(())
+    }
+
+    #[cfg(not(feature = "s3"))]
+    fn download_from_s3(&self, _url: &str, _output_path: &Path) -> Result<()> {
+        s3::ensure_available()
+    }
+
+    /// Display summary of imported data
+    fn display_import_summary(
+        export_data: &ExportDataV2,
+        target_state: Option<&str>,
+        applied: &AppliedLayer,
+        excludes: Option<&ExcludeSet>,
+        options: &ImportOptions,
+        backup_rotation: Option<&BackupRotation>,
+    ) -> Result<()> {
+        print_section_header("Import Summary");
+        let mut summary = KeyValues::new();
+        summary.value("Export version", &export_data.version.to_string());
+        summary.value("Layer kind", &export_data.layer_kind.to_string());
+        if let Some(state) = target_state {
+            summary.value("Target state", state);
+        }
+        if let Some(rotation) = backup_rotation {
+            summary.value("Backup", &rotation.created.path().display().to_string());
+            if !rotation.pruned.is_empty() {
+                summary.value("Pruned backups", &format!("{} (--keep-backups {})", rotation.pruned.len(), options.keep_backups));
+                for backup in &rotation.pruned {
+                    summary.item(&backup.path().display().to_string());
+                }
+            }
+        }
+        if let Some(owner) = options.chown {
+            let group = if owner.gid.is_some() { "" } else { " (groups as recorded)" };
+            summary.value("Ownership", &format!("overridden to {}{} by --chown; recorded owners were not kept", owner, group));
+        }
+        if applied.whiteouts > 0 {
+            summary.value("Whiteouts applied", &applied.whiteouts.to_string());
+        }
+        if let Some(stats) = &applied.merge_stats {
+            summary.value("Mode", "merge");
+            summary.value("Files added", &stats.added.to_string());
+            summary.value("Files overwritten", &stats.overwritten.to_string());
+            summary.value("Files deleted", &stats.deleted.len().to_string());
+            if !options.only.is_empty() {
+                summary.value("Restored paths", &stats.imported.len().to_string());
+                for path in &stats.imported {
+                    summary.item(path);
+                }
+                for path in &stats.deleted {
+                    summary.item(&format!("{} (deleted)", path));
+                }
+            }
+        }
+        if let Some(excludes) = excludes {
+            summary.value("Excluded paths", &applied.excluded.len().to_string());
+            // List where each excluded subtree starts rather than every entry below it
+            for path in &applied.excluded {
+                let parent_excluded = Path::new(path).parent()
+                    .is_some_and(|parent| excludes.is_excluded(&parent.to_string_lossy()));
+                if !parent_excluded {
+                    summary.item(path);
+                }
+            }
+        }
+        if let Some(incremental) = &export_data.incremental {
+            summary.value("Incremental parent", &incremental.parent_layer_checksum.to_string());
+        }
+        summary.value("Export created", &export_data.created.format("%Y-%m-%d %H:%M:%S UTC").to_string());
+        summary.print();
+        if target_state.is_some_and(is_running_state) {
+            print_warning("The target container was running during the import; restart it for the changes to be fully visible");
+        }
+        print_annotations(&export_data.annotations);
+        print_transient_entries(&export_data.transient_entries);
+
+        print_info("Source container:");
+        let mut container = KeyValues::indented();
+        container.value("ID", &export_data.container_metadata.id);
+        container.value("Name", &export_data.container_metadata.name);
+        container.value("Image", &export_data.container_metadata.image);
+        container.value("Image SHA256", &export_data.container_metadata.image_sha256);
+        container.value("Created", &export_data.container_metadata.created.format("%Y-%m-%d %H:%M:%S UTC").to_string());
+        container.value("State", &export_data.container_metadata.state);
+        if !export_data.container_metadata.labels.is_empty() {
+            container.value("Labels", "");
+            for (key, value) in &export_data.container_metadata.labels {
+                container.entry(key, value);
+            }
+        }
+        if !export_data.container_metadata.mounts.is_empty() {
+            container.value("Mounts", &format!("{} mount(s)", export_data.container_metadata.mounts.len()));
+        }
+        container.print();
+
+        print_info("Docker environment:");
+        KeyValues::indented()
+            .value("Storage driver", &export_data.environment.driver)
+            .value("Operating system", &export_data.environment.os)
+            .value("Architecture", &export_data.environment.architecture)
+            .value("Docker version", &export_data.environment.server_version)
+            .print();
+
+        Ok(())
+    }
+}
+
+impl Default for ImportCommand {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Describe how `needed` exceeds `free` on the filesystem holding `path`, if it does
+fn space_shortfall(path: &Path, needed: DiskUsage, free: DiskUsage) -> Option<String> {
+    if needed.bytes <= free.bytes && needed.inodes <= free.inodes {
+        return None;
+    }
+    let free_inodes = if free.inodes == u64::MAX { "unlimited".to_string() } else { free.inodes.to_string() };
+    Some(format!(
+        "Filesystem holding {:?}: needs {} and {} inodes, has {} and {} inodes free",
+        path, format_file_size(needed.bytes), needed.inodes, format_file_size(free.bytes), free_inodes
+    ))
+}
+
+/// Whether two paths (or their nearest existing ancestors) live on the same filesystem
+fn same_filesystem(first: &Path, second: &Path) -> bool {
+    let device = |path: &Path| path.ancestors()
+        .find_map(|ancestor| std::fs::metadata(ancestor).ok())
+        .map(|metadata| metadata.dev());
+    device(first).is_some() && device(first) == device(second)
+}
+
+/// Whether the import writes a JSON report, to a file or stdout
+fn wants_report(options: &ImportOptions) -> bool {
+    options.report.is_some() || options.output == OutputFormat::Json
+}
+
+/// Report of an import that has not got anywhere yet
+fn new_import_report(input_path: &str, target: ImportTarget, options: &ImportOptions) -> ImportReport {
+    ImportReport {
+        report_version: IMPORT_REPORT_VERSION,
+        status: ImportStatus::Failed,
+        target_layer: TargetLayerState::Unchanged,
+        target: match target {
+            ImportTarget::Container(container_id) => container_id.to_string(),
+            ImportTarget::Directory(target_dir) => target_dir.display().to_string(),
+        },
+        target_container_id: None,
+        input_path: input_path.to_string(),
+        mode: options.mode.to_string(),
+        source_container_id: None,
+        source_container_name: None,
+        image: None,
+        incremental: false,
+        backup_path: None,
+        pruned_backups: Vec::new(),
+        verified_checksum: None,
+        files_written: 0,
+        files_deleted: 0,
+        whiteouts_applied: 0,
+        excluded_paths: 0,
+        resumed: options.resume,
+        post_check: None,
+        collisions: None,
+        phases: Vec::new(),
+        duration_secs: 0.0,
+        warnings: Vec::new(),
+        error: Vec::new(),
+        diagnostics: Vec::new(),
+        metrics: CommandMetrics::default(),
+    }
+}
+
+/// Record the source the export was taken from
+fn describe_source(report: &mut ImportReport, prepared: &PreparedExport) {
+    let source = &prepared.export_data.container_metadata;
+    report.source_container_id = Some(source.id.clone());
+    report.source_container_name = Some(source.name.clone());
+    report.image = Some(source.image.clone());
+    report.incremental = prepared.export_data.incremental.is_some();
+}
+
+/// Fill in the outcome, timings, and warnings once the import has finished
+fn finish_import_report(report: &mut ImportReport, result: &Result<()>, timer: PhaseTimer) {
+    report.duration_secs = timer.elapsed_secs();
+    report.phases = timer.finish();
+    report.warnings = take_warnings();
+    report.diagnostics = take_diagnostics();
+    report.metrics = metrics::snapshot();
+    match result {
+        Ok(()) => report.status = ImportStatus::Succeeded,
+        Err(error) => {
+            report.status = ImportStatus::Failed;
+            report.error = error.chain().map(ToString::to_string).collect();
+        }
+    }
+}
+
+/// Write the report (or a batch's reports) to --report and, with --output json, to stdout
+fn write_import_report<T: serde::Serialize + ?Sized>(report: &T, options: &ImportOptions) -> Result<()> {
+    if let Some(report_path) = &options.report {
+        write_json_report(report_path, report)?;
+        print_labeled_value("Report", report_path);
+    }
+    if options.output == OutputFormat::Json {
+        let report_json = serde_json::to_string_pretty(report)
+            .context("Failed to serialize report")?;
+        print_result(&report_json);
+    }
+    Ok(())
+}
+
+/// Replacing a container's layer is confirmed interactively, so without a terminal it needs --yes.
+/// A merge with --max-overwrites is checked once its collisions are known.
+fn check_can_confirm(options: &ImportOptions) -> Result<()> {
+    if options.yes || can_prompt() || confirms_by_overwrites(options) {
+        return Ok(());
+    }
+    Err(anyhow::anyhow!("Refusing to run destructive import non-interactively; pass --yes to confirm"))
+}
+
+/// --max-overwrites limits what a merge may change without confirmation, so it needs --mode merge
+fn check_max_overwrites(options: &ImportOptions) -> Result<()> {
+    if options.max_overwrites.is_some() && options.mode != ImportMode::Merge {
+        return Err(anyhow::anyhow!("--max-overwrites only applies to --mode merge (or --only)"));
+    }
+    Ok(())
+}
+
+/// Whether the import is only confirmed when its merge changes more than --max-overwrites entries
+fn confirms_by_overwrites(options: &ImportOptions) -> bool {
+    options.mode == ImportMode::Merge && options.max_overwrites.is_some()
+}
+
+/// Whether the import has to be confirmed before the layer is modified: always without --yes,
+/// except for a merge within --max-overwrites. A merge beyond it fails without a terminal.
+fn needs_confirmation(collisions: Option<&CollisionReport>, options: &ImportOptions) -> Result<bool> {
+    if options.yes {
+        return Ok(false);
+    }
+    let (Some(collisions), Some(max_overwrites)) = (collisions, options.max_overwrites) else {
+        return Ok(true);
+    };
+    let changed = collisions.changed_count();
+    if changed <= max_overwrites {
+        print_check_result("Overwrites", &format!("✓ {} within --max-overwrites {}", changed, max_overwrites), true);
+        return Ok(false);
+    }
+    print_check_result("Overwrites", &format!("✗ {} exceed --max-overwrites {}", changed, max_overwrites), false);
+    if !can_prompt() {
+        return Err(anyhow::anyhow!(
+            "Merge would overwrite or delete {} existing file(s), more than --max-overwrites {}; the target was not modified. \
+            Pass --yes to confirm",
+            changed, max_overwrites
+        ));
+    }
+    Ok(true)
+}
+
+/// Show the start of each list in a merge's collision report; the full lists go into --report
+fn print_collision_report(collisions: &CollisionReport) {
+    let format_mtime = |mtime: Option<DateTime<Utc>>| {
+        mtime.map(|mtime| mtime.format("%Y-%m-%d %H:%M:%S").to_string()).unwrap_or_else(|| "-".to_string())
+    };
+    let more = |shown: usize, total: usize| {
+        if total > shown {
+            print_list_item(&format!("... and {} more (full list with --report)", total - shown));
+        }
+    };
+
+    print_section_header("Merge Preview");
+    print_labeled_value("New files", &collisions.added.len().to_string());
+    print_labeled_value("Existing files overwritten", &collisions.overwritten.len().to_string());
+    if !collisions.overwritten.is_empty() {
+        let rows: Vec<Vec<String>> = collisions.overwritten.iter()
+            .take(COLLISION_PREVIEW_LIMIT)
+            .map(|collision| vec![
+                collision.path.clone(),
+                format_file_size(collision.old_size),
+                format_mtime(collision.old_mtime),
+                format_file_size(collision.new_size),
+                format_mtime(collision.new_mtime),
+            ])
+            .collect();
+        print_table(&["Overwritten", "Old size", "Old mtime", "New size", "New mtime"], &rows);
+        more(rows.len(), collisions.overwritten.len());
+    }
+    print_labeled_value("Existing paths deleted by whiteouts", &collisions.deleted.len().to_string());
+    if !collisions.deleted.is_empty() {
+        for path in collisions.deleted.iter().take(COLLISION_PREVIEW_LIMIT) {
+            print_list_item(path);
+        }
+        more(COLLISION_PREVIEW_LIMIT, collisions.deleted.len());
+    }
+}
+
+/// How the target's current layer is kept for rollback, for the confirmation prompt
+fn backup_plan(incremental: bool, options: &ImportOptions) -> String {
+    if options.mode == ImportMode::Merge {
+        return "None (--mode merge)".to_string();
+    }
+    if !options.backup {
+        return "None (--no-backup)".to_string();
+    }
+    match (&options.backup_dir, incremental) {
+        (Some(backup_dir), _) => format!("Tarball in {}", backup_dir),
+        (None, true) => "Copy of the current layer".to_string(),
+        (None, false) => "Current layer moved aside".to_string(),
+    }
+}
+
+/// Whether a container state reported by Docker means its processes are live
+pub(crate) fn is_running_state(state: &str) -> bool {
+    matches!(state.to_lowercase().as_str(), "running" | "paused" | "restarting")
+}
+
+/// Describe how the target container's image differs from the one the export was taken from
+pub(crate) fn image_mismatch(export_data: &ExportDataV2, target_metadata: &ContainerMetadata) -> Option<String> {
+    let source = &export_data.container_metadata;
+    if normalize_image_digest(&source.image_sha256) == normalize_image_digest(&target_metadata.image_sha256) {
+        return None;
+    }
+    Some(format!(
+        "Image mismatch: the export was taken from a container running {} ({}), but target container {} runs {} ({})",
+        source.image,
+        normalize_image_digest(&source.image_sha256),
+        target_metadata.name,
+        target_metadata.image,
+        normalize_image_digest(&target_metadata.image_sha256)
+    ))
+}
+
+/// Paths a verification error names; the errors section above it lists all of them
+const REPORTED_MISMATCHES: usize = 5;
+
+/// Error for a layer whose entries do not match the manifest, naming the first mismatching paths
+pub(crate) fn manifest_mismatch_error(what: &str, mismatches: &[String]) -> anyhow::Error {
+    let mut named = mismatches.iter().take(REPORTED_MISMATCHES).cloned().collect::<Vec<_>>().join("; ");
+    if mismatches.len() > REPORTED_MISMATCHES {
+        named.push_str(&format!("; and {} more", mismatches.len() - REPORTED_MISMATCHES));
+    }
+    anyhow::anyhow!("Layer verification failed: {} {} do not match the manifest: {}", mismatches.len(), what, named)
+}
+
+/// Checksum of the target layer to compare with an incremental export's parent, computed the way
+/// the parent's was with the owner shift `id_offset` of this import undone; None when the target
+/// has no layer directory
+fn current_parent_checksum(target_upper_path: &Path, incremental: &IncrementalInfo, id_offset: IdOffset) -> Result<Option<Checksum>> {
+    if !target_upper_path.exists() {
+        return Ok(None);
+    }
+    let format = ChecksumFormat::new(incremental.parent_checksum_scheme, incremental.parent_layer_checksum.algorithm)
+        .with_owner_shift(id_offset.inverse());
+    let checksum = calculate_directory_checksum(target_upper_path, format)
+        .context("Failed to calculate target layer checksum")?;
+    Ok(Some(checksum))
+}
+
+/// Error for a legacy export's layer whose directory checksum differs after the import
+fn layer_checksum_error(expected: &Checksum, actual: &Checksum) -> anyhow::Error {
+    anyhow::anyhow!(
+        "Layer checksum verification failed: expected {}, got {}. The export has no per-file manifest, so the differing paths cannot be named",
+        expected, actual
+    )
+}
+
+/// The error a failed post-check fails the import with
+fn post_check_error(outcome: &PostCheckReport, timeout_secs: u64) -> anyhow::Error {
+    match (outcome.timed_out, outcome.exit_code) {
+        (true, _) => anyhow::anyhow!("Post-check timed out after {}s", timeout_secs),
+        (false, Some(code)) => anyhow::anyhow!("Post-check failed with exit code {}", code),
+        (false, None) => anyhow::anyhow!("Post-check was terminated by a signal"),
+    }
+}
+
+/// Drop the journal of a failed import once the layer is back as it was; otherwise keep it for --resume
+fn finish_journal(journal: ImportJournal, state: TargetLayerState) {
+    if state == TargetLayerState::RolledBack {
+        if let Err(error) = journal.remove() {
+            print_warning(&format!("{:#}", error));
+        }
+    } else {
+        print_info(&format!(
+            "Import progress is recorded in {:?}; run the import again with --resume to continue it",
+            journal.path()
+        ));
+    }
+}
+
+/// Whiteouts stay whiteouts in a container's upper directory; a plain directory has no lower layers to hide
+fn whiteout_mode(options: &ImportOptions) -> WhiteoutMode {
+    match options.target_path {
+        Some(_) => WhiteoutMode::Delete,
+        None => WhiteoutMode::Overlay,
+    }
+}
+
+/// Compiled `--exclude` patterns, or None when nothing is excluded
+fn import_excludes(options: &ImportOptions) -> Result<Option<ExcludeSet>> {
+    let excludes = ExcludeSet::new(&options.excludes, &[])?;
+    Ok((!excludes.is_empty()).then_some(excludes))
+}
+
+/// Paths selected with `--only`, or None when the whole layer is imported
+fn import_only(options: &ImportOptions) -> Result<Option<PathSelection>> {
+    let only = PathSelection::new(&options.only)?;
+    Ok((!only.is_empty()).then_some(only))
+}
+
+/// Refuse to extract into `/` or a non-empty directory unless forced, creating the directory if missing
+fn check_target_directory(target_dir: &Path, force: bool) -> Result<()> {
+    if target_dir.exists() {
+        let canonical = target_dir.canonicalize()
+            .with_context(|| format!("Failed to resolve target path: {:?}", target_dir))?;
+        if !canonical.is_dir() {
+            return Err(anyhow::anyhow!("Target path is not a directory: {:?}", target_dir));
+        }
+        if canonical == Path::new("/") && !force {
+            return Err(anyhow::anyhow!("Refusing to extract into /; pass --force if you really mean it"));
+        }
+        if !is_empty_directory(&canonical)? && !force {
+            return Err(anyhow::anyhow!("Target directory {:?} is not empty; pass --force to extract into it anyway", target_dir));
+        }
+    } else {
+        std::fs::create_dir_all(target_dir)
+            .with_context(|| format!("Failed to create target directory: {:?}", target_dir))?;
+    }
+    Ok(())
+}
+
+fn is_empty_directory(dir: &Path) -> Result<bool> {
+    let mut entries = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read directory: {:?}", dir))?;
+    Ok(entries.next().is_none())
+}
+
+/// Warn once about entries whose ownership, mode, or xattrs could not be restored
+/// Report how a restored layer compares with the checksum recorded for its backup
+pub(crate) fn report_backup_check(label: &str, check: &BackupCheck) {
+    match check {
+        BackupCheck::Verified(checksum) => print_check_result(label, &format!("✓ Layer matches its backup checksum {}", checksum), true),
+        BackupCheck::Mismatch { expected, actual } => print_warning(&format!(
+            "{}: restored layer does not match the checksum recorded for its backup (expected {}, got {})",
+            label, expected, actual
+        )),
+        BackupCheck::Unrecorded => print_info(&format!("{}: no checksum was recorded for this backup; the layer was not verified", label)),
+    }
+}
+
+/// Attribute restorer shifting owners by the resolved import offsets, or forcing --chown's owner
+pub(crate) fn attribute_restorer(options: &ImportOptions) -> AttributeRestorer {
+    AttributeRestorer::new()
+        .with_id_offset(IdOffset {
+            uid: options.uid_offset.unwrap_or(0),
+            gid: options.gid_offset.unwrap_or(0),
+        })
+        .with_owner(options.chown)
+}
+
+pub(crate) fn warn_unrestored_attributes(failures: &[String]) {
+    if failures.is_empty() {
+        return;
+    }
+    print_warning(&format!(
+        "Could not restore ownership, permissions, or xattrs of {} path(s) (not running as root); services checking file ownership may fail",
+        failures.len()
+    ));
+    print_warnings_section(failures);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backup::move_layer_aside;
+    use crate::testing::{corrupt, write_export};
+    use crate::types::{ChecksumAlgorithm, ChecksumFormat, ChecksumScheme};
+    use crate::utils::calculate_directory_checksum;
+    use std::os::unix::fs::symlink;
+    use tempfile::TempDir;
+
+    const CONTAINER_ID: &str = "0123456789ab";
+
+    fn directory_options(target: &Path) -> ImportOptions {
+        ImportOptions {
+            target_path: Some(target.to_str().unwrap().to_string()),
+            force: true,
+            yes: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn corrupt_layer_leaves_the_target_untouched() {
+        let layer = TempDir::new().unwrap();
+        let workspace = TempDir::new().unwrap();
+        std::fs::create_dir(layer.path().join("etc")).unwrap();
+        std::fs::write(layer.path().join("etc/config"), b"layer-config-contents\n").unwrap();
+        let (export_path, _) = write_export(layer.path(), workspace.path());
+
+        let intact = TempDir::new().unwrap();
+        ImportCommand::new().with_output(Arc::new(CaptureSink::new()))
+            .execute(export_path.to_str().unwrap(), None, directory_options(intact.path()))
+            .unwrap();
+        assert_eq!(std::fs::read(intact.path().join("etc/config")).unwrap(), b"layer-config-contents\n");
+
+        // Damage the file's contents inside layer.tar; its tar headers stay valid
+        corrupt(&export_path, b"layer-config-contents", b"LAYER-CONFIG-CONTENTS");
+        let target = TempDir::new().unwrap();
+        std::fs::write(target.path().join("existing"), b"keep me\n").unwrap();
+        let result = ImportCommand::new().with_output(Arc::new(CaptureSink::new()))
+            .execute(export_path.to_str().unwrap(), None, directory_options(target.path()));
+        let error = format!("{:#}", result.unwrap_err());
+        assert!(error.starts_with("Precheck failed"), "{}", error);
+
+        let names: Vec<_> = std::fs::read_dir(target.path()).unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, vec![std::ffi::OsString::from("existing")]);
+        assert_eq!(std::fs::read(target.path().join("existing")).unwrap(), b"keep me\n");
+    }
+
+    /// Export of a one-file layer whose file contents were damaged inside layer.tar
+    fn corrupt_export(workspace: &TempDir) -> PathBuf {
+        let layer = TempDir::new().unwrap();
+        std::fs::create_dir(layer.path().join("etc")).unwrap();
+        std::fs::write(layer.path().join("etc/config"), b"layer-config-contents\n").unwrap();
+        let (export_path, _) = write_export(layer.path(), workspace.path());
+        corrupt(&export_path, b"layer-config-contents", b"LAYER-CONFIG-CONTENTS");
+        export_path
+    }
+
+    fn layer_checksum(upper: &Path) -> Checksum {
+        calculate_directory_checksum(upper, ChecksumFormat::new(ChecksumScheme::V4, ChecksumAlgorithm::Sha256)).unwrap()
+    }
+
+    #[test]
+    fn failed_extraction_rolls_the_layer_back() {
+        let workspace = TempDir::new().unwrap();
+        let export_path = corrupt_export(&workspace);
+        let container = TempDir::new().unwrap();
+        let upper = container.path().join("diff");
+        std::fs::create_dir_all(upper.join("var/lib/app")).unwrap();
+        std::fs::write(upper.join("var/lib/app/state"), b"previous state\n").unwrap();
+        symlink("app/state", upper.join("var/lib/current")).unwrap();
+        let before = layer_checksum(&upper);
+
+        let command = ImportCommand::new().with_output(Arc::new(CaptureSink::new()));
+        let _output = enter_output(&command.output);
+        let options = ImportOptions { skip_precheck: true, yes: true, ..Default::default() };
+        let mut timer = PhaseTimer::new();
+        let prepared = command.prepare_export(export_path.to_str().unwrap(), &options, &mut timer).unwrap();
+        let backup = move_layer_aside(&upper, CONTAINER_ID, export_path.to_str().unwrap()).unwrap();
+
+        // Without the precheck the corruption only shows once the layer is being written
+        let Err(error) = command.apply_layer(&prepared, &upper, None, &options, &mut timer) else {
+            panic!("corrupt layer was applied");
+        };
+        let cause = format!("{:#}", error);
+        let (error, state) = command.roll_back(error, ImportTarget::Container(CONTAINER_ID), &upper, Some(&backup), &options);
+        assert_eq!(state, TargetLayerState::RolledBack);
+        assert_eq!(format!("{:#}", error), format!("Import failed, previous layer restored: {}", cause));
+        assert_eq!(layer_checksum(&upper), before);
+        assert_eq!(std::fs::read(upper.join("var/lib/app/state")).unwrap(), b"previous state\n");
+    }
+
+    #[test]
+    fn failed_import_without_backup_says_no_rollback_was_possible() {
+        let container = TempDir::new().unwrap();
+        let command = ImportCommand::new().with_output(Arc::new(CaptureSink::new()));
+        let _output = enter_output(&command.output);
+        let options = ImportOptions { backup: false, ..Default::default() };
+
+        let (error, state) = command.roll_back(
+            anyhow::anyhow!("layer checksum mismatch"), ImportTarget::Container(CONTAINER_ID), container.path(), None, &options
+        );
+        assert_eq!(state, TargetLayerState::PartiallyImported);
+        let message = format!("{:#}", error);
+        assert!(message.starts_with("Import failed and no rollback was possible (--no-backup)"), "{}", message);
+        assert!(message.ends_with(": layer checksum mismatch"), "{}", message);
+    }
+
+    #[test]
+    fn reports_are_refused_where_none_would_be_written() {
+        let command = ImportCommand::new().with_output(Arc::new(CaptureSink::new()));
+        let remote = ImportOptions { ssh: Some("user@host".to_string()), report: Some("report.json".to_string()), yes: true, ..Default::default() };
+        let verify_only = ImportOptions { verify_only: true, output: OutputFormat::Json, ..Default::default() };
+
+        let error = command.execute("export.tar", Some(CONTAINER_ID), remote).unwrap_err();
+        assert_eq!(error.to_string(), "--report and --output json cannot be used with --ssh, which writes no import report");
+        let error = command.execute("export.tar", Some(CONTAINER_ID), verify_only).unwrap_err();
+        assert_eq!(error.to_string(), "--report and --output json cannot be used with --verify-only, which writes no import report");
+    }
+
+    #[test]
+    fn remote_imports_refuse_to_resume() {
+        let command = ImportCommand::new().with_output(Arc::new(CaptureSink::new()));
+        let options = ImportOptions { ssh: Some("user@host".to_string()), resume: true, yes: true, ..Default::default() };
+
+        let error = command.execute("export.tar", Some(CONTAINER_ID), options).unwrap_err();
+        assert!(error.to_string().starts_with("--resume cannot be used with --ssh"), "{}", error);
+    }
+
+    #[test]
+    fn remote_imports_decrypt_before_sending() {
+        let workspace = TempDir::new().unwrap();
+        let export_path = workspace.path().join("export.tar.age");
+        let recipient = age::x25519::Identity::generate().to_public();
+        let file = File::create(&export_path).unwrap();
+        crate::crypto::encrypt_to(file, &[recipient], |writer| Ok(std::io::Write::write_all(writer, b"layer")?)).unwrap();
+
+        // Without an identity the export is refused locally instead of failing on the remote host
+        let command = ImportCommand::new().with_output(Arc::new(CaptureSink::new()));
+        let options = ImportOptions { ssh: Some("user@host".to_string()), yes: true, ..Default::default() };
+        let error = command.execute(export_path.to_str().unwrap(), Some(CONTAINER_ID), options).unwrap_err();
+        assert!(error.to_string().starts_with("File is encrypted"), "{}", error);
     }
 }
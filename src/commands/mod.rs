@@ -1,7 +1,19 @@
 pub mod export;
 pub mod import;
 pub mod check;
+pub mod sign;
+pub mod restore;
+pub mod clone;
+pub mod diff;
+pub mod clean;
+pub mod explain;
 
 pub use export::ExportCommand;
 pub use import::ImportCommand;
 pub use check::CheckCommand;
+pub use sign::{KeygenCommand, SignCommand};
+pub use restore::{RestoreCommand, VerifyBackupCommand};
+pub use clone::CloneCommand;
+pub use diff::DiffCommand;
+pub use clean::CleanCommand;
+pub use explain::ExplainCommand;
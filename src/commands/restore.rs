@@ -0,0 +1,240 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::backup::{create_tarball_backup, list_backups, move_layer_aside, BackupCheck, LayerBackup};
+use crate::cancel::CancellationToken;
+use crate::commands::import::report_backup_check;
+use crate::docker::DockerClient;
+use crate::lock::ContainerLock;
+use crate::output::*;
+use crate::timing::PhaseTimer;
+use crate::types::{RestoreOptions, VerifyBackupOptions};
+
+pub struct RestoreCommand {
+    docker_client: DockerClient,
+    cancel: CancellationToken,
+}
+
+impl RestoreCommand {
+    pub fn new() -> Self {
+        Self {
+            docker_client: DockerClient::new(),
+            cancel: CancellationToken::new(),
+        }
+    }
+
+    /// Use `cancel` to stop the restore before the current layer is replaced
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Roll a container's upper layer back to one of the backups import took, after backing up
+    /// the current layer; with `options.list`, only list the backups
+    pub fn execute(&self, container_id: &str, options: &RestoreOptions) -> Result<()> {
+        let mut timer = PhaseTimer::new();
+        timer.start("validation");
+
+        let full_id = self.docker_client.resolve_container_id(container_id)?;
+        let target_upper_path = self.docker_client.get_upper_layer_path(container_id)
+            .context("Failed to get target container layer path")?;
+        let backup_dir = options.backup_dir.as_deref().map(Path::new);
+        let backups = list_backups(&target_upper_path, backup_dir, &full_id)
+            .context("Failed to list backups")?;
+
+        if options.list {
+            display_backups(container_id, &backups);
+            return Ok(());
+        }
+
+        let selected = match &options.backup {
+            Some(name) => find_backup(&backups, container_id, name)?,
+            None => backups.last().ok_or_else(|| anyhow::anyhow!(
+                "No backups found for container {}{}",
+                container_id,
+                if backup_dir.is_some() { "" } else { "; pass --backup-dir for tarball backups" }
+            ))?,
+        };
+
+        print_progress(&format!("Restoring container {} from backup: {}", container_id, selected.name()));
+        let _lock = ContainerLock::acquire(&full_id, "restore", options.wait.map(Duration::from_secs), &self.cancel)?;
+        self.docker_client.validate_container_for_layer_operations(container_id)
+            .context("Target container validation failed")?;
+
+        // A corrupt tarball must not replace the current layer
+        timer.start("checksum");
+        if let LayerBackup::Tarball(_) = selected {
+            print_progress("Verifying backup archive checksum...");
+        }
+        let archive_checksum = selected.verify_archive()?;
+
+        // Keep the current layer so the restore itself can be undone
+        timer.start("backup");
+        let safety_backup = if target_upper_path.exists() && std::fs::read_dir(&target_upper_path)?.next().is_some() {
+            let safety_backup = match backup_dir {
+                Some(backup_dir) => {
+                    let safety_backup = create_tarball_backup(&target_upper_path, backup_dir, &full_id, &selected.path().display().to_string(), &self.cancel)
+                        .context("Failed to backup current layer")?;
+                    std::fs::remove_dir_all(&target_upper_path)
+                        .context("Failed to remove current layer")?;
+                    safety_backup
+                }
+                None => move_layer_aside(&target_upper_path, &full_id, &selected.path().display().to_string())
+                    .context("Failed to backup current layer")?,
+            };
+            print_warning(&format!("Backed up current layer to: {:?}", safety_backup.path()));
+            Some(safety_backup)
+        } else {
+            None
+        };
+
+        timer.start("restore");
+        print_progress("Restoring layer data...");
+        let layer_check = match self.cancel.check().and_then(|_| selected.restore_verified(&target_upper_path)) {
+            Ok(layer_check) => layer_check,
+            Err(error) => {
+                if let Some(safety_backup) = &safety_backup {
+                    print_warning(&format!("Restore failed; putting the previous layer back from {:?}", safety_backup.path()));
+                    if let Err(rollback_error) = safety_backup.restore(&target_upper_path) {
+                        print_warning(&format!("Failed to put the previous layer back: {:#}", rollback_error));
+                    }
+                }
+                return Err(error);
+            }
+        };
+
+        print_success("Restore completed successfully!");
+        print_section_header("Restore Summary");
+        print_labeled_value("Container", container_id);
+        print_labeled_value("Layer path", &target_upper_path.display().to_string());
+        print_labeled_value("Restored backup", &selected.path().display().to_string());
+        if let Some(taken_at) = selected.taken_at() {
+            print_labeled_value("Backup taken", &taken_at.format("%Y-%m-%d %H:%M:%S UTC").to_string());
+        }
+        if let Some(checksum) = &archive_checksum {
+            print_checksum("Backup archive checksum verified", checksum);
+        }
+        report_backup_check("Restored layer", &layer_check);
+        if let LayerBackup::Directory(_) = selected {
+            print_info("Directory backups are moved back into place and no longer listed");
+        }
+        match &safety_backup {
+            Some(safety_backup) => print_labeled_value("Previous layer saved as", &safety_backup.path().display().to_string()),
+            None => print_info("The layer was empty before the restore; no safety backup was taken"),
+        }
+
+        let total_secs = timer.elapsed_secs();
+        print_phase_timings(&timer.finish(), total_secs);
+        Ok(())
+    }
+}
+
+impl Default for RestoreCommand {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Checks a container's backups against the checksums recorded when they were taken
+pub struct VerifyBackupCommand {
+    docker_client: DockerClient,
+}
+
+impl VerifyBackupCommand {
+    pub fn new() -> Self {
+        Self {
+            docker_client: DockerClient::new(),
+        }
+    }
+
+    /// Verify one backup, or all of the container's backups, without restoring anything
+    pub fn execute(&self, container_id: &str, options: &VerifyBackupOptions) -> Result<()> {
+        let full_id = self.docker_client.resolve_container_id(container_id)?;
+        let target_upper_path = self.docker_client.get_upper_layer_path(container_id)
+            .context("Failed to get target container layer path")?;
+        let backup_dir = options.backup_dir.as_deref().map(Path::new);
+        let backups = list_backups(&target_upper_path, backup_dir, &full_id)
+            .context("Failed to list backups")?;
+
+        let selected: Vec<&LayerBackup> = match &options.backup {
+            Some(name) => vec![find_backup(&backups, container_id, name)?],
+            None => backups.iter().collect(),
+        };
+        if selected.is_empty() {
+            return Err(anyhow::anyhow!("No backups found for container {}", container_id));
+        }
+
+        let mut failed = 0;
+        let rows: Vec<Vec<String>> = selected.iter()
+            .map(|backup| {
+                print_progress(&format!("Verifying backup: {}", backup.name()));
+                let status = match backup.verify() {
+                    Ok(BackupCheck::Verified(checksum)) => format!("✓ {}", checksum),
+                    Ok(BackupCheck::Mismatch { expected, actual }) => {
+                        failed += 1;
+                        format!("✗ expected {}, got {}", expected, actual)
+                    }
+                    Ok(BackupCheck::Unrecorded) => "no checksum recorded".to_string(),
+                    Err(error) => {
+                        failed += 1;
+                        format!("✗ {:#}", error)
+                    }
+                };
+                vec![backup.name(), status]
+            })
+            .collect();
+
+        print_section_header(&format!("Backups of {}", container_id));
+        print_table(&["Backup", "Result"], &rows);
+
+        if failed > 0 {
+            return Err(anyhow::anyhow!("{} backup(s) failed verification", failed));
+        }
+        print_success("All backups verified");
+        Ok(())
+    }
+}
+
+impl Default for VerifyBackupCommand {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Backup named `name`, by file name or timestamp
+fn find_backup<'a>(backups: &'a [LayerBackup], container_id: &str, name: &str) -> Result<&'a LayerBackup> {
+    backups.iter()
+        .find(|backup| backup.name() == name || backup.timestamp() == name)
+        .ok_or_else(|| anyhow::anyhow!(
+            "Backup not found for container {}: {} (use restore --list to see the available backups)",
+            container_id, name
+        ))
+}
+
+/// List backups oldest first, marking the one `restore` picks by default
+fn display_backups(container_id: &str, backups: &[LayerBackup]) {
+    print_section_header(&format!("Backups of {}", container_id));
+    if backups.is_empty() {
+        print_info("No backups found");
+        return;
+    }
+
+    let rows: Vec<Vec<String>> = backups.iter()
+        .enumerate()
+        .map(|(index, backup)| {
+            let latest = if index + 1 == backups.len() { " (latest)" } else { "" };
+            vec![
+                format!("{}{}", backup.name(), latest),
+                match backup {
+                    LayerBackup::Directory(_) => "directory".to_string(),
+                    LayerBackup::Tarball(_) => "tarball".to_string(),
+                },
+                backup.taken_at()
+                    .map(|taken_at| taken_at.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+                    .unwrap_or_else(|| "unknown".to_string()),
+            ]
+        })
+        .collect();
+    print_table(&["Backup", "Kind", "Taken"], &rows);
+}
@@ -0,0 +1,100 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+use crate::output::*;
+use crate::signing::{generate_key_pair, load_signing_key, sign_file};
+use crate::utils::{find_chunk_index, reject_partial_file};
+
+pub struct SignCommand;
+
+impl SignCommand {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Write a detached ed25519 signature for an export file to `<export>.sig`
+    pub fn execute(&self, input_path: &str, key_path: &str) -> Result<()> {
+        let input_file_path = Path::new(input_path);
+        if !input_file_path.exists() {
+            return Err(anyhow::anyhow!("Input file not found: {}", input_path));
+        }
+        reject_partial_file(input_file_path)?;
+
+        // Split exports are signed through their index, which pins every chunk's checksum
+        let signed_path = find_chunk_index(input_file_path).unwrap_or_else(|| input_file_path.to_path_buf());
+        if !signed_path.exists() {
+            return Err(anyhow::anyhow!("Chunk index not found: {}", signed_path.display()));
+        }
+
+        let signing_key = load_signing_key(key_path)?;
+        print_progress(&format!("Signing export file: {}", signed_path.display()));
+        let signature_path = sign_file(&signed_path, &signing_key)?;
+
+        print_success("Export signed successfully!");
+        print_labeled_value("Signed file", &signed_path.display().to_string());
+        print_labeled_value("Signature", &signature_path.display().to_string());
+        Ok(())
+    }
+}
+
+impl Default for SignCommand {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct KeygenCommand;
+
+impl KeygenCommand {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Generate an ed25519 key pair as `<prefix>.key` (private) and `<prefix>.pub` (public)
+    pub fn execute(&self, prefix: &str, force: bool) -> Result<()> {
+        let private_path = PathBuf::from(format!("{}.key", prefix));
+        let public_path = PathBuf::from(format!("{}.pub", prefix));
+        if !force {
+            for path in [&private_path, &public_path] {
+                if path.exists() {
+                    return Err(anyhow::anyhow!(
+                        "Key file already exists: {}. Use --force to overwrite",
+                        path.display()
+                    ));
+                }
+            }
+        }
+
+        let (private_pem, public_pem) = generate_key_pair()?;
+        write_private_key(&private_path, &private_pem)?;
+        std::fs::write(&public_path, public_pem)
+            .with_context(|| format!("Failed to write public key: {:?}", public_path))?;
+
+        print_success("Key pair generated successfully!");
+        print_labeled_value("Private key", &private_path.display().to_string());
+        print_labeled_value("Public key", &public_path.display().to_string());
+        Ok(())
+    }
+}
+
+impl Default for KeygenCommand {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Write the private key readable by the owner only
+fn write_private_key(path: &Path, pem: &str) -> Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(path)
+        .with_context(|| format!("Failed to create private key: {:?}", path))?;
+    std::io::Write::write_all(&mut file, pem.as_bytes())
+        .with_context(|| format!("Failed to write private key: {:?}", path))
+}
@@ -0,0 +1,99 @@
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// First bytes of a binary age file
+pub const AGE_MAGIC: &[u8] = b"age-encryption.org/";
+
+/// First line of an ASCII-armored age file
+pub const AGE_ARMOR_MAGIC: &[u8] = b"-----BEGIN AGE ENCRYPTED FILE-----";
+
+/// Check whether a file is age-encrypted (binary or armored)
+pub fn is_encrypted_file<P: AsRef<Path>>(file_path: P) -> Result<bool> {
+    let mut file = File::open(&file_path)
+        .with_context(|| format!("Failed to open file: {:?}", file_path.as_ref()))?;
+
+    let mut magic = [0u8; 34];
+    let mut read = 0;
+    while read < magic.len() {
+        match file.read(&mut magic[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+
+    let magic = &magic[..read];
+    Ok(magic.starts_with(AGE_MAGIC) || magic.starts_with(AGE_ARMOR_MAGIC))
+}
+
+/// Parse age X25519 recipients (`age1...` public keys)
+pub fn parse_recipients(recipients: &[String]) -> Result<Vec<age::x25519::Recipient>> {
+    recipients.iter()
+        .map(|recipient| {
+            recipient.trim().parse::<age::x25519::Recipient>()
+                .map_err(|e| anyhow::anyhow!("Invalid age recipient '{}': {}", recipient, e))
+        })
+        .collect()
+}
+
+/// Encrypt everything written by `write_body` to the given recipients, returning the
+/// underlying writer once the age stream is finalized
+pub fn encrypt_to<W, F>(writer: W, recipients: &[age::x25519::Recipient], write_body: F) -> Result<W>
+where
+    W: Write,
+    F: FnOnce(&mut age::stream::StreamWriter<W>) -> Result<()>,
+{
+    let encryptor = age::Encryptor::with_recipients(recipients.iter().map(|recipient| recipient as &dyn age::Recipient))
+        .context("Failed to set up encryption")?;
+    let mut encrypted = encryptor.wrap_output(writer)
+        .context("Failed to write encryption header")?;
+    write_body(&mut encrypted)?;
+    encrypted.finish()
+        .context("Failed to finish encrypted stream")
+}
+
+/// Decrypt an age-encrypted file using the identities in `identity_path`
+pub fn decrypt_file<P: AsRef<Path>>(input_path: P, output_path: P, identity_path: &Path) -> Result<()> {
+    let identity_file = age::IdentityFile::from_file(identity_path.to_string_lossy().into_owned())
+        .with_context(|| format!("Failed to read identity file: {:?}", identity_path))?;
+    let identities = identity_file.into_identities()
+        .map_err(|e| anyhow::anyhow!("Failed to load identities from {:?}: {}", identity_path, e))?;
+
+    let input_file = File::open(&input_path)
+        .with_context(|| format!("Failed to open encrypted file: {:?}", input_path.as_ref()))?;
+    let decryptor = age::Decryptor::new_buffered(BufReader::new(input_file))
+        .map_err(|e| anyhow::anyhow!("Failed to read encryption header: {}", e))?;
+    let mut reader = decryptor.decrypt(identities.iter().map(|identity| identity.as_ref() as &dyn age::Identity))
+        .map_err(|e| anyhow::anyhow!("Failed to decrypt export with identity {:?}: {}", identity_path, e))?;
+
+    let output_file = File::create(&output_path)
+        .with_context(|| format!("Failed to create output file: {:?}", output_path.as_ref()))?;
+    let mut writer = BufWriter::new(output_file);
+    std::io::copy(&mut reader, &mut writer)
+        .context("Failed to decrypt export")?;
+    writer.flush()
+        .context("Failed to write decrypted export")?;
+
+    Ok(())
+}
+
+/// Decrypt `input_path` into `decrypted_path` when it is encrypted, returning the path to read from.
+/// Encrypted input without an identity is rejected with a specific error.
+pub fn decrypt_if_needed<'a>(input_path: &'a Path, decrypted_path: &'a Path, identity_path: Option<&Path>) -> Result<&'a Path> {
+    if !is_encrypted_file(input_path)? {
+        return Ok(input_path);
+    }
+
+    match identity_path {
+        Some(identity_path) => {
+            crate::output::print_progress("Decrypting export file...");
+            decrypt_file(input_path, decrypted_path, identity_path)?;
+            Ok(decrypted_path)
+        }
+        None => Err(anyhow::anyhow!(
+            "File is encrypted: {}. Pass --identity <keyfile> to decrypt it",
+            input_path.display()
+        )),
+    }
+}
@@ -0,0 +1,419 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// How serious a diagnostic is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// The command goes on, but the result may not be what was intended
+    Warning,
+    /// The command, or the check reporting it, fails
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Stable identifier of a kind of diagnostic, serialized as e.g. `W001_STORAGE_DRIVER_MISMATCH`.
+/// Codes are never reused or renumbered, so tooling can count them across runs and versions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DiagnosticCode {
+    #[serde(rename = "W001_STORAGE_DRIVER_MISMATCH")]
+    StorageDriverMismatch,
+    #[serde(rename = "W002_OS_MISMATCH")]
+    OsMismatch,
+    #[serde(rename = "W003_DOCKER_VERSION_MISMATCH")]
+    DockerVersionMismatch,
+    #[serde(rename = "W004_KERNEL_VERSION_MISMATCH")]
+    KernelVersionMismatch,
+    #[serde(rename = "W005_CGROUP_DRIVER_MISMATCH")]
+    CgroupDriverMismatch,
+    #[serde(rename = "W006_DOCKER_INFO_UNAVAILABLE")]
+    DockerInfoUnavailable,
+    #[serde(rename = "W007_ARCHITECTURE_MISMATCH_SKIPPED")]
+    ArchitectureMismatchSkipped,
+    #[serde(rename = "W008_IMAGE_MISMATCH_ALLOWED")]
+    ImageMismatchAllowed,
+    #[serde(rename = "W009_TARGET_RUNNING_FORCED")]
+    TargetRunningForced,
+    #[serde(rename = "W010_USERNS_REMAP_MISMATCH")]
+    UsernsRemapMismatch,
+    #[serde(rename = "W011_INCREMENTAL_PARENT_FORCED")]
+    IncrementalParentForced,
+    #[serde(rename = "W012_UNSHIFT_WITHOUT_USERNS_REMAP")]
+    UnshiftWithoutUsernsRemap,
+    #[serde(rename = "W013_FULL_ROOTFS_EXPORT")]
+    FullRootfsExport,
+    #[serde(rename = "W014_CHECKSUM_ALGORITHM_CHANGED")]
+    ChecksumAlgorithmChanged,
+    #[serde(rename = "W015_TRANSIENT_ENTRIES")]
+    TransientEntries,
+    #[serde(rename = "W016_LARGE_LAYER")]
+    LargeLayer,
+    #[serde(rename = "W017_MOUNT_SKIPPED")]
+    MountSkipped,
+    #[serde(rename = "E001_ARCHITECTURE_MISMATCH")]
+    ArchitectureMismatch,
+    #[serde(rename = "E002_IMAGE_MISMATCH")]
+    ImageMismatch,
+    #[serde(rename = "E003_TARGET_RUNNING")]
+    TargetRunning,
+    #[serde(rename = "E004_INCREMENTAL_PARENT_MISMATCH")]
+    IncrementalParentMismatch,
+    #[serde(rename = "E005_INCREMENTAL_MERGE")]
+    IncrementalMerge,
+    #[serde(rename = "E006_FULL_ROOTFS_NEEDS_AS_UPPER")]
+    FullRootfsNeedsAsUpper,
+    #[serde(rename = "E007_INSUFFICIENT_SPACE")]
+    InsufficientSpace,
+}
+
+/// What `layer-tool explain` prints about a code
+#[derive(Debug)]
+pub struct CodeInfo {
+    pub code: DiagnosticCode,
+    /// Short form, e.g. `W001`
+    pub id: &'static str,
+    /// Long form after the short one, e.g. `STORAGE_DRIVER_MISMATCH`
+    pub name: &'static str,
+    pub severity: Severity,
+    pub title: &'static str,
+    pub description: &'static str,
+    pub hints: &'static [&'static str],
+}
+
+/// Every code, in order
+pub static CODES: &[CodeInfo] = &[
+    CodeInfo {
+        code: DiagnosticCode::StorageDriverMismatch,
+        id: "W001",
+        name: "STORAGE_DRIVER_MISMATCH",
+        severity: Severity::Warning,
+        title: "Storage driver mismatch",
+        description: "The export was taken on a Docker daemon using another storage driver than the one it is \
+            checked against or imported into. Layers are archived as plain files with overlay whiteouts, so they \
+            usually apply, but drivers other than overlay2 may represent deletions and opaque directories differently.",
+        hints: &[
+            "Import into a daemon running the same storage driver (see `docker info --format '{{.Driver}}'`)",
+            "Pass --skip-storage to check if the mismatch is expected",
+        ],
+    },
+    CodeInfo {
+        code: DiagnosticCode::OsMismatch,
+        id: "W002",
+        name: "OS_MISMATCH",
+        severity: Severity::Warning,
+        title: "Operating system mismatch",
+        description: "The export was taken on a host with another operating system than the current one. The layer \
+            itself does not depend on the host, but paths and tools the container relies on may.",
+        hints: &["Pass --skip-os to check if the hosts are known to be compatible"],
+    },
+    CodeInfo {
+        code: DiagnosticCode::DockerVersionMismatch,
+        id: "W003",
+        name: "DOCKER_VERSION_MISMATCH",
+        severity: Severity::Warning,
+        title: "Docker version mismatch",
+        description: "The export was taken on a Docker release with a newer major version than the current daemon. \
+            Overlay behavior has changed between releases, so an older daemon may not read the layer as it was written.",
+        hints: &[
+            "Upgrade the target daemon to at least the major version recorded in the export",
+            "Pass --skip-version to check if the mismatch is expected",
+        ],
+    },
+    CodeInfo {
+        code: DiagnosticCode::KernelVersionMismatch,
+        id: "W004",
+        name: "KERNEL_VERSION_MISMATCH",
+        severity: Severity::Warning,
+        title: "Kernel version mismatch",
+        description: "The export was taken on a kernel of another major version, or many minor releases apart. \
+            Overlayfs features such as metacopy and redirect_dir depend on the kernel.",
+        hints: &["Pass --skip-version to check if the kernels are known to be compatible"],
+    },
+    CodeInfo {
+        code: DiagnosticCode::CgroupDriverMismatch,
+        id: "W005",
+        name: "CGROUP_DRIVER_MISMATCH",
+        severity: Severity::Warning,
+        title: "Cgroup driver mismatch",
+        description: "The export was taken under another cgroup driver (cgroupfs or systemd). The layer is not \
+            affected, but services in the container that manage cgroups may behave differently.",
+        hints: &["Pass --skip-cgroup to check if the mismatch is expected"],
+    },
+    CodeInfo {
+        code: DiagnosticCode::DockerInfoUnavailable,
+        id: "W006",
+        name: "DOCKER_INFO_UNAVAILABLE",
+        severity: Severity::Warning,
+        title: "Docker info unavailable",
+        description: "The current Docker daemon could not be queried, so the export was not compared with the \
+            environment it would be imported into.",
+        hints: &[
+            "Check that the Docker daemon is running and that `docker info` works for this user",
+            "Run check on the host the export will be imported on",
+        ],
+    },
+    CodeInfo {
+        code: DiagnosticCode::ArchitectureMismatchSkipped,
+        id: "W007",
+        name: "ARCHITECTURE_MISMATCH_SKIPPED",
+        severity: Severity::Warning,
+        title: "Architecture mismatch ignored",
+        description: "A layer taken on one CPU architecture is imported onto a daemon of another because --skip-arch \
+            was given. Binaries in the layer will not run there unless emulated.",
+        hints: &["Drop --skip-arch unless the container runs under emulation such as qemu-user"],
+    },
+    CodeInfo {
+        code: DiagnosticCode::ImageMismatchAllowed,
+        id: "W008",
+        name: "IMAGE_MISMATCH_ALLOWED",
+        severity: Severity::Warning,
+        title: "Image mismatch allowed",
+        description: "The layer is imported onto a container of another image than the one it was taken from because \
+            --allow-image-mismatch was given. Files the layer changes or deletes may not exist in that image.",
+        hints: &["Import into a container created from the image recorded in the export"],
+    },
+    CodeInfo {
+        code: DiagnosticCode::TargetRunningForced,
+        id: "W009",
+        name: "TARGET_RUNNING_FORCED",
+        severity: Severity::Warning,
+        title: "Target container running",
+        description: "The layer of a running container is rewritten because --force was given. Its processes keep \
+            files that were replaced open, and overlayfs may serve stale entries until the container restarts.",
+        hints: &["Restart the container after the import, or stop it before importing"],
+    },
+    CodeInfo {
+        code: DiagnosticCode::UsernsRemapMismatch,
+        id: "W010",
+        name: "USERNS_REMAP_MISMATCH",
+        severity: Severity::Warning,
+        title: "User namespace remapping mismatch",
+        description: "The export and the target use different userns-remap id ranges, so the imported files keep \
+            owners that are wrong for the target.",
+        hints: &[
+            "Pass --auto-remap to shift the owners into the target daemon's range",
+            "Pass --uid-offset and --gid-offset to shift them explicitly",
+        ],
+    },
+    CodeInfo {
+        code: DiagnosticCode::IncrementalParentForced,
+        id: "W011",
+        name: "INCREMENTAL_PARENT_FORCED",
+        severity: Severity::Warning,
+        title: "Incremental parent mismatch forced",
+        description: "An incremental export is applied to a layer that is not the one it was taken against because \
+            --force-incremental was given. Changes made since the parent export are kept or overwritten at random.",
+        hints: &["Apply the parent export first, then the incremental one"],
+    },
+    CodeInfo {
+        code: DiagnosticCode::UnshiftWithoutUsernsRemap,
+        id: "W012",
+        name: "UNSHIFT_WITHOUT_USERNS_REMAP",
+        severity: Severity::Warning,
+        title: "--unshift without userns-remap",
+        description: "--unshift shifts owners out of the daemon's userns-remap range, but the daemon does not use one, \
+            so it has no effect.",
+        hints: &["Drop --unshift"],
+    },
+    CodeInfo {
+        code: DiagnosticCode::FullRootfsExport,
+        id: "W013",
+        name: "FULL_ROOTFS_EXPORT",
+        severity: Severity::Warning,
+        title: "Full root filesystem export",
+        description: "The export archives the container's complete merged filesystem, image layers included, rather \
+            than only what the container changed. It may be very large and needs --as-upper to import.",
+        hints: &["Drop --full-rootfs to export only the container's own layer"],
+    },
+    CodeInfo {
+        code: DiagnosticCode::ChecksumAlgorithmChanged,
+        id: "W014",
+        name: "CHECKSUM_ALGORITHM_CHANGED",
+        severity: Severity::Warning,
+        title: "Checksum algorithm changed",
+        description: "The previous export of an incremental export hashed files with another algorithm, so no file \
+            can be recognized as unchanged and every file is archived again.",
+        hints: &["Pass the --checksum-algo the previous export used"],
+    },
+    CodeInfo {
+        code: DiagnosticCode::TransientEntries,
+        id: "W015",
+        name: "TRANSIENT_ENTRIES",
+        severity: Severity::Warning,
+        title: "Paths changed while archiving",
+        description: "Files vanished or changed while the layer of a running container was archived, so the export \
+            is not an exact point-in-time snapshot.",
+        hints: &["Stop or pause the container before exporting"],
+    },
+    CodeInfo {
+        code: DiagnosticCode::LargeLayer,
+        id: "W016",
+        name: "LARGE_LAYER",
+        severity: Severity::Warning,
+        title: "Large layer archive",
+        description: "The layer archive is large, which makes the export slow to write, transfer and import.",
+        hints: &[
+            "Leave caches and build artifacts out with --exclude or --exclude-preset",
+            "Export incrementally against a previous export with --since",
+        ],
+    },
+    CodeInfo {
+        code: DiagnosticCode::MountSkipped,
+        id: "W017",
+        name: "MOUNT_SKIPPED",
+        severity: Severity::Warning,
+        title: "Mount skipped",
+        description: "A mount of the container could not be exported with --include-mounts, because its source is \
+            not a directory or could not be read.",
+        hints: &["Check that the mount source exists on this host and is readable"],
+    },
+    CodeInfo {
+        code: DiagnosticCode::ArchitectureMismatch,
+        id: "E001",
+        name: "ARCHITECTURE_MISMATCH",
+        severity: Severity::Error,
+        title: "Architecture mismatch",
+        description: "The export was taken on another CPU architecture than the daemon it is checked against or \
+            imported into. Binaries in the layer will not run there.",
+        hints: &[
+            "Import into a daemon of the architecture recorded in the export",
+            "Pass --skip-arch if the container runs under emulation",
+        ],
+    },
+    CodeInfo {
+        code: DiagnosticCode::ImageMismatch,
+        id: "E002",
+        name: "IMAGE_MISMATCH",
+        severity: Severity::Error,
+        title: "Image mismatch",
+        description: "The target container runs another image than the one the export was taken from. A layer only \
+            makes sense on top of its own image.",
+        hints: &[
+            "Create the target container from the image recorded in the export",
+            "Pass --allow-image-mismatch to import anyway",
+        ],
+    },
+    CodeInfo {
+        code: DiagnosticCode::TargetRunning,
+        id: "E003",
+        name: "TARGET_RUNNING",
+        severity: Severity::Error,
+        title: "Target container running",
+        description: "The target container is running. Rewriting its layer underneath it leaves its processes on \
+            deleted files and overlayfs serving stale entries.",
+        hints: &["Stop the container first (`docker stop`)", "Pass --force to import anyway and restart it afterwards"],
+    },
+    CodeInfo {
+        code: DiagnosticCode::IncrementalParentMismatch,
+        id: "E004",
+        name: "INCREMENTAL_PARENT_MISMATCH",
+        severity: Severity::Error,
+        title: "Incremental parent mismatch",
+        description: "The target's layer is not the one the incremental export was taken against, so applying only \
+            the changes would not reproduce the exported layer.",
+        hints: &["Apply the parent export first", "Pass --force-incremental to apply the changes anyway"],
+    },
+    CodeInfo {
+        code: DiagnosticCode::IncrementalMerge,
+        id: "E005",
+        name: "INCREMENTAL_MERGE",
+        severity: Severity::Error,
+        title: "Merge of an incremental export",
+        description: "--mode merge and --only apply part of a layer on top of the existing one, which an incremental \
+            export, being a set of changes itself, does not support.",
+        hints: &["Import the incremental export in replace mode"],
+    },
+    CodeInfo {
+        code: DiagnosticCode::FullRootfsNeedsAsUpper,
+        id: "E006",
+        name: "FULL_ROOTFS_NEEDS_AS_UPPER",
+        severity: Severity::Error,
+        title: "Full root filesystem without --as-upper",
+        description: "The export holds a container's complete root filesystem, which replaces the upper layer only \
+            when asked to explicitly.",
+        hints: &["Pass --as-upper to apply it as the container's upper layer"],
+    },
+    CodeInfo {
+        code: DiagnosticCode::InsufficientSpace,
+        id: "E007",
+        name: "INSUFFICIENT_SPACE",
+        severity: Severity::Error,
+        title: "Not enough free space",
+        description: "The filesystem of the target's layer, or of the backup, does not have room for the extracted \
+            layer and the backup of the current one.",
+        hints: &[
+            "Free space on the filesystem holding Docker's data root",
+            "Put backups elsewhere with --backup-dir, or skip them with --no-backup",
+            "Pass --no-space-check if the estimate is known to be too high",
+        ],
+    },
+];
+
+impl DiagnosticCode {
+    /// The code's entry in [`CODES`]
+    pub fn info(self) -> &'static CodeInfo {
+        CODES.iter().find(|info| info.code == self).expect("every code is listed in CODES")
+    }
+
+    /// Short form, e.g. `W001`
+    pub fn id(self) -> &'static str {
+        self.info().id
+    }
+
+    pub fn severity(self) -> Severity {
+        self.info().severity
+    }
+}
+
+impl std::fmt::Display for DiagnosticCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let info = self.info();
+        write!(f, "{}_{}", info.id, info.name)
+    }
+}
+
+/// The code `query` names, by short form (`W001`) or long form (`W001_STORAGE_DRIVER_MISMATCH`),
+/// in any case
+pub fn find_code(query: &str) -> Option<&'static CodeInfo> {
+    let query = query.trim().to_ascii_uppercase();
+    CODES.iter().find(|info| query == info.id || query == format!("{}_{}", info.id, info.name))
+}
+
+/// A warning or error with a stable code, printed as `[W001] Storage driver mismatch: ...` and
+/// recorded with its code and context in the JSON reports
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub code: DiagnosticCode,
+    pub severity: Severity,
+    pub message: String,
+    /// Values the message was built from, e.g. the storage drivers compared
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub context: BTreeMap<String, String>,
+}
+
+impl Diagnostic {
+    /// Diagnostic of `code`, at the code's severity
+    pub fn new(code: DiagnosticCode, message: impl Into<String>) -> Self {
+        Self { code, severity: code.severity(), message: message.into(), context: BTreeMap::new() }
+    }
+
+    /// Record `value` under `key` in the context
+    pub fn with_context(mut self, key: &str, value: impl ToString) -> Self {
+        self.context.insert(key.to_string(), value.to_string());
+        self
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}] {}", self.code.id(), self.message)
+    }
+}
@@ -1,8 +1,11 @@
 use anyhow::{anyhow, Context, Result};
 use serde_json::Value;
 use std::path::{Path, PathBuf};
-use std::process::Command;
-use crate::types::{ContainerMetadata, DockerInfo};
+use std::ffi::OsStr;
+use std::process::{Command, Output};
+use std::time::Instant;
+use crate::output::{print_debug, print_plain, print_trace};
+use crate::types::{ContainerMetadata, DockerInfo, IdOffset};
 
 /// Docker client for interacting with Docker daemon
 pub struct DockerClient;
@@ -14,9 +17,7 @@ impl DockerClient {
 
     /// Get container metadata by container ID
     pub fn get_container_metadata(&self, container_id: &str) -> Result<ContainerMetadata> {
-        let output = Command::new("docker")
-            .args(&["inspect", container_id])
-            .output()
+        let output = run_docker(&["inspect", container_id])
             .context("Failed to execute docker inspect command")?;
 
         if !output.status.success() {
@@ -38,11 +39,38 @@ impl DockerClient {
         self.parse_container_metadata(container)
     }
 
+    /// List all containers (running or not) matching every given label filter (`key` or `key=value`)
+    pub fn list_containers(&self, label_filters: &[String]) -> Result<Vec<ContainerMetadata>> {
+        let mut args = vec!["ps".to_string(), "--all".to_string(), "--no-trunc".to_string()];
+        for label in label_filters {
+            args.push("--filter".to_string());
+            args.push(format!("label={}", label));
+        }
+        args.push("--format".to_string());
+        args.push("{{.ID}}".to_string());
+
+        let output = run_docker(&args)
+            .context("Failed to execute docker ps command")?;
+
+        if !output.status.success() {
+            let error = String::from_utf8_lossy(&output.stderr);
+            return Err(anyhow!("Docker ps failed: {}", error));
+        }
+
+        let stdout = String::from_utf8(output.stdout)
+            .context("Failed to parse docker ps output as UTF-8")?;
+
+        stdout
+            .lines()
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(|id| self.get_container_metadata(id))
+            .collect()
+    }
+
     /// Get Docker daemon information
     pub fn get_docker_info(&self) -> Result<DockerInfo> {
-        let output = Command::new("docker")
-            .args(&["info", "--format", "{{json .}}"])
-            .output()
+        let output = run_docker(&["info", "--format", "{{json .}}"])
             .context("Failed to execute docker info command")?;
 
         if !output.status.success() {
@@ -64,9 +92,7 @@ impl DockerClient {
         let _metadata = self.get_container_metadata(container_id)?;
 
         // Try to get the layer path from container metadata
-        let output = Command::new("docker")
-            .args(&["inspect", "--format", "{{.GraphDriver.Data.MergedDir}}", container_id])
-            .output()
+        let output = run_docker(&["inspect", "--format", "{{.GraphDriver.Data.MergedDir}}", container_id])
             .context("Failed to get container layer path")?;
 
         if !output.status.success() {
@@ -91,24 +117,58 @@ impl DockerClient {
         Ok(layer_path.to_path_buf())
     }
 
+    /// Get the merged directory path (complete root filesystem seen by the container)
+    /// The overlay is only mounted while the container is running, so the directory must exist
+    pub fn get_merged_dir_path(&self, container_id: &str) -> Result<PathBuf> {
+        let output = run_docker(&["inspect", "--format", "{{.GraphDriver.Data.MergedDir}}", container_id])
+            .context("Failed to get container merged directory path")?;
+
+        if !output.status.success() {
+            let error = String::from_utf8_lossy(&output.stderr);
+            return Err(anyhow!("Failed to get container merged directory path: {}", error));
+        }
+
+        let merged_dir = String::from_utf8(output.stdout)
+            .context("Failed to parse merged directory path as UTF-8")?
+            .trim()
+            .to_string();
+
+        if merged_dir.is_empty() || merged_dir == "<no value>" {
+            return Err(anyhow!("Container merged directory path is empty or not available"));
+        }
+
+        let merged_path = PathBuf::from(merged_dir);
+        let is_mounted = std::fs::read_dir(&merged_path)
+            .map(|mut entries| entries.next().is_some())
+            .unwrap_or(false);
+        if !is_mounted {
+            return Err(anyhow!(
+                "Container merged directory {:?} is not mounted. Start the container so the overlay filesystem is mounted.",
+                merged_path
+            ));
+        }
+
+        Ok(merged_path)
+    }
+
     /// Get the upper layer directory path (read-write layer) with enhanced resolution
     /// Returns the path directly without checking if the directory exists
     pub fn get_upper_layer_path(&self, container_id: &str) -> Result<PathBuf> {
         // Method 1: Try to get UpperDir directly from GraphDriver.Data
         if let Ok(upper_path) = self.get_upper_layer_path_direct(container_id) {
-            println!("Found upper layer using direct method: {:?}", upper_path);
+            print_debug(&format!("Found upper layer using direct method: {:?}", upper_path));
             return Ok(upper_path);
         }
 
         // Method 2: Try the traditional approach (MergedDir parent + upper)
         if let Ok(upper_path) = self.get_upper_layer_path_traditional(container_id) {
-            println!("Found upper layer using traditional method: {:?}", upper_path);
+            print_debug(&format!("Found upper layer using traditional method: {:?}", upper_path));
             return Ok(upper_path);
         }
 
         // Method 3: Try to find the upper layer by inspecting the overlay2 structure
         if let Ok(upper_path) = self.get_upper_layer_path_by_inspection(container_id) {
-            println!("Found upper layer using inspection method: {:?}", upper_path);
+            print_debug(&format!("Found upper layer using inspection method: {:?}", upper_path));
             return Ok(upper_path);
         }
 
@@ -118,9 +178,7 @@ impl DockerClient {
 
     /// Method 1: Try to get UpperDir directly from GraphDriver.Data
     fn get_upper_layer_path_direct(&self, container_id: &str) -> Result<PathBuf> {
-        let output = Command::new("docker")
-            .args(&["inspect", "--format", "{{.GraphDriver.Data.UpperDir}}", container_id])
-            .output()
+        let output = run_docker(&["inspect", "--format", "{{.GraphDriver.Data.UpperDir}}", container_id])
             .context("Failed to get container upper layer path directly")?;
 
         if !output.status.success() {
@@ -140,7 +198,7 @@ impl DockerClient {
         let upper_path = PathBuf::from(upper_dir);
 
         // Use the returned path directly, regardless of whether it's "upper" or "diff"
-        println!("Using container layer directory: {:?}", upper_path);
+        print_debug(&format!("Using container layer directory: {:?}", upper_path));
 
         Ok(upper_path)
     }
@@ -154,9 +212,7 @@ impl DockerClient {
     /// Method 3: Inspect overlay2 structure to find the upper layer
     fn get_upper_layer_path_by_inspection(&self, container_id: &str) -> Result<PathBuf> {
         // Get full GraphDriver data
-        let output = Command::new("docker")
-            .args(&["inspect", "--format", "{{json .GraphDriver}}", container_id])
-            .output()
+        let output = run_docker(&["inspect", "--format", "{{json .GraphDriver}}", container_id])
             .context("Failed to get container GraphDriver data")?;
 
         if !output.status.success() {
@@ -174,15 +230,15 @@ impl DockerClient {
         if let Some(data) = graph_driver["Data"].as_object() {
             // Look for any path that might contain the layer ID
             for (key, value) in data {
-                if let Some(path_str) = value.as_str() {
-                    if path_str.contains("/overlay2/") && (key.contains("Dir") || key.contains("Path")) {
-                        // Extract the layer ID from the path
-                        if let Some(layer_id) = self.extract_layer_id_from_path(path_str) {
-                            let upper_path = PathBuf::from(format!("/var/lib/docker/overlay2/{}/upper", layer_id));
-                            if upper_path.exists() {
-                                return Ok(upper_path);
-                            }
-                        }
+                if let Some(path_str) = value.as_str()
+                    && path_str.contains("/overlay2/")
+                    && (key.contains("Dir") || key.contains("Path"))
+                    // Extract the layer ID from the path
+                    && let Some(layer_id) = self.extract_layer_id_from_path(path_str)
+                {
+                    let upper_path = PathBuf::from(format!("/var/lib/docker/overlay2/{}/upper", layer_id));
+                    if upper_path.exists() {
+                        return Ok(upper_path);
                     }
                 }
             }
@@ -207,83 +263,75 @@ impl DockerClient {
 
     /// Provide detailed error information when upper layer path cannot be found
     fn provide_detailed_layer_error(&self, container_id: &str) -> Result<PathBuf> {
-        println!("=== DEBUGGING CONTAINER LAYER PATHS ===");
+        print_plain("=== DEBUGGING CONTAINER LAYER PATHS ===");
 
         // Get full container inspect data for debugging
-        let output = Command::new("docker")
-            .args(&["inspect", container_id])
-            .output()
+        let output = run_docker(&["inspect", container_id])
             .context("Failed to get container inspect data for debugging")?;
 
         if output.status.success() {
             let stdout = String::from_utf8_lossy(&output.stdout);
-            if let Ok(inspect_data) = serde_json::from_str::<Vec<Value>>(&stdout) {
-                if let Some(container) = inspect_data.first() {
-                    if let Some(graph_driver) = container.get("GraphDriver") {
-                        println!("GraphDriver data: {}", serde_json::to_string_pretty(graph_driver).unwrap_or_default());
-
-                        if let Some(data) = graph_driver.get("Data") {
-                            if let Some(data_obj) = data.as_object() {
-                                for (key, value) in data_obj {
-                                    println!("  {}: {}", key, value.as_str().unwrap_or("N/A"));
-
-                                    // Check if any of these paths exist
-                                    if let Some(path_str) = value.as_str() {
-                                        let path = PathBuf::from(path_str);
-                                        println!("    Path exists: {}", path.exists());
-                                        if path.exists() && path.is_dir() {
-                                            if let Ok(entries) = std::fs::read_dir(&path) {
-                                                let count = entries.count();
-                                                println!("    Directory contains {} entries", count);
-                                            }
-                                        }
-                                    }
+            if let Ok(inspect_data) = serde_json::from_str::<Vec<Value>>(&stdout)
+                && let Some(container) = inspect_data.first()
+            {
+                if let Some(graph_driver) = container.get("GraphDriver") {
+                    print_plain(&format!("GraphDriver data: {}", serde_json::to_string_pretty(graph_driver).unwrap_or_default()));
+
+                    if let Some(data_obj) = graph_driver.get("Data").and_then(|data| data.as_object()) {
+                        for (key, value) in data_obj {
+                            print_plain(&format!("  {}: {}", key, value.as_str().unwrap_or("N/A")));
+
+                            // Check if any of these paths exist
+                            if let Some(path_str) = value.as_str() {
+                                let path = PathBuf::from(path_str);
+                                print_plain(&format!("    Path exists: {}", path.exists()));
+                                if path.is_dir()
+                                    && let Ok(entries) = std::fs::read_dir(&path)
+                                {
+                                    let count = entries.count();
+                                    print_plain(&format!("    Directory contains {} entries", count));
                                 }
                             }
                         }
                     }
+                }
 
-                    // Check container state
-                    if let Some(state) = container.get("State") {
-                        println!("Container State: {}", serde_json::to_string_pretty(state).unwrap_or_default());
-                    }
+                // Check container state
+                if let Some(state) = container.get("State") {
+                    print_plain(&format!("Container State: {}", serde_json::to_string_pretty(state).unwrap_or_default()));
                 }
             }
         }
 
         // Check if Docker daemon is using overlay2
-        let info_output = Command::new("docker")
-            .args(&["info", "--format", "{{.Driver}}"])
-            .output();
-
-        if let Ok(output) = info_output {
-            if output.status.success() {
-                let driver = String::from_utf8_lossy(&output.stdout).trim().to_string();
-                println!("Docker storage driver: {}", driver);
-                if driver != "overlay2" {
-                    println!("WARNING: This tool is designed for overlay2 storage driver, but Docker is using: {}", driver);
-                }
+        let info_output = run_docker(&["info", "--format", "{{.Driver}}"]);
+
+        if let Ok(output) = info_output
+            && output.status.success()
+        {
+            let driver = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            print_plain(&format!("Docker storage driver: {}", driver));
+            if driver != "overlay2" {
+                print_plain(&format!("WARNING: This tool is designed for overlay2 storage driver, but Docker is using: {}", driver));
             }
         }
 
         // List overlay2 directory to see what's available
         let overlay2_dir = PathBuf::from("/var/lib/docker/overlay2");
         if overlay2_dir.exists() {
-            println!("Overlay2 directory exists: {:?}", overlay2_dir);
+            print_plain(&format!("Overlay2 directory exists: {:?}", overlay2_dir));
             if let Ok(entries) = std::fs::read_dir(&overlay2_dir) {
                 let mut count = 0;
-                for entry in entries {
-                    if let Ok(entry) = entry {
-                        count += 1;
-                        if count <= 5 { // Show first 5 entries
-                            println!("  Found layer: {:?}", entry.file_name());
-                        }
+                for entry in entries.flatten() {
+                    count += 1;
+                    if count <= 5 { // Show first 5 entries
+                        print_plain(&format!("  Found layer: {:?}", entry.file_name()));
                     }
                 }
-                println!("  Total overlay2 layers found: {}", count);
+                print_plain(&format!("  Total overlay2 layers found: {}", count));
             }
         } else {
-            println!("Overlay2 directory does not exist: {:?}", overlay2_dir);
+            print_plain(&format!("Overlay2 directory does not exist: {:?}", overlay2_dir));
         }
 
         Err(anyhow!(
@@ -295,14 +343,66 @@ impl DockerClient {
 
     /// Check if container exists
     pub fn container_exists(&self, container_id: &str) -> Result<bool> {
-        let output = Command::new("docker")
-            .args(&["inspect", container_id])
-            .output()
+        let output = run_docker(&["inspect", container_id])
             .context("Failed to check if container exists")?;
 
         Ok(output.status.success())
     }
 
+    /// Start a stopped container
+    pub fn start_container(&self, container_id: &str) -> Result<()> {
+        self.change_container_state("start", container_id)
+    }
+
+    /// Stop a running container
+    pub fn stop_container(&self, container_id: &str) -> Result<()> {
+        self.change_container_state("stop", container_id)
+    }
+
+    /// Restart a running container
+    pub fn restart_container(&self, container_id: &str) -> Result<()> {
+        self.change_container_state("restart", container_id)
+    }
+
+    fn change_container_state(&self, action: &str, container_id: &str) -> Result<()> {
+        let output = run_docker(&[action, container_id])
+            .with_context(|| format!("Failed to execute docker {} command", action))?;
+
+        if !output.status.success() {
+            return Err(anyhow!(
+                "docker {} {} failed: {}",
+                action, container_id, String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(())
+    }
+
+    /// `docker exec` command running `command` through `sh -c` inside the container
+    pub fn exec_command(&self, container_id: &str, command: &str) -> Command {
+        let mut exec = Command::new("docker");
+        exec.args(["exec", container_id, "sh", "-c", command]);
+        exec
+    }
+
+    /// Resolve a container name or short ID to the full container ID
+    pub fn resolve_container_id(&self, container_id: &str) -> Result<String> {
+        let output = run_docker(&["inspect", "--format", "{{.Id}}", container_id])
+            .context("Failed to execute docker inspect command")?;
+
+        if !output.status.success() {
+            return Err(anyhow!("Container not found: {}", container_id));
+        }
+
+        let id = String::from_utf8(output.stdout)
+            .context("Failed to parse docker inspect output as UTF-8")?
+            .trim()
+            .to_string();
+        if id.is_empty() {
+            return Err(anyhow!("Container not found: {}", container_id));
+        }
+        Ok(id)
+    }
+
     /// Validate container state and readiness for layer operations
     pub fn validate_container_for_layer_operations(&self, container_id: &str) -> Result<()> {
         // Check if container exists
@@ -325,15 +425,15 @@ impl DockerClient {
         // Check storage driver compatibility
         let docker_info = self.get_docker_info()?;
         if docker_info.driver != "overlay2" {
-            println!("WARNING: This tool is optimized for overlay2 storage driver, but Docker is using: {}", docker_info.driver);
-            println!("Layer operations may not work correctly with other storage drivers.");
+            print_plain(&format!("WARNING: This tool is optimized for overlay2 storage driver, but Docker is using: {}", docker_info.driver));
+            print_plain("Layer operations may not work correctly with other storage drivers.");
         }
 
-        println!("Container validation passed:");
-        println!("  Container ID: {}", metadata.id);
-        println!("  Container Name: {}", metadata.name);
-        println!("  State: {}", metadata.state);
-        println!("  Storage Driver: {}", docker_info.driver);
+        print_plain("Container validation passed:");
+        print_plain(&format!("  Container ID: {}", metadata.id));
+        print_plain(&format!("  Container Name: {}", metadata.name));
+        print_plain(&format!("  State: {}", metadata.state));
+        print_plain(&format!("  Storage Driver: {}", docker_info.driver));
 
         Ok(())
     }
@@ -341,7 +441,7 @@ impl DockerClient {
     /// Parse container metadata from Docker inspect JSON
     fn parse_container_metadata(&self, container: &Value) -> Result<ContainerMetadata> {
         use chrono::{DateTime, Utc};
-        use std::collections::HashMap;
+        use std::collections::BTreeMap;
         use crate::types::MountInfo;
 
         let id = container["Id"]
@@ -367,11 +467,7 @@ impl DockerClient {
             .to_string();
 
         // Extract SHA256 from image ID
-        let image_sha256 = if image_id.starts_with("sha256:") {
-            image_id.clone()
-        } else {
-            format!("sha256:{}", image_id)
-        };
+        let image_sha256 = normalize_image_digest(&image_id);
 
         let created_str = container["Created"]
             .as_str()
@@ -392,8 +488,8 @@ impl DockerClient {
             state["StartedAt"].as_str().unwrap_or("")
         );
 
-        // Parse labels
-        let mut labels = HashMap::new();
+        // Parse labels (sorted so serialized metadata is stable)
+        let mut labels = BTreeMap::new();
         if let Some(labels_obj) = config["Labels"].as_object() {
             for (key, value) in labels_obj {
                 if let Some(value_str) = value.as_str() {
@@ -532,9 +628,160 @@ impl DockerClient {
                 id: "".to_string(),
                 expected: "".to_string(),
             },
-            security_options: Vec::new(),
+            security_options: info["SecurityOptions"].as_array()
+                .map(|options| options.iter().filter_map(|option| option.as_str()).map(str::to_string).collect())
+                .unwrap_or_default(),
         };
 
         Ok(docker_info)
     }
 }
+
+/// User Docker maps container ids to when userns-remap is set to "default"
+const DEFAULT_REMAP_USER: &str = "dockremap";
+
+/// Offset a userns-remap daemon shifts container ids by, or None if the daemon is not remapped.
+/// The offset is the start of the remap user's range in /etc/subuid and /etc/subgid, or else taken
+/// from the daemon's data root, which a remapped daemon names `<uid>.<gid>`.
+pub fn userns_remap_offset(docker_info: &DockerInfo) -> Result<Option<IdOffset>> {
+    if !docker_info.security_options.iter().any(|option| option.split(',').any(|field| field == "name=userns")) {
+        return Ok(None);
+    }
+
+    let from_subordinate_ids = subordinate_id_start("/etc/subuid").zip(subordinate_id_start("/etc/subgid"));
+    let from_root_dir = Path::new(&docker_info.docker_root_dir)
+        .file_name()
+        .and_then(|name| name.to_str()?.split_once('.'))
+        .and_then(|(uid, gid)| Some((uid.parse().ok()?, gid.parse().ok()?)));
+
+    from_subordinate_ids.or(from_root_dir)
+        .map(|(uid, gid)| Some(IdOffset { uid, gid }))
+        .ok_or_else(|| anyhow!(
+            "Docker runs with userns-remap, but its id range was found neither in /etc/subuid and /etc/subgid nor in its data root"
+        ))
+}
+
+/// First id of the remap user's range in a subordinate id file (`user:start:count` lines)
+fn subordinate_id_start(path: &str) -> Option<i64> {
+    let content = std::fs::read_to_string(path).ok()?;
+    content.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        if fields.next()? != DEFAULT_REMAP_USER {
+            return None;
+        }
+        fields.next()?.trim().parse().ok()
+    })
+}
+
+/// Exit code of an import refused because the export was taken on another architecture, and of
+/// a check whose target container the export cannot be applied to
+pub const ARCH_MISMATCH_EXIT_CODE: i32 = 3;
+
+/// Architecture names reported by different daemons and platforms for the same CPU family
+const ARCHITECTURE_ALIASES: &[(&str, &str)] = &[
+    ("x86_64", "amd64"),
+    ("x86-64", "amd64"),
+    ("aarch64", "arm64"),
+    ("arm64v8", "arm64"),
+    ("armv7l", "arm"),
+    ("armhf", "arm"),
+    ("i386", "386"),
+    ("i686", "386"),
+];
+
+/// Canonical name of an architecture, so that e.g. `x86_64` and `amd64` compare equal
+pub fn normalize_architecture(architecture: &str) -> String {
+    let architecture = architecture.trim().to_lowercase();
+    ARCHITECTURE_ALIASES.iter()
+        .find(|(alias, _)| *alias == architecture)
+        .map(|(_, canonical)| canonical.to_string())
+        .unwrap_or(architecture)
+}
+
+/// Major and minor number of a Docker or kernel version, ignoring vendor suffixes: both
+/// "20.10.21-ce" and "20.10" give (20, 10). None if the version does not start with a number.
+pub fn major_minor_version(version: &str) -> Option<(u64, u64)> {
+    let mut numbers = version.trim().trim_start_matches('v').split(|c: char| !c.is_ascii_digit());
+    let major = numbers.next()?.parse().ok()?;
+    let minor = numbers.next().and_then(|minor| minor.parse().ok()).unwrap_or(0);
+    Some((major, minor))
+}
+
+/// Whether two reported architectures refer to the same CPU family
+pub fn same_architecture(a: &str, b: &str) -> bool {
+    normalize_architecture(a) == normalize_architecture(b)
+}
+
+/// An export taken on one architecture being imported on another
+#[derive(Debug)]
+pub struct ArchitectureMismatch {
+    pub export_architecture: String,
+    pub host_architecture: String,
+}
+
+impl std::fmt::Display for ArchitectureMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Architecture mismatch: export was taken on '{}', but the target daemon runs on '{}'. \
+             Binaries in the layer will not run here; pass --skip-arch to import anyway",
+            self.export_architecture, self.host_architecture
+        )
+    }
+}
+
+impl std::error::Error for ArchitectureMismatch {}
+
+/// A container that `check --container` found an export cannot be applied to cleanly
+#[derive(Debug)]
+pub struct IncompatibleContainer {
+    pub container: String,
+    pub problems: Vec<String>,
+}
+
+impl std::fmt::Display for IncompatibleContainer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Export cannot be applied cleanly to container {}: {}",
+            self.container, self.problems.join("; ")
+        )
+    }
+}
+
+impl std::error::Error for IncompatibleContainer {}
+
+/// Image ID in the `sha256:<hex>` form used in container metadata
+pub fn normalize_image_digest(image_id: &str) -> String {
+    let image_id = image_id.trim().to_lowercase();
+    if image_id.starts_with("sha256:") {
+        image_id
+    } else {
+        format!("sha256:{}", image_id)
+    }
+}
+
+impl Default for DockerClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Run a docker command to completion, capturing its output. The command is printed at the trace
+/// level as it starts and at the debug level with its duration once it ends.
+fn run_docker<S: AsRef<OsStr>>(args: &[S]) -> std::io::Result<Output> {
+    let command_line = args.iter()
+        .map(|arg| arg.as_ref().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ");
+    print_trace(&format!("Running docker {}", command_line));
+    let started = Instant::now();
+    let output = Command::new("docker").args(args).output();
+    let outcome = match &output {
+        Ok(output) if output.status.success() => "succeeded".to_string(),
+        Ok(output) => format!("failed ({})", output.status),
+        Err(error) => format!("could not run ({})", error),
+    };
+    print_debug(&format!("docker {} {} in {:.2}s", command_line, outcome, started.elapsed().as_secs_f64()));
+    output
+}
@@ -0,0 +1,317 @@
+use anyhow::Result;
+use std::fmt;
+use std::str::FromStr;
+
+/// Annotation recording which exclude presets an export was created with
+pub const EXCLUDE_PRESETS_ANNOTATION: &str = "layer-tool.exclude-presets";
+
+/// Package manager and language tool caches
+const CACHES_PATTERNS: &[&str] = &[
+    "/var/cache/apt",
+    "/var/lib/apt/lists",
+    "/var/cache/yum",
+    "/var/cache/dnf",
+    "/var/cache/apk",
+    "/root/.cache/pip",
+    "/home/*/.cache/pip",
+    "/root/.npm/_cacache",
+    "/home/*/.npm/_cacache",
+    "/root/.cache/yarn",
+    "/home/*/.cache/yarn",
+    "/usr/local/share/.cache/yarn",
+];
+
+/// System and application log directories
+const LOGS_PATTERNS: &[&str] = &["/var/log"];
+
+/// Scratch directories
+const TMP_PATTERNS: &[&str] = &["/tmp", "/var/tmp"];
+
+/// Curated list of exclude patterns selectable with `--exclude-preset`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExcludePreset {
+    Caches,
+    Logs,
+    Tmp,
+}
+
+impl ExcludePreset {
+    pub const ALL: [ExcludePreset; 3] = [ExcludePreset::Caches, ExcludePreset::Logs, ExcludePreset::Tmp];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExcludePreset::Caches => "caches",
+            ExcludePreset::Logs => "logs",
+            ExcludePreset::Tmp => "tmp",
+        }
+    }
+
+    /// Patterns the preset expands to (anchored at the layer root)
+    pub fn patterns(&self) -> &'static [&'static str] {
+        match self {
+            ExcludePreset::Caches => CACHES_PATTERNS,
+            ExcludePreset::Logs => LOGS_PATTERNS,
+            ExcludePreset::Tmp => TMP_PATTERNS,
+        }
+    }
+}
+
+impl fmt::Display for ExcludePreset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for ExcludePreset {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        ExcludePreset::ALL.into_iter()
+            .find(|preset| preset.name() == value)
+            .ok_or_else(|| {
+                let names: Vec<&str> = ExcludePreset::ALL.iter().map(|preset| preset.name()).collect();
+                format!("unknown exclude preset '{}' (expected one of: {})", value, names.join(", "))
+            })
+    }
+}
+
+/// Compiled exclude patterns matched against layer-relative paths.
+///
+/// Patterns are globs where `*` and `?` match within one path component and `**` matches any
+/// number of components. A pattern containing `/` is anchored at the layer root; one without
+/// matches a name at any depth. A match on a directory excludes everything below it, and
+/// components are compared whole, so `/var/log` does not match `var/logical`.
+#[derive(Debug, Clone, Default)]
+pub struct ExcludeSet {
+    patterns: Vec<Vec<String>>,
+}
+
+impl ExcludeSet {
+    /// Combine user patterns with the patterns of the given presets
+    pub fn new(patterns: &[String], presets: &[ExcludePreset]) -> Result<Self> {
+        let preset_patterns = presets.iter().flat_map(|preset| preset.patterns().iter().copied());
+        let patterns = patterns.iter().map(String::as_str)
+            .chain(preset_patterns)
+            .map(compile_pattern)
+            .collect::<Result<_>>()?;
+        Ok(Self { patterns })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Whether a layer-relative path, or one of its parent directories, is excluded
+    pub fn is_excluded(&self, relative_path: &str) -> bool {
+        let components: Vec<&str> = relative_path.split('/')
+            .filter(|component| !component.is_empty() && *component != ".")
+            .collect();
+        !components.is_empty()
+            && self.patterns.iter().any(|pattern| matches_prefix(pattern, &components))
+    }
+}
+
+/// Layer-relative path prefixes selected with `import --only`. A path is selected when it is one of
+/// the prefixes or lies below one; components are compared whole.
+#[derive(Debug, Clone, Default)]
+pub struct PathSelection {
+    prefixes: Vec<Vec<String>>,
+}
+
+impl PathSelection {
+    pub fn new(prefixes: &[String]) -> Result<Self> {
+        let prefixes = prefixes.iter()
+            .map(|prefix| {
+                let components = path_components(prefix);
+                if components.is_empty() {
+                    return Err(anyhow::anyhow!("Invalid --only path '{}': it selects the whole layer", prefix));
+                }
+                if components.contains(&"..") {
+                    return Err(anyhow::anyhow!("Invalid --only path '{}': '..' is not allowed", prefix));
+                }
+                Ok(components.into_iter().map(str::to_string).collect())
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { prefixes })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prefixes.is_empty()
+    }
+
+    /// The selected prefixes in layer-relative form
+    pub fn prefixes(&self) -> Vec<String> {
+        self.prefixes.iter().map(|prefix| prefix.join("/")).collect()
+    }
+
+    /// Whether a layer-relative path is one of the prefixes or lies below one
+    pub fn is_selected(&self, relative_path: &str) -> bool {
+        let components = path_components(relative_path);
+        self.prefixes.iter().any(|prefix| is_under(prefix, &components))
+    }
+
+    /// Prefixes none of `relative_paths` falls under
+    pub fn unmatched(&self, relative_paths: &[String]) -> Vec<String> {
+        self.prefixes.iter()
+            .filter(|prefix| !relative_paths.iter().any(|path| is_under(prefix, &path_components(path))))
+            .map(|prefix| prefix.join("/"))
+            .collect()
+    }
+}
+
+/// Whether a path (as components) is `prefix` or lies below it
+fn is_under(prefix: &[String], components: &[&str]) -> bool {
+    prefix.len() <= components.len() && prefix.iter().zip(components).all(|(expected, component)| expected == component)
+}
+
+/// Non-empty components of a layer-relative path, ignoring `.`
+fn path_components(relative_path: &str) -> Vec<&str> {
+    relative_path.trim().split('/')
+        .filter(|component| !component.is_empty() && *component != ".")
+        .collect()
+}
+
+/// Split a pattern into components; unanchored patterns get a leading `**`
+fn compile_pattern(pattern: &str) -> Result<Vec<String>> {
+    let trimmed = pattern.trim();
+    let anchored = trimmed.trim_end_matches('/').contains('/');
+    let mut components: Vec<String> = trimmed.split('/')
+        .filter(|component| !component.is_empty() && *component != ".")
+        .map(str::to_string)
+        .collect();
+    if components.is_empty() {
+        return Err(anyhow::anyhow!("Invalid exclude pattern '{}': it matches the whole layer", pattern));
+    }
+    if components.iter().any(|component| component == "..") {
+        return Err(anyhow::anyhow!("Invalid exclude pattern '{}': '..' is not allowed", pattern));
+    }
+    if !anchored {
+        components.insert(0, "**".to_string());
+    }
+    Ok(components)
+}
+
+/// Whether the pattern matches the path or one of its ancestors
+fn matches_prefix(pattern: &[String], path: &[&str]) -> bool {
+    let Some((first, rest)) = pattern.split_first() else {
+        return true;
+    };
+    if first == "**" {
+        return (0..=path.len()).any(|skip| matches_prefix(rest, &path[skip..]));
+    }
+    match path.split_first() {
+        Some((component, path_rest)) => matches_component(first, component) && matches_prefix(rest, path_rest),
+        None => false,
+    }
+}
+
+/// Match one path component against a glob with `*` and `?`
+pub(crate) fn matches_component(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            // Let the last `*` swallow one more character
+            p = star + 1;
+            n = matched + 1;
+            backtrack = Some((star, n));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preset_set(preset: ExcludePreset) -> ExcludeSet {
+        ExcludeSet::new(&[], &[preset]).unwrap()
+    }
+
+    #[test]
+    fn caches_preset_matches_cache_directories_only() {
+        let caches = preset_set(ExcludePreset::Caches);
+        for path in [
+            "var/cache/apt/archives/curl_8.5.0_amd64.deb",
+            "var/lib/apt/lists/deb.debian.org_dists_bookworm_InRelease",
+            "var/cache/yum",
+            "var/cache/dnf/fedora.solv",
+            "var/cache/apk/APKINDEX.tar.gz",
+            "root/.cache/pip/http/a/b",
+            "home/app/.cache/pip/wheels/x.whl",
+            "root/.npm/_cacache/index-v5/00",
+            "home/node/.npm/_cacache/content-v2/sha512",
+            "root/.cache/yarn/v6",
+            "home/app/.cache/yarn",
+            "usr/local/share/.cache/yarn/v6/npm-react",
+        ] {
+            assert!(caches.is_excluded(path), "{} should be excluded", path);
+        }
+        for path in [
+            "var/cache",
+            "var/cache/nginx/proxy",
+            "var/lib/apt/extended_states",
+            "var/lib/dpkg/status",
+            "root/.cache/go-build",
+            "home/app/.npmrc",
+            "home/app/src/.cache/pip",
+            "home/app/.npm/_logs/debug.log",
+            "usr/share/yarn/bin/yarn",
+        ] {
+            assert!(!caches.is_excluded(path), "{} should be kept", path);
+        }
+    }
+
+    #[test]
+    fn logs_preset_matches_whole_components() {
+        let logs = preset_set(ExcludePreset::Logs);
+        assert!(logs.is_excluded("var/log"));
+        assert!(logs.is_excluded("var/log/nginx/access.log"));
+        assert!(logs.is_excluded("/var/log/syslog"));
+        for path in ["var/logical", "var/logical/volume", "var/lib/log", "app/var/log/app.log", "var"] {
+            assert!(!logs.is_excluded(path), "{} should be kept", path);
+        }
+    }
+
+    #[test]
+    fn tmp_preset_matches_scratch_directories_only() {
+        let tmp = preset_set(ExcludePreset::Tmp);
+        for path in ["tmp", "tmp/.X11-unix/X0", "var/tmp/systemd-private/tmp"] {
+            assert!(tmp.is_excluded(path), "{} should be excluded", path);
+        }
+        for path in ["tmpfiles", "usr/lib/tmpfiles.d/tmp.conf", "var/tmpdata", "app/tmp/upload"] {
+            assert!(!tmp.is_excluded(path), "{} should be kept", path);
+        }
+    }
+
+    #[test]
+    fn presets_combine_with_user_patterns() {
+        let excludes = ExcludeSet::new(&["*.pyc".to_string()], &[ExcludePreset::Logs, ExcludePreset::Tmp]).unwrap();
+        assert!(excludes.is_excluded("app/__pycache__/main.cpython-312.pyc"));
+        assert!(excludes.is_excluded("var/log/dpkg.log"));
+        assert!(excludes.is_excluded("tmp/upload"));
+        assert!(!excludes.is_excluded("app/main.py"));
+        assert!(!ExcludeSet::new(&[], &[]).unwrap().is_excluded("var/log"));
+    }
+
+    #[test]
+    fn preset_names_parse() {
+        for preset in ExcludePreset::ALL {
+            assert_eq!(preset.name().parse::<ExcludePreset>(), Ok(preset));
+        }
+        let error = "cache".parse::<ExcludePreset>().unwrap_err();
+        assert_eq!(error, "unknown exclude preset 'cache' (expected one of: caches, logs, tmp)");
+    }
+}
@@ -0,0 +1,141 @@
+use anyhow::{Context, Result};
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::output::{print_plain, print_warning};
+
+/// Interval between checks for hook completion
+const HOOK_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Seconds an import's post-check may run by default (import --post-check-timeout)
+pub const DEFAULT_POST_CHECK_TIMEOUT: u64 = 60;
+
+/// Run a hook command through `sh -c` with extra environment variables.
+/// Hook output is streamed line by line with a `[name]` prefix; the hook is killed if it
+/// runs longer than `timeout`.
+pub fn run_hook(name: &str, command: &str, env: &[(&str, &str)], timeout: Option<Duration>) -> Result<()> {
+    print_plain(&format!("Running {}: {}", name, command));
+
+    let mut shell = Command::new("sh");
+    shell.args(["-c", command]).envs(env.iter().copied());
+    let run = run_command(name, shell, timeout)?;
+    let Some(status) = run.status else {
+        return Err(anyhow::anyhow!("{} timed out after {}s", name, timeout.unwrap_or_default().as_secs()));
+    };
+
+    if !status.success() {
+        return Err(match status.code() {
+            Some(code) => anyhow::anyhow!("{} failed with exit code {}", name, code),
+            None => anyhow::anyhow!("{} was terminated by a signal", name),
+        });
+    }
+
+    Ok(())
+}
+
+/// How a command run by [`run_command`] ended
+pub struct CommandRun {
+    /// Exit status; None if the command was killed for running past its timeout
+    pub status: Option<ExitStatus>,
+    /// Lines of stdout and stderr, in the order they were read
+    pub output: Vec<String>,
+}
+
+/// Run `command`, echoing its output line by line with a `[name]` prefix and collecting it.
+/// The command is killed if it runs longer than `timeout`.
+pub fn run_command(name: &str, mut command: Command, timeout: Option<Duration>) -> Result<CommandRun> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to start {}", name))?;
+
+    let output = Arc::new(Mutex::new(Vec::new()));
+    let readers = [
+        child.stdout.take().map(|stdout| forward_lines(name, stdout, Arc::clone(&output))),
+        child.stderr.take().map(|stderr| forward_lines(name, stderr, Arc::clone(&output))),
+    ];
+
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().with_context(|| format!("Failed to wait for {}", name))? {
+            break Some(status);
+        }
+        if let Some(timeout) = timeout
+            && started.elapsed() >= timeout
+        {
+            let _ = child.kill();
+            let _ = child.wait();
+            break None;
+        }
+        std::thread::sleep(HOOK_POLL_INTERVAL);
+    };
+
+    // A killed command's grandchildren may still hold the pipes open, so only wait for the readers
+    // of commands that exited
+    if status.is_some() {
+        for reader in readers.into_iter().flatten() {
+            let _ = reader.join();
+        }
+    }
+
+    let output = output.lock().map(|lines| lines.clone()).unwrap_or_default();
+    Ok(CommandRun { status, output })
+}
+
+/// Echo each line of a command's output stream with its name as prefix, collecting the lines
+fn forward_lines<R: Read + Send + 'static>(name: &str, stream: R, lines: Arc<Mutex<Vec<String>>>) -> JoinHandle<()> {
+    let prefix = format!("[{}]", name);
+    std::thread::spawn(move || {
+        for line in BufReader::new(stream).lines().map_while(Result::ok) {
+            print_plain(&format!("{} {}", prefix, line));
+            if let Ok(mut lines) = lines.lock() {
+                lines.push(line);
+            }
+        }
+    })
+}
+
+/// Post-operation hook that always runs: explicitly via `finish`, or with
+/// `LAYER_TOOL_RESULT=failure` when dropped because the operation bailed out early.
+pub struct PostHook {
+    command: Option<String>,
+    env: Vec<(String, String)>,
+    timeout: Option<Duration>,
+}
+
+impl PostHook {
+    pub fn new(command: Option<String>, env: Vec<(String, String)>, timeout: Option<Duration>) -> Self {
+        Self { command, env, timeout }
+    }
+
+    /// Run the hook with the operation's result
+    pub fn finish(mut self, success: bool) -> Result<()> {
+        self.run(success)
+    }
+
+    fn run(&mut self, success: bool) -> Result<()> {
+        let Some(command) = self.command.take() else {
+            return Ok(());
+        };
+
+        let result = if success { "success" } else { "failure" };
+        let mut env: Vec<(&str, &str)> = self.env.iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+        env.push(("LAYER_TOOL_RESULT", result));
+        run_hook("post-hook", &command, &env, self.timeout)
+    }
+}
+
+impl Drop for PostHook {
+    fn drop(&mut self) {
+        if let Err(error) = self.run(false) {
+            print_warning(&format!("{:#}", error));
+        }
+    }
+}
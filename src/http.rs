@@ -0,0 +1,176 @@
+use anyhow::Result;
+use std::path::Path;
+
+use crate::cancel::CancellationToken;
+use crate::types::HttpHeader;
+
+/// URL schemes selecting an HTTP(S) server as an import or check source
+const HTTP_SCHEMES: &[&str] = &["http://", "https://"];
+
+/// Check whether a path refers to an HTTP(S) URL
+pub fn is_http_url(path: &str) -> bool {
+    HTTP_SCHEMES.iter().any(|scheme| path.starts_with(scheme))
+}
+
+/// Fail unless this build can download over HTTP(S)
+pub fn ensure_available() -> Result<()> {
+    if cfg!(feature = "net") {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "http(s):// inputs require layer-tool to be built with the `net` feature (cargo build --features net)"
+        ))
+    }
+}
+
+/// Download `url` into `output_path` with a progress bar, sending `headers` with every request and
+/// resuming with Range requests when the connection drops; returns the number of bytes downloaded
+#[cfg(feature = "net")]
+pub fn download_to_file(url: &str, headers: &[HttpHeader], output_path: &Path, cancel: &CancellationToken) -> Result<u64> {
+    client::download_to_file(url, headers, output_path, cancel)
+}
+
+#[cfg(not(feature = "net"))]
+pub fn download_to_file(_url: &str, _headers: &[HttpHeader], _output_path: &Path, _cancel: &CancellationToken) -> Result<u64> {
+    ensure_available().map(|_| 0)
+}
+
+#[cfg(feature = "net")]
+mod client {
+    use anyhow::{Context, Result};
+    use std::fs::File;
+    use std::io::{Read, Seek, SeekFrom, Write};
+    use std::path::Path;
+    use std::time::Duration;
+    use indicatif::ProgressBar;
+
+    use crate::cancel::{CancellableReader, CancellationToken};
+    use crate::output::{byte_progress_bar, finish_progress, print_warning};
+    use crate::types::HttpHeader;
+    use crate::utils::format_file_size;
+
+    /// Attempts at a download, counting the first, before a dropped connection is an error
+    const MAX_ATTEMPTS: u32 = 5;
+
+    /// How long to wait for the server to connect or send more data
+    const TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// Why an attempt at the download ended early
+    enum Interrupted {
+        /// The connection failed or dropped; the download can resume where it stopped
+        Retry(anyhow::Error),
+        /// The server refused the request, or writing the file failed
+        Fatal(anyhow::Error),
+    }
+
+    /// A download in progress: the file being written and how much of it has arrived
+    struct Download {
+        file: File,
+        downloaded: u64,
+        total: Option<u64>,
+        progress: ProgressBar,
+    }
+
+    pub fn download_to_file(url: &str, headers: &[HttpHeader], output_path: &Path, cancel: &CancellationToken) -> Result<u64> {
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(TIMEOUT)
+            .timeout_read(TIMEOUT)
+            .build();
+        let file = File::create(output_path)
+            .with_context(|| format!("Failed to create file: {:?}", output_path))?;
+        let mut download = Download {
+            file,
+            downloaded: 0,
+            total: None,
+            progress: byte_progress_bar("Downloading"),
+        };
+
+        for attempt in 1..=MAX_ATTEMPTS {
+            cancel.check()?;
+            match download.fetch(&agent, url, headers, cancel) {
+                Ok(()) => {
+                    finish_progress(&download.progress);
+                    return Ok(download.downloaded);
+                }
+                Err(Interrupted::Fatal(error)) => return Err(error),
+                Err(Interrupted::Retry(error)) => {
+                    cancel.check()?;
+                    if attempt == MAX_ATTEMPTS {
+                        return Err(error.context(format!("Download of {} failed after {} attempts", url, MAX_ATTEMPTS)));
+                    }
+                    download.progress.suspend(|| print_warning(&format!(
+                        "Download interrupted after {} ({:#}); resuming (attempt {}/{})",
+                        format_file_size(download.downloaded), error, attempt + 1, MAX_ATTEMPTS
+                    )));
+                    std::thread::sleep(Duration::from_secs(attempt as u64));
+                }
+            }
+        }
+        unreachable!("the last attempt always returns")
+    }
+
+    impl Download {
+        /// Request the rest of the file from what has already arrived and append it
+        fn fetch(&mut self, agent: &ureq::Agent, url: &str, headers: &[HttpHeader], cancel: &CancellationToken) -> Result<(), Interrupted> {
+            let mut request = agent.get(url);
+            for header in headers {
+                request = request.set(&header.name, &header.value);
+            }
+            if self.downloaded > 0 {
+                request = request.set("Range", &format!("bytes={}-", self.downloaded));
+            }
+
+            let response = match request.call() {
+                Ok(response) => response,
+                Err(ureq::Error::Status(code, response)) => {
+                    return Err(Interrupted::Fatal(anyhow::anyhow!(
+                        "HTTP {} {} from {}", code, response.status_text(), url
+                    )));
+                }
+                Err(error) => return Err(Interrupted::Retry(anyhow::Error::new(error))),
+            };
+
+            if response.status() == 206 {
+                self.total = response.header("Content-Range")
+                    .and_then(|range| range.rsplit_once('/'))
+                    .and_then(|(_, size)| size.parse().ok())
+                    .or(self.total);
+            } else {
+                // The server sent the whole file, whether or not a range was asked for
+                if self.downloaded > 0 {
+                    self.file.set_len(0)
+                        .and_then(|_| self.file.seek(SeekFrom::Start(0)))
+                        .map_err(|error| Interrupted::Fatal(anyhow::Error::new(error).context("Failed to restart download")))?;
+                    self.downloaded = 0;
+                    self.progress.set_position(0);
+                }
+                self.total = response.header("Content-Length").and_then(|length| length.parse().ok());
+            }
+            if let Some(total) = self.total {
+                self.progress.set_length(total);
+            }
+
+            let mut reader = CancellableReader::new(response.into_reader(), cancel);
+            let mut buffer = vec![0; 64 * 1024];
+            loop {
+                let bytes_read = match reader.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(bytes_read) => bytes_read,
+                    Err(error) => return Err(Interrupted::Retry(anyhow::Error::new(error).context("Connection dropped"))),
+                };
+                self.file.write_all(&buffer[..bytes_read])
+                    .map_err(|error| Interrupted::Fatal(anyhow::Error::new(error).context("Failed to write downloaded data")))?;
+                self.downloaded += bytes_read as u64;
+                self.progress.inc(bytes_read as u64);
+            }
+
+            // A connection closed early can look like a clean end of the body
+            match self.total {
+                Some(total) if self.downloaded < total => Err(Interrupted::Retry(anyhow::anyhow!(
+                    "Connection closed after {} of {} bytes", self.downloaded, total
+                ))),
+                _ => Ok(()),
+            }
+        }
+    }
+}
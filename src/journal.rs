@@ -0,0 +1,300 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::backup::{short_id, LayerBackup};
+use crate::types::{Checksum, ChecksumAlgorithm};
+use crate::utils::calculate_file_digest;
+
+/// Suffix of the journal an import keeps next to its backups while it modifies a layer
+const JOURNAL_SUFFIX: &str = ".import-journal.jsonl";
+
+/// Journal format written by this version; others are not resumed
+pub const JOURNAL_VERSION: u32 = 1;
+
+/// One extracted file in this many is hashed, so a resume can sample contents as well as sizes
+const HASH_SAMPLE_INTERVAL: usize = 64;
+
+/// Buffered records are written out at least this often
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What an import journal was started for, written as its first line
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalHeader {
+    pub journal_version: u32,
+    pub started: DateTime<Utc>,
+    /// The input as given on the command line
+    pub input_path: String,
+    /// SHA256 of the export file being imported
+    pub input_checksum: String,
+    pub container_id: String,
+    pub target_upper_path: PathBuf,
+    /// Backup taken before the layer was modified, rolled back to or rotated once the import ends
+    pub backup: Option<LayerBackup>,
+}
+
+/// A layer entry that was completely written, with what a resume checks it against
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CompletedEntry {
+    path: String,
+    size: u64,
+    mtime: i64,
+    /// Content digest, in the algorithm of the export's manifest when the extractor computed it
+    #[serde(rename = "sha256", default, skip_serializing_if = "Option::is_none")]
+    digest: Option<Checksum>,
+}
+
+/// One line of the journal
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum JournalRecord {
+    Started(JournalHeader),
+    Phase(String),
+    Entry(CompletedEntry),
+    /// Layer-relative path of a whiteout entry that was applied
+    Whiteout(String),
+}
+
+/// Append-only record of an import in progress: its phase and the entries fully extracted so far,
+/// so an interrupted import can be continued with `import --resume`. Records are buffered and
+/// written at least every second; the journal is removed once the import is over.
+pub struct ImportJournal {
+    path: PathBuf,
+    header: JournalHeader,
+    phase: String,
+    completed: HashMap<String, CompletedEntry>,
+    whiteouts: HashSet<String>,
+    writer: BufWriter<File>,
+    unsampled_files: usize,
+    last_flush: Instant,
+}
+
+impl ImportJournal {
+    /// Where an import into `upper_path` keeps its journal: next to its backups, i.e. in
+    /// `backup_dir` when given and beside the upper layer otherwise
+    pub fn path_for(upper_path: &Path, backup_dir: Option<&Path>, container_id: &str) -> PathBuf {
+        match backup_dir {
+            Some(backup_dir) => backup_dir.join(format!("{}{}", short_id(container_id), JOURNAL_SUFFIX)),
+            None => {
+                let mut name = upper_path.as_os_str().to_owned();
+                name.push(JOURNAL_SUFFIX);
+                PathBuf::from(name)
+            }
+        }
+    }
+
+    /// Start a journal at `path`, replacing any left by an earlier import
+    pub fn create(path: &Path, header: JournalHeader) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {:?}", parent))?;
+        }
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create import journal: {:?}", path))?;
+        let mut journal = Self {
+            path: path.to_path_buf(),
+            header: header.clone(),
+            phase: String::new(),
+            completed: HashMap::new(),
+            whiteouts: HashSet::new(),
+            writer: BufWriter::new(file),
+            unsampled_files: 0,
+            last_flush: Instant::now(),
+        };
+        journal.append(&JournalRecord::Started(header))?;
+        journal.flush()?;
+        Ok(journal)
+    }
+
+    /// Read the journal at `path` to continue appending to it. A last line cut short by the
+    /// interruption is ignored; its entry is simply extracted again.
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open import journal: {:?}", path))?;
+        let mut lines = BufReader::new(file).lines().peekable();
+        let header = match lines.next().transpose()?.map(|line| serde_json::from_str(&line)) {
+            Some(Ok(JournalRecord::Started(header))) => header,
+            _ => return Err(anyhow::anyhow!("Import journal {:?} is not a layer-tool import journal", path)),
+        };
+        if header.journal_version != JOURNAL_VERSION {
+            return Err(anyhow::anyhow!(
+                "Import journal {:?} has unsupported version {} (expected {})",
+                path, header.journal_version, JOURNAL_VERSION
+            ));
+        }
+
+        let mut phase = String::new();
+        let mut completed = HashMap::new();
+        let mut whiteouts = HashSet::new();
+        while let Some(line) = lines.next() {
+            let line = line.with_context(|| format!("Failed to read import journal: {:?}", path))?;
+            let record = match serde_json::from_str(&line) {
+                Ok(record) => record,
+                Err(_) if lines.peek().is_none() => break,
+                Err(e) => return Err(e).with_context(|| format!("Failed to parse import journal: {:?}", path)),
+            };
+            match record {
+                JournalRecord::Started(_) => return Err(anyhow::anyhow!("Import journal {:?} was started twice", path)),
+                JournalRecord::Phase(name) => phase = name,
+                JournalRecord::Entry(entry) => {
+                    completed.insert(entry.path.clone(), entry);
+                }
+                JournalRecord::Whiteout(path) => {
+                    whiteouts.insert(path);
+                }
+            }
+        }
+
+        let file = OpenOptions::new()
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open import journal: {:?}", path))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            header,
+            phase,
+            completed,
+            whiteouts,
+            writer: BufWriter::new(file),
+            unsampled_files: 0,
+            last_flush: Instant::now(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn header(&self) -> &JournalHeader {
+        &self.header
+    }
+
+    /// The import phase last started; empty if the import stopped before extracting anything
+    pub fn phase(&self) -> &str {
+        &self.phase
+    }
+
+    /// Number of entries recorded as fully extracted
+    pub fn completed_count(&self) -> usize {
+        self.completed.len()
+    }
+
+    pub fn completed_paths(&self) -> impl Iterator<Item = &str> {
+        self.completed.keys().map(String::as_str)
+    }
+
+    pub fn is_completed(&self, relative: &str) -> bool {
+        self.completed.contains_key(relative)
+    }
+
+    pub fn is_whiteout_applied(&self, relative: &str) -> bool {
+        self.whiteouts.contains(relative)
+    }
+
+    /// Record that the import moved on to `phase`, writing it out immediately
+    pub fn set_phase(&mut self, phase: &str) -> Result<()> {
+        self.phase = phase.to_string();
+        self.append(&JournalRecord::Phase(phase.to_string()))?;
+        self.flush()
+    }
+
+    /// Record the entry at `relative`, written to `destination`, as fully extracted. `digest` is the
+    /// content digest of a regular file if the extractor computed one; otherwise a sample of files
+    /// is hashed here.
+    pub fn record_entry(&mut self, relative: &str, destination: &Path, digest: Option<Checksum>) -> Result<()> {
+        let metadata = destination.symlink_metadata()
+            .with_context(|| format!("Failed to read metadata: {:?}", destination))?;
+        let digest = match digest {
+            Some(digest) => Some(digest),
+            None if metadata.is_file() => {
+                self.unsampled_files += 1;
+                if self.unsampled_files >= HASH_SAMPLE_INTERVAL {
+                    self.unsampled_files = 0;
+                    Some(calculate_file_digest(destination, ChecksumAlgorithm::Sha256)?)
+                } else {
+                    None
+                }
+            }
+            None => None,
+        };
+        let entry = CompletedEntry {
+            path: relative.to_string(),
+            size: if metadata.is_file() { metadata.len() } else { 0 },
+            mtime: metadata.mtime(),
+            digest,
+        };
+        self.append(&JournalRecord::Entry(entry.clone()))?;
+        self.completed.insert(entry.path.clone(), entry);
+        self.flush_if_due()
+    }
+
+    /// Record that the whiteout entry at `relative` was applied
+    pub fn record_whiteout(&mut self, relative: &str) -> Result<()> {
+        self.append(&JournalRecord::Whiteout(relative.to_string()))?;
+        self.whiteouts.insert(relative.to_string());
+        self.flush_if_due()
+    }
+
+    /// Check the entries recorded as extracted against the layer in `root`: every entry by size
+    /// and modification time, and the sampled ones by content too. Entries that no longer match
+    /// are forgotten, so they are extracted again. Returns a description of each.
+    pub fn verify_completed(&mut self, root: &Path) -> Result<Vec<String>> {
+        let mut mismatches = Vec::new();
+        for entry in self.completed.values() {
+            let path = root.join(&entry.path);
+            let problem = match path.symlink_metadata() {
+                Err(_) => Some("missing".to_string()),
+                Ok(metadata) if metadata.is_file() && metadata.len() != entry.size => {
+                    Some(format!("size {} (journal: {})", metadata.len(), entry.size))
+                }
+                Ok(metadata) if metadata.mtime() != entry.mtime => Some("modification time changed".to_string()),
+                Ok(_) => match &entry.digest {
+                    Some(expected) if calculate_file_digest(&path, expected.algorithm)? != *expected => Some("content changed".to_string()),
+                    _ => None,
+                },
+            };
+            if let Some(problem) = problem {
+                mismatches.push((entry.path.clone(), problem));
+            }
+        }
+
+        for (path, _) in &mismatches {
+            self.completed.remove(path);
+        }
+        Ok(mismatches.into_iter().map(|(path, problem)| format!("{}: {}", path, problem)).collect())
+    }
+
+    /// Delete the journal once the import it tracks has finished, or was rolled back
+    pub fn remove(self) -> Result<()> {
+        drop(self.writer);
+        std::fs::remove_file(&self.path)
+            .with_context(|| format!("Failed to remove import journal: {:?}", self.path))
+    }
+
+    fn append(&mut self, record: &JournalRecord) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, record)
+            .context("Failed to serialize import journal record")?;
+        self.writer.write_all(b"\n")
+            .with_context(|| format!("Failed to write import journal: {:?}", self.path))
+    }
+
+    fn flush_if_due(&mut self) -> Result<()> {
+        if self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush()
+            .with_context(|| format!("Failed to write import journal: {:?}", self.path))?;
+        self.last_flush = Instant::now();
+        Ok(())
+    }
+}
@@ -1,9 +1,30 @@
+pub mod attributes;
+pub mod backup;
+pub mod cancel;
 pub mod commands;
+pub mod crypto;
+pub mod diagnostics;
 pub mod docker;
+pub mod excludes;
+pub mod hooks;
+pub mod http;
+pub mod journal;
+pub mod lock;
 pub mod output;
+pub mod s3;
+pub mod seekable;
+pub mod signing;
+pub mod ssh;
+pub mod stream;
+#[cfg(test)]
+mod testing;
+pub mod timing;
+pub mod tuning;
 pub mod types;
 pub mod utils;
+pub mod verify;
+pub mod workspace;
 
-pub use commands::{CheckCommand, ExportCommand, ImportCommand};
-pub use types::{CheckOptions, ContainerMetadata, DockerInfo, ExportData};
+pub use commands::{CheckCommand, CleanCommand, CloneCommand, DiffCommand, ExplainCommand, ExportCommand, ImportCommand, KeygenCommand, RestoreCommand, SignCommand, VerifyBackupCommand};
+pub use types::{CheckOptions, CloneOptions, ContainerMetadata, DiffOptions, DockerInfo, EnvironmentInfo, ExportData, ExportDataV2, ExportMetadata, ExportOptions, ImportOptions, LayerKind, Manifest, RestoreOptions, VerifyBackupOptions};
 pub use docker::DockerClient;
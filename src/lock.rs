@@ -0,0 +1,142 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::cancel::CancellationToken;
+use crate::output::print_warning;
+
+/// Preferred directory for container lock files
+const LOCK_DIR: &str = "/run/layer-tool";
+
+/// Interval between attempts while waiting for a lock
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Holder details written into the lock file for other invocations to report
+#[derive(Debug, Serialize, Deserialize)]
+struct LockHolder {
+    pid: u32,
+    operation: String,
+    /// Seconds since the Unix epoch
+    started: u64,
+}
+
+/// Advisory per-container lock held for the duration of an export or import.
+/// The flock is released when the lock is dropped or the process exits, whatever the exit path.
+pub struct ContainerLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl ContainerLock {
+    /// Lock `full_container_id` for `operation`. Fails immediately if another process holds the
+    /// lock, or after `wait` if given.
+    pub fn acquire(full_container_id: &str, operation: &str, wait: Option<Duration>, cancel: &CancellationToken) -> Result<Self> {
+        let path = lock_path(full_container_id)?;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("Failed to open lock file: {:?}", path))?;
+
+        let started = Instant::now();
+        let mut waiting = false;
+        loop {
+            match file.try_lock() {
+                Ok(()) => break,
+                Err(TryLockError::WouldBlock) => {}
+                Err(TryLockError::Error(e)) => {
+                    return Err(e).with_context(|| format!("Failed to lock {:?}", path));
+                }
+            }
+
+            let holder = describe_holder(full_container_id, &path);
+            match wait {
+                Some(wait) if started.elapsed() < wait => {
+                    if !waiting {
+                        print_warning(&format!("{}; waiting up to {}s", holder, wait.as_secs()));
+                        waiting = true;
+                    }
+                    cancel.check()?;
+                    std::thread::sleep(LOCK_POLL_INTERVAL);
+                }
+                Some(wait) => {
+                    return Err(anyhow::anyhow!("Timed out after {}s waiting for lock: {}", wait.as_secs(), holder));
+                }
+                None => {
+                    return Err(anyhow::anyhow!("{}. Use --wait <secs> to wait for it", holder));
+                }
+            }
+        }
+
+        let mut lock = Self { file, path };
+        lock.write_holder(operation)?;
+        Ok(lock)
+    }
+
+    fn write_holder(&mut self, operation: &str) -> Result<()> {
+        let holder = LockHolder {
+            pid: std::process::id(),
+            operation: operation.to_string(),
+            started: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+        };
+        let contents = serde_json::to_string(&holder)
+            .context("Failed to serialize lock holder")?;
+
+        self.file.set_len(0)
+            .and_then(|_| self.file.seek(SeekFrom::Start(0)))
+            .and_then(|_| self.file.write_all(contents.as_bytes()))
+            .and_then(|_| self.file.flush())
+            .with_context(|| format!("Failed to write lock file: {:?}", self.path))
+    }
+}
+
+impl Drop for ContainerLock {
+    fn drop(&mut self) {
+        // The file is kept: removing it would let a waiter lock an unlinked inode
+        let _ = self.file.set_len(0);
+        let _ = self.file.unlock();
+    }
+}
+
+/// Lock file for a container under /run/layer-tool, or the temp directory when /run is not writable
+fn lock_path(full_container_id: &str) -> Result<PathBuf> {
+    let file_name = format!("{}.lock", full_container_id);
+    let run_dir = Path::new(LOCK_DIR);
+    if std::fs::create_dir_all(run_dir).is_ok() && is_writable_dir(run_dir) {
+        return Ok(run_dir.join(file_name));
+    }
+
+    let temp_dir = std::env::temp_dir().join("layer-tool");
+    std::fs::create_dir_all(&temp_dir)
+        .with_context(|| format!("Failed to create lock directory: {:?}", temp_dir))?;
+    Ok(temp_dir.join(file_name))
+}
+
+fn is_writable_dir(dir: &Path) -> bool {
+    tempfile::tempfile_in(dir).is_ok()
+}
+
+/// Human-readable description of who holds a container's lock
+fn describe_holder(full_container_id: &str, path: &Path) -> String {
+    let holder = std::fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| serde_json::from_str::<LockHolder>(&contents).ok());
+    match holder {
+        Some(holder) => {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+            format!(
+                "container {} is locked by pid {} (operation: {}, started {}s ago)",
+                full_container_id,
+                holder.pid,
+                holder.operation,
+                now.saturating_sub(holder.started)
+            )
+        }
+        None => format!("container {} is locked by another layer-tool process", full_container_id),
+    }
+}
@@ -1,42 +1,281 @@
-use anyhow::Result;
-use clap::{Parser, Subcommand};
-use layer_tool::{CheckCommand, CheckOptions, ExportCommand, ImportCommand};
+use anyhow::{anyhow, Context, Result};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use layer_tool::{
+    CheckCommand, CheckOptions, CleanCommand, CloneCommand, CloneOptions, DiffCommand, DiffOptions, ExplainCommand, ExportCommand, ExportOptions, ImportCommand, ImportOptions, KeygenCommand, RestoreCommand,
+    RestoreOptions, SignCommand, VerifyBackupCommand, VerifyBackupOptions
+};
+use layer_tool::backup::DEFAULT_KEEP_BACKUPS;
+use layer_tool::cancel::{CancellationToken, CANCELLED_EXIT_CODE};
+use layer_tool::commands::diff::{Differences, DIFFERENCES_EXIT_CODE};
+use layer_tool::docker::{ArchitectureMismatch, IncompatibleContainer, ARCH_MISMATCH_EXIT_CODE};
+use layer_tool::excludes::ExcludePreset;
+use layer_tool::hooks::DEFAULT_POST_CHECK_TIMEOUT;
+use layer_tool::output::events::{self, EventKind, ProgressMode};
+use layer_tool::output::metrics;
+use layer_tool::output::{print_debug, print_footer, set_log_file, ColorChoice, Printer, SymbolStyle, Verbosity};
+use layer_tool::ssh::RemoteCommandError;
+use layer_tool::tuning::IoTuning;
+use layer_tool::types::{ChecksumAlgorithm, ExportCompression, ExportFormatVersion, HttpHeader, ImportMode, OutputFormat, Owner, Sha256Digest, DEFAULT_TOP_ENTRIES};
+use layer_tool::verify::{IntegrityFailure, VerifyFailure, INTEGRITY_EXIT_CODE};
+use layer_tool::utils::{parse_size, PathViolation};
+use layer_tool::workspace::remove_workspaces;
 
 #[derive(Parser)]
 #[command(name = "layer-tool")]
 #[command(about = "A tool for exporting, importing, and checking Docker container layers")]
 #[command(version = "1.0.0")]
 struct Cli {
+    /// Print nothing but errors (on stderr) and, for export, the output path
+    #[arg(long, short, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Print debug detail such as each docker command run and its duration; repeat (-vv) to also print commands as they start
+    #[arg(long, short, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// When to color the output: auto (when stdout is a terminal and NO_COLOR is not set), always, never
+    #[arg(long, global = true, value_name = "WHEN", default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+    /// Also append every message, including debug detail, to this file as timestamped lines
+    #[arg(long, global = true, value_name = "PATH")]
+    log_file: Option<String>,
+    /// Show progress as bars on a terminal (`bar`), or as newline-delimited JSON events on stderr (`json`)
+    #[arg(long, global = true, value_name = "MODE", default_value_t = ProgressMode::Bar)]
+    progress: ProgressMode,
+    /// Print status symbols as ASCII, e.g. [ok] for ✓ (the default when the locale is not UTF-8)
+    #[arg(long, global = true)]
+    ascii: bool,
+    /// Print digests and paths in full instead of shortening them to fit the terminal
+    #[arg(long, global = true)]
+    full: bool,
+    /// Size of the buffers files are read and written through, e.g. 256K (default 1M, or $LAYER_TOOL_IO_BUFFER_SIZE)
+    #[arg(long, global = true, value_name = "SIZE", value_parser = parse_size_arg)]
+    io_buffer_size: Option<u64>,
     #[command(subcommand)]
     command: Commands,
 }
 
 #[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)] // parsed once; boxing would only obscure the clap definitions
 enum Commands {
     /// Export container layer and metadata to a file
     Export {
-        /// Container ID or name to export
-        container_id: String,
-        /// Output file path
-        output_file: String,
-        /// Compress the output file using gzip
+        /// Container ID or name to export (omit when using --label)
+        #[arg(required_unless_present = "label")]
+        container_id: Option<String>,
+        /// Output file path, a directory to write <container-name>.tar[.gz] into, or '-' for stdout
+        #[arg(conflicts_with = "ssh")]
+        output_file: Option<String>,
+        /// Export every container matching this label (key or key=value, repeatable) into the output directory
+        #[arg(long)]
+        label: Vec<String>,
+        /// Show what would be exported without writing anything
         #[arg(long)]
+        dry_run: bool,
+        /// Compress the output file using gzip (same as --compression gzip)
+        #[arg(long, conflicts_with = "compression")]
         compress: bool,
+        /// Compress the output file: none, gzip or zstd
+        #[arg(long, value_name = "ALGORITHM")]
+        compression: Option<ExportCompression>,
+        /// With --compression zstd, end the file in a frame index so readers can jump to any member
+        #[arg(long)]
+        seekable: bool,
+        /// Export the complete root filesystem (MergedDir) instead of the read-write layer
+        #[arg(long)]
+        full_rootfs: bool,
+        /// Append .gz (or .zst) to the output path when compressing and it does not already end in it
+        #[arg(long)]
+        auto_extension: bool,
+        /// Overwrite the output file if it already exists
+        #[arg(long)]
+        force: bool,
+        /// Produce a bit-identical archive for identical layer content (timestamps from SOURCE_DATE_EPOCH or container creation)
+        #[arg(long)]
+        reproducible: bool,
+        /// Only archive changes relative to a previous export file (incremental export)
+        #[arg(long, value_name = "PREVIOUS_EXPORT")]
+        since: Option<String>,
+        /// Annotation to embed in the export metadata (repeatable)
+        #[arg(long = "annotation", value_name = "KEY=VALUE", value_parser = parse_key_value)]
+        annotations: Vec<(String, String)>,
+        /// Redact metadata values whose key contains this pattern (repeatable, defaults to PASSWORD, TOKEN, SECRET, KEY)
+        #[arg(long = "redact-env", value_name = "PATTERN")]
+        redact_patterns: Vec<String>,
+        /// Worker threads for hashing and compression (defaults to the number of CPUs)
+        #[arg(long, value_name = "N")]
+        threads: Option<usize>,
+        /// Split the export into chunks of at most this size (e.g. 500M, 5G) plus an index file
+        #[arg(long, value_name = "SIZE", value_parser = parse_size_arg)]
+        split_size: Option<u64>,
+        /// Stream the export to a remote host instead of a local file
+        #[arg(long, value_name = "USER@HOST:PATH", conflicts_with = "label")]
+        ssh: Option<String>,
+        /// Re-read and validate the written export before reporting success
+        #[arg(long)]
+        verify: bool,
+        /// Command run (via sh -c) before archiving the layer; the export aborts if it fails
+        #[arg(long, value_name = "CMD")]
+        pre_hook: Option<String>,
+        /// Command run (via sh -c) after the export, even on failure, with LAYER_TOOL_RESULT=success|failure
+        #[arg(long, value_name = "CMD")]
+        post_hook: Option<String>,
+        /// Kill hook commands that run longer than this many seconds
+        #[arg(long, value_name = "SECS")]
+        hook_timeout: Option<u64>,
+        /// Encrypt the export to this age public key (repeatable)
+        #[arg(long = "encrypt-recipient", value_name = "AGE_PUBLIC_KEY")]
+        encrypt_recipients: Vec<String>,
+        /// Skip layer files that cannot be read instead of failing (vanished files are always skipped)
+        #[arg(long)]
+        ignore_transient_errors: bool,
+        /// Also archive volumes and bind mounts that are local directories
+        #[arg(long)]
+        include_mounts: bool,
+        /// Leave out layer paths matching this glob (repeatable; patterns with '/' are anchored at the layer root)
+        #[arg(long = "exclude", value_name = "PATTERN")]
+        excludes: Vec<String>,
+        /// Leave out a curated set of paths: caches, logs, or tmp (repeatable)
+        #[arg(long = "exclude-preset", value_name = "PRESET")]
+        exclude_presets: Vec<ExcludePreset>,
+        /// Wait up to this many seconds for another operation's lock on the container instead of failing
+        #[arg(long, value_name = "SECS")]
+        wait: Option<u64>,
+        /// Write a JSON report of the export (an array of reports with --label) to this file
+        #[arg(long, value_name = "PATH.json")]
+        report: Option<String>,
+        /// Undo the daemon's userns-remap shift so the export holds unshifted uids and gids
+        #[arg(long)]
+        unshift: bool,
+        /// Hash function of the layer checksum and per-file digests: sha256 or blake3
+        #[arg(long = "checksum-algo", value_name = "ALGO", default_value = "sha256")]
+        checksum_algorithm: ChecksumAlgorithm,
+        /// Metadata format to write: 2, or 1 for layer-tool releases that only read format 1
+        #[arg(long, value_name = "VERSION", default_value = "2")]
+        format_version: ExportFormatVersion,
     },
     /// Import layer data from export file to container
     Import {
         /// Input export file path
         input_file: String,
-        /// Target container ID or name
-        container_id: String,
+        /// Target container IDs or names; the export is prepared once and applied to each (not used with --target-path)
+        #[arg(required_unless_present_any = ["target_path", "label"])]
+        container_ids: Vec<String>,
+        /// Import into every container matching this label (key or key=value, repeatable)
+        #[arg(long, conflicts_with = "container_ids")]
+        label: Vec<String>,
+        /// Import into this many containers at a time when there are several targets
+        #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+        jobs: u64,
+        /// Skip the remaining containers once an import into one of them fails
+        #[arg(long)]
+        fail_fast: bool,
         /// Skip backing up existing layer before import
         #[arg(long)]
         no_backup: bool,
+        /// Allow applying a full rootfs export onto the container's upper layer
+        #[arg(long)]
+        as_upper: bool,
+        /// Apply an incremental export even if the target layer does not match its parent
+        #[arg(long)]
+        force_incremental: bool,
+        /// Stream the export file to `layer-tool import -` on a remote host
+        #[arg(long, value_name = "USER@HOST")]
+        ssh: Option<String>,
+        /// age identity file used to decrypt an encrypted export
+        #[arg(long, value_name = "KEYFILE")]
+        identity: Option<String>,
+        /// Require a valid detached signature (<file>.sig) made by the key in --pubkey
+        #[arg(long, requires = "pubkey")]
+        verify_signature: bool,
+        /// ed25519 public key (PEM or raw) used by --verify-signature
+        #[arg(long, value_name = "FILE", requires = "verify_signature")]
+        pubkey: Option<String>,
+        /// Fail unless the input file, as downloaded or read, has this SHA256 (checked before anything else)
+        #[arg(long, value_name = "DIGEST")]
+        sha256: Option<Sha256Digest>,
+        /// Extra request header for http(s):// inputs, e.g. 'Authorization: Bearer ...' (repeatable)
+        #[arg(long = "header", value_name = "NAME: VALUE")]
+        headers: Vec<HttpHeader>,
+        /// Restore mounts included with `export --include-mounts` to their original source paths
+        #[arg(long)]
+        restore_mounts: bool,
+        /// Wait up to this many seconds for another operation's lock on the container instead of failing
+        #[arg(long, value_name = "SECS")]
+        wait: Option<u64>,
+        /// Validate the export against the target container without modifying it (exit 0 ok, 1 warnings, 2 errors)
+        #[arg(long)]
+        verify_only: bool,
+        /// Do not validate the export before modifying the container (faster, but a corrupt export is found too late)
+        #[arg(long, conflicts_with = "verify_only")]
+        skip_precheck: bool,
+        /// Do not check the target filesystem's free space and inodes before modifying the container
+        #[arg(long)]
+        no_space_check: bool,
+        /// Import even if the target container runs a different image than the export was taken from
+        #[arg(long)]
+        allow_image_mismatch: bool,
+        /// Import even if the export was taken on a different architecture
+        #[arg(long)]
+        skip_arch: bool,
+        /// replace: back up and replace the existing layer; merge: extract over it, applying whiteouts as deletions
+        #[arg(long, value_name = "MODE", default_value_t = ImportMode::Replace)]
+        mode: ImportMode,
+        /// Do not write layer paths matching this glob into the container (repeatable, same syntax as export)
+        #[arg(long = "exclude", value_name = "GLOB")]
+        excludes: Vec<String>,
+        /// Only import entries under this layer-relative path (repeatable); implies --mode merge
+        #[arg(long, value_name = "PATH")]
+        only: Vec<String>,
+        /// Extract into this directory instead of a container's layer, without using Docker
+        #[arg(long, value_name = "DIR", conflicts_with_all = ["container_ids", "label", "ssh", "verify_only"])]
+        target_path: Option<String>,
+        /// Import into a running container, or allow --target-path to be / or a non-empty directory
+        #[arg(long)]
+        force: bool,
+        /// Add this (possibly negative) number to the uid of every imported entry
+        #[arg(long, value_name = "N", allow_negative_numbers = true)]
+        uid_offset: Option<i64>,
+        /// Add this (possibly negative) number to the gid of every imported entry
+        #[arg(long, value_name = "N", allow_negative_numbers = true)]
+        gid_offset: Option<i64>,
+        /// Shift ownership into the target daemon's userns-remap range (from /etc/subuid and docker info)
+        #[arg(long, conflicts_with_all = ["uid_offset", "gid_offset", "target_path"])]
+        auto_remap: bool,
+        /// Give every imported entry this owner instead of the recorded one
+        #[arg(long, value_name = "UID[:GID]", conflicts_with = "auto_remap")]
+        chown: Option<Owner>,
+        /// Write the backup of the replaced layer as a tarball into this directory
+        #[arg(long, value_name = "PATH", conflicts_with = "no_backup")]
+        backup_dir: Option<String>,
+        /// Number of backups to keep per container; older ones are pruned after a successful import
+        #[arg(long, value_name = "N", default_value_t = DEFAULT_KEEP_BACKUPS as u64, value_parser = clap::value_parser!(u64).range(1..))]
+        keep_backups: u64,
+        /// Answer yes to confirmation prompts (replacing the container's layer, restoring each mount)
+        #[arg(long, short = 'y')]
+        yes: bool,
+        /// Continue an interrupted import of the same input from its journal instead of starting over
+        #[arg(long, conflicts_with_all = ["ssh", "verify_only", "target_path", "only", "label"])]
+        resume: bool,
+        /// After the import, run this command in the container via `docker exec` (starting it if needed); failing rolls the import back
+        #[arg(long, value_name = "CMD", conflicts_with_all = ["target_path", "verify_only"])]
+        post_check: Option<String>,
+        /// Seconds the post-check may run before it counts as failed
+        #[arg(long, value_name = "SECS", default_value_t = DEFAULT_POST_CHECK_TIMEOUT, requires = "post_check")]
+        post_check_timeout: u64,
+        /// With --mode merge, only ask for confirmation when more existing files than this would be overwritten or deleted
+        #[arg(long, value_name = "N", conflicts_with = "verify_only")]
+        max_overwrites: Option<usize>,
+        /// Write a JSON report of the import, also when it fails (an array of reports for several containers)
+        #[arg(long, value_name = "PATH.json", conflicts_with_all = ["ssh", "verify_only"])]
+        report: Option<String>,
+        /// Print the result as `text` or as the `json` report on stdout (human-readable output moves to stderr)
+        #[arg(long, value_name = "FORMAT", default_value_t = OutputFormat::Text, conflicts_with_all = ["ssh", "verify_only"])]
+        output: OutputFormat,
     },
     /// Check export file integrity and compatibility
     Check {
-        /// Input export file path to check
-        input_file: String,
+        /// Export files to check; several files, or directories with --glob, end with a summary table
+        #[arg(required = true, value_name = "INPUT_FILE")]
+        input_files: Vec<String>,
         /// Skip image SHA256 verification
         #[arg(long)]
         skip_image: bool,
@@ -46,47 +285,542 @@ enum Commands {
         /// Skip operating system compatibility check
         #[arg(long)]
         skip_os: bool,
+        /// Skip the Docker and kernel version comparisons
+        #[arg(long)]
+        skip_version: bool,
+        /// Skip the cgroup driver comparison
+        #[arg(long)]
+        skip_cgroup: bool,
         /// Skip architecture compatibility check
         #[arg(long)]
         skip_arch: bool,
+        /// Extract the layer, recompute its checksum, and verify every entry against the per-file manifest
+        #[arg(long)]
+        deep: bool,
+        /// Fail unless the export carries this annotation (`key` or `key=value`, repeatable)
+        #[arg(long = "require-annotation", value_name = "KEY[=VALUE]")]
+        required_annotations: Vec<String>,
+        /// age identity file used to decrypt an encrypted export
+        #[arg(long, value_name = "KEYFILE")]
+        identity: Option<String>,
+        /// Require a valid detached signature (<file>.sig) made by the key in --pubkey
+        #[arg(long, requires = "pubkey")]
+        verify_signature: bool,
+        /// ed25519 public key (PEM or raw) used by --verify-signature
+        #[arg(long, value_name = "FILE", requires = "verify_signature")]
+        pubkey: Option<String>,
+        /// Fail unless the input file, as downloaded or read, has this SHA256 (checked before anything else)
+        #[arg(long, value_name = "DIGEST")]
+        sha256: Option<Sha256Digest>,
+        /// Extra request header for http(s):// inputs, e.g. 'Authorization: Bearer ...' (repeatable)
+        #[arg(long = "header", value_name = "NAME: VALUE")]
+        headers: Vec<HttpHeader>,
+        /// Create the temporary workspace (decompressed export and, with --deep, the extracted layer) in this directory
+        #[arg(long, value_name = "DIR")]
+        tmpdir: Option<String>,
+        /// Number of largest layer files to list (0 to list none)
+        #[arg(long, value_name = "N", default_value_t = DEFAULT_TOP_ENTRIES)]
+        top: usize,
+        /// Warn about suspicious layer entries: links escaping the layer, device nodes, setuid/setgid executables, world-writable files
+        #[arg(long)]
+        scan: bool,
+        /// Check the files in directory inputs whose name matches this glob, e.g. '*.tar.gz'
+        #[arg(long, value_name = "PATTERN")]
+        glob: Option<String>,
+        /// Stop at the first export file that fails the check
+        #[arg(long)]
+        fail_fast: bool,
+        /// Treat every warning as an error: the check fails and the report verdict is `failed`
+        #[arg(long)]
+        strict: bool,
+        /// Treat the warnings of this check as errors, by the `id` of the JSON report (e.g. `storage-driver`; repeatable)
+        #[arg(long = "warn-as-error", value_name = "NAME")]
+        warn_as_error: Vec<String>,
+        /// Also check that the export applies cleanly to this container: image, state, layer directory and free space
+        #[arg(long, value_name = "ID")]
+        container: Option<String>,
+        /// Print the result as `text` or as a `json` check report on stdout (human-readable output moves to stderr)
+        #[arg(long, value_name = "FORMAT", default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
+    },
+    /// Show what changed between two layers, each an export file or a container
+    Diff {
+        /// Export file or container to compare from
+        a: String,
+        /// Export file or container to compare to
+        b: String,
+        /// Print the result as `text` or as a `json` diff report on stdout (human-readable output moves to stderr)
+        #[arg(long, value_name = "FORMAT", default_value_t = OutputFormat::Text, conflicts_with = "name_only")]
+        output: OutputFormat,
+        /// Print only the paths that differ, one per line, on stdout
+        #[arg(long)]
+        name_only: bool,
+    },
+    /// Copy one container's layer onto another, streaming it without an export file
+    Clone {
+        /// Container whose layer is copied
+        source_container: String,
+        /// Container whose layer is replaced
+        target_container: String,
+        /// Import into the target container on this host over SSH (runs `layer-tool import -` there)
+        #[arg(long, value_name = "USER@HOST")]
+        to_host: Option<String>,
+        /// Skip backing up the target's existing layer
+        #[arg(long)]
+        no_backup: bool,
+        /// Write the backup of the replaced layer as a tarball into this directory
+        #[arg(long, value_name = "PATH", conflicts_with = "no_backup")]
+        backup_dir: Option<String>,
+        /// Number of backups to keep for the target; older ones are pruned after a successful clone
+        #[arg(long, value_name = "N", default_value_t = DEFAULT_KEEP_BACKUPS as u64, value_parser = clap::value_parser!(u64).range(1..))]
+        keep_backups: u64,
+        /// Clone into a running target container
+        #[arg(long)]
+        force: bool,
+        /// Clone even if the two containers run different images
+        #[arg(long)]
+        allow_image_mismatch: bool,
+        /// Clone even if the remote daemon runs on a different architecture (with --to-host)
+        #[arg(long)]
+        skip_arch: bool,
+        /// Do not check the target filesystem's free space and inodes before modifying the container
+        #[arg(long)]
+        no_space_check: bool,
+        /// Wait up to this many seconds for another operation's lock on either container instead of failing
+        #[arg(long, value_name = "SECS")]
+        wait: Option<u64>,
+    },
+    /// Roll a container's layer back to a backup taken by import
+    Restore {
+        /// Container whose layer is restored
+        container_id: String,
+        /// Backup to restore, by file name or timestamp (default: the most recent)
+        #[arg(long, value_name = "NAME")]
+        backup: Option<String>,
+        /// List the container's backups instead of restoring
+        #[arg(long, conflicts_with = "backup")]
+        list: bool,
+        /// Directory holding tarball backups (import --backup-dir); the current layer is backed up there too
+        #[arg(long, value_name = "PATH")]
+        backup_dir: Option<String>,
+        /// Wait up to this many seconds for another operation's lock on the container instead of failing
+        #[arg(long, value_name = "SECS")]
+        wait: Option<u64>,
+    },
+    /// Check a container's backups against the checksums recorded when they were taken
+    VerifyBackup {
+        /// Container whose backups are checked
+        container_id: String,
+        /// Backup to check, by file name or timestamp (default: all backups)
+        #[arg(long, value_name = "NAME")]
+        backup: Option<String>,
+        /// Directory holding tarball backups (import --backup-dir)
+        #[arg(long, value_name = "PATH")]
+        backup_dir: Option<String>,
+    },
+    /// Write a detached ed25519 signature for an export file to <export>.sig
+    Sign {
+        /// Export file to sign (split exports are signed through their index)
+        export_file: String,
+        /// ed25519 private key (PKCS#8 PEM or raw 32 bytes)
+        #[arg(long, value_name = "FILE")]
+        key: String,
     },
+    /// Generate an ed25519 key pair for signing exports
+    Keygen {
+        /// Output path prefix; writes <prefix>.key and <prefix>.pub
+        #[arg(default_value = "layer-tool")]
+        prefix: String,
+        /// Overwrite existing key files
+        #[arg(long)]
+        force: bool,
+    },
+    /// Remove temporary workspaces left behind by layer-tool runs that crashed or were killed
+    Clean,
+    /// Describe a warning or error code such as W001 and how to address it
+    Explain {
+        /// Code as printed in brackets, e.g. W001, or in full as in JSON reports (default: list all codes)
+        code: Option<String>,
+    },
+}
+
+/// Parse a `key=value` command-line argument
+fn parse_key_value(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VALUE, got '{}'", value)),
+    }
+}
+
+/// Parse a size argument such as `500M` or `5G`
+fn parse_size_arg(value: &str) -> Result<u64, String> {
+    parse_size(value).map_err(|e| e.to_string())
+}
+
+fn main() {
+    metrics::start();
+    let cancel = CancellationToken::new();
+    let result = run(&cancel);
+    print_footer(&metrics::snapshot());
+    if let Err(error) = result {
+        tracing::error!("Error: {:#}", error);
+        events::emit(EventKind::Error { message: format!("{:#}", error), code: None });
+        eprintln!("Error: {:?}", error);
+        std::process::exit(exit_code(&error, &cancel));
+    }
 }
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
+/// Cancel the running command on Ctrl-C so it can clean up; a second Ctrl-C removes the temporary
+/// workspaces and exits immediately
+fn install_interrupt_handler(cancel: &CancellationToken) -> Result<()> {
+    let cancel = cancel.clone();
+    ctrlc::set_handler(move || {
+        if cancel.is_cancelled() {
+            // Exiting skips destructors, so the temporary workspaces are removed here
+            remove_workspaces();
+            std::process::exit(CANCELLED_EXIT_CODE);
+        }
+        eprintln!("\nInterrupted, cleaning up (press Ctrl-C again to exit immediately)...");
+        cancel.cancel();
+    })
+    .context("Failed to install Ctrl-C handler")
+}
+
+/// Process exit code for a failed command: 130 when interrupted, the code of a failed
+/// verification, the diff exit code when layers differ, a failed remote command's own exit code,
+/// otherwise 1
+fn exit_code(error: &anyhow::Error, cancel: &CancellationToken) -> i32 {
+    if cancel.is_cancelled() {
+        return CANCELLED_EXIT_CODE;
+    }
+    if let Some(failure) = error.downcast_ref::<VerifyFailure>() {
+        return failure.exit_code;
+    }
+    if error.chain().any(|cause| cause.is::<ArchitectureMismatch>() || cause.is::<IncompatibleContainer>()) {
+        return ARCH_MISMATCH_EXIT_CODE;
+    }
+    if error.chain().any(|cause| cause.is::<IntegrityFailure>() || cause.is::<PathViolation>()) {
+        return INTEGRITY_EXIT_CODE;
+    }
+    if error.is::<Differences>() {
+        return DIFFERENCES_EXIT_CODE;
+    }
+    error.chain()
+        .find_map(|cause| cause.downcast_ref::<RemoteCommandError>())
+        .and_then(|remote| remote.exit_code)
+        .unwrap_or(1)
+}
+
+fn run(cancel: &CancellationToken) -> Result<()> {
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|error| error.exit());
+    if cli.progress == ProgressMode::Json {
+        events::enable(matches.subcommand_name().unwrap_or_default());
+    }
+    cli.color.apply();
+    let symbols = if cli.ascii { SymbolStyle::Ascii } else { SymbolStyle::from_locale() };
+    Printer::new(Verbosity::from_flags(cli.quiet, cli.verbose))
+        .with_symbols(symbols)
+        .with_full_values(cli.full)
+        .install();
+    if let Some(log_file) = &cli.log_file {
+        set_log_file(std::path::Path::new(log_file))?;
+        print_debug(&format!("Running {}", std::env::args().collect::<Vec<_>>().join(" ")));
+    }
+    install_interrupt_handler(cancel)?;
+    match cli.io_buffer_size {
+        Some(buffer_size) => IoTuning::new(buffer_size).context("Invalid --io-buffer-size")?,
+        None => IoTuning::from_env()?,
+    }.apply();
 
     match cli.command {
         Commands::Export {
             container_id,
             output_file,
+            label,
+            dry_run,
             compress,
+            compression,
+            seekable,
+            full_rootfs,
+            auto_extension,
+            force,
+            reproducible,
+            since,
+            annotations,
+            redact_patterns,
+            threads,
+            split_size,
+            ssh,
+            verify,
+            pre_hook,
+            post_hook,
+            hook_timeout,
+            encrypt_recipients,
+            ignore_transient_errors,
+            include_mounts,
+            excludes,
+            exclude_presets,
+            wait,
+            report,
+            unshift,
+            checksum_algorithm,
+            format_version,
         } => {
-            let export_cmd = ExportCommand::new();
-            export_cmd.execute(&container_id, &output_file, compress)?;
+            let compression = match (compression, compress) {
+                (Some(ExportCompression::Zstd { .. }), _) => ExportCompression::Zstd { seekable },
+                _ if seekable => return Err(anyhow::anyhow!("--seekable requires --compression zstd")),
+                (Some(compression), _) => compression,
+                (None, true) => ExportCompression::Gzip,
+                (None, false) => ExportCompression::None,
+            };
+            let export_options = ExportOptions {
+                compression,
+                full_rootfs,
+                auto_extension,
+                force,
+                dry_run,
+                reproducible,
+                since,
+                annotations: annotations.into_iter().collect(),
+                redact_patterns,
+                threads,
+                split_size,
+                ssh,
+                verify,
+                pre_hook,
+                post_hook,
+                hook_timeout,
+                encrypt_recipients,
+                ignore_transient_errors,
+                include_mounts,
+                excludes,
+                exclude_presets,
+                wait,
+                report,
+                unshift,
+                checksum_algorithm,
+                format_version,
+            };
+            let export_cmd = ExportCommand::new().with_cancellation(cancel.clone());
+            if label.is_empty() {
+                let container_id = container_id.expect("container id is required without --label");
+                let output_file = match output_file {
+                    Some(output_file) => output_file,
+                    None if export_options.ssh.is_some() => String::new(),
+                    None => return Err(anyhow!("Missing output file path")),
+                };
+                export_cmd.execute(&container_id, &output_file, export_options)?;
+            } else {
+                // With --label the single positional argument is the output directory
+                let output_dir = match (container_id, output_file) {
+                    (Some(output_dir), None) => output_dir,
+                    (None, None) => return Err(anyhow!("Missing output directory for --label export")),
+                    _ => return Err(anyhow!("A container ID cannot be combined with --label; pass only the output directory")),
+                };
+                export_cmd.execute_by_label(&label, &output_dir, export_options)?;
+            }
         }
         Commands::Import {
             input_file,
-            container_id,
+            container_ids,
+            label,
+            jobs,
+            fail_fast,
             no_backup,
+            as_upper,
+            force_incremental,
+            ssh,
+            identity,
+            verify_signature,
+            pubkey,
+            sha256,
+            headers,
+            restore_mounts,
+            yes,
+            wait,
+            verify_only,
+            skip_precheck,
+            no_space_check,
+            allow_image_mismatch,
+            skip_arch,
+            mode,
+            excludes,
+            only,
+            target_path,
+            force,
+            uid_offset,
+            gid_offset,
+            auto_remap,
+            chown,
+            backup_dir,
+            keep_backups,
+            resume,
+            post_check,
+            post_check_timeout,
+            max_overwrites,
+            report,
+            output,
         } => {
-            let import_cmd = ImportCommand::new();
-            import_cmd.execute(&input_file, &container_id, !no_backup)?;
+            let import_options = ImportOptions {
+                backup: !no_backup,
+                as_upper,
+                force_incremental,
+                ssh,
+                identity,
+                verify_signature,
+                pubkey,
+                sha256,
+                headers,
+                restore_mounts,
+                yes,
+                wait,
+                verify_only,
+                skip_precheck,
+                no_space_check,
+                allow_image_mismatch,
+                skip_arch,
+                mode,
+                excludes,
+                only,
+                target_path,
+                force,
+                uid_offset,
+                gid_offset,
+                auto_remap,
+                chown,
+                backup_dir,
+                keep_backups: keep_backups as usize,
+                jobs: jobs as usize,
+                fail_fast,
+                resume,
+                post_check,
+                post_check_timeout,
+                max_overwrites,
+                report,
+                output,
+            };
+            let import_cmd = ImportCommand::new().with_cancellation(cancel.clone());
+            if !label.is_empty() {
+                import_cmd.execute_by_label(&input_file, &label, import_options)?;
+            } else if container_ids.len() > 1 {
+                import_cmd.execute_many(&input_file, &container_ids, import_options)?;
+            } else {
+                import_cmd.execute(&input_file, container_ids.first().map(String::as_str), import_options)?;
+            }
         }
         Commands::Check {
-            input_file,
+            input_files,
             skip_image,
             skip_storage,
             skip_os,
+            skip_version,
+            skip_cgroup,
             skip_arch,
+            deep,
+            required_annotations,
+            identity,
+            verify_signature,
+            pubkey,
+            sha256,
+            headers,
+            tmpdir,
+            container,
+            top,
+            scan,
+            glob,
+            fail_fast,
+            strict,
+            warn_as_error,
+            output,
         } => {
             let check_options = CheckOptions {
                 skip_image,
                 skip_storage,
                 skip_os,
+                skip_version,
+                skip_cgroup,
                 skip_arch,
+                deep,
+                required_annotations,
+                identity,
+                verify_signature,
+                pubkey,
+                sha256,
+                headers,
+                tmpdir,
+                container,
+                top,
+                scan,
+                glob,
+                fail_fast,
+                strict,
+                warn_as_error,
+                output,
             };
-            let check_cmd = CheckCommand::new();
-            check_cmd.execute(&input_file, check_options)?;
+            let check_cmd = CheckCommand::new().with_cancellation(cancel.clone());
+            if let [input_file] = &input_files[..] && !std::path::Path::new(input_file).is_dir() {
+                check_cmd.execute(input_file, check_options)?;
+            } else {
+                check_cmd.execute_many(&input_files, check_options)?;
+            }
+        }
+        Commands::Diff { a, b, output, name_only } => {
+            let diff_cmd = DiffCommand::new().with_cancellation(cancel.clone());
+            diff_cmd.execute(&a, &b, DiffOptions { output, name_only })?;
+        }
+        Commands::Clone {
+            source_container,
+            target_container,
+            to_host,
+            no_backup,
+            backup_dir,
+            keep_backups,
+            force,
+            allow_image_mismatch,
+            skip_arch,
+            no_space_check,
+            wait,
+        } => {
+            let clone_options = CloneOptions {
+                backup: !no_backup,
+                backup_dir,
+                keep_backups: keep_backups as usize,
+                force,
+                allow_image_mismatch,
+                skip_arch,
+                no_space_check,
+                wait,
+                to_host,
+            };
+            let clone_cmd = CloneCommand::new().with_cancellation(cancel.clone());
+            clone_cmd.execute(&source_container, &target_container, &clone_options)?;
+        }
+        Commands::Restore { container_id, backup, list, backup_dir, wait } => {
+            let restore_options = RestoreOptions { backup, list, backup_dir, wait };
+            let restore_cmd = RestoreCommand::new().with_cancellation(cancel.clone());
+            restore_cmd.execute(&container_id, &restore_options)?;
+        }
+        Commands::VerifyBackup { container_id, backup, backup_dir } => {
+            let verify_options = VerifyBackupOptions { backup, backup_dir };
+            let verify_cmd = VerifyBackupCommand::new();
+            verify_cmd.execute(&container_id, &verify_options)?;
+        }
+        Commands::Sign { export_file, key } => {
+            let sign_cmd = SignCommand::new();
+            sign_cmd.execute(&export_file, &key)?;
+        }
+        Commands::Keygen { prefix, force } => {
+            let keygen_cmd = KeygenCommand::new();
+            keygen_cmd.execute(&prefix, force)?;
+        }
+        Commands::Clean => {
+            let clean_cmd = CleanCommand::new();
+            clean_cmd.execute()?;
+        }
+        Commands::Explain { code } => {
+            let explain_cmd = ExplainCommand::new();
+            explain_cmd.execute(code.as_deref())?;
         }
     }
 
@@ -1,42 +1,777 @@
+use anyhow::{Context, Result};
 use colored::*;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::fmt::Display;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+
+use crate::diagnostics::{Diagnostic, DiagnosticCode, Severity};
+use crate::types::{CheckResult, CheckStatus, CommandMetrics};
+use crate::utils::format_file_size;
+
+pub mod events;
+pub mod metrics;
+
+use events::EventKind;
+
+/// Whether human-readable output goes to stderr instead of stdout
+static OUTPUT_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Environment variable that shows the messages hidden by quiet mode on stderr
+pub const DEBUG_ENV: &str = "LAYER_TOOL_DEBUG";
+
+/// Printer every print_* function goes through; see [`Printer::install`]
+static PRINTER: RwLock<Printer> = RwLock::new(Printer::new(Verbosity::Normal));
+
+thread_local! {
+    /// Sink entered by the command running on this thread, see [`enter_output`]
+    static SCOPED_SINK: RefCell<Option<Arc<dyn OutputSink>>> = const { RefCell::new(None) };
+}
+
+/// Environment variable that turns colors off when set to anything, unless `--color always`
+pub const NO_COLOR_ENV: &str = "NO_COLOR";
+
+/// Locale variables in the order they take precedence
+const LOCALE_ENVS: [&str; 3] = ["LC_ALL", "LC_CTYPE", "LANG"];
+
+/// ASCII replacements for the symbols the messages use
+const ASCII_SYMBOLS: [(char, &str); 8] = [
+    ('✓', "[ok]"),
+    ('✅', "[ok]"),
+    ('⏭', "[skip]"),
+    ('✗', "[fail]"),
+    ('⚠', "[warn]"),
+    ('❌', "[error]"),
+    ('•', "*"),
+    ('…', "..."),
+];
+
+/// Spinner drawn while [`with_spinner`] runs; lines printed meanwhile suspend it
+static SPINNER: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+/// Warnings printed since the last `take_warnings`, collected for JSON reports
+static WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Diagnostics printed since the last `take_diagnostics`, collected for JSON reports
+static DIAGNOSTICS: Mutex<Vec<Diagnostic>> = Mutex::new(Vec::new());
+
+/// Check results printed since the last `take_check_results`, collected for JSON reports
+static CHECK_RESULTS: Mutex<Vec<CheckResult>> = Mutex::new(Vec::new());
+
+/// How much the commands print, from `--quiet` up to `-vv`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Verbosity {
+    /// Errors and the command's result only
+    Quiet,
+    /// Everything but debug detail
+    #[default]
+    Normal,
+    /// Also debug detail, e.g. how a container's layer directory was found (`-v`)
+    Verbose,
+    /// Also every docker command run (`-vv`)
+    Trace,
+}
+
+impl Verbosity {
+    /// Verbosity for the `--quiet` flag and the number of `-v` flags
+    pub fn from_flags(quiet: bool, verbose: u8) -> Self {
+        match (quiet, verbose) {
+            (true, _) => Self::Quiet,
+            (false, 0) => Self::Normal,
+            (false, 1) => Self::Verbose,
+            (false, _) => Self::Trace,
+        }
+    }
+}
+
+/// When the output is colored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorChoice {
+    /// Colored when stdout is a terminal and NO_COLOR is not set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Turn the colors of all output on or off for the rest of the process
+    pub fn apply(self) {
+        let enabled = match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => std::env::var_os(NO_COLOR_ENV).is_none_or(|value| value.is_empty())
+                && std::io::stdout().is_terminal(),
+        };
+        colored::control::set_override(enabled);
+    }
+}
+
+impl std::fmt::Display for ColorChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ColorChoice::Auto => write!(f, "auto"),
+            ColorChoice::Always => write!(f, "always"),
+            ColorChoice::Never => write!(f, "never"),
+        }
+    }
+}
+
+impl std::str::FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!("unknown color choice '{}' (expected one of: auto, always, never)", value)),
+        }
+    }
+}
+
+/// How status symbols such as ✓ and ⏭ are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SymbolStyle {
+    #[default]
+    Unicode,
+    /// Plain ASCII, e.g. `[ok]` and `[skip]`, for terminals and logs without UTF-8
+    Ascii,
+}
+
+impl SymbolStyle {
+    /// Unicode when the locale (LC_ALL, LC_CTYPE or LANG, whichever is set first) is UTF-8
+    pub fn from_locale() -> Self {
+        let locale = LOCALE_ENVS.iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .unwrap_or_default()
+            .to_lowercase();
+        if locale.contains("utf-8") || locale.contains("utf8") {
+            SymbolStyle::Unicode
+        } else {
+            SymbolStyle::Ascii
+        }
+    }
+
+    /// `text` with its symbols in this style
+    pub fn render(self, text: &str) -> Cow<'_, str> {
+        if self == SymbolStyle::Unicode || text.is_ascii() {
+            return Cow::Borrowed(text);
+        }
+        let mut rendered = String::with_capacity(text.len());
+        for character in text.chars() {
+            match ASCII_SYMBOLS.iter().find(|(symbol, _)| *symbol == character) {
+                Some((_, replacement)) => rendered.push_str(replacement),
+                None => rendered.push(character),
+            }
+        }
+        Cow::Owned(rendered)
+    }
+}
+
+/// Kind of a printed message, which decides the verbosity it is shown at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    /// The command's output proper: JSON reports, changed paths, the quiet result line.
+    /// Always shown, on stdout.
+    Result,
+    Error,
+    Warn,
+    Info,
+    Progress,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    /// Lowest verbosity the level is shown at
+    fn verbosity(self) -> Verbosity {
+        match self {
+            Self::Result | Self::Error => Verbosity::Quiet,
+            Self::Warn | Self::Info | Self::Progress => Verbosity::Normal,
+            Self::Debug => Verbosity::Verbose,
+            Self::Trace => Verbosity::Trace,
+        }
+    }
+}
+
+/// Destination of the printed lines. Library consumers install their own for the whole process
+/// through [`Printer::with_sink`], or for one command through its `with_output`, to capture or
+/// redirect the output.
+pub trait OutputSink: Send + Sync {
+    /// Write one line, already colored, at `level`
+    fn emit(&self, level: Level, message: &str);
+
+    /// A check's result, printed as `line`
+    fn check_result(&self, result: &CheckResult, line: &str) {
+        let _ = result;
+        self.emit(Level::Info, line);
+    }
+
+    /// Progress of a phase that moves data, the same events `--progress json` writes; the
+    /// console ignores them and draws progress bars instead
+    fn progress(&self, event: &EventKind) {
+        let _ = event;
+    }
+
+    /// Whether progress bars may be drawn on the terminal next to this sink's output
+    fn draws_progress_bars(&self) -> bool {
+        false
+    }
+}
+
+/// Sink writing to the terminal: results to stdout, debug detail and errors in quiet mode to
+/// stderr, everything else to stdout unless [`set_output_to_stderr`] moved it to stderr
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConsoleSink;
+
+impl OutputSink for ConsoleSink {
+    fn emit(&self, level: Level, message: &str) {
+        let to_stderr = match level {
+            Level::Result => false,
+            Level::Debug | Level::Trace => true,
+            Level::Error if is_quiet() => true,
+            _ => OUTPUT_TO_STDERR.load(Ordering::Relaxed),
+        };
+        let write = || {
+            if to_stderr {
+                eprintln!("{}", message);
+            } else {
+                println!("{}", message);
+            }
+        };
+        // Clear the spinner so the line does not end up drawn over it
+        match SPINNER.lock().ok().and_then(|spinner| spinner.clone()) {
+            Some(spinner) => spinner.suspend(write),
+            None => write(),
+        }
+    }
+
+    fn draws_progress_bars(&self) -> bool {
+        true
+    }
+}
+
+/// Sink keeping every line, check result and progress event in memory, e.g. for tests asserting
+/// what a command printed
+#[derive(Debug, Default)]
+pub struct CaptureSink {
+    lines: Mutex<Vec<(Level, String)>>,
+    check_results: Mutex<Vec<CheckResult>>,
+    progress: Mutex<Vec<EventKind>>,
+}
+
+impl CaptureSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lines captured so far with their levels, in order
+    pub fn lines(&self) -> Vec<(Level, String)> {
+        self.lines.lock().map(|lines| lines.clone()).unwrap_or_default()
+    }
+
+    /// Lines captured so far at `level`
+    pub fn lines_at(&self, level: Level) -> Vec<String> {
+        self.lines().into_iter()
+            .filter(|(line_level, _)| *line_level == level)
+            .map(|(_, line)| line)
+            .collect()
+    }
+
+    pub fn check_results(&self) -> Vec<CheckResult> {
+        self.check_results.lock().map(|results| results.clone()).unwrap_or_default()
+    }
+
+    /// Progress events captured so far, in order
+    pub fn progress_events(&self) -> Vec<EventKind> {
+        self.progress.lock().map(|events| events.clone()).unwrap_or_default()
+    }
+}
+
+impl OutputSink for CaptureSink {
+    fn emit(&self, level: Level, message: &str) {
+        if let Ok(mut lines) = self.lines.lock() {
+            lines.push((level, message.to_string()));
+        }
+    }
+
+    fn check_result(&self, result: &CheckResult, line: &str) {
+        if let Ok(mut results) = self.check_results.lock() {
+            results.push(result.clone());
+        }
+        self.emit(Level::Info, line);
+    }
+
+    fn progress(&self, event: &EventKind) {
+        if let Ok(mut events) = self.progress.lock() {
+            events.push(event.clone());
+        }
+    }
+}
+
+/// Restores the sink the thread used before [`enter_output`] when dropped
+pub struct OutputScope {
+    previous: Option<Arc<dyn OutputSink>>,
+}
+
+impl Drop for OutputScope {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        SCOPED_SINK.with(|sink| *sink.borrow_mut() = previous);
+    }
+}
+
+/// Send the output printed on this thread to `sink` until the returned scope is dropped. Commands
+/// enter their `with_output` sink, and enter it again on the worker threads they print from.
+pub fn enter_output(sink: &Arc<dyn OutputSink>) -> OutputScope {
+    let previous = SCOPED_SINK.with(|scoped| scoped.borrow_mut().replace(sink.clone()));
+    OutputScope { previous }
+}
+
+/// The sink output printed on this thread goes to: the one entered, else the installed printer's
+pub fn current_sink() -> Arc<dyn OutputSink> {
+    SCOPED_SINK.with(|scoped| scoped.borrow().clone())
+        .or_else(|| PRINTER.read().ok().and_then(|printer| printer.sink.clone()))
+        .unwrap_or_else(|| Arc::new(ConsoleSink))
+}
+
+/// Verbosity, symbol style and sink of the human-readable output
+pub struct Printer {
+    verbosity: Verbosity,
+    symbols: SymbolStyle,
+    /// Print digests and paths in full even where they do not fit the terminal (`--full`)
+    full_values: bool,
+    /// None for the [`ConsoleSink`]
+    sink: Option<Arc<dyn OutputSink>>,
+}
+
+impl Printer {
+    /// Printer at `verbosity` writing to the console
+    pub const fn new(verbosity: Verbosity) -> Self {
+        Self { verbosity, symbols: SymbolStyle::Unicode, full_values: false, sink: None }
+    }
+
+    /// Print status symbols in `symbols` style
+    pub fn with_symbols(mut self, symbols: SymbolStyle) -> Self {
+        self.symbols = symbols;
+        self
+    }
+
+    /// Never shorten digests and paths to fit the terminal
+    pub fn with_full_values(mut self, full_values: bool) -> Self {
+        self.full_values = full_values;
+        self
+    }
+
+    /// Send the output to `sink` instead of the console
+    pub fn with_sink(mut self, sink: Arc<dyn OutputSink>) -> Self {
+        self.sink = Some(sink);
+        self
+    }
+
+    pub fn verbosity(&self) -> Verbosity {
+        self.verbosity
+    }
+
+    pub fn symbols(&self) -> SymbolStyle {
+        self.symbols
+    }
+
+    pub fn full_values(&self) -> bool {
+        self.full_values
+    }
+
+    /// Whether messages at `level` are shown
+    pub fn enabled(&self, level: Level) -> bool {
+        level.verbosity() <= self.verbosity
+    }
+
+    /// Print `message` at `level` if the verbosity shows it. In quiet mode the messages it hides
+    /// are demoted to the debug level, shown on stderr only when LAYER_TOOL_DEBUG is set.
+    pub fn print(&self, level: Level, message: impl Display) {
+        self.dispatch(level, message, None, None);
+    }
+
+    /// Print `message` to `scoped`, else to this printer's sink, passing `check_result` along
+    /// with it to the sink's structured variant
+    fn dispatch(&self, level: Level, message: impl Display, scoped: Option<&dyn OutputSink>, check_result: Option<&CheckResult>) {
+        let message = message.to_string();
+        log_event(level, &message);
+        let level = if self.enabled(level) {
+            level
+        } else if self.verbosity == Verbosity::Quiet
+            && level.verbosity() == Verbosity::Normal
+            && std::env::var_os(DEBUG_ENV).is_some()
+        {
+            Level::Debug
+        } else {
+            return;
+        };
+        let message = self.symbols.render(&message);
+        let sink = scoped.or(self.sink.as_deref()).unwrap_or(&ConsoleSink);
+        match check_result {
+            Some(result) if level == Level::Info => sink.check_result(result, &message),
+            _ => sink.emit(level, &message),
+        }
+    }
+
+    /// Use this printer for all output from now on
+    pub fn install(self) {
+        match PRINTER.write() {
+            Ok(mut printer) => *printer = self,
+            Err(poisoned) => *poisoned.into_inner() = self,
+        }
+    }
+}
+
+/// Print `message` at `level` through the installed printer
+pub fn print_at(level: Level, message: impl Display) {
+    dispatch(level, message, None);
+}
+
+/// Print through the installed printer to the sink entered on this thread, if any
+fn dispatch(level: Level, message: impl Display, check_result: Option<&CheckResult>) {
+    let scoped = SCOPED_SINK.with(|scoped| scoped.borrow().clone());
+    match PRINTER.read() {
+        Ok(printer) => printer.dispatch(level, message, scoped.as_deref(), check_result),
+        Err(poisoned) => poisoned.into_inner().dispatch(level, message, scoped.as_deref(), check_result),
+    }
+}
+
+/// Report progress to `sink` and, with `--progress json`, as an event on stderr
+fn report_progress(sink: &dyn OutputSink, event: EventKind) {
+    sink.progress(&event);
+    events::emit(event);
+}
+
+/// Pass a message to the tracing subscriber, if any, whatever the verbosity; the command's
+/// result is data rather than a message and is left out
+fn log_event(level: Level, message: &str) {
+    match level {
+        Level::Result => {}
+        Level::Error => tracing::error!("{}", strip_ansi(message)),
+        Level::Warn => tracing::warn!("{}", strip_ansi(message)),
+        Level::Info | Level::Progress => tracing::info!("{}", strip_ansi(message)),
+        Level::Debug => tracing::debug!("{}", strip_ansi(message)),
+        Level::Trace => tracing::trace!("{}", strip_ansi(message)),
+    }
+}
+
+/// `text` without the ANSI escape sequences that color it, and without the blank lines that
+/// separate sections on the console
+fn strip_ansi(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut characters = text.chars();
+    while let Some(character) = characters.next() {
+        if character == '\x1b' {
+            // Skip `[`, the parameters and the final byte
+            for character in characters.by_ref().skip(1) {
+                if ('@'..='~').contains(&character) {
+                    break;
+                }
+            }
+        } else {
+            plain.push(character);
+        }
+    }
+    plain.trim_start_matches('\n').to_string()
+}
+
+/// Append every message, at any verbosity, to the file at `path` as timestamped lines, while the
+/// console output stays as it is. Fails if a tracing subscriber is already installed.
+pub fn set_log_file(path: &Path) -> Result<()> {
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open log file {}", path.display()))?;
+    let file_layer = tracing_subscriber::fmt::layer()
+        .with_writer(Mutex::new(file))
+        .with_ansi(false)
+        .with_target(false)
+        .with_filter(LevelFilter::TRACE);
+    tracing_subscriber::registry()
+        .with(file_layer)
+        .try_init()
+        .context("Failed to install the log file subscriber")
+}
+
+/// Verbosity of the installed printer
+pub fn verbosity() -> Verbosity {
+    PRINTER.read().map_or(Verbosity::Normal, |printer| printer.verbosity())
+}
+
+/// Symbol style of the installed printer
+pub fn symbols() -> SymbolStyle {
+    PRINTER.read().map_or(SymbolStyle::Unicode, |printer| printer.symbols())
+}
+
+/// Whether the installed printer prints digests and paths in full
+pub fn full_values() -> bool {
+    PRINTER.read().is_ok_and(|printer| printer.full_values())
+}
+
+/// Whether the output printed on this thread goes to a custom sink rather than the console
+fn has_custom_sink() -> bool {
+    !current_sink().draws_progress_bars()
+}
+
+/// Route all human-readable output to stderr, keeping stdout free for data (e.g. `export -`)
+pub fn set_output_to_stderr(enabled: bool) {
+    OUTPUT_TO_STDERR.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    verbosity() == Verbosity::Quiet
+}
+
+/// Progress bar over bytes on stderr, labelled `message`; the length is set by whoever drives it.
+/// Hidden in quiet mode, with a custom sink and when stderr is not a terminal, so logs and pipes
+/// stay clean. With `--progress json` the bar is never drawn and reports the phase as events
+/// instead; end it with [`finish_progress`].
+pub fn byte_progress_bar(message: &str) -> ProgressBar {
+    metrics::sample_temp_space();
+    let sink = current_sink();
+    if events::is_enabled() || !sink.draws_progress_bars() {
+        report_progress(sink.as_ref(), EventKind::PhaseStarted { phase: message.to_string() });
+        let bar = ProgressBar::with_draw_target(None, ProgressDrawTarget::hidden());
+        bar.set_style(ProgressStyle::default_bar().with_key("event", events::PhaseTracker::new(message, sink)));
+        bar.set_message(message.to_string());
+        return bar;
+    }
+    if is_quiet() || !std::io::stderr().is_terminal() {
+        return ProgressBar::hidden();
+    }
+    let bar = ProgressBar::with_draw_target(None, ProgressDrawTarget::stderr());
+    bar.set_style(
+        ProgressStyle::with_template("{msg} [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})")
+            .unwrap_or_else(|_| ProgressStyle::default_bar())
+            .progress_chars("=> "),
+    );
+    bar.set_message(message.to_string());
+    bar
+}
+
+/// Clear a bar from [`byte_progress_bar`] once its phase is done, reporting the phase as
+/// completed to a custom sink and with `--progress json`
+pub fn finish_progress(progress: &ProgressBar) {
+    progress.finish_and_clear();
+    metrics::sample_temp_space();
+    let sink = current_sink();
+    if events::is_enabled() || !sink.draws_progress_bars() {
+        report_progress(sink.as_ref(), EventKind::PhaseCompleted {
+            phase: progress.message(),
+            bytes_done: progress.position(),
+            duration_ms: progress.elapsed().as_millis() as u64,
+        });
+    }
+}
+
+/// Clears the spinner of [`with_spinner`] when dropped, even if the call panics
+struct SpinnerGuard(ProgressBar);
+
+impl Drop for SpinnerGuard {
+    fn drop(&mut self) {
+        if let Ok(mut spinner) = SPINNER.lock() {
+            *spinner = None;
+        }
+        self.0.finish_and_clear();
+    }
+}
+
+/// Run `f`, typically a docker daemon query that can take seconds without printing anything,
+/// under a spinner on stderr labelled `message`. Shown only on a terminal, above quiet verbosity
+/// and without a custom sink or `--progress json`; lines printed meanwhile appear above it, and
+/// it is cleared before this returns.
+pub fn with_spinner<T>(message: &str, f: impl FnOnce() -> T) -> T {
+    if is_quiet() || events::is_enabled() || has_custom_sink() || !std::io::stderr().is_terminal() {
+        return f();
+    }
+    let Ok(mut active) = SPINNER.lock() else {
+        return f();
+    };
+    // A spinner is already running further up the call
+    if active.is_some() {
+        drop(active);
+        return f();
+    }
+
+    let spinner = ProgressBar::with_draw_target(None, ProgressDrawTarget::stderr());
+    let style = ProgressStyle::with_template("{spinner:.cyan} {msg}").unwrap_or_else(|_| ProgressStyle::default_spinner());
+    spinner.set_style(match symbols() {
+        SymbolStyle::Unicode => style,
+        SymbolStyle::Ascii => style.tick_chars("|/-\\ "),
+    });
+    spinner.set_message(symbols().render(message).into_owned());
+    spinner.enable_steady_tick(Duration::from_millis(100));
+    *active = Some(spinner.clone());
+    drop(active);
+
+    let _guard = SpinnerGuard(spinner);
+    f()
+}
+
+/// Label of the spinner shown while the docker daemon is queried
+pub const DOCKER_QUERY_MESSAGE: &str = "Querying docker daemon…";
+
+/// Print the one line a quiet command reports on stdout (e.g. the export's output path)
+pub fn print_quiet_result(message: &str) {
+    if is_quiet() {
+        print_result(message);
+    }
+}
+
+/// Print the command's output proper, e.g. a JSON report, on stdout at any verbosity
+pub fn print_result(message: &str) {
+    print_at(Level::Result, message);
+}
+
+/// Write one line of informational output
+fn emit(line: impl Display) {
+    print_at(Level::Info, line);
+}
+
+/// Print a plain, uncolored message
+pub fn print_plain(message: &str) {
+    emit(message);
+}
 
 /// Print a success message in green
 pub fn print_success(message: &str) {
-    println!("{}", message.green());
+    emit(message.green());
 }
 
 /// Print an error message in red
 pub fn print_error(message: &str) {
-    println!("{}", message.red());
+    error_with_code(message, None);
+}
+
+fn error_with_code(message: &str, code: Option<DiagnosticCode>) {
+    events::emit(EventKind::Error { message: message.trim().to_string(), code });
+    print_at(Level::Error, message.red());
 }
 
 /// Print a warning message in yellow
 pub fn print_warning(message: &str) {
-    println!("{}", message.yellow());
+    warning_with_code(message, None);
+}
+
+fn warning_with_code(message: &str, code: Option<DiagnosticCode>) {
+    events::emit(EventKind::Warning { message: message.trim().to_string(), code });
+    metrics::count_warning();
+    if let Ok(mut warnings) = WARNINGS.lock() {
+        warnings.push(message.to_string());
+    }
+    print_at(Level::Warn, message.yellow());
+}
+
+/// Print a diagnostic as `[W001] message`, like [`print_warning`] or [`print_error`] for its
+/// severity, and record it for the JSON report
+pub fn print_diagnostic(diagnostic: Diagnostic) {
+    let line = diagnostic.to_string();
+    match diagnostic.severity {
+        Severity::Warning => warning_with_code(&line, Some(diagnostic.code)),
+        Severity::Error => error_with_code(&line, Some(diagnostic.code)),
+    }
+    record_diagnostics(std::iter::once(diagnostic));
+}
+
+/// Record diagnostics printed some other way, e.g. in a section, for the JSON report
+pub fn record_diagnostics(diagnostics: impl IntoIterator<Item = Diagnostic>) {
+    if let Ok(mut recorded) = DIAGNOSTICS.lock() {
+        recorded.extend(diagnostics);
+    }
+}
+
+/// Return and clear the diagnostics recorded since the last call
+pub fn take_diagnostics() -> Vec<Diagnostic> {
+    DIAGNOSTICS.lock()
+        .map(|mut diagnostics| std::mem::take(&mut *diagnostics))
+        .unwrap_or_default()
+}
+
+/// Return and clear the warnings printed since the last call
+pub fn take_warnings() -> Vec<String> {
+    WARNINGS.lock()
+        .map(|mut warnings| std::mem::take(&mut *warnings))
+        .unwrap_or_default()
+}
+
+/// The warnings printed since the last `take_warnings`, left in place
+pub fn printed_warnings() -> Vec<String> {
+    WARNINGS.lock()
+        .map(|warnings| warnings.clone())
+        .unwrap_or_default()
+}
+
+/// Return and clear the check results printed since the last call
+pub fn take_check_results() -> Vec<CheckResult> {
+    CHECK_RESULTS.lock()
+        .map(|mut results| std::mem::take(&mut *results))
+        .unwrap_or_default()
 }
 
 /// Print an info message in blue
 pub fn print_info(message: &str) {
-    println!("{}", message.blue());
+    emit(message.blue());
 }
 
 /// Print a progress/status message in cyan
 pub fn print_progress(message: &str) {
-    println!("{}", message.cyan());
+    print_at(Level::Progress, message.cyan());
+}
+
+/// Print detail shown only with `-v`, e.g. how a container's layer directory was found
+pub fn print_debug(message: &str) {
+    print_at(Level::Debug, message.bright_black());
+}
+
+/// Print detail shown only with `-vv`, e.g. each docker command run
+pub fn print_trace(message: &str) {
+    print_at(Level::Trace, message.bright_black());
 }
 
 /// Print a header message in bold white
 pub fn print_header(message: &str) {
-    println!("{}", message.bold().white());
+    emit(message.bold().white());
 }
 
 /// Print a value with a label (label in white, value in bright white)
 pub fn print_labeled_value(label: &str, value: &str) {
-    println!("{}: {}", label.white(), value.bright_white());
+    emit(format!("{}: {}", label.white(), fit_value(value, label.chars().count() + 2).bright_white()));
 }
 
-/// Print a check result with appropriate color
+/// Print a check result with appropriate color. A status starting with ⏭ is a skipped check and
+/// one starting with ✗ a failed one; other unsuccessful ones are warnings. The symbol is printed in
+/// the installed printer's [`SymbolStyle`].
 pub fn print_check_result(label: &str, status: &str, is_success: bool) {
+    let status_kind = match status.chars().next() {
+        _ if is_success => CheckStatus::Pass,
+        Some('⏭') => CheckStatus::Skipped,
+        Some('✗') => CheckStatus::Fail,
+        _ => CheckStatus::Warn,
+    };
+    let result = CheckResult {
+        id: CheckResult::id_of(label),
+        name: label.to_string(),
+        status: status_kind,
+        detail: status.trim_start_matches(['✓', '⏭', '✗', '⚠']).trim().to_string(),
+    };
+    if let Ok(mut results) = CHECK_RESULTS.lock() {
+        results.push(result.clone());
+    }
     let colored_status = if is_success {
         status.green()
     } else if status.contains("Skipped") {
@@ -44,66 +779,421 @@ pub fn print_check_result(label: &str, status: &str, is_success: bool) {
     } else {
         status.red()
     };
-    println!("  {}: {}", label.white(), colored_status);
+    dispatch(Level::Info, format!("  {}: {}", label.white(), colored_status), Some(&result));
 }
 
 /// Print a section separator
 pub fn print_separator() {
-    println!("{}", "=".repeat(50).bright_black());
+    emit("=".repeat(50).bright_black());
 }
 
 /// Print formatted file size with label
 pub fn print_file_info(label: &str, path: &str, size: &str) {
-    println!("{}: {}", label.white(), path.bright_white());
-    println!("{}: {}", "File size".white(), size.bright_cyan());
+    emit(format!("{}: {}", label.white(), fit_value(path, label.chars().count() + 2).bright_white()));
+    emit(format!("{}: {}", "File size".white(), size.bright_cyan()));
 }
 
 /// Print container information
 pub fn print_container_info(label: &str, name: &str, id: &str) {
-    println!("{}: {} ({})", label.white(), name.bright_white(), id.bright_black());
+    emit(format!("{}: {} ({})", label.white(), name.bright_white(), shorten_digest(id).bright_black()));
 }
 
 /// Print checksum information
 pub fn print_checksum(label: &str, checksum: &str) {
-    println!("{}: {}", label.white(), checksum.bright_green());
+    emit(format!("{}: {}", label.white(), fit_value(checksum, label.chars().count() + 2).bright_green()));
 }
 
 /// Print a list item with bullet point
 pub fn print_list_item(item: &str) {
-    println!("  • {}", item.white());
+    emit(format!("  • {}", item.white()));
 }
 
 /// Print warnings section header and items
 pub fn print_warnings_section(warnings: &[String]) {
     if !warnings.is_empty() {
-        println!("\n{} {}", "⚠".yellow(), "Warnings:".yellow().bold());
+        print_at(Level::Warn, format!("\n{} {}", "⚠".yellow(), "Warnings:".yellow().bold()));
         for warning in warnings {
-            println!("  {}", warning.yellow());
+            print_at(Level::Warn, format!("  {}", warning.yellow()));
         }
     }
 }
 
+/// Print the line every command ends with: how long it took, the temp space it needed at most,
+/// the bytes it moved and the warnings it printed
+pub fn print_footer(metrics: &CommandMetrics) {
+    let mut parts = vec![format!("peak temp space {}", format_file_size(metrics.peak_temp_bytes))];
+    if let (Some(bytes_read), Some(bytes_written)) = (metrics.bytes_read, metrics.bytes_written) {
+        parts.push(format!("read {}", format_file_size(bytes_read)));
+        parts.push(format!("written {}", format_file_size(bytes_written)));
+    }
+    parts.push(match metrics.warnings {
+        1 => "1 warning".to_string(),
+        count => format!("{} warnings", count),
+    });
+    emit(format!("\nFinished in {:.2}s: {}", metrics.elapsed_secs, parts.join(", ")).bright_black());
+}
+
+/// Print the warnings and errors sections of diagnostics a check collected, each as
+/// `[W001] message`, and record them for the JSON report
+pub fn print_diagnostics_sections(diagnostics: &[Diagnostic]) {
+    let lines = |severity: Severity| diagnostics.iter()
+        .filter(|diagnostic| diagnostic.severity == severity)
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    print_warnings_section(&lines(Severity::Warning));
+    print_errors_section(&lines(Severity::Error));
+    record_diagnostics(diagnostics.iter().cloned());
+}
+
 /// Print errors section header and items
 pub fn print_errors_section(errors: &[String]) {
     if !errors.is_empty() {
-        println!("\n{} {}", "❌".red(), "Errors:".red().bold());
+        print_at(Level::Error, format!("\n{} {}", "❌".red(), "Errors:".red().bold()));
         for error in errors {
-            println!("  {}", error.red());
+            print_at(Level::Error, format!("  {}", error.red()));
         }
     }
 }
 
 /// Print a section header with decorative formatting
 pub fn print_section_header(title: &str) {
-    println!("\n{}", format!("=== {} ===", title).bold().bright_white());
+    emit(format!("\n{}", format!("=== {} ===", title).bold().bright_white()));
 }
 
 /// Print key-value pairs in a formatted way
 pub fn print_metadata_item(key: &str, value: &str) {
-    println!("  {}: {}", key.white(), value.bright_white());
+    emit(format!("  {}: {}", key.white(), fit_value(value, key.chars().count() + 4).bright_white()));
 }
 
 /// Print nested metadata item (with extra indentation)
 pub fn print_nested_metadata_item(key: &str, value: &str) {
-    println!("    {}: {}", key.white(), value.bright_white());
+    emit(format!("    {}: {}", key.white(), fit_value(value, key.chars().count() + 6).bright_white()));
+}
+
+/// Print export annotations as nested key-value pairs
+pub fn print_annotations(annotations: &std::collections::BTreeMap<String, String>) {
+    if !annotations.is_empty() {
+        print_metadata_item("Annotations", "");
+        for (key, value) in annotations {
+            print_nested_metadata_item(key, value);
+        }
+    }
+}
+
+/// Print the paths that vanished or changed while a running container's layer was archived
+pub fn print_transient_entries(entries: &[crate::types::TransientEntry]) {
+    let warnings: Vec<String> = entries.iter()
+        .map(|entry| format!("{}: {}", entry.path, entry.issue))
+        .collect();
+    print_warnings_section(&warnings);
+}
+
+/// Narrowest a table column is shrunk to when the table is wider than the terminal
+const MIN_COLUMN_WIDTH: usize = 8;
+
+/// Width of the terminal the human-readable output goes to; None when it goes to a pipe, a file
+/// or a custom sink, where lines are never cut
+pub fn output_width() -> Option<usize> {
+    if has_custom_sink() {
+        return None;
+    }
+    let size = if OUTPUT_TO_STDERR.load(Ordering::Relaxed) {
+        terminal_size::terminal_size_of(std::io::stderr())
+    } else {
+        terminal_size::terminal_size_of(std::io::stdout())
+    };
+    size.map(|(terminal_size::Width(width), _)| width as usize)
+}
+
+/// `text` cut to `width` characters, ending in an ellipsis when anything was cut
+pub fn truncate_end(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    let kept = width.saturating_sub(ellipsis().chars().count());
+    text.chars().take(kept).chain(ellipsis().chars()).take(width).collect()
+}
+
+/// Characters of a digest's hex kept when it is shortened, as `docker ps` does for IDs
+const SHORT_DIGEST_LEN: usize = 12;
+
+/// Shortest hex that is taken for a digest rather than ordinary text
+const MIN_DIGEST_LEN: usize = 32;
+
+/// Width long values are shortened to fit: the terminal's, unless `--full` was given or the
+/// output does not go to a terminal. JSON output is never shortened.
+fn shortening_width() -> Option<usize> {
+    if full_values() {
+        None
+    } else {
+        output_width()
+    }
+}
+
+/// Whether `value` is a digest or ID: hex of [`MIN_DIGEST_LEN`] or more characters, optionally
+/// after an algorithm such as `sha256:`
+fn is_digest(value: &str) -> bool {
+    let hex = value.split_once(':').map_or(value, |(_, hex)| hex);
+    hex.len() >= MIN_DIGEST_LEN && hex.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+/// The ellipsis marking where text was cut
+fn ellipsis() -> &'static str {
+    match symbols() {
+        SymbolStyle::Unicode => "…",
+        SymbolStyle::Ascii => "...",
+    }
+}
+
+/// A digest or ID cut to its algorithm and first [`SHORT_DIGEST_LEN`] hex characters, e.g.
+/// `sha256:3f2a9c1b7e4d…`, where values are shortened; anything else unchanged
+pub fn shorten_digest(value: &str) -> Cow<'_, str> {
+    if !is_digest(value) || shortening_width().is_none() {
+        return Cow::Borrowed(value);
+    }
+    let (prefix, hex) = match value.split_once(':') {
+        Some((algorithm, hex)) => (&value[..=algorithm.len()], hex),
+        None => ("", value),
+    };
+    Cow::Owned(format!("{}{}{}", prefix, &hex[..SHORT_DIGEST_LEN], ellipsis()))
+}
+
+/// `text` cut to `width` characters by replacing its middle with an ellipsis, so both the start
+/// and the end of a path stay readable
+pub fn truncate_middle(text: &str, width: usize) -> String {
+    let length = text.chars().count();
+    if length <= width {
+        return text.to_string();
+    }
+    let kept = width.saturating_sub(ellipsis().chars().count());
+    let tail = kept / 2;
+    let head = kept - tail;
+    text.chars().take(head)
+        .chain(ellipsis().chars())
+        .chain(text.chars().skip(length - tail))
+        .collect()
+}
+
+/// `value` as printed after `used` columns of label where values are shortened: a digest cut to
+/// its start, anything else, typically a path, cut in the middle to fit the rest of the line
+fn fit_value(value: &str, used: usize) -> Cow<'_, str> {
+    let Some(width) = shortening_width() else {
+        return Cow::Borrowed(value);
+    };
+    if is_digest(value) {
+        return shorten_digest(value);
+    }
+    let available = width.saturating_sub(used).max(MIN_COLUMN_WIDTH);
+    if value.chars().count() <= available {
+        Cow::Borrowed(value)
+    } else {
+        Cow::Owned(truncate_middle(value, available))
+    }
+}
+
+/// Print rows as a table with columns padded to their widest cell and a bold header row.
+/// Columns after the first are right-aligned, which suits numbers. On a terminal too narrow for
+/// the table the widest columns are narrowed, cutting their cells with an ellipsis. Commands with
+/// `--output json` put the rows in their report instead.
+pub fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = headers.iter().map(|header| header.chars().count()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    // Two spaces of indent and two between columns
+    if let Some(available) = output_width() {
+        let table_width = |widths: &[usize]| 2 + widths.iter().sum::<usize>() + 2 * widths.len().saturating_sub(1);
+        while table_width(&widths) > available {
+            let Some(widest) = widths.iter_mut().filter(|width| **width > MIN_COLUMN_WIDTH).max() else {
+                break;
+            };
+            *widest -= 1;
+        }
+    }
+
+    let format_row = |cells: &[String]| {
+        cells.iter()
+            .zip(&widths)
+            .enumerate()
+            .map(|(column, (cell, width))| {
+                let cell = truncate_end(cell, *width);
+                if column == 0 {
+                    format!("{:<width$}", cell, width = width)
+                } else {
+                    format!("{:>width$}", cell, width = width)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("  ")
+    };
+
+    let header_cells: Vec<String> = headers.iter().map(|header| header.to_string()).collect();
+    emit(format!("  {}", format_row(&header_cells).bold().white()));
+    for row in rows {
+        emit(format!("  {}", format_row(row).bright_white()));
+    }
+}
+
+/// Label/value lines printed as two columns, every value starting in the same column after the
+/// longest label. Values are colored like the matching print_* function, and shortened like it
+/// to fit the terminal.
+#[derive(Debug, Default)]
+pub struct KeyValues {
+    indent: usize,
+    /// An empty label continues the value above
+    rows: Vec<KeyValue>,
+}
+
+/// One line of [`KeyValues`]
+#[derive(Debug)]
+struct KeyValue {
+    label: String,
+    value: String,
+    /// Colors the value once it is fitted to the line
+    color: fn(&str) -> String,
+    /// Whether the value, e.g. a path, may be cut in the middle to fit the line
+    fit: bool,
+}
+
+impl KeyValues {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lines nested under a heading such as "Source container:"
+    pub fn indented() -> Self {
+        Self { indent: 2, rows: Vec::new() }
+    }
+
+    fn push(&mut self, label: &str, value: String, color: fn(&str) -> String, fit: bool) -> &mut Self {
+        self.rows.push(KeyValue { label: label.to_string(), value, color, fit });
+        self
+    }
+
+    /// A value, like [`print_labeled_value`]
+    pub fn value(&mut self, label: &str, value: &str) -> &mut Self {
+        self.push(label, value.to_string(), |value| value.bright_white().to_string(), true)
+    }
+
+    /// A file and its size, like [`print_file_info`]
+    pub fn file(&mut self, label: &str, path: &str, size: &str) -> &mut Self {
+        self.push(label, path.to_string(), |path| path.bright_white().to_string(), true);
+        self.push("File size", size.to_string(), |size| size.bright_cyan().to_string(), false)
+    }
+
+    /// A container's name and ID, like [`print_container_info`]
+    pub fn container(&mut self, label: &str, name: &str, id: &str) -> &mut Self {
+        let value = format!("{} ({})", name.bright_white(), shorten_digest(id).bright_black());
+        self.push(label, value, str::to_string, false)
+    }
+
+    /// A checksum, like [`print_checksum`]
+    pub fn checksum(&mut self, label: &str, checksum: &str) -> &mut Self {
+        self.push(label, shorten_digest(checksum).into_owned(), |checksum| checksum.bright_green().to_string(), false)
+    }
+
+    /// A list item under the value above, like [`print_list_item`]
+    pub fn item(&mut self, item: &str) -> &mut Self {
+        self.push("", format!("• {}", item.white()), str::to_string, false)
+    }
+
+    /// A key-value pair under the value above, e.g. one container label
+    pub fn entry(&mut self, key: &str, value: &str) -> &mut Self {
+        self.push("", format!("{}: {}", key.white(), value.bright_white()), str::to_string, false)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    pub fn print(&self) {
+        let label_width = self.rows.iter()
+            .filter(|row| !row.label.is_empty())
+            .map(|row| row.label.chars().count() + 1)
+            .max()
+            .unwrap_or(0);
+        for row in &self.rows {
+            let label = if row.label.is_empty() { String::new() } else { format!("{}:", row.label) };
+            if row.value.is_empty() {
+                emit(format!("{:indent$}{}", "", label.white(), indent = self.indent));
+                continue;
+            }
+            let value = if row.fit {
+                fit_value(&row.value, self.indent + label_width + 1)
+            } else {
+                Cow::Borrowed(row.value.as_str())
+            };
+            emit(format!(
+                "{:indent$}{}{:pad$} {}",
+                "", label.white(), "", (row.color)(&value),
+                indent = self.indent,
+                pad = label_width - label.chars().count(),
+            ));
+        }
+    }
+}
+
+/// Print how long each phase took, with throughput for phases that moved data
+pub fn print_phase_timings(phases: &[crate::types::PhaseTiming], total_secs: f64) {
+    if phases.is_empty() {
+        return;
+    }
+
+    let mut rows: Vec<Vec<String>> = phases.iter()
+        .map(|phase| vec![
+            phase.name.clone(),
+            format!("{:.2}s", phase.duration_secs),
+            phase.bytes.map(crate::utils::format_file_size).unwrap_or_default(),
+            phase.mb_per_sec.map(|rate| format!("{:.1} MB/s", rate)).unwrap_or_default(),
+        ])
+        .collect();
+    rows.push(vec!["total".to_string(), format!("{:.2}s", total_secs), String::new(), String::new()]);
+
+    print_info("\nPhase timings:");
+    print_table(&["Phase", "Duration", "Data", "Throughput"], &rows);
+}
+
+/// Terminal a prompt can be answered on when stdin carries data (e.g. an export piped into `import -`)
+const TTY_PATH: &str = "/dev/tty";
+
+/// Whether `confirm` can ask anything: stdin is a terminal, or the controlling terminal can be opened
+pub fn can_prompt() -> bool {
+    std::io::stdin().is_terminal() || std::fs::OpenOptions::new().read(true).write(true).open(TTY_PATH).is_ok()
+}
+
+/// Ask a yes/no question; anything but "y"/"yes" is a no. When stdin is not a terminal the question
+/// goes to /dev/tty instead, and without a terminal the answer is no.
+pub fn confirm(question: &str) -> bool {
+    prompt(&format!("{} [y/N] ", question.yellow()))
+        .is_some_and(|answer| matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Ask for the exact `expected` text, e.g. a container ID before its layer is replaced
+pub fn confirm_typed(question: &str, expected: &str) -> bool {
+    prompt(&format!("{} ", question.yellow()))
+        .is_some_and(|answer| answer.trim() == expected)
+}
+
+/// Read one line answering `prompt`, from stdin or else /dev/tty; None without a terminal
+fn prompt(prompt: &str) -> Option<String> {
+    let mut answer = String::new();
+
+    if std::io::stdin().is_terminal() {
+        if OUTPUT_TO_STDERR.load(Ordering::Relaxed) {
+            eprint!("{}", prompt);
+            let _ = std::io::stderr().flush();
+        } else {
+            print!("{}", prompt);
+            let _ = std::io::stdout().flush();
+        }
+        std::io::stdin().lock().read_line(&mut answer).ok()?;
+    } else {
+        let mut tty = std::fs::OpenOptions::new().read(true).write(true).open(TTY_PATH).ok()?;
+        tty.write_all(prompt.as_bytes()).and_then(|_| tty.flush()).ok()?;
+        std::io::BufReader::new(tty).read_line(&mut answer).ok()?;
+    }
+    Some(answer)
 }
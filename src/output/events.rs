@@ -0,0 +1,163 @@
+//! Newline-delimited JSON progress events for `--progress json`, one [`Event`] per line on stderr.
+//!
+//! The format is stable: fields and event types are only ever added, never renamed or removed,
+//! so readers should ignore what they do not know. Other lines on stderr (e.g. human-readable
+//! output moved there by `export -`) are not JSON objects and do not start with `{`.
+
+use indicatif::style::ProgressTracker;
+use indicatif::ProgressState;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use super::OutputSink;
+use crate::diagnostics::DiagnosticCode;
+
+/// Shortest interval between two `phase_progress` events of the same phase
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Whether events are written, set by [`enable`]
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Command the events are tagged with and when it started
+static COMMAND: OnceLock<(String, Instant)> = OnceLock::new();
+
+/// How progress is shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProgressMode {
+    /// Progress bars on stderr when it is a terminal
+    #[default]
+    Bar,
+    /// [`Event`]s on stderr, one JSON object per line
+    Json,
+}
+
+impl std::fmt::Display for ProgressMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProgressMode::Bar => write!(f, "bar"),
+            ProgressMode::Json => write!(f, "json"),
+        }
+    }
+}
+
+impl std::str::FromStr for ProgressMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "bar" => Ok(ProgressMode::Bar),
+            "json" => Ok(ProgressMode::Json),
+            _ => Err(format!("unknown progress mode '{}' (expected one of: bar, json)", value)),
+        }
+    }
+}
+
+/// One progress event
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    /// Subcommand that emitted the event, e.g. `export`
+    pub command: String,
+    /// Milliseconds since the command started, from a monotonic clock
+    pub timestamp_ms: u64,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+/// What happened, in the `event` field
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum EventKind {
+    /// A phase that moves data began, e.g. `Extracting`
+    PhaseStarted { phase: String },
+    /// Bytes processed so far; `bytes_total` is absent while the total is unknown
+    PhaseProgress {
+        phase: String,
+        bytes_done: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        bytes_total: Option<u64>,
+    },
+    PhaseCompleted { phase: String, bytes_done: u64, duration_ms: u64 },
+    /// A warning; `code` is present for diagnostics, e.g. `W001_STORAGE_DRIVER_MISMATCH`
+    Warning {
+        message: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        code: Option<DiagnosticCode>,
+    },
+    /// An error, including the one that ends a failed command
+    Error {
+        message: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        code: Option<DiagnosticCode>,
+    },
+}
+
+/// Write events for `command` from now on
+pub fn enable(command: &str) {
+    let _ = COMMAND.set((command.to_string(), Instant::now()));
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Write one event line to stderr if events are enabled
+pub fn emit(kind: EventKind) {
+    if !is_enabled() {
+        return;
+    }
+    let (command, started) = COMMAND.get_or_init(|| (String::new(), Instant::now()));
+    let event = Event {
+        command: command.clone(),
+        timestamp_ms: started.elapsed().as_millis() as u64,
+        kind,
+    };
+    if let Ok(line) = serde_json::to_string(&event) {
+        let _ = writeln!(std::io::stderr().lock(), "{}", line);
+    }
+}
+
+/// Tracker installed in a progress bar's style that turns its updates into `phase_progress`
+/// events for `sink`, at most one per [`PROGRESS_INTERVAL`]. The bar may be advanced from worker
+/// threads, so the sink is the one current where the bar was created.
+#[derive(Clone)]
+pub(crate) struct PhaseTracker {
+    phase: String,
+    last_emitted: Option<Instant>,
+    sink: Arc<dyn OutputSink>,
+}
+
+impl PhaseTracker {
+    pub(crate) fn new(phase: &str, sink: Arc<dyn OutputSink>) -> Self {
+        Self { phase: phase.to_string(), last_emitted: None, sink }
+    }
+}
+
+impl ProgressTracker for PhaseTracker {
+    fn clone_box(&self) -> Box<dyn ProgressTracker> {
+        Box::new(self.clone())
+    }
+
+    fn tick(&mut self, state: &ProgressState, now: Instant) {
+        if self.last_emitted.is_some_and(|last| now.duration_since(last) < PROGRESS_INTERVAL) {
+            return;
+        }
+        self.last_emitted = Some(now);
+        let event = EventKind::PhaseProgress {
+            phase: self.phase.clone(),
+            bytes_done: state.pos(),
+            bytes_total: state.len(),
+        };
+        self.sink.progress(&event);
+        emit(event);
+    }
+
+    fn reset(&mut self, _: &ProgressState, _: Instant) {
+        self.last_emitted = None;
+    }
+
+    fn write(&self, _: &ProgressState, _: &mut dyn std::fmt::Write) {}
+}
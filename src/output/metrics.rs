@@ -0,0 +1,56 @@
+//! Resource use of the running command, summed up in [`CommandMetrics`] for the footer every
+//! command ends with. The printing functions feed it: warnings are counted as they are printed,
+//! and the temporary workspaces are measured at each phase boundary, when a progress bar or a
+//! [`crate::timing::PhaseTimer`] phase starts or ends and when a workspace is removed.
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use crate::types::CommandMetrics;
+use crate::workspace::temp_space_used;
+
+/// When the command started, set by [`start`] or the first use
+static STARTED: OnceLock<Instant> = OnceLock::new();
+
+/// Largest temp space sampled so far
+static PEAK_TEMP_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// Warnings printed so far
+static WARNINGS: AtomicUsize = AtomicUsize::new(0);
+
+/// Start measuring the command; its elapsed time counts from here
+pub fn start() {
+    STARTED.get_or_init(Instant::now);
+}
+
+/// Measure the temporary workspaces and keep the size if it is the largest yet
+pub fn sample_temp_space() {
+    PEAK_TEMP_BYTES.fetch_max(temp_space_used(), Ordering::Relaxed);
+}
+
+pub(crate) fn count_warning() {
+    WARNINGS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// The command's resource use up to now
+pub fn snapshot() -> CommandMetrics {
+    sample_temp_space();
+    let (bytes_read, bytes_written) = process_io().unzip();
+    CommandMetrics {
+        elapsed_secs: STARTED.get_or_init(Instant::now).elapsed().as_secs_f64(),
+        peak_temp_bytes: PEAK_TEMP_BYTES.load(Ordering::Relaxed),
+        bytes_read,
+        bytes_written,
+        warnings: WARNINGS.load(Ordering::Relaxed),
+    }
+}
+
+/// Bytes this process read and wrote through any file descriptor, from `/proc/self/io`
+fn process_io() -> Option<(u64, u64)> {
+    let io = std::fs::read_to_string("/proc/self/io").ok()?;
+    let counter = |name: &str| io.lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+        .and_then(|value| value.trim().parse::<u64>().ok());
+    Some((counter("rchar")?, counter("wchar")?))
+}
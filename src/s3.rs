@@ -0,0 +1,205 @@
+use anyhow::{Context, Result};
+
+/// URL scheme selecting S3-compatible object storage as an export destination or import source
+pub const S3_SCHEME: &str = "s3://";
+
+/// Check whether a path refers to an S3 object
+pub fn is_s3_url(path: &str) -> bool {
+    path.starts_with(S3_SCHEME)
+}
+
+/// Bucket and key of an `s3://bucket/key` URL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct S3Location {
+    pub bucket: String,
+    pub key: String,
+}
+
+impl S3Location {
+    pub fn parse(url: &str) -> Result<Self> {
+        let rest = url.strip_prefix(S3_SCHEME)
+            .with_context(|| format!("Not an s3:// URL: {}", url))?;
+        match rest.split_once('/') {
+            Some((bucket, key)) if !bucket.is_empty() && !key.is_empty() && !key.ends_with('/') => Ok(Self {
+                bucket: bucket.to_string(),
+                key: key.to_string(),
+            }),
+            _ => Err(anyhow::anyhow!("Invalid S3 URL (expected s3://bucket/key): {}", url)),
+        }
+    }
+}
+
+/// Fail unless this build can talk to object storage
+pub fn ensure_available() -> Result<()> {
+    if cfg!(feature = "s3") {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "s3:// paths require layer-tool to be built with the `s3` feature (cargo build --features s3)"
+        ))
+    }
+}
+
+#[cfg(feature = "s3")]
+pub use client::{download_to_file, S3Upload, S3Writer};
+
+#[cfg(feature = "s3")]
+mod client {
+    use anyhow::{Context, Result};
+    use s3::bucket::Bucket;
+    use s3::creds::Credentials;
+    use s3::serde_types::Part;
+    use s3::Region;
+    use std::fs::File;
+    use std::io::{BufWriter, Write};
+    use std::path::Path;
+
+    use super::S3Location;
+
+    /// Size of each multipart upload part (S3 requires at least 5 MiB for all but the last part)
+    const PART_SIZE: usize = 32 * 1024 * 1024;
+
+    /// Content type recorded on uploaded export objects
+    const CONTENT_TYPE: &str = "application/octet-stream";
+
+    /// Final location of a completed upload
+    #[derive(Debug, Clone)]
+    pub struct S3Upload {
+        pub url: String,
+        pub etag: String,
+    }
+
+    /// Open a bucket using the standard AWS credential chain (environment, then profile).
+    /// `AWS_ENDPOINT_URL` selects an S3-compatible endpoint using path-style addressing.
+    fn open_bucket(location: &S3Location) -> Result<Box<Bucket>> {
+        let region_name = std::env::var("AWS_REGION")
+            .or_else(|_| std::env::var("AWS_DEFAULT_REGION"))
+            .unwrap_or_else(|_| "us-east-1".to_string());
+        let credentials = Credentials::new(None, None, None, None, None)
+            .context("Failed to load S3 credentials")?;
+
+        match std::env::var("AWS_ENDPOINT_URL") {
+            Ok(endpoint) => {
+                let region = Region::Custom { region: region_name, endpoint };
+                let bucket = Bucket::new(&location.bucket, region, credentials)
+                    .context("Failed to open S3 bucket")?;
+                Ok(bucket.with_path_style())
+            }
+            Err(_) => {
+                let region: Region = region_name.parse()
+                    .with_context(|| format!("Invalid S3 region: {}", region_name))?;
+                Bucket::new(&location.bucket, region, credentials)
+                    .context("Failed to open S3 bucket")
+            }
+        }
+    }
+
+    /// Writer that streams its data into an S3 multipart upload.
+    /// Dropping it without calling `finish` aborts the upload so no orphaned parts are left behind.
+    pub struct S3Writer {
+        bucket: Box<Bucket>,
+        key: String,
+        upload_id: String,
+        buffer: Vec<u8>,
+        parts: Vec<Part>,
+        finished: bool,
+    }
+
+    impl S3Writer {
+        pub fn create(url: &str) -> Result<Self> {
+            let location = S3Location::parse(url)?;
+            let bucket = open_bucket(&location)?;
+            let upload = bucket.initiate_multipart_upload(&location.key, CONTENT_TYPE)
+                .with_context(|| format!("Failed to start multipart upload to {}", url))?;
+
+            Ok(Self {
+                bucket,
+                key: location.key,
+                upload_id: upload.upload_id,
+                buffer: Vec::with_capacity(PART_SIZE),
+                parts: Vec::new(),
+                finished: false,
+            })
+        }
+
+        /// Upload the buffered data as the next part
+        fn upload_part(&mut self) -> std::io::Result<()> {
+            let part_number = self.parts.len() as u32 + 1;
+            let part = self.bucket
+                .put_multipart_chunk(&self.buffer, &self.key, part_number, &self.upload_id, CONTENT_TYPE)
+                .map_err(|e| std::io::Error::other(format!("Failed to upload part {}: {}", part_number, e)))?;
+            self.parts.push(part);
+            self.buffer.clear();
+            Ok(())
+        }
+
+        /// Upload the remaining data and complete the multipart upload
+        pub fn finish(mut self) -> Result<S3Upload> {
+            if !self.buffer.is_empty() || self.parts.is_empty() {
+                self.upload_part()?;
+            }
+
+            let response = self.bucket
+                .complete_multipart_upload(&self.key, &self.upload_id, self.parts.clone())
+                .context("Failed to complete multipart upload")?;
+            self.finished = true;
+
+            let etag = response.as_str()
+                .ok()
+                .and_then(|body| {
+                    let start = body.find("<ETag>")? + "<ETag>".len();
+                    let end = body[start..].find("</ETag>")? + start;
+                    Some(body[start..end].replace("&quot;", "\"").trim_matches('"').to_string())
+                })
+                .unwrap_or_default();
+
+            Ok(S3Upload {
+                url: format!("{}/{}", self.bucket.url(), self.key),
+                etag,
+            })
+        }
+    }
+
+    impl Write for S3Writer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let space = PART_SIZE - self.buffer.len();
+            let accepted = buf.len().min(space);
+            self.buffer.extend_from_slice(&buf[..accepted]);
+            if self.buffer.len() == PART_SIZE {
+                self.upload_part()?;
+            }
+            Ok(accepted)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Drop for S3Writer {
+        fn drop(&mut self) {
+            if !self.finished {
+                let _ = self.bucket.abort_upload(&self.key, &self.upload_id);
+            }
+        }
+    }
+
+    /// Download an S3 object into a local file, returning the number of bytes written
+    pub fn download_to_file<P: AsRef<Path>>(url: &str, output_path: P) -> Result<u64> {
+        let location = S3Location::parse(url)?;
+        let bucket = open_bucket(&location)?;
+
+        let output_file = File::create(&output_path)
+            .with_context(|| format!("Failed to create output file: {:?}", output_path.as_ref()))?;
+        let mut writer = BufWriter::new(output_file);
+        let status = bucket.get_object_to_writer(&location.key, &mut writer)
+            .with_context(|| format!("Failed to download {}", url))?;
+        if !(200..300).contains(&status) {
+            return Err(anyhow::anyhow!("Failed to download {}: HTTP status {}", url, status));
+        }
+        writer.flush()
+            .context("Failed to write downloaded export")?;
+
+        crate::utils::get_file_size(&output_path)
+    }
+}
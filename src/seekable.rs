@@ -0,0 +1,276 @@
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// Uncompressed bytes per frame of a seekable zstd export; reading any offset decompresses at
+/// most one frame before it
+pub const SEEKABLE_FRAME_SIZE: usize = 4 * 1024 * 1024;
+
+/// Compression level of zstd exports, zstd's default
+pub const ZSTD_LEVEL: i32 = 3;
+
+/// Magic of the skippable frame holding the seek table; zstd decoders without seekable
+/// support skip it
+const SKIPPABLE_MAGIC: u32 = 0x184D2A5E;
+
+/// Magic ending the seek table footer
+const SEEKABLE_MAGIC: u32 = 0x8F92EAB1;
+
+/// Number of frames, descriptor byte and seekable magic
+const FOOTER_SIZE: u64 = 9;
+
+/// Skippable frame magic and frame size
+const SKIPPABLE_HEADER_SIZE: u64 = 8;
+
+/// Descriptor flag for seek table entries carrying a checksum of the frame's content
+const CHECKSUM_FLAG: u8 = 0x80;
+
+/// Descriptor bits the format reserves, which must be zero
+const RESERVED_BITS: u8 = 0x7c;
+
+/// Largest number of frames a seek table may list
+const MAX_FRAMES: u32 = 0x0800_0000;
+
+/// One zstd frame of a seekable file and the part of the content it holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SeekFrame {
+    pub compressed_offset: u64,
+    pub compressed_size: u32,
+    pub decompressed_offset: u64,
+    pub decompressed_size: u32,
+}
+
+/// Frame index of a file in the zstd seekable format: independent zstd frames followed by a
+/// skippable frame listing their sizes, so any offset of the content can be reached by
+/// decompressing a single frame
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SeekTable {
+    frames: Vec<SeekFrame>,
+}
+
+impl SeekTable {
+    /// Read the seek table at the end of `reader`; None if it does not end in one
+    pub fn read<R: Read + Seek>(reader: &mut R) -> Result<Option<Self>> {
+        let file_size = reader.seek(SeekFrom::End(0))
+            .context("Failed to seek in zstd file")?;
+        if file_size < SKIPPABLE_HEADER_SIZE + FOOTER_SIZE {
+            return Ok(None);
+        }
+        let mut footer = [0u8; FOOTER_SIZE as usize];
+        reader.seek(SeekFrom::End(-(FOOTER_SIZE as i64)))
+            .and_then(|_| reader.read_exact(&mut footer))
+            .context("Failed to read zstd seek table footer")?;
+        if u32::from_le_bytes(footer[5..9].try_into().unwrap()) != SEEKABLE_MAGIC {
+            return Ok(None);
+        }
+
+        let frame_count = u32::from_le_bytes(footer[0..4].try_into().unwrap());
+        let descriptor = footer[4];
+        if frame_count > MAX_FRAMES || descriptor & RESERVED_BITS != 0 {
+            return Err(anyhow::anyhow!("Invalid zstd seek table footer"));
+        }
+        let entry_size: u64 = if descriptor & CHECKSUM_FLAG != 0 { 12 } else { 8 };
+        let table_size = SKIPPABLE_HEADER_SIZE + frame_count as u64 * entry_size + FOOTER_SIZE;
+        if table_size > file_size {
+            return Err(anyhow::anyhow!("zstd seek table is larger than the file"));
+        }
+
+        let mut table = vec![0u8; table_size as usize];
+        reader.seek(SeekFrom::End(-(table_size as i64)))
+            .and_then(|_| reader.read_exact(&mut table))
+            .context("Failed to read zstd seek table")?;
+        let magic = u32::from_le_bytes(table[0..4].try_into().unwrap());
+        let frame_size = u32::from_le_bytes(table[4..8].try_into().unwrap());
+        if magic != SKIPPABLE_MAGIC || frame_size as u64 != table_size - SKIPPABLE_HEADER_SIZE {
+            return Err(anyhow::anyhow!("Invalid zstd seek table frame"));
+        }
+
+        let mut frames = Vec::with_capacity(frame_count as usize);
+        let (mut compressed_offset, mut decompressed_offset) = (0, 0);
+        for entry in table[SKIPPABLE_HEADER_SIZE as usize..].chunks_exact(entry_size as usize).take(frame_count as usize) {
+            let compressed_size = u32::from_le_bytes(entry[0..4].try_into().unwrap());
+            let decompressed_size = u32::from_le_bytes(entry[4..8].try_into().unwrap());
+            frames.push(SeekFrame { compressed_offset, compressed_size, decompressed_offset, decompressed_size });
+            compressed_offset += compressed_size as u64;
+            decompressed_offset += decompressed_size as u64;
+        }
+        if compressed_offset != file_size - table_size {
+            return Err(anyhow::anyhow!(
+                "zstd seek table lists {} compressed bytes, the file holds {}", compressed_offset, file_size - table_size
+            ));
+        }
+        Ok(Some(Self { frames }))
+    }
+
+    pub fn frames(&self) -> &[SeekFrame] {
+        &self.frames
+    }
+
+    /// Size of the whole content once decompressed
+    pub fn decompressed_size(&self) -> u64 {
+        self.frames.last().map_or(0, |frame| frame.decompressed_offset + frame.decompressed_size as u64)
+    }
+
+    /// Index of the frame holding the content byte at `offset`
+    fn frame_at(&self, offset: u64) -> Option<usize> {
+        let index = self.frames.partition_point(|frame| frame.decompressed_offset + frame.decompressed_size as u64 <= offset);
+        (index < self.frames.len()).then_some(index)
+    }
+
+    fn push(&mut self, compressed_size: u32, decompressed_size: u32) {
+        let (compressed_offset, decompressed_offset) = self.frames.last()
+            .map_or((0, 0), |frame| (
+                frame.compressed_offset + frame.compressed_size as u64,
+                frame.decompressed_offset + frame.decompressed_size as u64,
+            ));
+        self.frames.push(SeekFrame { compressed_offset, compressed_size, decompressed_offset, decompressed_size });
+    }
+
+    /// The table as the skippable frame that ends a seekable file, without frame checksums
+    fn to_bytes(&self) -> Vec<u8> {
+        let entries_size = self.frames.len() as u64 * 8;
+        let mut bytes = Vec::with_capacity((SKIPPABLE_HEADER_SIZE + entries_size + FOOTER_SIZE) as usize);
+        bytes.extend_from_slice(&SKIPPABLE_MAGIC.to_le_bytes());
+        bytes.extend_from_slice(&((entries_size + FOOTER_SIZE) as u32).to_le_bytes());
+        for frame in &self.frames {
+            bytes.extend_from_slice(&frame.compressed_size.to_le_bytes());
+            bytes.extend_from_slice(&frame.decompressed_size.to_le_bytes());
+        }
+        bytes.extend_from_slice(&(self.frames.len() as u32).to_le_bytes());
+        bytes.push(0);
+        bytes.extend_from_slice(&SEEKABLE_MAGIC.to_le_bytes());
+        bytes
+    }
+}
+
+/// Writes a seekable zstd file: the content cut into frames of [`SEEKABLE_FRAME_SIZE`], up to
+/// `threads` of them compressed at once, then the seek table on [`SeekableWriter::finish`]
+pub struct SeekableWriter<W: Write> {
+    inner: W,
+    threads: usize,
+    pending: Vec<Vec<u8>>,
+    table: SeekTable,
+}
+
+impl<W: Write> SeekableWriter<W> {
+    pub fn new(inner: W, threads: usize) -> Self {
+        Self { inner, threads: threads.max(1), pending: vec![Vec::with_capacity(SEEKABLE_FRAME_SIZE)], table: SeekTable::default() }
+    }
+
+    /// Compress and write the pending frames
+    fn write_frames(&mut self) -> std::io::Result<()> {
+        let frames: Vec<(Vec<u8>, usize)> = self.pending.par_iter()
+            .filter(|content| !content.is_empty())
+            .map(|content| {
+                let mut compressor = zstd::bulk::Compressor::new(ZSTD_LEVEL)?;
+                compressor.set_parameter(zstd::zstd_safe::CParameter::ChecksumFlag(true))?;
+                Ok((compressor.compress(content)?, content.len()))
+            })
+            .collect::<std::io::Result<_>>()?;
+        for (frame, content_size) in frames {
+            self.inner.write_all(&frame)?;
+            self.table.push(frame.len() as u32, content_size as u32);
+        }
+        self.pending.clear();
+        self.pending.push(Vec::with_capacity(SEEKABLE_FRAME_SIZE));
+        Ok(())
+    }
+
+    /// Write the last frames and the seek table, returning the inner writer
+    pub fn finish(mut self) -> std::io::Result<W> {
+        self.write_frames()?;
+        self.inner.write_all(&self.table.to_bytes())?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for SeekableWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let current = self.pending.last_mut().expect("a frame is always pending");
+        let taken = buf.len().min(SEEKABLE_FRAME_SIZE - current.len());
+        current.extend_from_slice(&buf[..taken]);
+        if current.len() == SEEKABLE_FRAME_SIZE {
+            if self.pending.len() == self.threads {
+                self.write_frames()?;
+            } else {
+                self.pending.push(Vec::with_capacity(SEEKABLE_FRAME_SIZE));
+            }
+        }
+        Ok(taken)
+    }
+
+    /// Whole frames are only written once complete, so this flushes what was written before
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reads the content of a seekable zstd file, decompressing only the frames the reads touch
+pub struct SeekableReader<R: Read + Seek> {
+    inner: R,
+    table: SeekTable,
+    position: u64,
+    /// Index and content of the frame decompressed last
+    frame: Option<(usize, Vec<u8>)>,
+}
+
+impl<R: Read + Seek> SeekableReader<R> {
+    /// Reader over `inner`; None if it is not in the seekable format
+    pub fn new(mut inner: R) -> Result<Option<Self>> {
+        let Some(table) = SeekTable::read(&mut inner)? else {
+            return Ok(None);
+        };
+        Ok(Some(Self { inner, table, position: 0, frame: None }))
+    }
+
+    pub fn seek_table(&self) -> &SeekTable {
+        &self.table
+    }
+
+    /// Content of the frame at `index`, decompressing it unless it was the last one read
+    fn load_frame(&mut self, index: usize) -> std::io::Result<&[u8]> {
+        if self.frame.as_ref().is_none_or(|(loaded, _)| *loaded != index) {
+            let frame = self.table.frames[index];
+            let mut compressed = vec![0; frame.compressed_size as usize];
+            self.inner.seek(SeekFrom::Start(frame.compressed_offset))?;
+            self.inner.read_exact(&mut compressed)?;
+            let content = zstd::bulk::decompress(&compressed, frame.decompressed_size as usize)?;
+            if content.len() != frame.decompressed_size as usize {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!(
+                    "zstd frame {} holds {} bytes, the seek table records {}", index, content.len(), frame.decompressed_size
+                )));
+            }
+            self.frame = Some((index, content));
+        }
+        Ok(&self.frame.as_ref().expect("frame was just loaded").1)
+    }
+}
+
+impl<R: Read + Seek> Read for SeekableReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let Some(index) = self.table.frame_at(self.position) else {
+            return Ok(0);
+        };
+        let start = (self.position - self.table.frames[index].decompressed_offset) as usize;
+        let content = self.load_frame(index)?;
+        let bytes_read = buf.len().min(content.len() - start);
+        buf[..bytes_read].copy_from_slice(&content[start..start + bytes_read]);
+        self.position += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+
+impl<R: Read + Seek> Seek for SeekableReader<R> {
+    fn seek(&mut self, position: SeekFrom) -> std::io::Result<u64> {
+        let target = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.table.decompressed_size().checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = target.ok_or_else(|| std::io::Error::new(
+            std::io::ErrorKind::InvalidInput, "Seek before the start of the zstd content"
+        ))?;
+        Ok(self.position)
+    }
+}
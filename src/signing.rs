@@ -0,0 +1,181 @@
+use anyhow::{Context, Result};
+use ed25519_dalek::pkcs8::{DecodePrivateKey, DecodePublicKey, EncodePrivateKey, EncodePublicKey};
+use ed25519_dalek::pkcs8::spki::der::pem::LineEnding;
+use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+use sha2::{Digest, Sha512};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::output::{print_check_result, print_progress};
+use crate::tuning::IoTuning;
+use crate::utils::find_chunk_index;
+
+/// Suffix of the detached signature written next to a signed export
+pub const SIGNATURE_SUFFIX: &str = ".sig";
+
+/// Domain separation context for export signatures
+const SIGNATURE_CONTEXT: &[u8] = b"layer-tool export signature v1";
+
+/// Outcome of signature verification, shown in check results
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureStatus {
+    NotChecked,
+    Valid,
+}
+
+impl std::fmt::Display for SignatureStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SignatureStatus::NotChecked => write!(f, "Not checked"),
+            SignatureStatus::Valid => write!(f, "Valid"),
+        }
+    }
+}
+
+/// Path of the detached signature for a file
+pub fn signature_path<P: AsRef<Path>>(file_path: P) -> PathBuf {
+    let mut name = file_path.as_ref().as_os_str().to_owned();
+    name.push(SIGNATURE_SUFFIX);
+    PathBuf::from(name)
+}
+
+/// Generate a new key pair, returning the private and public key PEM documents
+pub fn generate_key_pair() -> Result<(String, String)> {
+    let signing_key = SigningKey::generate(&mut rand_core::OsRng);
+    let private_pem = signing_key.to_pkcs8_pem(LineEnding::LF)
+        .map_err(|e| anyhow::anyhow!("Failed to encode private key: {}", e))?;
+    let public_pem = signing_key.verifying_key().to_public_key_pem(LineEnding::LF)
+        .map_err(|e| anyhow::anyhow!("Failed to encode public key: {}", e))?;
+    Ok((private_pem.to_string(), public_pem))
+}
+
+/// Load an ed25519 private key from a PKCS#8 PEM file or a raw 32-byte (binary or hex) seed
+pub fn load_signing_key<P: AsRef<Path>>(key_path: P) -> Result<SigningKey> {
+    let key_path = key_path.as_ref();
+    let contents = std::fs::read(key_path)
+        .with_context(|| format!("Failed to read private key: {:?}", key_path))?;
+
+    if let Some(pem) = pem_text(&contents) {
+        return SigningKey::from_pkcs8_pem(pem)
+            .map_err(|e| anyhow::anyhow!("Invalid ed25519 private key in {:?}: {}", key_path, e));
+    }
+
+    let bytes = raw_key_bytes(&contents)
+        .with_context(|| format!("Invalid ed25519 private key in {:?}: expected PEM or 32 raw bytes", key_path))?;
+    Ok(SigningKey::from_bytes(&bytes))
+}
+
+/// Load an ed25519 public key from an SPKI PEM file or raw 32 bytes (binary or hex)
+pub fn load_verifying_key<P: AsRef<Path>>(key_path: P) -> Result<VerifyingKey> {
+    let key_path = key_path.as_ref();
+    let contents = std::fs::read(key_path)
+        .with_context(|| format!("Failed to read public key: {:?}", key_path))?;
+
+    if let Some(pem) = pem_text(&contents) {
+        return VerifyingKey::from_public_key_pem(pem)
+            .map_err(|e| anyhow::anyhow!("Invalid ed25519 public key in {:?}: {}", key_path, e));
+    }
+
+    let bytes = raw_key_bytes(&contents)
+        .with_context(|| format!("Invalid ed25519 public key in {:?}: expected PEM or 32 raw bytes", key_path))?;
+    VerifyingKey::from_bytes(&bytes)
+        .map_err(|e| anyhow::anyhow!("Invalid ed25519 public key in {:?}: {}", key_path, e))
+}
+
+/// Sign a file's bytes, writing the hex-encoded signature to `<file>.sig`
+pub fn sign_file<P: AsRef<Path>>(file_path: P, signing_key: &SigningKey) -> Result<PathBuf> {
+    let file_path = file_path.as_ref();
+    let digest = hash_file(file_path)?;
+    let signature = signing_key.sign_prehashed(digest, Some(SIGNATURE_CONTEXT))
+        .map_err(|e| anyhow::anyhow!("Failed to sign {:?}: {}", file_path, e))?;
+
+    let signature_path = signature_path(file_path);
+    std::fs::write(&signature_path, format!("{}\n", to_hex(&signature.to_bytes())))
+        .with_context(|| format!("Failed to write signature: {:?}", signature_path))?;
+    Ok(signature_path)
+}
+
+/// Verify `<file>.sig` against the file's bytes; a missing or invalid signature is an error
+pub fn verify_file_signature<P: AsRef<Path>>(file_path: P, verifying_key: &VerifyingKey) -> Result<()> {
+    let file_path = file_path.as_ref();
+    let signature_path = signature_path(file_path);
+    if !signature_path.exists() {
+        return Err(anyhow::anyhow!("Signature file not found: {}", signature_path.display()));
+    }
+
+    let signature_hex = std::fs::read_to_string(&signature_path)
+        .with_context(|| format!("Failed to read signature: {:?}", signature_path))?;
+    let signature_bytes: [u8; 64] = from_hex(signature_hex.trim())
+        .and_then(|bytes| bytes.try_into().ok())
+        .with_context(|| format!("Malformed signature file: {}", signature_path.display()))?;
+    let signature = Signature::from_bytes(&signature_bytes);
+
+    let digest = hash_file(file_path)?;
+    verifying_key.verify_prehashed(digest, Some(SIGNATURE_CONTEXT), &signature)
+        .map_err(|_| anyhow::anyhow!("Signature verification FAILED for {}: the file or signature has been tampered with or was signed by a different key", file_path.display()))
+}
+
+/// Verify the detached signature of an export before it is read.
+/// Split exports are signed through their chunk index, which records every chunk's checksum.
+pub fn verify_export_signature(input_path: &Path, pubkey_path: Option<&str>) -> Result<SignatureStatus> {
+    let pubkey_path = pubkey_path
+        .ok_or_else(|| anyhow::anyhow!("--verify-signature requires --pubkey <file>"))?;
+    let verifying_key = load_verifying_key(pubkey_path)?;
+
+    let signed_path = find_chunk_index(input_path).unwrap_or_else(|| input_path.to_path_buf());
+    print_progress(&format!("Verifying signature of {}...", signed_path.display()));
+    verify_file_signature(&signed_path, &verifying_key)?;
+    print_check_result("Signature", "✓ Valid", true);
+
+    Ok(SignatureStatus::Valid)
+}
+
+/// Streaming SHA-512 prehash of a file for Ed25519ph
+fn hash_file(file_path: &Path) -> Result<Sha512> {
+    let mut file = File::open(file_path)
+        .with_context(|| format!("Failed to open file for signing: {:?}", file_path))?;
+    let mut hasher = Sha512::new();
+    IoTuning::current().with_buffer(|buffer| loop {
+        let bytes_read = file.read(buffer)
+            .with_context(|| format!("Failed to read file: {:?}", file_path))?;
+        if bytes_read == 0 {
+            break anyhow::Ok(());
+        }
+        hasher.update(&buffer[..bytes_read]);
+    })?;
+
+    Ok(hasher)
+}
+
+/// Key file contents as PEM text, if they look like PEM
+fn pem_text(contents: &[u8]) -> Option<&str> {
+    std::str::from_utf8(contents)
+        .ok()
+        .filter(|text| text.trim_start().starts_with("-----BEGIN"))
+}
+
+/// Raw 32-byte key material given either as binary or as 64 hex characters
+fn raw_key_bytes(contents: &[u8]) -> Option<[u8; 32]> {
+    if let Ok(bytes) = <[u8; 32]>::try_from(contents) {
+        return Some(bytes);
+    }
+    std::str::from_utf8(contents)
+        .ok()
+        .and_then(|text| from_hex(text.trim()))
+        .and_then(|bytes| bytes.try_into().ok())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,168 @@
+use anyhow::{Context, Result};
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::thread::JoinHandle;
+
+/// Remote destination given as `user@host:/path`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SshTarget {
+    pub destination: String,
+    pub path: String,
+}
+
+impl SshTarget {
+    pub fn parse(spec: &str) -> Result<Self> {
+        match spec.split_once(':') {
+            Some((destination, path)) if !destination.is_empty() && !path.is_empty() => Ok(Self {
+                destination: destination.to_string(),
+                path: path.to_string(),
+            }),
+            _ => Err(anyhow::anyhow!("Invalid SSH destination (expected user@host:/path): {}", spec)),
+        }
+    }
+}
+
+/// Quote a string for safe use as a single word in a remote POSIX shell command
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Error returned when the remote side of an SSH pipe exits unsuccessfully
+#[derive(Debug)]
+pub struct RemoteCommandError {
+    pub destination: String,
+    pub exit_code: Option<i32>,
+    pub stderr: String,
+}
+
+impl fmt::Display for RemoteCommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.exit_code {
+            Some(code) => write!(f, "Remote command on {} failed with exit code {}", self.destination, code)?,
+            None => write!(f, "Remote command on {} was terminated by a signal", self.destination)?,
+        }
+        let stderr = self.stderr.trim();
+        if !stderr.is_empty() {
+            write!(f, ": {}", stderr)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RemoteCommandError {}
+
+/// Writer feeding the stdin of a remote command run over `ssh`.
+/// The remote stderr is echoed locally as it arrives and kept for error reporting.
+pub struct SshPipe {
+    destination: String,
+    child: Child,
+    stdin: Option<ChildStdin>,
+    stderr_reader: Option<JoinHandle<String>>,
+}
+
+impl SshPipe {
+    /// Run `remote_command` on `destination` with its stdin connected to this writer
+    pub fn spawn(destination: &str, remote_command: &str) -> Result<Self> {
+        let mut child = Command::new("ssh")
+            .args([destination, remote_command])
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("Failed to run ssh. Is the OpenSSH client installed?")?;
+
+        let stdin = child.stdin.take();
+        let stderr = child.stderr.take();
+        let stderr_reader = stderr.map(|stderr| {
+            std::thread::spawn(move || {
+                let mut captured = String::new();
+                for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                    eprintln!("{}", line);
+                    captured.push_str(&line);
+                    captured.push('\n');
+                }
+                captured
+            })
+        });
+
+        Ok(Self {
+            destination: destination.to_string(),
+            child,
+            stdin,
+            stderr_reader,
+        })
+    }
+
+    /// Close the remote stdin and wait for the command, failing with its exit code and stderr
+    pub fn finish(mut self) -> Result<()> {
+        drop(self.stdin.take());
+        let status = self.child.wait()
+            .context("Failed to wait for ssh")?;
+        let stderr = self.stderr_reader.take()
+            .and_then(|reader| reader.join().ok())
+            .unwrap_or_default();
+
+        if status.success() {
+            Ok(())
+        } else {
+            Err(RemoteCommandError {
+                destination: self.destination.clone(),
+                exit_code: status.code(),
+                stderr,
+            }.into())
+        }
+    }
+}
+
+impl Write for SshPipe {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.stdin.as_mut() {
+            Some(stdin) => stdin.write(buf),
+            None => Err(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "ssh stdin is closed")),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.stdin.as_mut() {
+            Some(stdin) => stdin.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for SshPipe {
+    fn drop(&mut self) {
+        // Only reached without finish() on a local failure; make sure the remote side stops too
+        if self.stdin.is_some() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+/// Stream data into a remote command, preferring the remote failure over the local broken-pipe error
+pub fn pipe_to_remote<F>(destination: &str, remote_command: &str, send: F) -> Result<u64>
+where
+    F: FnOnce(&mut SshPipe) -> Result<u64>,
+{
+    let mut pipe = SshPipe::spawn(destination, remote_command)?;
+    match send(&mut pipe) {
+        Ok(bytes) => {
+            pipe.finish()?;
+            Ok(bytes)
+        }
+        Err(send_error) => {
+            // A broken pipe means the remote side gave up first, so its error is the useful one.
+            // Any other local failure drops the pipe, killing the remote command before it can
+            // treat the truncated stream as complete.
+            let remote_closed = send_error.chain().any(|cause| {
+                cause.downcast_ref::<std::io::Error>()
+                    .is_some_and(|error| error.kind() == std::io::ErrorKind::BrokenPipe)
+            });
+            if remote_closed {
+                pipe.finish()?;
+            }
+            Err(send_error)
+        }
+    }
+}
@@ -0,0 +1,569 @@
+use anyhow::{Context, Result};
+use indicatif::ProgressBar;
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read};
+use std::path::{Component, Path};
+use std::rc::Rc;
+use tar::Archive;
+
+use crate::cancel::{CancellableReader, CancellationToken};
+use crate::tuning::IoTuning;
+use crate::types::{ArchiveFormat, Checksum, ChecksumAlgorithm, ChecksumScheme, EntryType, ExtractOptions, FindingKind, LayerStats, Manifest, ManifestEntry, OverwritePolicy, ScanFinding, SizedEntry};
+use crate::utils::{
+    check_archived_directory, check_archived_file, check_archived_symlink, decompressing_reader, open_seekable_export, format_file_size, is_overlay_whiteout, whiteout_of, Whiteout, get_file_size, export_format, extract_tar_archive,
+    AnyDigest, DiskUsage, EntryAttributes, HashingReader, LargestEntries, StreamingDirectoryChecksum
+};
+use crate::verify::MOUNTS_DIR;
+
+/// Name of the layer archive inside an export
+pub const LAYER_MEMBER: &str = "layer.tar";
+
+/// Open an export, compressed or not, as a tar archive read straight from the file
+pub fn open_export_archive<'a>(export_path: &Path, cancel: &'a CancellationToken) -> Result<Archive<Box<dyn Read + 'a>>> {
+    open_export_reader(export_path, cancel).map(Archive::new)
+}
+
+/// The tar stream of an export, decompressed if needed
+pub fn open_export_reader<'a>(export_path: &Path, cancel: &'a CancellationToken) -> Result<Box<dyn Read + 'a>> {
+    let file = File::open(export_path)
+        .with_context(|| format!("Failed to open export file: {:?}", export_path))?;
+    let file = IoTuning::current().reader(file);
+    let reader = decompressing_reader(export_format(export_path)?, file)?;
+    Ok(Box::new(CancellableReader::new(reader, cancel)))
+}
+
+/// Size of the end-of-archive marker a complete tar archive ends with
+const TAR_TRAILER_SIZE: u64 = 1024;
+
+/// Reader adapter counting the bytes read through it into a shared counter, so the position can be
+/// inspected after the reader was handed to a decoder or archive
+struct PositionReader<R> {
+    inner: R,
+    position: Rc<Cell<u64>>,
+}
+
+impl<R: Read> Read for PositionReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let bytes_read = self.inner.read(buf)?;
+        self.position.set(self.position.get() + bytes_read as u64);
+        Ok(bytes_read)
+    }
+}
+
+/// Read the whole export the way a check does, and describe where and how it is damaged if it
+/// cannot be read to the end: a gzip or zstd stream that breaks off or fails its integrity check
+/// (the gzip CRC/ISIZE trailer, zstd frame checksums), or a tar archive cut short inside an entry or before its end-of-archive marker. Returns None
+/// for an export that reads cleanly.
+pub fn diagnose_export_damage(export_path: &Path, cancel: &CancellationToken) -> Result<Option<String>> {
+    let file_size = get_file_size(export_path)?;
+    let format = export_format(export_path)?;
+    let compressed = format.is_compressed();
+    let file = File::open(export_path)
+        .with_context(|| format!("Failed to open export file: {:?}", export_path))?;
+    let file_position = Rc::new(Cell::new(0));
+    let archive_position = Rc::new(Cell::new(0));
+    let file = PositionReader { inner: file, position: file_position.clone() };
+    let reader = PositionReader { inner: decompressing_reader(format, BufReader::new(file))?, position: archive_position.clone() };
+
+    // Where the archive has to extend to, going by the sizes its entry headers record
+    let mut expected_end = 0;
+    let mut archive = Archive::new(CancellableReader::new(reader, cancel));
+    let mut read_error = None;
+    match archive.entries() {
+        Ok(entries) => {
+            for entry in entries {
+                let mut entry = match entry {
+                    Ok(entry) => entry,
+                    Err(error) => {
+                        read_error = Some(error);
+                        break;
+                    }
+                };
+                expected_end = entry.raw_file_position() + entry.size().div_ceil(512) * 512;
+                if let Err(error) = std::io::copy(&mut entry, &mut std::io::sink()) {
+                    read_error = Some(error);
+                    break;
+                }
+            }
+        }
+        Err(error) => read_error = Some(error),
+    }
+    if read_error.is_none()
+        && let Err(error) = std::io::copy(&mut archive.into_inner(), &mut std::io::sink())
+    {
+        read_error = Some(error);
+    }
+    cancel.check()?;
+
+    let archive_read = archive_position.get();
+    let expected_archive = expected_end + TAR_TRAILER_SIZE;
+    let truncated = match &read_error {
+        Some(error) => error.kind() == ErrorKind::UnexpectedEof || archive_read < expected_end,
+        None => archive_read < expected_archive,
+    };
+    if truncated {
+        // A compressed file is expected to be short by about as much as the archive it holds
+        let expected_file = if compressed {
+            (file_size as f64 * expected_archive as f64 / archive_read.max(1) as f64) as u64
+        } else {
+            expected_archive
+        };
+        let position = if compressed {
+            format!(
+                "the {} stream breaks off after {} of archive, which its entry headers say runs to at least {}",
+                format, format_file_size(archive_read), format_file_size(expected_archive)
+            )
+        } else {
+            format!(
+                "the archive ends at byte {}, which its entry headers say runs to at least byte {}",
+                archive_read, expected_archive
+            )
+        };
+        return Ok(Some(format!(
+            "The file appears truncated — expected roughly {} bytes, got {}: {}. Copy or download it again",
+            expected_file, file_size, position
+        )));
+    }
+
+    Ok(read_error.map(|error| if compressed && archive_read < expected_archive {
+        format!(
+            "The file is corrupt: decompression failed near byte {} of {}, after {} of archive: {}",
+            file_position.get().min(file_size), file_size, format_file_size(archive_read), error
+        )
+    } else if format == ArchiveFormat::Gzip {
+        format!("The file is corrupt: the gzip trailer check failed: {}", error)
+    } else if compressed {
+        format!("The file is corrupt: the {} integrity check failed: {}", format, error)
+    } else {
+        format!("The file is corrupt: invalid archive data after byte {} of {}: {}", expected_end, file_size, error)
+    }))
+}
+
+/// The members of an export that precede its layer archive, read into memory
+#[derive(Debug, Default)]
+pub struct ExportHeader {
+    pub metadata: Option<Vec<u8>>,
+    pub manifest: Option<Vec<u8>>,
+    /// Size of layer.tar, or None if the export has no layer archive
+    pub layer_size: Option<u64>,
+}
+
+/// Read metadata.json and manifest.json without unpacking the export. Exports write both ahead
+/// of layer.tar, so reading stops there rather than decompressing the layer; a seekable zstd
+/// export is read through its seek table, so only the frames holding the headers and these
+/// members are decompressed.
+pub fn read_export_header(export_path: &Path, cancel: &CancellationToken) -> Result<ExportHeader> {
+    if let Some(reader) = open_seekable_export(export_path)? {
+        let mut archive = Archive::new(reader);
+        let entries = archive.entries_with_seek().context("Failed to read export archive entries")?;
+        return read_header_members(entries, cancel);
+    }
+    let mut archive = open_export_archive(export_path, cancel)?;
+    read_header_members(archive.entries().context("Failed to read export archive entries")?, cancel)
+}
+
+fn read_header_members<R: Read>(entries: tar::Entries<'_, R>, cancel: &CancellationToken) -> Result<ExportHeader> {
+    let mut header = ExportHeader::default();
+    for entry in entries {
+        cancel.check()?;
+        let mut entry = entry.context("Failed to read export archive entry")?;
+        let path = entry.path().context("Failed to read export entry path")?.into_owned();
+        match path.to_str() {
+            Some("metadata.json") => header.metadata = Some(read_member(&mut entry, "metadata.json")?),
+            Some("manifest.json") => header.manifest = Some(read_member(&mut entry, "manifest.json")?),
+            Some(LAYER_MEMBER) => {
+                header.layer_size = Some(entry.size());
+                if header.metadata.is_some() {
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(header)
+}
+
+fn read_member<R: Read>(entry: &mut R, name: &str) -> Result<Vec<u8>> {
+    let mut content = Vec::new();
+    entry.read_to_end(&mut content)
+        .with_context(|| format!("Failed to read {} from export", name))?;
+    Ok(content)
+}
+
+/// Call `f` with a reader over the export's layer archive, decompressed on the fly
+pub fn with_layer_stream<T>(
+    export_path: &Path,
+    cancel: &CancellationToken,
+    f: impl FnOnce(&mut dyn Read) -> Result<T>,
+) -> Result<T> {
+    with_layer_stream_progress(export_path, cancel, &ProgressBar::hidden(), f)
+}
+
+/// [`with_layer_stream`], advancing `progress` by the layer archive bytes read out of the export
+pub fn with_layer_stream_progress<T>(
+    export_path: &Path,
+    cancel: &CancellationToken,
+    progress: &ProgressBar,
+    f: impl FnOnce(&mut dyn Read) -> Result<T>,
+) -> Result<T> {
+    let mut archive = open_export_archive(export_path, cancel)?;
+    for entry in archive.entries().context("Failed to read export archive entries")? {
+        let entry = entry.context("Failed to read export archive entry")?;
+        if entry.path().context("Failed to read export entry path")?.as_os_str() == LAYER_MEMBER {
+            progress.set_length(entry.size());
+            return f(&mut progress.wrap_read(entry));
+        }
+    }
+    Err(anyhow::anyhow!("Layer archive not found in export"))
+}
+
+/// Extract only the included mounts of an export into `output_dir` (as `mounts/<index>/...`)
+pub fn extract_export_mounts(export_path: &Path, output_dir: &Path, cancel: &CancellationToken) -> Result<()> {
+    let reader = open_export_reader(export_path, cancel)?;
+    // Owners and modes are part of the mount checksums, so all attributes are kept
+    let in_mounts = |path: &Path| path.starts_with(MOUNTS_DIR);
+    let options = ExtractOptions {
+        overwrite: OverwritePolicy::Error,
+        filter: Some(&in_mounts),
+        ..ExtractOptions::default()
+    };
+    extract_tar_archive(reader, output_dir, &options, cancel)?;
+    Ok(())
+}
+
+/// What a single pass over a layer archive found
+#[derive(Debug)]
+pub struct LayerScan {
+    pub entry_count: usize,
+    /// Entries whose header is not POSIX ustar, e.g. GNU headers from older exports
+    pub nonstandard_entries: usize,
+    /// Checksum of the layer archive's bytes
+    pub archive_checksum: Checksum,
+    /// Checksum of the layer the archive extracts to, finished in the export's scheme; None if
+    /// its layout does not allow computing it from the stream
+    pub layer_checksum: StreamingDirectoryChecksum,
+    /// Entries that do not match `manifest`, when one was given
+    pub mismatches: Vec<String>,
+    pub sizes: LayerSizes,
+    pub whiteouts: LayerWhiteouts,
+    pub findings: LayerFindings,
+}
+
+/// The whiteouts of a layer archive: what importing it deletes
+#[derive(Debug, Default)]
+pub struct LayerWhiteouts {
+    /// Layer-relative paths an overlayfs whiteout or AUFS-style `.wh.` file removes
+    pub removed: Vec<String>,
+    /// Layer-relative directories an opaque marker empties of what the archive does not write
+    pub opaque: Vec<String>,
+    /// How many of the whiteouts are overlayfs whiteout devices
+    pub devices: usize,
+}
+
+impl LayerWhiteouts {
+    /// Record `entry` if it is a whiteout or opaque marker; returns whether it was
+    pub fn add<R: Read>(&mut self, entry: &tar::Entry<R>, entry_path: &Path) -> bool {
+        let Some(whiteout) = whiteout_of(entry, entry_path) else {
+            return false;
+        };
+        if is_overlay_whiteout(entry) {
+            self.devices += 1;
+        }
+        match whiteout {
+            Whiteout::Path(path) => self.removed.push(path.to_string_lossy().into_owned()),
+            Whiteout::Opaque(directory) => self.opaque.push(directory.to_string_lossy().into_owned()),
+        }
+        true
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty() && self.opaque.is_empty()
+    }
+}
+
+/// Suspicious entries of a layer archive, as check --scan reports them
+#[derive(Debug, Default)]
+pub struct LayerFindings {
+    pub findings: Vec<ScanFinding>,
+}
+
+impl LayerFindings {
+    /// Record what is suspicious about `entry`; returns false if its path lies outside the layer,
+    /// in which case nothing else about it is looked at
+    pub fn add<R: Read>(&mut self, entry: &tar::Entry<R>, entry_path: &Path) -> bool {
+        if let Some(reason) = path_escape(entry_path) {
+            self.push(FindingKind::PathTraversal, entry_path, reason.to_string());
+            return false;
+        }
+
+        let header = entry.header();
+        let entry_type = header.entry_type();
+        // Absolute symlink targets resolve against the container's root, so only relative ones
+        // can leave it; hardlink targets are archive paths and must stay inside the layer
+        let link_name = entry.link_name().ok().flatten();
+        if let Some(target) = link_name {
+            if entry_type.is_symlink() && !target.is_absolute() && escapes_root(entry_path.parent().unwrap_or(Path::new("")), &target) {
+                self.push(FindingKind::LinkEscape, entry_path, format!("symlink to {} escapes the layer root", target.display()));
+            } else if entry_type.is_hard_link() && escapes_root(Path::new(""), &target) {
+                self.push(FindingKind::LinkEscape, entry_path, format!("hardlink to {} escapes the layer root", target.display()));
+            }
+        }
+
+        if (entry_type.is_character_special() || entry_type.is_block_special()) && !is_overlay_whiteout(entry) {
+            let kind = if entry_type.is_character_special() { "character" } else { "block" };
+            let major = header.device_major().ok().flatten().unwrap_or(0);
+            let minor = header.device_minor().ok().flatten().unwrap_or(0);
+            self.push(FindingKind::DeviceNode, entry_path, format!("{} device {}:{}", kind, major, minor));
+        }
+
+        if entry_type.is_file() {
+            let mode = header.mode().unwrap_or(0) & 0o7777;
+            let setid = match (mode & 0o4000 != 0, mode & 0o2000 != 0) {
+                (true, true) => Some("setuid and setgid"),
+                (true, false) => Some("setuid"),
+                (false, true) => Some("setgid"),
+                (false, false) => None,
+            };
+            if let Some(bits) = setid && mode & 0o111 != 0 {
+                self.push(FindingKind::SetidExecutable, entry_path, format!("{}, mode {:04o}", bits, mode));
+            }
+            if mode & 0o002 != 0 {
+                self.push(FindingKind::WorldWritable, entry_path, format!("mode {:04o}", mode));
+            }
+        }
+        true
+    }
+
+    fn push(&mut self, kind: FindingKind, path: &Path, detail: String) {
+        self.findings.push(ScanFinding { kind, path: path.to_string_lossy().into_owned(), detail });
+    }
+
+    /// Findings that place entries outside the layer
+    pub fn path_traversals(&self) -> impl Iterator<Item = &ScanFinding> {
+        self.findings.iter().filter(|finding| finding.kind.is_error())
+    }
+}
+
+/// Why an archive entry path would land outside the layer, if it would
+fn path_escape(path: &Path) -> Option<&'static str> {
+    path.components().find_map(|component| match component {
+        Component::ParentDir => Some("parent directory reference"),
+        Component::RootDir | Component::Prefix(_) => Some("absolute path"),
+        _ => None,
+    })
+}
+
+/// Whether `target`, resolved lexically from the layer directory `base`, climbs above the layer root
+fn escapes_root(base: &Path, target: &Path) -> bool {
+    let mut depth = 0usize;
+    for component in base.components().chain(target.components()) {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir => match depth.checked_sub(1) {
+                Some(parent) => depth = parent,
+                None => return true,
+            },
+            Component::RootDir | Component::Prefix(_) => return true,
+        }
+    }
+    false
+}
+
+/// Tracks the size of a layer, its largest regular files and the bytes under each top-level
+/// directory as its entries stream past
+#[derive(Debug)]
+pub struct LayerSizes {
+    largest: LargestEntries,
+    directories: BTreeMap<String, u64>,
+    stats: LayerStats,
+    /// Sum of the sizes of all entries: the uncompressed layer content
+    pub content_bytes: u64,
+    /// Estimated space the layer takes once extracted; one inode per entry
+    pub disk_usage: DiskUsage,
+}
+
+impl LayerSizes {
+    /// Keep the `top` largest files
+    pub fn new(top: usize) -> Self {
+        Self {
+            largest: LargestEntries::new(top),
+            directories: BTreeMap::new(),
+            stats: LayerStats::default(),
+            content_bytes: 0,
+            disk_usage: DiskUsage::default(),
+        }
+    }
+
+    /// Account for any layer entry of `entry_type` and `size` bytes
+    pub fn add_entry(&mut self, entry_type: tar::EntryType, size: u64) {
+        self.content_bytes += size;
+        self.disk_usage.add_entry(size);
+        if entry_type.is_file() {
+            self.stats.files += 1;
+            self.stats.logical_bytes += size;
+        } else if entry_type.is_hard_link() {
+            self.stats.files += 1;
+        } else if entry_type.is_dir() {
+            self.stats.directories += 1;
+        } else if entry_type.is_symlink() {
+            self.stats.symlinks += 1;
+        }
+    }
+
+    /// Account for a regular file of `size` bytes at the layer-relative `path`
+    pub fn add_file(&mut self, path: &str, size: u64) {
+        let directory = match path.split_once('/') {
+            Some((directory, _)) => format!("/{}", directory),
+            None => "/".to_string(),
+        };
+        *self.directories.entry(directory).or_default() += size;
+        self.largest.add(path, size);
+    }
+
+    /// The largest files, largest first
+    pub fn largest_entries(&self) -> Vec<SizedEntry> {
+        self.largest.sorted()
+    }
+
+    /// The layer's statistics, its physical size estimated like its disk usage
+    pub fn stats(&self) -> LayerStats {
+        LayerStats {
+            physical_bytes: self.disk_usage.bytes,
+            largest_entries: self.largest_entries(),
+            ..self.stats.clone()
+        }
+    }
+
+    /// Bytes under each top-level directory, largest first
+    pub fn directory_sizes(&self) -> Vec<SizedEntry> {
+        let mut directories: Vec<_> = self.directories.iter()
+            .map(|(path, size)| SizedEntry { path: path.clone(), size: *size })
+            .collect();
+        directories.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path)));
+        directories
+    }
+}
+
+/// Estimated space a layer archive takes once extracted, from its entry headers
+pub fn layer_stream_disk_usage<R: Read>(layer: R, cancel: &CancellationToken) -> Result<DiskUsage> {
+    let mut archive = Archive::new(CancellableReader::new(layer, cancel));
+    let mut usage = DiskUsage::default();
+    for entry in archive.entries().context("Failed to read layer archive entries")? {
+        let entry = entry.context("Failed to read layer archive entry")?;
+        usage.add_entry(entry.size());
+    }
+    Ok(usage)
+}
+
+/// Manifest of a layer archive as the export would have recorded it: file contents hashed with
+/// `algorithm` as they stream past, directories, symlinks, and nothing else
+pub fn layer_stream_manifest<R: Read>(layer: R, algorithm: ChecksumAlgorithm, cancel: &CancellationToken) -> Result<Manifest> {
+    let mut archive = Archive::new(CancellableReader::new(layer, cancel));
+    let mut manifest = Manifest::default();
+    for entry in archive.entries().context("Failed to read layer archive entries")? {
+        let mut entry = entry.context("Failed to read layer archive entry")?;
+        let entry_path = entry.path()
+            .context("Failed to read tar entry path")?
+            .into_owned();
+        let relative = entry_path.to_string_lossy().trim_end_matches('/').to_string();
+        let entry_type = entry.header().entry_type();
+        let mode = entry.header().mode().unwrap_or(0) & 0o7777;
+        let uid = entry.header().uid().ok().and_then(|uid| u32::try_from(uid).ok());
+        let gid = entry.header().gid().ok().and_then(|gid| u32::try_from(gid).ok());
+        let manifest_entry = if entry_type.is_file() {
+            let mut reader = HashingReader::with_digest(&mut entry, AnyDigest::new(algorithm));
+            std::io::copy(&mut reader, &mut std::io::sink())
+                .with_context(|| format!("Failed to read {:?} from layer archive", entry_path))?;
+            let (digest, size) = reader.finish();
+            ManifestEntry { entry_type: EntryType::File, size, mode, digest: Some(digest), uid, gid, target: None }
+        } else if entry_type.is_dir() {
+            ManifestEntry { entry_type: EntryType::Directory, size: 0, mode, digest: None, uid, gid, target: None }
+        } else if entry_type.is_symlink() {
+            let target = entry.link_name()
+                .context("Failed to read tar link target")?
+                .map(|target| target.to_string_lossy().into_owned());
+            ManifestEntry { entry_type: EntryType::Symlink, size: 0, mode, digest: None, uid, gid, target }
+        } else {
+            continue;
+        };
+        manifest.entries.insert(relative, manifest_entry);
+    }
+    Ok(manifest)
+}
+
+/// Read a layer archive once, without writing anything: validate its entries, hash it and recompute
+/// the layer checksum with `algorithm` in `scheme` (every scheme if None), track the `top` largest files and the suspicious entries, and compare every
+/// archived entry with the manifest if one is given. Manifest paths absent from the archive are
+/// not reported, as an incremental layer only carries changes.
+pub fn scan_layer_stream<R: Read>(
+    layer: R,
+    manifest: Option<&Manifest>,
+    scheme: Option<ChecksumScheme>,
+    algorithm: ChecksumAlgorithm,
+    top: usize,
+    cancel: &CancellationToken,
+) -> Result<LayerScan> {
+    let mut reader = HashingReader::with_digest(layer, AnyDigest::new(algorithm));
+    let mut entry_count = 0;
+    let mut nonstandard_entries = 0;
+    let mut sizes = LayerSizes::new(top);
+    let mut whiteouts = LayerWhiteouts::default();
+    let mut findings = LayerFindings::default();
+    let mut checksum = StreamingDirectoryChecksum::new(scheme, algorithm);
+    let mut mismatches = Vec::new();
+
+    let mut archive = Archive::new(&mut reader);
+    for entry in archive.entries().context("Failed to read layer archive entries")? {
+        cancel.check()?;
+        let mut entry = entry.context("Failed to read layer archive entry")?;
+        let entry_path = entry.path()
+            .context("Failed to read tar entry path")?
+            .into_owned();
+        entry_count += 1;
+        if entry.header().as_ustar().is_none() {
+            nonstandard_entries += 1;
+        }
+        sizes.add_entry(entry.header().entry_type(), entry.size());
+        if !findings.add(&entry, &entry_path) {
+            continue;
+        }
+        whiteouts.add(&entry, &entry_path);
+
+        let relative = entry_path.to_string_lossy().trim_end_matches('/').to_string();
+        let is_dir = entry.header().entry_type().is_dir();
+        let file_digest = checksum.add_entry(&entry_path, &mut entry)?;
+        if let Some((size, _)) = &file_digest {
+            sizes.add_file(&relative, *size);
+        }
+        if let Some(manifest) = manifest {
+            let expected = manifest.entries.get(&relative);
+            let archived = EntryAttributes::from_entry(&mut entry)?;
+            match file_digest {
+                Some((size, digest)) => mismatches.extend(check_archived_file(&relative, expected, size, &digest, &archived)),
+                None if is_dir => mismatches.extend(check_archived_directory(&relative, expected, &archived)),
+                None if entry.header().entry_type().is_symlink() => mismatches.extend(check_archived_symlink(&relative, expected, &mut entry)?),
+                None => {}
+            }
+        }
+    }
+
+    // Read the end-of-archive blocks too, so the checksum covers the whole file
+    std::io::copy(archive.into_inner(), &mut std::io::sink())
+        .context("Failed to read layer archive")?;
+    let (archive_checksum, _) = reader.finish();
+
+    Ok(LayerScan {
+        entry_count,
+        nonstandard_entries,
+        archive_checksum,
+        layer_checksum: checksum,
+        mismatches,
+        sizes,
+        whiteouts,
+        findings,
+    })
+}
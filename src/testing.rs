@@ -0,0 +1,66 @@
+//! Export files built without a Docker daemon, for tests of the commands reading them
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use tar::{Builder, EntryType, Header};
+
+use crate::cancel::CancellationToken;
+use crate::types::{ExportData, ExportDataV2, ExportFormatVersion, ExportMetadata, FormatVersion, LayerArchiveOptions, MANIFEST_FILE};
+use crate::utils::{calculate_file_checksum, calculate_file_digest, create_tar_archive_filtered};
+
+/// metadata.json as written by format 1.4
+pub const METADATA_V1: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/metadata-v1.4.json"));
+
+/// The export data of the format 1.4 fixture
+pub fn fixture_export_data() -> ExportData {
+    match ExportMetadata::parse(METADATA_V1.as_bytes(), FormatVersion::V1_4) {
+        Ok(ExportMetadata::V1(export_data)) => export_data,
+        other => panic!("format 1 fixture did not parse as ExportData: {:?}", other),
+    }
+}
+
+/// Write an uncompressed export of the layer directory `layer` into `workspace` as export does,
+/// with the fixture's container and daemon, returning its path and metadata
+pub fn write_export(layer: &Path, workspace: &Path) -> (PathBuf, ExportDataV2) {
+    let layer_tar = workspace.join("layer.tar");
+    let (layer_checksum, manifest, _) = create_tar_archive_filtered(
+        layer, &layer_tar, None, None, LayerArchiveOptions::default(), &CancellationToken::new()
+    ).unwrap();
+    let manifest_path = workspace.join(MANIFEST_FILE);
+    std::fs::write(&manifest_path, serde_json::to_string_pretty(&manifest).unwrap()).unwrap();
+
+    let mut export_data = fixture_export_data();
+    export_data.version = ExportFormatVersion::V2.format_version();
+    export_data.layer_archive_checksum = Some(calculate_file_digest(&layer_tar, layer_checksum.algorithm).unwrap());
+    export_data.layer_checksum = layer_checksum;
+    export_data.compressed = false;
+    export_data.manifest_checksum = Some(calculate_file_checksum(&manifest_path).unwrap());
+    let metadata = ExportMetadata::V2(export_data.into());
+    let metadata_path = workspace.join("metadata.json");
+    std::fs::write(&metadata_path, serde_json::to_string_pretty(&metadata).unwrap()).unwrap();
+
+    let export_path = workspace.join("export.tar");
+    let mut builder = Builder::new(File::create(&export_path).unwrap());
+    for (path, name) in [(&metadata_path, "metadata.json"), (&manifest_path, MANIFEST_FILE), (&layer_tar, "layer.tar")] {
+        let file = File::open(path).unwrap();
+        let mut header = Header::new_ustar();
+        header.set_size(file.metadata().unwrap().len());
+        header.set_mode(0o644);
+        header.set_mtime(0);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_entry_type(EntryType::Regular);
+        builder.append_data(&mut header, name, file).unwrap();
+    }
+    builder.finish().unwrap();
+    (export_path, metadata.normalize())
+}
+
+/// Overwrite the first occurrence of `original` in the file with `replacement` of the same length,
+/// e.g. to damage a file's contents inside an export while its tar headers stay valid
+pub fn corrupt(path: &Path, original: &[u8], replacement: &[u8]) {
+    let mut bytes = std::fs::read(path).unwrap();
+    let offset = bytes.windows(original.len()).position(|window| window == original).unwrap();
+    bytes[offset..offset + replacement.len()].copy_from_slice(replacement);
+    std::fs::write(path, bytes).unwrap();
+}
@@ -0,0 +1,67 @@
+use std::time::Instant;
+
+use crate::output::metrics;
+use crate::types::PhaseTiming;
+
+/// Records how long each phase of a command takes. Starting a phase ends the previous one.
+#[derive(Debug)]
+pub struct PhaseTimer {
+    started: Instant,
+    current: Option<(String, Instant, Option<u64>)>,
+    phases: Vec<PhaseTiming>,
+}
+
+impl PhaseTimer {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            current: None,
+            phases: Vec::new(),
+        }
+    }
+
+    /// End the running phase, if any, and start timing `name`
+    pub fn start(&mut self, name: &str) {
+        self.stop();
+        metrics::sample_temp_space();
+        self.current = Some((name.to_string(), Instant::now(), None));
+    }
+
+    /// Count `bytes` as data moved by the running phase
+    pub fn add_bytes(&mut self, bytes: u64) {
+        if let Some((_, _, phase_bytes)) = &mut self.current {
+            *phase_bytes = Some(phase_bytes.unwrap_or(0) + bytes);
+        }
+    }
+
+    /// End the running phase, if any
+    pub fn stop(&mut self) {
+        if let Some((name, started, bytes)) = self.current.take() {
+            metrics::sample_temp_space();
+            let duration_secs = started.elapsed().as_secs_f64();
+            self.phases.push(PhaseTiming {
+                name,
+                duration_secs,
+                bytes,
+                mb_per_sec: bytes.map(|bytes| bytes as f64 / (1024.0 * 1024.0) / duration_secs.max(0.001)),
+            });
+        }
+    }
+
+    /// Seconds since the timer was created
+    pub fn elapsed_secs(&self) -> f64 {
+        self.started.elapsed().as_secs_f64()
+    }
+
+    /// End the running phase and return every recorded phase in order
+    pub fn finish(mut self) -> Vec<PhaseTiming> {
+        self.stop();
+        self.phases
+    }
+}
+
+impl Default for PhaseTimer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
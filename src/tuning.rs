@@ -0,0 +1,195 @@
+use anyhow::{Context, Result};
+use std::cell::RefCell;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::utils::parse_size;
+
+/// Default size of the buffers files are hashed, archived and copied through. In the `io`
+/// benchmarks (`cargo bench --bench io`), 1 MiB archives a generated layer ~45% and BLAKE3-hashes
+/// a large file ~50% faster than the former 8 KiB; larger buffers gain nothing more, and SHA256
+/// hashing is bound by the CPU at any size.
+pub const DEFAULT_IO_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Smallest accepted buffer size, a single tar block
+pub const MIN_IO_BUFFER_SIZE: usize = 512;
+
+/// Largest accepted buffer size; every worker thread holds one buffer
+pub const MAX_IO_BUFFER_SIZE: usize = 256 * 1024 * 1024;
+
+/// Environment variable overriding the I/O buffer size, e.g. `LAYER_TOOL_IO_BUFFER_SIZE=256K`
+pub const IO_BUFFER_SIZE_ENV: &str = "LAYER_TOOL_IO_BUFFER_SIZE";
+
+/// Buffer size set through [`IoTuning::apply`]; 0 until then
+static APPLIED_BUFFER_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// Copy buffer reused by every [`IoTuning::copy`] on this thread
+    static COPY_BUFFER: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// How file I/O is buffered. The tuning given to [`IoTuning::apply`] is used process-wide;
+/// before that, [`IO_BUFFER_SIZE_ENV`] or the default is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoTuning {
+    buffer_size: usize,
+}
+
+impl Default for IoTuning {
+    fn default() -> Self {
+        Self { buffer_size: DEFAULT_IO_BUFFER_SIZE }
+    }
+}
+
+impl IoTuning {
+    /// Tuning with buffers of `buffer_size` bytes, which must lie between
+    /// [`MIN_IO_BUFFER_SIZE`] and [`MAX_IO_BUFFER_SIZE`]
+    pub fn new(buffer_size: u64) -> Result<Self> {
+        let range = MIN_IO_BUFFER_SIZE as u64..=MAX_IO_BUFFER_SIZE as u64;
+        if !range.contains(&buffer_size) {
+            return Err(anyhow::anyhow!(
+                "I/O buffer size must be between {} and {} bytes, got {}",
+                MIN_IO_BUFFER_SIZE, MAX_IO_BUFFER_SIZE, buffer_size
+            ));
+        }
+        Ok(Self { buffer_size: buffer_size as usize })
+    }
+
+    /// Tuning from [`IO_BUFFER_SIZE_ENV`], or the default when it is not set
+    pub fn from_env() -> Result<Self> {
+        match std::env::var(IO_BUFFER_SIZE_ENV) {
+            Ok(value) => parse_size(&value)
+                .and_then(Self::new)
+                .with_context(|| format!("Invalid {}", IO_BUFFER_SIZE_ENV)),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    /// The tuning in effect: the applied one, else the one from the environment, else the default
+    pub fn current() -> Self {
+        match APPLIED_BUFFER_SIZE.load(Ordering::Relaxed) {
+            0 => Self::from_env().unwrap_or_default(),
+            buffer_size => Self { buffer_size },
+        }
+    }
+
+    /// Use this tuning for all I/O from now on
+    pub fn apply(self) {
+        APPLIED_BUFFER_SIZE.store(self.buffer_size, Ordering::Relaxed);
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    pub fn reader<R: Read>(&self, inner: R) -> BufReader<R> {
+        BufReader::with_capacity(self.buffer_size, inner)
+    }
+
+    /// [`IoTuning::reader`] for `len` bytes, with no more buffer than that takes; many small
+    /// files do not each allocate a full buffer
+    pub fn reader_for<R: Read>(&self, inner: R, len: u64) -> BufReader<R> {
+        let capacity = usize::try_from(len).unwrap_or(usize::MAX).clamp(MIN_IO_BUFFER_SIZE, self.buffer_size);
+        BufReader::with_capacity(capacity, inner)
+    }
+
+    pub fn writer<W: Write>(&self, inner: W) -> BufWriter<W> {
+        BufWriter::with_capacity(self.buffer_size, inner)
+    }
+
+    /// Run `f` with a buffer of this size, reused across calls on the same thread
+    pub fn with_buffer<T>(&self, f: impl FnOnce(&mut [u8]) -> T) -> T {
+        COPY_BUFFER.with(|buffer| match buffer.try_borrow_mut() {
+            Ok(mut buffer) => {
+                buffer.resize(self.buffer_size, 0);
+                f(&mut buffer)
+            }
+            // Nested use, e.g. a reader that copies while being copied from
+            Err(_) => f(&mut vec![0; self.buffer_size]),
+        })
+    }
+
+    /// [`std::io::copy`] moving data in chunks of this size rather than std's 8 KiB
+    pub fn copy<R: Read + ?Sized, W: Write + ?Sized>(&self, reader: &mut R, writer: &mut W) -> std::io::Result<u64> {
+        self.with_buffer(|buffer| {
+            let mut copied = 0;
+            loop {
+                let bytes_read = match reader.read(buffer) {
+                    Ok(0) => return Ok(copied),
+                    Ok(bytes_read) => bytes_read,
+                    Err(error) if error.kind() == std::io::ErrorKind::Interrupted => continue,
+                    Err(error) => return Err(error),
+                };
+                writer.write_all(&buffer[..bytes_read])?;
+                copied += bytes_read as u64;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reader handing out at most 700 bytes per read, failing every other read with `Interrupted`
+    struct ChoppyReader<'a> {
+        data: &'a [u8],
+        interrupt: bool,
+    }
+
+    impl Read for ChoppyReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.interrupt = !self.interrupt;
+            if self.interrupt {
+                return Err(std::io::ErrorKind::Interrupted.into());
+            }
+            let len = buf.len().min(self.data.len()).min(700);
+            buf[..len].copy_from_slice(&self.data[..len]);
+            self.data = &self.data[len..];
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn buffer_sizes_outside_the_limits_are_refused() {
+        assert!(IoTuning::new(MIN_IO_BUFFER_SIZE as u64 - 1).is_err());
+        assert!(IoTuning::new(MAX_IO_BUFFER_SIZE as u64 + 1).is_err());
+        assert_eq!(IoTuning::new(MIN_IO_BUFFER_SIZE as u64).unwrap().buffer_size(), MIN_IO_BUFFER_SIZE);
+        assert_eq!(IoTuning::new(MAX_IO_BUFFER_SIZE as u64).unwrap().buffer_size(), MAX_IO_BUFFER_SIZE);
+        assert_eq!(IoTuning::default().buffer_size(), DEFAULT_IO_BUFFER_SIZE);
+
+        let parsed = parse_size("256K").and_then(IoTuning::new).unwrap();
+        assert_eq!(parsed.buffer_size(), 256 * 1024);
+    }
+
+    #[test]
+    fn copy_moves_every_byte_whatever_the_buffer_size() {
+        let data: Vec<u8> = (0..100_000u32).map(|index| (index % 253) as u8).collect();
+        for buffer_size in [MIN_IO_BUFFER_SIZE as u64, 4096, DEFAULT_IO_BUFFER_SIZE as u64] {
+            let tuning = IoTuning::new(buffer_size).unwrap();
+            let mut copied = Vec::new();
+            let bytes = tuning.copy(&mut ChoppyReader { data: &data, interrupt: false }, &mut copied).unwrap();
+            assert_eq!(bytes, data.len() as u64);
+            assert_eq!(copied, data);
+        }
+    }
+
+    #[test]
+    fn buffers_have_the_tuned_size() {
+        let tuning = IoTuning::new(64 * 1024).unwrap();
+        assert_eq!(tuning.reader(std::io::empty()).capacity(), 64 * 1024);
+        assert_eq!(tuning.writer(Vec::new()).capacity(), 64 * 1024);
+        // Small files get no more buffer than they need, but at least a tar block
+        assert_eq!(tuning.reader_for(std::io::empty(), 10).capacity(), MIN_IO_BUFFER_SIZE);
+        assert_eq!(tuning.reader_for(std::io::empty(), 5000).capacity(), 5000);
+        assert_eq!(tuning.reader_for(std::io::empty(), u64::MAX).capacity(), 64 * 1024);
+
+        // A nested use gets a buffer of its own, of the same size
+        let sizes = tuning.with_buffer(|outer| {
+            outer[0] = 1;
+            let inner = tuning.with_buffer(|inner| (inner.len(), inner[0]));
+            (outer.len(), inner)
+        });
+        assert_eq!(sizes, (64 * 1024, (64 * 1024, 0)));
+    }
+}
@@ -1,6 +1,12 @@
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use crate::backup::DEFAULT_KEEP_BACKUPS;
+use crate::diagnostics::Diagnostic;
+use crate::excludes::ExcludePreset;
+use crate::hooks::DEFAULT_POST_CHECK_TIMEOUT;
 
 /// Container metadata information
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -13,7 +19,7 @@ pub struct ContainerMetadata {
     pub created: DateTime<Utc>,
     pub state: String,
     pub status: String,
-    pub labels: HashMap<String, String>,
+    pub labels: BTreeMap<String, String>,
     pub mounts: Vec<MountInfo>,
 }
 
@@ -263,15 +269,1847 @@ pub struct CommitInfo {
     pub expected: String,
 }
 
-/// Export data structure
+/// Version of the export file format, recorded as `major.minor` in metadata.json. Files of an
+/// older version are read through the serde defaults of the fields added since; fields a newer
+/// version adds are ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum FormatVersion {
+    /// The original format, whose layer checksum is always [`ChecksumScheme::V1`]
+    V1_0,
+    /// Records the layer checksum scheme in `checksum_scheme`
+    V1_1,
+    /// Tags every checksum with its algorithm, as `<algorithm>:<hex>`
+    V1_2,
+    /// Records the owner of each manifest entry, which [`ChecksumScheme::V3`] hashes
+    V1_3,
+    /// Writes layer checksums in [`ChecksumScheme::V4`]
+    V1_4,
+    /// [`ExportDataV2`]: a compact [`EnvironmentInfo`] instead of the whole `docker info`, a
+    /// [`ManifestRef`], and checksums written as objects
+    V2_0,
+    /// A version this release does not know, written by another layer-tool release
+    Other { major: u32, minor: u32 },
+}
+
+/// Format version this release writes
+pub const CURRENT_FORMAT_VERSION: FormatVersion = FormatVersion::V2_0;
+
+/// Format version written by `export --format-version 1`, for releases that only read format 1
+pub const LATEST_V1_FORMAT_VERSION: FormatVersion = FormatVersion::V1_4;
+
+/// Whether this release can read files of a format version
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormatSupport {
+    /// This or an older version of the format
+    Supported,
+    /// A newer minor version: readable, but what it adds is ignored
+    NewerMinor,
+    /// A newer major version, which this release cannot read
+    NewerMajor,
+}
+
+impl FormatVersion {
+    pub fn new(major: u32, minor: u32) -> Self {
+        match (major, minor) {
+            (1, 0) => FormatVersion::V1_0,
+            (1, 1) => FormatVersion::V1_1,
+            (1, 2) => FormatVersion::V1_2,
+            (1, 3) => FormatVersion::V1_3,
+            (1, 4) => FormatVersion::V1_4,
+            (2, 0) => FormatVersion::V2_0,
+            (major, minor) => FormatVersion::Other { major, minor },
+        }
+    }
+
+    pub fn major(self) -> u32 {
+        match self {
+            FormatVersion::V1_0 | FormatVersion::V1_1 | FormatVersion::V1_2 | FormatVersion::V1_3 | FormatVersion::V1_4 => 1,
+            FormatVersion::V2_0 => 2,
+            FormatVersion::Other { major, .. } => major,
+        }
+    }
+
+    pub fn minor(self) -> u32 {
+        match self {
+            FormatVersion::V1_0 => 0,
+            FormatVersion::V1_1 => 1,
+            FormatVersion::V1_2 => 2,
+            FormatVersion::V1_3 => 3,
+            FormatVersion::V1_4 => 4,
+            FormatVersion::V2_0 => 0,
+            FormatVersion::Other { minor, .. } => minor,
+        }
+    }
+
+    /// How this release supports files of this version
+    pub fn support(self) -> FormatSupport {
+        let (major, minor) = (self.major(), self.minor());
+        let (current_major, current_minor) = (CURRENT_FORMAT_VERSION.major(), CURRENT_FORMAT_VERSION.minor());
+        if major > current_major {
+            FormatSupport::NewerMajor
+        } else if major == current_major && minor > current_minor {
+            FormatSupport::NewerMinor
+        } else {
+            FormatSupport::Supported
+        }
+    }
+}
+
+impl std::fmt::Display for FormatVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major(), self.minor())
+    }
+}
+
+impl std::str::FromStr for FormatVersion {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid format version '{}' (expected MAJOR.MINOR, e.g. 1.0)", value);
+        let (major, minor) = value.split_once('.').unwrap_or((value, "0"));
+        let major = major.parse().map_err(|_| invalid())?;
+        let minor = minor.parse().map_err(|_| invalid())?;
+        Ok(FormatVersion::new(major, minor))
+    }
+}
+
+impl TryFrom<String> for FormatVersion {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<FormatVersion> for String {
+    fn from(version: FormatVersion) -> String {
+        version.to_string()
+    }
+}
+
+/// How a directory checksum (the layer and mount checksums) is computed from the directory's
+/// files and subdirectories, taken in path order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChecksumScheme {
+    /// One SHA256 over each entry's relative path, followed by a file's contents; exports
+    /// of format 1.0 use it
+    #[default]
+    V1,
+    /// SHA256 over a record per entry: its relative path, a NUL, and for a file the SHA256 of
+    /// its contents, so files can be hashed in parallel
+    V2,
+    /// Like V2, but each record also holds the entry's type, permission bits, uid and gid, and
+    /// symlinks are recorded with their target instead of being followed. Modification times are
+    /// left out so that identical trees hash the same.
+    V3,
+    /// Like V3, but paths are hashed as their raw bytes instead of being converted to UTF-8
+    /// lossily, so names that differ only in invalid UTF-8 bytes no longer hash the same
+    V4,
+}
+
+/// Checksum scheme this release writes
+pub const CURRENT_CHECKSUM_SCHEME: ChecksumScheme = ChecksumScheme::V4;
+
+impl std::fmt::Display for ChecksumScheme {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChecksumScheme::V1 => write!(f, "v1"),
+            ChecksumScheme::V2 => write!(f, "v2"),
+            ChecksumScheme::V3 => write!(f, "v3"),
+            ChecksumScheme::V4 => write!(f, "v4"),
+        }
+    }
+}
+
+/// How a directory checksum is computed from the directory on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ChecksumFormat {
+    pub scheme: ChecksumScheme,
+    pub algorithm: ChecksumAlgorithm,
+    /// Shift applied to on-disk owners before [`ChecksumScheme::V3`] and later hash them, undoing an id
+    /// remapping done since the checksum was recorded
+    pub owner_shift: IdOffset,
+}
+
+impl ChecksumFormat {
+    pub fn new(scheme: ChecksumScheme, algorithm: ChecksumAlgorithm) -> Self {
+        Self { scheme, algorithm, owner_shift: IdOffset::default() }
+    }
+
+    pub fn with_owner_shift(self, owner_shift: IdOffset) -> Self {
+        Self { owner_shift, ..self }
+    }
+}
+
+/// Hash function of the layer, mount and manifest checksums
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChecksumAlgorithm {
+    #[default]
+    Sha256,
+    Blake3,
+}
+
+impl std::fmt::Display for ChecksumAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChecksumAlgorithm::Sha256 => write!(f, "sha256"),
+            ChecksumAlgorithm::Blake3 => write!(f, "blake3"),
+        }
+    }
+}
+
+impl std::str::FromStr for ChecksumAlgorithm {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "sha256" => Ok(ChecksumAlgorithm::Sha256),
+            "blake3" => Ok(ChecksumAlgorithm::Blake3),
+            _ => Err(format!("invalid checksum algorithm '{}' (expected sha256 or blake3)", value)),
+        }
+    }
+}
+
+/// A digest tagged with the algorithm that produced it, written as `<algorithm>:<hex>`. A bare hex
+/// digest, as exports of format 1.1 and older record them, is read as SHA256.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Checksum {
+    pub algorithm: ChecksumAlgorithm,
+    /// Lowercase hex digits of the digest
+    pub hex: String,
+}
+
+impl Checksum {
+    pub fn new(algorithm: ChecksumAlgorithm, hex: impl Into<String>) -> Self {
+        Self { algorithm, hex: hex.into() }
+    }
+}
+
+impl std::fmt::Display for Checksum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.algorithm, self.hex)
+    }
+}
+
+impl std::str::FromStr for Checksum {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (algorithm, hex) = match value.split_once(':') {
+            Some((algorithm, hex)) => (algorithm.parse()?, hex),
+            None => (ChecksumAlgorithm::Sha256, value),
+        };
+        if hex.is_empty() || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("invalid checksum '{}' (expected [ALGORITHM:]HEX)", value));
+        }
+        Ok(Checksum::new(algorithm, hex.to_lowercase()))
+    }
+}
+
+impl TryFrom<String> for Checksum {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Checksum> for String {
+    fn from(checksum: Checksum) -> String {
+        checksum.to_string()
+    }
+}
+
+/// How a layer directory is archived by export and clone
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LayerArchiveOptions {
+    /// Skip paths that cannot be read instead of failing
+    pub ignore_transient_errors: bool,
+    /// Shift applied to the owner ids recorded in the archive
+    pub id_offset: IdOffset,
+    /// Hash function of the layer checksum and manifest digests
+    pub checksum_algorithm: ChecksumAlgorithm,
+}
+
+/// Recorded attributes that verifying a layer against its manifest compares besides entry types,
+/// sizes, contents and symlink targets
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VerifiedAttributes {
+    /// Compare the mode, including setuid/setgid/sticky
+    pub mode: bool,
+    /// Compare the owners recorded in the manifest, shifting those on disk by this offset first
+    pub owner_shift: Option<IdOffset>,
+}
+
+/// What extraction does with an entry whose destination already exists. Directories onto
+/// existing directories are always merged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverwritePolicy {
+    /// Fail the extraction
+    Error,
+    /// Keep what is there and leave the entry out
+    Skip,
+    /// Remove what is there and extract the entry
+    #[default]
+    Replace,
+}
+
+/// Callback of [`ExtractOptions::progress`]
+pub type ExtractProgress = dyn Fn(&Path, u64);
+
+/// How [`crate::utils::extract_tar_archive`] writes entries. Entries are always kept inside the
+/// destination, whatever the options.
+#[derive(Clone, Copy)]
+pub struct ExtractOptions<'a> {
+    pub overwrite: OverwritePolicy,
+    /// Restore the full recorded mode, including setuid/setgid/sticky; without it entries get
+    /// only the recorded permission bits
+    pub preserve_permissions: bool,
+    /// Restore the recorded owner ids
+    pub preserve_ownership: bool,
+    /// Restore the recorded modification times; without it entries keep the time of extraction
+    pub preserve_mtime: bool,
+    /// Restore the extended attributes recorded in PAX headers
+    pub xattrs: bool,
+    /// Only entries whose archive path this returns true for are extracted
+    pub filter: Option<&'a dyn Fn(&Path) -> bool>,
+    /// Called with the archive path and size of each extracted entry
+    pub progress: Option<&'a ExtractProgress>,
+}
+
+impl Default for ExtractOptions<'_> {
+    fn default() -> Self {
+        Self {
+            overwrite: OverwritePolicy::default(),
+            preserve_permissions: true,
+            preserve_ownership: true,
+            preserve_mtime: true,
+            xattrs: true,
+            filter: None,
+            progress: None,
+        }
+    }
+}
+
+impl std::fmt::Debug for ExtractOptions<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExtractOptions")
+            .field("overwrite", &self.overwrite)
+            .field("preserve_permissions", &self.preserve_permissions)
+            .field("preserve_ownership", &self.preserve_ownership)
+            .field("preserve_mtime", &self.preserve_mtime)
+            .field("xattrs", &self.xattrs)
+            .field("filter", &self.filter.is_some())
+            .field("progress", &self.progress.is_some())
+            .finish()
+    }
+}
+
+/// What [`crate::utils::extract_tar_archive`] did
+#[derive(Debug, Default)]
+pub struct ExtractSummary {
+    /// Entries written to the destination
+    pub extracted: usize,
+    /// Archive paths left out because the destination existed under [`OverwritePolicy::Skip`]
+    pub skipped: Vec<String>,
+    /// Attributes that could not be restored, one line per path; see [`crate::attributes::AttributeRestorer::finish`]
+    pub unrestored: Vec<String>,
+}
+
+/// Export data of format 1, with the whole `docker info` of the daemon the export was taken on
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ExportData {
-    pub version: String,
+    pub version: FormatVersion,
     pub created: DateTime<Utc>,
     pub container_metadata: ContainerMetadata,
     pub docker_info: DockerInfo,
-    pub layer_checksum: String,
+    /// Directory checksum of the layer; its algorithm is also that of the mount, manifest entry
+    /// and layer archive checksums
+    pub layer_checksum: Checksum,
+    /// Scheme of the layer and mount checksums; V1 for exports of format 1.0
+    #[serde(default)]
+    pub checksum_scheme: ChecksumScheme,
+    /// Checksum of the layer.tar file itself, so a check can detect a corrupted archive without
+    /// extracting it; None for exports created by older versions
+    #[serde(default)]
+    pub layer_archive_checksum: Option<Checksum>,
+    pub compressed: bool,
+    #[serde(default)]
+    pub layer_kind: LayerKind,
+    #[serde(default)]
+    pub manifest_checksum: Option<String>,
+    #[serde(default)]
+    pub incremental: Option<IncrementalInfo>,
+    #[serde(default)]
+    pub annotations: BTreeMap<String, String>,
+    #[serde(default)]
+    pub redacted_keys: Vec<String>,
+    /// Paths that vanished or changed while a running container's layer was archived
+    #[serde(default)]
+    pub transient_entries: Vec<TransientEntry>,
+    /// Container mounts archived alongside the layer (export --include-mounts)
+    #[serde(default)]
+    pub mounts: Vec<MountExport>,
+    /// UID/GID offset of the userns-remapped daemon the export was taken on, still applied to
+    /// the layer's ownership; None when the layer holds unshifted ids
+    #[serde(default)]
+    pub id_offset: Option<IdOffset>,
+}
+
+/// Export data of format 2, the format this release writes. Only the daemon properties a
+/// compatibility check compares are kept, the manifest is referenced by path and checksum, and
+/// checksums are written as `{"algorithm": .., "hex": ..}` objects.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportDataV2 {
+    pub version: FormatVersion,
+    pub created: DateTime<Utc>,
+    pub container_metadata: ContainerMetadata,
+    pub environment: EnvironmentInfo,
+    /// Directory checksum of the layer; its algorithm is also that of the mount, manifest entry
+    /// and layer archive checksums
+    #[serde(with = "structured_checksum")]
+    pub layer_checksum: Checksum,
+    pub checksum_scheme: ChecksumScheme,
+    #[serde(default, with = "structured_checksum::option")]
+    pub layer_archive_checksum: Option<Checksum>,
     pub compressed: bool,
+    #[serde(default)]
+    pub layer_kind: LayerKind,
+    #[serde(default)]
+    pub manifest: Option<ManifestRef>,
+    #[serde(default)]
+    pub incremental: Option<IncrementalInfo>,
+    #[serde(default)]
+    pub annotations: BTreeMap<String, String>,
+    #[serde(default)]
+    pub redacted_keys: Vec<String>,
+    #[serde(default)]
+    pub transient_entries: Vec<TransientEntry>,
+    #[serde(default)]
+    pub mounts: Vec<MountExport>,
+    #[serde(default)]
+    pub id_offset: Option<IdOffset>,
+}
+
+impl ExportDataV2 {
+    /// SHA256 of manifest.json as the metadata records it, for [`crate::utils::parse_manifest`]
+    pub fn manifest_checksum(&self) -> Option<&str> {
+        self.manifest.as_ref().map(|manifest| manifest.checksum.hex.as_str())
+    }
+}
+
+/// Format 1 data in the shape of format 2; `version` still tells which format the file has
+impl From<ExportData> for ExportDataV2 {
+    fn from(data: ExportData) -> Self {
+        Self {
+            version: data.version,
+            created: data.created,
+            container_metadata: data.container_metadata,
+            environment: EnvironmentInfo::from(&data.docker_info),
+            layer_checksum: data.layer_checksum,
+            checksum_scheme: data.checksum_scheme,
+            layer_archive_checksum: data.layer_archive_checksum,
+            compressed: data.compressed,
+            layer_kind: data.layer_kind,
+            manifest: data.manifest_checksum.map(|hex| ManifestRef {
+                path: MANIFEST_FILE.to_string(),
+                checksum: Checksum::new(ChecksumAlgorithm::Sha256, hex),
+            }),
+            incremental: data.incremental,
+            annotations: data.annotations,
+            redacted_keys: data.redacted_keys,
+            transient_entries: data.transient_entries,
+            mounts: data.mounts,
+            id_offset: data.id_offset,
+        }
+    }
+}
+
+/// Name of the manifest in an export archive
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Where the manifest of an export is and what it hashes to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestRef {
+    /// Path of the manifest in the export archive
+    pub path: String,
+    /// SHA256 of the manifest file
+    #[serde(with = "structured_checksum")]
+    pub checksum: Checksum,
+}
+
+/// The properties of the Docker daemon an export was taken on that check and import compare
+/// with the target's
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EnvironmentInfo {
+    pub driver: String,
+    pub os: String,
+    pub os_type: String,
+    pub architecture: String,
+    pub kernel: String,
+    pub server_version: String,
+    pub root_dir: String,
+    #[serde(default)]
+    pub security_options: Vec<String>,
+    #[serde(default)]
+    pub cgroup_driver: String,
+}
+
+impl From<&DockerInfo> for EnvironmentInfo {
+    fn from(info: &DockerInfo) -> Self {
+        Self {
+            driver: info.driver.clone(),
+            os: info.operating_system.clone(),
+            os_type: info.os_type.clone(),
+            architecture: info.architecture.clone(),
+            kernel: info.kernel_version.clone(),
+            server_version: info.server_version.clone(),
+            root_dir: info.docker_root_dir.clone(),
+            security_options: info.security_options.clone(),
+            cgroup_driver: info.cgroup_driver.clone(),
+        }
+    }
+}
+
+/// The metadata.json of an export in either format; [`ExportMetadata::normalize`] gives the
+/// view the commands work with
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+#[allow(clippy::large_enum_variant)] // one per command, normalized right after parsing
+pub enum ExportMetadata {
+    V1(ExportData),
+    V2(ExportDataV2),
+}
+
+impl ExportMetadata {
+    /// Parse metadata.json of format `version`, which the caller has read ahead of the rest
+    pub fn parse(metadata_content: &[u8], version: FormatVersion) -> serde_json::Result<Self> {
+        if version.major() < 2 {
+            serde_json::from_slice(metadata_content).map(ExportMetadata::V1)
+        } else {
+            serde_json::from_slice(metadata_content).map(ExportMetadata::V2)
+        }
+    }
+
+    pub fn version(&self) -> FormatVersion {
+        match self {
+            ExportMetadata::V1(data) => data.version,
+            ExportMetadata::V2(data) => data.version,
+        }
+    }
+
+    /// The metadata in the shape of format 2, whichever format it was read from
+    pub fn normalize(self) -> ExportDataV2 {
+        match self {
+            ExportMetadata::V1(data) => data.into(),
+            ExportMetadata::V2(data) => data,
+        }
+    }
+}
+
+/// Serde of a [`Checksum`] as format 2 writes it, an object of its algorithm and hex digest
+pub mod structured_checksum {
+    use super::{Checksum, ChecksumAlgorithm};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    struct Structured<'a> {
+        algorithm: ChecksumAlgorithm,
+        hex: std::borrow::Cow<'a, str>,
+    }
+
+    pub fn serialize<S: Serializer>(checksum: &Checksum, serializer: S) -> Result<S::Ok, S::Error> {
+        Structured { algorithm: checksum.algorithm, hex: checksum.hex.as_str().into() }.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Checksum, D::Error> {
+        let structured = Structured::deserialize(deserializer)?;
+        // Validated the same way as the `<algorithm>:<hex>` form
+        Checksum::try_from(format!("{}:{}", structured.algorithm, structured.hex)).map_err(serde::de::Error::custom)
+    }
+
+    pub mod option {
+        use super::Checksum;
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        #[derive(Deserialize)]
+        struct Wrapped(#[serde(with = "super")] Checksum);
+
+        pub fn serialize<S: Serializer>(checksum: &Option<Checksum>, serializer: S) -> Result<S::Ok, S::Error> {
+            match checksum {
+                Some(checksum) => super::serialize(checksum, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Checksum>, D::Error> {
+            Ok(Option::<Wrapped>::deserialize(deserializer)?.map(|Wrapped(checksum)| checksum))
+        }
+    }
+}
+
+/// Shift applied to the owner ids of layer entries, as done by a userns-remap daemon
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdOffset {
+    pub uid: i64,
+    pub gid: i64,
+}
+
+impl IdOffset {
+    pub fn is_zero(&self) -> bool {
+        self.uid == 0 && self.gid == 0
+    }
+
+    /// Shift a uid/gid pair, or None if the result is not a valid id
+    pub fn apply(&self, uid: u64, gid: u64) -> Option<(u32, u32)> {
+        let shift = |id: u64, offset: i64| u32::try_from(i64::try_from(id).ok()?.checked_add(offset)?).ok();
+        Some((shift(uid, self.uid)?, shift(gid, self.gid)?))
+    }
+
+    /// The offset undoing this one
+    pub fn inverse(&self) -> IdOffset {
+        IdOffset { uid: -self.uid, gid: -self.gid }
+    }
+
+    /// The offset taking ids shifted by `from` to ids shifted by `self`
+    pub fn relative_to(&self, from: IdOffset) -> IdOffset {
+        IdOffset { uid: self.uid - from.uid, gid: self.gid - from.gid }
+    }
+}
+
+impl std::fmt::Display for IdOffset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "uid {:+}, gid {:+}", self.uid, self.gid)
+    }
+}
+
+/// A container mount whose contents are stored under `mounts/<index>/` in the export archive
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MountExport {
+    /// Position of the mount in the container's mount list
+    pub index: usize,
+    pub source: String,
+    pub destination: String,
+    /// Directory checksum of the mount contents (same scheme as the layer checksum)
+    pub checksum: Checksum,
+    pub entries: usize,
+}
+
+/// Linkage of an incremental export to the export it was taken relative to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IncrementalInfo {
+    /// Layer checksum recorded in the parent export; the target layer must match it before applying
+    pub parent_layer_checksum: Checksum,
+    /// Scheme of the parent's layer checksum
+    #[serde(default)]
+    pub parent_checksum_scheme: ChecksumScheme,
+    /// SHA256 of the parent export file
+    pub parent_export_checksum: String,
+    /// Paths present in the parent but deleted since
+    pub deleted_paths: Vec<String>,
+}
+
+/// Type of an entry recorded in the layer manifest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryType {
+    File,
+    Directory,
+    /// A symlink, archived as itself rather than followed
+    Symlink,
+}
+
+/// Manifest entry describing a single path in the layer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    #[serde(rename = "type")]
+    pub entry_type: EntryType,
+    pub size: u64,
+    pub mode: u32,
+    /// Digest of a file's contents, in the algorithm of the export's layer checksum; stored as
+    /// `sha256` from when that was the only algorithm
+    #[serde(rename = "sha256", default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<Checksum>,
+    /// Owner as archived (after any export id offset); None in manifests older than format 1.3
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gid: Option<u32>,
+    /// Target of a symlink, converted to UTF-8 lossily
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+}
+
+impl ManifestEntry {
+    /// Whether this entry is unchanged from `recorded`, the same path in an earlier manifest; an
+    /// owner the earlier manifest did not record is taken as unchanged
+    pub fn matches_recorded(&self, recorded: &ManifestEntry) -> bool {
+        let owner_matches = |current: Option<u32>, recorded: Option<u32>| recorded.is_none() || current == recorded;
+        self.entry_type == recorded.entry_type
+            && self.size == recorded.size
+            && self.mode == recorded.mode
+            && self.digest == recorded.digest
+            && self.target == recorded.target
+            && owner_matches(self.uid, recorded.uid)
+            && owner_matches(self.gid, recorded.gid)
+    }
+}
+
+/// Per-file manifest of the layer, keyed by path relative to the layer root
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub entries: BTreeMap<String, ManifestEntry>,
+}
+
+impl Manifest {
+    /// The algorithm of the file digests, or None without any. Digests of different algorithms
+    /// cannot be combined into one layer checksum, so a manifest mixing them is rejected.
+    pub fn checksum_algorithm(&self) -> Result<Option<ChecksumAlgorithm>, String> {
+        let mut algorithm = None;
+        for (path, entry) in &self.entries {
+            let Some(digest) = &entry.digest else { continue };
+            match algorithm {
+                None => algorithm = Some((digest.algorithm, path)),
+                Some((first, first_path)) if first != digest.algorithm => {
+                    return Err(format!(
+                        "manifest mixes checksum algorithms: {} is hashed with {}, {} with {}",
+                        first_path, first, path, digest.algorithm
+                    ));
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(algorithm.map(|(algorithm, _)| algorithm))
+    }
+}
+
+/// Kind of filesystem captured in the layer archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum LayerKind {
+    /// Container's read-write layer (UpperDir)
+    #[default]
+    Upper,
+    /// Complete root filesystem as seen by the container (MergedDir)
+    Full,
+}
+
+impl std::fmt::Display for LayerKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LayerKind::Upper => write!(f, "Upper (read-write layer)"),
+            LayerKind::Full => write!(f, "Full (complete root filesystem)"),
+        }
+    }
+}
+
+/// What happened to a path that was not stable while the layer was being archived
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransientIssue {
+    /// Deleted after the directory walk; left out of the archive and the checksum
+    Vanished,
+    /// Modified while being read; archived as re-read once
+    Changed,
+    /// Could not be read (only skipped with --ignore-transient-errors)
+    Unreadable,
+}
+
+impl std::fmt::Display for TransientIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransientIssue::Vanished => write!(f, "vanished during export, skipped"),
+            TransientIssue::Changed => write!(f, "changed while being read, archived as re-read"),
+            TransientIssue::Unreadable => write!(f, "unreadable, skipped"),
+        }
+    }
+}
+
+/// Sidecar of a backup taken by import or restore: `<name>.json` next to a backup directory, or
+/// next to `<name>.tar.gz` for a tarball in the backup directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupRecord {
+    pub container_id: String,
+    /// Upper layer directory the backup was taken from
+    pub original_path: String,
+    /// SHA256 of the backup tarball; None for directory backups
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive_checksum: Option<String>,
+    /// Directory checksum of the layer when it was backed up
+    #[serde(default)]
+    pub layer_checksum: Option<Checksum>,
+    /// Scheme of `layer_checksum`
+    #[serde(default)]
+    pub checksum_scheme: ChecksumScheme,
+    /// Export file whose import replaced the layer (for `restore`, the backup restored over it)
+    pub export_file: String,
+    pub created: DateTime<Utc>,
+}
+
+/// A layer path affected by concurrent modification during export
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransientEntry {
+    pub path: String,
+    pub issue: TransientIssue,
+}
+
+/// Index written next to an export that was split into fixed-size chunks
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkIndex {
+    pub file_name: String,
+    pub total_size: u64,
+    pub chunk_size: u64,
+    pub chunk_count: usize,
+    pub chunks: Vec<ChunkInfo>,
+}
+
+/// One chunk of a split export
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkInfo {
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// Version of the export report schema; bumped on incompatible changes
+pub const EXPORT_REPORT_VERSION: u32 = 1;
+
+/// Machine-readable summary of one export (export --report)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportReport {
+    pub report_version: u32,
+    pub container_id: String,
+    pub container_name: String,
+    pub image: String,
+    pub image_digest: String,
+    pub layer_kind: LayerKind,
+    pub layer_checksum: Checksum,
+    /// What the layer holds, as walked before archiving
+    #[serde(default)]
+    pub layer_stats: LayerStats,
+    /// Regular files in the layer
+    pub file_count: usize,
+    /// Size of the layer archive before compression
+    pub bytes_archived: u64,
+    pub compression: CompressionReport,
+    pub encrypted: bool,
+    /// Duration of each export phase, in order
+    pub phases: Vec<PhaseTiming>,
+    pub duration_secs: f64,
+    pub warnings: Vec<String>,
+    /// The warnings above that carry a code, with the values they were built from
+    #[serde(default)]
+    pub diagnostics: Vec<Diagnostic>,
+    /// Local file, chunk index, `-` for stdout, `user@host:path`, or `s3://` URL
+    pub output_path: String,
+    pub output_bytes: u64,
+    /// Time, temp space, I/O and warnings of the whole command
+    #[serde(default)]
+    pub metrics: CommandMetrics,
+}
+
+/// Version of the import report schema; bumped on incompatible changes
+pub const IMPORT_REPORT_VERSION: u32 = 1;
+
+/// Machine-readable outcome of one import (import --report, --output json), written for failed
+/// imports too
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportReport {
+    pub report_version: u32,
+    pub status: ImportStatus,
+    /// What the import left the target's layer as
+    pub target_layer: TargetLayerState,
+    /// Container as given on the command line, or the --target-path directory
+    pub target: String,
+    /// Full ID of the target container, once resolved
+    pub target_container_id: Option<String>,
+    pub input_path: String,
+    /// `replace` or `merge`
+    pub mode: String,
+    /// Source container and image recorded in the export, once it was read
+    pub source_container_id: Option<String>,
+    pub source_container_name: Option<String>,
+    pub image: Option<String>,
+    pub incremental: bool,
+    /// Backup of the replaced layer, kept as the rollback point
+    pub backup_path: Option<String>,
+    pub pruned_backups: Vec<String>,
+    /// Layer checksum the imported layer was verified against; None when only the imported
+    /// paths could be verified
+    pub verified_checksum: Option<Checksum>,
+    /// Layer entries written into the target
+    pub files_written: usize,
+    /// Paths removed from the target by whiteouts or the incremental export's deletions
+    pub files_deleted: usize,
+    /// Whiteouts and opaque markers applied from the layer archive
+    pub whiteouts_applied: usize,
+    pub excluded_paths: usize,
+    /// Whether the import continued an interrupted one (--resume)
+    pub resumed: bool,
+    /// Outcome of the --post-check command, if one was run
+    pub post_check: Option<PostCheckReport>,
+    /// What a merge found in the existing layer before modifying it
+    pub collisions: Option<CollisionReport>,
+    /// Duration of each import phase, in order
+    pub phases: Vec<PhaseTiming>,
+    pub duration_secs: f64,
+    pub warnings: Vec<String>,
+    /// Error chain of a failed import, outermost context first
+    pub error: Vec<String>,
+    /// The warnings above that carry a code, with the values they were built from
+    #[serde(default)]
+    pub diagnostics: Vec<Diagnostic>,
+    /// Time, temp space, I/O and warnings of the whole command
+    #[serde(default)]
+    pub metrics: CommandMetrics,
+}
+
+/// Outcome of the command an import runs inside the target container once the layer is in place
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PostCheckReport {
+    pub command: String,
+    pub passed: bool,
+    /// Exit code of the command; None if it timed out or was killed by a signal
+    pub exit_code: Option<i32>,
+    pub timed_out: bool,
+    pub duration_secs: f64,
+    /// Lines of stdout and stderr, in the order they were read
+    pub output: Vec<String>,
+}
+
+/// Existing layer file a merge import replaces with the export's version
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Collision {
+    pub path: String,
+    pub old_size: u64,
+    pub old_mtime: Option<DateTime<Utc>>,
+    pub new_size: u64,
+    pub new_mtime: Option<DateTime<Utc>>,
+}
+
+/// How a merge import changes the existing layer, worked out before the layer is modified.
+/// Paths are layer-relative.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CollisionReport {
+    /// Existing non-directory entries the export overwrites
+    pub overwritten: Vec<Collision>,
+    /// Existing entries whiteouts in the export remove
+    pub deleted: Vec<String>,
+    /// Non-directory entries the layer does not have yet
+    pub added: Vec<String>,
+}
+
+impl CollisionReport {
+    /// Existing entries the merge overwrites or deletes
+    pub fn changed_count(&self) -> usize {
+        self.overwritten.len() + self.deleted.len()
+    }
+}
+
+/// Whether an import succeeded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportStatus {
+    Succeeded,
+    Failed,
+}
+
+/// State an import left the target's layer in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TargetLayerState {
+    /// The import failed before the layer was touched
+    Unchanged,
+    /// The export was imported
+    Imported,
+    /// The import failed and the backup of the previous layer was restored
+    RolledBack,
+    /// The import failed with no backup to restore (--no-backup, --mode merge, --target-path)
+    PartiallyImported,
+    /// The import failed and restoring the backup failed too, or failed while taking the backup
+    Unknown,
+}
+
+/// Number of largest layer entries check lists by default
+pub const DEFAULT_TOP_ENTRIES: usize = 10;
+
+/// A layer path with the bytes stored under it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SizedEntry {
+    pub path: String,
+    pub size: u64,
+}
+
+/// What a layer holds, counted in one walk of its directory by a [`crate::utils::LayerScanner`]
+/// or as its archive is read. Symlinks are counted as such, not as what they point to.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LayerStats {
+    pub files: u64,
+    pub directories: u64,
+    pub symlinks: u64,
+    /// Sum of the sizes of the regular files
+    pub logical_bytes: u64,
+    /// Space the layer takes on disk: the blocks allocated to its files and directories, so holes
+    /// in sparse files are left out and hardlinked files counted once. Estimated from the entry
+    /// sizes for a layer archive.
+    pub physical_bytes: u64,
+    /// The largest regular files, largest first
+    pub largest_entries: Vec<SizedEntry>,
+    /// Every walked path in path order, for archiving and checksumming to reuse
+    #[serde(skip)]
+    pub entries: Vec<walkdir::DirEntry>,
+    /// Paths that vanished, or could not be read, during the walk
+    #[serde(skip)]
+    pub transient_entries: Vec<TransientEntry>,
+}
+
+/// Version of the check report schema; bumped on incompatible changes
+pub const CHECK_REPORT_VERSION: u32 = 1;
+
+/// Machine-readable result of `check` (check --output json)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckReport {
+    pub report_version: u32,
+    pub verdict: CheckVerdict,
+    pub input_path: String,
+    /// Container the export was checked against (check --container)
+    pub target_container: Option<String>,
+    /// Size of the input file, once it was read
+    pub file_size: Option<u64>,
+    /// Whether the export is gzip- or zstd-compressed, once it was opened
+    pub compressed: Option<bool>,
+    /// Whether a zstd export ends in a seek table that lets check jump to its members
+    pub seekable: Option<bool>,
+    /// Size of the export once decompressed, estimated from the gzip trailer (or the file size)
+    /// and the manifest
+    pub estimated_uncompressed_size: Option<u64>,
+    /// Sum of the sizes of the layer's entries: its uncompressed content
+    pub layer_bytes: Option<u64>,
+    pub layer_entries: Option<u64>,
+    /// Files, directories and symlinks in the layer and the bytes they hold
+    pub layer_stats: Option<LayerStats>,
+    /// Uncompressed layer size divided by the file size, for compressed exports
+    pub compression_ratio: Option<f64>,
+    /// Estimated disk space importing the layer takes, its entries rounded up to whole blocks
+    pub import_disk_usage: Option<u64>,
+    /// Free space on the target container's layer filesystem (check --container)
+    pub target_free_space: Option<u64>,
+    /// What the export's metadata records, once it was read
+    pub export: Option<CheckedExport>,
+    /// Every check performed, in order
+    pub checks: Vec<CheckResult>,
+    /// The largest regular files in the layer, largest first (check --top)
+    pub largest_entries: Vec<SizedEntry>,
+    /// Bytes of regular files under each top-level directory of the layer, largest first; files
+    /// directly in the layer root are counted under "/"
+    pub directory_sizes: Vec<SizedEntry>,
+    /// Layer paths the export's whiteouts remove on import
+    pub whiteouts: Vec<String>,
+    /// Layer directories the export's opaque markers empty on import, except for what it writes
+    pub opaque_directories: Vec<String>,
+    /// Suspicious layer entries found by check --scan
+    pub findings: Vec<ScanFinding>,
+    pub warnings: Vec<String>,
+    /// Problems found, followed by the error chain of a failed check
+    pub errors: Vec<String>,
+    /// The warnings and errors above that carry a code, with the values they were built from
+    #[serde(default)]
+    pub diagnostics: Vec<Diagnostic>,
+    /// Time, temp space, I/O and warnings of the whole command
+    #[serde(default)]
+    pub metrics: CommandMetrics,
+}
+
+/// A suspicious layer entry found while scanning an export
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanFinding {
+    pub kind: FindingKind,
+    /// Entry path as recorded in the layer archive
+    pub path: String,
+    pub detail: String,
+}
+
+/// What makes a layer entry suspicious
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FindingKind {
+    /// Absolute path or `..` component that would place the entry outside the layer
+    PathTraversal,
+    /// Hardlink or symlink whose target lies outside the layer root
+    LinkEscape,
+    /// Character or block device other than an overlayfs whiteout
+    DeviceNode,
+    /// Executable with the setuid or setgid bit
+    SetidExecutable,
+    /// Regular file anyone may write to
+    WorldWritable,
+}
+
+impl FindingKind {
+    /// Whether the finding fails the check instead of raising a warning
+    pub fn is_error(self) -> bool {
+        self == FindingKind::PathTraversal
+    }
+}
+
+impl std::fmt::Display for FindingKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FindingKind::PathTraversal => write!(f, "Path traversal"),
+            FindingKind::LinkEscape => write!(f, "Link escape"),
+            FindingKind::DeviceNode => write!(f, "Device node"),
+            FindingKind::SetidExecutable => write!(f, "Setuid/setgid executable"),
+            FindingKind::WorldWritable => write!(f, "World-writable file"),
+        }
+    }
+}
+
+/// Summary of an export's metadata in a check report
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckedExport {
+    pub version: FormatVersion,
+    pub created: DateTime<Utc>,
+    pub layer_kind: LayerKind,
+    pub container_id: String,
+    pub container_name: String,
+    pub image: String,
+    pub image_sha256: String,
+    pub layer_checksum: Checksum,
+    pub checksum_scheme: ChecksumScheme,
+    pub layer_archive_checksum: Option<Checksum>,
+    pub incremental: bool,
+    pub storage_driver: String,
+    pub operating_system: String,
+    pub architecture: String,
+    pub docker_version: String,
+    pub kernel_version: String,
+    pub cgroup_driver: String,
+    pub annotations: BTreeMap<String, String>,
+    pub mounts: usize,
+}
+
+/// One check performed on an export
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckResult {
+    /// Stable identifier of the check: its name in lowercase words joined by `-`, e.g.
+    /// `storage-driver`, as check --warn-as-error takes it
+    pub id: String,
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+impl CheckResult {
+    /// Identifier of the check named `name`
+    pub fn id_of(name: &str) -> String {
+        name.split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(str::to_lowercase)
+            .collect::<Vec<_>>()
+            .join("-")
+    }
+}
+
+/// Outcome of a single check
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+    Skipped,
+}
+
+/// Overall outcome of `check`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckVerdict {
+    Passed,
+    /// Every check passed or was skipped, but some raised warnings
+    PassedWithWarnings,
+    Failed,
+}
+
+impl std::fmt::Display for CheckVerdict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheckVerdict::Passed => write!(f, "passed"),
+            CheckVerdict::PassedWithWarnings => write!(f, "passed with warnings"),
+            CheckVerdict::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// Version of the diff report schema; bumped on incompatible changes
+pub const DIFF_REPORT_VERSION: u32 = 1;
+
+/// Machine-readable result of `diff` (diff --output json)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiffReport {
+    pub report_version: u32,
+    /// Whether the layers and the compared metadata are the same
+    pub identical: bool,
+    pub a: DiffSource,
+    pub b: DiffSource,
+    pub added: usize,
+    pub removed: usize,
+    pub modified: usize,
+    /// Change in the total size of regular files from `a` to `b`
+    pub size_delta: i64,
+    /// Every path that differs, in path order
+    pub changes: Vec<PathChange>,
+    pub metadata_changes: Vec<MetadataChange>,
+    /// Time, temp space, I/O and warnings of the whole command
+    #[serde(default)]
+    pub metrics: CommandMetrics,
+}
+
+/// One side of a diff
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiffSource {
+    /// The export file or container as given on the command line
+    pub source: String,
+    pub kind: DiffSourceKind,
+    pub image: String,
+    pub image_sha256: String,
+    pub layer_kind: LayerKind,
+    /// Files and directories in the layer
+    pub entries: usize,
+}
+
+/// Where one side of a diff was read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffSourceKind {
+    Export,
+    Container,
+}
+
+/// A layer path that differs between the two sides of a diff
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PathChange {
+    pub path: String,
+    pub change: ChangeKind,
+    pub size_before: Option<u64>,
+    pub size_after: Option<u64>,
+    pub size_delta: i64,
+    /// What differs about a modified path: its type, content and/or mode
+    pub detail: Option<String>,
+}
+
+/// How a path differs between the two sides of a diff
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Modified,
+}
+
+impl std::fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChangeKind::Added => write!(f, "A"),
+            ChangeKind::Removed => write!(f, "D"),
+            ChangeKind::Modified => write!(f, "M"),
+        }
+    }
+}
+
+/// A metadata field that differs between the two sides of a diff; None where a side lacks it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MetadataChange {
+    pub field: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+/// Compression applied to an export and its effect
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompressionReport {
+    /// `gzip`, `zstd` or `none`
+    pub algorithm: String,
+    /// Whether a zstd export ends in a seek table
+    #[serde(default)]
+    pub seekable: bool,
+    pub uncompressed_bytes: u64,
+    pub compressed_bytes: u64,
+    /// Uncompressed size divided by compressed size
+    pub ratio: f64,
+}
+
+/// Container format of an export file, recognized from its leading magic bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Gzip,
+    Zstd,
+    Xz,
+    /// An uncompressed tar archive
+    PlainTar,
+    /// Encrypted with age, in its binary or ASCII-armored form
+    Age,
+    Unknown,
+}
+
+/// Offset of the `ustar` magic in a tar header
+const TAR_MAGIC_OFFSET: usize = 257;
+
+impl ArchiveFormat {
+    /// Recognize the format from the first bytes of a file; [`ArchiveFormat::PlainTar`] needs the
+    /// first 262 to reach the tar magic
+    pub fn detect(head: &[u8]) -> Self {
+        const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+        const XZ_MAGIC: &[u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0x00];
+        if head.starts_with(&[0x1f, 0x8b]) {
+            ArchiveFormat::Gzip
+        } else if head.starts_with(ZSTD_MAGIC)
+            // A skippable frame, which seekable zstd archives may start with
+            || (head.len() >= 4 && head[0] & 0xf0 == 0x50 && head[1..4] == [0x2a, 0x4d, 0x18])
+        {
+            ArchiveFormat::Zstd
+        } else if head.starts_with(XZ_MAGIC) {
+            ArchiveFormat::Xz
+        } else if head.starts_with(b"age-encryption.org/") || head.starts_with(b"-----BEGIN AGE ENCRYPTED FILE-----") {
+            ArchiveFormat::Age
+        } else if head.get(TAR_MAGIC_OFFSET..TAR_MAGIC_OFFSET + 5) == Some(b"ustar") {
+            ArchiveFormat::PlainTar
+        } else {
+            ArchiveFormat::Unknown
+        }
+    }
+
+    /// Whether this release can read exports of this format
+    pub fn is_supported(self) -> bool {
+        matches!(self, ArchiveFormat::Gzip | ArchiveFormat::Zstd | ArchiveFormat::PlainTar)
+    }
+
+    pub fn is_compressed(self) -> bool {
+        matches!(self, ArchiveFormat::Gzip | ArchiveFormat::Zstd | ArchiveFormat::Xz)
+    }
+}
+
+impl std::fmt::Display for ArchiveFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArchiveFormat::Gzip => write!(f, "gzip"),
+            ArchiveFormat::Zstd => write!(f, "zstd"),
+            ArchiveFormat::Xz => write!(f, "xz"),
+            ArchiveFormat::PlainTar => write!(f, "tar"),
+            ArchiveFormat::Age => write!(f, "age"),
+            ArchiveFormat::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// Wall-clock duration of one phase of a command
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PhaseTiming {
+    pub name: String,
+    pub duration_secs: f64,
+    /// Data processed by the phase, for phases that move data
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<u64>,
+    /// Throughput in MiB per second, for phases that move data
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mb_per_sec: Option<f64>,
+}
+
+/// Resource use of one command, printed in the footer every command ends with and included in
+/// its JSON report
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CommandMetrics {
+    /// Wall-clock time since the command started
+    pub elapsed_secs: f64,
+    /// Most space the command's temporary workspaces took, sampled at phase boundaries
+    pub peak_temp_bytes: u64,
+    /// Bytes the process read and wrote, including through pipes and sockets; None where the
+    /// platform does not count them
+    pub bytes_read: Option<u64>,
+    pub bytes_written: Option<u64>,
+    /// Warnings printed
+    pub warnings: usize,
+}
+
+/// How an export file is compressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExportCompression {
+    #[default]
+    None,
+    Gzip,
+    /// A seekable zstd export ends in a frame index, so readers can jump to any member
+    Zstd { seekable: bool },
+}
+
+impl ExportCompression {
+    pub fn is_compressed(self) -> bool {
+        self != ExportCompression::None
+    }
+
+    /// Extension appended to `.tar` for exports compressed this way
+    pub fn extension(self) -> Option<&'static str> {
+        match self {
+            ExportCompression::None => None,
+            ExportCompression::Gzip => Some("gz"),
+            ExportCompression::Zstd { .. } => Some("zst"),
+        }
+    }
+}
+
+impl std::fmt::Display for ExportCompression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportCompression::None => write!(f, "none"),
+            ExportCompression::Gzip => write!(f, "gzip"),
+            ExportCompression::Zstd { .. } => write!(f, "zstd"),
+        }
+    }
+}
+
+impl std::str::FromStr for ExportCompression {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "none" => Ok(ExportCompression::None),
+            "gzip" => Ok(ExportCompression::Gzip),
+            "zstd" => Ok(ExportCompression::Zstd { seekable: false }),
+            _ => Err(format!("unknown compression '{}' (expected one of: none, gzip, zstd)", value)),
+        }
+    }
+}
+
+/// Major version of the metadata format an export writes (`export --format-version`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExportFormatVersion {
+    /// [`ExportData`], for releases that only read format 1
+    V1,
+    /// [`ExportDataV2`]
+    #[default]
+    V2,
+}
+
+impl ExportFormatVersion {
+    /// Version recorded in metadata.json
+    pub fn format_version(self) -> FormatVersion {
+        match self {
+            ExportFormatVersion::V1 => LATEST_V1_FORMAT_VERSION,
+            ExportFormatVersion::V2 => CURRENT_FORMAT_VERSION,
+        }
+    }
+}
+
+impl std::fmt::Display for ExportFormatVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportFormatVersion::V1 => write!(f, "1"),
+            ExportFormatVersion::V2 => write!(f, "2"),
+        }
+    }
+}
+
+impl std::str::FromStr for ExportFormatVersion {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "1" => Ok(ExportFormatVersion::V1),
+            "2" => Ok(ExportFormatVersion::V2),
+            _ => Err(format!("unknown format version '{}' (expected one of: 1, 2)", value)),
+        }
+    }
+}
+
+/// Export options
+#[derive(Debug, Clone, Default)]
+pub struct ExportOptions {
+    pub compression: ExportCompression,
+    pub full_rootfs: bool,
+    pub auto_extension: bool,
+    pub force: bool,
+    pub dry_run: bool,
+    pub reproducible: bool,
+    pub since: Option<String>,
+    pub annotations: BTreeMap<String, String>,
+    pub redact_patterns: Vec<String>,
+    pub threads: Option<usize>,
+    pub split_size: Option<u64>,
+    pub ssh: Option<String>,
+    pub verify: bool,
+    pub pre_hook: Option<String>,
+    pub post_hook: Option<String>,
+    pub hook_timeout: Option<u64>,
+    pub encrypt_recipients: Vec<String>,
+    pub ignore_transient_errors: bool,
+    pub include_mounts: bool,
+    pub excludes: Vec<String>,
+    pub exclude_presets: Vec<ExcludePreset>,
+    /// Seconds to wait for another operation's lock on the container
+    pub wait: Option<u64>,
+    /// Write a JSON export report to this path
+    pub report: Option<String>,
+    /// Undo the daemon's userns-remap shift so the export holds unshifted ids
+    pub unshift: bool,
+    /// Hash function of the layer, mount and manifest checksums
+    pub checksum_algorithm: ChecksumAlgorithm,
+    /// Metadata format to write
+    pub format_version: ExportFormatVersion,
+}
+
+/// Import options
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    pub backup: bool,
+    pub as_upper: bool,
+    pub force_incremental: bool,
+    pub ssh: Option<String>,
+    pub identity: Option<String>,
+    pub verify_signature: bool,
+    pub pubkey: Option<String>,
+    pub restore_mounts: bool,
+    pub yes: bool,
+    /// Seconds to wait for another operation's lock on the container
+    pub wait: Option<u64>,
+    /// Only validate the export against the target container; never modify it
+    pub verify_only: bool,
+    /// Skip validating the export in the workspace before the target layer is modified
+    pub skip_precheck: bool,
+    /// Skip comparing the layer's size and entry count with the target filesystem's free space
+    pub no_space_check: bool,
+    /// Fail unless the input file, as downloaded or read, has this SHA256
+    pub sha256: Option<Sha256Digest>,
+    /// Extra request headers for http(s):// inputs
+    pub headers: Vec<HttpHeader>,
+    /// Import even if the target container runs a different image than the export was taken from
+    pub allow_image_mismatch: bool,
+    /// Import even if the export was taken on a different architecture
+    pub skip_arch: bool,
+    /// Replace the existing layer or merge the export into it
+    pub mode: ImportMode,
+    /// Glob patterns of layer paths not to write into the target (same syntax as export's --exclude)
+    pub excludes: Vec<String>,
+    /// Only import entries under these layer-relative paths (implies merge mode)
+    pub only: Vec<String>,
+    /// Extract into this directory instead of a container's layer, without using Docker
+    pub target_path: Option<String>,
+    /// Import into a running container, or allow --target-path to be `/` or a non-empty directory
+    pub force: bool,
+    /// Add this to the uid of every imported entry
+    pub uid_offset: Option<i64>,
+    /// Add this to the gid of every imported entry
+    pub gid_offset: Option<i64>,
+    /// Shift ids to the target daemon's userns-remap range, relative to the export's recorded offset
+    pub auto_remap: bool,
+    /// Give every imported entry this owner instead of the one recorded in the archive
+    pub chown: Option<Owner>,
+    /// Write backups as tarballs into this directory instead of next to the upper layer
+    pub backup_dir: Option<String>,
+    /// Backups to keep per container after a successful import; older ones are pruned
+    pub keep_backups: usize,
+    /// Containers imported into at the same time when importing into several
+    pub jobs: usize,
+    /// Stop importing into further containers after the first one fails
+    pub fail_fast: bool,
+    /// Continue an interrupted import from its journal instead of backing up and starting over
+    pub resume: bool,
+    /// Run this command inside the target container after the import; failing it rolls the import back
+    pub post_check: Option<String>,
+    /// Seconds the post-check may run before it counts as failed
+    pub post_check_timeout: u64,
+    /// With --mode merge, only ask for confirmation when more existing entries than this would be
+    /// overwritten or deleted
+    pub max_overwrites: Option<usize>,
+    /// Write a JSON import report to this path
+    pub report: Option<String>,
+    /// Print the import report as JSON on stdout, with human-readable output on stderr
+    pub output: OutputFormat,
+}
+
+/// How extracting a layer archive treats its whiteouts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WhiteoutMode {
+    /// Into a container's overlayfs upper directory: the hidden path is removed and a 0:0 character
+    /// device written in its place (AUFS-style `.wh.` files included), and opaque markers become the
+    /// `trusted.overlay.opaque` xattr, so the image's copies stay hidden
+    Overlay,
+    /// Into a plain directory: the hidden paths are removed and nothing is written for the whiteout
+    Delete,
+}
+
+/// Whiteouts applied while extracting a layer archive
+#[derive(Debug, Clone, Default)]
+pub struct AppliedWhiteouts {
+    /// Whiteouts and opaque markers applied
+    pub count: usize,
+    /// Existing layer-relative paths they removed
+    pub deleted: Vec<String>,
+}
+
+/// Format of a command's result on stdout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// Human-readable output only
+    #[default]
+    Text,
+    /// A JSON report on stdout; human-readable output moves to stderr
+    Json,
+}
+
+impl std::fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputFormat::Text => write!(f, "text"),
+            OutputFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("unknown output format '{}' (expected one of: text, json)", value)),
+        }
+    }
+}
+
+/// How an import combines the export's layer with the target's existing layer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ImportMode {
+    /// Back up and remove the existing layer, then extract the export in its place
+    #[default]
+    Replace,
+    /// Extract the export over the existing layer, applying its whiteouts as deletions
+    Merge,
+}
+
+impl std::fmt::Display for ImportMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportMode::Replace => write!(f, "replace"),
+            ImportMode::Merge => write!(f, "merge"),
+        }
+    }
+}
+
+impl std::str::FromStr for ImportMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "replace" => Ok(ImportMode::Replace),
+            "merge" => Ok(ImportMode::Merge),
+            _ => Err(format!("unknown import mode '{}' (expected one of: replace, merge)", value)),
+        }
+    }
+}
+
+/// Owner forced onto imported entries (import --chown); without a gid the recorded group is kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Owner {
+    pub uid: u32,
+    pub gid: Option<u32>,
+}
+
+impl std::fmt::Display for Owner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.gid {
+            Some(gid) => write!(f, "{}:{}", self.uid, gid),
+            None => write!(f, "{}", self.uid),
+        }
+    }
+}
+
+impl std::str::FromStr for Owner {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let parse = |id: &str| id.parse::<u32>()
+            .map_err(|_| format!("invalid owner '{}' (expected numeric uid[:gid])", value));
+        match value.split_once(':') {
+            Some((uid, gid)) => Ok(Owner { uid: parse(uid)?, gid: Some(parse(gid)?) }),
+            None => Ok(Owner { uid: parse(value)?, gid: None }),
+        }
+    }
+}
+
+/// Extra request header for HTTP(S) inputs, given as `Name: value` (import/check --header)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpHeader {
+    pub name: String,
+    pub value: String,
+}
+
+impl std::str::FromStr for HttpHeader {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.split_once(':') {
+            Some((name, header_value)) if !name.trim().is_empty() && !name.trim().contains(char::is_whitespace) => Ok(HttpHeader {
+                name: name.trim().to_string(),
+                value: header_value.trim().to_string(),
+            }),
+            _ => Err(format!("invalid header '{}' (expected 'Name: value')", value)),
+        }
+    }
+}
+
+/// Expected SHA256 of an input file (import/check --sha256), as 64 lowercase hex digits
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sha256Digest(pub String);
+
+impl std::fmt::Display for Sha256Digest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::str::FromStr for Sha256Digest {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let digest = value.strip_prefix("sha256:").unwrap_or(value).to_lowercase();
+        if digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit()) {
+            Ok(Sha256Digest(digest))
+        } else {
+            Err(format!("invalid SHA256 digest '{}' (expected 64 hex digits)", value))
+        }
+    }
+}
+
+/// Changes a merge import made to the existing layer
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MergeStats {
+    /// Non-directory entries that did not exist before
+    pub added: usize,
+    /// Existing non-directory entries replaced by the export's version
+    pub overwritten: usize,
+    /// Existing layer-relative paths removed by whiteouts in the export
+    pub deleted: Vec<String>,
+    /// Whiteouts and opaque markers applied
+    pub whiteouts: usize,
+    /// Layer-relative paths written from the archive
+    pub imported: Vec<String>,
+    /// Layer-relative paths left out because they matched an exclude pattern
+    pub excluded: Vec<String>,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            backup: true,
+            as_upper: false,
+            force_incremental: false,
+            ssh: None,
+            identity: None,
+            verify_signature: false,
+            pubkey: None,
+            restore_mounts: false,
+            yes: false,
+            wait: None,
+            verify_only: false,
+            skip_precheck: false,
+            no_space_check: false,
+            sha256: None,
+            headers: Vec::new(),
+            allow_image_mismatch: false,
+            skip_arch: false,
+            mode: ImportMode::Replace,
+            excludes: Vec::new(),
+            only: Vec::new(),
+            target_path: None,
+            force: false,
+            uid_offset: None,
+            gid_offset: None,
+            auto_remap: false,
+            chown: None,
+            backup_dir: None,
+            keep_backups: DEFAULT_KEEP_BACKUPS,
+            jobs: 1,
+            fail_fast: false,
+            resume: false,
+            post_check: None,
+            post_check_timeout: DEFAULT_POST_CHECK_TIMEOUT,
+            max_overwrites: None,
+            report: None,
+            output: OutputFormat::Text,
+        }
+    }
+}
+
+/// Clone options
+#[derive(Debug, Clone)]
+pub struct CloneOptions {
+    /// Back up the target's existing layer before it is replaced
+    pub backup: bool,
+    /// Write the backup as a tarball into this directory instead of next to the upper layer
+    pub backup_dir: Option<String>,
+    /// Backups to keep for the target after a successful clone; older ones are pruned
+    pub keep_backups: usize,
+    /// Clone into a running target container
+    pub force: bool,
+    /// Clone even if the two containers run different images
+    pub allow_image_mismatch: bool,
+    /// Clone even if the daemons' architectures differ (with --to-host)
+    pub skip_arch: bool,
+    /// Skip checking free space and inodes on the target filesystem
+    pub no_space_check: bool,
+    /// Seconds to wait for another operation's lock on either container
+    pub wait: Option<u64>,
+    /// Import into the target container on this host over SSH instead of locally
+    pub to_host: Option<String>,
+}
+
+impl Default for CloneOptions {
+    fn default() -> Self {
+        Self {
+            backup: true,
+            backup_dir: None,
+            keep_backups: DEFAULT_KEEP_BACKUPS,
+            force: false,
+            allow_image_mismatch: false,
+            skip_arch: false,
+            no_space_check: false,
+            wait: None,
+            to_host: None,
+        }
+    }
+}
+
+/// Restore options
+#[derive(Debug, Clone, Default)]
+pub struct RestoreOptions {
+    /// Backup to restore, by file name or timestamp; the most recent when None
+    pub backup: Option<String>,
+    /// Only list the container's backups
+    pub list: bool,
+    /// Directory holding tarball backups (import --backup-dir); the safety backup goes there too
+    pub backup_dir: Option<String>,
+    /// Wait up to this many seconds for another operation on the container to finish
+    pub wait: Option<u64>,
+}
+
+/// Verify-backup options
+#[derive(Debug, Clone, Default)]
+pub struct VerifyBackupOptions {
+    /// Backup to verify, by file name or timestamp; all of the container's backups when None
+    pub backup: Option<String>,
+    /// Directory holding tarball backups (import --backup-dir)
+    pub backup_dir: Option<String>,
+}
+
+/// Diff options
+#[derive(Debug, Clone, Default)]
+pub struct DiffOptions {
+    /// Print the diff report as JSON on stdout, with human-readable output on stderr
+    pub output: OutputFormat,
+    /// Print only the paths that differ, one per line
+    pub name_only: bool,
 }
 
 /// Check options
@@ -280,7 +2118,39 @@ pub struct CheckOptions {
     pub skip_image: bool,
     pub skip_storage: bool,
     pub skip_os: bool,
+    /// Skip the Docker and kernel version comparisons
+    pub skip_version: bool,
+    /// Skip the cgroup driver comparison
+    pub skip_cgroup: bool,
     pub skip_arch: bool,
+    pub deep: bool,
+    pub required_annotations: Vec<String>,
+    pub identity: Option<String>,
+    pub verify_signature: bool,
+    pub pubkey: Option<String>,
+    /// Fail unless the input file, as downloaded or read, has this SHA256
+    pub sha256: Option<Sha256Digest>,
+    /// Extra request headers for http(s):// inputs
+    pub headers: Vec<HttpHeader>,
+    /// Directory the check's temporary workspace is created in, instead of the system default
+    pub tmpdir: Option<String>,
+    /// Container the export is checked against, in addition to the daemon's environment
+    pub container: Option<String>,
+    /// Number of largest layer entries to list
+    pub top: usize,
+    /// Fail the check on any warning
+    pub strict: bool,
+    /// Pattern selecting the exports in directory inputs (check several files)
+    pub glob: Option<String>,
+    /// Stop checking further files after the first failure (check several files)
+    pub fail_fast: bool,
+    /// Identifiers of the checks whose warnings fail the check
+    pub warn_as_error: Vec<String>,
+    /// Report suspicious layer entries: links escaping the layer, device nodes, setuid/setgid
+    /// executables and world-writable files
+    pub scan: bool,
+    /// Print the check report as JSON on stdout, with human-readable output on stderr
+    pub output: OutputFormat,
 }
 
 impl Default for CheckOptions {
@@ -289,7 +2159,25 @@ impl Default for CheckOptions {
             skip_image: false,
             skip_storage: false,
             skip_os: false,
+            skip_version: false,
+            skip_cgroup: false,
             skip_arch: false,
+            deep: false,
+            required_annotations: Vec::new(),
+            identity: None,
+            verify_signature: false,
+            pubkey: None,
+            sha256: None,
+            headers: Vec::new(),
+            tmpdir: None,
+            container: None,
+            top: DEFAULT_TOP_ENTRIES,
+            scan: false,
+            strict: false,
+            glob: None,
+            fail_fast: false,
+            warn_as_error: Vec::new(),
+            output: OutputFormat::Text,
         }
     }
 }
@@ -1,14 +1,36 @@
 use anyhow::{Context, Result};
-use flate2::read::GzDecoder;
+use chrono::DateTime;
+use flate2::read::MultiGzDecoder;
 use flate2::write::GzEncoder;
 use flate2::Compression;
+use gzp::deflate::Gzip;
+use gzp::par::compress::ParCompressBuilder;
+use gzp::ZWriter;
+use indicatif::ProgressBar;
+use rayon::prelude::*;
 use sha2::{Digest, Sha256};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::fs::File;
-use std::io::{BufReader, BufWriter, Read, Write};
-use std::path::Path;
-use tar::{Archive, Builder};
+use std::io::{BufRead, BufReader, BufWriter, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use tar::{Archive, Builder, Header};
 use walkdir::WalkDir;
 
+use crate::attributes::{append_pax_entry, archived_owner, entry_owner, read_xattrs, set_header_owner, AttributeRestorer, PreservedAttributes};
+use crate::cancel::{CancellableReader, CancellationToken};
+use crate::excludes::{ExcludeSet, PathSelection};
+use crate::journal::ImportJournal;
+use crate::seekable::{SeekTable, SeekableReader, SeekableWriter, ZSTD_LEVEL};
+use crate::tuning::IoTuning;
+use crate::types::{
+    AppliedWhiteouts, ArchiveFormat, Checksum, ChecksumAlgorithm, ChecksumFormat, ChecksumScheme, ChunkIndex, Collision, CollisionReport, IdOffset, ChunkInfo, EntryType, ExportCompression, ExtractOptions, ExtractSummary, Manifest, ManifestEntry, MergeStats, Sha256Digest,
+    LayerArchiveOptions, LayerStats, OverwritePolicy, SizedEntry, TransientEntry, TransientIssue, VerifiedAttributes, WhiteoutMode, CURRENT_CHECKSUM_SCHEME
+};
+
 /// Compress data using gzip
 pub fn compress_data(input: &[u8]) -> Result<Vec<u8>> {
     let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
@@ -20,7 +42,7 @@ pub fn compress_data(input: &[u8]) -> Result<Vec<u8>> {
 
 /// Decompress gzip data
 pub fn decompress_data(input: &[u8]) -> Result<Vec<u8>> {
-    let mut decoder = GzDecoder::new(input);
+    let mut decoder = MultiGzDecoder::new(input);
     let mut output = Vec::new();
     decoder.read_to_end(&mut output)
         .context("Failed to decompress gzip data")?;
@@ -34,11 +56,11 @@ pub fn compress_file<P: AsRef<Path>>(input_path: P, output_path: P) -> Result<()
     let output_file = File::create(&output_path)
         .with_context(|| format!("Failed to create output file: {:?}", output_path.as_ref()))?;
 
-    let mut reader = BufReader::new(input_file);
-    let writer = BufWriter::new(output_file);
-    let mut encoder = GzEncoder::new(writer, Compression::default());
+    let tuning = IoTuning::current();
+    let mut reader = input_file;
+    let mut encoder = GzEncoder::new(tuning.writer(output_file), Compression::default());
 
-    std::io::copy(&mut reader, &mut encoder)
+    tuning.copy(&mut reader, &mut encoder)
         .context("Failed to compress file")?;
     encoder.finish()
         .context("Failed to finish file compression")?;
@@ -46,230 +68,3894 @@ pub fn compress_file<P: AsRef<Path>>(input_path: P, output_path: P) -> Result<()
     Ok(())
 }
 
-/// Decompress a gzip file
-pub fn decompress_file<P: AsRef<Path>>(input_path: P, output_path: P) -> Result<()> {
+/// Compress a file using gzip, spreading the deflate work over `threads` worker threads
+pub fn compress_file_parallel<P: AsRef<Path>>(input_path: P, output_path: P, threads: usize, cancel: &CancellationToken) -> Result<()> {
+    let input_file = File::open(&input_path)
+        .with_context(|| format!("Failed to open input file: {:?}", input_path.as_ref()))?;
+    let output_file = File::create(&output_path)
+        .with_context(|| format!("Failed to create output file: {:?}", output_path.as_ref()))?;
+
+    let tuning = IoTuning::current();
+    let mut writer = tuning.writer(output_file);
+    parallel_gzip_copy(CancellableReader::new(tuning.reader(input_file), cancel), &mut writer, threads)
+        .context("Failed to compress file")?;
+    writer.flush()
+        .context("Failed to finish file compression")?;
+
+    Ok(())
+}
+
+/// Gzip everything from a reader into a writer using a pigz-style block pipeline.
+/// The output is a single standard gzip stream that `decompress_file` reads unchanged.
+fn parallel_gzip_copy<R: Read, W: Write + Send>(mut reader: R, writer: W, threads: usize) -> Result<()> {
+    let builder = ParCompressBuilder::<Gzip>::new()
+        .num_threads(threads.max(1))
+        .map_err(|e| anyhow::anyhow!("Invalid compression thread count: {}", e))?;
+
+    std::thread::scope(|scope| {
+        let mut encoder = builder.from_borrowed_writer(writer, scope);
+        IoTuning::current().copy(&mut reader, &mut encoder)
+            .context("Failed to write data to gzip encoder")?;
+        encoder.finish()
+            .map_err(|e| anyhow::anyhow!("Failed to finish gzip compression: {}", e))?;
+        Ok(())
+    })
+}
+
+/// zstd-compress the `size` bytes of a reader into a writer with `threads` workers. A seekable
+/// stream is cut into independent frames indexed by a seek table at its end, which zstd
+/// decoders without seekable support skip; otherwise a single frame records `size` in its header.
+fn zstd_copy<R: Read, W: Write>(mut reader: R, writer: W, seekable: bool, threads: usize, size: u64) -> Result<()> {
+    if seekable {
+        let mut encoder = SeekableWriter::new(writer, threads);
+        IoTuning::current().copy(&mut reader, &mut encoder)
+            .context("Failed to write data to zstd encoder")?;
+        encoder.finish()
+            .context("Failed to finish zstd compression")?;
+        return Ok(());
+    }
+
+    let mut encoder = zstd::Encoder::new(writer, ZSTD_LEVEL)
+        .context("Failed to start zstd compression")?;
+    encoder.multithread(threads.max(1) as u32)
+        .and_then(|_| encoder.include_checksum(true))
+        .and_then(|_| encoder.set_pledged_src_size(Some(size)))
+        .context("Failed to start zstd compression")?;
+    IoTuning::current().copy(&mut reader, &mut encoder)
+        .context("Failed to write data to zstd encoder")?;
+    encoder.finish()
+        .context("Failed to finish zstd compression")?;
+    Ok(())
+}
+
+/// `reader` over a file of `format`, with gzip and zstd decompressed on the fly
+pub fn decompressing_reader<'a, R: BufRead + 'a>(format: ArchiveFormat, reader: R) -> Result<Box<dyn Read + 'a>> {
+    Ok(match format {
+        ArchiveFormat::Gzip => Box::new(MultiGzDecoder::new(reader)),
+        ArchiveFormat::Zstd => Box::new(zstd::Decoder::with_buffer(reader)
+            .context("Failed to start zstd decompression")?),
+        _ => Box::new(reader),
+    })
+}
+
+/// Seek table of the seekable zstd file at `file_path`; None for any other file
+pub fn zstd_seek_table<P: AsRef<Path>>(file_path: P) -> Result<Option<SeekTable>> {
+    if detect_format(&file_path)? != ArchiveFormat::Zstd {
+        return Ok(None);
+    }
+    let mut file = File::open(&file_path)
+        .with_context(|| format!("Failed to open file: {:?}", file_path.as_ref()))?;
+    SeekTable::read(&mut file)
+        .with_context(|| format!("Invalid zstd seek table in {:?}", file_path.as_ref()))
+}
+
+/// Random-access reader over the content of the seekable zstd export at `export_path`; None if
+/// it is not one
+pub fn open_seekable_export(export_path: &Path) -> Result<Option<SeekableReader<BufReader<File>>>> {
+    if detect_format(export_path)? != ArchiveFormat::Zstd {
+        return Ok(None);
+    }
+    let file = File::open(export_path)
+        .with_context(|| format!("Failed to open export file: {:?}", export_path))?;
+    SeekableReader::new(BufReader::new(file))
+        .with_context(|| format!("Invalid zstd seek table in {:?}", export_path))
+}
+
+/// Decompress a gzip or zstd file
+pub fn decompress_file<P: AsRef<Path>>(input_path: P, output_path: P, cancel: &CancellationToken) -> Result<()> {
+    let format = detect_format(&input_path)?;
     let input_file = File::open(&input_path)
         .with_context(|| format!("Failed to open compressed file: {:?}", input_path.as_ref()))?;
     let output_file = File::create(&output_path)
         .with_context(|| format!("Failed to create output file: {:?}", output_path.as_ref()))?;
 
-    let reader = BufReader::new(input_file);
-    let mut writer = BufWriter::new(output_file);
-    let mut decoder = GzDecoder::new(reader);
+    let tuning = IoTuning::current();
+    let mut writer = tuning.writer(output_file);
+    let mut decoder = CancellableReader::new(decompressing_reader(format, tuning.reader(input_file))?, cancel);
 
-    std::io::copy(&mut decoder, &mut writer)
+    tuning.copy(&mut decoder, &mut writer)
+        .context("Failed to decompress file")?;
+    writer.flush()
         .context("Failed to decompress file")?;
 
     Ok(())
 }
 
+/// Writer adapter that counts the bytes written through it
+pub struct CountingWriter<W> {
+    inner: W,
+    bytes_written: u64,
+}
+
+impl<W: Write> CountingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            bytes_written: 0,
+        }
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.bytes_written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Stream a file into a writer, compressing it on the way as `compression` says with `threads`
+/// workers. Returns the number of bytes written to the writer.
+pub fn stream_file_to_writer<P: AsRef<Path>, W: Write + Send>(
+    input_path: P,
+    writer: W,
+    compression: ExportCompression,
+    threads: usize,
+    cancel: &CancellationToken,
+) -> Result<u64> {
+    let input_file = File::open(&input_path)
+        .with_context(|| format!("Failed to open input file: {:?}", input_path.as_ref()))?;
+    let tuning = IoTuning::current();
+    let mut reader = CancellableReader::new(tuning.reader(input_file), cancel);
+    let mut counter = CountingWriter::new(tuning.writer(writer));
+
+    match compression {
+        ExportCompression::None => {
+            tuning.copy(&mut reader, &mut counter)
+                .context("Failed to write output stream")?;
+        }
+        ExportCompression::Gzip => parallel_gzip_copy(reader, &mut counter, threads)
+            .context("Failed to compress output stream")?,
+        ExportCompression::Zstd { seekable } => {
+            let size = get_file_size(&input_path)?;
+            zstd_copy(reader, &mut counter, seekable, threads, size)
+                .context("Failed to compress output stream")?
+        }
+    }
+
+    counter.flush()
+        .context("Failed to flush output stream")?;
+    Ok(counter.bytes_written())
+}
+
 /// Create a tar archive from a directory
-pub fn create_tar_archive<P: AsRef<Path>>(source_dir: P, output_path: P) -> Result<String> {
+pub fn create_tar_archive<P: AsRef<Path>>(source_dir: P, output_path: P) -> Result<Checksum> {
+    let (checksum, _manifest) = create_tar_archive_with_manifest(source_dir, output_path, &CancellationToken::new())?;
+    Ok(checksum)
+}
+
+/// Create a tar archive from a directory, building a per-file manifest while walking it
+pub fn create_tar_archive_with_manifest<P: AsRef<Path>>(source_dir: P, output_path: P, cancel: &CancellationToken) -> Result<(Checksum, Manifest)> {
+    let (checksum, manifest, _) = create_tar_archive_filtered(source_dir, output_path, None, None, LayerArchiveOptions::default(), cancel)?;
+    Ok((checksum, manifest))
+}
+
+/// Decides from a path relative to the layer root and its manifest entry whether an entry is archived
+pub type IncludeFilter<'a> = dyn Fn(&str, &ManifestEntry) -> bool + 'a;
+
+/// Create a tar archive containing only the entries accepted by `include`, or all of them.
+/// The checksum and manifest always describe the complete directory, so a filtered
+/// archive applied on top of its base reproduces the recorded layer checksum.
+/// `include` decides on file digests, so with it the files are hashed in a parallel pass
+/// first; without it each file is read once, for the archive, checksum and manifest together.
+/// Paths matched by `exclude` are pruned from the walk and left out of all three.
+///
+/// The directory may belong to a running container: paths deleted after the walk are left
+/// out, and files modified while being read are re-read once from a snapshot, so the archive,
+/// checksum and manifest always agree. Affected paths are returned in walk order. Other read
+/// errors are fatal unless `options.ignore_transient_errors` is set, in which case the path is skipped.
+pub fn create_tar_archive_filtered<P: AsRef<Path>>(
+    source_dir: P,
+    output_path: P,
+    include: Option<&IncludeFilter<'_>>,
+    exclude: Option<&ExcludeSet>,
+    options: LayerArchiveOptions,
+    cancel: &CancellationToken,
+) -> Result<(Checksum, Manifest, Vec<TransientEntry>)> {
     let output_file = File::create(&output_path)
         .with_context(|| format!("Failed to create tar file: {:?}", output_path.as_ref()))?;
-    let mut builder = Builder::new(output_file);
+    let mut writer = IoTuning::current().writer(output_file);
+    let archived = write_tar_archive_filtered(source_dir.as_ref(), &mut writer, include, exclude, options, cancel)?;
+    writer.flush()
+        .with_context(|| format!("Failed to write tar file: {:?}", output_path.as_ref()))?;
+    Ok(archived)
+}
 
-    let source_path = source_dir.as_ref();
-    if !source_path.exists() {
-        return Err(anyhow::anyhow!("Source directory does not exist: {:?}", source_path));
-    }
+/// [`create_tar_archive_filtered`] archiving the entries of an earlier [`LayerScanner`] walk of
+/// `source_dir`, which decided what is excluded
+pub fn create_layer_archive<P: AsRef<Path>>(
+    source_dir: P,
+    stats: &LayerStats,
+    output_path: P,
+    include: Option<&IncludeFilter<'_>>,
+    options: LayerArchiveOptions,
+    cancel: &CancellationToken,
+) -> Result<(Checksum, Manifest, Vec<TransientEntry>)> {
+    let output_file = File::create(&output_path)
+        .with_context(|| format!("Failed to create tar file: {:?}", output_path.as_ref()))?;
+    let mut writer = IoTuning::current().writer(output_file);
+    let archived = write_layer_archive(source_dir.as_ref(), stats, &mut writer, include, options, cancel)?;
+    writer.flush()
+        .with_context(|| format!("Failed to write tar file: {:?}", output_path.as_ref()))?;
+    Ok(archived)
+}
 
-    // Collect and sort entries for consistent checksums
-    let mut entries: Vec<_> = WalkDir::new(source_path)
-        .into_iter()
-        .collect::<Result<Vec<_>, _>>()
-        .context("Failed to walk directory")?;
+/// [`create_tar_archive_filtered`] writing the archive into any [`ArchiveSink`]
+pub fn write_tar_archive_filtered<W: ArchiveSink>(
+    source_path: &Path,
+    sink: W,
+    include: Option<&IncludeFilter<'_>>,
+    exclude: Option<&ExcludeSet>,
+    options: LayerArchiveOptions,
+    cancel: &CancellationToken,
+) -> Result<(Checksum, Manifest, Vec<TransientEntry>)> {
+    let stats = LayerScanner::new()
+        .exclude(exclude)
+        .ignore_transient_errors(options.ignore_transient_errors)
+        .scan(source_path, cancel)?;
+    write_layer_archive(source_path, &stats, sink, include, options, cancel)
+}
 
-    // Sort entries for consistent checksums (same as calculate_directory_checksum)
-    entries.sort_by(|a, b| a.path().cmp(b.path()));
+/// [`create_layer_archive`] writing the archive into any [`ArchiveSink`]
+pub fn write_layer_archive<W: ArchiveSink>(
+    source_path: &Path,
+    stats: &LayerStats,
+    sink: W,
+    include: Option<&IncludeFilter<'_>>,
+    options: LayerArchiveOptions,
+    cancel: &CancellationToken,
+) -> Result<(Checksum, Manifest, Vec<TransientEntry>)> {
+    let LayerArchiveOptions { ignore_transient_errors, id_offset, checksum_algorithm } = options;
+    let mut builder = Builder::new(sink);
 
-    // Calculate checksum while creating archive
-    let mut hasher = Sha256::new();
+    let entries = &stats.entries;
+    let mut transient_entries = stats.transient_entries.clone();
+    let filtered = include.is_some();
 
-    for entry in entries {
+    // With a filter, hash file contents in parallel; these digests decide which entries an
+    // incremental export includes and reveal files modified between this pass and archiving.
+    // Failures are handled when the file is archived.
+    let include = include.unwrap_or(&|_, _| true);
+    let file_digests: Vec<Option<(u64, Checksum)>> = entries
+        .par_iter()
+        .map(|entry| {
+            cancel.check()?;
+            if filtered && entry.file_type().is_file() {
+                Ok(hash_file_contents(entry.path(), AnyDigest::new(checksum_algorithm)).ok())
+            } else {
+                Ok(None)
+            }
+        })
+        .collect::<Result<_>>()?;
+
+    // The manifest records every file's digest; the checksum is computed from the same entries,
+    // with their exact path bytes, as they are archived in path order
+    let mut manifest = Manifest::default();
+    let mut checksum = EntryRecordChecksum::new(CURRENT_CHECKSUM_SCHEME, checksum_algorithm);
+
+    for (entry, file_digest) in entries.iter().zip(file_digests) {
+        cancel.check()?;
         let path = entry.path();
+        if path == source_path {
+            continue;
+        }
 
-        if path.is_file() {
-            let relative_path = path.strip_prefix(source_path)
-                .context("Failed to create relative path")?;
+        let relative_path = path.strip_prefix(source_path)
+            .context("Failed to create relative path")?;
+        let relative_name = relative_path.to_string_lossy().into_owned();
 
-            // Add file to archive
-            builder.append_path_with_name(path, relative_path)
-                .with_context(|| format!("Failed to add file to archive: {:?}", path))?;
+        // Roll the archive back here if this entry has to be skipped or re-read
+        let checkpoint = builder.get_mut().position()
+            .context("Failed to read tar archive position")?;
 
-            // Update checksum (same method as calculate_directory_checksum)
-            hasher.update(relative_path.to_string_lossy().as_bytes());
+        // Symlinks are archived as themselves; following them would read outside the layer
+        let mut link_target = None;
+        let captured = std::fs::symlink_metadata(path).and_then(|metadata| {
+            if metadata.is_file() {
+                append_live_file(&mut builder, path, relative_path, file_digest.as_ref(), include, id_offset, checksum_algorithm)
+                    .map(Some)
+            } else if metadata.is_dir() {
+                let (uid, gid) = archived_owner(&metadata, id_offset)?;
+                let manifest_entry = ManifestEntry {
+                    entry_type: EntryType::Directory,
+                    size: 0,
+                    mode: metadata.permissions().mode() & 0o7777,
+                    digest: None,
+                    uid: Some(uid),
+                    gid: Some(gid),
+                    target: None,
+                };
 
-            let mut file = File::open(path)
-                .with_context(|| format!("Failed to open file for checksum: {:?}", path))?;
-            let mut buffer = [0; 8192];
+                // Add directory to archive
+                if include(&relative_name, &manifest_entry) {
+                    let mut header = Header::new_ustar();
+                    header.set_metadata(&metadata);
+                    set_header_owner(&mut header, &metadata, id_offset)?;
+                    append_pax_entry(
+                        &mut builder, &mut header, relative_path, None, metadata.mtime_nsec() as u32, &read_xattrs(path), std::io::empty()
+                    )?;
+                }
+                Ok(Some((manifest_entry, false)))
+            } else if metadata.file_type().is_symlink() {
+                let target = std::fs::read_link(path)?;
+                let (uid, gid) = archived_owner(&metadata, id_offset)?;
+                let manifest_entry = ManifestEntry {
+                    entry_type: EntryType::Symlink,
+                    size: 0,
+                    mode: metadata.permissions().mode() & 0o7777,
+                    digest: None,
+                    uid: Some(uid),
+                    gid: Some(gid),
+                    target: Some(target.to_string_lossy().into_owned()),
+                };
 
-            loop {
-                let bytes_read = file.read(&mut buffer)
-                    .with_context(|| format!("Failed to read file: {:?}", path))?;
-                if bytes_read == 0 {
-                    break;
+                if include(&relative_name, &manifest_entry) {
+                    let mut header = Header::new_ustar();
+                    header.set_metadata(&metadata);
+                    header.set_entry_type(tar::EntryType::Symlink);
+                    header.set_size(0);
+                    set_header_owner(&mut header, &metadata, id_offset)?;
+                    append_pax_entry(
+                        &mut builder, &mut header, relative_path, Some(&target), metadata.mtime_nsec() as u32, &[], std::io::empty()
+                    )?;
                 }
-                hasher.update(&buffer[..bytes_read]);
+                link_target = Some(target);
+                Ok(Some((manifest_entry, false)))
+            } else if metadata.file_type().is_char_device() && metadata.rdev() == 0 {
+                // An overlay whiteout, archived as itself so the deletion survives import. Import
+                // applies it rather than writing it, so it stays out of the manifest and checksum.
+                let mut header = Header::new_ustar();
+                header.set_metadata(&metadata);
+                header.set_entry_type(tar::EntryType::Char);
+                header.set_size(0);
+                set_header_owner(&mut header, &metadata, id_offset)?;
+                append_pax_entry(
+                    &mut builder, &mut header, relative_path, None, metadata.mtime_nsec() as u32, &[], std::io::empty()
+                )?;
+                Ok(None)
+            } else {
+                Ok(None)
             }
-        } else if path.is_dir() && path != source_path {
-            let relative_path = path.strip_prefix(source_path)
-                .context("Failed to create relative path")?;
-
-            // Add directory to archive
-            builder.append_dir(relative_path, path)
-                .with_context(|| format!("Failed to add directory to archive: {:?}", path))?;
+        });
 
-            // Update checksum (same method as calculate_directory_checksum)
-            hasher.update(relative_path.to_string_lossy().as_bytes());
+        match captured {
+            Ok(Some((manifest_entry, changed))) => {
+                if changed {
+                    transient_entries.push(TransientEntry { path: relative_name.clone(), issue: TransientIssue::Changed });
+                }
+                // With the exact target bytes, which the manifest holds lossily
+                match &link_target {
+                    Some(target) => checksum.add_symlink(relative_path, &EntryAttributes::from(&manifest_entry), target.as_os_str().as_bytes()),
+                    None => checksum.add_manifest_entry(relative_path, &manifest_entry),
+                }
+                manifest.entries.insert(relative_name, manifest_entry);
+            }
+            Ok(None) => {}
+            Err(error) => {
+                let Some(issue) = transient_issue(&error, ignore_transient_errors) else {
+                    return Err(error).with_context(|| format!("Failed to add {:?} to archive", path));
+                };
+                builder.get_mut().roll_back_to(checkpoint)
+                    .context("Failed to roll back tar archive")?;
+                transient_entries.push(TransientEntry { path: relative_name, issue });
+            }
         }
     }
 
     builder.finish()
         .context("Failed to finish tar archive")?;
 
-    let checksum = format!("{:x}", hasher.finalize());
-    Ok(checksum)
+    Ok((checksum.finish(), manifest, transient_entries))
 }
 
-/// Extract a tar archive to a directory
-pub fn extract_tar_archive<P: AsRef<Path>>(archive_path: P, output_dir: P) -> Result<()> {
-    let archive_file = File::open(&archive_path)
-        .with_context(|| format!("Failed to open tar file: {:?}", archive_path.as_ref()))?;
-    let mut archive = Archive::new(archive_file);
+/// Archive one regular file of a possibly running container, reading it once for the archive
+/// and the manifest digest. A file modified while being read, or since the
+/// parallel hashing pass, is rolled back and re-read once through a snapshot so all three agree.
+/// Returns the manifest entry and whether the file changed.
+fn append_live_file<W: ArchiveSink>(
+    builder: &mut Builder<W>,
+    path: &Path,
+    relative_path: &Path,
+    expected: Option<&(u64, Checksum)>,
+    include: &dyn Fn(&str, &ManifestEntry) -> bool,
+    id_offset: IdOffset,
+    algorithm: ChecksumAlgorithm,
+) -> std::io::Result<(ManifestEntry, bool)> {
+    let relative_name = relative_path.to_string_lossy();
+    let checkpoint = builder.get_mut().position()?;
 
-    archive.unpack(&output_dir)
-        .with_context(|| format!("Failed to extract tar archive to: {:?}", output_dir.as_ref()))?;
+    let mut file = File::open(path)?;
+    let metadata = file.metadata()?;
+    let mode = metadata.permissions().mode() & 0o7777;
+    let (uid, gid) = archived_owner(&metadata, id_offset)?;
+    let xattrs = read_xattrs(path);
+    let entry_for = |size: u64, digest: Option<Checksum>| ManifestEntry {
+        entry_type: EntryType::File,
+        size,
+        mode,
+        digest,
+        uid: Some(uid),
+        gid: Some(gid),
+        target: None,
+    };
 
-    Ok(())
+    // Decide inclusion from the parallel pass; the decision is revisited if the file changed
+    let provisional = entry_for(
+        expected.map_or(metadata.len(), |(size, _)| *size),
+        expected.map(|(_, digest)| digest.clone()),
+    );
+    let included = include(&relative_name, &provisional);
+
+    let (size, digest, changed) = stream_file_entry(
+        builder, relative_path, &mut file, &metadata, included.then_some(xattrs.as_slice()), id_offset, algorithm
+    )?;
+    let entry = entry_for(size, Some(digest));
+    // Without a digest from the parallel pass, only a different inclusion decision needs a re-read
+    let consistent = match expected {
+        Some(_) => entry == provisional,
+        None => include(&relative_name, &entry) == included,
+    };
+    if !changed && consistent {
+        return Ok((entry, false));
+    }
+
+    // Re-read once: snapshot the current contents so the archived bytes are self-consistent
+    builder.get_mut().roll_back_to(checkpoint)?;
+
+    let mut snapshot = tempfile::tempfile()?;
+    let mut reader = HashingReader::with_digest(File::open(path)?, AnyDigest::new(algorithm));
+    IoTuning::current().copy(&mut reader, &mut snapshot)?;
+    let (digest, size) = reader.finish();
+    snapshot.seek(SeekFrom::Start(0))?;
+
+    let entry = entry_for(size, Some(digest));
+    let included = include(&relative_name, &entry);
+    let mut header = Header::new_ustar();
+    header.set_metadata(&metadata);
+    set_header_owner(&mut header, &metadata, id_offset)?;
+    header.set_size(size);
+
+    if included {
+        append_pax_entry(builder, &mut header, relative_path, None, metadata.mtime_nsec() as u32, &xattrs, &mut snapshot)?;
+    }
+
+    Ok((entry, true))
 }
 
-/// Calculate SHA256 checksum of a file
-pub fn calculate_file_checksum<P: AsRef<Path>>(file_path: P) -> Result<String> {
-    let mut file = File::open(&file_path)
-        .with_context(|| format!("Failed to open file for checksum: {:?}", file_path.as_ref()))?;
-    
-    let mut hasher = Sha256::new();
-    let mut buffer = [0; 8192];
-    
-    loop {
-        let bytes_read = file.read(&mut buffer)
-            .context("Failed to read file for checksum")?;
-        if bytes_read == 0 {
-            break;
+/// Stream `size` bytes of an open file into the archive with its xattrs, owned by its ids shifted
+/// by `id_offset` (or only through the hasher when not included, i.e. `xattrs` is None). Returns the size, content digest
+/// in `algorithm`, and whether the file changed while being read.
+fn stream_file_entry<W: ArchiveSink>(
+    builder: &mut Builder<W>,
+    relative_path: &Path,
+    file: &mut File,
+    metadata: &std::fs::Metadata,
+    xattrs: Option<&[(String, Vec<u8>)]>,
+    id_offset: IdOffset,
+    algorithm: ChecksumAlgorithm,
+) -> std::io::Result<(u64, Checksum, bool)> {
+    let size = metadata.len();
+    let tuning = IoTuning::current();
+    // Pad a file that shrank mid-read so the entry still matches its header; it is re-read anyway.
+    // The buffer reads no further than `size`, so growth is still seen below.
+    let padded = tuning.reader_for((&mut *file).take(size), size).chain(std::io::repeat(0)).take(size);
+    let mut reader = HashingReader::with_digest(padded, AnyDigest::new(algorithm));
+
+    if let Some(xattrs) = xattrs {
+        let mut header = Header::new_ustar();
+        header.set_metadata(metadata);
+        set_header_owner(&mut header, metadata, id_offset)?;
+        append_pax_entry(builder, &mut header, relative_path, None, metadata.mtime_nsec() as u32, xattrs, &mut reader)?;
+    } else {
+        tuning.copy(&mut reader, &mut std::io::sink())?;
+    }
+    let (digest, _) = reader.finish();
+
+    let after = file.metadata()?;
+    let grew = file.read(&mut [0u8; 1])? > 0;
+    let changed = grew
+        || after.len() != size
+        || after.modified().ok() != metadata.modified().ok();
+    Ok((size, digest, changed))
+}
+
+/// Destination of a layer archive. An entry of a live directory may have to be archived again,
+/// so everything written since a position taken before the entry can be dropped.
+pub trait ArchiveSink: Write {
+    /// Position of the next byte written
+    fn position(&mut self) -> std::io::Result<u64>;
+    /// Drop everything written after `position`
+    fn roll_back_to(&mut self, position: u64) -> std::io::Result<()>;
+}
+
+impl ArchiveSink for File {
+    fn position(&mut self) -> std::io::Result<u64> {
+        self.stream_position()
+    }
+
+    fn roll_back_to(&mut self, position: u64) -> std::io::Result<()> {
+        self.set_len(position)?;
+        self.seek(SeekFrom::Start(position)).map(|_| ())
+    }
+}
+
+impl<S: ArchiveSink> ArchiveSink for BufWriter<S> {
+    fn position(&mut self) -> std::io::Result<u64> {
+        let buffered = self.buffer().len() as u64;
+        Ok(self.get_mut().position()? + buffered)
+    }
+
+    fn roll_back_to(&mut self, position: u64) -> std::io::Result<()> {
+        self.flush()?;
+        self.get_mut().roll_back_to(position)
+    }
+}
+
+impl<S: ArchiveSink + ?Sized> ArchiveSink for &mut S {
+    fn position(&mut self) -> std::io::Result<u64> {
+        (**self).position()
+    }
+
+    fn roll_back_to(&mut self, position: u64) -> std::io::Result<()> {
+        (**self).roll_back_to(position)
+    }
+}
+
+/// Bytes of the current entry held in memory before spilling to a temporary file
+const HOLDBACK_MEMORY_LIMIT: usize = 8 * 1024 * 1024;
+
+/// [`ArchiveSink`] for a stream that cannot seek, such as a pipe. The entry being written is held
+/// back until the next position is taken, so it can still be dropped; everything before it has
+/// already been passed on.
+pub struct HoldbackWriter<W: Write> {
+    inner: W,
+    held: tempfile::SpooledTempFile,
+    sent: u64,
+}
+
+impl<W: Write> HoldbackWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            held: tempfile::SpooledTempFile::new(HOLDBACK_MEMORY_LIMIT),
+            sent: 0,
         }
-        hasher.update(&buffer[..bytes_read]);
     }
-    
-    Ok(format!("{:x}", hasher.finalize()))
+
+    /// Pass on the held-back bytes and return the inner writer
+    pub fn finish(mut self) -> std::io::Result<W> {
+        self.release()?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn release(&mut self) -> std::io::Result<()> {
+        self.held.seek(SeekFrom::Start(0))?;
+        self.sent += std::io::copy(&mut self.held, &mut self.inner)?;
+        self.held.set_len(0)?;
+        self.held.seek(SeekFrom::Start(0)).map(|_| ())
+    }
 }
 
-/// Calculate SHA256 checksum of a directory (recursive)
-pub fn calculate_directory_checksum<P: AsRef<Path>>(dir_path: P) -> Result<String> {
-    let mut hasher = Sha256::new();
-    let mut entries: Vec<_> = WalkDir::new(&dir_path)
-        .into_iter()
-        .collect::<Result<Vec<_>, _>>()
-        .context("Failed to walk directory")?;
-    
-    // Sort entries for consistent checksums
-    entries.sort_by(|a, b| a.path().cmp(b.path()));
-    
-    for entry in entries {
-        let path = entry.path();
-        
-        if path.is_file() {
-            // Include file path and content in checksum
-            let relative_path = path.strip_prefix(&dir_path)
-                .context("Failed to create relative path")?;
-            hasher.update(relative_path.to_string_lossy().as_bytes());
-            
-            let mut file = File::open(path)
-                .with_context(|| format!("Failed to open file: {:?}", path))?;
-            let mut buffer = [0; 8192];
-            
-            loop {
-                let bytes_read = file.read(&mut buffer)
-                    .with_context(|| format!("Failed to read file: {:?}", path))?;
-                if bytes_read == 0 {
-                    break;
-                }
-                hasher.update(&buffer[..bytes_read]);
-            }
-        } else if path.is_dir() && path != dir_path.as_ref() {
-            // Include directory path in checksum
-            let relative_path = path.strip_prefix(&dir_path)
-                .context("Failed to create relative path")?;
-            hasher.update(relative_path.to_string_lossy().as_bytes());
+impl<W: Write> Write for HoldbackWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.held.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<W: Write> ArchiveSink for HoldbackWriter<W> {
+    fn position(&mut self) -> std::io::Result<u64> {
+        self.release()?;
+        Ok(self.sent)
+    }
+
+    fn roll_back_to(&mut self, position: u64) -> std::io::Result<()> {
+        if position != self.sent {
+            return Err(std::io::Error::other("Cannot roll back past data already sent"));
         }
+        self.held.set_len(0)?;
+        self.held.seek(SeekFrom::Start(0)).map(|_| ())
     }
-    
-    Ok(format!("{:x}", hasher.finalize()))
 }
 
-/// Check if a file is gzip compressed
-pub fn is_gzip_file<P: AsRef<Path>>(file_path: P) -> Result<bool> {
-    let mut file = File::open(&file_path)
-        .with_context(|| format!("Failed to open file: {:?}", file_path.as_ref()))?;
-    
-    let mut magic = [0u8; 2];
-    match file.read_exact(&mut magic) {
-        Ok(_) => Ok(magic == [0x1f, 0x8b]),
-        Err(_) => Ok(false), // File too short or read error
+/// Hash a single file's contents with `digest`, returning its size and digest
+fn hash_file_contents<D: ChecksumDigest>(path: &Path, digest: D) -> Result<(u64, Checksum)> {
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open file for checksum: {:?}", path))?;
+    let mut reader = HashingReader::with_digest(file, digest);
+    IoTuning::current().copy(&mut reader, &mut std::io::sink())
+        .with_context(|| format!("Failed to read file: {:?}", path))?;
+    let (digest, size) = reader.finish();
+    Ok((size, digest))
+}
+
+/// Extract a tar archive read from a stream to a directory entry by entry, as `options` say
+pub fn extract_tar_archive<R: Read>(
+    archive: R,
+    output_dir: &Path,
+    options: &ExtractOptions,
+    cancel: &CancellationToken,
+) -> Result<ExtractSummary> {
+    let mut attributes = AttributeRestorer::new().with_preserved(PreservedAttributes {
+        ownership: options.preserve_ownership,
+        permissions: options.preserve_permissions,
+        mtime: options.preserve_mtime,
+        xattrs: options.xattrs,
+    });
+    let mut summary = extract_tar_stream(archive, output_dir, options, &mut attributes, cancel)?;
+    summary.unrestored = attributes.finish()?;
+    Ok(summary)
+}
+
+/// [`extract_tar_archive`], restoring entry attributes through `attributes`, which the caller
+/// configures and finishes; `summary.unrestored` is left empty
+pub fn extract_tar_stream<R: Read>(
+    archive: R,
+    output_dir: &Path,
+    options: &ExtractOptions,
+    attributes: &mut AttributeRestorer,
+    cancel: &CancellationToken,
+) -> Result<ExtractSummary> {
+    ensure_directory_exists(output_dir)?;
+    let mut summary = ExtractSummary::default();
+    let mut archive = Archive::new(CancellableReader::new(archive, cancel));
+    for entry in archive.entries().context("Failed to read tar archive entries")? {
+        cancel.check()?;
+        let mut entry = entry.context("Failed to read tar archive entry")?;
+        let entry_path = entry.path().context("Failed to read tar entry path")?.into_owned();
+        if options.filter.is_some_and(|filter| !filter(&entry_path)) {
+            continue;
+        }
+
+        let destination = resolve_within_root(output_dir, &entry_path)
+            .with_context(|| format!("Failed to extract tar archive to: {:?}", output_dir))?;
+        if !make_room_for_entry(&destination, entry.header().entry_type().is_dir(), options.overwrite)? {
+            summary.skipped.push(entry_path.to_string_lossy().into_owned());
+            continue;
+        }
+
+        entry.set_preserve_mtime(options.preserve_mtime);
+        let destination = unpack_entry_within(&mut entry, output_dir)
+            .with_context(|| format!("Failed to extract tar archive to: {:?}", output_dir))?;
+        attributes.restore(&mut entry, &destination)?;
+        summary.extracted += 1;
+        if let Some(progress) = options.progress {
+            progress(&entry_path, entry.size());
+        }
     }
+    Ok(summary)
 }
 
-/// Validate file path to prevent directory traversal attacks
-pub fn validate_file_path<P: AsRef<Path>>(path: P) -> Result<()> {
-    let path = path.as_ref();
-    
-    // Check for directory traversal attempts
-    for component in path.components() {
-        match component {
-            std::path::Component::ParentDir => {
-                return Err(anyhow::anyhow!("Path contains parent directory reference: {:?}", path));
+/// Apply `overwrite` to whatever exists at `destination` before an entry is extracted there,
+/// returning whether the entry should be extracted. A directory entry merges into an existing
+/// directory.
+fn make_room_for_entry(destination: &Path, is_dir: bool, overwrite: OverwritePolicy) -> Result<bool> {
+    let Ok(existing) = std::fs::symlink_metadata(destination) else {
+        return Ok(true);
+    };
+    if is_dir && existing.is_dir() {
+        return Ok(true);
+    }
+    match overwrite {
+        OverwritePolicy::Error => Err(anyhow::anyhow!("Refusing to overwrite existing path: {:?}", destination)),
+        OverwritePolicy::Skip => Ok(false),
+        OverwritePolicy::Replace => remove_existing_path(destination).map(|_| true),
+    }
+}
+
+/// Prefix of an AUFS/OCI-style whiteout file hiding the sibling named by the rest of the file name
+const WHITEOUT_PREFIX: &str = ".wh.";
+
+/// Whether a layer-relative path names an AUFS/OCI-style whiteout file
+fn is_whiteout_path(relative_path: &str) -> bool {
+    Path::new(relative_path).file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with(WHITEOUT_PREFIX))
+}
+
+/// Whether a tar entry is an overlayfs whiteout (a 0:0 character device)
+pub(crate) fn is_overlay_whiteout<R: Read>(entry: &tar::Entry<R>) -> bool {
+    let header = entry.header();
+    header.entry_type() == tar::EntryType::Char
+        && header.device_major().ok().flatten() == Some(0)
+        && header.device_minor().ok().flatten() == Some(0)
+}
+
+/// File name of an AUFS/OCI-style opaque marker, hiding what lower layers have in its directory
+const OPAQUE_MARKER: &str = ".wh..wh..opq";
+
+/// Xattr overlayfs reads to treat an upper directory as opaque
+const OVERLAY_OPAQUE_XATTR: &str = "trusted.overlay.opaque";
+
+/// What a whiteout entry of a layer archive hides, by layer-relative path
+pub(crate) enum Whiteout {
+    /// One path: an overlayfs whiteout at it, or an AUFS-style `.wh.<name>` next to it
+    Path(PathBuf),
+    /// Whatever the directory held that this archive does not write (an opaque marker)
+    Opaque(PathBuf),
+}
+
+pub(crate) fn whiteout_of<R: Read>(entry: &tar::Entry<R>, entry_path: &Path) -> Option<Whiteout> {
+    if is_overlay_whiteout(entry) {
+        return Some(Whiteout::Path(entry_path.to_path_buf()));
+    }
+    let name = entry_path.file_name()?.to_str()?;
+    let parent = entry_path.parent().unwrap_or(Path::new(""));
+    if name == OPAQUE_MARKER {
+        return Some(Whiteout::Opaque(parent.to_path_buf()));
+    }
+    name.strip_prefix(WHITEOUT_PREFIX).map(|hidden| Whiteout::Path(parent.join(hidden)))
+}
+
+/// Applies the whiteouts met while extracting a layer archive, as its [`WhiteoutMode`] says.
+/// Extractors hand every entry to [`apply`](Self::apply) first and [`record`](Self::record) the
+/// ordinary entries they write, which opaque markers leave alone.
+struct WhiteoutApplier {
+    mode: WhiteoutMode,
+    written: HashSet<PathBuf>,
+    applied: AppliedWhiteouts,
+}
+
+impl WhiteoutApplier {
+    fn new(mode: WhiteoutMode) -> Self {
+        Self {
+            mode,
+            written: HashSet::new(),
+            applied: AppliedWhiteouts::default(),
+        }
+    }
+
+    /// Apply `entry` if it is a whiteout, leaving the paths `skip` selects alone. Returns false for
+    /// ordinary entries, which are the caller's to extract.
+    fn apply<R: Read>(
+        &mut self,
+        entry: &mut tar::Entry<R>,
+        output_dir: &Path,
+        skip: &dyn Fn(&Path) -> bool,
+        attributes: &mut AttributeRestorer,
+    ) -> Result<bool> {
+        let entry_path = entry.path()
+            .context("Failed to read tar entry path")?
+            .into_owned();
+        let Some(whiteout) = whiteout_of(entry, &entry_path) else {
+            return Ok(false);
+        };
+
+        match whiteout {
+            Whiteout::Path(hidden) => {
+                if skip(&hidden) {
+                    return Ok(true);
+                }
+                let destination = resolve_within_root(output_dir, &hidden)?;
+                if remove_existing_path(&destination)? {
+                    self.applied.deleted.push(hidden.to_string_lossy().into_owned());
+                }
+                if self.mode == WhiteoutMode::Overlay {
+                    if let Some(parent) = destination.parent() {
+                        ensure_directory_exists(parent)?;
+                    }
+                    make_device_node(&destination, libc::S_IFCHR, libc::makedev(0, 0))?;
+                    attributes.restore(entry, &destination)?;
+                }
             }
-            std::path::Component::RootDir => {
-                return Err(anyhow::anyhow!("Absolute paths are not allowed: {:?}", path));
+            Whiteout::Opaque(directory) => {
+                if skip(&directory) {
+                    return Ok(true);
+                }
+                let destination = resolve_within_root(output_dir, &directory)?;
+                ensure_directory_exists(&destination)?;
+                let children = std::fs::read_dir(&destination)
+                    .with_context(|| format!("Failed to read directory: {:?}", destination))?;
+                for child in children {
+                    let child = child.with_context(|| format!("Failed to read directory: {:?}", destination))?;
+                    let relative = directory.join(child.file_name());
+                    if !self.written.contains(&relative) && !skip(&relative) && remove_existing_path(&child.path())? {
+                        self.applied.deleted.push(relative.to_string_lossy().into_owned());
+                    }
+                }
+                if self.mode == WhiteoutMode::Overlay {
+                    attributes.set_xattr(&destination, OVERLAY_OPAQUE_XATTR, b"y");
+                }
             }
-            _ => {}
         }
+        self.applied.count += 1;
+        Ok(true)
     }
-    
-    Ok(())
-}
 
-/// Create directory if it doesn't exist
-pub fn ensure_directory_exists<P: AsRef<Path>>(dir_path: P) -> Result<()> {
-    let path = dir_path.as_ref();
-    if !path.exists() {
-        std::fs::create_dir_all(path)
-            .with_context(|| format!("Failed to create directory: {:?}", path))?;
+    /// Note an ordinary entry written at the layer-relative `entry_path`
+    fn record(&mut self, entry_path: &Path) {
+        self.written.insert(entry_path.to_path_buf());
+    }
+
+    fn finish(self) -> AppliedWhiteouts {
+        self.applied
     }
-    Ok(())
 }
 
-/// Get file size in bytes
-pub fn get_file_size<P: AsRef<Path>>(file_path: P) -> Result<u64> {
-    let metadata = std::fs::metadata(&file_path)
-        .with_context(|| format!("Failed to get file metadata: {:?}", file_path.as_ref()))?;
-    Ok(metadata.len())
+/// Per-entry bookkeeping of a layer extraction: the whiteouts it applies and, for an import that
+/// can be resumed, the journal of entries already written, which are then skipped
+pub struct ExtractionState<'a> {
+    whiteouts: WhiteoutApplier,
+    journal: Option<&'a mut ImportJournal>,
 }
 
-/// Format file size in human readable format
-pub fn format_file_size(size: u64) -> String {
-    const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
-    let mut size = size as f64;
-    let mut unit_index = 0;
-    
-    while size >= 1024.0 && unit_index < UNITS.len() - 1 {
-        size /= 1024.0;
-        unit_index += 1;
+impl<'a> ExtractionState<'a> {
+    pub fn new(whiteouts: WhiteoutMode, journal: Option<&'a mut ImportJournal>) -> Self {
+        let mut whiteouts = WhiteoutApplier::new(whiteouts);
+        // Opaque markers must not remove what an interrupted run already extracted
+        for path in journal.iter().flat_map(|journal| journal.completed_paths()) {
+            whiteouts.record(Path::new(path));
+        }
+        Self { whiteouts, journal }
     }
-    
-    if unit_index == 0 {
-        format!("{} {}", size as u64, UNITS[unit_index])
+
+    /// Apply `entry` if it is a whiteout not applied yet, leaving the paths `skip` selects alone.
+    /// Returns false for ordinary entries, which are the caller's to extract.
+    fn apply_whiteout<R: Read>(
+        &mut self,
+        entry: &mut tar::Entry<R>,
+        output_dir: &Path,
+        skip: &dyn Fn(&Path) -> bool,
+        attributes: &mut AttributeRestorer,
+    ) -> Result<bool> {
+        let relative = entry.path()
+            .context("Failed to read tar entry path")?
+            .to_string_lossy()
+            .into_owned();
+        if let Some(journal) = &self.journal
+            && journal.is_whiteout_applied(&relative)
+        {
+            return Ok(true);
+        }
+        let applied = self.whiteouts.apply(entry, output_dir, skip, attributes)?;
+        if applied && let Some(journal) = &mut self.journal {
+            journal.record_whiteout(&relative)?;
+        }
+        Ok(applied)
+    }
+
+    /// Whether the journal has the entry at `relative` as extracted by an interrupted run
+    fn is_extracted(&self, relative: &str) -> bool {
+        self.journal.as_ref().is_some_and(|journal| journal.is_completed(relative))
+    }
+
+    /// Note an ordinary entry written at `destination`, with its content digest if known
+    fn record(&mut self, entry_path: &Path, destination: &Path, digest: Option<Checksum>) -> Result<()> {
+        self.whiteouts.record(entry_path);
+        // Directories are cheap to extract again and only get their final attributes at the end
+        match &mut self.journal {
+            Some(journal) if !destination.is_dir() || destination.is_symlink() => {
+                journal.record_entry(entry_path.to_string_lossy().trim_end_matches('/'), destination, digest)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn finish(self) -> AppliedWhiteouts {
+        self.whiteouts.finish()
+    }
+}
+
+/// Remove whatever exists at `path`, returning whether anything was there
+fn remove_existing_path(path: &Path) -> Result<bool> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return Ok(false),
+    };
+    if metadata.is_dir() {
+        std::fs::remove_dir_all(path)
+            .with_context(|| format!("Failed to remove directory: {:?}", path))?;
+    } else {
+        std::fs::remove_file(path)
+            .with_context(|| format!("Failed to remove file: {:?}", path))?;
+    }
+    Ok(true)
+}
+
+/// Extract a layer archive over an existing layer directory, keeping files the archive does not
+/// touch. Whiteouts remove the paths they hide and are then applied as `whiteouts` says. Entries
+/// (and whiteouts of paths) matching `exclude` are skipped, as are those outside `only` when given.
+pub fn merge_tar_stream<R: Read>(
+    archive: R,
+    output_dir: &Path,
+    exclude: Option<&ExcludeSet>,
+    only: Option<&PathSelection>,
+    whiteouts: WhiteoutMode,
+    attributes: &mut AttributeRestorer,
+    cancel: &CancellationToken,
+) -> Result<MergeStats> {
+    let mut archive = Archive::new(CancellableReader::new(archive, cancel));
+
+    let mut stats = MergeStats::default();
+    let mut applier = WhiteoutApplier::new(whiteouts);
+    let is_excluded = |path: &Path| exclude.is_some_and(|exclude| exclude.is_excluded(&path.to_string_lossy()));
+    let is_selected = |path: &Path| only.is_none_or(|only| only.is_selected(&path.to_string_lossy()));
+
+    for entry in archive.entries().context("Failed to read tar archive entries")? {
+        cancel.check()?;
+        let mut entry = entry.context("Failed to read tar archive entry")?;
+        if applier.apply(&mut entry, output_dir, &|path| !is_selected(path) || is_excluded(path), attributes)? {
+            continue;
+        }
+        let entry_path = entry.path()
+            .context("Failed to read tar entry path")?
+            .into_owned();
+        let destination = resolve_within_root(output_dir, &entry_path)?;
+
+        let relative = entry_path.to_string_lossy().trim_end_matches('/').to_string();
+        if !is_selected(&entry_path) {
+            continue;
+        }
+        if is_excluded(&entry_path) {
+            stats.excluded.push(relative);
+            continue;
+        }
+
+        let existing = std::fs::symlink_metadata(&destination).ok();
+        if entry.header().entry_type().is_dir() {
+            // Directories merge; anything else in the way is replaced
+            if existing.is_some_and(|metadata| !metadata.is_dir()) {
+                remove_existing_path(&destination)?;
+            }
+        } else if existing.is_some() {
+            remove_existing_path(&destination)?;
+            stats.overwritten += 1;
+        } else {
+            stats.added += 1;
+        }
+
+        unpack_entry_within(&mut entry, output_dir)?;
+        attributes.restore(&mut entry, &destination)?;
+        applier.record(&entry_path);
+        stats.imported.push(relative);
+    }
+
+    let applied = applier.finish();
+    stats.deleted = applied.deleted;
+    stats.whiteouts = applied.count;
+    Ok(stats)
+}
+
+/// Work out what [`merge_tar_stream`] would do to the layer in `output_dir` without modifying it:
+/// the existing files it overwrites, the existing paths its whiteouts delete, and the files it adds
+pub fn preview_merge_stream<R: Read>(
+    archive: R,
+    output_dir: &Path,
+    exclude: Option<&ExcludeSet>,
+    only: Option<&PathSelection>,
+    cancel: &CancellationToken,
+) -> Result<CollisionReport> {
+    let mut archive = Archive::new(CancellableReader::new(archive, cancel));
+
+    let mut report = CollisionReport::default();
+    let mut written = HashSet::new();
+    let mut removed = HashSet::new();
+    let skip = |path: &Path| {
+        let path = path.to_string_lossy();
+        !only.is_none_or(|only| only.is_selected(&path)) || exclude.is_some_and(|exclude| exclude.is_excluded(&path))
+    };
+    let delete = |relative: PathBuf, removed: &mut HashSet<PathBuf>, report: &mut CollisionReport| {
+        if !removed.contains(&relative) && output_dir.join(&relative).symlink_metadata().is_ok() {
+            report.deleted.push(relative.to_string_lossy().into_owned());
+            removed.insert(relative);
+        }
+    };
+
+    for entry in archive.entries().context("Failed to read tar archive entries")? {
+        cancel.check()?;
+        let entry = entry.context("Failed to read tar archive entry")?;
+        let entry_path = entry.path()
+            .context("Failed to read tar entry path")?
+            .into_owned();
+
+        match whiteout_of(&entry, &entry_path) {
+            Some(Whiteout::Path(hidden)) => {
+                if !skip(&hidden) {
+                    resolve_within_root(output_dir, &hidden)?;
+                    delete(hidden, &mut removed, &mut report);
+                }
+                continue;
+            }
+            Some(Whiteout::Opaque(directory)) => {
+                let destination = resolve_within_root(output_dir, &directory)?;
+                if !skip(&directory) && destination.is_dir() {
+                    let children = std::fs::read_dir(&destination)
+                        .with_context(|| format!("Failed to read directory: {:?}", destination))?;
+                    for child in children {
+                        let child = child.with_context(|| format!("Failed to read directory: {:?}", destination))?;
+                        let relative = directory.join(child.file_name());
+                        if !written.contains(&relative) && !skip(&relative) {
+                            delete(relative, &mut removed, &mut report);
+                        }
+                    }
+                }
+                continue;
+            }
+            None => {}
+        }
+
+        if skip(&entry_path) {
+            continue;
+        }
+        let destination = resolve_within_root(output_dir, &entry_path)?;
+        let relative = entry_path.to_string_lossy().trim_end_matches('/').to_string();
+        written.insert(entry_path.clone());
+        if entry.header().entry_type().is_dir() {
+            continue;
+        }
+        let existing = std::fs::symlink_metadata(&destination).ok()
+            .filter(|_| !removed.contains(&entry_path));
+        match existing {
+            Some(metadata) => report.overwritten.push(Collision {
+                path: relative,
+                old_size: if metadata.is_file() { metadata.len() } else { 0 },
+                old_mtime: DateTime::from_timestamp(metadata.mtime(), 0),
+                new_size: entry.size(),
+                new_mtime: entry.header().mtime().ok()
+                    .and_then(|mtime| DateTime::from_timestamp(i64::try_from(mtime).ok()?, 0)),
+            }),
+            None => report.added.push(relative),
+        }
+    }
+
+    Ok(report)
+}
+
+/// A hash function the hashing helpers can compute checksums with
+pub trait ChecksumDigest: Send {
+    fn update(&mut self, data: &[u8]);
+    fn finish(self) -> Checksum;
+}
+
+impl ChecksumDigest for Sha256 {
+    fn update(&mut self, data: &[u8]) {
+        Digest::update(self, data);
+    }
+
+    fn finish(self) -> Checksum {
+        Checksum::new(ChecksumAlgorithm::Sha256, format!("{:x}", self.finalize()))
+    }
+}
+
+impl ChecksumDigest for blake3::Hasher {
+    fn update(&mut self, data: &[u8]) {
+        blake3::Hasher::update(self, data);
+    }
+
+    fn finish(self) -> Checksum {
+        Checksum::new(ChecksumAlgorithm::Blake3, self.finalize().to_hex().to_string())
+    }
+}
+
+/// [`ChecksumDigest`] of an algorithm chosen at run time, e.g. from an export's metadata
+#[derive(Debug, Clone)]
+pub enum AnyDigest {
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl AnyDigest {
+    pub fn new(algorithm: ChecksumAlgorithm) -> Self {
+        match algorithm {
+            ChecksumAlgorithm::Sha256 => AnyDigest::Sha256(Sha256::new()),
+            ChecksumAlgorithm::Blake3 => AnyDigest::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+}
+
+impl ChecksumDigest for AnyDigest {
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            AnyDigest::Sha256(hasher) => ChecksumDigest::update(hasher, data),
+            AnyDigest::Blake3(hasher) => ChecksumDigest::update(hasher.as_mut(), data),
+        }
+    }
+
+    fn finish(self) -> Checksum {
+        match self {
+            AnyDigest::Sha256(hasher) => hasher.finish(),
+            AnyDigest::Blake3(hasher) => hasher.finish(),
+        }
+    }
+}
+
+/// Reader adapter that feeds every byte read through it into a hasher, SHA256 unless given one
+pub struct HashingReader<R, D = Sha256> {
+    inner: R,
+    hasher: D,
+    bytes_read: u64,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self::with_digest(inner, Sha256::new())
+    }
+
+    /// Consume the reader, returning the hex SHA256 digest and number of bytes read
+    pub fn finalize(self) -> (String, u64) {
+        let (checksum, bytes_read) = self.finish();
+        (checksum.hex, bytes_read)
+    }
+}
+
+impl<R: Read, D: ChecksumDigest> HashingReader<R, D> {
+    pub fn with_digest(inner: R, hasher: D) -> Self {
+        Self {
+            inner,
+            hasher,
+            bytes_read: 0,
+        }
+    }
+
+    /// Consume the reader, returning the digest and number of bytes read
+    pub fn finish(self) -> (Checksum, u64) {
+        (self.hasher.finish(), self.bytes_read)
+    }
+}
+
+impl<R: Read, D: ChecksumDigest> Read for HashingReader<R, D> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let bytes_read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..bytes_read]);
+        self.bytes_read += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+
+/// Extract a tar archive to a directory, verifying each entry against the manifest as it is written.
+/// Entries matching `exclude` are neither written nor expected. Returns a description of every
+/// mismatching path; an empty list means the layer is intact.
+pub fn extract_tar_archive_verified<P: AsRef<Path>>(
+    archive_path: P,
+    output_dir: P,
+    manifest: &Manifest,
+    exclude: Option<&ExcludeSet>,
+    cancel: &CancellationToken,
+) -> Result<Vec<String>> {
+    let archive_file = File::open(&archive_path)
+        .with_context(|| format!("Failed to open tar file: {:?}", archive_path.as_ref()))?;
+    let mut attributes = AttributeRestorer::new();
+    let mut state = ExtractionState::new(WhiteoutMode::Delete, None);
+    let mismatches = extract_tar_stream_verified(
+        archive_file, output_dir.as_ref(), manifest, exclude, &mut state, &mut attributes, cancel
+    )?;
+    attributes.finish()?;
+    Ok(mismatches)
+}
+
+/// Stream variant of [`extract_tar_archive_verified`], restoring entry attributes through `attributes`.
+/// Whiteouts, and entries an interrupted import already extracted, are handled through `state`.
+pub fn extract_tar_stream_verified<R: Read>(
+    archive: R,
+    output_dir: &Path,
+    manifest: &Manifest,
+    exclude: Option<&ExcludeSet>,
+    state: &mut ExtractionState,
+    attributes: &mut AttributeRestorer,
+    cancel: &CancellationToken,
+) -> Result<Vec<String>> {
+    let mut archive = Archive::new(CancellableReader::new(archive, cancel));
+
+    let mut mismatches = Vec::new();
+    let mut seen = HashSet::new();
+    let is_excluded = |path: &Path| exclude.is_some_and(|exclude| exclude.is_excluded(&path.to_string_lossy()));
+
+    for entry in archive.entries().context("Failed to read tar archive entries")? {
+        cancel.check()?;
+        let mut entry = entry.context("Failed to read tar archive entry")?;
+        // Whiteouts are applied rather than written, so the manifest does not list them
+        if state.apply_whiteout(&mut entry, output_dir, &is_excluded, attributes)? {
+            continue;
+        }
+        let entry_path = entry.path()
+            .context("Failed to read tar entry path")?
+            .into_owned();
+        let destination = resolve_within_root(output_dir, &entry_path)?;
+
+        let relative = entry_path.to_string_lossy().trim_end_matches('/').to_string();
+        // Verified against the manifest when it was written, and checked again by the resume
+        if state.is_extracted(&relative) {
+            seen.insert(relative);
+            continue;
+        }
+        if exclude.is_some_and(|exclude| exclude.is_excluded(&relative)) {
+            continue;
+        }
+        let expected = manifest.entries.get(&relative);
+
+        if entry.header().entry_type().is_file() {
+            if let Some(parent) = destination.parent() {
+                ensure_directory_exists(parent)?;
+            }
+            // Replace rather than write through a symlink already at the destination
+            if destination.symlink_metadata().is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+                std::fs::remove_file(&destination)
+                    .with_context(|| format!("Failed to remove symlink: {:?}", destination))?;
+            }
+            let mode = entry.header().mode().unwrap_or(0o644);
+            let mut output_file = File::create(&destination)
+                .with_context(|| format!("Failed to create file: {:?}", destination))?;
+            // Hashed in the algorithm of the manifest's digest, so it can be compared with it
+            let algorithm = expected.and_then(|expected| expected.digest.as_ref())
+                .map_or_else(ChecksumAlgorithm::default, |digest| digest.algorithm);
+            let mut reader = HashingReader::with_digest(&mut entry, AnyDigest::new(algorithm));
+            IoTuning::current().copy(&mut reader, &mut output_file)
+                .with_context(|| format!("Failed to extract file: {:?}", destination))?;
+            std::fs::set_permissions(&destination, std::fs::Permissions::from_mode(mode & 0o777))
+                .with_context(|| format!("Failed to set permissions: {:?}", destination))?;
+            let (digest, size) = reader.finish();
+            attributes.restore(&mut entry, &destination)?;
+            let archived = EntryAttributes::from_entry(&mut entry)?;
+            let mismatch = check_archived_file(&relative, expected, size, &digest, &archived);
+            // A mismatching file fails the import, so it must not count as done for a resume
+            if mismatch.is_none() {
+                state.record(&entry_path, &destination, Some(digest))?;
+            }
+            mismatches.extend(mismatch);
+        } else {
+            unpack_entry_within(&mut entry, output_dir)?;
+            attributes.restore(&mut entry, &destination)?;
+
+            if entry.header().entry_type().is_dir() {
+                let archived = EntryAttributes::from_entry(&mut entry)?;
+                mismatches.extend(check_archived_directory(&relative, expected, &archived));
+            } else if entry.header().entry_type().is_symlink() {
+                mismatches.extend(check_archived_symlink(&relative, expected, &mut entry)?);
+            }
+            state.record(&entry_path, &destination, None)?;
+        }
+
+        seen.insert(relative);
+    }
+
+    for path in manifest.entries.keys() {
+        if !seen.contains(path) && !exclude.is_some_and(|exclude| exclude.is_excluded(path)) {
+            mismatches.push(format!("{}: missing from layer archive", path));
+        }
+    }
+
+    Ok(mismatches)
+}
+
+/// Compare a regular file read from a layer archive, with the `archived` mode and owner, with its manifest entry
+pub(crate) fn check_archived_file(
+    relative: &str,
+    expected: Option<&ManifestEntry>,
+    size: u64,
+    digest: &Checksum,
+    archived: &EntryAttributes,
+) -> Option<String> {
+    match expected {
+        Some(expected) if expected.entry_type != EntryType::File => {
+            Some(format!("{}: expected {:?}, found file", relative, expected.entry_type))
+        }
+        Some(expected) if expected.size != size => {
+            Some(format!("{}: size mismatch (expected {}, got {})", relative, expected.size, size))
+        }
+        Some(expected) if expected.digest.as_ref() != Some(digest) => {
+            Some(format!("{}: content checksum mismatch", relative))
+        }
+        Some(expected) => attribute_mismatch(relative, expected, archived, true, true),
+        None => Some(format!("{}: not present in manifest", relative)),
+    }
+}
+
+/// Compare a directory read from a layer archive, with the `archived` mode and owner, with its manifest entry
+pub(crate) fn check_archived_directory(relative: &str, expected: Option<&ManifestEntry>, archived: &EntryAttributes) -> Option<String> {
+    match expected {
+        Some(expected) if expected.entry_type != EntryType::Directory => {
+            Some(format!("{}: expected {:?}, found directory", relative, expected.entry_type))
+        }
+        Some(expected) => attribute_mismatch(relative, expected, archived, true, true),
+        None => Some(format!("{}: not present in manifest", relative)),
+    }
+}
+
+/// Compare a symlink read from a layer archive with its manifest entry
+pub(crate) fn check_archived_symlink<R: Read>(relative: &str, expected: Option<&ManifestEntry>, entry: &mut tar::Entry<R>) -> Result<Option<String>> {
+    let target = entry.link_name()
+        .context("Failed to read tar link target")?
+        .map(|target| target.to_string_lossy().into_owned());
+    let archived = EntryAttributes::from_entry(entry)?;
+    Ok(match expected {
+        Some(expected) if expected.entry_type != EntryType::Symlink => {
+            Some(format!("{}: expected {:?}, found symlink", relative, expected.entry_type))
+        }
+        Some(expected) if expected.target != target => {
+            Some(format!("{}: symlink target mismatch (expected {:?}, got {:?})", relative, expected.target, target))
+        }
+        Some(expected) => attribute_mismatch(relative, expected, &archived, true, true),
+        None => Some(format!("{}: not present in manifest", relative)),
+    })
+}
+
+/// Describe how the mode and owner of an entry differ from those its manifest entry records.
+/// Symlink modes mean nothing on Linux and are never compared; owners only where recorded.
+fn attribute_mismatch(relative: &str, expected: &ManifestEntry, actual: &EntryAttributes, compare_mode: bool, compare_owner: bool) -> Option<String> {
+    let mode = expected.mode & 0o7777;
+    if compare_mode && expected.entry_type != EntryType::Symlink && actual.mode != mode {
+        return Some(format!("{}: mode mismatch (expected {:o}, got {:o})", relative, mode, actual.mode));
+    }
+    match (expected.uid, expected.gid) {
+        (Some(uid), Some(gid)) if compare_owner && (uid, gid) != (actual.uid, actual.gid) => Some(format!(
+            "{}: owner mismatch (expected {}:{}, got {}:{})", relative, uid, gid, actual.uid, actual.gid
+        )),
+        _ => None,
+    }
+}
+
+/// Extract a layer archive read from a stream to a directory, applying its whiteouts and skipping
+/// entries already extracted through `state`, skipping entries that match `exclude`, and restoring entry attributes through
+/// `attributes`. Returns the layer-relative paths that were left out.
+pub fn extract_layer_stream<R: Read>(
+    archive: R,
+    output_dir: &Path,
+    exclude: Option<&ExcludeSet>,
+    state: &mut ExtractionState,
+    attributes: &mut AttributeRestorer,
+    cancel: &CancellationToken,
+) -> Result<Vec<String>> {
+    ensure_directory_exists(output_dir)?;
+    let mut archive = Archive::new(CancellableReader::new(archive, cancel));
+    let mut excluded = Vec::new();
+    let is_excluded = |path: &Path| exclude.is_some_and(|exclude| exclude.is_excluded(&path.to_string_lossy()));
+
+    for entry in archive.entries().context("Failed to read tar archive entries")? {
+        cancel.check()?;
+        let mut entry = entry.context("Failed to read tar archive entry")?;
+        if state.apply_whiteout(&mut entry, output_dir, &is_excluded, attributes)? {
+            continue;
+        }
+        let entry_path = entry.path()
+            .context("Failed to read tar entry path")?
+            .into_owned();
+
+        let relative = entry_path.to_string_lossy().trim_end_matches('/').to_string();
+        if state.is_extracted(&relative) {
+            continue;
+        }
+        if is_excluded(&entry_path) {
+            excluded.push(relative);
+            continue;
+        }
+        let destination = unpack_entry_within(&mut entry, output_dir)?;
+        attributes.restore(&mut entry, &destination)?;
+        state.record(&entry_path, &destination, None)?;
+    }
+
+    Ok(excluded)
+}
+
+/// Load the layer manifest extracted from an export, verifying it against the recorded checksum.
+/// Returns None for exports created before manifests were introduced.
+pub fn load_manifest<P: AsRef<Path>>(manifest_path: P, expected_checksum: Option<&str>) -> Result<Option<Manifest>> {
+    let manifest_path = manifest_path.as_ref();
+    let expected_checksum = match expected_checksum {
+        Some(checksum) => checksum,
+        None => return Ok(None),
+    };
+
+    if !manifest_path.exists() {
+        return parse_manifest(None, Some(expected_checksum));
+    }
+    let manifest_content = std::fs::read(manifest_path)
+        .context("Failed to read manifest file")?;
+    parse_manifest(Some(&manifest_content), Some(expected_checksum))
+}
+
+/// Parse manifest.json read from an export, verifying it against the recorded checksum.
+/// Returns None for exports created before manifests were introduced.
+pub fn parse_manifest(manifest_content: Option<&[u8]>, expected_checksum: Option<&str>) -> Result<Option<Manifest>> {
+    let expected_checksum = match expected_checksum {
+        Some(checksum) => checksum,
+        None => return Ok(None),
+    };
+    let manifest_content = manifest_content.ok_or_else(|| {
+        anyhow::anyhow!("Export metadata references a manifest but manifest.json is missing from the archive")
+    })?;
+
+    let actual_checksum = format!("{:x}", Sha256::digest(manifest_content));
+    if actual_checksum != expected_checksum {
+        return Err(anyhow::anyhow!(
+            "Manifest checksum mismatch: expected {}, got {}",
+            expected_checksum,
+            actual_checksum
+        ));
+    }
+
+    let manifest: Manifest = serde_json::from_slice(manifest_content)
+        .context("Failed to parse manifest JSON")?;
+    manifest.checksum_algorithm()
+        .map_err(|error| anyhow::anyhow!("Invalid manifest: {}", error))?;
+
+    Ok(Some(manifest))
+}
+
+/// Read a single member (e.g. metadata.json) from an export file without extracting the rest.
+/// Compressed exports are decompressed on the fly, and seekable zstd exports only where the
+/// member and the headers leading to it lie; returns None if the member is absent.
+pub fn read_export_member<P: AsRef<Path>>(export_path: P, member_name: &str) -> Result<Option<Vec<u8>>> {
+    let export_path = export_path.as_ref();
+    if let Some(reader) = open_seekable_export(export_path)? {
+        let mut archive = Archive::new(reader);
+        let entries = archive.entries_with_seek().context("Failed to read export archive entries")?;
+        return find_export_member(entries, member_name);
+    }
+
+    let file = File::open(export_path)
+        .with_context(|| format!("Failed to open export file: {:?}", export_path))?;
+    let reader = decompressing_reader(export_format(export_path)?, BufReader::new(file))?;
+    let mut archive = Archive::new(reader);
+    find_export_member(archive.entries().context("Failed to read export archive entries")?, member_name)
+}
+
+fn find_export_member<R: Read>(entries: tar::Entries<'_, R>, member_name: &str) -> Result<Option<Vec<u8>>> {
+    for entry in entries {
+        let mut entry = entry.context("Failed to read export archive entry")?;
+        if entry.path().context("Failed to read export entry path")?.as_os_str() == member_name {
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content)
+                .with_context(|| format!("Failed to read {} from export", member_name))?;
+            return Ok(Some(content));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Verify an extracted directory against the manifest, returning a description of every mismatching path.
+/// With `allow_missing`, manifest entries absent from the directory are not reported (incremental exports).
+pub fn verify_directory_against_manifest<P: AsRef<Path>>(
+    dir_path: P,
+    manifest: &Manifest,
+    allow_missing: bool,
+    verified: VerifiedAttributes,
+) -> Result<Vec<String>> {
+    let dir_path = dir_path.as_ref();
+    let mut mismatches = Vec::new();
+    let mut seen = std::collections::HashSet::new();
+
+    let mut entries: Vec<_> = WalkDir::new(dir_path)
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to walk directory")?;
+    entries.sort_by(|a, b| a.path().cmp(b.path()));
+
+    for entry in entries {
+        let path = entry.path();
+        if path == dir_path {
+            continue;
+        }
+        let relative = path.strip_prefix(dir_path)
+            .context("Failed to create relative path")?
+            .to_string_lossy()
+            .into_owned();
+
+        // Whiteouts and devices are not recorded; one where the manifest has an entry is a mismatch
+        if disk_entry_type(&entry.file_type()).is_none() && !manifest.entries.contains_key(&relative) {
+            continue;
+        }
+        if let Some(mismatch) = check_manifest_entry(path, &relative, manifest, verified)? {
+            mismatches.push(mismatch);
+        }
+
+        seen.insert(relative);
+    }
+
+    if !allow_missing {
+        for path in manifest.entries.keys() {
+            if !seen.contains(path) {
+                mismatches.push(format!("{}: missing from extracted layer", path));
+            }
+        }
+    }
+
+    Ok(mismatches)
+}
+
+/// Verify only the given layer-relative paths of a directory against the manifest (merge imports,
+/// where the directory also holds files that were there before). Manifest entries missing from
+/// `relative_paths` are reported as missing from the layer archive if `expected` returns true for
+/// them; whiteouts are never expected, as they are applied as deletions rather than written.
+pub fn verify_paths_against_manifest<P: AsRef<Path>>(
+    dir_path: P,
+    manifest: &Manifest,
+    relative_paths: &[String],
+    expected: &dyn Fn(&str) -> bool,
+    verified: VerifiedAttributes,
+) -> Result<Vec<String>> {
+    let dir_path = dir_path.as_ref();
+    let mut mismatches = Vec::new();
+    let imported: std::collections::HashSet<&str> = relative_paths.iter().map(String::as_str).collect();
+
+    for relative in relative_paths {
+        let path = dir_path.join(relative);
+        let metadata = match std::fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(_) => {
+                mismatches.push(format!("{}: missing from merged layer", relative));
+                continue;
+            }
+        };
+        if disk_entry_type(&metadata.file_type()).is_none() && !manifest.entries.contains_key(relative) {
+            continue;
+        }
+        if let Some(mismatch) = check_manifest_entry(&path, relative, manifest, verified)? {
+            mismatches.push(mismatch);
+        }
+    }
+
+    for path in manifest.entries.keys() {
+        if !imported.contains(path.as_str()) && !is_whiteout_path(path) && expected(path) {
+            mismatches.push(format!("{}: missing from layer archive", path));
+        }
+    }
+
+    Ok(mismatches)
+}
+
+/// Check every manifest entry selected by `expected` at its location under `dir_path`: files by
+/// size and content hash, symlinks by target, and all by type and the attributes in `verified`. Entries in the directory that the manifest does
+/// not list are ignored, so files left in the layer from before an import do not count.
+/// Returns one description per mismatching path, in manifest order.
+pub fn verify_layer_against_manifest<P: AsRef<Path>>(
+    dir_path: P,
+    manifest: &Manifest,
+    expected: &dyn Fn(&str) -> bool,
+    verified: VerifiedAttributes,
+    progress: &ProgressBar,
+) -> Result<Vec<String>> {
+    let dir_path = dir_path.as_ref();
+    let mut mismatches = Vec::new();
+
+    for (relative, entry) in &manifest.entries {
+        if !expected(relative) {
+            continue;
+        }
+        let path = dir_path.join(relative);
+        if std::fs::symlink_metadata(&path).is_err() {
+            mismatches.push(format!("{}: missing from imported layer", relative));
+            continue;
+        }
+        if let Some(mismatch) = check_manifest_entry(&path, relative, manifest, verified)? {
+            mismatches.push(mismatch);
+        }
+        progress.inc(entry.size);
+    }
+
+    Ok(mismatches)
+}
+
+/// The manifest type of an entry on disk; None for anything a manifest does not record, such as
+/// devices and overlayfs whiteouts
+fn disk_entry_type(file_type: &std::fs::FileType) -> Option<EntryType> {
+    if file_type.is_file() {
+        Some(EntryType::File)
+    } else if file_type.is_dir() {
+        Some(EntryType::Directory)
+    } else if file_type.is_symlink() {
+        Some(EntryType::Symlink)
+    } else {
+        None
+    }
+}
+
+/// Compare one file, directory or symlink, and the attributes in `verified`, with its manifest
+/// entry, describing the mismatch if any
+fn check_manifest_entry(path: &Path, relative: &str, manifest: &Manifest, verified: VerifiedAttributes) -> Result<Option<String>> {
+    let metadata = std::fs::symlink_metadata(path)
+        .with_context(|| format!("Failed to get metadata: {:?}", path))?;
+    let actual_type = disk_entry_type(&metadata.file_type());
+    let expected = manifest.entries.get(relative);
+    let mismatch = match expected {
+        None => Some(format!("{}: not present in manifest", relative)),
+        Some(expected) if Some(expected.entry_type) != actual_type => Some(match actual_type {
+            Some(actual_type) => format!("{}: expected {:?}, found {:?}", relative, expected.entry_type, actual_type),
+            None => format!("{}: expected {:?}, found a special file", relative, expected.entry_type),
+        }),
+        Some(expected) if expected.entry_type == EntryType::Symlink => {
+            let target = std::fs::read_link(path)
+                .with_context(|| format!("Failed to read symlink: {:?}", path))?
+                .to_string_lossy()
+                .into_owned();
+            (expected.target.as_ref() != Some(&target)).then(|| {
+                format!("{}: symlink target mismatch (expected {:?}, got {:?})", relative, expected.target, target)
+            })
+        }
+        Some(expected) if expected.entry_type == EntryType::File => {
+            let size = get_file_size(path)?;
+            if size != expected.size {
+                Some(format!("{}: size mismatch (expected {}, got {})", relative, expected.size, size))
+            } else {
+                match &expected.digest {
+                    Some(digest) if *digest == calculate_file_digest(path, digest.algorithm)? => None,
+                    _ => Some(format!("{}: content checksum mismatch", relative)),
+                }
+            }
+        }
+        Some(_) => None,
+    };
+    Ok(mismatch.or_else(|| {
+        let expected = expected?;
+        let actual = EntryAttributes::from_metadata(&metadata, verified.owner_shift.unwrap_or_default());
+        attribute_mismatch(relative, expected, &actual, verified.mode, verified.owner_shift.is_some())
+    }))
+}
+
+/// Calculate SHA256 checksum of a file
+pub fn calculate_file_checksum<P: AsRef<Path>>(file_path: P) -> Result<String> {
+    calculate_file_checksum_with_progress(file_path, &ProgressBar::hidden())
+}
+
+/// [`calculate_file_checksum`], advancing `progress` by the bytes hashed
+pub fn calculate_file_checksum_with_progress<P: AsRef<Path>>(file_path: P, progress: &ProgressBar) -> Result<String> {
+    let mut file = File::open(&file_path)
+        .with_context(|| format!("Failed to open file for checksum: {:?}", file_path.as_ref()))?;
+    
+    let mut hasher = Sha256::new();
+    IoTuning::current().with_buffer(|buffer| loop {
+        let bytes_read = file.read(buffer)
+            .context("Failed to read file for checksum")?;
+        if bytes_read == 0 {
+            break anyhow::Ok(());
+        }
+        Digest::update(&mut hasher, &buffer[..bytes_read]);
+        progress.inc(bytes_read as u64);
+    })?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Calculate the checksum of a file in `algorithm`
+pub fn calculate_file_digest<P: AsRef<Path>>(file_path: P, algorithm: ChecksumAlgorithm) -> Result<Checksum> {
+    let (_, digest) = hash_file_contents(file_path.as_ref(), AnyDigest::new(algorithm))?;
+    Ok(digest)
+}
+
+/// Calculate the checksum of a directory (recursive) in `format`
+pub fn calculate_directory_checksum<P: AsRef<Path>>(dir_path: P, format: ChecksumFormat) -> Result<Checksum> {
+    calculate_directory_checksum_with_progress(dir_path, format, &ProgressBar::hidden())
+}
+
+/// [`calculate_directory_checksum`], advancing `progress` by the file bytes hashed
+pub fn calculate_directory_checksum_with_progress<P: AsRef<Path>>(
+    dir_path: P,
+    format: ChecksumFormat,
+    progress: &ProgressBar,
+) -> Result<Checksum> {
+    let dir_path = dir_path.as_ref();
+    let stats = LayerScanner::new().scan(dir_path, &CancellationToken::new())?;
+    if let Some(vanished) = stats.transient_entries.first() {
+        return Err(anyhow::anyhow!("Failed to walk directory: {:?} vanished", dir_path.join(&vanished.path)));
+    }
+    progress.set_length(stats.logical_bytes);
+    let entries = stats.entries;
+
+    match format.scheme {
+        ChecksumScheme::V1 => sequential_directory_checksum(dir_path, &entries, AnyDigest::new(format.algorithm), progress),
+        ChecksumScheme::V2 | ChecksumScheme::V3 | ChecksumScheme::V4 => entry_record_directory_checksum(dir_path, &entries, format, progress),
+    }
+}
+
+/// [`ChecksumScheme::V1`]: the paths and file contents hashed one after the other
+fn sequential_directory_checksum<D: ChecksumDigest>(
+    dir_path: &Path,
+    entries: &[walkdir::DirEntry],
+    mut hasher: D,
+    progress: &ProgressBar,
+) -> Result<Checksum> {
+    for entry in entries {
+        let path = entry.path();
+        
+        if path.is_file() {
+            // Include file path and content in checksum
+            let relative_path = path.strip_prefix(dir_path)
+                .context("Failed to create relative path")?;
+            hasher.update(relative_path.to_string_lossy().as_bytes());
+            
+            let mut file = File::open(path)
+                .with_context(|| format!("Failed to open file: {:?}", path))?;
+            IoTuning::current().with_buffer(|buffer| loop {
+                let bytes_read = file.read(buffer)
+                    .with_context(|| format!("Failed to read file: {:?}", path))?;
+                if bytes_read == 0 {
+                    break anyhow::Ok(());
+                }
+                hasher.update(&buffer[..bytes_read]);
+                progress.inc(bytes_read as u64);
+            })?;
+        } else if path.is_dir() && path != dir_path {
+            // Include directory path in checksum
+            let relative_path = path.strip_prefix(dir_path)
+                .context("Failed to create relative path")?;
+            hasher.update(relative_path.to_string_lossy().as_bytes());
+        }
+    }
+    
+    Ok(hasher.finish())
+}
+
+/// [`ChecksumScheme::V2`] and later: the files hashed in parallel, then their digests combined in
+/// path order. V2 follows symlinks, later schemes record them.
+fn entry_record_directory_checksum(
+    dir_path: &Path,
+    entries: &[walkdir::DirEntry],
+    format: ChecksumFormat,
+    progress: &ProgressBar,
+) -> Result<Checksum> {
+    let follow_symlinks = format.scheme == ChecksumScheme::V2;
+    let is_file = |entry: &walkdir::DirEntry| match follow_symlinks {
+        true => entry.path().is_file(),
+        false => entry.file_type().is_file(),
+    };
+    let digests: Vec<Option<Checksum>> = entries
+        .par_iter()
+        .map(|entry| {
+            let path = entry.path();
+            if !is_file(entry) {
+                return Ok(None);
+            }
+            let file = File::open(path)
+                .with_context(|| format!("Failed to open file: {:?}", path))?;
+            let mut reader = HashingReader::with_digest(file, AnyDigest::new(format.algorithm));
+            IoTuning::current().copy(&mut progress.wrap_read(&mut reader), &mut std::io::sink())
+                .with_context(|| format!("Failed to read file: {:?}", path))?;
+            Ok(Some(reader.finish().0))
+        })
+        .collect::<Result<_>>()?;
+
+    let mut checksum = EntryRecordChecksum::new(format.scheme, format.algorithm);
+    for (entry, digest) in entries.iter().zip(digests) {
+        let path = entry.path();
+        if path == dir_path {
+            continue;
+        }
+        let relative_path = path.strip_prefix(dir_path)
+            .context("Failed to create relative path")?;
+        let attributes = || -> Result<EntryAttributes> {
+            let metadata = entry.metadata()
+                .with_context(|| format!("Failed to get metadata: {:?}", path))?;
+            Ok(EntryAttributes::from_metadata(&metadata, format.owner_shift))
+        };
+        if let Some(digest) = digest {
+            checksum.add_file(relative_path, &attributes()?, &digest);
+        } else if follow_symlinks && path.is_dir() {
+            checksum.add_directory(relative_path, &EntryAttributes::default());
+        } else if !follow_symlinks && entry.file_type().is_dir() {
+            checksum.add_directory(relative_path, &attributes()?);
+        } else if !follow_symlinks && entry.file_type().is_symlink() {
+            let target = std::fs::read_link(path)
+                .with_context(|| format!("Failed to read symlink: {:?}", path))?;
+            checksum.add_symlink(relative_path, &attributes()?, target.as_os_str().as_bytes());
+        }
+    }
+    Ok(checksum.finish())
+}
+
+/// Permission bits and owner of an entry, which records of [`ChecksumScheme::V3`] and later hash
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EntryAttributes {
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl EntryAttributes {
+    /// Attributes of an entry on disk, with its owner shifted by `owner_shift` (kept as is if the
+    /// shift takes it out of range)
+    pub fn from_metadata(metadata: &std::fs::Metadata, owner_shift: IdOffset) -> Self {
+        let (uid, gid) = owner_shift.apply(metadata.uid() as u64, metadata.gid() as u64)
+            .unwrap_or((metadata.uid(), metadata.gid()));
+        Self { mode: metadata.permissions().mode() & 0o7777, uid, gid }
+    }
+
+    /// Attributes of an archive entry as its header records them
+    fn from_header(header: &tar::Header) -> Self {
+        Self {
+            mode: header.mode().unwrap_or(0) & 0o7777,
+            uid: header.uid().ok().and_then(|uid| u32::try_from(uid).ok()).unwrap_or(0),
+            gid: header.gid().ok().and_then(|gid| u32::try_from(gid).ok()).unwrap_or(0),
+        }
+    }
+
+    /// Attributes of an archive entry, with owners from PAX records where its header cannot hold them
+    pub(crate) fn from_entry<R: Read>(entry: &mut tar::Entry<R>) -> Result<Self> {
+        let (uid, gid) = entry_owner(entry).context("Failed to read tar entry owner")?;
+        Ok(Self {
+            mode: entry.header().mode().unwrap_or(0) & 0o7777,
+            uid: u32::try_from(uid).unwrap_or(0),
+            gid: u32::try_from(gid).unwrap_or(0),
+        })
+    }
+}
+
+impl From<&ManifestEntry> for EntryAttributes {
+    fn from(entry: &ManifestEntry) -> Self {
+        Self { mode: entry.mode, uid: entry.uid.unwrap_or(0), gid: entry.gid.unwrap_or(0) }
+    }
+}
+
+/// Combines the entries of a directory, added in path order, into a checksum of
+/// [`ChecksumScheme::V2`] or later. The records and the file digests in them are hashed with the
+/// same algorithm.
+#[derive(Debug)]
+pub struct EntryRecordChecksum {
+    scheme: ChecksumScheme,
+    hasher: AnyDigest,
+}
+
+impl EntryRecordChecksum {
+    pub fn new(scheme: ChecksumScheme, algorithm: ChecksumAlgorithm) -> Self {
+        Self { scheme, hasher: AnyDigest::new(algorithm) }
+    }
+
+    pub fn scheme(&self) -> ChecksumScheme {
+        self.scheme
+    }
+
+    pub fn add_directory(&mut self, relative: &Path, attributes: &EntryAttributes) {
+        self.add_record(relative, 'd', attributes, b"");
+    }
+
+    /// Account for a file whose contents have the digest `digest`
+    pub fn add_file(&mut self, relative: &Path, attributes: &EntryAttributes, digest: &Checksum) {
+        self.add_record(relative, 'f', attributes, digest.hex.as_bytes());
+    }
+
+    /// Account for a symlink pointing at `target`; V2 follows symlinks rather than recording them
+    pub fn add_symlink(&mut self, relative: &Path, attributes: &EntryAttributes, target: &[u8]) {
+        if self.scheme != ChecksumScheme::V2 {
+            self.add_record(relative, 'l', attributes, target);
+        }
+    }
+
+    /// Account for an entry as a manifest records it; an entry without a recorded owner hashes as
+    /// owned by root
+    pub fn add_manifest_entry(&mut self, relative: &Path, entry: &ManifestEntry) {
+        let attributes = EntryAttributes::from(entry);
+        match (entry.entry_type, &entry.digest) {
+            (EntryType::File, Some(digest)) => self.add_file(relative, &attributes, digest),
+            (EntryType::Directory, _) => self.add_directory(relative, &attributes),
+            (EntryType::Symlink, _) => self.add_symlink(relative, &attributes, entry.target.as_deref().unwrap_or_default().as_bytes()),
+            _ => {}
+        }
+    }
+
+    fn add_record(&mut self, relative: &Path, kind: char, attributes: &EntryAttributes, payload: &[u8]) {
+        // Schemes before V4 hash the lossy UTF-8 form of the path
+        if self.scheme == ChecksumScheme::V4 {
+            self.hasher.update(relative.as_os_str().as_bytes());
+        } else {
+            self.hasher.update(relative.to_string_lossy().as_bytes());
+        }
+        self.hasher.update(&[0]);
+        if self.scheme != ChecksumScheme::V2 {
+            let EntryAttributes { mode, uid, gid } = attributes;
+            self.hasher.update(format!("{}\0{:o}\0{}\0{}\0", kind, mode, uid, gid).as_bytes());
+        }
+        self.hasher.update(payload);
+        self.hasher.update(b"\n");
+    }
+
+    pub fn finish(self) -> Checksum {
+        self.hasher.finish()
+    }
+}
+
+/// Computes [`calculate_directory_checksum`] of the directory a tar archive extracts to from the
+/// archive's entries as they stream past. This only works when entries arrive in the sorted order
+/// exports write them in, every parent directory precedes its children, and all entries are files,
+/// directories or (for [`ChecksumScheme::V3`] and later) symlinks, other than whiteouts; otherwise
+/// [`StreamingDirectoryChecksum::finish`] returns None.
+#[derive(Debug)]
+pub struct StreamingDirectoryChecksum {
+    algorithm: ChecksumAlgorithm,
+    /// [`ChecksumScheme::V1`] hasher, which hashes the contents once more; None when another scheme is known
+    sequential: Option<AnyDigest>,
+    /// Records of every later scheme computed
+    records: Vec<EntryRecordChecksum>,
+    previous: Option<PathBuf>,
+    directories: std::collections::HashSet<PathBuf>,
+    computable: bool,
+}
+
+impl StreamingDirectoryChecksum {
+    /// Compute the checksum in `scheme`, or in every scheme while it is not known yet, hashed with `algorithm`
+    pub fn new(scheme: Option<ChecksumScheme>, algorithm: ChecksumAlgorithm) -> Self {
+        let computed = |candidate: ChecksumScheme| scheme.is_none_or(|scheme| scheme == candidate);
+        Self {
+            algorithm,
+            sequential: computed(ChecksumScheme::V1).then(|| AnyDigest::new(algorithm)),
+            records: [ChecksumScheme::V2, ChecksumScheme::V3, ChecksumScheme::V4].into_iter()
+                .filter(|scheme| computed(*scheme))
+                .map(|scheme| EntryRecordChecksum::new(scheme, algorithm))
+                .collect(),
+            previous: None,
+            directories: std::collections::HashSet::new(),
+            computable: true,
+        }
+    }
+
+    /// Account for an entry at `relative` (relative to the extraction root), consuming its content.
+    /// Returns the size and digest of regular files.
+    pub fn add_entry<R: Read>(&mut self, relative: &Path, entry: &mut tar::Entry<R>) -> Result<Option<(u64, Checksum)>> {
+        // Without the trailing slash of directory entries
+        let path: PathBuf = relative.components().collect();
+        let relative = &*path.to_string_lossy();
+        if self.previous.as_ref().is_some_and(|previous| path <= *previous) {
+            self.computable = false;
+        }
+        if let Some(parent) = path.parent()
+            && !parent.as_os_str().is_empty()
+            && !self.directories.contains(parent)
+        {
+            self.computable = false;
+        }
+
+        let entry_type = entry.header().entry_type();
+        let attributes = EntryAttributes::from_header(entry.header());
+        let file_digest = if entry_type.is_file() && is_whiteout_path(relative) {
+            // Whiteout files are applied rather than extracted, which the stream alone cannot follow
+            self.computable = false;
+            None
+        } else if entry_type.is_file() {
+            if let Some(sequential) = &mut self.sequential {
+                sequential.update(relative.as_bytes());
+            }
+            let mut file_hasher = AnyDigest::new(self.algorithm);
+            let mut size = 0;
+            IoTuning::current().with_buffer(|buffer| loop {
+                let bytes_read = entry.read(buffer)
+                    .with_context(|| format!("Failed to read {} from archive", relative))?;
+                if bytes_read == 0 {
+                    break anyhow::Ok(());
+                }
+                if let Some(sequential) = &mut self.sequential {
+                    sequential.update(&buffer[..bytes_read]);
+                }
+                file_hasher.update(&buffer[..bytes_read]);
+                size += bytes_read as u64;
+            })?;
+            let digest = file_hasher.finish();
+            for records in &mut self.records {
+                records.add_file(&path, &attributes, &digest);
+            }
+            Some((size, digest))
+        } else if entry_type.is_dir() {
+            if let Some(sequential) = &mut self.sequential {
+                sequential.update(relative.as_bytes());
+            }
+            for records in &mut self.records {
+                records.add_directory(&path, &attributes);
+            }
+            self.directories.insert(path.clone());
+            None
+        } else if entry_type.is_symlink() {
+            // V1 and V2 hash what a symlink points to, which the stream alone cannot follow
+            self.sequential = None;
+            self.records.retain(|records| records.scheme() != ChecksumScheme::V2);
+            let target = entry.link_name_bytes().unwrap_or_default();
+            for records in &mut self.records {
+                records.add_symlink(&path, &attributes, &target);
+            }
+            None
+        } else {
+            // Hard links and special files extract to something the stream alone cannot hash
+            self.computable = false;
+            None
+        };
+
+        self.previous = Some(path);
+        Ok(file_digest)
+    }
+
+    /// The checksum in `scheme`, if the stream allowed computing it
+    pub fn finish(self, scheme: ChecksumScheme) -> Option<Checksum> {
+        if !self.computable {
+            return None;
+        }
+        match scheme {
+            ChecksumScheme::V1 => self.sequential.map(ChecksumDigest::finish),
+            _ => self.records.into_iter()
+                .find(|records| records.scheme() == scheme)
+                .map(EntryRecordChecksum::finish),
+        }
+    }
+}
+
+/// Suffix used for export files that are still being written
+pub const PARTIAL_SUFFIX: &str = ".partial";
+
+/// Output file written under a `.partial` name in the destination directory and renamed into
+/// place only once complete. The partial file is removed if it is dropped without being committed.
+pub struct PartialFile {
+    partial_path: PathBuf,
+    final_path: PathBuf,
+    committed: bool,
+}
+
+impl PartialFile {
+    pub fn new<P: AsRef<Path>>(final_path: P) -> Self {
+        let final_path = final_path.as_ref().to_path_buf();
+        let mut partial_name = final_path.as_os_str().to_owned();
+        partial_name.push(PARTIAL_SUFFIX);
+        Self {
+            partial_path: PathBuf::from(partial_name),
+            final_path,
+            committed: false,
+        }
+    }
+
+    /// Path the data should be written to
+    pub fn path(&self) -> &Path {
+        &self.partial_path
+    }
+
+    /// Flush the partial file to disk and atomically rename it to its final name
+    pub fn commit(mut self) -> Result<PathBuf> {
+        File::open(&self.partial_path)
+            .and_then(|file| file.sync_all())
+            .with_context(|| format!("Failed to sync output file: {:?}", self.partial_path))?;
+        std::fs::rename(&self.partial_path, &self.final_path)
+            .with_context(|| format!("Failed to move {:?} into place at {:?}", self.partial_path, self.final_path))?;
+        self.committed = true;
+        // The rename is only durable once the directory holding it is on disk as well
+        if let Some(parent) = self.final_path.parent() {
+            sync_directory(if parent.as_os_str().is_empty() { Path::new(".") } else { parent })?;
+        }
+        Ok(self.final_path.clone())
+    }
+}
+
+impl Drop for PartialFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = std::fs::remove_file(&self.partial_path);
+        }
+    }
+}
+
+/// Fail unless `file_path` has the SHA256 given with --sha256; returns the verified checksum
+pub fn verify_file_sha256<P: AsRef<Path>>(file_path: P, expected: &Sha256Digest) -> Result<String> {
+    let calculated = calculate_file_checksum(&file_path)?;
+    if calculated != expected.0 {
+        return Err(anyhow::anyhow!(
+            "Input checksum mismatch: expected {}, got {}",
+            expected, calculated
+        ));
+    }
+    Ok(calculated)
+}
+
+/// Input file name that reads the export from stdin, as written by `export -`
+pub const STDIN_PATH: &str = "-";
+
+/// Copy an export piped into stdin to `spool_path`, where it can be read more than once and its
+/// compression detected from the leading bytes; returns the number of bytes spooled
+pub fn spool_stdin(spool_path: &Path, cancel: &CancellationToken) -> Result<u64> {
+    let stdin = std::io::stdin();
+    if stdin.is_terminal() {
+        return Err(anyhow::anyhow!(
+            "Refusing to read an export from a terminal; pipe one in, e.g. `layer-tool export <container> - | layer-tool import - <container>`"
+        ));
+    }
+    // Recognize the format first, so input that cannot be read fails before it is spooled
+    let (format, mut reader) = detect_stream_format(CancellableReader::new(stdin.lock(), cancel))
+        .context("Failed to read export from stdin")?;
+    ensure_supported_format(format)?;
+    let output_file = File::create(spool_path)
+        .with_context(|| format!("Failed to create file: {:?}", spool_path))?;
+    let tuning = IoTuning::current();
+    let mut writer = tuning.writer(output_file);
+    let bytes = tuning.copy(&mut reader, &mut writer)
+        .context("Failed to read export from stdin")?;
+    writer.flush().context("Failed to write export read from stdin")?;
+    Ok(bytes)
+}
+
+/// Reject input files left behind by an interrupted export
+pub fn reject_partial_file<P: AsRef<Path>>(file_path: P) -> Result<()> {
+    let file_path = file_path.as_ref();
+    if file_path.to_string_lossy().ends_with(PARTIAL_SUFFIX) {
+        return Err(anyhow::anyhow!(
+            "{:?} looks like an incomplete export (the export that produced it did not finish)",
+            file_path
+        ));
+    }
+    Ok(())
+}
+
+/// Suffix of the index file written next to a split export
+pub const CHUNK_INDEX_SUFFIX: &str = ".index.json";
+
+/// Path of the chunk with the given position (`<file>.000`, `<file>.001`, ...)
+pub fn chunk_path<P: AsRef<Path>>(file_path: P, position: usize) -> PathBuf {
+    let mut name = file_path.as_ref().as_os_str().to_owned();
+    name.push(format!(".{:03}", position));
+    PathBuf::from(name)
+}
+
+/// Path of the chunk index for a split export
+pub fn chunk_index_path<P: AsRef<Path>>(file_path: P) -> PathBuf {
+    let mut name = file_path.as_ref().as_os_str().to_owned();
+    name.push(CHUNK_INDEX_SUFFIX);
+    PathBuf::from(name)
+}
+
+/// Locate the chunk index when the input is a split export's index file or first chunk
+pub fn find_chunk_index<P: AsRef<Path>>(input_path: P) -> Option<PathBuf> {
+    let input_path = input_path.as_ref();
+    let name = input_path.to_string_lossy();
+    if name.ends_with(CHUNK_INDEX_SUFFIX) {
+        Some(input_path.to_path_buf())
+    } else {
+        name.strip_suffix(".000")
+            .map(|base| chunk_index_path(PathBuf::from(base)))
+    }
+}
+
+/// Split a file into chunks of at most `chunk_size` bytes and write the chunk index.
+/// The source file is left in place.
+pub fn split_file<P: AsRef<Path>>(file_path: P, chunk_size: u64) -> Result<ChunkIndex> {
+    let file_path = file_path.as_ref();
+    if chunk_size == 0 {
+        return Err(anyhow::anyhow!("Chunk size must be greater than zero"));
+    }
+
+    let file_name = file_path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .with_context(|| format!("Invalid output file name: {:?}", file_path))?;
+    let total_size = get_file_size(file_path)?;
+    let tuning = IoTuning::current();
+    let mut reader = tuning.reader(File::open(file_path)
+        .with_context(|| format!("Failed to open file for splitting: {:?}", file_path))?);
+
+    let mut chunks = Vec::new();
+    let mut remaining = total_size;
+    while remaining > 0 || chunks.is_empty() {
+        let path = chunk_path(file_path, chunks.len());
+        let partial = PartialFile::new(&path);
+        let output_file = File::create(partial.path())
+            .with_context(|| format!("Failed to create chunk file: {:?}", partial.path()))?;
+
+        let mut hashing = HashingReader::new((&mut reader).take(chunk_size.min(remaining)));
+        let mut writer = tuning.writer(output_file);
+        tuning.copy(&mut hashing, &mut writer)
+            .with_context(|| format!("Failed to write chunk file: {:?}", path))?;
+        writer.flush()
+            .with_context(|| format!("Failed to write chunk file: {:?}", path))?;
+        drop(writer);
+        partial.commit()?;
+
+        let (sha256, size) = hashing.finalize();
+        remaining -= size;
+        chunks.push(ChunkInfo {
+            name: path.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+            size,
+            sha256,
+        });
+    }
+
+    let index = ChunkIndex {
+        file_name,
+        total_size,
+        chunk_size,
+        chunk_count: chunks.len(),
+        chunks,
+    };
+
+    let index_partial = PartialFile::new(chunk_index_path(file_path));
+    let index_json = serde_json::to_string_pretty(&index)
+        .context("Failed to serialize chunk index")?;
+    std::fs::write(index_partial.path(), index_json)
+        .context("Failed to write chunk index")?;
+    index_partial.commit()?;
+
+    Ok(index)
+}
+
+/// Concatenate the chunks listed in a chunk index into `output_path`, verifying each chunk's
+/// position, size, and checksum on the way
+pub fn assemble_chunks<P: AsRef<Path>>(index_path: P, output_path: P) -> Result<ChunkIndex> {
+    let index_path = index_path.as_ref();
+    let index_json = std::fs::read_to_string(index_path)
+        .with_context(|| format!("Failed to read chunk index: {:?}", index_path))?;
+    let index: ChunkIndex = serde_json::from_str(&index_json)
+        .with_context(|| format!("Failed to parse chunk index: {:?}", index_path))?;
+
+    if index.chunk_count != index.chunks.len() {
+        return Err(anyhow::anyhow!(
+            "Chunk index declares {} chunks but lists {}",
+            index.chunk_count,
+            index.chunks.len()
+        ));
+    }
+
+    let chunk_dir = index_path.parent().unwrap_or_else(|| Path::new(""));
+    let mut writer = BufWriter::new(File::create(&output_path)
+        .with_context(|| format!("Failed to create output file: {:?}", output_path.as_ref()))?);
+
+    let mut total_size = 0u64;
+    for (position, chunk) in index.chunks.iter().enumerate() {
+        let expected_name = chunk_path(&index.file_name, position);
+        if Path::new(&chunk.name) != expected_name {
+            return Err(anyhow::anyhow!(
+                "Chunk index is out of order: position {} lists {} but expected {}",
+                position,
+                chunk.name,
+                expected_name.display()
+            ));
+        }
+
+        let path = chunk_dir.join(&chunk.name);
+        if !path.exists() {
+            return Err(anyhow::anyhow!(
+                "Missing chunk {} of {}: {}",
+                position + 1,
+                index.chunk_count,
+                path.display()
+            ));
+        }
+
+        let mut hashing = HashingReader::new(File::open(&path)
+            .with_context(|| format!("Failed to open chunk: {:?}", path))?);
+        IoTuning::current().copy(&mut hashing, &mut writer)
+            .with_context(|| format!("Failed to read chunk: {:?}", path))?;
+        let (sha256, size) = hashing.finalize();
+        if size != chunk.size || sha256 != chunk.sha256 {
+            return Err(anyhow::anyhow!(
+                "Chunk {} of {} is corrupt: {} (expected {} bytes with checksum {}, found {} bytes with checksum {})",
+                position + 1,
+                index.chunk_count,
+                path.display(),
+                chunk.size,
+                chunk.sha256,
+                size,
+                sha256
+            ));
+        }
+        total_size += size;
+    }
+
+    writer.flush()
+        .context("Failed to write reassembled export")?;
+    if total_size != index.total_size {
+        return Err(anyhow::anyhow!(
+            "Reassembled export is {} bytes but the chunk index expects {}",
+            total_size,
+            index.total_size
+        ));
+    }
+
+    Ok(index)
+}
+
+/// Parse a human-readable size such as `4096`, `500M`, or `5GiB` into bytes
+pub fn parse_size(value: &str) -> Result<u64> {
+    let value = value.trim();
+    let digits_end = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (number, unit) = value.split_at(digits_end);
+    let number: u64 = number.parse()
+        .with_context(|| format!("Invalid size: {:?}", value))?;
+
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1024,
+        "M" | "MB" | "MIB" => 1024 * 1024,
+        "G" | "GB" | "GIB" => 1024 * 1024 * 1024,
+        "T" | "TB" | "TIB" => 1024 * 1024 * 1024 * 1024,
+        _ => return Err(anyhow::anyhow!("Unknown size unit in {:?}", value)),
+    };
+
+    number.checked_mul(multiplier)
+        .filter(|size| *size > 0)
+        .with_context(|| format!("Size out of range: {:?}", value))
+}
+
+/// Leading bytes read to recognize an [`ArchiveFormat`], a whole tar header
+const FORMAT_PROBE_SIZE: u64 = 512;
+
+/// Format of the file at `file_path`. A file of no known format is not an export and fails with
+/// its magic bytes; Zstd, Xz and Age are returned for the caller to decide on.
+pub fn detect_format<P: AsRef<Path>>(file_path: P) -> Result<ArchiveFormat> {
+    let file = File::open(&file_path)
+        .with_context(|| format!("Failed to open file: {:?}", file_path.as_ref()))?;
+    let head = read_format_probe(file)
+        .with_context(|| format!("Failed to read file: {:?}", file_path.as_ref()))?;
+    recognized_format(&head)
+}
+
+/// A stream with the bytes already read from it put back in front
+pub type PeekedReader<R> = std::io::Chain<std::io::Cursor<Vec<u8>>, R>;
+
+/// Format of a stream that cannot be seeked, such as stdin, as [`detect_format`]. Returns it with
+/// a reader yielding the whole stream, the bytes examined included.
+pub fn detect_stream_format<R: Read>(mut reader: R) -> Result<(ArchiveFormat, PeekedReader<R>)> {
+    let head = read_format_probe(&mut reader)
+        .context("Failed to read input")?;
+    let format = recognized_format(&head)?;
+    Ok((format, std::io::Cursor::new(head).chain(reader)))
+}
+
+/// Format of the export at `file_path`, failing for formats this release cannot read
+pub fn export_format<P: AsRef<Path>>(file_path: P) -> Result<ArchiveFormat> {
+    let format = detect_format(&file_path)?;
+    ensure_supported_format(format)?;
+    Ok(format)
+}
+
+/// Fail for an export format this release recognizes but cannot read
+pub fn ensure_supported_format(format: ArchiveFormat) -> Result<()> {
+    match format {
+        _ if format.is_supported() => Ok(()),
+        ArchiveFormat::Age => Err(anyhow::anyhow!("Export is encrypted with age; decrypt it first (`age -d`)")),
+        _ => Err(anyhow::anyhow!(
+            "Export is {}-compressed, which this release cannot read; decompress it first (e.g. `{} -d`)", format, format
+        )),
+    }
+}
+
+fn read_format_probe<R: Read>(reader: R) -> std::io::Result<Vec<u8>> {
+    let mut head = Vec::new();
+    reader.take(FORMAT_PROBE_SIZE).read_to_end(&mut head)?;
+    Ok(head)
+}
+
+fn recognized_format(head: &[u8]) -> Result<ArchiveFormat> {
+    match ArchiveFormat::detect(head) {
+        ArchiveFormat::Unknown if head.is_empty() => Err(anyhow::anyhow!("not a layer-tool export (it is empty)")),
+        ArchiveFormat::Unknown => {
+            let magic: Vec<String> = head.iter().take(4).map(|byte| format!("{:02X}", byte)).collect();
+            Err(anyhow::anyhow!("not a layer-tool export (unrecognized magic bytes {})", magic.join(" ")))
+        }
+        format => Ok(format),
+    }
+}
+
+/// Largest ratio of uncompressed to compressed size deflate can reach
+const MAX_DEFLATE_RATIO: u64 = 1032;
+
+/// Where an [`UncompressedEstimate`] comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EstimateSource {
+    /// The file is not compressed, or records no size; the estimate is its size
+    FileSize,
+    /// The ISIZE field of the gzip trailer
+    GzipTrailer,
+    /// The seek table of a seekable zstd file, which records the exact size
+    SeekTable,
+    /// The content size in the header of a single-frame zstd file, also exact
+    ZstdFrameHeader,
+}
+
+/// Largest zstd frame header, magic number included
+const ZSTD_FRAME_HEADER_MAX: u64 = 18;
+
+/// How big an export is once decompressed, estimated without decompressing it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UncompressedEstimate {
+    /// Smallest size consistent with the file
+    pub bytes: u64,
+    pub source: EstimateSource,
+    /// Whether `bytes` plus a multiple of 4 GiB is consistent with the file too: the gzip
+    /// trailer records the size modulo 2^32
+    pub wraps: bool,
+}
+
+impl UncompressedEstimate {
+    /// The size consistent with the file that is closest to, and not below, `at_least`
+    pub fn at_least(&self, at_least: u64) -> u64 {
+        if !self.wraps || self.bytes >= at_least {
+            return self.bytes;
+        }
+        let wraps = (at_least - self.bytes).div_ceil(1 << 32);
+        self.bytes + wraps * (1 << 32)
+    }
+}
+
+/// Estimate the uncompressed size of the file at `file_path`, `file_size` bytes long: its size
+/// for an uncompressed file, for zstd the exact size its seek table or, for a single frame, its
+/// frame header records, and for gzip the ISIZE field of the trailer, the last 4 bytes. ISIZE is the size modulo 2^32, so 4 GiB multiples are added until the size is at least
+/// the compressed size; deflate's maximum ratio tells whether further multiples are possible. For
+/// a file of several gzip members the trailer covers only the last one.
+pub fn estimate_uncompressed_size<P: AsRef<Path>>(file_path: P, file_size: u64) -> Result<UncompressedEstimate> {
+    let file_path = file_path.as_ref();
+    let format = export_format(file_path)?;
+    if format == ArchiveFormat::Zstd
+        && let Some(table) = zstd_seek_table(file_path)?
+    {
+        return Ok(UncompressedEstimate { bytes: table.decompressed_size(), source: EstimateSource::SeekTable, wraps: false });
+    }
+    if format == ArchiveFormat::Zstd {
+        let file = File::open(file_path)
+            .with_context(|| format!("Failed to open file: {:?}", file_path))?;
+        let mut head = Vec::new();
+        file.take(ZSTD_FRAME_HEADER_MAX).read_to_end(&mut head)
+            .with_context(|| format!("Failed to read the zstd frame header of {:?}", file_path))?;
+        if let Ok(Some(bytes)) = zstd::zstd_safe::get_frame_content_size(&head) {
+            return Ok(UncompressedEstimate { bytes, source: EstimateSource::ZstdFrameHeader, wraps: false });
+        }
+    }
+    if format != ArchiveFormat::Gzip {
+        return Ok(UncompressedEstimate { bytes: file_size, source: EstimateSource::FileSize, wraps: false });
+    }
+    let mut file = File::open(file_path)
+        .with_context(|| format!("Failed to open file: {:?}", file_path))?;
+    let mut trailer = [0u8; 4];
+    file.seek(SeekFrom::End(-4))
+        .and_then(|_| file.read_exact(&mut trailer))
+        .with_context(|| format!("Failed to read the gzip trailer of {:?}", file_path))?;
+    let isize = u32::from_le_bytes(trailer) as u64;
+
+    // Stored blocks make deflate output slightly larger than its input at worst
+    let smallest = file_size.saturating_sub(file_size / 4096 + 1024);
+    let estimate = UncompressedEstimate { bytes: isize, source: EstimateSource::GzipTrailer, wraps: true };
+    let bytes = estimate.at_least(smallest);
+    Ok(UncompressedEstimate {
+        bytes,
+        source: EstimateSource::GzipTrailer,
+        wraps: file_size.saturating_mul(MAX_DEFLATE_RATIO) >= bytes + (1 << 32),
+    })
+}
+
+/// Why an archive entry was refused for landing outside the extraction root
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathViolationReason {
+    /// Its path has a `..` component
+    ParentDirectory,
+    /// Its path is absolute, or starts with a Windows drive or UNC prefix
+    Absolute,
+    /// A directory on its way (or, for a directory entry, the entry itself) is a symlink leading
+    /// outside the root
+    SymlinkEscape,
+    /// It is a hard link or symlink whose target lies outside the root
+    LinkTarget,
+}
+
+impl std::fmt::Display for PathViolationReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PathViolationReason::ParentDirectory => write!(f, "path contains a parent directory reference"),
+            PathViolationReason::Absolute => write!(f, "absolute paths are not allowed"),
+            PathViolationReason::SymlinkEscape => write!(f, "path escapes the extraction root through a symlink"),
+            PathViolationReason::LinkTarget => write!(f, "link target lies outside the extraction root"),
+        }
+    }
+}
+
+/// An archive entry that would be written outside the extraction root
+#[derive(Debug)]
+pub struct PathViolation {
+    pub entry: PathBuf,
+    pub reason: PathViolationReason,
+}
+
+impl PathViolation {
+    fn new(entry: &Path, reason: PathViolationReason) -> Self {
+        Self { entry: entry.to_path_buf(), reason }
+    }
+}
+
+impl std::fmt::Display for PathViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Refusing archive entry {:?}: {}", self.entry, self.reason)
+    }
+}
+
+impl std::error::Error for PathViolation {}
+
+/// Validate file path to prevent directory traversal attacks: `..` components and absolute paths
+/// are refused, including Windows-style ones (`C:\x`, `\\server\share`, `..\x`) that archives written on
+/// Windows may carry. Fails with a [`PathViolation`].
+pub fn validate_file_path<P: AsRef<Path>>(path: P) -> Result<()> {
+    let path = path.as_ref();
+    let violation = |reason| Err(PathViolation::new(path, reason).into());
+
+    if path.as_os_str().as_bytes().starts_with(b"\\") {
+        return violation(PathViolationReason::Absolute);
+    }
+    for (index, component) in path.components().enumerate() {
+        match component {
+            std::path::Component::ParentDir => return violation(PathViolationReason::ParentDirectory),
+            std::path::Component::RootDir | std::path::Component::Prefix(_) => {
+                return violation(PathViolationReason::Absolute);
+            }
+            std::path::Component::Normal(name) => {
+                let name = name.as_bytes();
+                if index == 0 && name.len() >= 2 && name[0].is_ascii_alphabetic() && name[1] == b':' {
+                    return violation(PathViolationReason::Absolute);
+                }
+                if name.split(|&byte| byte == b'\\').any(|part| part == b"..") {
+                    return violation(PathViolationReason::ParentDirectory);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether `path` exists and resolves (through symlinks) to somewhere under `canonical_root`
+fn resolves_within(path: &Path, canonical_root: &Path) -> bool {
+    path.canonicalize().is_ok_and(|resolved| resolved.starts_with(canonical_root))
+}
+
+/// Resolve a relative path to its location under `root`, refusing paths that could land outside
+/// it: absolute and `..` paths, and paths whose existing parent directories resolve (through
+/// symlinks) outside the root. Fails with a [`PathViolation`].
+pub fn resolve_within_root(root: &Path, relative: &Path) -> Result<PathBuf> {
+    validate_file_path(relative)?;
+    let canonical_root = root.canonicalize()
+        .with_context(|| format!("Failed to resolve extraction root: {:?}", root))?;
+    // Without a trailing slash, which would make looking the destination up follow a symlink there
+    let destination = root.join(relative.components().collect::<PathBuf>());
+
+    // The deepest existing ancestor decides where missing parent directories get created
+    let mut ancestor = destination.parent();
+    while let Some(directory) = ancestor {
+        if directory.symlink_metadata().is_ok() {
+            if !resolves_within(directory, &canonical_root) {
+                return Err(PathViolation::new(relative, PathViolationReason::SymlinkEscape).into());
+            }
+            break;
+        }
+        ancestor = directory.parent();
+    }
+
+    Ok(destination)
+}
+
+/// Refuse link entries whose target lies outside the extraction root. Hard link targets are
+/// archive paths; relative symlink targets are resolved against the link's directory. Absolute
+/// symlink targets point into the container's root filesystem and are never followed while
+/// extracting, so they are left alone.
+fn check_link_target<R: Read>(entry: &tar::Entry<R>, root: &Path, entry_path: &Path) -> Result<()> {
+    let link_name = match entry.link_name().context("Failed to read tar link target")? {
+        Some(link_name) => link_name.into_owned(),
+        None => return Ok(()),
+    };
+    let violation = || PathViolation::new(entry_path, PathViolationReason::LinkTarget);
+
+    match entry.header().entry_type() {
+        tar::EntryType::Link => {
+            resolve_within_root(root, &link_name)
+                .map_err(|_| violation())?;
+        }
+        tar::EntryType::Symlink if link_name.is_relative() => {
+            let mut depth = entry_path.parent()
+                .map_or(0, |parent| parent.components().filter(|c| matches!(c, std::path::Component::Normal(_))).count());
+            for component in link_name.components() {
+                match component {
+                    std::path::Component::ParentDir => {
+                        depth = depth.checked_sub(1).ok_or_else(violation)?;
+                    }
+                    std::path::Component::Normal(_) => depth += 1,
+                    _ => {}
+                }
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Extract a single tar entry under `root`, refusing entries that would write outside it.
+/// Returns where the entry was written.
+pub fn unpack_entry_within<R: Read>(entry: &mut tar::Entry<R>, root: &Path) -> Result<PathBuf> {
+    let entry_path = entry.path()
+        .context("Failed to read tar entry path")?
+        .into_owned();
+    let destination = resolve_within_root(root, &entry_path)?;
+    check_link_target(entry, root, &entry_path)?;
+    // A directory entry merges into what is already there, following a symlink an earlier
+    // entry left at its path; other entries replace such a symlink instead
+    if entry.header().entry_type().is_dir()
+        && destination.symlink_metadata().is_ok_and(|metadata| metadata.is_symlink())
+        && !resolves_within(&destination, &root.canonicalize().context("Failed to resolve extraction root")?)
+    {
+        return Err(PathViolation::new(&entry_path, PathViolationReason::SymlinkEscape).into());
+    }
+
+    let unpacked = entry.unpack_in(root)
+        .with_context(|| format!("Failed to extract entry: {:?}", entry_path))?;
+    if !unpacked {
+        return Err(PathViolation::new(&entry_path, PathViolationReason::SymlinkEscape).into());
+    }
+    Ok(destination)
+}
+
+/// Recursively copy a directory, preserving permissions and symlinks
+pub fn copy_directory<P: AsRef<Path>>(source_dir: P, target_dir: P) -> Result<()> {
+    let source_dir = source_dir.as_ref();
+    let target_dir = target_dir.as_ref();
+
+    for entry in WalkDir::new(source_dir) {
+        let entry = entry.context("Failed to walk directory")?;
+        let relative_path = entry.path().strip_prefix(source_dir)
+            .context("Failed to create relative path")?;
+        let destination = target_dir.join(relative_path);
+        let file_type = entry.file_type();
+
+        if file_type.is_dir() {
+            std::fs::create_dir_all(&destination)
+                .with_context(|| format!("Failed to create directory: {:?}", destination))?;
+            let permissions = entry.metadata().context("Failed to read directory metadata")?.permissions();
+            std::fs::set_permissions(&destination, permissions)
+                .with_context(|| format!("Failed to set permissions: {:?}", destination))?;
+        } else if file_type.is_symlink() {
+            let link_target = std::fs::read_link(entry.path())
+                .with_context(|| format!("Failed to read symlink: {:?}", entry.path()))?;
+            std::os::unix::fs::symlink(&link_target, &destination)
+                .with_context(|| format!("Failed to create symlink: {:?}", destination))?;
+        } else {
+            std::fs::copy(entry.path(), &destination)
+                .with_context(|| format!("Failed to copy {:?} to {:?}", entry.path(), destination))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Move a directory to `destination`, which must not exist yet. When the two are on different
+/// filesystems (EXDEV) the tree is copied instead, keeping modes, ownership, times, symlinks, hard
+/// links, device nodes (whiteouts), and xattrs, and the source is removed once the copy is complete;
+/// `progress` counts the bytes copied. Returns the paths whose attributes could not be copied, as
+/// [`AttributeRestorer::finish`] does.
+pub fn move_directory(source_dir: &Path, destination: &Path, progress: &ProgressBar) -> Result<Vec<String>> {
+    match std::fs::rename(source_dir, destination) {
+        Ok(()) => return Ok(Vec::new()),
+        Err(error) if error.raw_os_error() == Some(libc::EXDEV) => {}
+        Err(error) => {
+            return Err(error).with_context(|| format!("Failed to move {:?} to {:?}", source_dir, destination));
+        }
+    }
+
+    progress.set_length(directory_disk_usage(source_dir)?.bytes);
+    let failures = match copy_tree_preserving(source_dir, destination, progress) {
+        Ok(failures) => failures,
+        Err(error) => {
+            // The source is untouched; only the partial copy has to go
+            let _ = std::fs::remove_dir_all(destination);
+            return Err(error).with_context(|| format!("Failed to copy {:?} to {:?}", source_dir, destination));
+        }
+    };
+    std::fs::remove_dir_all(source_dir)
+        .with_context(|| format!("Failed to remove {:?} after copying it to {:?}", source_dir, destination))?;
+    Ok(failures)
+}
+
+/// Copy a directory tree entry by entry with everything [`move_directory`] keeps
+fn copy_tree_preserving(source_dir: &Path, target_dir: &Path, progress: &ProgressBar) -> Result<Vec<String>> {
+    let mut attributes = AttributeRestorer::new();
+    let mut hard_links: HashMap<(u64, u64), PathBuf> = HashMap::new();
+
+    for entry in WalkDir::new(source_dir) {
+        let entry = entry.context("Failed to walk directory")?;
+        let relative_path = entry.path().strip_prefix(source_dir)
+            .context("Failed to create relative path")?;
+        let destination = target_dir.join(relative_path);
+        let metadata = entry.metadata()
+            .with_context(|| format!("Failed to get metadata: {:?}", entry.path()))?;
+        let file_type = metadata.file_type();
+
+        if file_type.is_file() && metadata.nlink() > 1 {
+            let inode = (metadata.dev(), metadata.ino());
+            if let Some(first) = hard_links.get(&inode) {
+                std::fs::hard_link(first, &destination)
+                    .with_context(|| format!("Failed to create hard link: {:?}", destination))?;
+                continue;
+            }
+            hard_links.insert(inode, destination.clone());
+        }
+
+        if file_type.is_dir() {
+            std::fs::create_dir(&destination)
+                .with_context(|| format!("Failed to create directory: {:?}", destination))?;
+        } else if file_type.is_symlink() {
+            let link_target = std::fs::read_link(entry.path())
+                .with_context(|| format!("Failed to read symlink: {:?}", entry.path()))?;
+            std::os::unix::fs::symlink(&link_target, &destination)
+                .with_context(|| format!("Failed to create symlink: {:?}", destination))?;
+        } else if file_type.is_file() {
+            let mut source = File::open(entry.path())
+                .with_context(|| format!("Failed to open {:?}", entry.path()))?;
+            let mut target = File::create(&destination)
+                .with_context(|| format!("Failed to create {:?}", destination))?;
+            IoTuning::current().copy(&mut source, &mut progress.wrap_write(&mut target))
+                .with_context(|| format!("Failed to copy {:?} to {:?}", entry.path(), destination))?;
+        } else {
+            make_device_node(&destination, metadata.mode() & libc::S_IFMT, metadata.rdev())?;
+        }
+        attributes.copy_from(entry.path(), &metadata, &destination);
+    }
+
+    attributes.finish()
+}
+
+/// Flush a directory's entries to disk, so files created or renamed in it survive a power loss
+pub fn sync_directory(path: &Path) -> Result<()> {
+    File::open(path)
+        .and_then(|dir| dir.sync_all())
+        .with_context(|| format!("Failed to sync directory: {:?}", path))
+}
+
+/// Flush everything written to the filesystem holding `path`, such as a layer an import has just
+/// written file by file
+pub fn sync_filesystem(path: &Path) -> Result<()> {
+    let dir = File::open(path)
+        .with_context(|| format!("Failed to open {:?}", path))?;
+    // SAFETY: the descriptor belongs to `dir`, which outlives the call
+    if unsafe { libc::syncfs(std::os::fd::AsRawFd::as_raw_fd(&dir)) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to sync filesystem of {:?}", path));
+    }
+    Ok(())
+}
+
+/// Create a device node or fifo of `file_type` (`S_IFCHR`, `S_IFBLK`, or `S_IFIFO`), as overlayfs
+/// whiteouts are; the tar crate and `std::fs` cannot make them
+pub fn make_device_node(destination: &Path, file_type: libc::mode_t, device: libc::dev_t) -> Result<()> {
+    let c_path = CString::new(destination.as_os_str().as_bytes())
+        .context("Path contains a NUL byte")?;
+    // SAFETY: c_path is a valid NUL-terminated string that outlives the call
+    if unsafe { libc::mknod(c_path.as_ptr(), file_type | 0o600, device) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to create {:?}", destination));
+    }
+    Ok(())
+}
+
+/// Remove layer-relative paths from a directory, ignoring paths that no longer exist
+pub fn remove_layer_paths<P: AsRef<Path>>(layer_dir: P, relative_paths: &[String]) -> Result<usize> {
+    let layer_dir = layer_dir.as_ref();
+    let mut removed = 0;
+
+    // Deepest paths first so directory removal never races its own children
+    let mut paths: Vec<&String> = relative_paths.iter().collect();
+    paths.sort_by(|a, b| b.cmp(a));
+
+    for relative_path in paths {
+        let path = resolve_within_root(layer_dir, Path::new(relative_path))?;
+        let metadata = match std::fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        if metadata.is_dir() {
+            std::fs::remove_dir_all(&path)
+                .with_context(|| format!("Failed to remove directory: {:?}", path))?;
+        } else {
+            std::fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove file: {:?}", path))?;
+        }
+        removed += 1;
+    }
+
+    Ok(removed)
+}
+
+/// Create directory if it doesn't exist
+pub fn ensure_directory_exists<P: AsRef<Path>>(dir_path: P) -> Result<()> {
+    let path = dir_path.as_ref();
+    if !path.exists() {
+        std::fs::create_dir_all(path)
+            .with_context(|| format!("Failed to create directory: {:?}", path))?;
+    }
+    Ok(())
+}
+
+/// Write a JSON report file, creating its parent directory if needed
+pub fn write_json_report<P: AsRef<Path>, T: serde::Serialize + ?Sized>(report_path: P, report: &T) -> Result<()> {
+    let report_path = report_path.as_ref();
+    if let Some(parent) = report_path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        ensure_directory_exists(parent)?;
+    }
+    let report_json = serde_json::to_string_pretty(report)
+        .context("Failed to serialize report")?;
+    std::fs::write(report_path, format!("{}\n", report_json))
+        .with_context(|| format!("Failed to write report: {:?}", report_path))
+}
+
+/// Get file size in bytes
+pub fn get_file_size<P: AsRef<Path>>(file_path: P) -> Result<u64> {
+    let metadata = std::fs::metadata(&file_path)
+        .with_context(|| format!("Failed to get file metadata: {:?}", file_path.as_ref()))?;
+    Ok(metadata.len())
+}
+
+/// Block size assumed when estimating the space files will take before they are written
+const ESTIMATED_BLOCK_SIZE: u64 = 4096;
+
+/// Space a set of files occupies, or will occupy, on a filesystem
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiskUsage {
+    pub bytes: u64,
+    pub inodes: u64,
+}
+
+impl DiskUsage {
+    /// Account for one entry of `size` bytes that is yet to be written, rounded up to whole blocks
+    pub fn add_entry(&mut self, size: u64) {
+        self.bytes += size.div_ceil(ESTIMATED_BLOCK_SIZE).max(1) * ESTIMATED_BLOCK_SIZE;
+        self.inodes += 1;
+    }
+}
+
+impl std::ops::Add for DiskUsage {
+    type Output = DiskUsage;
+
+    fn add(self, other: DiskUsage) -> DiskUsage {
+        DiskUsage {
+            bytes: self.bytes.saturating_add(other.bytes),
+            inodes: self.inodes.saturating_add(other.inodes),
+        }
+    }
+}
+
+/// Estimated space the layer described by `manifest` takes once extracted
+pub fn manifest_disk_usage(manifest: &Manifest) -> DiskUsage {
+    let mut usage = DiskUsage::default();
+    for entry in manifest.entries.values() {
+        usage.add_entry(entry.size);
+    }
+    usage
+}
+
+/// Space the files under `dir_path` occupy; nothing if it does not exist
+pub fn directory_disk_usage<P: AsRef<Path>>(dir_path: P) -> Result<DiskUsage> {
+    let mut usage = DiskUsage::default();
+    if !dir_path.as_ref().exists() {
+        return Ok(usage);
+    }
+    for entry in WalkDir::new(&dir_path) {
+        let entry = entry.context("Failed to walk directory")?;
+        let metadata = entry.metadata()
+            .with_context(|| format!("Failed to get metadata: {:?}", entry.path()))?;
+        usage.bytes += metadata.blocks() * 512;
+        usage.inodes += 1;
+    }
+    Ok(usage)
+}
+
+/// Classify a failure on a single path of a layer being walked or archived: vanished paths are
+/// always skipped, other errors only when `ignore_transient_errors` is set
+fn transient_issue(error: &std::io::Error, ignore_transient_errors: bool) -> Option<TransientIssue> {
+    if error.kind() == std::io::ErrorKind::NotFound {
+        Some(TransientIssue::Vanished)
+    } else if ignore_transient_errors {
+        Some(TransientIssue::Unreadable)
+    } else {
+        None
+    }
+}
+
+/// Keeps the `top` largest of the entries it is given
+#[derive(Debug, Default)]
+pub struct LargestEntries {
+    top: usize,
+    heap: BinaryHeap<Reverse<(u64, String)>>,
+}
+
+impl LargestEntries {
+    pub fn new(top: usize) -> Self {
+        Self { top, heap: BinaryHeap::new() }
+    }
+
+    pub fn add(&mut self, path: &str, size: u64) {
+        if self.top == 0 {
+            return;
+        }
+        if self.heap.len() < self.top {
+            self.heap.push(Reverse((size, path.to_string())));
+        } else if let Some(Reverse((smallest, _))) = self.heap.peek()
+            && size > *smallest
+        {
+            self.heap.pop();
+            self.heap.push(Reverse((size, path.to_string())));
+        }
+    }
+
+    /// The entries kept, largest first
+    pub fn sorted(&self) -> Vec<SizedEntry> {
+        let mut largest: Vec<_> = self.heap.iter()
+            .map(|Reverse((size, path))| SizedEntry { path: path.clone(), size: *size })
+            .collect();
+        largest.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path)));
+        largest
+    }
+}
+
+/// Walks a layer directory once, in the path order archives and checksums record it, and counts
+/// what it holds. The [`LayerStats`] it returns keep the walked entries, so archiving the layer
+/// with [`create_layer_archive`] does not walk it again.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LayerScanner<'a> {
+    exclude: Option<&'a ExcludeSet>,
+    ignore_transient_errors: bool,
+    top: usize,
+}
+
+impl<'a> LayerScanner<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Prune the paths matched by `exclude` from the walk
+    pub fn exclude(mut self, exclude: Option<&'a ExcludeSet>) -> Self {
+        self.exclude = exclude;
+        self
+    }
+
+    /// Record paths that cannot be read as transient entries instead of failing; paths that
+    /// vanish during the walk are always recorded
+    pub fn ignore_transient_errors(mut self, ignore_transient_errors: bool) -> Self {
+        self.ignore_transient_errors = ignore_transient_errors;
+        self
+    }
+
+    /// Keep the `top` largest regular files
+    pub fn top(mut self, top: usize) -> Self {
+        self.top = top;
+        self
+    }
+
+    pub fn scan(&self, root: &Path, cancel: &CancellationToken) -> Result<LayerStats> {
+        if !root.exists() {
+            return Err(anyhow::anyhow!("Source directory does not exist: {:?}", root));
+        }
+
+        let mut stats = LayerStats::default();
+        let walker = WalkDir::new(root).into_iter().filter_entry(|entry| {
+            self.exclude.is_none_or(|exclude| {
+                let relative_path = entry.path().strip_prefix(root).unwrap_or(entry.path());
+                !exclude.is_excluded(&relative_path.to_string_lossy())
+            })
+        });
+        for entry in walker {
+            cancel.check()?;
+            match entry {
+                Ok(entry) => stats.entries.push(entry),
+                Err(error) => {
+                    let issue = error.io_error().and_then(|error| transient_issue(error, self.ignore_transient_errors));
+                    let (Some(issue), Some(path)) = (issue, error.path().map(Path::to_path_buf)) else {
+                        return Err(error).context("Failed to walk directory");
+                    };
+                    stats.transient_entries.push(TransientEntry {
+                        path: path.strip_prefix(root).unwrap_or(&path).to_string_lossy().into_owned(),
+                        issue,
+                    });
+                }
+            }
+        }
+        stats.entries.sort_by(|a, b| a.path().cmp(b.path()));
+
+        // Hardlinked inodes take their blocks once, however many paths lead to them
+        let mut largest = LargestEntries::new(self.top);
+        let mut linked_inodes = HashSet::new();
+        for entry in &stats.entries {
+            let file_type = entry.file_type();
+            if entry.depth() == 0 {
+                continue;
+            }
+            if file_type.is_symlink() {
+                stats.symlinks += 1;
+                continue;
+            }
+            // A path vanishing now is recorded when it is archived
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if file_type.is_dir() {
+                stats.directories += 1;
+            } else if file_type.is_file() {
+                stats.files += 1;
+                stats.logical_bytes += metadata.len();
+                let relative_path = entry.path().strip_prefix(root).unwrap_or(entry.path());
+                largest.add(&relative_path.to_string_lossy(), metadata.len());
+                if metadata.nlink() > 1 && !linked_inodes.insert((metadata.dev(), metadata.ino())) {
+                    continue;
+                }
+            } else {
+                continue;
+            }
+            stats.physical_bytes += metadata.blocks() * 512;
+        }
+        stats.largest_entries = largest.sorted();
+        Ok(stats)
+    }
+}
+
+/// Manifest of a directory as an export of it would record it: file contents hashed in parallel
+/// with `algorithm`, symlinks with their targets, and anything else, such as overlayfs whiteout
+/// devices, left out
+pub fn directory_manifest<P: AsRef<Path>>(dir_path: P, algorithm: ChecksumAlgorithm, cancel: &CancellationToken) -> Result<Manifest> {
+    let dir_path = dir_path.as_ref();
+    let mut paths = Vec::new();
+    for entry in WalkDir::new(dir_path).min_depth(1) {
+        let entry = entry.context("Failed to walk directory")?;
+        paths.push(entry.into_path());
+    }
+
+    let entries: Vec<Option<(String, ManifestEntry)>> = paths
+        .par_iter()
+        .map(|path| {
+            cancel.check()?;
+            let metadata = match std::fs::symlink_metadata(path) {
+                Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+                metadata => metadata.with_context(|| format!("Failed to get metadata: {:?}", path))?,
+            };
+            let mode = metadata.permissions().mode() & 0o7777;
+            let (uid, gid) = (Some(metadata.uid()), Some(metadata.gid()));
+            let manifest_entry = if metadata.is_file() {
+                let (size, digest) = hash_file_contents(path, AnyDigest::new(algorithm))?;
+                ManifestEntry { entry_type: EntryType::File, size, mode, digest: Some(digest), uid, gid, target: None }
+            } else if metadata.is_dir() {
+                ManifestEntry { entry_type: EntryType::Directory, size: 0, mode, digest: None, uid, gid, target: None }
+            } else if metadata.is_symlink() {
+                let target = std::fs::read_link(path)
+                    .with_context(|| format!("Failed to read symlink: {:?}", path))?;
+                let target = Some(target.to_string_lossy().into_owned());
+                ManifestEntry { entry_type: EntryType::Symlink, size: 0, mode, digest: None, uid, gid, target }
+            } else {
+                return Ok(None);
+            };
+            let relative_path = path.strip_prefix(dir_path)
+                .context("Failed to create relative path")?;
+            Ok(Some((relative_path.to_string_lossy().into_owned(), manifest_entry)))
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(Manifest { entries: entries.into_iter().flatten().collect() })
+}
+
+/// Free bytes and inodes available to unprivileged processes on the filesystem holding `path`,
+/// or the nearest ancestor that exists. Filesystems without an inode limit report `u64::MAX` inodes.
+pub fn filesystem_free_space<P: AsRef<Path>>(path: P) -> Result<DiskUsage> {
+    let existing = path.as_ref().ancestors()
+        .find(|ancestor| ancestor.exists())
+        .ok_or_else(|| anyhow::anyhow!("No existing directory holds {:?}", path.as_ref()))?;
+    let c_path = CString::new(existing.as_os_str().as_bytes())
+        .with_context(|| format!("Invalid path: {:?}", existing))?;
+    // SAFETY: statvfs is plain old data, for which all-zero bytes are a valid value
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: c_path is a valid NUL-terminated string and stat a writable statvfs, both outliving the call
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to get filesystem statistics for {:?}", existing));
+    }
+    Ok(DiskUsage {
+        bytes: stat.f_bavail as u64 * stat.f_frsize as u64,
+        inodes: if stat.f_files == 0 { u64::MAX } else { stat.f_favail as u64 },
+    })
+}
+
+/// Whether this process may write to `path`; a read-only filesystem counts as not writable
+pub fn is_writable<P: AsRef<Path>>(path: P) -> Result<bool> {
+    let c_path = CString::new(path.as_ref().as_os_str().as_bytes())
+        .with_context(|| format!("Invalid path: {:?}", path.as_ref()))?;
+    // SAFETY: c_path is a valid NUL-terminated string outliving the call
+    Ok(unsafe { libc::access(c_path.as_ptr(), libc::W_OK) } == 0)
+}
+
+/// Format file size in human readable format
+pub fn format_file_size(size: u64) -> String {
+    const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
+    let mut size = size as f64;
+    let mut unit_index = 0;
+    
+    while size >= 1024.0 && unit_index < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit_index += 1;
+    }
+    
+    if unit_index == 0 {
+        format!("{} {}", size as u64, UNITS[unit_index])
     } else {
         format!("{:.2} {}", size, UNITS[unit_index])
     }
 }
+
+/// One-line summary of `stats`: entry counts, then the file bytes and the space they take on disk
+pub fn describe_layer_stats(stats: &LayerStats) -> String {
+    format!(
+        "{} files, {} directories, {} symlinks; {} ({} on disk)",
+        stats.files, stats.directories, stats.symlinks,
+        format_file_size(stats.logical_bytes), format_file_size(stats.physical_bytes)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsString;
+    use std::os::unix::ffi::OsStringExt;
+    use std::os::unix::fs::{symlink, FileExt};
+    use std::sync::{Arc, Mutex};
+    use tempfile::TempDir;
+
+    fn layer_checksum(dir: &Path, scheme: ChecksumScheme) -> Checksum {
+        calculate_directory_checksum(dir, ChecksumFormat::new(scheme, ChecksumAlgorithm::Sha256)).unwrap()
+    }
+
+    /// Archive `source` as export does, returning the checksum, manifest and archive path
+    fn archive_layer(source: &Path, workspace: &TempDir) -> (Checksum, Manifest, PathBuf) {
+        let archive_path = workspace.path().join("layer.tar");
+        let (checksum, manifest, _) = create_tar_archive_filtered(
+            source, &archive_path, None, None, LayerArchiveOptions::default(), &CancellationToken::new()
+        ).unwrap();
+        (checksum, manifest, archive_path)
+    }
+
+    #[test]
+    fn chmod_changes_attribute_schemes_only() {
+        let layer = TempDir::new().unwrap();
+        let file = layer.path().join("script.sh");
+        std::fs::write(&file, b"#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o644)).unwrap();
+        let before: Vec<Checksum> = [ChecksumScheme::V1, ChecksumScheme::V2, ChecksumScheme::V3, ChecksumScheme::V4].into_iter()
+            .map(|scheme| layer_checksum(layer.path(), scheme))
+            .collect();
+
+        std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(layer_checksum(layer.path(), ChecksumScheme::V1), before[0]);
+        assert_eq!(layer_checksum(layer.path(), ChecksumScheme::V2), before[1]);
+        assert_ne!(layer_checksum(layer.path(), ChecksumScheme::V3), before[2]);
+        assert_ne!(layer_checksum(layer.path(), ChecksumScheme::V4), before[3]);
+    }
+
+    #[test]
+    fn symlinks_are_archived_as_links() {
+        let layer = TempDir::new().unwrap();
+        let workspace = TempDir::new().unwrap();
+        std::fs::write(layer.path().join("target"), b"contents").unwrap();
+        symlink("target", layer.path().join("relative")).unwrap();
+        symlink("/etc/hostname", layer.path().join("absolute")).unwrap();
+        symlink("missing", layer.path().join("dangling")).unwrap();
+
+        let (checksum, manifest, archive_path) = archive_layer(layer.path(), &workspace);
+        assert_eq!(checksum, layer_checksum(layer.path(), CURRENT_CHECKSUM_SCHEME));
+        for (name, target) in [("relative", "target"), ("absolute", "/etc/hostname"), ("dangling", "missing")] {
+            let entry = &manifest.entries[name];
+            assert_eq!(entry.entry_type, EntryType::Symlink);
+            assert_eq!(entry.target.as_deref(), Some(target));
+            assert_eq!(entry.digest, None);
+        }
+
+        // No host file is read through a link: every link entry is empty and names its target
+        let mut archive = Archive::new(File::open(&archive_path).unwrap());
+        for entry in archive.entries().unwrap() {
+            let entry = entry.unwrap();
+            if entry.path().unwrap() != Path::new("target") {
+                assert_eq!(entry.header().entry_type(), tar::EntryType::Symlink);
+                assert_eq!(entry.size(), 0);
+            }
+        }
+    }
+
+    #[test]
+    fn repointing_a_symlink_changes_the_checksum() {
+        let layer = TempDir::new().unwrap();
+        let workspace = TempDir::new().unwrap();
+        std::fs::write(layer.path().join("a"), b"same").unwrap();
+        std::fs::write(layer.path().join("b"), b"same").unwrap();
+        symlink("a", layer.path().join("link")).unwrap();
+        let (before, _, _) = archive_layer(layer.path(), &workspace);
+
+        std::fs::remove_file(layer.path().join("link")).unwrap();
+        symlink("b", layer.path().join("link")).unwrap();
+        let (after, manifest, _) = archive_layer(layer.path(), &workspace);
+        assert_ne!(before, after);
+        assert_eq!(after, layer_checksum(layer.path(), CURRENT_CHECKSUM_SCHEME));
+        assert_eq!(manifest.entries["link"].target.as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn raw_path_bytes_distinguish_non_utf8_names() {
+        let first = TempDir::new().unwrap();
+        let second = TempDir::new().unwrap();
+        let workspace = TempDir::new().unwrap();
+        // Both names decode lossily to "data-\u{FFFD}"
+        std::fs::write(first.path().join(OsString::from_vec(b"data-\xff".to_vec())), b"same").unwrap();
+        std::fs::write(second.path().join(OsString::from_vec(b"data-\xfe".to_vec())), b"same").unwrap();
+
+        assert_eq!(layer_checksum(first.path(), ChecksumScheme::V3), layer_checksum(second.path(), ChecksumScheme::V3));
+        assert_ne!(layer_checksum(first.path(), ChecksumScheme::V4), layer_checksum(second.path(), ChecksumScheme::V4));
+
+        let (checksum, _, _) = archive_layer(first.path(), &workspace);
+        assert_eq!(checksum, layer_checksum(first.path(), ChecksumScheme::V4));
+    }
+
+    /// Archive `source` and extract it again into a fresh directory
+    fn round_trip(source: &Path, workspace: &TempDir) -> (Manifest, PathBuf) {
+        let (_, manifest, archive_path) = archive_layer(source, workspace);
+        let extracted = workspace.path().join("extracted");
+        extract_tar_archive(
+            File::open(&archive_path).unwrap(), &extracted, &ExtractOptions::default(), &CancellationToken::new()
+        ).unwrap();
+        (manifest, extracted)
+    }
+
+    #[test]
+    fn long_paths_and_link_targets_round_trip() {
+        let layer = TempDir::new().unwrap();
+        let workspace = TempDir::new().unwrap();
+        // The final component alone is too long for a ustar name, so only a PAX path can hold it
+        let nested: PathBuf = (0..3).map(|level| format!("directory-{:04}", level)).collect();
+        let relative = nested.join(format!("{}.txt", "f".repeat(101)));
+        assert_eq!(relative.as_os_str().len(), 150);
+        std::fs::create_dir_all(layer.path().join(&nested)).unwrap();
+        std::fs::write(layer.path().join(&relative), b"deep").unwrap();
+        symlink(&relative, layer.path().join("shortcut")).unwrap();
+
+        let (manifest, extracted) = round_trip(layer.path(), &workspace);
+        let mut archive = Archive::new(File::open(workspace.path().join("layer.tar")).unwrap());
+        let pax_keys: HashSet<String> = archive.entries().unwrap()
+            .flat_map(|entry| {
+                let mut entry = entry.unwrap();
+                let keys: Vec<String> = entry.pax_extensions().unwrap().into_iter().flatten()
+                    .map(|extension| extension.unwrap().key().unwrap().to_string())
+                    .collect();
+                keys
+            })
+            .collect();
+        assert!(pax_keys.contains("path") && pax_keys.contains("linkpath"));
+        assert_eq!(std::fs::read(extracted.join(&relative)).unwrap(), b"deep");
+        assert_eq!(std::fs::read_link(extracted.join("shortcut")).unwrap(), relative);
+        assert!(verify_directory_against_manifest(&extracted, &manifest, false, AttributeRestorer::new().verified_attributes()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn ownership_modes_mtimes_and_xattrs_round_trip() {
+        if !crate::attributes::running_as_root() {
+            return;
+        }
+        let layer = TempDir::new().unwrap();
+        let workspace = TempDir::new().unwrap();
+        let file = layer.path().join("data");
+        std::fs::write(&file, b"contents").unwrap();
+        std::os::unix::fs::chown(&file, Some(1234), Some(1234)).unwrap();
+        std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o4750)).unwrap();
+        xattr::set(&file, "user.origin", b"fixture").unwrap();
+        filetime::set_file_mtime(&file, filetime::FileTime::from_unix_time(1_600_000_000, 0)).unwrap();
+
+        let (manifest, extracted) = round_trip(layer.path(), &workspace);
+        let entry = &manifest.entries["data"];
+        assert_eq!((entry.uid, entry.gid), (Some(1234), Some(1234)));
+
+        let restored = extracted.join("data");
+        let metadata = std::fs::symlink_metadata(&restored).unwrap();
+        assert_eq!((metadata.uid(), metadata.gid()), (1234, 1234));
+        assert_eq!(metadata.mode() & 0o7777, 0o4750);
+        assert_eq!(metadata.mtime(), 1_600_000_000);
+        assert_eq!(xattr::get(&restored, "user.origin").unwrap().as_deref(), Some(&b"fixture"[..]));
+    }
+
+    /// Archive of a setuid file with an owner, mtime and xattr to restore, next to a plain file;
+    /// also whether the filesystem took the xattr
+    fn attribute_fixture(workspace: &TempDir) -> (PathBuf, bool) {
+        let layer = TempDir::new().unwrap();
+        let file = layer.path().join("data");
+        std::fs::write(&file, b"archived").unwrap();
+        std::fs::write(layer.path().join("plain"), b"plain").unwrap();
+        if crate::attributes::running_as_root() {
+            std::os::unix::fs::chown(&file, Some(1234), Some(1234)).unwrap();
+        }
+        std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o4750)).unwrap();
+        let xattrs = xattr::set(&file, "user.origin", b"fixture").is_ok();
+        filetime::set_file_mtime(&file, filetime::FileTime::from_unix_time(1_600_000_000, 0)).unwrap();
+        (archive_layer(layer.path(), workspace).2, xattrs)
+    }
+
+    fn extract_with(archive_path: &Path, destination: &Path, options: &ExtractOptions) -> Result<ExtractSummary> {
+        extract_tar_archive(File::open(archive_path).unwrap(), destination, options, &CancellationToken::new())
+    }
+
+    #[test]
+    fn extraction_options_select_the_restored_attributes() {
+        let workspace = TempDir::new().unwrap();
+        let (archive_path, xattrs) = attribute_fixture(&workspace);
+
+        let restored = workspace.path().join("restored");
+        extract_with(&archive_path, &restored, &ExtractOptions::default()).unwrap();
+        let metadata = std::fs::metadata(restored.join("data")).unwrap();
+        assert_eq!(metadata.mode() & 0o7777, 0o4750);
+        assert_eq!(metadata.mtime(), 1_600_000_000);
+        if crate::attributes::running_as_root() {
+            assert_eq!(metadata.uid(), 1234);
+        }
+
+        let bare = workspace.path().join("bare");
+        let options = ExtractOptions {
+            preserve_permissions: false,
+            preserve_ownership: false,
+            preserve_mtime: false,
+            xattrs: false,
+            ..ExtractOptions::default()
+        };
+        extract_with(&archive_path, &bare, &options).unwrap();
+        let metadata = std::fs::metadata(bare.join("data")).unwrap();
+        assert_eq!(metadata.mode() & 0o7777, 0o750);
+        assert_ne!(metadata.mtime(), 1_600_000_000);
+        assert_eq!(metadata.uid(), std::fs::metadata(workspace.path()).unwrap().uid());
+        if xattrs {
+            assert_eq!(xattr::get(restored.join("data"), "user.origin").unwrap().as_deref(), Some(&b"fixture"[..]));
+            assert_eq!(xattr::get(bare.join("data"), "user.origin").unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn overwrite_policies_decide_about_existing_files() {
+        let workspace = TempDir::new().unwrap();
+        let (archive_path, _) = attribute_fixture(&workspace);
+        let destination = workspace.path().join("destination");
+        std::fs::create_dir(&destination).unwrap();
+        std::fs::write(destination.join("data"), b"existing").unwrap();
+
+        let options = |overwrite| ExtractOptions { overwrite, ..ExtractOptions::default() };
+        assert!(extract_with(&archive_path, &destination, &options(OverwritePolicy::Error)).is_err());
+        assert_eq!(std::fs::read(destination.join("data")).unwrap(), b"existing");
+
+        let summary = extract_with(&archive_path, &destination, &options(OverwritePolicy::Skip)).unwrap();
+        assert_eq!(summary.skipped, ["data"]);
+        assert_eq!(std::fs::read(destination.join("data")).unwrap(), b"existing");
+        assert_eq!(std::fs::read(destination.join("plain")).unwrap(), b"plain");
+
+        let summary = extract_with(&archive_path, &destination, &options(OverwritePolicy::Replace)).unwrap();
+        assert!(summary.skipped.is_empty());
+        assert_eq!(std::fs::read(destination.join("data")).unwrap(), b"archived");
+    }
+
+    #[test]
+    fn extraction_filter_and_progress_see_each_entry() {
+        let workspace = TempDir::new().unwrap();
+        let (archive_path, _) = attribute_fixture(&workspace);
+        let destination = workspace.path().join("destination");
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let filter = |path: &Path| path != Path::new("data");
+        let progress = {
+            let reported = reported.clone();
+            move |path: &Path, size: u64| reported.lock().unwrap().push((path.to_path_buf(), size))
+        };
+        let options = ExtractOptions { filter: Some(&filter), progress: Some(&progress), ..ExtractOptions::default() };
+
+        let summary = extract_with(&archive_path, &destination, &options).unwrap();
+        assert_eq!(summary.extracted, 1);
+        assert!(!destination.join("data").exists());
+        assert_eq!(std::fs::read(destination.join("plain")).unwrap(), b"plain");
+        assert_eq!(*reported.lock().unwrap(), [(PathBuf::from("plain"), 5)]);
+    }
+
+    #[test]
+    fn sizes_over_ustar_limit_are_written_as_pax_records() {
+        let layer = TempDir::new().unwrap();
+        let size = (8 << 30) + 1;
+        let file = File::create(layer.path().join("sparse")).unwrap();
+        file.set_len(size).unwrap();
+
+        let mut builder = Builder::new(Vec::new());
+        let mut header = Header::new_ustar();
+        header.set_metadata(&file.metadata().unwrap());
+        // Only the headers are read back, so the body is left out
+        append_pax_entry(&mut builder, &mut header, Path::new("sparse"), None, 0, &[], std::io::empty()).unwrap();
+        let head = builder.into_inner().unwrap();
+
+        let mut archive = Archive::new(head.as_slice());
+        let entry = archive.entries().unwrap().next().unwrap().unwrap();
+        assert_eq!(entry.path().unwrap(), Path::new("sparse"));
+        assert_eq!(entry.size(), size);
+    }
+
+    #[test]
+    fn sparse_file_over_ustar_limit_round_trips() {
+        let layer = TempDir::new().unwrap();
+        let workspace = TempDir::new().unwrap();
+        let size = (8 << 20) + 1;
+        let file = File::create(layer.path().join("sparse")).unwrap();
+        file.set_len(size).unwrap();
+        file.write_all_at(b"end", size - 3).unwrap();
+
+        // An 8 MiB limit stands in for the 8 GiB of the ustar size field, which real files would
+        // take minutes to archive, hash and extract; each test runs on a thread of its own
+        crate::attributes::USTAR_SIZE_LIMIT.set(8 << 20);
+        let (manifest, extracted) = round_trip(layer.path(), &workspace);
+
+        let mut archive = Archive::new(File::open(workspace.path().join("layer.tar")).unwrap());
+        let mut entries = archive.entries().unwrap();
+        let mut entry = entries.find(|entry| entry.as_ref().unwrap().path().unwrap() == Path::new("sparse")).unwrap().unwrap();
+        assert!(entry.header().as_ustar().is_some());
+        assert_eq!(entry.header().size().unwrap(), 0);
+        let records: Vec<(String, String)> = entry.pax_extensions().unwrap().unwrap()
+            .map(|record| {
+                let record = record.unwrap();
+                (record.key().unwrap().to_string(), record.value().unwrap().to_string())
+            })
+            .collect();
+        assert!(records.contains(&("size".to_string(), size.to_string())), "{:?}", records);
+        assert_eq!(entry.size(), size);
+
+        let restored = extracted.join("sparse");
+        assert_eq!(std::fs::metadata(&restored).unwrap().len(), size);
+        let mut tail = [0u8; 3];
+        File::open(&restored).unwrap().read_exact_at(&mut tail, size - 3).unwrap();
+        assert_eq!(&tail, b"end");
+        assert_eq!(manifest.entries["sparse"].size, size);
+    }
+
+    #[test]
+    fn parallel_gzip_is_a_single_standard_stream() {
+        let workspace = TempDir::new().unwrap();
+        let input = workspace.path().join("input.tar");
+        let contents: Vec<u8> = (0..2_500_000u32).map(|index| (index % 7919) as u8).collect();
+        std::fs::write(&input, &contents).unwrap();
+
+        let compressed = workspace.path().join("input.tar.gz");
+        compress_file_parallel(&input, &compressed, 8, &CancellationToken::new()).unwrap();
+        // The serial single-member decoder stops at the first member's end, so it only reads
+        // everything back if the blocks were joined into one stream
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(File::open(&compressed).unwrap()).read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, contents);
+    }
+
+    #[test]
+    fn concatenated_gzip_members_are_all_read() {
+        let workspace = TempDir::new().unwrap();
+        let (first, second) = (workspace.path().join("first"), workspace.path().join("second"));
+        std::fs::write(&first, b"first member\n").unwrap();
+        std::fs::write(&second, vec![b'2'; 100_000]).unwrap();
+
+        // As `cat first.gz second.gz` or a pigz-style tool writing one member per block makes them
+        let mut joined = Vec::new();
+        for (path, threads) in [(&first, 1), (&second, 4)] {
+            let member = workspace.path().join("member.gz");
+            compress_file_parallel(path, &member, threads, &CancellationToken::new()).unwrap();
+            joined.extend(std::fs::read(&member).unwrap());
+        }
+        let joined_path = workspace.path().join("joined.gz");
+        std::fs::write(&joined_path, joined).unwrap();
+
+        let output = workspace.path().join("output");
+        decompress_file(&joined_path, &output, &CancellationToken::new()).unwrap();
+        let mut expected = b"first member\n".to_vec();
+        expected.extend(vec![b'2'; 100_000]);
+        assert_eq!(std::fs::read(&output).unwrap(), expected);
+    }
+
+    /// xorshift64, so fixture trees are random but reproducible from their seed
+    struct FixtureRng(u64);
+
+    impl FixtureRng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, bound: u64) -> u64 {
+            self.next() % bound
+        }
+    }
+
+    /// A random tree of directories, files of random sizes and modes, and symlinks
+    fn populate_random_tree(root: &Path, seed: u64) {
+        let mut rng = FixtureRng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1);
+        let mut directories = vec![root.to_path_buf()];
+        for index in 0..rng.below(60) + 10 {
+            let parent = directories[rng.below(directories.len() as u64) as usize].clone();
+            match rng.below(8) {
+                0 | 1 => {
+                    let dir = parent.join(format!("d{}", index));
+                    std::fs::create_dir(&dir).unwrap();
+                    directories.push(dir);
+                }
+                2 => symlink(format!("f{}", rng.below(index + 1)), parent.join(format!("l{}", index))).unwrap(),
+                _ => {
+                    let path = parent.join(format!("f{}", index));
+                    // Mostly small files, now and then one spanning several read buffers
+                    let size = match rng.below(10) {
+                        0 => rng.below(1 << 20),
+                        _ => rng.below(5000),
+                    };
+                    let contents: Vec<u8> = (0..size).map(|_| rng.next() as u8).collect();
+                    std::fs::write(&path, contents).unwrap();
+                    let mode = [0o644, 0o600, 0o755, 0o4755, 0o1777][rng.below(5) as usize];
+                    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn single_pass_archiving_matches_directory_checksums() {
+        for seed in 0..16 {
+            let layer = TempDir::new().unwrap();
+            let workspace = TempDir::new().unwrap();
+            populate_random_tree(layer.path(), seed);
+
+            let (checksum, manifest, _) = archive_layer(layer.path(), &workspace);
+            assert_eq!(checksum, layer_checksum(layer.path(), CURRENT_CHECKSUM_SCHEME), "seed {}", seed);
+            for (path, entry) in &manifest.entries {
+                if entry.entry_type == EntryType::File {
+                    let digest = calculate_file_digest(layer.path().join(path), ChecksumAlgorithm::Sha256).unwrap();
+                    assert_eq!(entry.digest.as_ref(), Some(&digest), "seed {}: {}", seed, path);
+                }
+            }
+
+            let (_, extracted) = round_trip(layer.path(), &workspace);
+            assert_eq!(layer_checksum(&extracted, CURRENT_CHECKSUM_SCHEME), checksum, "seed {}", seed);
+        }
+    }
+
+    /// A tree of files of varied sizes spread over nested directories
+    fn populate_fixture_tree(root: &Path) {
+        for index in 0..120 {
+            let dir = root.join(format!("dir{}/sub{}", index % 7, index % 3));
+            std::fs::create_dir_all(&dir).unwrap();
+            let contents: Vec<u8> = (0..index * 997).map(|byte| (byte * 31 + index) as u8).collect();
+            std::fs::write(dir.join(format!("file{}", index)), contents).unwrap();
+        }
+    }
+
+    fn with_threads<T: Send>(threads: usize, operation: impl FnOnce() -> T + Send) -> T {
+        rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap().install(operation)
+    }
+
+    #[test]
+    fn parallel_hashing_matches_the_sequential_path() {
+        let layer = TempDir::new().unwrap();
+        let workspace = TempDir::new().unwrap();
+        populate_fixture_tree(layer.path());
+
+        for scheme in [ChecksumScheme::V2, ChecksumScheme::V3, ChecksumScheme::V4] {
+            let sequential = with_threads(1, || layer_checksum(layer.path(), scheme));
+            let parallel = with_threads(8, || layer_checksum(layer.path(), scheme));
+            assert_eq!(sequential, parallel);
+        }
+
+        // Archiving without a filter hashes each file in path order as it is written; with one,
+        // the digests are computed in parallel up front
+        let (checksum, manifest, archive_path) = archive_layer(layer.path(), &workspace);
+        let filtered_path = workspace.path().join("filtered.tar");
+        let (filtered_checksum, filtered_manifest, _) = with_threads(8, || create_tar_archive_filtered(
+            layer.path(), &filtered_path, Some(&|_, _| true), None, LayerArchiveOptions::default(), &CancellationToken::new()
+        )).unwrap();
+        assert_eq!(checksum, layer_checksum(layer.path(), CURRENT_CHECKSUM_SCHEME));
+        assert_eq!(filtered_checksum, checksum);
+        assert_eq!(serde_json::to_string(&filtered_manifest).unwrap(), serde_json::to_string(&manifest).unwrap());
+        assert_eq!(std::fs::read(&filtered_path).unwrap(), std::fs::read(&archive_path).unwrap());
+    }
+
+    #[test]
+    fn parallel_compression_matches_the_sequential_path() {
+        let workspace = TempDir::new().unwrap();
+        let input = workspace.path().join("input.tar");
+        let contents: Vec<u8> = (0..3_000_000u32).map(|index| (index % 251) as u8 ^ (index / 4096) as u8).collect();
+        std::fs::write(&input, &contents).unwrap();
+        let cancel = CancellationToken::new();
+
+        let decompressed = |compressed: &Path| {
+            let output = workspace.path().join("decompressed");
+            decompress_file(compressed, &output, &cancel).unwrap();
+            std::fs::read(&output).unwrap()
+        };
+
+        let sequential = workspace.path().join("sequential.gz");
+        compress_file(&input, &sequential).unwrap();
+        assert_eq!(decompressed(&sequential), contents);
+        for threads in [1, 4] {
+            let parallel = workspace.path().join(format!("parallel-{}.gz", threads));
+            compress_file_parallel(&input, &parallel, threads, &cancel).unwrap();
+            assert_eq!(decompressed(&parallel), contents);
+        }
+
+        for seekable in [false, true] {
+            for threads in [1, 4] {
+                let output = workspace.path().join(format!("{}-{}.zst", seekable, threads));
+                let compression = ExportCompression::Zstd { seekable };
+                stream_file_to_writer(&input, File::create(&output).unwrap(), compression, threads, &cancel).unwrap();
+                assert_eq!(decompressed(&output), contents);
+            }
+        }
+    }
+
+    #[test]
+    fn overlay_whiteouts_delete_files_from_the_target() {
+        if !crate::attributes::running_as_root() {
+            return;
+        }
+        let layer = TempDir::new().unwrap();
+        let workspace = TempDir::new().unwrap();
+        std::fs::create_dir(layer.path().join("etc")).unwrap();
+        std::fs::write(layer.path().join("etc/kept"), b"new").unwrap();
+        let device = CString::new(layer.path().join("etc/removed").into_os_string().into_vec()).unwrap();
+        assert_eq!(unsafe { libc::mknod(device.as_ptr(), libc::S_IFCHR | 0o600, 0) }, 0);
+        let (_, manifest, archive_path) = archive_layer(layer.path(), &workspace);
+        assert!(!manifest.entries.contains_key("etc/removed"));
+        let whiteouts = Archive::new(File::open(&archive_path).unwrap()).entries().unwrap()
+            .filter(|entry| is_overlay_whiteout(entry.as_ref().unwrap()))
+            .count();
+        assert_eq!(whiteouts, 1);
+
+        let target = workspace.path().join("target");
+        std::fs::create_dir_all(target.join("etc")).unwrap();
+        std::fs::write(target.join("etc/removed"), b"old").unwrap();
+        std::fs::write(target.join("etc/untouched"), b"old").unwrap();
+        let mut state = ExtractionState::new(WhiteoutMode::Delete, None);
+        let mismatches = extract_tar_stream_verified(
+            File::open(&archive_path).unwrap(), &target, &manifest, None, &mut state, &mut AttributeRestorer::new(), &CancellationToken::new()
+        ).unwrap();
+        let applied = state.finish();
+
+        assert!(mismatches.is_empty(), "{:?}", mismatches);
+        assert_eq!(applied.count, 1);
+        assert_eq!(applied.deleted, ["etc/removed"]);
+        assert!(!target.join("etc/removed").exists());
+        assert_eq!(std::fs::read(target.join("etc/kept")).unwrap(), b"new");
+        assert!(target.join("etc/untouched").exists());
+    }
+
+    #[test]
+    fn verification_compares_modes_owners_and_types() {
+        if !crate::attributes::running_as_root() {
+            return;
+        }
+        let layer = TempDir::new().unwrap();
+        let workspace = TempDir::new().unwrap();
+        for name in ["chmodded", "chowned", "replaced", "intact"] {
+            std::fs::write(layer.path().join(name), name).unwrap();
+        }
+        let (manifest, extracted) = round_trip(layer.path(), &workspace);
+        let verified = VerifiedAttributes { mode: true, owner_shift: Some(IdOffset::default()) };
+        assert!(verify_directory_against_manifest(&extracted, &manifest, false, verified).unwrap().is_empty());
+
+        std::fs::set_permissions(extracted.join("chmodded"), std::fs::Permissions::from_mode(0o4755)).unwrap();
+        let owner = &manifest.entries["chowned"];
+        std::os::unix::fs::lchown(extracted.join("chowned"), Some(owner.uid.unwrap() + 1), None).unwrap();
+        std::fs::remove_file(extracted.join("replaced")).unwrap();
+        let fifo = CString::new(extracted.join("replaced").into_os_string().into_vec()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o644) }, 0);
+
+        let mismatches = verify_paths_against_manifest(
+            &extracted, &manifest, &["chmodded".into(), "chowned".into(), "replaced".into()], &|_| false, verified
+        ).unwrap();
+        let expected_mode = manifest.entries["chmodded"].mode;
+        let (uid, gid) = (owner.uid.unwrap(), owner.gid.unwrap());
+        assert_eq!(mismatches, [
+            format!("chmodded: mode mismatch (expected {:o}, got 4755)", expected_mode),
+            format!("chowned: owner mismatch (expected {}:{}, got {}:{})", uid, gid, uid + 1, gid),
+            "replaced: expected File, found a special file".to_string(),
+        ]);
+
+        // Without them in `verified`, only the special file is reported
+        let mismatches = verify_directory_against_manifest(&extracted, &manifest, true, VerifiedAttributes::default()).unwrap();
+        assert_eq!(mismatches, ["replaced: expected File, found a special file"]);
+    }
+
+    #[test]
+    fn extraction_compares_archived_modes_with_the_manifest() {
+        let layer = TempDir::new().unwrap();
+        let workspace = TempDir::new().unwrap();
+        std::fs::write(layer.path().join("file"), b"contents").unwrap();
+        std::fs::set_permissions(layer.path().join("file"), std::fs::Permissions::from_mode(0o640)).unwrap();
+        let (_, mut manifest, archive_path) = archive_layer(layer.path(), &workspace);
+        manifest.entries.get_mut("file").unwrap().mode = 0o644;
+
+        let extracted = workspace.path().join("extracted");
+        std::fs::create_dir(&extracted).unwrap();
+        let mismatches = extract_tar_archive_verified(
+            &archive_path, &extracted, &manifest, None, &CancellationToken::new()
+        ).unwrap();
+        assert_eq!(mismatches, ["file: mode mismatch (expected 644, got 640)"]);
+    }
+
+    /// An archive of raw entries, with paths and link names written into the headers unchecked
+    fn malicious_archive(entries: &[(&str, tar::EntryType, &str, &[u8])]) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+        for (path, entry_type, link_name, data) in entries {
+            let mut header = Header::new_old();
+            header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+            header.as_old_mut().linkname[..link_name.len()].copy_from_slice(link_name.as_bytes());
+            header.set_entry_type(*entry_type);
+            header.set_mode(0o644);
+            header.set_uid(0);
+            header.set_gid(0);
+            header.set_mtime(0);
+            header.set_size(data.len() as u64);
+            header.set_cksum();
+            builder.append(&header, *data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    /// Sandbox holding the extraction root and, next to it, a directory the archives aim at
+    fn escape_sandbox() -> (TempDir, PathBuf, PathBuf) {
+        let sandbox = TempDir::new().unwrap();
+        let root = sandbox.path().join("root");
+        let outside = sandbox.path().join("outside");
+        std::fs::create_dir(&root).unwrap();
+        std::fs::create_dir(&outside).unwrap();
+        std::fs::write(outside.join("victim"), b"original").unwrap();
+        (sandbox, root, outside)
+    }
+
+    fn sandbox_contents(sandbox: &Path, root: &Path) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = WalkDir::new(sandbox).into_iter()
+            .map(|entry| entry.unwrap().into_path())
+            .filter(|path| !path.starts_with(root))
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn malicious_archives_write_nothing_outside_the_root() {
+        let (sandbox, root, outside) = escape_sandbox();
+        let absolute = outside.join("absolute");
+        let outside_str = outside.to_str().unwrap();
+        let archives: Vec<Vec<u8>> = vec![
+            malicious_archive(&[("../escaped", tar::EntryType::Regular, "", b"payload")]),
+            malicious_archive(&[("nested/../../escaped", tar::EntryType::Regular, "", b"payload")]),
+            malicious_archive(&[(absolute.to_str().unwrap(), tar::EntryType::Regular, "", b"payload")]),
+            malicious_archive(&[
+                ("link", tar::EntryType::Symlink, outside_str, b""),
+                ("link/victim", tar::EntryType::Regular, "", b"payload"),
+            ]),
+            malicious_archive(&[("link", tar::EntryType::Symlink, "../outside", b"")]),
+            malicious_archive(&[("hard", tar::EntryType::Link, "../outside/victim", b"")]),
+        ];
+
+        for (index, archive) in archives.iter().enumerate() {
+            let before = sandbox_contents(sandbox.path(), &root);
+            let result = extract_tar_archive(archive.as_slice(), &root, &ExtractOptions::default(), &CancellationToken::new());
+            assert!(result.is_err(), "archive {} was extracted", index);
+            assert_eq!(sandbox_contents(sandbox.path(), &root), before, "archive {} wrote outside the root", index);
+            assert_eq!(std::fs::read(outside.join("victim")).unwrap(), b"original");
+            std::fs::remove_dir_all(&root).unwrap();
+            std::fs::create_dir(&root).unwrap();
+        }
+
+        // A directory entry replaces a symlink an earlier entry left at its path instead of
+        // following it out of the root
+        let before = sandbox_contents(sandbox.path(), &root);
+        let archive = malicious_archive(&[
+            ("link", tar::EntryType::Symlink, outside_str, b""),
+            ("link", tar::EntryType::Directory, "", b""),
+            ("link/created", tar::EntryType::Regular, "", b"payload"),
+        ]);
+        extract_tar_archive(archive.as_slice(), &root, &ExtractOptions::default(), &CancellationToken::new()).unwrap();
+        assert!(std::fs::symlink_metadata(root.join("link")).unwrap().is_dir());
+        assert_eq!(std::fs::read(root.join("link/created")).unwrap(), b"payload");
+        assert_eq!(sandbox_contents(sandbox.path(), &root), before);
+    }
+
+    #[test]
+    fn moving_across_filesystems_copies_everything() {
+        let source = TempDir::new().unwrap();
+        let Ok(other_filesystem) = TempDir::new_in("/dev/shm") else {
+            return;
+        };
+        let source_device = std::fs::metadata(source.path()).unwrap().dev();
+        if std::fs::metadata(other_filesystem.path()).unwrap().dev() == source_device {
+            return;
+        }
+
+        let layer = source.path().join("diff");
+        std::fs::create_dir_all(layer.join("usr/bin")).unwrap();
+        std::fs::write(layer.join("usr/bin/tool"), vec![b'x'; 100_000]).unwrap();
+        std::fs::set_permissions(layer.join("usr/bin/tool"), std::fs::Permissions::from_mode(0o4755)).unwrap();
+        std::fs::hard_link(layer.join("usr/bin/tool"), layer.join("usr/bin/alias")).unwrap();
+        symlink("bin/tool", layer.join("usr/link")).unwrap();
+        if crate::attributes::running_as_root() {
+            std::os::unix::fs::chown(layer.join("usr/bin/tool"), Some(1234), Some(1234)).unwrap();
+            make_device_node(&layer.join("usr/removed"), libc::S_IFCHR, 0).unwrap();
+        }
+        let xattrs = xattr::set(layer.join("usr/bin/tool"), "user.origin", b"fixture").is_ok();
+        let before = layer_checksum(&layer, CURRENT_CHECKSUM_SCHEME);
+
+        let destination = other_filesystem.path().join("diff.backup");
+        let progress = ProgressBar::hidden();
+        let failures = move_directory(&layer, &destination, &progress).unwrap();
+        assert!(failures.is_empty(), "{:?}", failures);
+        assert!(!layer.exists());
+        assert_eq!(progress.position(), 100_000);
+
+        assert_eq!(layer_checksum(&destination, CURRENT_CHECKSUM_SCHEME), before);
+        let tool = std::fs::metadata(destination.join("usr/bin/tool")).unwrap();
+        assert_ne!(tool.dev(), source_device);
+        assert_eq!(tool.ino(), std::fs::metadata(destination.join("usr/bin/alias")).unwrap().ino());
+        if crate::attributes::running_as_root() {
+            let whiteout = std::fs::symlink_metadata(destination.join("usr/removed")).unwrap();
+            assert!(whiteout.file_type().is_char_device() && whiteout.rdev() == 0);
+        }
+        if xattrs {
+            let origin = xattr::get(destination.join("usr/bin/tool"), "user.origin").unwrap();
+            assert_eq!(origin.as_deref(), Some(&b"fixture"[..]));
+        }
+    }
+
+    fn path_violation(error: &anyhow::Error) -> &PathViolation {
+        error.chain()
+            .find_map(|cause| cause.downcast_ref::<PathViolation>())
+            .unwrap_or_else(|| panic!("not a path violation: {:#}", error))
+    }
+
+    #[test]
+    fn validate_file_path_refuses_escaping_paths() {
+        for path in ["etc/passwd", "usr/lib/..data", "a/./b", "name..with..dots"] {
+            validate_file_path(path).unwrap();
+        }
+        for (path, reason) in [
+            ("../etc/cron.d/x", PathViolationReason::ParentDirectory),
+            ("usr/../../etc/x", PathViolationReason::ParentDirectory),
+            ("usr\\..\\..\\x", PathViolationReason::ParentDirectory),
+            ("/etc/cron.d/x", PathViolationReason::Absolute),
+            ("C:\\Windows\\x", PathViolationReason::Absolute),
+            ("\\\\server\\share\\x", PathViolationReason::Absolute),
+        ] {
+            let error = validate_file_path(path).unwrap_err();
+            let violation = path_violation(&error);
+            assert_eq!((violation.entry.as_path(), violation.reason), (Path::new(path), reason));
+        }
+    }
+
+    #[test]
+    fn malicious_entries_fail_with_the_violation_they_commit() {
+        let (_sandbox, root, outside) = escape_sandbox();
+        let outside_str = outside.to_str().unwrap();
+        let absolute = outside.join("absolute");
+        let cases = [
+            (malicious_archive(&[("../escaped", tar::EntryType::Regular, "", b"payload")]), "../escaped", PathViolationReason::ParentDirectory),
+            (malicious_archive(&[(absolute.to_str().unwrap(), tar::EntryType::Regular, "", b"payload")]), absolute.to_str().unwrap(), PathViolationReason::Absolute),
+            (malicious_archive(&[
+                ("link", tar::EntryType::Symlink, outside_str, b""),
+                ("link/victim", tar::EntryType::Regular, "", b"payload"),
+            ]), "link/victim", PathViolationReason::SymlinkEscape),
+            (malicious_archive(&[("link", tar::EntryType::Symlink, "../outside", b"")]), "link", PathViolationReason::LinkTarget),
+            (malicious_archive(&[("hard", tar::EntryType::Link, "../outside/victim", b"")]), "hard", PathViolationReason::LinkTarget),
+        ];
+
+        for (archive, entry, reason) in cases {
+            let error = extract_tar_archive(archive.as_slice(), &root, &ExtractOptions::default(), &CancellationToken::new())
+                .unwrap_err();
+            let violation = path_violation(&error);
+            assert_eq!((violation.entry.as_path(), violation.reason), (Path::new(entry), reason));
+            assert_eq!(std::fs::read(outside.join("victim")).unwrap(), b"original");
+            std::fs::remove_dir_all(&root).unwrap();
+            std::fs::create_dir(&root).unwrap();
+        }
+    }
+}
@@ -0,0 +1,881 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use tar::Archive;
+
+use crate::attributes::AttributeRestorer;
+use crate::cancel::CancellationToken;
+use crate::output::*;
+use crate::stream::{diagnose_export_damage, extract_export_mounts, LayerFindings, LayerSizes, LayerWhiteouts, open_export_archive, scan_layer_stream, with_layer_stream, LAYER_MEMBER};
+use crate::types::{ArchiveFormat, Checksum, ChecksumAlgorithm, ChecksumFormat, ChecksumScheme, ExportDataV2, ExportMetadata, ExtractOptions, FormatSupport, FormatVersion, Manifest, MountExport, OverwritePolicy, WhiteoutMode, structured_checksum, CURRENT_FORMAT_VERSION};
+use crate::utils::{
+    calculate_directory_checksum, calculate_directory_checksum_with_progress, calculate_file_digest, decompress_file, get_file_size, extract_layer_stream, extract_tar_archive, export_format,
+    load_manifest, parse_manifest, ExtractionState, StreamingDirectoryChecksum
+};
+
+/// Directory of the export archive holding the contents of included mounts
+pub const MOUNTS_DIR: &str = "mounts";
+
+/// Directory an included mount is stored in, relative to the extracted export archive
+pub fn mount_dir(extract_dir: &Path, index: usize) -> PathBuf {
+    extract_dir.join(MOUNTS_DIR).join(index.to_string())
+}
+
+/// Exit code of `import --verify-only` when the export is usable but raised warnings
+pub const VERIFY_WARNINGS_EXIT_CODE: i32 = 1;
+
+/// Exit code of `import --verify-only` when the export cannot be imported into the target
+pub const VERIFY_ERRORS_EXIT_CODE: i32 = 2;
+
+/// Result of a verification that did not pass cleanly, carrying the process exit code
+#[derive(Debug)]
+pub struct VerifyFailure {
+    pub exit_code: i32,
+    pub message: String,
+}
+
+impl std::fmt::Display for VerifyFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for VerifyFailure {}
+
+/// Exit code of a check or import that found the export damaged: truncated, corrupt, or not
+/// matching its recorded checksums
+pub const INTEGRITY_EXIT_CODE: i32 = 4;
+
+/// An export whose contents are not what was written at export time
+#[derive(Debug)]
+pub struct IntegrityFailure {
+    pub message: String,
+}
+
+impl std::fmt::Display for IntegrityFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for IntegrityFailure {}
+
+/// Replace an error verifying the export at `export_path` with a plain description of the damage,
+/// if reading the whole file shows it is truncated or corrupt
+fn diagnose_damage(error: anyhow::Error, export_path: &Path, cancel: &CancellationToken) -> anyhow::Error {
+    if cancel.is_cancelled() || error.chain().any(|cause| cause.is::<IntegrityFailure>()) {
+        return error;
+    }
+    print_progress("Reading the export file to locate the damage...");
+    match diagnose_export_damage(export_path, cancel) {
+        Ok(Some(message)) => IntegrityFailure { message }.into(),
+        _ => error,
+    }
+}
+
+/// An export file that passed structural, metadata, and layer checksum validation
+pub struct VerifiedExport {
+    pub export_data: ExportDataV2,
+    pub manifest: Option<Manifest>,
+    /// Format of the export file
+    pub format: ArchiveFormat,
+    /// Size of layer.tar
+    pub layer_size: u64,
+    /// Whether the layer checksum was recomputed, rather than left to the archive checksum
+    pub layer_checksum_verified: bool,
+    /// Where the layer was extracted to; only `deep` verification extracts it
+    pub layer_dir: Option<PathBuf>,
+    /// The largest files of the layer and the bytes under its top-level directories
+    pub sizes: LayerSizes,
+    pub whiteouts: LayerWhiteouts,
+    pub findings: LayerFindings,
+}
+
+/// Validate an export file: archive structure, metadata, layer archive readability and checksum,
+/// manifest checksum, and mounts. With `deep`, the export is unpacked into `workspace` and the
+/// layer extracted to recompute the recorded layer checksum; otherwise the export is read in a
+/// single streaming pass that writes no layer content to disk. The `top` largest layer files are
+/// tracked along the way.
+/// This is the pipeline shared by `check`, `export --verify` and `import --verify-only`.
+pub fn verify_export_file(input_path: &Path, workspace: &Path, deep: bool, top: usize, cancel: &CancellationToken) -> Result<VerifiedExport> {
+    let verified = if deep {
+        verify_unpacked_export(input_path, workspace, top, cancel)
+    } else {
+        verify_export_stream(input_path, workspace, top, cancel)
+    };
+    verified.map_err(|error| diagnose_damage(error, input_path, cancel))
+}
+
+/// Validate an export unpacked into `workspace`, with its layer extracted to recompute the layer checksum
+fn verify_unpacked_export(input_path: &Path, workspace: &Path, top: usize, cancel: &CancellationToken) -> Result<VerifiedExport> {
+    // Handle decompression if needed
+    let format = export_format(input_path)?;
+    let export_tar_path = if format.is_compressed() {
+        print_check_result("File compression", &format!("✓ Compressed ({})", format), true);
+        let decompressed_path = workspace.join("export.tar");
+        decompress_file(input_path, &decompressed_path, cancel)
+            .context("Failed to decompress input file")?;
+        decompressed_path
+    } else {
+        print_check_result("File compression", "✓ Uncompressed", true);
+        input_path.to_path_buf()
+    };
+
+    // Extract and validate archive structure
+    print_progress("Checking archive structure...");
+    let extract_dir = workspace.join("extracted");
+    std::fs::create_dir_all(&extract_dir)
+        .context("Failed to create extraction directory")?;
+
+    extract_and_validate_structure(&export_tar_path, &extract_dir, cancel)
+        .context("Failed to validate archive structure")?;
+
+    // Read and validate metadata
+    print_progress("Validating metadata...");
+    let metadata_path = extract_dir.join("metadata.json");
+    let metadata_content = std::fs::read(&metadata_path)
+        .context("Failed to read metadata file")?;
+    let export_data = validate_metadata(&metadata_content)
+        .context("Failed to validate metadata")?;
+
+    // Validate layer archive
+    print_progress("Validating layer archive...");
+    let layer_tar_path = extract_dir.join("layer.tar");
+    let (sizes, whiteouts, findings) = validate_layer_archive(&layer_tar_path, top)
+        .context("Failed to validate layer archive")?;
+    reject_path_traversals(&findings)?;
+    let archive_algorithm = export_data.layer_archive_checksum.as_ref()
+        .map_or(export_data.layer_checksum.algorithm, |checksum| checksum.algorithm);
+    let archive_checksum = calculate_file_digest(&layer_tar_path, archive_algorithm)
+        .context("Failed to calculate layer archive checksum")?;
+    verify_layer_archive_checksum(&export_data, &archive_checksum)?;
+
+    // Validate the per-file manifest
+    let manifest = load_manifest(extract_dir.join("manifest.json"), export_data.manifest_checksum())
+        .context("Failed to validate layer manifest")?;
+    match &manifest {
+        Some(manifest) => print_check_result("Manifest", &format!("✓ Valid ({} entries)", manifest.entries.len()), true),
+        None => print_check_result("Manifest", "⏭ Not present (created by an older version)", false),
+    }
+
+    let layer_dir = extract_and_verify_layer(&layer_tar_path, workspace, &export_data, cancel)?;
+    verify_mount_checksums(&extract_dir, &export_data)?;
+
+    Ok(VerifiedExport {
+        export_data,
+        manifest,
+        format,
+        layer_size: get_file_size(&layer_tar_path)?,
+        layer_checksum_verified: true,
+        layer_dir: Some(layer_dir),
+        sizes,
+        whiteouts,
+        findings,
+    })
+}
+
+/// Validate an export in one pass over its (decompressed on the fly) archive: metadata and the
+/// manifest are read into memory, layer.tar is hashed and its entries counted as they stream past,
+/// and mounts are hashed the same way. The layer checksum is recomputed from the stream where its
+/// layout allows; only layers that do not, from exports recording no archive checksum to rely on,
+/// and mounts the stream cannot hash are extracted into `workspace`.
+fn verify_export_stream(input_path: &Path, workspace: &Path, top: usize, cancel: &CancellationToken) -> Result<VerifiedExport> {
+    let format = export_format(input_path)?;
+    if format.is_compressed() {
+        print_check_result("File compression", &format!("✓ Compressed ({})", format), true);
+    } else {
+        print_check_result("File compression", "✓ Uncompressed", true);
+    }
+
+    print_progress("Checking archive structure...");
+    let mut metadata_content = None;
+    let mut manifest_content = None;
+    let mut layer = None;
+    let mut mount_checksums: BTreeMap<usize, StreamingDirectoryChecksum> = BTreeMap::new();
+
+    let progress = byte_progress_bar("Scanning");
+    let mut archive = open_export_archive(input_path, cancel)?;
+    for entry in archive.entries().context("Failed to read export archive entries")? {
+        let mut entry = entry.context("Failed to read export archive entry")?;
+        let path = entry.path().context("Failed to read export entry path")?.into_owned();
+        match path.to_str() {
+            Some("metadata.json") => metadata_content = Some(read_entry(&mut entry, "metadata.json")?),
+            Some("manifest.json") => manifest_content = Some(read_entry(&mut entry, "manifest.json")?),
+            Some(LAYER_MEMBER) => {
+                let layer_size = entry.size();
+                progress.set_length(layer_size);
+                let (scheme, algorithm) = metadata_checksum_format(metadata_content.as_deref());
+                let scan = scan_layer_stream(progress.wrap_read(&mut entry), None, scheme, algorithm, top, cancel)
+                    .context("Failed to validate layer archive")?;
+                layer = Some((layer_size, scan));
+            }
+            _ => {
+                // mounts/<index>/<path inside the mount>
+                let Ok(mount_path) = path.strip_prefix(MOUNTS_DIR) else { continue };
+                let mut components = mount_path.components();
+                let Some(index) = components.next().and_then(|index| index.as_os_str().to_str()?.parse::<usize>().ok()) else {
+                    continue;
+                };
+                let (scheme, algorithm) = metadata_checksum_format(metadata_content.as_deref());
+                let checksum = mount_checksums.entry(index).or_insert_with(|| StreamingDirectoryChecksum::new(scheme, algorithm));
+                let relative = components.as_path().to_path_buf();
+                if !relative.as_os_str().is_empty() {
+                    checksum.add_entry(&relative, &mut entry)
+                        .context("Failed to read mount from export archive")?;
+                }
+            }
+        }
+    }
+    finish_progress(&progress);
+    // Read to the end of the file too, so a gzip trailer's CRC and size are checked
+    std::io::copy(&mut archive.into_inner(), &mut std::io::sink())
+        .context("Failed to read export archive")?;
+
+    let metadata_content = metadata_content.ok_or_else(|| anyhow::anyhow!("Missing metadata.json in export archive"))
+        .context("Failed to validate archive structure")?;
+    let (layer_size, layer_scan) = layer.ok_or_else(|| anyhow::anyhow!("Missing layer.tar in export archive"))
+        .context("Failed to validate archive structure")?;
+    print_check_result("Archive structure", "✓ Valid", true);
+
+    print_progress("Validating metadata...");
+    let export_data = validate_metadata(&metadata_content)
+        .context("Failed to validate metadata")?;
+
+    print_check_result("Layer archive", &format!("✓ Readable ({} entries)", layer_scan.entry_count), true);
+    print_layer_format(layer_scan.nonstandard_entries);
+    reject_path_traversals(&layer_scan.findings)?;
+    verify_layer_archive_checksum(&export_data, &layer_scan.archive_checksum)?;
+
+    let manifest = parse_manifest(manifest_content.as_deref(), export_data.manifest_checksum())
+        .context("Failed to validate layer manifest")?;
+    match &manifest {
+        Some(manifest) => print_check_result("Manifest", &format!("✓ Valid ({} entries)", manifest.entries.len()), true),
+        None => print_check_result("Manifest", "⏭ Not present (created by an older version)", false),
+    }
+
+    // The archive checksum already covers every byte of the layer, so the layer is only extracted
+    // to recompute its checksum when there is no archive checksum to rely on
+    let layer_checksum_verified = match layer_scan.layer_checksum.finish(export_data.checksum_scheme) {
+        Some(checksum) => {
+            verify_layer_checksum(&export_data, || Ok(checksum))?;
+            export_data.incremental.is_none()
+        }
+        None if export_data.layer_archive_checksum.is_none() => {
+            verify_layer_checksum(&export_data, || extracted_layer_checksum(input_path, workspace, &export_data, cancel))?;
+            export_data.incremental.is_none()
+        }
+        None => false,
+    };
+
+    verify_streamed_mount_checksums(input_path, workspace, &export_data, mount_checksums, cancel)?;
+
+    Ok(VerifiedExport {
+        export_data,
+        manifest,
+        format,
+        layer_size,
+        layer_checksum_verified,
+        layer_dir: None,
+        sizes: layer_scan.sizes,
+        whiteouts: layer_scan.whiteouts,
+        findings: layer_scan.findings,
+    })
+}
+
+fn read_entry<R: Read>(entry: &mut R, name: &str) -> Result<Vec<u8>> {
+    let mut content = Vec::new();
+    entry.read_to_end(&mut content)
+        .with_context(|| format!("Failed to read {} from export", name))?;
+    Ok(content)
+}
+
+/// Checksum of the export's layer, extracted into `workspace` for layouts the stream cannot hash
+fn extracted_layer_checksum(export_path: &Path, workspace: &Path, export_data: &ExportDataV2, cancel: &CancellationToken) -> Result<Checksum> {
+    let layer_dir = workspace.join("precheck-layer");
+    std::fs::create_dir_all(&layer_dir)
+        .context("Failed to create precheck directory")?;
+    with_layer_stream(export_path, cancel, |layer| {
+        extract_layer_for_checksum(layer, &layer_dir, cancel)
+    })
+        .context("Failed to extract layer archive")?;
+    cancel.check()?;
+    let checksum = calculate_directory_checksum(&layer_dir, ChecksumFormat::new(export_data.checksum_scheme, export_data.layer_checksum.algorithm))
+        .context("Failed to calculate layer checksum")?;
+    std::fs::remove_dir_all(&layer_dir)
+        .context("Failed to remove precheck directory")?;
+    Ok(checksum)
+}
+
+/// Compare the mount checksums computed from the stream with the recorded ones, extracting the
+/// mounts into `workspace` instead if any could not be hashed from the stream
+fn verify_streamed_mount_checksums(
+    export_path: &Path,
+    workspace: &Path,
+    export_data: &ExportDataV2,
+    mount_checksums: BTreeMap<usize, StreamingDirectoryChecksum>,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let mount_checksums: BTreeMap<usize, Option<Checksum>> = mount_checksums.into_iter()
+        .map(|(index, checksum)| (index, checksum.finish(export_data.checksum_scheme)))
+        .collect();
+    if mount_checksums.values().all(Option::is_some) {
+        for mount in &export_data.mounts {
+            let calculated_checksum = mount_checksums.get(&mount.index)
+                .cloned()
+                .flatten()
+                .ok_or_else(|| missing_mount(mount))?;
+            compare_mount_checksum(mount, &calculated_checksum)?;
+        }
+    } else {
+        let mounts_dir = workspace.join("precheck-mounts");
+        extract_export_mounts(export_path, &mounts_dir, cancel)?;
+        verify_mount_checksums(&mounts_dir, export_data)?;
+        std::fs::remove_dir_all(&mounts_dir)
+            .context("Failed to remove precheck directory")?;
+    }
+    Ok(())
+}
+
+/// Verify an export by streaming it, before anything outside `workspace` is modified: the layer
+/// archive is read once to recompute the layer checksum (or, for incremental exports, to check
+/// every entry against the manifest) and mounts are hashed as they pass, without unpacking either.
+/// Only archives whose layout the stream cannot account for are extracted into `workspace`.
+pub fn precheck_export_stream(
+    export_path: &Path,
+    workspace: &Path,
+    export_data: &ExportDataV2,
+    manifest: Option<&Manifest>,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    precheck_export(export_path, workspace, export_data, manifest, cancel)
+        .map_err(|error| diagnose_damage(error, export_path, cancel))
+}
+
+fn precheck_export(
+    export_path: &Path,
+    workspace: &Path,
+    export_data: &ExportDataV2,
+    manifest: Option<&Manifest>,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let layer_manifest = manifest.filter(|_| export_data.incremental.is_some());
+    let mut layer_scan = None;
+    let mut mount_checksums: BTreeMap<usize, StreamingDirectoryChecksum> = BTreeMap::new();
+
+    let mut archive = open_export_archive(export_path, cancel)?;
+    for entry in archive.entries().context("Failed to read export archive entries")? {
+        let mut entry = entry.context("Failed to read export archive entry")?;
+        let path = entry.path().context("Failed to read export entry path")?.into_owned();
+        if path.as_os_str() == LAYER_MEMBER {
+            layer_scan = Some(scan_layer_stream(
+                &mut entry, layer_manifest, Some(export_data.checksum_scheme), export_data.layer_checksum.algorithm, 0, cancel
+            )
+                .context("Failed to validate layer archive")?);
+        } else if let Ok(mount_path) = path.strip_prefix(MOUNTS_DIR) {
+            // mounts/<index>/<path inside the mount>
+            let mut components = mount_path.components();
+            let Some(index) = components.next().and_then(|index| index.as_os_str().to_str()?.parse::<usize>().ok()) else {
+                continue;
+            };
+            let checksum = mount_checksums.entry(index)
+                .or_insert_with(|| StreamingDirectoryChecksum::new(Some(export_data.checksum_scheme), export_data.layer_checksum.algorithm));
+            let relative = components.as_path().to_path_buf();
+            if !relative.as_os_str().is_empty() {
+                checksum.add_entry(&relative, &mut entry)
+                    .context("Failed to read mount from export archive")?;
+            }
+        }
+    }
+
+    let layer_scan = layer_scan.ok_or_else(|| anyhow::anyhow!("Layer archive not found in export"))?;
+    print_check_result("Layer archive", &format!("✓ Readable ({} entries)", layer_scan.entry_count), true);
+    print_layer_format(layer_scan.nonstandard_entries);
+    reject_path_traversals(&layer_scan.findings)?;
+    verify_layer_archive_checksum(export_data, &layer_scan.archive_checksum)?;
+
+    if layer_manifest.is_some() {
+        if !layer_scan.mismatches.is_empty() {
+            print_errors_section(&layer_scan.mismatches);
+            return Err(anyhow::anyhow!("{} path(s) in the layer archive do not match the manifest", layer_scan.mismatches.len()));
+        }
+        print_check_result("Layer entries", "✓ Match the manifest", true);
+    } else {
+        verify_layer_checksum(export_data, || match layer_scan.layer_checksum.finish(export_data.checksum_scheme) {
+            Some(checksum) => Ok(checksum),
+            None => extracted_layer_checksum(export_path, workspace, export_data, cancel),
+        })?;
+    }
+
+    verify_streamed_mount_checksums(export_path, workspace, export_data, mount_checksums, cancel)
+}
+
+/// Extract archive and validate basic structure
+fn extract_and_validate_structure(archive_path: &Path, output_dir: &Path, cancel: &CancellationToken) -> Result<()> {
+    let archive_file = File::open(archive_path)
+        .context("Failed to open export archive")?;
+
+    // Mounts keep their owners and modes, which their checksums cover; an export never holds a
+    // path twice, so a repeated one is refused rather than silently replacing the first
+    let options = ExtractOptions { overwrite: OverwritePolicy::Error, ..ExtractOptions::default() };
+    extract_tar_archive(archive_file, output_dir, &options, cancel)
+        .context("Failed to extract export archive")?;
+
+    // Check required files exist
+    let metadata_path = output_dir.join("metadata.json");
+    let layer_tar_path = output_dir.join("layer.tar");
+
+    if !metadata_path.exists() {
+        return Err(anyhow::anyhow!("Missing metadata.json in export archive"));
+    }
+
+    if !layer_tar_path.exists() {
+        return Err(anyhow::anyhow!("Missing layer.tar in export archive"));
+    }
+
+    print_check_result("Archive structure", "✓ Valid", true);
+    Ok(())
+}
+
+/// The version field of metadata.json, read on its own so a file of a newer format is recognized
+/// before the rest of it fails to parse
+#[derive(Deserialize)]
+struct VersionedMetadata {
+    version: FormatVersion,
+    #[serde(default)]
+    checksum_scheme: ChecksumScheme,
+    /// Kept unparsed, so a malformed checksum does not hide the version; a string in format 1
+    /// and an object in format 2
+    #[serde(default)]
+    layer_checksum: Option<serde_json::Value>,
+}
+
+/// The checksum scheme metadata.json records (None if it cannot be read) and the algorithm of its
+/// layer checksum, read ahead of the rest of it; SHA256 until metadata.json was read
+fn metadata_checksum_format(metadata_content: Option<&[u8]>) -> (Option<ChecksumScheme>, ChecksumAlgorithm) {
+    let metadata = metadata_content.and_then(|content| serde_json::from_slice::<VersionedMetadata>(content).ok());
+    let algorithm = metadata.as_ref()
+        .and_then(|metadata| match metadata.layer_checksum.clone()? {
+            serde_json::Value::String(checksum) => checksum.parse::<Checksum>().ok(),
+            checksum => structured_checksum::deserialize(checksum).ok(),
+        })
+        .map_or_else(ChecksumAlgorithm::default, |checksum| checksum.algorithm);
+    (metadata.map(|metadata| metadata.checksum_scheme), algorithm)
+}
+
+/// Parse the contents of metadata.json in either format into the shape of format 2, refusing files
+/// of a newer major format version and warning about a newer minor version, whose additions this
+/// release ignores
+pub fn parse_export_metadata(metadata_content: &[u8]) -> Result<ExportDataV2> {
+    let versioned: VersionedMetadata = serde_json::from_slice(metadata_content)
+        .context("Failed to read the format version from metadata JSON")?;
+    let version = versioned.version;
+    match version.support() {
+        FormatSupport::Supported => {}
+        FormatSupport::NewerMinor => print_warning(&format!(
+            "Export format version {} is newer than this layer-tool supports ({}); what the newer version adds is ignored",
+            version, CURRENT_FORMAT_VERSION
+        )),
+        FormatSupport::NewerMajor => return Err(anyhow::anyhow!(
+            "Export format version {} is not supported (this layer-tool reads up to {}.x): this file requires layer-tool >= {}.0",
+            version, CURRENT_FORMAT_VERSION.major(), version.major()
+        )),
+    }
+
+    ExportMetadata::parse(metadata_content, version)
+        .map(ExportMetadata::normalize)
+        .with_context(|| format!("Failed to parse metadata JSON (format version {})", version))
+}
+
+/// Parse and validate the contents of metadata.json
+fn validate_metadata(metadata_content: &[u8]) -> Result<ExportDataV2> {
+    let export_data = parse_export_metadata(metadata_content)?;
+
+    // Validate required fields
+
+    if export_data.container_metadata.id.is_empty() {
+        return Err(anyhow::anyhow!("Missing or empty container ID in metadata"));
+    }
+
+    if export_data.container_metadata.image_sha256.is_empty() {
+        return Err(anyhow::anyhow!("Missing or empty image SHA256 in metadata"));
+    }
+
+    if export_data.layer_checksum.hex.is_empty() {
+        return Err(anyhow::anyhow!("Missing or empty layer checksum in metadata"));
+    }
+
+    print_check_result("Metadata", "✓ Valid", true);
+    print_metadata_item("Version", &export_data.version.to_string());
+    print_check_result("Layer kind", &export_data.layer_kind.to_string(), true);
+    print_container_info("Container", &export_data.container_metadata.name, &export_data.container_metadata.id);
+    print_metadata_item("Image", &export_data.container_metadata.image);
+
+    Ok(export_data)
+}
+
+/// Validate layer archive integrity, tracking the `top` largest files, the whiteouts and the
+/// suspicious entries
+fn validate_layer_archive(layer_tar_path: &Path, top: usize) -> Result<(LayerSizes, LayerWhiteouts, LayerFindings)> {
+    // Check if layer tar file can be opened
+    let layer_file = File::open(layer_tar_path)
+        .context("Failed to open layer archive")?;
+    let mut layer_archive = Archive::new(layer_file);
+
+    // Try to list entries to validate tar structure
+    let entries = layer_archive.entries()
+        .context("Failed to read layer archive entries")?;
+
+    let mut entry_count = 0;
+    let mut nonstandard_entries = 0;
+    let mut sizes = LayerSizes::new(top);
+    let mut whiteouts = LayerWhiteouts::default();
+    let mut findings = LayerFindings::default();
+    for entry in entries {
+        let entry = entry.context("Failed to read layer archive entry")?;
+        entry_count += 1;
+        if entry.header().as_ustar().is_none() {
+            nonstandard_entries += 1;
+        }
+        let entry_path = entry.path().context("Failed to read tar entry path")?.into_owned();
+        sizes.add_entry(entry.header().entry_type(), entry.size());
+        if !findings.add(&entry, &entry_path) {
+            continue;
+        }
+        if !whiteouts.add(&entry, &entry_path) && entry.header().entry_type().is_file() {
+            sizes.add_file(&entry_path.to_string_lossy(), entry.size());
+        }
+    }
+
+    print_check_result("Layer archive", &format!("✓ Readable ({} entries)", entry_count), true);
+    print_layer_format(nonstandard_entries);
+    Ok((sizes, whiteouts, findings))
+}
+
+/// Report whether every layer entry has a POSIX ustar header, extended only through PAX records.
+/// Exports from older versions use GNU headers, which not every tar implementation reads.
+fn print_layer_format(nonstandard_entries: usize) {
+    if nonstandard_entries == 0 {
+        print_check_result("Layer format", "✓ POSIX ustar/PAX", true);
+    } else {
+        print_check_result("Layer format", &format!("⚠ {} entr(ies) use non-POSIX (GNU) tar headers", nonstandard_entries), false);
+    }
+}
+
+/// Fail the integrity check if any layer entry would be written outside the layer
+fn reject_path_traversals(findings: &LayerFindings) -> Result<()> {
+    let traversals: Vec<String> = findings.path_traversals()
+        .map(|finding| format!("{}: {}", finding.path, finding.detail))
+        .collect();
+    if traversals.is_empty() {
+        return Ok(());
+    }
+    print_check_result("Layer paths", &format!("✗ {} entr(ies) outside the layer root", traversals.len()), false);
+    print_errors_section(&traversals);
+    Err(IntegrityFailure {
+        message: format!(
+            "Layer integrity check FAILED: {} layer entr(ies) would be written outside the layer: {}",
+            traversals.len(), traversals.join(", ")
+        ),
+    }.into())
+}
+
+/// Compare the checksum of layer.tar with the one recorded at export time, if the export has one
+fn verify_layer_archive_checksum(export_data: &ExportDataV2, archive_checksum: &Checksum) -> Result<()> {
+    let Some(expected) = &export_data.layer_archive_checksum else {
+        print_checksum("Layer archive checksum calculated", &archive_checksum.to_string());
+        print_check_result("Layer archive checksum", "⏭ Not recorded (created by an older version)", false);
+        return Ok(());
+    };
+    if archive_checksum != expected {
+        return Err(IntegrityFailure {
+            message: format!("Layer integrity check FAILED: layer.tar checksum mismatch: expected {}, got {}", expected, archive_checksum),
+        }.into());
+    }
+    print_check_result("Layer archive checksum", &format!("✓ Matches {}", expected), true);
+    Ok(())
+}
+
+/// Extract layer.tar into `workspace` and compare the checksum of the extracted layer with the
+/// recorded one, with progress bars for both. Returns the directory the layer was extracted to.
+fn extract_and_verify_layer(layer_tar_path: &Path, workspace: &Path, export_data: &ExportDataV2, cancel: &CancellationToken) -> Result<PathBuf> {
+    print_progress("Verifying layer checksum...");
+    let layer_dir = workspace.join("layer");
+    std::fs::create_dir_all(&layer_dir)
+        .context("Failed to create layer extraction directory")?;
+    let layer_tar = File::open(layer_tar_path)
+        .with_context(|| format!("Failed to open tar file: {:?}", layer_tar_path))?;
+    let progress = byte_progress_bar("Extracting");
+    progress.set_length(get_file_size(layer_tar_path)?);
+    extract_layer_for_checksum(progress.wrap_read(layer_tar), &layer_dir, cancel)
+        .context("Failed to extract layer archive")?;
+    finish_progress(&progress);
+    cancel.check()?;
+    verify_layer_checksum(export_data, || {
+        let progress = byte_progress_bar("Hashing");
+        let checksum = calculate_directory_checksum_with_progress(
+            &layer_dir, ChecksumFormat::new(export_data.checksum_scheme, export_data.layer_checksum.algorithm), &progress
+        )
+            .context("Failed to calculate layer checksum");
+        finish_progress(&progress);
+        checksum
+    })?;
+    Ok(layer_dir)
+}
+
+/// Extract a layer archive as an import into a plain directory would, whiteouts applied, so the
+/// directory checksum describes the layer rather than its archive
+fn extract_layer_for_checksum<R: Read>(layer: R, layer_dir: &Path, cancel: &CancellationToken) -> Result<()> {
+    let mut state = ExtractionState::new(WhiteoutMode::Delete, None);
+    let mut attributes = AttributeRestorer::new();
+    extract_layer_stream(layer, layer_dir, None, &mut state, &mut attributes, cancel)?;
+    attributes.finish()?;
+    Ok(())
+}
+
+/// Compare the checksum of the layer, calculated on demand, with the one recorded at export time
+fn verify_layer_checksum(export_data: &ExportDataV2, calculate: impl FnOnce() -> Result<Checksum>) -> Result<()> {
+    // Incremental exports only carry changed entries; their checksum covers the whole layer
+    // and can only be verified once applied on top of the parent
+    if export_data.incremental.is_some() {
+        print_check_result("Layer checksum", "⏭ Incremental export (verified against the parent layer on import)", false);
+        return Ok(());
+    }
+
+    let calculated_checksum = calculate()?;
+    if calculated_checksum != export_data.layer_checksum {
+        return Err(IntegrityFailure {
+            message: format!(
+                "Layer integrity check FAILED: layer checksum mismatch: expected {}, got {}",
+                export_data.layer_checksum, calculated_checksum
+            ),
+        }.into());
+    }
+
+    print_check_result("Layer checksum", &format!("✓ Matches {}", export_data.layer_checksum), true);
+    Ok(())
+}
+
+/// Compare the contents of every included mount with the checksum recorded at export time
+pub fn verify_mount_checksums(extract_dir: &Path, export_data: &ExportDataV2) -> Result<()> {
+    for mount in &export_data.mounts {
+        let directory = mount_dir(extract_dir, mount.index);
+        if !directory.is_dir() {
+            return Err(missing_mount(mount));
+        }
+
+        let calculated_checksum = calculate_directory_checksum(&directory, ChecksumFormat::new(export_data.checksum_scheme, mount.checksum.algorithm))
+            .with_context(|| format!("Failed to calculate checksum of mount {}", mount.destination))?;
+        compare_mount_checksum(mount, &calculated_checksum)?;
+    }
+
+    Ok(())
+}
+
+fn missing_mount(mount: &MountExport) -> anyhow::Error {
+    anyhow::anyhow!(
+        "Mount {} ({}) is recorded in the metadata but missing from the export archive",
+        mount.index,
+        mount.destination
+    )
+}
+
+fn compare_mount_checksum(mount: &MountExport, calculated_checksum: &Checksum) -> Result<()> {
+    if *calculated_checksum != mount.checksum {
+        return Err(IntegrityFailure {
+            message: format!("Mount checksum mismatch for {}: expected {}, got {}", mount.destination, mount.checksum, calculated_checksum),
+        }.into());
+    }
+
+    print_check_result(&format!("Mount {}", mount.destination), &format!("✓ Matches {}", mount.checksum), true);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::{enter_output, CaptureSink, Level, OutputSink};
+    use crate::testing::{fixture_export_data, METADATA_V1};
+    use crate::types::{ChecksumAlgorithm, ExportFormatVersion, LayerArchiveOptions, LayerKind, MANIFEST_FILE};
+    use crate::utils::create_tar_archive_filtered;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    /// Metadata as export builds it for `format_version` and writes it out
+    fn written_metadata(format_version: ExportFormatVersion) -> String {
+        let mut export_data = fixture_export_data();
+        export_data.version = format_version.format_version();
+        let metadata = match format_version {
+            ExportFormatVersion::V1 => ExportMetadata::V1(export_data),
+            ExportFormatVersion::V2 => ExportMetadata::V2(export_data.into()),
+        };
+        serde_json::to_string_pretty(&metadata).unwrap()
+    }
+
+    #[test]
+    fn format_1_fixture_normalizes_to_format_2() {
+        let export_data = parse_export_metadata(METADATA_V1.as_bytes()).unwrap();
+        assert_eq!(export_data.version, FormatVersion::V1_4);
+        assert_eq!(export_data.container_metadata.name, "web");
+        assert_eq!(export_data.environment.driver, "overlay2");
+        assert_eq!(export_data.environment.os, "Ubuntu 24.04.2 LTS");
+        assert_eq!(export_data.environment.kernel, "6.8.0-55-generic");
+        assert_eq!(export_data.environment.root_dir, "/var/lib/docker");
+        assert_eq!(export_data.environment.cgroup_driver, "systemd");
+        assert_eq!(export_data.environment.security_options.len(), 3);
+        assert_eq!(export_data.checksum_scheme, ChecksumScheme::V4);
+        assert_eq!(export_data.layer_kind, LayerKind::Upper);
+        assert_eq!(export_data.layer_checksum.to_string(), "sha256:2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae");
+        let manifest = export_data.manifest.unwrap();
+        assert_eq!(manifest.path, MANIFEST_FILE);
+        assert_eq!(manifest.checksum.to_string(), "sha256:b5bb9d8014a0f9b1d61e21e796d78dccdf1352f23cd32812f4850b878ae4944c");
+        assert_eq!(export_data.annotations["ticket"], "OPS-1234");
+    }
+
+    #[test]
+    fn format_2_round_trips() {
+        let written = written_metadata(ExportFormatVersion::V2);
+        let json: serde_json::Value = serde_json::from_str(&written).unwrap();
+        assert_eq!(json["version"], "2.0");
+        assert!(json.get("docker_info").is_none() && json.get("manifest_checksum").is_none());
+        assert_eq!(json["layer_checksum"]["algorithm"], "sha256");
+        assert_eq!(json["manifest"]["path"], MANIFEST_FILE);
+
+        let read = parse_export_metadata(written.as_bytes()).unwrap();
+        assert_eq!(read.version, FormatVersion::V2_0);
+        let expected = parse_export_metadata(METADATA_V1.as_bytes()).unwrap();
+        assert_eq!(read.environment, expected.environment);
+        assert_eq!(read.layer_checksum, expected.layer_checksum);
+        assert_eq!(read.layer_archive_checksum, expected.layer_archive_checksum);
+        assert_eq!(read.manifest, expected.manifest);
+        assert_eq!(serde_json::to_value(&read).unwrap(), json);
+    }
+
+    #[test]
+    fn format_version_1_is_still_written_and_read() {
+        let written = written_metadata(ExportFormatVersion::V1);
+        let json: serde_json::Value = serde_json::from_str(&written).unwrap();
+        assert_eq!(json["version"], "1.4");
+        assert_eq!(json["docker_info"]["driver"], "overlay2");
+        assert_eq!(json["layer_checksum"], "sha256:2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae");
+
+        let metadata = ExportMetadata::parse(written.as_bytes(), FormatVersion::V1_4).unwrap();
+        assert!(matches!(metadata, ExportMetadata::V1(_)));
+        assert_eq!(metadata.version(), FormatVersion::V1_4);
+        let normalized = serde_json::to_value(metadata.normalize()).unwrap();
+        let fixture = serde_json::to_value(parse_export_metadata(METADATA_V1.as_bytes()).unwrap()).unwrap();
+        assert_eq!(normalized, fixture);
+    }
+
+    /// The format 1.4 fixture as JSON, to be edited into other versions
+    fn fixture_json() -> serde_json::Value {
+        serde_json::from_str(METADATA_V1).unwrap()
+    }
+
+    /// Parse `json` as check and import do, returning the result and the warnings printed
+    fn parse_capturing_warnings(json: &serde_json::Value) -> (Result<ExportDataV2>, Vec<String>) {
+        let sink = Arc::new(CaptureSink::new());
+        let output: Arc<dyn OutputSink> = sink.clone();
+        let _output = enter_output(&output);
+        let result = parse_export_metadata(json.to_string().as_bytes());
+        let warnings = sink.lines_at(Level::Warn);
+        (result, warnings)
+    }
+
+    #[test]
+    fn format_1_0_is_read_with_its_implied_checksum_scheme() {
+        let mut json = fixture_json();
+        json["version"] = "1.0".into();
+        json["layer_checksum"] = "2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae".into();
+        let fields = json.as_object_mut().unwrap();
+        for field in ["checksum_scheme", "layer_archive_checksum", "layer_kind", "annotations", "redacted_keys"] {
+            fields.remove(field);
+        }
+
+        let (result, warnings) = parse_capturing_warnings(&json);
+        let export_data = result.unwrap();
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(export_data.version, FormatVersion::V1_0);
+        assert_eq!(export_data.checksum_scheme, ChecksumScheme::V1);
+        assert_eq!(export_data.layer_checksum.algorithm, ChecksumAlgorithm::Sha256);
+        assert_eq!(export_data.layer_archive_checksum, None);
+        assert_eq!(export_data.layer_kind, LayerKind::Upper);
+    }
+
+    #[test]
+    fn unknown_fields_are_tolerated() {
+        let mut json = fixture_json();
+        json["version"] = "1.1".into();
+        json["signing_key_id"] = "ops-2025".into();
+        json["container_metadata"]["platform"] = serde_json::json!({ "os": "linux", "variant": "v8" });
+        json["docker_info"]["cdi_spec_dirs"] = serde_json::json!(["/etc/cdi"]);
+
+        let (result, warnings) = parse_capturing_warnings(&json);
+        let export_data = result.unwrap();
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(export_data.version, FormatVersion::V1_1);
+        assert_eq!(export_data.container_metadata.name, "web");
+    }
+
+    #[test]
+    fn newer_minor_versions_are_read_with_a_warning() {
+        let mut json = serde_json::from_str::<serde_json::Value>(&written_metadata(ExportFormatVersion::V2)).unwrap();
+        json["version"] = "2.1".into();
+        json["provenance"] = serde_json::json!({ "builder": "ci" });
+
+        let (result, warnings) = parse_capturing_warnings(&json);
+        let export_data = result.unwrap();
+        assert_eq!(export_data.version, FormatVersion::Other { major: 2, minor: 1 });
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("Export format version 2.1 is newer than this layer-tool supports (2.0)"), "{}", warnings[0]);
+    }
+
+    #[test]
+    fn newer_major_versions_are_refused() {
+        let json = serde_json::json!({ "version": "3.0", "layer": { "digest": "sha256:00" } });
+        let (result, _) = parse_capturing_warnings(&json);
+        let error = format!("{:#}", result.unwrap_err());
+        assert!(error.contains("this file requires layer-tool >= 3.0"), "{}", error);
+    }
+
+    #[test]
+    fn layers_verify_under_the_scheme_they_were_recorded_with() {
+        let layer = TempDir::new().unwrap();
+        let workspace = TempDir::new().unwrap();
+        std::fs::create_dir_all(layer.path().join("etc/nginx")).unwrap();
+        std::fs::write(layer.path().join("etc/nginx/nginx.conf"), b"worker_processes 4;\n").unwrap();
+        std::os::unix::fs::symlink("nginx/nginx.conf", layer.path().join("etc/nginx.conf")).unwrap();
+        let layer_tar = workspace.path().join("layer.tar");
+        create_tar_archive_filtered(
+            layer.path(), &layer_tar, None, None, LayerArchiveOptions::default(), &CancellationToken::new()
+        ).unwrap();
+
+        let schemes = [ChecksumScheme::V1, ChecksumScheme::V2, ChecksumScheme::V3, ChecksumScheme::V4];
+        let checksums: Vec<Checksum> = schemes.iter()
+            .map(|&scheme| calculate_directory_checksum(layer.path(), ChecksumFormat::new(scheme, ChecksumAlgorithm::Sha256)).unwrap())
+            .collect();
+        // Each scheme gives its own value, so a checksum is only meaningful with its scheme; V4
+        // only departs from V3 for names that are not UTF-8
+        assert_ne!(checksums[0], checksums[1]);
+        assert_ne!(checksums[1], checksums[2]);
+        assert_eq!(checksums[2], checksums[3]);
+
+        let _output = enter_output(&(Arc::new(CaptureSink::new()) as Arc<dyn OutputSink>));
+        let mut export_data = parse_export_metadata(METADATA_V1.as_bytes()).unwrap();
+        for (index, (&scheme, checksum)) in schemes.iter().zip(&checksums).enumerate() {
+            export_data.checksum_scheme = scheme;
+            export_data.layer_checksum = checksum.clone();
+            let scratch = workspace.path().join(format!("verified-{}", index));
+            extract_and_verify_layer(&layer_tar, &scratch, &export_data, &CancellationToken::new()).unwrap();
+        }
+
+        // A legacy checksum read as the current scheme is a mismatch, not a pass
+        export_data.checksum_scheme = ChecksumScheme::V4;
+        export_data.layer_checksum = checksums[0].clone();
+        let scratch = workspace.path().join("mismatched");
+        let error = extract_and_verify_layer(&layer_tar, &scratch, &export_data, &CancellationToken::new()).unwrap_err();
+        assert!(error.chain().any(|cause| cause.is::<IntegrityFailure>()), "{:#}", error);
+    }
+}
@@ -0,0 +1,178 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tempfile::TempDir;
+
+use crate::output::metrics;
+use crate::utils::directory_disk_usage;
+
+/// File name prefix of the per-process workspace registries in the temp directory
+const REGISTRY_PREFIX: &str = "layer-tool-";
+
+/// Workspaces this process holds, mirrored in its registry file
+static WORKSPACES: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Contents of `layer-tool-<pid>.json`: the temporary workspaces a process holds
+#[derive(Debug, Serialize, Deserialize)]
+struct Registry {
+    pid: u32,
+    workspaces: Vec<PathBuf>,
+}
+
+/// Temporary directory removed when dropped. While it exists it is recorded in the process'
+/// registry, so `layer-tool clean` can remove it should the process die without dropping it.
+#[derive(Debug)]
+pub struct Workspace {
+    dir: TempDir,
+}
+
+impl Workspace {
+    /// Create a workspace in the system temp directory
+    pub fn new() -> std::io::Result<Self> {
+        TempDir::new().map(Self::register)
+    }
+
+    /// Create a workspace in `dir`
+    pub fn new_in<P: AsRef<Path>>(dir: P) -> std::io::Result<Self> {
+        TempDir::new_in(dir).map(Self::register)
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    fn register(dir: TempDir) -> Self {
+        // Best effort: a workspace that cannot be recorded is still removed on a normal exit
+        if let Ok(mut workspaces) = WORKSPACES.lock() {
+            workspaces.push(dir.path().to_path_buf());
+            write_registry(&workspaces);
+        }
+        Self { dir }
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        // The workspace is at its largest just before it is removed
+        metrics::sample_temp_space();
+        if let Ok(mut workspaces) = WORKSPACES.lock() {
+            workspaces.retain(|path| path != self.dir.path());
+            write_registry(&workspaces);
+        }
+    }
+}
+
+/// Disk space the workspaces this process holds take; one that cannot be walked, e.g. as files
+/// vanish meanwhile, is left out
+pub fn temp_space_used() -> u64 {
+    let workspaces = WORKSPACES.lock().map(|workspaces| workspaces.clone()).unwrap_or_default();
+    workspaces.iter()
+        .filter_map(|path| directory_disk_usage(path).ok())
+        .map(|usage| usage.bytes)
+        .sum()
+}
+
+fn registry_path(pid: u32) -> PathBuf {
+    std::env::temp_dir().join(format!("{}{}.json", REGISTRY_PREFIX, pid))
+}
+
+/// Rewrite this process' registry to list `workspaces`, removing it once there are none
+fn write_registry(workspaces: &[PathBuf]) {
+    let pid = std::process::id();
+    let path = registry_path(pid);
+    if workspaces.is_empty() {
+        let _ = std::fs::remove_file(&path);
+        return;
+    }
+    let registry = Registry { pid, workspaces: workspaces.to_vec() };
+    let Ok(contents) = serde_json::to_vec(&registry) else {
+        return;
+    };
+    // Written aside and renamed, so `clean` never reads half a registry
+    let mut partial = path.clone().into_os_string();
+    partial.push(".tmp");
+    if std::fs::write(&partial, contents).is_ok() {
+        let _ = std::fs::rename(&partial, &path);
+    }
+}
+
+/// Remove every workspace this process holds, for an exit that skips their destructors. Does
+/// nothing if the registry is being updated at the same moment.
+pub fn remove_workspaces() {
+    let Ok(mut workspaces) = WORKSPACES.try_lock() else {
+        return;
+    };
+    for path in workspaces.drain(..) {
+        let _ = std::fs::remove_dir_all(path);
+    }
+    write_registry(&workspaces);
+}
+
+/// Workspaces a layer-tool process that is no longer running left behind
+#[derive(Debug)]
+pub struct Leftovers {
+    pub pid: u32,
+    pub registry_path: PathBuf,
+    /// Recorded workspaces that still exist
+    pub workspaces: Vec<PathBuf>,
+}
+
+/// Find the registries of layer-tool processes that are no longer running
+pub fn find_leftovers() -> Result<Vec<Leftovers>> {
+    let temp_dir = std::env::temp_dir();
+    let entries = std::fs::read_dir(&temp_dir)
+        .with_context(|| format!("Failed to read temporary directory: {:?}", temp_dir))?;
+
+    let mut leftovers = Vec::new();
+    for entry in entries {
+        let entry = entry.context("Failed to read temporary directory entry")?;
+        let file_name = entry.file_name();
+        let Some(pid) = file_name.to_str()
+            .and_then(|name| name.strip_prefix(REGISTRY_PREFIX))
+            .and_then(|name| name.strip_suffix(".json"))
+            .and_then(|pid| pid.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        if pid == std::process::id() || process_is_running(pid) {
+            continue;
+        }
+
+        // An unreadable registry is still removed, as its process is gone
+        let workspaces = std::fs::read(entry.path())
+            .ok()
+            .and_then(|contents| serde_json::from_slice::<Registry>(&contents).ok())
+            .map(|registry| registry.workspaces)
+            .unwrap_or_default();
+        leftovers.push(Leftovers {
+            pid,
+            registry_path: entry.path(),
+            workspaces: workspaces.into_iter().filter(|path| path.symlink_metadata().is_ok()).collect(),
+        });
+    }
+    leftovers.sort_by_key(|leftover| leftover.pid);
+    Ok(leftovers)
+}
+
+/// Remove the workspaces of `leftovers` and then its registry
+pub fn remove_leftovers(leftovers: &Leftovers) -> Result<()> {
+    for path in &leftovers.workspaces {
+        std::fs::remove_dir_all(path)
+            .with_context(|| format!("Failed to remove leftover workspace: {:?}", path))?;
+    }
+    std::fs::remove_file(&leftovers.registry_path)
+        .with_context(|| format!("Failed to remove workspace registry: {:?}", leftovers.registry_path))
+}
+
+/// Whether a process with `pid` exists; one owned by another user counts as running
+fn process_is_running(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: signal 0 only checks whether the process can be signalled; nothing is sent
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        return true;
+    }
+    std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}